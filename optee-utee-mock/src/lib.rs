@@ -17,6 +17,8 @@
 
 #[allow(non_snake_case)]
 pub mod object;
+#[allow(non_snake_case)]
+pub mod session;
 
 // re-export some dependencies;
 pub use mockall;
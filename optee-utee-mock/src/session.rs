@@ -0,0 +1,144 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::cell::UnsafeCell;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::raw::{self, TEE_Param, TEE_Result, TEE_TASessionHandle, TEE_UUID};
+
+static GLOBAL_SESSION_MOCK: RwLock<Option<Box<dyn SessionController + 'static>>> =
+    RwLock::new(None);
+pub static SERIAL_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+#[mockall::automock]
+// currently we just add functions that we need
+pub trait SessionController: Send + Sync {
+    // Internal Client API - TA sessions
+    fn TEE_OpenTASession(
+        &self,
+        destination: *const TEE_UUID,
+        cancellationRequestTimeout: u32,
+        paramTypes: u32,
+        params: *mut TEE_Param,
+        session: *mut TEE_TASessionHandle,
+        returnOrigin: *mut u32,
+    ) -> TEE_Result;
+    fn TEE_CloseTASession(&self, session: TEE_TASessionHandle);
+    fn TEE_InvokeTACommand(
+        &self,
+        session: TEE_TASessionHandle,
+        cancellationRequestTimeout: u32,
+        commandID: u32,
+        paramTypes: u32,
+        params: *mut TEE_Param,
+        returnOrigin: *mut u32,
+    ) -> TEE_Result;
+}
+
+pub fn set_global_session_mock(mock: impl SessionController + 'static) {
+    let mut value = GLOBAL_SESSION_MOCK.write().unwrap();
+    value.replace(Box::new(mock));
+}
+
+fn with_global_session_mock<R, F: FnOnce(&dyn SessionController) -> R>(f: F) -> R {
+    let mock = GLOBAL_SESSION_MOCK.read().unwrap();
+    let borrow = mock.as_ref().expect("Global Session Mock Not Set");
+    f(borrow.as_ref())
+}
+
+macro_rules! forward_to_mock {
+    ($fn_name:ident($($param:ident: $ty:ty),*) -> $ret:ty) => {
+        #[no_mangle]
+        fn $fn_name($($param: $ty),*) -> $ret {
+            with_global_session_mock(|mock: &dyn SessionController| {
+                mock.$fn_name($($param),*)
+            })
+        }
+    };
+}
+
+forward_to_mock!(TEE_OpenTASession(
+    destination: *const TEE_UUID,
+    cancellationRequestTimeout: u32,
+    paramTypes: u32,
+    params: *mut TEE_Param,
+    session: *mut TEE_TASessionHandle,
+    returnOrigin: *mut u32
+) -> TEE_Result);
+forward_to_mock!(TEE_CloseTASession(session: TEE_TASessionHandle) -> ());
+forward_to_mock!(TEE_InvokeTACommand(
+    session: TEE_TASessionHandle,
+    cancellationRequestTimeout: u32,
+    commandID: u32,
+    paramTypes: u32,
+    params: *mut TEE_Param,
+    returnOrigin: *mut u32
+) -> TEE_Result);
+
+type ValidTestHandle = Arc<UnsafeCell<raw::TEE_TASessionHandle>>;
+
+impl MockSessionController {
+    pub fn new_valid_test_handle_struct() -> raw::__TEE_TASessionHandle {
+        unsafe { core::mem::zeroed() }
+    }
+    pub fn new_valid_test_handle(handle: &mut raw::__TEE_TASessionHandle) -> ValidTestHandle {
+        Arc::new(UnsafeCell::new(handle))
+    }
+
+    pub fn expect_TEE_OpenTASession_success_once(&mut self, handle: ValidTestHandle) {
+        self.expect_TEE_OpenTASession()
+            .return_once_st(move |_, _, _, _, session, _| {
+                unsafe {
+                    *session = *handle.get();
+                }
+                raw::TEE_SUCCESS
+            });
+    }
+    pub fn expect_TEE_OpenTASession_fail_once(&mut self, code: raw::TEE_Result, origin: u32) {
+        self.expect_TEE_OpenTASession()
+            .return_once_st(move |_, _, _, _, _, return_origin| {
+                unsafe {
+                    *return_origin = origin;
+                }
+                code
+            });
+    }
+
+    pub fn expect_TEE_InvokeTACommand_fail_once(
+        &mut self,
+        exp_handle: ValidTestHandle,
+        code: raw::TEE_Result,
+        origin: u32,
+    ) {
+        self.expect_TEE_InvokeTACommand().return_once_st(
+            move |session, _, _, _, _, return_origin| {
+                assert_eq!(session, unsafe { *exp_handle.get() });
+                unsafe {
+                    *return_origin = origin;
+                }
+                code
+            },
+        );
+    }
+
+    pub fn expect_TEE_CloseTASession_once(&mut self, exp_handle: ValidTestHandle) {
+        self.expect_TEE_CloseTASession()
+            .return_once_st(move |session| {
+                assert_eq!(session, unsafe { *exp_handle.get() });
+            });
+    }
+}
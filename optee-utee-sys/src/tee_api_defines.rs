@@ -196,6 +196,7 @@ pub const TEE_ALG_DSA_SHA1: u32 = 0x70002131;
 pub const TEE_ALG_DSA_SHA224: u32 = 0x70003131;
 pub const TEE_ALG_DSA_SHA256: u32 = 0x70004131;
 pub const TEE_ALG_SM2_DSA_SM3: u32 = 0x70006045;
+pub const TEE_ALG_ED25519: u32 = 0x70006043;
 pub const TEE_ALG_DH_DERIVE_SHARED_SECRET: u32 = 0x80000032;
 pub const TEE_ALG_SM2_KEP: u32 = 0x60000045;
 pub const TEE_ALG_MD5: u32 = 0x50000001;
@@ -204,6 +205,10 @@ pub const TEE_ALG_SHA224: u32 = 0x50000003;
 pub const TEE_ALG_SHA256: u32 = 0x50000004;
 pub const TEE_ALG_SHA384: u32 = 0x50000005;
 pub const TEE_ALG_SHA512: u32 = 0x50000006;
+pub const TEE_ALG_SHA3_224: u32 = 0x50000008;
+pub const TEE_ALG_SHA3_256: u32 = 0x50000009;
+pub const TEE_ALG_SHA3_384: u32 = 0x5000000A;
+pub const TEE_ALG_SHA3_512: u32 = 0x5000000B;
 pub const TEE_ALG_MD5SHA1: u32 = 0x5000000F;
 pub const TEE_ALG_HMAC_MD5: u32 = 0x30000001;
 pub const TEE_ALG_HMAC_SHA1: u32 = 0x30000002;
@@ -223,6 +228,8 @@ pub const TEE_ALG_ECDH_P256: u32 = 0x80003042;
 pub const TEE_ALG_ECDH_P384: u32 = 0x80004042;
 pub const TEE_ALG_ECDH_P521: u32 = 0x80005042;
 pub const TEE_ALG_SM2_PKE: u32 = 0x80000045;
+pub const TEE_ALG_X25519: u32 = 0x80000044;
+pub const TEE_ALG_PBKDF2_HMAC_SHA1_DERIVE_KEY: u32 = 0x800020C2;
 pub const TEE_ALG_SM3: u32 = 0x50000007;
 pub const TEE_ALG_ILLEGAL_VALUE: u32 = 0xEFFFFFFF;
 
@@ -247,6 +254,10 @@ pub const TEE_TYPE_ECDSA_PUBLIC_KEY: u32 = 0xA0000041;
 pub const TEE_TYPE_ECDSA_KEYPAIR: u32 = 0xA1000041;
 pub const TEE_TYPE_ECDH_PUBLIC_KEY: u32 = 0xA0000042;
 pub const TEE_TYPE_ECDH_KEYPAIR: u32 = 0xA1000042;
+pub const TEE_TYPE_ED25519_PUBLIC_KEY: u32 = 0xA0000043;
+pub const TEE_TYPE_ED25519_KEYPAIR: u32 = 0xA1000043;
+pub const TEE_TYPE_X25519_PUBLIC_KEY: u32 = 0xA0000044;
+pub const TEE_TYPE_X25519_KEYPAIR: u32 = 0xA1000044;
 pub const TEE_TYPE_SM2_DSA_PUBLIC_KEY: u32 = 0xA0000045;
 pub const TEE_TYPE_SM2_DSA_KEYPAIR: u32 = 0xA1000045;
 pub const TEE_TYPE_SM2_KEP_PUBLIC_KEY: u32 = 0xA0000046;
@@ -254,6 +265,7 @@ pub const TEE_TYPE_SM2_KEP_KEYPAIR: u32 = 0xA1000046;
 pub const TEE_TYPE_SM2_PKE_PUBLIC_KEY: u32 = 0xA0000047;
 pub const TEE_TYPE_SM2_PKE_KEYPAIR: u32 = 0xA1000047;
 pub const TEE_TYPE_GENERIC_SECRET: u32 = 0xA0000000;
+pub const TEE_TYPE_PBKDF2_PASSWORD: u32 = 0xA10000C2;
 pub const TEE_TYPE_CORRUPTED_OBJECT: u32 = 0xA00000BE;
 pub const TEE_TYPE_DATA: u32 = 0xA00000BF;
 
@@ -284,6 +296,12 @@ pub const TEE_ATTR_ECC_PUBLIC_VALUE_X: u32 = 0xD0000141;
 pub const TEE_ATTR_ECC_PUBLIC_VALUE_Y: u32 = 0xD0000241;
 pub const TEE_ATTR_ECC_PRIVATE_VALUE: u32 = 0xC0000341;
 pub const TEE_ATTR_ECC_CURVE: u32 = 0xF0000441;
+pub const TEE_ATTR_EDDSA_CTX: u32 = 0xD0000643;
+pub const TEE_ATTR_ED25519_PUBLIC_VALUE: u32 = 0xD0000743;
+pub const TEE_ATTR_ED25519_PRIVATE_VALUE: u32 = 0xC0000843;
+pub const TEE_ATTR_X25519_PUBLIC_VALUE: u32 = 0xD0000944;
+pub const TEE_ATTR_X25519_PRIVATE_VALUE: u32 = 0xC0000A44;
+pub const TEE_ATTR_EDDSA_PREHASH: u32 = 0xF0000004;
 pub const TEE_ATTR_SM2_ID_INITIATOR: u32 = 0xD0000446;
 pub const TEE_ATTR_SM2_ID_RESPONDER: u32 = 0xD0000546;
 pub const TEE_ATTR_SM2_KEP_USER: u32 = 0xF0000646;
@@ -291,6 +309,10 @@ pub const TEE_ATTR_SM2_KEP_CONFIRMATION_IN: u32 = 0xD0000746;
 pub const TEE_ATTR_SM2_KEP_CONFIRMATION_OUT: u32 = 0xD0000846;
 pub const TEE_ATTR_ECC_EPHEMERAL_PUBLIC_VALUE_X: u32 = 0xD0000946;
 pub const TEE_ATTR_ECC_EPHEMERAL_PUBLIC_VALUE_Y: u32 = 0xD0000A46;
+pub const TEE_ATTR_PBKDF2_PASSWORD: u32 = 0xC00001C2;
+pub const TEE_ATTR_PBKDF2_SALT: u32 = 0xD00002C2;
+pub const TEE_ATTR_PBKDF2_ITERATION_COUNT: u32 = 0xF00003C2;
+pub const TEE_ATTR_PBKDF2_DK_LENGTH: u32 = 0xF00004C2;
 pub const TEE_ATTR_FLAG_PUBLIC: u32 = 1 << 28;
 pub const TEE_ATTR_FLAG_VALUE: u32 = 1 << 29;
 // Deprecated, but kept for backwards compatibility
@@ -304,7 +326,8 @@ pub const TEE_ECC_CURVE_NIST_P224: u32 = 0x00000002;
 pub const TEE_ECC_CURVE_NIST_P256: u32 = 0x00000003;
 pub const TEE_ECC_CURVE_NIST_P384: u32 = 0x00000004;
 pub const TEE_ECC_CURVE_NIST_P521: u32 = 0x00000005;
-pub const TEE_ECC_CURVE_SM2: u32 = 0x00000300;
+pub const TEE_ECC_CURVE_25519: u32 = 0x00000300;
+pub const TEE_ECC_CURVE_SM2: u32 = 0x00000400;
 
 // Panicked Functions Identification
 // TA Interface
@@ -0,0 +1,175 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Build manifest of a TA, embedded in its binary by
+//! [`build_manifest!`](crate::build_manifest) so that a reproducible build
+//! can be checked against a deployed TA.
+//!
+//! The manifest records the name and version of the TA crate and of this
+//! crate, the features of this crate and, when the build script of the TA
+//! exports them, the features of the TA crate, the target and the git
+//! revision:
+//!
+//! ``` rust,ignore
+//! // build.rs of the TA
+//! use std::{env, process::Command};
+//!
+//! fn main() {
+//!     let mut features: Vec<String> = env::vars()
+//!         .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_lowercase))
+//!         .collect();
+//!     features.sort();
+//!     println!("cargo:rustc-env=TA_FEATURES={}", features.join(","));
+//!     println!("cargo:rustc-env=TA_TARGET={}", env::var("TARGET").unwrap());
+//!     if let Ok(out) = Command::new("git").args(["rev-parse", "HEAD"]).output() {
+//!         let hash = String::from_utf8_lossy(&out.stdout);
+//!         println!("cargo:rustc-env=TA_GIT_HASH={}", hash.trim());
+//!     }
+//! }
+//! ```
+//!
+//! # Format
+//!
+//! [`BuildManifest::encode`] writes one `key=value` line per known field,
+//! in this order, skipping unknown ones:
+//!
+//! * `crate`: name and version of the TA crate, separated by a space.
+//! * `optee-utee`: version of this crate.
+//! * `optee-utee-features`: enabled features of this crate, comma separated.
+//! * `features`: `TA_FEATURES`.
+//! * `target`: `TA_TARGET`.
+//! * `git`: `TA_GIT_HASH`.
+//!
+//! The encoding only depends on the build inputs, so two reproducible
+//! builds have the same manifest and the same [`BuildManifest::digest`],
+//! which is the value to bind into attestation evidence about the TA.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{AlgorithmId, Digest, Result};
+
+/// Version of this crate.
+pub const OPTEE_UTEE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Features of this crate, in alphabetical order.
+const FEATURES: [(&str, bool); 11] = [
+    ("build_info_service", cfg!(feature = "build_info_service")),
+    ("cbor", cfg!(feature = "cbor")),
+    ("counter_service", cfg!(feature = "counter_service")),
+    ("error_telemetry", cfg!(feature = "error_telemetry")),
+    ("key_import_service", cfg!(feature = "key_import_service")),
+    ("no_error_strings", cfg!(feature = "no_error_strings")),
+    ("no_panic_handler", cfg!(feature = "no_panic_handler")),
+    ("random_service", cfg!(feature = "random_service")),
+    ("selftest_service", cfg!(feature = "selftest_service")),
+    ("session_journal", cfg!(feature = "session_journal")),
+    ("std", cfg!(feature = "std")),
+];
+
+/// Returns the enabled features of this crate, in alphabetical order.
+pub fn optee_utee_features() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Build inputs of a TA, built with [`build_manifest!`](crate::build_manifest).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildManifest {
+    pub crate_name: &'static str,
+    pub crate_version: &'static str,
+    pub optee_utee_version: &'static str,
+    /// Enabled features of the TA crate, comma separated, from `TA_FEATURES`.
+    pub features: Option<&'static str>,
+    /// Target triple, from `TA_TARGET`.
+    pub target: Option<&'static str>,
+    /// Git revision of the sources, from `TA_GIT_HASH`.
+    pub git_hash: Option<&'static str>,
+}
+
+impl BuildManifest {
+    /// Returns the manifest in the format described in the
+    /// [module documentation](self).
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        let mut line = |key: &str, value: &str| {
+            out.push_str(key);
+            out.push('=');
+            out.push_str(value);
+            out.push('\n');
+        };
+        line("crate", &[self.crate_name, self.crate_version].join(" "));
+        line("optee-utee", self.optee_utee_version);
+        line("optee-utee-features", &optee_utee_features().join(","));
+        for (key, value) in [
+            ("features", self.features),
+            ("target", self.target),
+            ("git", self.git_hash),
+        ] {
+            if let Some(value) = value {
+                line(key, value);
+            }
+        }
+        out
+    }
+
+    /// Returns the SHA-256 digest of [`encode`](Self::encode).
+    ///
+    /// # Errors
+    ///
+    /// Errors from allocating or running the digest operation.
+    pub fn digest(&self) -> Result<[u8; 32]> {
+        let digest = Digest::allocate(AlgorithmId::Sha256)?;
+        let mut hash = [0u8; 32];
+        digest.do_final(self.encode().as_bytes(), &mut hash)?;
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut manifest = BuildManifest {
+            crate_name: "hello_world",
+            crate_version: "0.3.0",
+            optee_utee_version: "0.6.0",
+            features: Some("default,std"),
+            target: None,
+            git_hash: Some("0123abcd"),
+        };
+        let features = optee_utee_features().join(",");
+        assert_eq!(
+            manifest.encode(),
+            format!(
+                "crate=hello_world 0.3.0\noptee-utee=0.6.0\noptee-utee-features={}\n\
+                 features=default,std\ngit=0123abcd\n",
+                features
+            )
+        );
+
+        manifest.target = Some("aarch64-unknown-optee");
+        assert!(manifest
+            .encode()
+            .ends_with("features=default,std\ntarget=aarch64-unknown-optee\ngit=0123abcd\n"));
+    }
+}
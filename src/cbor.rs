@@ -0,0 +1,346 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Deterministic CBOR payloads for command parameters, enabled by the `cbor`
+//! feature.
+//!
+//! Payloads are encoded with [`minicbor`], which always uses definite lengths
+//! and the shortest encoding of integers, and the entries of maps are then
+//! sorted by their encoded keys. This is the deterministic encoding of RFC
+//! 8949 expected by interfaces specified in CDDL, so that a value has a
+//! single encoding, e.g. to be signed. Decoding refuses payloads in any other
+//! form: integers and lengths not in their shortest form, indefinite lengths,
+//! and map keys out of order or duplicated. Floats are kept at the width they
+//! are encoded with.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::{cbor, Parameters, Result};
+//! use optee_utee::cbor::minicbor::{Decode, Encode};
+//!
+//! #[derive(Encode, Decode)]
+//! struct SignRequest {
+//!     #[n(0)] key_id: u32,
+//!     #[n(1)] message: Vec<u8>,
+//! }
+//!
+//! fn sign(params: &mut Parameters) -> Result<()> {
+//!     let mut input = unsafe { params.0.as_memref()? };
+//!     let request: SignRequest = input.decode_cbor()?;
+//!     // ...
+//!     let mut output = unsafe { params.1.as_memref()? };
+//!     output.encode_cbor(&request.message)
+//! }
+//! ```
+
+use core::convert::TryFrom;
+
+use alloc::vec::Vec;
+
+use minicbor::{Decode, Decoder, Encode};
+
+use crate::parameter::ParamMemref;
+use crate::{Error, ErrorKind, Result};
+
+pub use minicbor;
+
+// Nesting depth of arrays, maps and tags past which payloads are refused,
+// bounding the recursion of the checks.
+const MAX_DEPTH: usize = 32;
+
+/// Encodes `value` into `buf` and returns the number of bytes written.
+///
+/// # Errors
+///
+/// 1) `ShortBuffer`: If `buf` is too small for the encoded value.
+/// 2) `BadFormat`: If `value` cannot be encoded, or has a map with duplicate
+///    keys.
+pub fn encode_into<T: Encode<()>>(value: &T, buf: &mut [u8]) -> Result<usize> {
+    let encoded = to_vec(value)?;
+    let out = buf
+        .get_mut(..encoded.len())
+        .ok_or_else(|| Error::new(ErrorKind::ShortBuffer))?;
+    out.copy_from_slice(&encoded);
+    Ok(encoded.len())
+}
+
+/// Encodes `value` into a new buffer.
+///
+/// # Errors
+///
+/// 1) `BadFormat`: If `value` cannot be encoded, or has a map with duplicate
+///    keys.
+pub fn to_vec<T: Encode<()>>(value: &T) -> Result<Vec<u8>> {
+    let encoded = minicbor::to_vec(value).map_err(|_| Error::new(ErrorKind::BadFormat))?;
+    let mut sorted = Vec::with_capacity(encoded.len());
+    let mut pos = 0;
+    sort_maps(&encoded, &mut pos, &mut sorted, 0)?;
+    Ok(sorted)
+}
+
+/// Decodes a value from `buf`, which must hold exactly one CBOR item in
+/// deterministic encoding.
+///
+/// # Errors
+///
+/// 1) `BadFormat`: If `buf` is not a valid encoding of `T`, is not in
+///    deterministic encoding or has trailing bytes.
+pub fn decode<'b, T: Decode<'b, ()>>(buf: &'b [u8]) -> Result<T> {
+    let mut pos = 0;
+    check_item(buf, &mut pos, 0)?;
+    if pos != buf.len() {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    let mut decoder = Decoder::new(buf);
+    let value = decoder
+        .decode()
+        .map_err(|_| Error::new(ErrorKind::BadFormat))?;
+    if decoder.position() != buf.len() {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    Ok(value)
+}
+
+// Reads the head of the item at `*pos`, returning its major type and
+// argument. Fails on indefinite lengths, reserved values and arguments not in
+// their shortest form.
+fn head(buf: &[u8], pos: &mut usize) -> Result<(u8, u64)> {
+    let initial = *buf.get(*pos).ok_or_else(bad_format)?;
+    *pos += 1;
+    let (major, info) = (initial >> 5, initial & 0x1f);
+    let (len, min) = match info {
+        0..=23 => return Ok((major, u64::from(info))),
+        // Simple values below 32 are only encoded inline.
+        24 if major == 7 => (1, 32),
+        24 => (1, 24),
+        25 => (2, 1 << 8),
+        26 => (4, 1 << 16),
+        27 => (8, 1 << 32),
+        // 28 to 30 are reserved, 31 stands for an indefinite length.
+        _ => return Err(bad_format()),
+    };
+    let end = pos.checked_add(len).ok_or_else(bad_format)?;
+    let bytes = buf.get(*pos..end).ok_or_else(bad_format)?;
+    *pos = end;
+    let arg = bytes.iter().fold(0, |arg, b| arg << 8 | u64::from(*b));
+    // Floats are kept at the width they are encoded with.
+    let float = major == 7 && info >= 25;
+    if arg < min && !float {
+        return Err(bad_format());
+    }
+    Ok((major, arg))
+}
+
+// Skips the content of the string at `*pos`, `len` bytes long.
+fn skip(buf: &[u8], pos: &mut usize, len: u64) -> Result<()> {
+    let end = usize::try_from(len)
+        .ok()
+        .and_then(|len| pos.checked_add(len))
+        .filter(|end| *end <= buf.len())
+        .ok_or_else(bad_format)?;
+    *pos = end;
+    Ok(())
+}
+
+// Skips the item at `*pos`, checking that it is in deterministic encoding:
+// with the keys of maps in strictly ascending order of their encodings.
+fn check_item(buf: &[u8], pos: &mut usize, depth: usize) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(bad_format());
+    }
+    match head(buf, pos)? {
+        (2, len) | (3, len) => skip(buf, pos, len),
+        (4, items) => (0..items).try_for_each(|_| check_item(buf, pos, depth + 1)),
+        (5, entries) => {
+            let mut previous: Option<&[u8]> = None;
+            for _ in 0..entries {
+                let start = *pos;
+                check_item(buf, pos, depth + 1)?;
+                let key = &buf[start..*pos];
+                if matches!(previous, Some(previous) if previous >= key) {
+                    return Err(bad_format());
+                }
+                previous = Some(key);
+                check_item(buf, pos, depth + 1)?;
+            }
+            Ok(())
+        }
+        (6, _) => check_item(buf, pos, depth + 1),
+        _ => Ok(()),
+    }
+}
+
+// Copies the item at `*pos` to `out`, with the entries of maps sorted by
+// their encoded keys. Fails on duplicate keys.
+fn sort_maps(buf: &[u8], pos: &mut usize, out: &mut Vec<u8>, depth: usize) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Err(bad_format());
+    }
+    let start = *pos;
+    let head = head(buf, pos)?;
+    out.extend_from_slice(&buf[start..*pos]);
+    match head {
+        (2, len) | (3, len) => {
+            let start = *pos;
+            skip(buf, pos, len)?;
+            out.extend_from_slice(&buf[start..*pos]);
+        }
+        (4, items) => {
+            for _ in 0..items {
+                sort_maps(buf, pos, out, depth + 1)?;
+            }
+        }
+        (5, entries) => {
+            let mut sorted = Vec::new();
+            for _ in 0..entries {
+                let (mut key, mut value) = (Vec::new(), Vec::new());
+                sort_maps(buf, pos, &mut key, depth + 1)?;
+                sort_maps(buf, pos, &mut value, depth + 1)?;
+                sorted.push((key, value));
+            }
+            sorted.sort();
+            if sorted.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                return Err(bad_format());
+            }
+            for (key, value) in sorted {
+                out.extend_from_slice(&key);
+                out.extend_from_slice(&value);
+            }
+        }
+        (6, _) => sort_maps(buf, pos, out, depth + 1)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn bad_format() -> Error {
+    Error::new(ErrorKind::BadFormat)
+}
+
+impl<'parameter> ParamMemref<'parameter> {
+    /// Decodes the CBOR payload held by the memref.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the buffer is not a valid encoding of `T` or is not
+    ///    in deterministic encoding.
+    pub fn decode_cbor<T: for<'b> Decode<'b, ()>>(&mut self) -> Result<T> {
+        decode(self.buffer())
+    }
+
+    /// Encodes `value` into the memref and updates its size to the length of
+    /// the encoding.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ShortBuffer`: If the buffer provided by the CA is too small.
+    /// 2) `BadFormat`: If `value` cannot be encoded.
+    pub fn encode_cbor<T: Encode<()>>(&mut self, value: &T) -> Result<()> {
+        let size = encode_into(value, self.buffer())?;
+        self.set_updated_size(size);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+    use alloc::vec;
+    use minicbor::{Decode, Encode};
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    struct Payload {
+        #[n(0)]
+        id: u32,
+        #[n(1)]
+        data: Vec<u8>,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let payload = Payload {
+            id: 23,
+            data: vec![1, 2, 3],
+        };
+        let mut buf = [0u8; 32];
+        let size = encode_into(&payload, &mut buf).expect("it should be ok");
+        assert_eq!(&buf[..size], to_vec(&payload).unwrap().as_slice());
+        assert_eq!(decode::<Payload>(&buf[..size]).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_short_buffer() {
+        let mut buf = [0u8; 2];
+        let err = encode_into(&vec![0u8; 8], &mut buf).expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::ShortBuffer);
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        let err = decode::<u32>(&[0x01, 0x02]).expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+    }
+
+    #[test]
+    fn test_non_shortest_integer() {
+        // 1 encoded on one more byte.
+        let err = decode::<u32>(&[0x18, 0x01]).expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+        // A length of 1 encoded on one more byte.
+        let err = decode::<Vec<u8>>(&[0x58, 0x01, 0xaa]).expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+    }
+
+    #[test]
+    fn test_indefinite_length() {
+        let err = decode::<Vec<u32>>(&[0x9f, 0x01, 0xff]).expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+    }
+
+    #[test]
+    fn test_unsorted_keys() {
+        // {2: 0, 1: 0}
+        let err = decode::<BTreeMap<u32, u32>>(&[0xa2, 0x02, 0x00, 0x01, 0x00])
+            .expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        // {1: 0, 1: 1}
+        let err = decode::<BTreeMap<u32, u32>>(&[0xa2, 0x01, 0x00, 0x01, 0x01])
+            .expect_err("it should be err");
+        assert_eq!(err.kind(), ErrorKind::BadFormat);
+    }
+
+    #[test]
+    fn test_keys_sorted_on_encode() {
+        let mut map = BTreeMap::new();
+        map.insert(String::from("aa"), 1u32);
+        map.insert(String::from("b"), 2u32);
+        // "b" comes first, its encoding being shorter.
+        let expected = [0xa2, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x01];
+        assert_eq!(to_vec(&map).unwrap(), expected);
+        let mut buf = [0u8; 16];
+        let size = encode_into(&map, &mut buf).expect("it should be ok");
+        assert_eq!(&buf[..size], expected);
+        assert_eq!(decode::<BTreeMap<String, u32>>(&expected).unwrap(), map);
+    }
+}
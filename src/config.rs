@@ -0,0 +1,347 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Configuration records protected against rollback.
+//!
+//! Each record carries a version number, checked on load against a
+//! monotonic counter holding the version last committed: a record older
+//! than the counter, e.g. restored from a backup of the storage, is
+//! rejected. New records are first staged next to the committed one, then
+//! committed by advancing the counter, so an interrupted update leaves the
+//! committed record in place.
+//!
+//! The counter is a [`RollbackCounter`], to be backed by storage the normal
+//! world cannot roll back along with the records, e.g. an RPMB partition or
+//! a hardware monotonic counter reached through a pseudo-TA. The
+//! [`StorageCounter`] keeps it in the TA private storage next to the
+//! records: a rollback of the whole storage then goes unnoticed, so it only
+//! protects against restoring records one by one, unless the private storage
+//! itself is RPMB backed.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::config::{ConfigStore, StorageCounter};
+//! # use optee_utee::Result;
+//! fn update_policy(policy: &[u8], version: u64) -> Result<()> {
+//!     // The private storage of this device is RPMB backed.
+//!     let store = ConfigStore::new(b"boot-policy", StorageCounter)?;
+//!     store.stage(version, policy)?;
+//!     // Check the staged policy before applying it.
+//!     store.commit()?;
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{
+    DataFlag, Error, ErrorKind, GenericObject, ObjectStorageConstants, PersistentObject, Result,
+    Whence,
+};
+
+const OBJECT_ID_PREFIX: &[u8] = b"fw.config.";
+const COUNTER_SUFFIX: &[u8] = b".counter";
+const SLOT_SUFFIXES: [&[u8]; 2] = [b".0", b".1"];
+/// Longest name of a [`ConfigStore`].
+pub const MAX_NAME_LEN: usize = 64 - OBJECT_ID_PREFIX.len() - COUNTER_SUFFIX.len();
+
+/// A configuration record and its version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigRecord {
+    pub version: u64,
+    pub data: Vec<u8>,
+}
+
+/// Monotonic counter holding the version last committed to each
+/// [`ConfigStore`], against which loaded records are checked.
+pub trait RollbackCounter {
+    /// Returns the counter of the store `name`, 0 until it is first
+    /// advanced.
+    fn read(&self, name: &[u8]) -> Result<u64>;
+
+    /// Advances the counter of the store `name` to `value`, greater than
+    /// its current value.
+    fn advance(&self, name: &[u8], value: u64) -> Result<()>;
+}
+
+/// [`RollbackCounter`] kept in an object of the TA private storage, next to
+/// the records it protects.
+///
+/// Rolling back the whole storage rolls the counter back along with the
+/// records, which goes unnoticed: only use it where the private storage is
+/// itself protected against rollback, e.g. RPMB backed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StorageCounter;
+
+impl RollbackCounter for StorageCounter {
+    fn read(&self, name: &[u8]) -> Result<u64> {
+        match read_object(&object_id(name, COUNTER_SUFFIX))? {
+            Some(data) if data.len() == 8 => {
+                let mut value = [0u8; 8];
+                value.copy_from_slice(&data);
+                Ok(u64::from_le_bytes(value))
+            }
+            Some(_) => Err(Error::new(ErrorKind::CorruptObject)),
+            None => Ok(0),
+        }
+    }
+
+    fn advance(&self, name: &[u8], value: u64) -> Result<()> {
+        let object_id = object_id(name, COUNTER_SUFFIX);
+        let flags = DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE;
+        match PersistentObject::open(ObjectStorageConstants::Private, &object_id, flags) {
+            Ok(mut object) => {
+                object.seek(0, Whence::DataSeekSet)?;
+                object.write(&value.to_le_bytes())
+            }
+            Err(e) if e.kind() == ErrorKind::ItemNotFound => PersistentObject::create(
+                ObjectStorageConstants::Private,
+                &object_id,
+                flags,
+                None,
+                &value.to_le_bytes(),
+            )
+            .map(|_| ()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Versioned configuration records stored under a name, protected against
+/// rollback by a [`RollbackCounter`].
+pub struct ConfigStore<C: RollbackCounter> {
+    name: Vec<u8>,
+    counter: C,
+}
+
+impl<C: RollbackCounter> ConfigStore<C> {
+    /// Opens the store `name`, which needs not exist yet, checked against
+    /// `counter`.
+    ///
+    /// # Errors
+    ///
+    /// `BadParameters`: If `name` is empty or longer than [`MAX_NAME_LEN`].
+    pub fn new(name: &[u8], counter: C) -> Result<Self> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        Ok(Self {
+            name: name.to_vec(),
+            counter,
+        })
+    }
+
+    /// Loads the committed record.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If no record was committed yet.
+    /// 2) `Security`: If the committed record is missing or older than the
+    ///    counter, i.e. the storage was rolled back.
+    /// 3) `CorruptObject`: If a record or the counter is malformed.
+    /// 4) Errors from reading the storage.
+    pub fn load(&self) -> Result<ConfigRecord> {
+        let counter = self.read_counter()?;
+        let slots = self.read_slots()?;
+        match committed_slot(counter, &slots) {
+            Some(slot) => Ok(slots[slot].clone().unwrap()),
+            None if counter == 0 => Err(Error::new(ErrorKind::ItemNotFound)),
+            None => Err(Error::new(ErrorKind::Security)),
+        }
+    }
+
+    /// Stages `data` as the record of `version`, replacing a record already
+    /// staged. The committed record stays in use until [`commit`](Self::commit).
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `version` is not newer than the committed one.
+    /// 2) Errors from accessing the storage.
+    pub fn stage(&self, version: u64, data: &[u8]) -> Result<()> {
+        let counter = self.read_counter()?;
+        if version <= counter {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let slots = self.read_slots()?;
+        // Never overwrite the committed record.
+        let slot = match committed_slot(counter, &slots) {
+            Some(0) => 1,
+            _ => 0,
+        };
+
+        let mut record = version.to_le_bytes().to_vec();
+        record.extend_from_slice(data);
+        PersistentObject::create(
+            ObjectStorageConstants::Private,
+            &self.object_id(SLOT_SUFFIXES[slot]),
+            DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE | DataFlag::OVERWRITE,
+            None,
+            &record,
+        )?;
+        Ok(())
+    }
+
+    /// Returns the staged record, if any.
+    ///
+    /// # Errors
+    ///
+    /// Errors from reading the storage.
+    pub fn staged(&self) -> Result<Option<ConfigRecord>> {
+        let counter = self.read_counter()?;
+        let slots = self.read_slots()?;
+        Ok(staged_slot(counter, &slots).and_then(|slot| slots[slot].clone()))
+    }
+
+    /// Commits the staged record, which [`load`](Self::load) returns from
+    /// then on, and returns its version.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If no record is staged.
+    /// 2) Errors from accessing the storage.
+    pub fn commit(&self) -> Result<u64> {
+        let counter = self.read_counter()?;
+        let slots = self.read_slots()?;
+        let version = match staged_slot(counter, &slots) {
+            Some(slot) => slots[slot].as_ref().unwrap().version,
+            None => return Err(Error::new(ErrorKind::BadState)),
+        };
+        self.counter.advance(&self.name, version)?;
+        Ok(version)
+    }
+
+    /// Drops the staged record, if any.
+    ///
+    /// # Errors
+    ///
+    /// Errors from accessing the storage.
+    pub fn discard(&self) -> Result<()> {
+        let counter = self.read_counter()?;
+        let slots = self.read_slots()?;
+        match staged_slot(counter, &slots) {
+            Some(slot) => PersistentObject::open(
+                ObjectStorageConstants::Private,
+                &self.object_id(SLOT_SUFFIXES[slot]),
+                DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE_META,
+            )?
+            .close_and_delete(),
+            None => Ok(()),
+        }
+    }
+
+    fn object_id(&self, suffix: &[u8]) -> Vec<u8> {
+        object_id(&self.name, suffix)
+    }
+
+    fn read_slots(&self) -> Result<[Option<ConfigRecord>; 2]> {
+        Ok([
+            self.read_record(SLOT_SUFFIXES[0])?,
+            self.read_record(SLOT_SUFFIXES[1])?,
+        ])
+    }
+
+    fn read_record(&self, suffix: &[u8]) -> Result<Option<ConfigRecord>> {
+        let data = match read_object(&self.object_id(suffix))? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        if data.len() < 8 {
+            return Err(Error::new(ErrorKind::CorruptObject));
+        }
+        let mut version = [0u8; 8];
+        version.copy_from_slice(&data[..8]);
+        Ok(Some(ConfigRecord {
+            version: u64::from_le_bytes(version),
+            data: data[8..].to_vec(),
+        }))
+    }
+
+    // The counter reads as 0 until a record is committed.
+    fn read_counter(&self) -> Result<u64> {
+        self.counter.read(&self.name)
+    }
+}
+
+fn object_id(name: &[u8], suffix: &[u8]) -> Vec<u8> {
+    let mut object_id = OBJECT_ID_PREFIX.to_vec();
+    object_id.extend_from_slice(name);
+    object_id.extend_from_slice(suffix);
+    object_id
+}
+
+fn read_object(object_id: &[u8]) -> Result<Option<Vec<u8>>> {
+    let object = match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        object_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    ) {
+        Ok(object) => object,
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut data = vec![0u8; object.info()?.data_size()];
+    let read = object.read(&mut data)? as usize;
+    data.truncate(read);
+    Ok(Some(data))
+}
+
+// Index of the slot holding the version the counter was advanced to.
+fn committed_slot(counter: u64, slots: &[Option<ConfigRecord>; 2]) -> Option<usize> {
+    if counter == 0 {
+        return None;
+    }
+    slots.iter().position(|slot| {
+        slot.as_ref()
+            .is_some_and(|record| record.version == counter)
+    })
+}
+
+// Index of the slot holding a version newer than the counter.
+fn staged_slot(counter: u64, slots: &[Option<ConfigRecord>; 2]) -> Option<usize> {
+    slots
+        .iter()
+        .position(|slot| slot.as_ref().is_some_and(|record| record.version > counter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(version: u64) -> Option<ConfigRecord> {
+        Some(ConfigRecord {
+            version,
+            data: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_slots() {
+        assert_eq!(committed_slot(0, &[None, None]), None);
+        assert_eq!(staged_slot(0, &[record(1), None]), Some(0));
+
+        let slots = [record(1), record(2)];
+        assert_eq!(committed_slot(1, &slots), Some(0));
+        assert_eq!(staged_slot(1, &slots), Some(1));
+        assert_eq!(committed_slot(2, &slots), Some(1));
+        assert_eq!(staged_slot(2, &slots), None);
+
+        // Both records are older than the counter: the storage was rolled back.
+        assert_eq!(committed_slot(3, &slots), None);
+        assert_eq!(staged_slot(3, &slots), None);
+    }
+}
@@ -15,14 +15,20 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use alloc::{boxed::Box, vec::Vec};
-use core::{mem, ptr};
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{ffi::c_void, mem, ptr};
 
 use optee_utee_sys as raw;
+use subtle::{Choice, ConstantTimeEq};
 
-use crate::{Attribute, Error, GenericObject, Result, TransientObject};
+use crate::secret::wipe;
+use crate::{
+    Attribute, AttributeId, AttributeMemref, AttributeValue, Error, ErrorKind, GenericObject,
+    Result, SecretBuffer, TransientObject, TransientObjectType,
+};
 
 /// Specify one of the available cryptographic operations.
+#[derive(Clone, Copy)]
 #[repr(u32)]
 pub enum OperationMode {
     /// Encryption mode
@@ -176,15 +182,13 @@ impl OperationHandle {
     }
 
     fn allocate(algo: AlgorithmId, mode: OperationMode, max_key_size: usize) -> Result<Self> {
+        Self::allocate_raw(algo as u32, mode as u32, max_key_size as u32)
+    }
+
+    fn allocate_raw(algo: u32, mode: u32, max_key_size: u32) -> Result<Self> {
         let raw_handle: *mut raw::TEE_OperationHandle = Box::into_raw(Box::new(ptr::null_mut()));
-        match unsafe {
-            raw::TEE_AllocateOperation(
-                raw_handle as *mut _,
-                algo as u32,
-                mode as u32,
-                max_key_size as u32,
-            )
-        } {
+        match unsafe { raw::TEE_AllocateOperation(raw_handle as *mut _, algo, mode, max_key_size) }
+        {
             raw::TEE_SUCCESS => Ok(Self::from_raw(raw_handle)),
             code => Err(Error::from_raw_error(code)),
         }
@@ -227,6 +231,47 @@ impl OperationHandle {
             raw::TEE_CopyOperation(self.handle(), src.handle());
         }
     }
+
+    // Allocates an operation of the same algorithm, mode and maximum key size and copies the
+    // state and key of this one into it. TEE_CopyOperation panics on any mismatch between the two,
+    // so they are checked first and reported as errors instead.
+    fn try_clone(&self) -> Result<Self> {
+        if self.is_null() {
+            return Err(Error::new(ErrorKind::BadState));
+        }
+        let info = self.info().raw;
+        let copy = Self::allocate_raw(info.algorithm, info.mode, info.maxKeySize)?;
+        let copied = copy.info().raw;
+        if copied.algorithm != info.algorithm
+            || copied.mode != info.mode
+            || copied.maxKeySize < info.keySize
+        {
+            return Err(Error::new(ErrorKind::BadState));
+        }
+        unsafe {
+            raw::TEE_CopyOperation(copy.handle(), self.handle());
+        }
+        Ok(copy)
+    }
+
+    fn is_null(&self) -> bool {
+        self.raw.is_null() || self.handle().is_null()
+    }
+
+    // Returns the operation to its initial state with no key programmed, so that it can be
+    // handed out again without leaking the state or key of its previous user.
+    fn recycle(&mut self) {
+        let info = self.info().raw;
+        let key_set = info.handleState & raw::TEE_HANDLE_FLAG_KEY_SET != 0;
+        if key_set || info.operationClass == raw::TEE_OPERATION_DIGEST {
+            self.reset();
+        }
+        if key_set {
+            unsafe {
+                raw::TEE_SetOperationKey(self.handle(), ptr::null_mut());
+            }
+        }
+    }
 }
 
 /// determine whether a combination of algId and element is supported
@@ -257,6 +302,51 @@ pub trait OpHandle {
     fn handle(&self) -> raw::TEE_OperationHandle;
 }
 
+/// A hash algorithm whose hash has a fixed size, as computed by [Digest::hash](Digest::hash).
+pub trait HashAlgorithm {
+    /// The algorithm the [Digest](Digest) operation is allocated with.
+    const ALGORITHM: AlgorithmId;
+    /// The hash, a byte array of exactly its size.
+    type Output: HashOutput;
+}
+
+/// The output of a [HashAlgorithm](HashAlgorithm), implemented for byte arrays.
+pub trait HashOutput: AsMut<[u8]> {
+    /// Return an output filled with zeros, to be overwritten by the hash.
+    fn zeroed() -> Self;
+}
+
+impl<const N: usize> HashOutput for [u8; N] {
+    fn zeroed() -> Self {
+        [0u8; N]
+    }
+}
+
+macro_rules! hash_algorithm {
+    ($name:ident, $len:literal) => {
+        #[doc = concat!("[", stringify!($name), "](AlgorithmId::", stringify!($name), "), hashing to ", stringify!($len), " bytes.")]
+        pub struct $name;
+
+        impl HashAlgorithm for $name {
+            const ALGORITHM: AlgorithmId = AlgorithmId::$name;
+            type Output = [u8; $len];
+        }
+    };
+}
+
+hash_algorithm!(Md5, 16);
+hash_algorithm!(Sha1, 20);
+hash_algorithm!(Sha224, 28);
+hash_algorithm!(Sha256, 32);
+hash_algorithm!(Sha384, 48);
+hash_algorithm!(Sha512, 64);
+hash_algorithm!(Sha3_224, 28);
+hash_algorithm!(Sha3_256, 32);
+hash_algorithm!(Sha3_384, 48);
+hash_algorithm!(Sha3_512, 64);
+hash_algorithm!(Sm3, 32);
+hash_algorithm!(Md5Sha1, 36);
+
 /// An operation for digest the message.
 pub struct Digest(OperationHandle);
 
@@ -341,6 +431,35 @@ impl Digest {
         }
     }
 
+    /// Hash `data` with `A` in one call, allocating and releasing the operation, and return the
+    /// hash as an array of exactly its size.
+    ///
+    /// # Example
+    ///
+    /// ``` rust,no_run
+    /// # use optee_utee::{Digest, Sha256};
+    /// # fn main() -> optee_utee::Result<()> {
+    /// let hash: [u8; 32] = Digest::hash::<Sha256>(b"message")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    /// 2) `NotSupported`: If the TEE does not implement `A`.
+    ///
+    /// # Panics
+    ///
+    /// 1) if input data exceeds maximum length for algorithm.
+    /// 2) Hardware or cryptographic algorithm failure.
+    pub fn hash<A: HashAlgorithm>(data: &[u8]) -> Result<A::Output> {
+        let mut hash = A::Output::zeroed();
+        let len = Self::allocate(A::ALGORITHM)?.do_final(data, hash.as_mut())?;
+        debug_assert_eq!(len, hash.as_mut().len());
+        Ok(hash)
+    }
+
     /// Create a Digest operation without any specific algorithm or other data.
     pub fn null() -> Self {
         Self(OperationHandle::null())
@@ -514,6 +633,34 @@ impl Digest {
     pub fn copy<T: OpHandle>(&mut self, src: &T) {
         self.0.copy(src)
     }
+
+    /// Return a new operation holding a copy of the state and key of this one, e.g. to finish a
+    /// snapshot of a running hash, such as a TLS transcript, while continuing to feed this
+    /// operation. It is allocated with the same algorithm, mode and maximum key size, then filled
+    /// by [copy](Self::copy).
+    ///
+    /// # Example
+    ///
+    /// ``` rust,no_run
+    /// # use optee_utee::{Digest, AlgorithmId};
+    /// # fn main() -> optee_utee::Result<()> {
+    /// let transcript = Digest::allocate(AlgorithmId::Sha256)?;
+    /// transcript.update(b"client hello");
+    /// let mut hash = [0u8; 32];
+    /// transcript.try_clone()?.do_final(&[], &mut hash)?;
+    /// transcript.update(b"server hello");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    /// 2) `BadState`: If this operation is [null](Self::null), or the TEE allocates an operation
+    ///    its state cannot be copied to.
+    pub fn try_clone(&self) -> Result<Self> {
+        self.0.try_clone().map(Self)
+    }
 }
 
 impl OpHandle for Digest {
@@ -755,6 +902,19 @@ impl Cipher {
     pub fn copy<T: OpHandle>(&mut self, src: &T) {
         self.0.copy(src)
     }
+
+    /// Return a new operation holding a copy of the state and key of this one, e.g. to finish a
+    /// snapshot of a running encryption while continuing to feed this operation. It is allocated
+    /// with the same algorithm, mode and maximum key size, then filled by [copy](Self::copy).
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    /// 2) `BadState`: If this operation is [null](Self::null), or the TEE allocates an operation
+    ///    its state cannot be copied to.
+    pub fn try_clone(&self) -> Result<Self> {
+        self.0.try_clone().map(Self)
+    }
 }
 
 impl OpHandle for Cipher {
@@ -763,6 +923,238 @@ impl OpHandle for Cipher {
     }
 }
 
+/// Key wrapping scheme of [wrap_key](wrap_key) and [unwrap_key](unwrap_key).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyWrap {
+    /// AES Key Wrap (RFC 3394), for keys of at least 16 bytes whose length is a multiple of 8.
+    Rfc3394,
+    /// AES Key Wrap with Padding (RFC 5649), for keys of any length.
+    Rfc5649,
+}
+
+const KW_IV: [u8; 8] = [0xa6; 8];
+const KWP_IV_PREFIX: [u8; 4] = [0xa6, 0x59, 0x59, 0xa6];
+
+/// Wrap the secret value of `key` under the key-encryption key `kek` with `scheme`, e.g. to
+/// export it to another device holding the same KEK.
+///
+/// The secret value is read and wrapped within this function and wiped before it returns, so
+/// that it never reaches the caller. `key` must have been created with
+/// [EXTRACTABLE](crate::UsageFlag::EXTRACTABLE) usage for the TEE to hand it out.
+///
+/// # Parameters
+///
+/// 1) `kek`: An [Aes](crate::TransientObjectType::Aes) key object, transient or persistent.
+/// 2) `key`: A secret key object, e.g. an AES or HMAC key, transient or persistent.
+/// 3) `scheme`: The key wrapping scheme.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{unwrap_key, wrap_key, KeyWrap, TransientObject, TransientObjectType};
+/// # fn main() -> optee_utee::Result<()> {
+/// # let kek = TransientObject::allocate(TransientObjectType::Aes, 256)?;
+/// let key = TransientObject::allocate(TransientObjectType::Aes, 128)?;
+/// key.generate_key(128, &[])?;
+/// let wrapped = wrap_key(&kek, &key, KeyWrap::Rfc3394)?;
+/// let unwrapped = unwrap_key(&kek, &wrapped, KeyWrap::Rfc3394, TransientObjectType::Aes)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If the length of the secret value is not supported by `scheme`.
+/// 2) `AccessDenied`: If `key` is not extractable.
+/// 3) `ItemNotFound`: If `key` has no secret value.
+/// 4) `OutOfMemory`: If not enough resources are available to allocate the operation.
+///
+/// # Panics
+///
+/// 1) If `kek` is not a valid AES key object.
+/// 2) Hardware or cryptographic algorithm failure.
+pub fn wrap_key<K: GenericObject, T: GenericObject>(
+    kek: &K,
+    key: &T,
+    scheme: KeyWrap,
+) -> Result<Vec<u8>> {
+    let secret = key.secret_attribute(AttributeId::SecretValue)?;
+    let cipher = key_wrap_cipher(kek, OperationMode::Encrypt)?;
+    key_wrap(scheme, &secret, |block| aes_block(&cipher, block))
+}
+
+/// Unwrap `wrapped`, the output of [wrap_key](wrap_key) under `kek` with `scheme`, into the
+/// `SecretValue` attribute of a new transient object of `object_type`, its size being the
+/// length of the unwrapped key. The unwrapped key never reaches the caller either.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If the length of `wrapped` is not supported by `scheme`.
+/// 2) `MacInvalid`: If `wrapped` was modified, or was not wrapped under `kek` with `scheme`.
+/// 3) `NotSupported`: If the length of the unwrapped key is not supported by `object_type`.
+/// 4) `OutOfMemory`: If not enough resources are available to allocate the operation or the
+///    object.
+///
+/// # Panics
+///
+/// 1) If `kek` is not a valid AES key object.
+/// 2) Hardware or cryptographic algorithm failure.
+pub fn unwrap_key<K: GenericObject>(
+    kek: &K,
+    wrapped: &[u8],
+    scheme: KeyWrap,
+    object_type: TransientObjectType,
+) -> Result<TransientObject> {
+    let cipher = key_wrap_cipher(kek, OperationMode::Decrypt)?;
+    let secret = SecretBuffer::from(key_unwrap(scheme, wrapped, |block| {
+        aes_block(&cipher, block)
+    })?);
+    let mut key = TransientObject::allocate(object_type, secret.len() * 8)?;
+    key.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &secret).into()])?;
+    Ok(key)
+}
+
+// Allocates an AES-ECB operation in `mode` keyed with `kek`, ready to process blocks.
+fn key_wrap_cipher<K: GenericObject>(kek: &K, mode: OperationMode) -> Result<Cipher> {
+    let cipher = Cipher::allocate(AlgorithmId::AesEcbNopad, mode, kek.info()?.object_size())?;
+    cipher.set_key(kek)?;
+    cipher.init(&[]);
+    Ok(cipher)
+}
+
+// Encrypts or decrypts `block` in place with the AES-ECB operation `cipher`.
+fn aes_block(cipher: &Cipher, block: &mut [u8; 16]) -> Result<()> {
+    let mut input = *block;
+    let result = cipher.update(&input, block);
+    wipe(&mut input);
+    match result? {
+        16 => Ok(()),
+        _ => Err(Error::new(ErrorKind::BadFormat)),
+    }
+}
+
+// Wraps `key` with `scheme`, `encrypt` encrypting a block in place with the KEK.
+fn key_wrap(
+    scheme: KeyWrap,
+    key: &[u8],
+    mut encrypt: impl FnMut(&mut [u8; 16]) -> Result<()>,
+) -> Result<Vec<u8>> {
+    let (iv, padded_len) = match scheme {
+        KeyWrap::Rfc3394 if key.len() >= 16 && key.len().is_multiple_of(8) => (KW_IV, key.len()),
+        KeyWrap::Rfc5649 if !key.is_empty() && key.len() <= u32::MAX as usize => {
+            let mut iv = [0u8; 8];
+            iv[..4].copy_from_slice(&KWP_IV_PREFIX);
+            iv[4..].copy_from_slice(&(key.len() as u32).to_be_bytes());
+            (iv, key.len().div_ceil(8) * 8)
+        }
+        _ => return Err(Error::new(ErrorKind::BadParameters)),
+    };
+    let mut out = vec![0u8; 8 + padded_len];
+    out[..8].copy_from_slice(&iv);
+    out[8..8 + key.len()].copy_from_slice(key);
+    let mut block = [0u8; 16];
+    let result = (|| {
+        let n = padded_len / 8;
+        if n == 1 {
+            // RFC 5649 encrypts a single padded block as is.
+            block.copy_from_slice(&out);
+            encrypt(&mut block)?;
+            out.copy_from_slice(&block);
+            return Ok(());
+        }
+        for j in 0..6 {
+            for i in 1..=n {
+                block[..8].copy_from_slice(&out[..8]);
+                block[8..].copy_from_slice(&out[8 * i..8 * i + 8]);
+                encrypt(&mut block)?;
+                let t = ((n * j + i) as u64).to_be_bytes();
+                for (a, (b, t)) in out[..8].iter_mut().zip(block[..8].iter().zip(t.iter())) {
+                    *a = b ^ t;
+                }
+                out[8 * i..8 * i + 8].copy_from_slice(&block[8..]);
+            }
+        }
+        Ok(())
+    })();
+    wipe(&mut block);
+    match result {
+        Ok(()) => Ok(out),
+        Err(e) => {
+            wipe(&mut out);
+            Err(e)
+        }
+    }
+}
+
+// Unwraps `wrapped` with `scheme`, `decrypt` decrypting a block in place with the KEK, and
+// checks its integrity.
+fn key_unwrap(
+    scheme: KeyWrap,
+    wrapped: &[u8],
+    mut decrypt: impl FnMut(&mut [u8; 16]) -> Result<()>,
+) -> Result<Vec<u8>> {
+    let n = (wrapped.len() / 8).saturating_sub(1);
+    let min_blocks = match scheme {
+        KeyWrap::Rfc3394 => 2,
+        KeyWrap::Rfc5649 => 1,
+    };
+    if !wrapped.len().is_multiple_of(8) || n < min_blocks {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut data = wrapped.to_vec();
+    let mut block = [0u8; 16];
+    let result = (|| {
+        if n == 1 {
+            block.copy_from_slice(&data);
+            decrypt(&mut block)?;
+            data.copy_from_slice(&block);
+        } else {
+            for j in (0..6).rev() {
+                for i in (1..=n).rev() {
+                    let t = ((n * j + i) as u64).to_be_bytes();
+                    for (b, (a, t)) in block[..8].iter_mut().zip(data[..8].iter().zip(t.iter())) {
+                        *b = a ^ t;
+                    }
+                    block[8..].copy_from_slice(&data[8 * i..8 * i + 8]);
+                    decrypt(&mut block)?;
+                    data[..8].copy_from_slice(&block[..8]);
+                    data[8 * i..8 * i + 8].copy_from_slice(&block[8..]);
+                }
+            }
+        }
+        // The integrity check runs in constant time, not to tell how much of
+        // the IV, length or padding was right.
+        let (valid, len) = match scheme {
+            KeyWrap::Rfc3394 => (data[..8].ct_eq(&KW_IV), n * 8),
+            KeyWrap::Rfc5649 => {
+                let mut mli = [0u8; 4];
+                mli.copy_from_slice(&data[4..8]);
+                let len = u32::from_be_bytes(mli) as usize;
+                let in_range = Choice::from((len > 8 * (n - 1) && len <= 8 * n) as u8);
+                let padding = data[8 + len.min(8 * n)..].iter().fold(0, |acc, &b| acc | b);
+                (data[..4].ct_eq(&KWP_IV_PREFIX) & in_range & padding.ct_eq(&0), len)
+            }
+        };
+        if !bool::from(valid) {
+            return Err(Error::new(ErrorKind::MacInvalid));
+        }
+        Ok(len)
+    })();
+    wipe(&mut block);
+    match result {
+        Ok(len) => {
+            data.copy_within(8..8 + len, 0);
+            wipe(&mut data[len..]);
+            data.truncate(len);
+            Ok(data)
+        }
+        Err(e) => {
+            wipe(&mut data);
+            Err(e)
+        }
+    }
+}
+
 /// An operation for performing MAC (Message Authentication Code) operations, such as `HMAC`
 /// or `AES-CMAC` operations. This operation is not used for Authenticated Encryption algorithms,
 /// which SHALL use the functions defined in [AE](AE).
@@ -918,6 +1310,78 @@ impl Mac {
         }
     }
 
+    /// Compute the MAC of `message` with `algorithm` and `key` in one call, allocating and
+    /// releasing the operation, and return it.
+    ///
+    /// # Parameters
+    ///
+    /// 1) `algorithm`: A [Mac](Mac) supported algorithm, e.g. [HmacSha256](AlgorithmId::HmacSha256)
+    ///    or [AesCmac](AlgorithmId::AesCmac), which the operation is initialized for without IV.
+    /// 2) `key`: A key object of the type `algorithm` requires, transient or persistent.
+    /// 3) `message`: The message to MAC.
+    ///
+    /// # Example
+    ///
+    /// ``` rust,no_run
+    /// # use optee_utee::{AlgorithmId, Mac, TransientObject};
+    /// # fn main() -> optee_utee::Result<()> {
+    /// # let key = TransientObject::null_object();
+    /// let tag = Mac::compute_oneshot(AlgorithmId::HmacSha256, &key, b"message")?;
+    /// Mac::verify_oneshot(AlgorithmId::HmacSha256, &key, b"message", &tag)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If `algorithm` is not supported.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    ///
+    /// # Panics
+    ///
+    /// 1) If `key` does not match `algorithm`.
+    /// 2) Hardware or cryptographic algorithm failure.
+    pub fn compute_oneshot<K: GenericObject>(
+        algorithm: AlgorithmId,
+        key: &K,
+        message: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mac = Self::keyed(algorithm, key)?;
+        let mut tag = vec![0u8; MAX_HMAC_LEN];
+        let len = mac.compute_final(message, &mut tag)?;
+        tag.truncate(len);
+        Ok(tag)
+    }
+
+    /// Verify that `tag` is the MAC of `message` with `algorithm` and `key` in one call. The
+    /// comparison is made by the TEE with [compare_final](Mac::compare_final), in constant time.
+    ///
+    /// # Errors
+    ///
+    /// 1) `MacInvalid`: If `tag` does not match.
+    /// 2) `NotSupported`: If `algorithm` is not supported.
+    /// 3) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    ///
+    /// # Panics
+    ///
+    /// Same as [compute_oneshot](Mac::compute_oneshot).
+    pub fn verify_oneshot<K: GenericObject>(
+        algorithm: AlgorithmId,
+        key: &K,
+        message: &[u8],
+        tag: &[u8],
+    ) -> Result<()> {
+        Self::keyed(algorithm, key)?.compare_final(message, tag)
+    }
+
+    // Allocates an operation of `algorithm` keyed with `key` and initialized without IV.
+    fn keyed<K: GenericObject>(algorithm: AlgorithmId, key: &K) -> Result<Self> {
+        let mac = Self::allocate(algorithm, key.info()?.object_size())?;
+        mac.set_key(key)?;
+        mac.init(&[]);
+        Ok(mac)
+    }
+
     /// Create a Mac operation without any specific algorithm or other data.
     pub fn null() -> Self {
         Self(OperationHandle::null())
@@ -955,6 +1419,19 @@ impl Mac {
     pub fn copy<T: OpHandle>(&mut self, src: &T) {
         self.0.copy(src)
     }
+
+    /// Return a new operation holding a copy of the state and key of this one, e.g. to finish a
+    /// snapshot of a running MAC while continuing to feed this operation. It is allocated with
+    /// the same algorithm, mode and maximum key size, then filled by [copy](Self::copy).
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: If not enough resources are available to allocate the operation.
+    /// 2) `BadState`: If this operation is [null](Self::null), or the TEE allocates an operation
+    ///    its state cannot be copied to.
+    pub fn try_clone(&self) -> Result<Self> {
+        self.0.try_clone().map(Self)
+    }
 }
 
 impl OpHandle for Mac {
@@ -1250,6 +1727,118 @@ impl OpHandle for AE {
     }
 }
 
+/// Length in bytes of the tag [aead_encrypt](aead_encrypt) appends to the ciphertext.
+pub const AEAD_TAG_LEN: usize = 16;
+
+/// Length in bytes of the nonces AES-GCM is meant to be used with, e.g. drawn from
+/// [Random](Random) for each message.
+pub const AEAD_NONCE_LEN: usize = 12;
+
+/// Encrypt `plaintext` with AES-GCM under `key`, authenticating `aad` along with it, and return
+/// the ciphertext followed by the tag of [AEAD_TAG_LEN](AEAD_TAG_LEN) bytes.
+///
+/// # Parameters
+///
+/// 1) `key`: An [Aes](crate::TransientObjectType::Aes) key object, transient or persistent.
+/// 2) `nonce`: The nonce, usually of [AEAD_NONCE_LEN](AEAD_NONCE_LEN) bytes, which must never be
+///    reused with the same key.
+/// 3) `aad`: Additional data to authenticate but not encrypt, empty if not used.
+/// 4) `plaintext`: The data to encrypt.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{aead_decrypt, aead_encrypt, Random, TransientObject, TransientObjectType};
+/// # use optee_utee::AEAD_NONCE_LEN;
+/// # fn main() -> optee_utee::Result<()> {
+/// let key = TransientObject::allocate(TransientObjectType::Aes, 256)?;
+/// key.generate_key(256, &[])?;
+/// let mut nonce = [0u8; AEAD_NONCE_LEN];
+/// Random::generate(&mut nonce);
+/// let sealed = aead_encrypt(&key, &nonce, b"header", b"payload")?;
+/// assert_eq!(aead_decrypt(&key, &nonce, b"header", &sealed)?, b"payload");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the nonce length is not supported by the TEE.
+/// 2) `OutOfMemory`: If not enough resources are available to allocate the operation.
+///
+/// # Panics
+///
+/// 1) If `key` is not a valid AES key object.
+/// 2) Hardware or cryptographic algorithm failure.
+pub fn aead_encrypt<K: GenericObject>(
+    key: &K,
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let op = aead_operation(key, OperationMode::Encrypt, nonce, aad)?;
+    let mut sealed = vec![0u8; plaintext.len() + AEAD_TAG_LEN];
+    let (ciphertext, tag) = sealed.split_at_mut(plaintext.len());
+    match op.encrypt_final(plaintext, ciphertext, tag)? {
+        (len, AEAD_TAG_LEN) if len == plaintext.len() => Ok(sealed),
+        _ => Err(Error::new(ErrorKind::BadFormat)),
+    }
+}
+
+/// Decrypt `sealed`, the output of [aead_encrypt](aead_encrypt), and return the plaintext once
+/// the tag is found to authenticate it and `aad`.
+///
+/// # Errors
+///
+/// 1) `MacInvalid`: If `sealed` or `aad` was modified, or `key` or `nonce` differ from the ones
+///    used to encrypt. No plaintext is returned then.
+/// 2) `BadParameters`: If `sealed` is shorter than a tag.
+/// 3) `NotSupported`: If the nonce length is not supported by the TEE.
+/// 4) `OutOfMemory`: If not enough resources are available to allocate the operation.
+///
+/// # Panics
+///
+/// 1) If `key` is not a valid AES key object.
+/// 2) Hardware or cryptographic algorithm failure.
+pub fn aead_decrypt<K: GenericObject>(
+    key: &K,
+    nonce: &[u8],
+    aad: &[u8],
+    sealed: &[u8],
+) -> Result<Vec<u8>> {
+    if sealed.len() < AEAD_TAG_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - AEAD_TAG_LEN);
+    let op = aead_operation(key, OperationMode::Decrypt, nonce, aad)?;
+    let mut plaintext = vec![0u8; ciphertext.len()];
+    match op.decrypt_final(ciphertext, &mut plaintext, tag) {
+        Ok(len) if len == ciphertext.len() => Ok(plaintext),
+        result => {
+            // The TEE may have written the plaintext before checking the tag.
+            wipe(&mut plaintext);
+            result.and(Err(Error::new(ErrorKind::BadFormat)))
+        }
+    }
+}
+
+// Allocates an AES-GCM operation in `mode` keyed with `key`, initialized with `nonce` and having
+// authenticated `aad`.
+fn aead_operation<K: GenericObject>(
+    key: &K,
+    mode: OperationMode,
+    nonce: &[u8],
+    aad: &[u8],
+) -> Result<AE> {
+    let op = AE::allocate(AlgorithmId::AesGcm, mode, key.info()?.object_size())?;
+    op.set_key(key)?;
+    op.init(nonce, AEAD_TAG_LEN * 8, aad.len(), 0)?;
+    if !aad.is_empty() {
+        op.update_aad(aad);
+    }
+    Ok(op)
+}
+
 /// An operation for conducting asymmetric encryption /decryption or asymmetric sign / verify.
 /// Note that asymmetric encryption is always “single-stage”,
 /// which differs from [Cipher](Cipher) which are always “multi-stage”.
@@ -1312,26 +1901,7 @@ impl Asymmetric {
     // short, and example acipher utilizes this feature!
     // Define this function as unsafe because we need to return Ok for short buffer error.
     pub fn encrypt(&self, params: &[Attribute], src: &[u8]) -> Result<Vec<u8>> {
-        let p: Vec<raw::TEE_Attribute> = params.iter().map(|p| p.raw()).collect();
-        let mut res_size: usize = self.info().key_size() as usize;
-        let mut res_vec: Vec<u8> = vec![0u8; res_size];
-        match unsafe {
-            raw::TEE_AsymmetricEncrypt(
-                self.handle(),
-                p.as_ptr() as _,
-                params.len() as u32,
-                src.as_ptr() as _,
-                src.len(),
-                res_vec.as_mut_ptr() as _,
-                &mut res_size,
-            )
-        } {
-            raw::TEE_SUCCESS => {
-                res_vec.truncate(res_size);
-                Ok(res_vec)
-            }
-            code => Err(Error::from_raw_error(code)),
-        }
+        self.crypt(raw::TEE_AsymmetricEncrypt, params, src)
     }
 
     /// Decrypt a message.
@@ -1355,26 +1925,51 @@ impl Asymmetric {
     /// 3) Hardware or cryptographic algorithm failure.
     /// 4) If the Implementation detects any other error.
     pub fn decrypt(&self, params: &[Attribute], src: &[u8]) -> Result<Vec<u8>> {
+        self.crypt(raw::TEE_AsymmetricDecrypt, params, src)
+    }
+
+    // Runs `TEE_AsymmetricEncrypt` or `TEE_AsymmetricDecrypt` into a buffer of the key size,
+    // grown once to the size the TEE asks for if it is too short, as for SM2 ciphertexts.
+    fn crypt(
+        &self,
+        f: unsafe extern "C" fn(
+            raw::TEE_OperationHandle,
+            *const raw::TEE_Attribute,
+            u32,
+            *const c_void,
+            usize,
+            *mut c_void,
+            *mut usize,
+        ) -> raw::TEE_Result,
+        params: &[Attribute],
+        src: &[u8],
+    ) -> Result<Vec<u8>> {
         let p: Vec<raw::TEE_Attribute> = params.iter().map(|p| p.raw()).collect();
         let mut res_size: usize = self.info().key_size() as usize;
         let mut res_vec: Vec<u8> = vec![0u8; res_size];
-        match unsafe {
-            raw::TEE_AsymmetricDecrypt(
-                self.handle(),
-                p.as_ptr() as _,
-                params.len() as u32,
-                src.as_ptr() as _,
-                src.len(),
-                res_vec.as_mut_ptr() as _,
-                &mut res_size,
-            )
-        } {
-            raw::TEE_SUCCESS => {
-                res_vec.truncate(res_size);
-                Ok(res_vec)
+        for _ in 0..2 {
+            match unsafe {
+                f(
+                    self.handle(),
+                    p.as_ptr(),
+                    params.len() as u32,
+                    src.as_ptr() as _,
+                    src.len(),
+                    res_vec.as_mut_ptr() as _,
+                    &mut res_size,
+                )
+            } {
+                raw::TEE_SUCCESS => {
+                    res_vec.truncate(res_size);
+                    return Ok(res_vec);
+                }
+                raw::TEE_ERROR_SHORT_BUFFER if res_size > res_vec.len() => {
+                    res_vec.resize(res_size, 0);
+                }
+                code => return Err(Error::from_raw_error(code)),
             }
-            code => Err(Error::from_raw_error(code)),
         }
+        Err(Error::new(ErrorKind::ShortBuffer))
     }
 
     /// Sign a message digest.
@@ -1508,6 +2103,222 @@ impl OpHandle for Asymmetric {
     }
 }
 
+/// Hash-then-sign of a message received in chunks, e.g. over several
+/// invocations of a command, without buffering it.
+///
+/// The TA keeps the signer in its session context, feeds it each chunk with
+/// [`chunk`](StreamingSigner::chunk) and produces the signature with
+/// [`end`](StreamingSigner::end), after which the signer accepts a new
+/// message. The digest is the one the signature algorithm names, so only
+/// algorithms signing a hash are supported: EdDSA signs the message itself.
+///
+/// The signer only spans invocations the TA sees: a memref streamed by the
+/// CA in chunks is reassembled by the TA Manager before the TA is invoked,
+/// and can be signed in one call with [Signer](Signer).
+///
+/// # Example
+/// ``` rust,no_run
+/// # use optee_utee::{AlgorithmId, Result, StreamingSigner, TransientObject};
+/// fn sign_chunks(key: &TransientObject, chunks: &[&[u8]]) -> Result<Vec<u8>> {
+///     let mut signer = StreamingSigner::new(AlgorithmId::EcDsaSha256, key)?;
+///     for chunk in chunks {
+///         signer.chunk(chunk);
+///     }
+///     let mut signature = vec![0u8; 64];
+///     let len = signer.end(&mut signature)?;
+///     signature.truncate(len);
+///     Ok(signature)
+/// }
+/// ```
+pub struct StreamingSigner {
+    digest: Digest,
+    op: Asymmetric,
+}
+
+impl StreamingSigner {
+    /// Creates a signer hashing with the digest `sign` names and signing the
+    /// digest with `sign` and `key`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If `sign` is not supported.
+    /// 2) `BadParameters`: If `sign` does not sign a hash, e.g. for
+    ///    [Ed25519](AlgorithmId::Ed25519), or `key` does not match `sign`.
+    pub fn new<K: GenericObject>(sign: AlgorithmId, key: &K) -> Result<Self> {
+        let hash = streamed_hash(sign)?;
+        let op = Asymmetric::allocate(sign, OperationMode::Sign, key.info()?.object_size())?;
+        op.set_key(key)?;
+        Ok(Self {
+            digest: Digest::allocate(hash)?,
+            op,
+        })
+    }
+
+    /// Adds the next chunk of the message to the digest.
+    pub fn chunk(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    /// Signs the digest of the chunks received since the signer was created or
+    /// last ended, and returns the length of the signature written to
+    /// `signature`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ShortBuffer`: If `signature` is not large enough to hold the result.
+    pub fn end(&mut self, signature: &mut [u8]) -> Result<usize> {
+        let mut hash = [0u8; 64];
+        let hash_len = self.digest.do_final(&[], &mut hash)?;
+        self.op.sign_digest(&[], &hash[..hash_len], signature)
+    }
+}
+
+// What a signature algorithm signs: the digest of the message with a hash algorithm, or the
+// message itself for EdDSA.
+fn signed_hash(algorithm: AlgorithmId) -> Result<Option<AlgorithmId>> {
+    use AlgorithmId::*;
+    Ok(Some(match algorithm {
+        RsassaPkcs1V15MD5 | RsassaPkcs1PssMgf1MD5 => Md5,
+        RsassaPkcs1V15Sha1 | RsassaPkcs1PssMgf1Sha1 | DSASha1 | EcDsaSha1 => Sha1,
+        RsassaPkcs1V15Sha224 | RsassaPkcs1PssMgf1Sha224 | DSASha224 | EcDsaSha224 => Sha224,
+        RsassaPkcs1V15Sha256 | RsassaPkcs1PssMgf1Sha256 | DSASha256 | EcDsaSha256 => Sha256,
+        RsassaPkcs1V15Sha384 | RsassaPkcs1PssMgf1Sha384 | EcDsaSha384 => Sha384,
+        RsassaPkcs1V15Sha512 | RsassaPkcs1PssMgf1Sha512 | EcDsaSha512 => Sha512,
+        RsassaPkcs1V15MD5Sha1 => Md5Sha1,
+        Ed25519 => return Ok(None),
+        _ => return Err(Error::new(ErrorKind::BadParameters)),
+    }))
+}
+
+// Returns the hash a streaming signer of `algorithm` computes, which must sign a hash.
+fn streamed_hash(algorithm: AlgorithmId) -> Result<AlgorithmId> {
+    signed_hash(algorithm)?.ok_or_else(|| Error::new(ErrorKind::BadParameters))
+}
+
+// Allocates an operation of `algorithm` in `mode` keyed with `key`, and the digest operation
+// of the hash it signs, if any.
+fn signature_operations<K: GenericObject>(
+    algorithm: AlgorithmId,
+    mode: OperationMode,
+    key: &K,
+) -> Result<(Option<Digest>, Asymmetric)> {
+    let digest = match signed_hash(algorithm)? {
+        Some(hash) => Some(Digest::allocate(hash)?),
+        None => None,
+    };
+    let op = Asymmetric::allocate(algorithm, mode, key.info()?.object_size())?;
+    op.set_key(key)?;
+    Ok((digest, op))
+}
+
+// Returns the hash `digest` computes of `message` in `hash`, or `message` itself if there is
+// no digest.
+fn signed_data<'a>(
+    digest: Option<&Digest>,
+    message: &'a [u8],
+    hash: &'a mut [u8; 64],
+) -> Result<&'a [u8]> {
+    match digest {
+        Some(digest) => {
+            let len = digest.do_final(message, hash)?;
+            Ok(&hash[..len])
+        }
+        None => Ok(message),
+    }
+}
+
+/// Signs messages in one call with a signature algorithm of [Asymmetric](Asymmetric), hashing
+/// them first with the digest the algorithm names, e.g. SHA-384 for
+/// [RsassaPkcs1PssMgf1Sha384](AlgorithmId::RsassaPkcs1PssMgf1Sha384).
+///
+/// A signer can be kept, e.g. in the session context, and sign any number of messages. Messages
+/// received in chunks are signed with [StreamingSigner](StreamingSigner) instead.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{AlgorithmId, Signer, TransientObject, Verifier};
+/// # fn main() -> optee_utee::Result<()> {
+/// # let key_pair = TransientObject::null_object();
+/// let signer = Signer::new(AlgorithmId::EcDsaSha256, &key_pair)?;
+/// let signature = signer.sign(b"message")?;
+/// Verifier::new(AlgorithmId::EcDsaSha256, &key_pair)?.verify(b"message", &signature)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Signer {
+    digest: Option<Digest>,
+    op: Asymmetric,
+}
+
+impl Signer {
+    /// Creates a signer for `algorithm`, signing with the key pair `key`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `algorithm` is not a signature algorithm naming its hash, e.g.
+    ///    [RsassaPkcs1V15](AlgorithmId::RsassaPkcs1V15) which signs a digest computed by the
+    ///    caller, or [Sm2DsaSm3](AlgorithmId::Sm2DsaSm3), see [sm2](crate::sm2).
+    /// 2) `NotSupported`: If `algorithm` or its hash is not supported.
+    /// 3) `OutOfMemory`: If not enough resources are available to allocate the operations.
+    ///
+    /// # Panics
+    ///
+    /// 1) If `key` does not match `algorithm`.
+    pub fn new<K: GenericObject>(algorithm: AlgorithmId, key: &K) -> Result<Self> {
+        let (digest, op) = signature_operations(algorithm, OperationMode::Sign, key)?;
+        Ok(Self { digest, op })
+    }
+
+    /// Signs `message` and returns the signature.
+    ///
+    /// # Panics
+    ///
+    /// 1) Hardware or cryptographic algorithm failure.
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let mut hash = [0u8; 64];
+        let data = signed_data(self.digest.as_ref(), message, &mut hash)?;
+        // Signatures are at most twice the key size, for (EC)DSA and EdDSA.
+        let mut signature = vec![0u8; 2 * (self.op.info().key_size() as usize).div_ceil(8)];
+        let len = self.op.sign_digest(&[], data, &mut signature)?;
+        signature.truncate(len);
+        Ok(signature)
+    }
+}
+
+/// Verifies signatures of messages in one call, as [Signer](Signer) makes them.
+pub struct Verifier {
+    digest: Option<Digest>,
+    op: Asymmetric,
+}
+
+impl Verifier {
+    /// Creates a verifier for `algorithm`, verifying with the public key or key pair `key`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [Signer::new](Signer::new).
+    pub fn new<K: GenericObject>(algorithm: AlgorithmId, key: &K) -> Result<Self> {
+        let (digest, op) = signature_operations(algorithm, OperationMode::Verify, key)?;
+        Ok(Self { digest, op })
+    }
+
+    /// Verifies that `signature` is a signature of `message`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `SignatureInvalid`: If the signature does not match.
+    ///
+    /// # Panics
+    ///
+    /// 1) Hardware or cryptographic algorithm failure.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let mut hash = [0u8; 64];
+        let data = signed_data(self.digest.as_ref(), message, &mut hash)?;
+        self.op.verify_digest(&[], data, signature)
+    }
+}
+
 /// An operation for derive a shared key object.
 pub struct DeriveKey(OperationHandle);
 
@@ -1621,6 +2432,18 @@ impl DeriveKey {
         self.0.set_key(object)
     }
 
+    /// Function usage is similar to [Cipher::set_key_2](Cipher::set_key_2), for
+    /// [Sm2Kep](AlgorithmId::Sm2Kep): `object1` is the static key pair and `object2` the
+    /// ephemeral one.
+    pub fn set_key_2<T: GenericObject, D: GenericObject>(&self, object1: &T, object2: &D) -> Result<()> {
+        match unsafe {
+            raw::TEE_SetOperationKey2(self.handle(), object1.handle(), object2.handle())
+        } {
+            raw::TEE_SUCCESS => Ok(()),
+            code => Err(Error::from_raw_error(code)),
+        }
+    }
+
     /// Function usage is similar to [Digest::copy](Digest::copy).
     pub fn copy<T: OpHandle>(&mut self, src: &T) {
         self.0.copy(src)
@@ -1662,7 +2485,704 @@ impl Random {
     }
 }
 
+/// A random number generator drawing from [Random](Random), for crates that take an
+/// `impl RngCore + CryptoRng`, e.g. to generate keys or nonces. Available with the `rand_core`
+/// feature.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::TeeRng;
+/// use rand_core::RngCore;
+///
+/// let mut nonce = [0u8; 12];
+/// TeeRng.fill_bytes(&mut nonce);
+/// let id = TeeRng.next_u64();
+/// ```
+///
+/// # Panics
+///
+/// Like [generate](Random::generate), on hardware or cryptographic algorithm failure: the TEE
+/// reports no error [try_fill_bytes](rand_core::RngCore::try_fill_bytes) could return.
+#[cfg(feature = "rand_core")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TeeRng;
+
+#[cfg(feature = "rand_core")]
+impl rand_core::RngCore for TeeRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        Random::generate(&mut bytes);
+        u32::from_ne_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        Random::generate(&mut bytes);
+        u64::from_ne_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        Random::generate(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> core::result::Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "rand_core")]
+impl rand_core::CryptoRng for TeeRng {}
+
+/// Length in bytes of the HMAC-SHA256 output [hkdf](hkdf) is built on. A derived key can be at
+/// most 255 times as long.
+pub const HKDF_HASH_LEN: usize = 32;
+
+// Largest output and block lengths of the HMAC algorithms used for key derivation.
+const MAX_HMAC_LEN: usize = 64;
+const MAX_HMAC_BLOCK_LEN: usize = 128;
+
+// Longest password and derived key of the PBKDF2 extension of OP-TEE, as it holds them in
+// objects of up to 4096 bits.
+const MAX_PBKDF2_OBJECT_LEN: usize = 512;
+
+/// Derive `len` bytes from the input keying material `ikm` with HKDF-SHA256 (RFC 5869), i.e.
+/// HKDF-Extract with `salt` followed by HKDF-Expand with `info`, over [Mac](Mac) operations.
+/// The derived bytes are returned in a [SecretBuffer](SecretBuffer), wiped once dropped.
+///
+/// # Parameters
+///
+/// 1) `salt`: Optional salt, of any length, empty if not used.
+/// 2) `ikm`: Input keying material, e.g. a Diffie-Hellman shared secret.
+/// 3) `info`: Context and application specific information binding the key to its use.
+/// 4) `len`: Length in bytes of the key to derive.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::hkdf;
+/// # fn main() -> optee_utee::Result<()> {
+/// # let shared_secret = [0u8; 32];
+/// let okm = hkdf(b"salt", &shared_secret, b"session keys", 64)?;
+/// let (encryption_key, mac_key) = okm.split_at(32);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `len` is greater than 255 times [HKDF_HASH_LEN](HKDF_HASH_LEN).
+/// 2) `OutOfMemory`: If not enough resources are available to allocate the operations.
+pub fn hkdf(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Result<SecretBuffer> {
+    let mut okm = SecretBuffer::new(len);
+    hkdf_fill(salt, ikm, info, &mut okm)?;
+    Ok(okm)
+}
+
+/// Derive a key of `key_size` bits with [hkdf](hkdf) into the `SecretValue` attribute of a new
+/// transient object of `object_type`, e.g. an AES or HMAC key to set on an operation.
+///
+/// The derived bytes are never handed to the caller: they only transit through a buffer of
+/// this function, wiped before it returns.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{hkdf_key, AlgorithmId, Cipher, OperationMode, TransientObjectType};
+/// # fn main() -> optee_utee::Result<()> {
+/// # let shared_secret = [0u8; 32];
+/// let key = hkdf_key(&[], &shared_secret, b"disk key", TransientObjectType::Aes, 256)?;
+/// let cipher = Cipher::allocate(AlgorithmId::AesCtr, OperationMode::Encrypt, 256)?;
+/// cipher.set_key(&key)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `key_size` is not a multiple of 8, or is greater than 255 times
+///    [HKDF_HASH_LEN](HKDF_HASH_LEN) bytes.
+/// 2) `NotSupported`: If `key_size` is not supported by `object_type`.
+/// 3) `OutOfMemory`: If not enough resources are available to allocate the operations or the
+///    object.
+pub fn hkdf_key(
+    salt: &[u8],
+    ikm: &[u8],
+    info: &[u8],
+    object_type: TransientObjectType,
+    key_size: usize,
+) -> Result<TransientObject> {
+    if !key_size.is_multiple_of(8) {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut okm = vec![0u8; key_size / 8];
+    let result = (|| {
+        hkdf_fill(salt, ikm, info, &mut okm)?;
+        let mut key = TransientObject::allocate(object_type, key_size)?;
+        key.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &okm).into()])?;
+        Ok(key)
+    })();
+    wipe(&mut okm);
+    result
+}
+
+// Fills `okm` with HKDF-SHA256 output.
+fn hkdf_fill(salt: &[u8], ikm: &[u8], info: &[u8], okm: &mut [u8]) -> Result<()> {
+    if okm.len() > 255 * HKDF_HASH_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut prk = [0u8; HKDF_HASH_LEN];
+    let mut t = [0u8; HKDF_HASH_LEN];
+    let result = (|| {
+        let extract = hmac(AlgorithmId::HmacSha256, salt)?;
+        extract.init(&[]);
+        extract.compute_final(ikm, &mut prk)?;
+
+        let expand = hmac(AlgorithmId::HmacSha256, &prk)?;
+        let mut t_len = 0;
+        for (i, chunk) in okm.chunks_mut(HKDF_HASH_LEN).enumerate() {
+            expand.init(&[]);
+            expand.update(&t[..t_len]);
+            expand.update(info);
+            t_len = expand.compute_final(&[i as u8 + 1], &mut t)?;
+            chunk.copy_from_slice(&t[..chunk.len()]);
+        }
+        Ok(())
+    })();
+    wipe(&mut prk);
+    wipe(&mut t);
+    result
+}
+
+/// Derive `len` bytes from `password` with PBKDF2 (RFC 8018), e.g. to unlock storage with a
+/// user PIN.
+///
+/// PBKDF2-HMAC-SHA1 runs in the TEE core where OP-TEE is built with its PBKDF2 extension, see
+/// [Pbkdf2HmacSha1DeriveKey](AlgorithmId::Pbkdf2HmacSha1DeriveKey). Otherwise, and with other
+/// PRFs, each iteration is an HMAC computed by a [Mac](Mac) operation, so that large iteration
+/// counts cost as many calls to the TEE.
+///
+/// The derived key is returned in a [SecretBuffer](SecretBuffer), so that it is wiped from
+/// memory once no longer needed.
+///
+/// # Parameters
+///
+/// 1) `prf`: [HmacSha1](AlgorithmId::HmacSha1), [HmacSha224](AlgorithmId::HmacSha224),
+///    [HmacSha256](AlgorithmId::HmacSha256), [HmacSha384](AlgorithmId::HmacSha384) or
+///    [HmacSha512](AlgorithmId::HmacSha512).
+/// 2) `password`: The password, of any length.
+/// 3) `salt`: The salt, e.g. 16 random bytes stored along with the derived key.
+/// 4) `iterations`: The iteration count, at least 1.
+/// 5) `len`: Length in bytes of the key to derive.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{pbkdf2, AlgorithmId};
+/// # fn main() -> optee_utee::Result<()> {
+/// # let salt = [0u8; 16];
+/// let key = pbkdf2(AlgorithmId::HmacSha256, b"1234", &salt, 600_000, 32)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `prf` is not one of the algorithms above, or `iterations` is 0.
+/// 2) `OutOfMemory`: If not enough resources are available to allocate the operations or the
+///    objects.
+pub fn pbkdf2(
+    prf: AlgorithmId,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    len: usize,
+) -> Result<SecretBuffer> {
+    let mut dk = SecretBuffer::new(len);
+    pbkdf2_fill(prf, password, salt, iterations, &mut dk)?;
+    Ok(dk)
+}
+
+/// Derive a key of `key_size` bits with [pbkdf2](pbkdf2) into the `SecretValue` attribute of a
+/// new transient object of `object_type`, wiping the derived bytes as
+/// [hkdf_key](hkdf_key) does.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `prf` is not supported by [pbkdf2](pbkdf2), `iterations` is 0 or
+///    `key_size` is not a multiple of 8.
+/// 2) `NotSupported`: If `key_size` is not supported by `object_type`.
+/// 3) `OutOfMemory`: If not enough resources are available to allocate the operations or the
+///    objects.
+pub fn pbkdf2_key(
+    prf: AlgorithmId,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    object_type: TransientObjectType,
+    key_size: usize,
+) -> Result<TransientObject> {
+    if !key_size.is_multiple_of(8) {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut dk = vec![0u8; key_size / 8];
+    let result = (|| {
+        pbkdf2_fill(prf, password, salt, iterations, &mut dk)?;
+        let mut key = TransientObject::allocate(object_type, key_size)?;
+        key.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &dk).into()])?;
+        Ok(key)
+    })();
+    wipe(&mut dk);
+    result
+}
+
+// Fills `dk` with PBKDF2 output, in the TEE core if it can.
+fn pbkdf2_fill(
+    prf: AlgorithmId,
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    dk: &mut [u8],
+) -> Result<()> {
+    let (_, _, hash_len, _) = hmac_params(prf)?;
+    if iterations == 0 {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    if matches!(prf, AlgorithmId::HmacSha1)
+        && (1..=MAX_PBKDF2_OBJECT_LEN).contains(&password.len())
+        && (1..=MAX_PBKDF2_OBJECT_LEN).contains(&dk.len())
+        && is_algorithm_supported(AlgorithmId::Pbkdf2HmacSha1DeriveKey as u32, 0).is_ok()
+    {
+        return pbkdf2_derive_key(password, salt, iterations, dk);
+    }
+
+    let mac = hmac(prf, password)?;
+    let mut u = [0u8; MAX_HMAC_LEN];
+    let mut next = [0u8; MAX_HMAC_LEN];
+    let mut t = [0u8; MAX_HMAC_LEN];
+    let result = (|| {
+        for (i, chunk) in dk.chunks_mut(hash_len).enumerate() {
+            mac.init(&[]);
+            mac.update(salt);
+            mac.compute_final(&(i as u32 + 1).to_be_bytes(), &mut u[..hash_len])?;
+            t.copy_from_slice(&u);
+            for _ in 1..iterations {
+                mac.init(&[]);
+                mac.compute_final(&u[..hash_len], &mut next[..hash_len])?;
+                mem::swap(&mut u, &mut next);
+                t.iter_mut().zip(u.iter()).for_each(|(t, u)| *t ^= u);
+            }
+            chunk.copy_from_slice(&t[..chunk.len()]);
+        }
+        Ok(())
+    })();
+    wipe(&mut u);
+    wipe(&mut next);
+    wipe(&mut t);
+    result
+}
+
+// Fills `dk` with PBKDF2-HMAC-SHA1 output computed by the OP-TEE extension.
+fn pbkdf2_derive_key(password: &[u8], salt: &[u8], iterations: u32, dk: &mut [u8]) -> Result<()> {
+    let password_size = password.len() * 8;
+    let mut password_object =
+        TransientObject::allocate(TransientObjectType::Pbkdf2Password, password_size)?;
+    password_object.populate(&[AttributeMemref::from_ref(
+        AttributeId::Pbkdf2Password,
+        password,
+    )
+    .into()])?;
+    let op = DeriveKey::allocate(AlgorithmId::Pbkdf2HmacSha1DeriveKey, password_size)?;
+    op.set_key(&password_object)?;
+    let mut derived = TransientObject::allocate(TransientObjectType::GenericSecret, dk.len() * 8)?;
+    op.derive(
+        &[
+            AttributeMemref::from_ref(AttributeId::Pbkdf2Salt, salt).into(),
+            AttributeValue::from_value(AttributeId::Pbkdf2IterationCount, iterations, 0).into(),
+            AttributeValue::from_value(AttributeId::Pbkdf2DkLength, dk.len() as u32, 0).into(),
+        ],
+        &mut derived,
+    );
+    if derived.ref_attribute(AttributeId::SecretValue, dk)? != dk.len() {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    Ok(())
+}
+
+// Hash, key object type, output and block lengths of the HMAC `algorithm`.
+fn hmac_params(algorithm: AlgorithmId) -> Result<(AlgorithmId, TransientObjectType, usize, usize)> {
+    use TransientObjectType as Type;
+    Ok(match algorithm {
+        AlgorithmId::HmacSha1 => (AlgorithmId::Sha1, Type::HmacSha1, 20, 64),
+        AlgorithmId::HmacSha224 => (AlgorithmId::Sha224, Type::HmacSha224, 28, 64),
+        AlgorithmId::HmacSha256 => (AlgorithmId::Sha256, Type::HmacSha256, 32, 64),
+        AlgorithmId::HmacSha384 => (AlgorithmId::Sha384, Type::HmacSha384, 48, 128),
+        AlgorithmId::HmacSha512 => (AlgorithmId::Sha512, Type::HmacSha512, 64, 128),
+        _ => return Err(Error::new(ErrorKind::BadParameters)),
+    })
+}
+
+// Allocates an HMAC operation of `algorithm` keyed with `key`. GP bounds the size of HMAC keys,
+// e.g. HMAC-SHA256 ones to 192 to 1024 bits, while HKDF salts and passwords may be empty or of
+// any length, so `key` is zero-padded to the block size, or hashed first if longer, as HMAC
+// itself would do.
+fn hmac(algorithm: AlgorithmId, key: &[u8]) -> Result<Mac> {
+    let (hash, object_type, hash_len, block_len) = hmac_params(algorithm)?;
+    let mut block = [0u8; MAX_HMAC_BLOCK_LEN];
+    let result = (|| {
+        if key.len() > block_len {
+            Digest::allocate(hash)?.do_final(key, &mut block[..hash_len])?;
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+        let mut object = TransientObject::allocate(object_type, block_len * 8)?;
+        object.populate(&[AttributeMemref::from_ref(
+            AttributeId::SecretValue,
+            &block[..block_len],
+        )
+        .into()])?;
+        let mac = Mac::allocate(algorithm, block_len * 8)?;
+        mac.set_key(&object)?;
+        Ok(mac)
+    })();
+    wipe(&mut block);
+    result
+}
+
+/// Key derivation [ecdh_derive](ecdh_derive) applies to the shared secret.
+pub enum EcdhKdf<'a> {
+    /// No derivation: the key is the shared secret itself, the x-coordinate of the shared point,
+    /// as a [GenericSecret](TransientObjectType::GenericSecret) object of the field size.
+    None,
+    /// [hkdf_key](hkdf_key) with `salt` and `info` into an object of `object_type` and
+    /// `key_size` bits, e.g. an AES key.
+    Hkdf {
+        salt: &'a [u8],
+        info: &'a [u8],
+        object_type: TransientObjectType,
+        key_size: usize,
+    },
+}
+
+/// Agree on a key with ECDH between the key pair `private_key` and the public key of a peer,
+/// and derive a secret key object from the shared secret with `kdf`.
+///
+/// # Parameters
+///
+/// 1) `private_key`: An [EcdhKeypair](TransientObjectType::EcdhKeypair) object, transient or
+///    persistent.
+/// 2) `peer_public_point`: The public key of the peer as an uncompressed point, `0x04 || x ||
+///    y`, on the curve of `private_key`.
+/// 3) `kdf`: The key derivation applied to the shared secret.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{ecdh_derive, EcdhKdf, TransientObject, TransientObjectType};
+/// # fn main() -> optee_utee::Result<()> {
+/// # let key_pair = TransientObject::null_object();
+/// # let peer_public_point = [4u8; 65];
+/// let kdf = EcdhKdf::Hkdf {
+///     salt: &[],
+///     info: b"channel key",
+///     object_type: TransientObjectType::Aes,
+///     key_size: 256,
+/// };
+/// let key = ecdh_derive(&key_pair, &peer_public_point, kdf)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `peer_public_point` is not an uncompressed point of the size of the
+///    curve, or `kdf` is not supported by [hkdf_key](hkdf_key).
+/// 2) `NotSupported`: If the curve of `private_key` is not supported.
+/// 3) `OutOfMemory`: If not enough resources are available to allocate the operations or the
+///    objects.
+///
+/// # Panics
+///
+/// 1) If `private_key` is not an ECDH key pair.
+/// 2) If `peer_public_point` is not on the curve of `private_key`.
+pub fn ecdh_derive<K: GenericObject>(
+    private_key: &K,
+    peer_public_point: &[u8],
+    kdf: EcdhKdf,
+) -> Result<TransientObject> {
+    let key_size = private_key.info()?.object_size();
+    let field_len = key_size.div_ceil(8);
+    match peer_public_point.split_first() {
+        Some((0x04, xy)) if xy.len() == 2 * field_len => {}
+        _ => return Err(Error::new(ErrorKind::BadParameters)),
+    }
+    let (x, y) = peer_public_point[1..].split_at(field_len);
+    let op = DeriveKey::allocate(AlgorithmId::EcDhDeriveSharedSecret, key_size)?;
+    op.set_key(private_key)?;
+    let mut shared = TransientObject::allocate(TransientObjectType::GenericSecret, field_len * 8)?;
+    op.derive(
+        &[
+            AttributeMemref::from_ref(AttributeId::EccPublicValueX, x).into(),
+            AttributeMemref::from_ref(AttributeId::EccPublicValueY, y).into(),
+        ],
+        &mut shared,
+    );
+    match kdf {
+        EcdhKdf::None => Ok(shared),
+        EcdhKdf::Hkdf {
+            salt,
+            info,
+            object_type,
+            key_size,
+        } => {
+            let secret = shared.secret_attribute(AttributeId::SecretValue)?;
+            hkdf_key(salt, &secret, info, object_type, key_size)
+        }
+    }
+}
+
+/// An operation an [OperationPool](OperationPool) caches: a [Digest](Digest), [Mac](Mac) or
+/// [Cipher](Cipher).
+pub trait PooledOperation: OpHandle + pooled::Sealed {}
+
+mod pooled {
+    pub trait Sealed {
+        fn into_handle(self) -> super::OperationHandle;
+    }
+}
+
+impl pooled::Sealed for Digest {
+    fn into_handle(self) -> OperationHandle {
+        self.0
+    }
+}
+
+impl PooledOperation for Digest {}
+
+impl pooled::Sealed for Mac {
+    fn into_handle(self) -> OperationHandle {
+        self.0
+    }
+}
+
+impl PooledOperation for Mac {}
+
+impl pooled::Sealed for Cipher {
+    fn into_handle(self) -> OperationHandle {
+        self.0
+    }
+}
+
+impl PooledOperation for Cipher {}
+
+// What an idle operation of an `OperationPool` was allocated with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct PoolKey {
+    algorithm: u32,
+    mode: u32,
+    max_key_size: u32,
+}
+
+impl PoolKey {
+    fn new(algorithm: AlgorithmId, mode: OperationMode, max_key_size: usize) -> Self {
+        Self {
+            algorithm: algorithm as u32,
+            mode: mode as u32,
+            max_key_size: max_key_size as u32,
+        }
+    }
+}
+
+/// A cache of idle operations keyed by algorithm, mode and maximum key size, sparing hot paths
+/// that hash or MAC many small messages a `TEE_AllocateOperation` and `TEE_FreeOperation` per
+/// message.
+///
+/// Operations are taken with [digest](OperationPool::digest), [mac](OperationPool::mac) or
+/// [cipher](OperationPool::cipher), which allocate one if none is idle, and given back with
+/// [release](OperationPool::release). Released operations are reset and their key cleared, so
+/// that they are always handed out in the initial state with no key programmed. At most
+/// `capacity` operations are kept idle, the least recently released being freed first.
+///
+/// # Example
+///
+/// ``` rust,no_run
+/// # use optee_utee::{AlgorithmId, OperationPool};
+/// # fn main() -> optee_utee::Result<()> {
+/// let mut pool = OperationPool::new(4);
+/// let mut hash = [0u8; 32];
+/// for message in [&b"first"[..], &b"second"[..]] {
+///     let digest = pool.digest(AlgorithmId::Sha256)?;
+///     digest.do_final(message, &mut hash)?;
+///     pool.release(digest);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct OperationPool {
+    idle: Vec<(PoolKey, OperationHandle)>,
+    capacity: usize,
+}
+
+impl OperationPool {
+    /// Create a pool keeping at most `capacity` idle operations.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            idle: Vec::new(),
+            capacity,
+        }
+    }
+
+    /// Take an idle [Digest](Digest) operation of `algorithm`, or allocate one.
+    ///
+    /// # Errors
+    ///
+    /// Same as [Digest::allocate](Digest::allocate).
+    pub fn digest(&mut self, algorithm: AlgorithmId) -> Result<Digest> {
+        self.take(algorithm, OperationMode::Digest, 0).map(Digest)
+    }
+
+    /// Take an idle [Mac](Mac) operation of `algorithm` and `max_key_size`, or allocate one. Its
+    /// key has to be set before use.
+    ///
+    /// # Errors
+    ///
+    /// Same as [Mac::allocate](Mac::allocate).
+    pub fn mac(&mut self, algorithm: AlgorithmId, max_key_size: usize) -> Result<Mac> {
+        self.take(algorithm, OperationMode::Mac, max_key_size)
+            .map(Mac)
+    }
+
+    /// Take an idle [Cipher](Cipher) operation of `algorithm`, `mode` and `max_key_size`, or
+    /// allocate one. Its key has to be set before use.
+    ///
+    /// # Errors
+    ///
+    /// Same as [Cipher::allocate](Cipher::allocate).
+    pub fn cipher(
+        &mut self,
+        algorithm: AlgorithmId,
+        mode: OperationMode,
+        max_key_size: usize,
+    ) -> Result<Cipher> {
+        self.take(algorithm, mode, max_key_size).map(Cipher)
+    }
+
+    /// Give `operation` back to the pool, resetting it and clearing its key. If the pool already
+    /// holds `capacity` idle operations, the least recently released one is freed.
+    ///
+    /// Operations need not come from this pool, but are keyed by the algorithm, mode and
+    /// maximum key size they report, so that they are only handed out again for the same ones.
+    pub fn release<T: PooledOperation>(&mut self, operation: T) {
+        let mut handle = operation.into_handle();
+        if self.capacity == 0 || handle.is_null() {
+            return;
+        }
+        handle.recycle();
+        let info = handle.info().raw;
+        let key = PoolKey {
+            algorithm: info.algorithm,
+            mode: info.mode,
+            max_key_size: info.maxKeySize,
+        };
+        if self.idle.len() == self.capacity {
+            self.idle.remove(0);
+        }
+        self.idle.push((key, handle));
+    }
+
+    /// Free the idle operations of `algorithm`, `mode` and `max_key_size`, returning how many
+    /// were.
+    pub fn evict(
+        &mut self,
+        algorithm: AlgorithmId,
+        mode: OperationMode,
+        max_key_size: usize,
+    ) -> usize {
+        let key = PoolKey::new(algorithm, mode, max_key_size);
+        let before = self.idle.len();
+        self.idle.retain(|(k, _)| *k != key);
+        before - self.idle.len()
+    }
+
+    /// Free every idle operation.
+    pub fn clear(&mut self) {
+        self.idle.clear();
+    }
+
+    /// Change the number of idle operations kept, freeing the least recently released ones
+    /// beyond it.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        let excess = self.idle.len().saturating_sub(capacity);
+        self.idle.drain(..excess);
+    }
+
+    /// Return the number of idle operations.
+    pub fn len(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Return whether no operation is idle.
+    pub fn is_empty(&self) -> bool {
+        self.idle.is_empty()
+    }
+
+    // Takes the most recently released operation matching, or allocates one.
+    fn take(
+        &mut self,
+        algorithm: AlgorithmId,
+        mode: OperationMode,
+        max_key_size: usize,
+    ) -> Result<OperationHandle> {
+        let key = PoolKey::new(algorithm, mode, max_key_size);
+        match self.idle.iter().rposition(|(k, _)| *k == key) {
+            Some(index) => Ok(self.idle.remove(index).1),
+            None => OperationHandle::allocate(algorithm, mode, max_key_size),
+        }
+    }
+}
+
+// With the `zeroize` feature, zeroizing an operation clears its state and key in the TEE, as
+// releasing it to an `OperationPool` does.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for Digest {
+    fn zeroize(&mut self) {
+        if !self.0.is_null() {
+            self.0.recycle();
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for Cipher {
+    fn zeroize(&mut self) {
+        if !self.0.is_null() {
+            self.0.recycle();
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for Mac {
+    fn zeroize(&mut self) {
+        if !self.0.is_null() {
+            self.0.recycle();
+        }
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for AE {
+    fn zeroize(&mut self) {
+        if !self.0.is_null() {
+            self.0.recycle();
+        }
+    }
+}
+
 /// Algorithms that can be allocated as an crypto operation.
+#[derive(Clone, Copy)]
 #[repr(u32)]
 pub enum AlgorithmId {
     /// [Cipher](Cipher) supported algorithm.
@@ -1701,6 +3221,12 @@ pub enum AlgorithmId {
     Des3CbcMacNopad = 0x30000113,
     /// [Mac](Mac) supported algorithm.
     Des3CbcMacPkcs5 = 0x30000513,
+    /// [Cipher](Cipher) supported algorithm.
+    Sm4EcbNopad = 0x10000014,
+    /// [Cipher](Cipher) supported algorithm.
+    Sm4CbcNopad = 0x10000114,
+    /// [Cipher](Cipher) supported algorithm.
+    Sm4Ctr = 0x10000214,
     /// [Asymmetric](Asymmetric) supported algorithm, can be applied with
     /// [Sign](OperationMode::Sign) or [Verify](OperationMode::Verify) mode.
     RsassaPkcs1V15 = 0xF0000830,
@@ -1800,6 +3326,19 @@ pub enum AlgorithmId {
     Ed25519 = 0x70006043,
     /// [DeriveKey](DeriveKey) supported algorithm.
     X25519 = 0x80000044,
+    /// [Asymmetric](Asymmetric) supported algorithm, can be applied with
+    /// [Sign](OperationMode::Sign) or [Verify](OperationMode::Verify) mode, over the SM3 hash
+    /// computed by the caller, see [sm2](crate::sm2).
+    Sm2DsaSm3 = 0x70006045,
+    /// [Asymmetric](Asymmetric) supported algorithm, can be applied with
+    /// [Encrypt](OperationMode::Encrypt) or [Decrypt](OperationMode::Decrypt) mode.
+    Sm2Pke = 0x80000045,
+    /// [DeriveKey](DeriveKey) supported algorithm, with two keys set by
+    /// [set_key_2](DeriveKey::set_key_2).
+    Sm2Kep = 0x60000045,
+    /// [DeriveKey](DeriveKey) supported algorithm. OP-TEE extension, available only if OP-TEE
+    /// is built with `CFG_CRYPTO_PBKDF2`.
+    Pbkdf2HmacSha1DeriveKey = 0x800020C2,
     /// [Digest](Digest) supported algorithm.
     Md5 = 0x50000001,
     /// [Digest](Digest) supported algorithm.
@@ -1812,6 +3351,16 @@ pub enum AlgorithmId {
     Sha384 = 0x50000005,
     /// [Digest](Digest) supported algorithm.
     Sha512 = 0x50000006,
+    /// [Digest](Digest) supported algorithm.
+    Sha3_224 = 0x50000008,
+    /// [Digest](Digest) supported algorithm.
+    Sha3_256 = 0x50000009,
+    /// [Digest](Digest) supported algorithm.
+    Sha3_384 = 0x5000000A,
+    /// [Digest](Digest) supported algorithm.
+    Sha3_512 = 0x5000000B,
+    /// [Digest](Digest) supported algorithm.
+    Sm3 = 0x50000007,
     /// [Mac](Mac) supported algorithm.
     Md5Sha1 = 0x5000000F,
     /// [Mac](Mac) supported algorithm.
@@ -1826,11 +3375,14 @@ pub enum AlgorithmId {
     HmacSha384 = 0x30000005,
     /// [Mac](Mac) supported algorithm.
     HmacSha512 = 0x30000006,
+    /// [Mac](Mac) supported algorithm.
+    HmacSm3 = 0x30000007,
     /// Reserved for GlobalPlatform compliance test applications.
     IllegalValue = 0xefffffff,
 }
 
 /// This specification defines support for optional cryptographic elements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum ElementId {
     /// Where algId fully defines the required support,
@@ -1848,4 +3400,189 @@ pub enum ElementId {
     EccCurveNistP521 = 0x00000005,
     /// Source: `IETF`, Generic: `N`, Size: 256 bits
     EccCurve25519 = 0x00000300,
+    /// Source: `OSCCA`, Generic: `N`, Size: 256 bits
+    EccCurveSm2 = 0x00000400,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
+    use aes::{Aes128, Aes192};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn wrap<C: KeyInit + BlockEncrypt>(scheme: KeyWrap, kek: &[u8], key: &[u8]) -> Result<Vec<u8>> {
+        let aes = C::new_from_slice(kek).unwrap();
+        key_wrap(scheme, key, |block| {
+            aes.encrypt_block(GenericArray::from_mut_slice(block));
+            Ok(())
+        })
+    }
+
+    fn unwrap<C: KeyInit + BlockDecrypt>(
+        scheme: KeyWrap,
+        kek: &[u8],
+        wrapped: &[u8],
+    ) -> Result<Vec<u8>> {
+        let aes = C::new_from_slice(kek).unwrap();
+        key_unwrap(scheme, wrapped, |block| {
+            aes.decrypt_block(GenericArray::from_mut_slice(block));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn signed_hash_follows_algorithm() {
+        assert!(matches!(
+            signed_hash(AlgorithmId::RsassaPkcs1PssMgf1Sha384),
+            Ok(Some(AlgorithmId::Sha384))
+        ));
+        assert!(matches!(
+            signed_hash(AlgorithmId::EcDsaSha256),
+            Ok(Some(AlgorithmId::Sha256))
+        ));
+        assert!(matches!(signed_hash(AlgorithmId::Ed25519), Ok(None)));
+        for algorithm in [AlgorithmId::RsassaPkcs1V15, AlgorithmId::Sm2DsaSm3] {
+            assert!(matches!(
+                signed_hash(algorithm),
+                Err(e) if e.kind() == ErrorKind::BadParameters
+            ));
+        }
+    }
+
+    #[test]
+    fn streamed_hash_follows_algorithm() {
+        assert!(matches!(
+            streamed_hash(AlgorithmId::EcDsaSha384),
+            Ok(AlgorithmId::Sha384)
+        ));
+        for algorithm in [AlgorithmId::Ed25519, AlgorithmId::RsassaPkcs1V15] {
+            assert!(matches!(
+                streamed_hash(algorithm),
+                Err(e) if e.kind() == ErrorKind::BadParameters
+            ));
+        }
+    }
+
+    #[test]
+    fn key_wrap_rfc3394_vector() {
+        // RFC 3394, 4.1.
+        let kek = hex("000102030405060708090a0b0c0d0e0f");
+        let key = hex("00112233445566778899aabbccddeeff");
+        let wrapped = hex("1fa68b0a8112b447aef34bd8fb5a7b829d3e862371d2cfe5");
+        assert_eq!(
+            wrap::<Aes128>(KeyWrap::Rfc3394, &kek, &key).unwrap(),
+            wrapped
+        );
+        assert_eq!(
+            unwrap::<Aes128>(KeyWrap::Rfc3394, &kek, &wrapped).unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn key_wrap_rfc5649_vectors() {
+        // RFC 5649, 6.
+        let kek = hex("5840df6e29b02af1ab493b705bf16ea1ae8338f4dcc176a8");
+        for (key, wrapped) in [
+            (
+                "c37b7e6492584340bed12207808941155068f738",
+                "138bdeaa9b8fa7fc61f97742e72248ee5ae6ae5360d1ae6a5f54f373fa543b6a",
+            ),
+            ("466f7250617369", "afbeb0f07dfbf5419200f2ccb50bb24f"),
+        ] {
+            let (key, wrapped) = (hex(key), hex(wrapped));
+            assert_eq!(
+                wrap::<Aes192>(KeyWrap::Rfc5649, &kek, &key).unwrap(),
+                wrapped
+            );
+            assert_eq!(
+                unwrap::<Aes192>(KeyWrap::Rfc5649, &kek, &wrapped).unwrap(),
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn key_wrap_rfc5649_round_trip() {
+        let kek = hex("5840df6e29b02af1ab493b705bf16ea1");
+        for len in [1usize, 7, 8, 9, 16, 20, 32, 33] {
+            let key: Vec<u8> = (0..len as u8).collect();
+            let wrapped = wrap::<Aes128>(KeyWrap::Rfc5649, &kek, &key).unwrap();
+            assert_eq!(wrapped.len(), 8 + len.div_ceil(8) * 8);
+            assert_eq!(
+                unwrap::<Aes128>(KeyWrap::Rfc5649, &kek, &wrapped).unwrap(),
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn key_unwrap_detects_tampering() {
+        let kek = hex("000102030405060708090a0b0c0d0e0f");
+        let key = hex("00112233445566778899aabbccddeeff");
+        for scheme in [KeyWrap::Rfc3394, KeyWrap::Rfc5649] {
+            let mut wrapped = wrap::<Aes128>(scheme, &kek, &key).unwrap();
+            wrapped[10] ^= 1;
+            let err = unwrap::<Aes128>(scheme, &kek, &wrapped).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::MacInvalid);
+        }
+        let wrapped = wrap::<Aes128>(KeyWrap::Rfc3394, &kek, &key).unwrap();
+        let err = unwrap::<Aes128>(KeyWrap::Rfc5649, &kek, &wrapped).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::MacInvalid);
+    }
+
+    #[test]
+    fn key_unwrap_checks_length_and_padding() {
+        let kek = [0u8; 16];
+        let aes = Aes128::new_from_slice(&kek).unwrap();
+        // A single block holds the IV and the padded key, encrypted as is.
+        for (mli, padding) in [(9u32, 0u8), (0, 0), (7, 1)] {
+            let mut block = [0u8; 16];
+            block[..4].copy_from_slice(&KWP_IV_PREFIX);
+            block[4..8].copy_from_slice(&mli.to_be_bytes());
+            block[15] = padding;
+            aes.encrypt_block(GenericArray::from_mut_slice(&mut block));
+            let err = unwrap::<Aes128>(KeyWrap::Rfc5649, &kek, &block).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::MacInvalid);
+        }
+    }
+
+    #[test]
+    fn key_wrap_rejects_bad_lengths() {
+        let kek = [0u8; 16];
+        for len in [8, 15, 20] {
+            let err = wrap::<Aes128>(KeyWrap::Rfc3394, &kek, &vec![0u8; len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::BadParameters);
+        }
+        let err = wrap::<Aes128>(KeyWrap::Rfc5649, &kek, &[]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadParameters);
+        for len in [0, 8, 16, 25] {
+            let err = unwrap::<Aes128>(KeyWrap::Rfc3394, &kek, &vec![0u8; len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::BadParameters);
+        }
+    }
+
+    fn hash_len<A: HashAlgorithm>() -> usize {
+        A::Output::zeroed().as_mut().len()
+    }
+
+    #[test]
+    fn hash_outputs_fit_algorithms() {
+        assert_eq!(hash_len::<Md5>(), 16);
+        assert_eq!(hash_len::<Sha1>(), 20);
+        assert_eq!(hash_len::<Sha224>(), 28);
+        assert_eq!(hash_len::<Sha256>(), 32);
+        assert_eq!(hash_len::<Sha384>(), 48);
+        assert_eq!(hash_len::<Sha512>(), 64);
+        assert_eq!(hash_len::<Sha3_512>(), 64);
+        assert_eq!(hash_len::<Sm3>(), 32);
+        assert_eq!(hash_len::<Md5Sha1>(), 36);
+    }
 }
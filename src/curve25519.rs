@@ -0,0 +1,195 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! X25519 key agreement and Ed25519 signatures, as used by Noise, TLS 1.3
+//! and SSH, over the [`AlgorithmId::X25519`] and [`AlgorithmId::Ed25519`]
+//! operations of the TEE.
+//!
+//! OP-TEE only provides Curve25519 when built with it, and older versions
+//! not at all. Every function checks for it first and fails with
+//! `NotSupported` rather than reaching a TEE call that would panic the TA.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::curve25519::{Ed25519KeyPair, X25519KeyPair, ed25519_verify};
+//! # fn main() -> optee_utee::Result<()> {
+//! # let peer_public = [9u8; 32];
+//! let ours = X25519KeyPair::generate()?;
+//! let shared = ours.derive(&peer_public)?;
+//!
+//! let signing = Ed25519KeyPair::generate()?;
+//! let signature = signing.sign(b"transcript")?;
+//! ed25519_verify(&signing.public_key()?, b"transcript", &signature)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::platform::{self, Capability};
+use crate::{
+    is_algorithm_supported, AlgorithmId, Asymmetric, AttributeId, AttributeMemref, DeriveKey,
+    ElementId, Error, ErrorKind, GenericObject, OperationMode, Result, TransientObject,
+    TransientObjectType,
+};
+
+/// Size in bits of Curve25519 keys, as objects and operations are allocated
+/// with.
+pub const KEY_SIZE: usize = 256;
+/// Size in bytes of X25519 and Ed25519 public keys, and of X25519 shared
+/// secrets.
+pub const PUBLIC_KEY_LEN: usize = 32;
+/// Size in bytes of Ed25519 signatures.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Checks that the TEE implements `algorithm` on Curve25519.
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the platform lacks Curve25519 or the TEE does not
+///    implement `algorithm` on it.
+pub fn ensure_supported(algorithm: AlgorithmId) -> Result<()> {
+    if !platform::current().supports(Capability::EccCurve(ElementId::EccCurve25519)) {
+        return Err(Error::new(ErrorKind::NotSupported));
+    }
+    is_algorithm_supported(algorithm as u32, ElementId::EccCurve25519 as u32)
+}
+
+// Allocates an object of `object_type` holding a new key pair.
+fn generate(algorithm: AlgorithmId, object_type: TransientObjectType) -> Result<TransientObject> {
+    ensure_supported(algorithm)?;
+    let key = TransientObject::allocate(object_type, KEY_SIZE)?;
+    key.generate_key(KEY_SIZE, &[])?;
+    Ok(key)
+}
+
+// Reads the public value `id` of `key`.
+fn public_value(key: &TransientObject, id: AttributeId) -> Result<[u8; PUBLIC_KEY_LEN]> {
+    let mut public = [0u8; PUBLIC_KEY_LEN];
+    match key.ref_attribute(id, &mut public)? {
+        PUBLIC_KEY_LEN => Ok(public),
+        _ => Err(Error::new(ErrorKind::BadFormat)),
+    }
+}
+
+/// An X25519 key pair, held in a transient object.
+pub struct X25519KeyPair(TransientObject);
+
+impl X25519KeyPair {
+    /// Generates a key pair.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement X25519.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the object.
+    pub fn generate() -> Result<Self> {
+        generate(AlgorithmId::X25519, TransientObjectType::X25519Keypair).map(Self)
+    }
+
+    /// Returns the public key, to send to the peer.
+    pub fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN]> {
+        public_value(&self.0, AttributeId::X25519PublicValue)
+    }
+
+    /// Derives the secret shared with the peer whose public key is
+    /// `peer_public`, as a [`GenericSecret`](TransientObjectType::GenericSecret)
+    /// object of 256 bits, e.g. to feed a key derivation function.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement X25519.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the operation or the object.
+    pub fn derive(&self, peer_public: &[u8; PUBLIC_KEY_LEN]) -> Result<TransientObject> {
+        ensure_supported(AlgorithmId::X25519)?;
+        let op = DeriveKey::allocate(AlgorithmId::X25519, KEY_SIZE)?;
+        op.set_key(&self.0)?;
+        let mut shared = TransientObject::allocate(TransientObjectType::GenericSecret, KEY_SIZE)?;
+        let peer = AttributeMemref::from_ref(AttributeId::X25519PublicValue, peer_public);
+        op.derive(&[peer.into()], &mut shared);
+        Ok(shared)
+    }
+
+    /// Returns the object holding the key pair, e.g. to store it.
+    pub fn object(&self) -> &TransientObject {
+        &self.0
+    }
+}
+
+/// An Ed25519 key pair, held in a transient object.
+pub struct Ed25519KeyPair(TransientObject);
+
+impl Ed25519KeyPair {
+    /// Generates a key pair.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement Ed25519.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the object.
+    pub fn generate() -> Result<Self> {
+        generate(AlgorithmId::Ed25519, TransientObjectType::Ed25519Keypair).map(Self)
+    }
+
+    /// Returns the public key, to verify signatures with.
+    pub fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN]> {
+        public_value(&self.0, AttributeId::Ed25519PublicValue)
+    }
+
+    /// Signs `message` itself, as Ed25519 hashes it internally.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement Ed25519.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the operation.
+    pub fn sign(&self, message: &[u8]) -> Result<[u8; SIGNATURE_LEN]> {
+        ensure_supported(AlgorithmId::Ed25519)?;
+        let op = Asymmetric::allocate(AlgorithmId::Ed25519, OperationMode::Sign, KEY_SIZE)?;
+        op.set_key(&self.0)?;
+        let mut signature = [0u8; SIGNATURE_LEN];
+        match op.sign_digest(&[], message, &mut signature)? {
+            SIGNATURE_LEN => Ok(signature),
+            _ => Err(Error::new(ErrorKind::BadFormat)),
+        }
+    }
+
+    /// Returns the object holding the key pair, e.g. to store it.
+    pub fn object(&self) -> &TransientObject {
+        &self.0
+    }
+}
+
+/// Verifies the Ed25519 `signature` of `message` under `public_key`.
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the TEE does not implement Ed25519.
+/// 2) `SignatureInvalid`: If the signature does not match.
+/// 3) `BadParameters`: If `public_key` is not a valid key.
+pub fn ed25519_verify(
+    public_key: &[u8; PUBLIC_KEY_LEN],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<()> {
+    ensure_supported(AlgorithmId::Ed25519)?;
+    let mut key = TransientObject::allocate(TransientObjectType::Ed25519PublicKey, KEY_SIZE)?;
+    key.populate(&[AttributeMemref::from_ref(AttributeId::Ed25519PublicValue, public_key).into()])?;
+    let op = Asymmetric::allocate(AlgorithmId::Ed25519, OperationMode::Verify, KEY_SIZE)?;
+    op.set_key(&key)?;
+    op.verify_digest(&[], message, signature)
+}
@@ -0,0 +1,491 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Minimal ASN.1 DER reader and writer.
+//!
+//! Only the universal types needed to handle keys, signatures and
+//! certificates are supported: `INTEGER` (non-negative), `BIT STRING`,
+//! `OCTET STRING`, `NULL`, `OBJECT IDENTIFIER` and `SEQUENCE`. Tags are
+//! limited to a single byte, which also covers context-specific tags such as
+//! `[0]`.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::Result;
+//! use optee_utee::der;
+//!
+//! // GP signs with ECDSA in the raw `r || s` form, X.509 expects DER.
+//! fn to_x509_signature(raw: &[u8]) -> Result<Vec<u8>> {
+//!     // P-256 has 32-byte fields.
+//!     der::ecdsa_signature_to_der(raw, 32)
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{Error, ErrorKind, Result};
+
+pub const TAG_INTEGER: u8 = 0x02;
+pub const TAG_BIT_STRING: u8 = 0x03;
+pub const TAG_OCTET_STRING: u8 = 0x04;
+pub const TAG_NULL: u8 = 0x05;
+pub const TAG_OID: u8 = 0x06;
+pub const TAG_SEQUENCE: u8 = 0x30;
+
+fn bad_format() -> Error {
+    Error::new(ErrorKind::BadFormat)
+}
+
+/// Reads DER elements one after the other from a buffer.
+pub struct DerReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns `true` once every element has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the tag of the next element without consuming it.
+    pub fn peek_tag(&self) -> Option<u8> {
+        self.data.first().copied()
+    }
+
+    /// Reads the next element and returns its tag and contents.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is truncated, uses a multi-byte tag, an
+    ///    indefinite length or a length that is not minimally encoded.
+    pub fn read_tlv(&mut self) -> Result<(u8, &'a [u8])> {
+        let (&tag, rest) = self.data.split_first().ok_or_else(bad_format)?;
+        if tag & 0x1F == 0x1F {
+            return Err(bad_format());
+        }
+        let (&first, mut rest) = rest.split_first().ok_or_else(bad_format)?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7F) as usize;
+            if count == 0 || count > 4 || rest.len() < count || rest[0] == 0 {
+                return Err(bad_format());
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0usize, |len, &b| (len << 8) | b as usize);
+            if len < 0x80 {
+                return Err(bad_format());
+            }
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return Err(bad_format());
+        }
+        let (contents, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, contents))
+    }
+
+    /// Reads the next element, which must be tagged `tag`, and returns its
+    /// contents.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is malformed or has another tag.
+    pub fn read_expected(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.read_tlv()? {
+            (t, contents) if t == tag => Ok(contents),
+            _ => Err(bad_format()),
+        }
+    }
+
+    /// Reads a non-negative `INTEGER` and returns its big-endian magnitude
+    /// without the leading zero byte used as sign.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is malformed, negative or not minimally
+    ///    encoded.
+    pub fn read_integer(&mut self) -> Result<&'a [u8]> {
+        let contents = self.read_expected(TAG_INTEGER)?;
+        match contents {
+            [] => Err(bad_format()),
+            [first, ..] if first & 0x80 != 0 => Err(bad_format()),
+            [0, second, ..] if second & 0x80 == 0 => Err(bad_format()),
+            [0, rest @ ..] if !rest.is_empty() => Ok(rest),
+            _ => Ok(contents),
+        }
+    }
+
+    /// Reads a non-negative `INTEGER` that fits in a `u32`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is malformed or out of range.
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let magnitude = self.read_integer()?;
+        if magnitude.len() > 4 {
+            return Err(bad_format());
+        }
+        Ok(magnitude
+            .iter()
+            .fold(0u32, |value, &b| (value << 8) | b as u32))
+    }
+
+    /// Reads an `OCTET STRING`.
+    pub fn read_octet_string(&mut self) -> Result<&'a [u8]> {
+        self.read_expected(TAG_OCTET_STRING)
+    }
+
+    /// Reads a `BIT STRING` made of whole bytes, such as a public key or a
+    /// signature.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is malformed or has unused bits.
+    pub fn read_bit_string(&mut self) -> Result<&'a [u8]> {
+        match self.read_expected(TAG_BIT_STRING)? {
+            [0, bits @ ..] => Ok(bits),
+            _ => Err(bad_format()),
+        }
+    }
+
+    /// Reads a `NULL`.
+    pub fn read_null(&mut self) -> Result<()> {
+        match self.read_expected(TAG_NULL)? {
+            [] => Ok(()),
+            _ => Err(bad_format()),
+        }
+    }
+
+    /// Reads an `OBJECT IDENTIFIER` and returns its arcs.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the element is malformed or an arc does not fit in
+    ///    a `u32`.
+    pub fn read_oid(&mut self) -> Result<Vec<u32>> {
+        let contents = self.read_expected(TAG_OID)?;
+        if contents.is_empty() || contents[contents.len() - 1] & 0x80 != 0 {
+            return Err(bad_format());
+        }
+
+        let mut arcs = Vec::new();
+        let mut value: u32 = 0;
+        let mut start = true;
+        for &b in contents {
+            if start && b == 0x80 {
+                return Err(bad_format());
+            }
+            if value > u32::MAX >> 7 {
+                return Err(bad_format());
+            }
+            value = (value << 7) | (b & 0x7F) as u32;
+            start = b & 0x80 == 0;
+            if start {
+                if arcs.is_empty() {
+                    let first = (value / 40).min(2);
+                    arcs.push(first);
+                    arcs.push(value - first * 40);
+                } else {
+                    arcs.push(value);
+                }
+                value = 0;
+            }
+        }
+        Ok(arcs)
+    }
+
+    /// Reads a `SEQUENCE` and returns a reader over its elements.
+    pub fn read_sequence(&mut self) -> Result<DerReader<'a>> {
+        self.read_expected(TAG_SEQUENCE).map(DerReader::new)
+    }
+
+    /// Checks that every element has been read.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If trailing data is left.
+    pub fn finish(self) -> Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(bad_format())
+        }
+    }
+}
+
+/// Appends DER elements to a buffer.
+#[derive(Default)]
+pub struct DerWriter {
+    buf: Vec<u8>,
+}
+
+impl DerWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the encoded elements.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Writes an element with the given tag and contents.
+    pub fn write_tlv(&mut self, tag: u8, contents: &[u8]) {
+        self.buf.push(tag);
+        let len = contents.len();
+        if len < 0x80 {
+            self.buf.push(len as u8);
+        } else {
+            let bytes = (len as u32).to_be_bytes();
+            let skip = bytes.iter().take_while(|&&b| b == 0).count();
+            self.buf.push(0x80 | (bytes.len() - skip) as u8);
+            self.buf.extend_from_slice(&bytes[skip..]);
+        }
+        self.buf.extend_from_slice(contents);
+    }
+
+    /// Writes a non-negative `INTEGER` from its big-endian magnitude.
+    /// Leading zero bytes are stripped and a sign byte is added if needed.
+    pub fn write_integer(&mut self, magnitude: &[u8]) {
+        let skip = magnitude.iter().take_while(|&&b| b == 0).count();
+        let magnitude = &magnitude[skip..];
+        let mut contents = Vec::with_capacity(magnitude.len() + 1);
+        if magnitude.first().is_none_or(|&b| b & 0x80 != 0) {
+            contents.push(0);
+        }
+        contents.extend_from_slice(magnitude);
+        self.write_tlv(TAG_INTEGER, &contents);
+    }
+
+    /// Writes a `u32` as an `INTEGER`.
+    pub fn write_u32(&mut self, value: u32) {
+        self.write_integer(&value.to_be_bytes());
+    }
+
+    /// Writes an `OCTET STRING`.
+    pub fn write_octet_string(&mut self, bytes: &[u8]) {
+        self.write_tlv(TAG_OCTET_STRING, bytes);
+    }
+
+    /// Writes a `BIT STRING` made of whole bytes.
+    pub fn write_bit_string(&mut self, bytes: &[u8]) {
+        let mut contents = Vec::with_capacity(bytes.len() + 1);
+        contents.push(0);
+        contents.extend_from_slice(bytes);
+        self.write_tlv(TAG_BIT_STRING, &contents);
+    }
+
+    /// Writes a `NULL`.
+    pub fn write_null(&mut self) {
+        self.write_tlv(TAG_NULL, &[]);
+    }
+
+    /// Writes an `OBJECT IDENTIFIER` from its arcs.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `arcs` is not a valid object identifier.
+    pub fn write_oid(&mut self, arcs: &[u32]) -> Result<()> {
+        let (first, second, rest) = match arcs {
+            [first @ 0..=1, second @ 0..=39, rest @ ..] => (*first, *second, rest),
+            [2, second, rest @ ..] if *second <= u32::MAX - 80 => (2, *second, rest),
+            _ => return Err(Error::new(ErrorKind::BadParameters)),
+        };
+
+        let mut contents = Vec::new();
+        for arc in core::iter::once(first * 40 + second).chain(rest.iter().copied()) {
+            let groups = (32 - arc.leading_zeros()).div_ceil(7).max(1);
+            for i in (0..groups).rev() {
+                let b = ((arc >> (7 * i)) & 0x7F) as u8;
+                contents.push(if i == 0 { b } else { b | 0x80 });
+            }
+        }
+        self.write_tlv(TAG_OID, &contents);
+        Ok(())
+    }
+
+    /// Writes a `SEQUENCE` whose elements are written by `f`.
+    pub fn write_sequence<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut DerWriter) -> Result<()>,
+    {
+        let mut inner = DerWriter::new();
+        f(&mut inner)?;
+        self.write_tlv(TAG_SEQUENCE, &inner.buf);
+        Ok(())
+    }
+}
+
+/// Converts a raw ECDSA signature `r || s`, as produced by
+/// [`Asymmetric::sign_digest`](crate::Asymmetric::sign_digest), into the DER
+/// `SEQUENCE { r INTEGER, s INTEGER }` used by X.509 and most libraries.
+/// Both halves are `field_len` bytes long (e.g. 32 for P-256, see
+/// [`ecdsa::field_len`](crate::ecdsa::field_len)).
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `field_len` is zero or `raw` is not twice
+///    `field_len` bytes long.
+pub fn ecdsa_signature_to_der(raw: &[u8], field_len: usize) -> Result<Vec<u8>> {
+    if field_len == 0 || raw.len() != field_len * 2 {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let (r, s) = raw.split_at(field_len);
+    let mut writer = DerWriter::new();
+    writer.write_sequence(|seq| {
+        seq.write_integer(r);
+        seq.write_integer(s);
+        Ok(())
+    })?;
+    Ok(writer.into_vec())
+}
+
+/// Converts a DER ECDSA signature into the raw `r || s` form expected by
+/// [`Asymmetric::verify_digest`](crate::Asymmetric::verify_digest), with
+/// both halves padded to `field_len` bytes (e.g. 32 for P-256).
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `field_len` is zero.
+/// 2) `BadFormat`: If `der` is not a valid signature or `r` or `s` does not
+///    fit in `field_len` bytes.
+pub fn ecdsa_signature_from_der(der: &[u8], field_len: usize) -> Result<Vec<u8>> {
+    if field_len == 0 {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut reader = DerReader::new(der);
+    let mut seq = reader.read_sequence()?;
+    let r = seq.read_integer()?;
+    let s = seq.read_integer()?;
+    seq.finish()?;
+    reader.finish()?;
+    if r.len() > field_len || s.len() > field_len {
+        return Err(bad_format());
+    }
+
+    let mut raw = vec![0u8; field_len * 2];
+    raw[field_len - r.len()..field_len].copy_from_slice(r);
+    raw[field_len * 2 - s.len()..].copy_from_slice(s);
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EC_PUBLIC_KEY_OID: [u32; 6] = [1, 2, 840, 10045, 2, 1];
+
+    #[test]
+    fn test_oid() {
+        let mut writer = DerWriter::new();
+        writer.write_oid(&EC_PUBLIC_KEY_OID).unwrap();
+        let der = writer.into_vec();
+        assert_eq!(der, [0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01]);
+
+        let mut reader = DerReader::new(&der);
+        assert_eq!(reader.read_oid().unwrap(), EC_PUBLIC_KEY_OID);
+        reader.finish().unwrap();
+
+        assert!(DerWriter::new().write_oid(&[3, 1]).is_err());
+        assert!(DerWriter::new().write_oid(&[1, 40]).is_err());
+    }
+
+    #[test]
+    fn test_integer() {
+        let mut writer = DerWriter::new();
+        writer.write_u32(0);
+        writer.write_u32(0x80);
+        writer.write_integer(&[0, 0, 0x7F, 0xFF]);
+        let der = writer.into_vec();
+        assert_eq!(
+            der,
+            [0x02, 0x01, 0x00, 0x02, 0x02, 0x00, 0x80, 0x02, 0x02, 0x7F, 0xFF]
+        );
+
+        let mut reader = DerReader::new(&der);
+        assert_eq!(reader.read_u32().unwrap(), 0);
+        assert_eq!(reader.read_integer().unwrap(), [0x80]);
+        assert_eq!(reader.read_u32().unwrap(), 0x7FFF);
+        reader.finish().unwrap();
+
+        // Negative and non-minimal encodings.
+        assert!(DerReader::new(&[0x02, 0x01, 0x80]).read_integer().is_err());
+        assert!(DerReader::new(&[0x02, 0x02, 0x00, 0x01])
+            .read_integer()
+            .is_err());
+    }
+
+    #[test]
+    fn test_long_length() {
+        let payload = [0xAB; 300];
+        let mut writer = DerWriter::new();
+        writer
+            .write_sequence(|seq| {
+                seq.write_octet_string(&payload);
+                seq.write_bit_string(&payload[..10]);
+                seq.write_null();
+                Ok(())
+            })
+            .unwrap();
+        let der = writer.into_vec();
+        assert_eq!(der[..4], [0x30, 0x82, 0x01, 0x3F]);
+
+        let mut reader = DerReader::new(&der);
+        let mut seq = reader.read_sequence().unwrap();
+        assert_eq!(seq.read_octet_string().unwrap(), payload);
+        assert_eq!(seq.read_bit_string().unwrap(), &payload[..10]);
+        seq.read_null().unwrap();
+        seq.finish().unwrap();
+
+        // Lengths must use the shortest form.
+        assert!(DerReader::new(&[0x04, 0x81, 0x01, 0x00])
+            .read_tlv()
+            .is_err());
+        assert!(DerReader::new(&[0x04, 0x80, 0x00, 0x00])
+            .read_tlv()
+            .is_err());
+        assert!(DerReader::new(&[0x04, 0x02, 0x00]).read_tlv().is_err());
+    }
+
+    #[test]
+    fn test_ecdsa_signature() {
+        let mut raw = [0u8; 64];
+        raw[0] = 0x80;
+        raw[31] = 0x01;
+        raw[33] = 0x7F;
+        raw[63] = 0x02;
+
+        let der = ecdsa_signature_to_der(&raw, 32).unwrap();
+        assert_eq!(der[..5], [0x30, 0x44, 0x02, 0x21, 0x00]);
+        assert_eq!(ecdsa_signature_from_der(&der, 32).unwrap(), raw);
+
+        assert!(ecdsa_signature_from_der(&der, 16).is_err());
+        assert!(ecdsa_signature_from_der(&der, 0).is_err());
+        assert!(ecdsa_signature_to_der(&raw[..63], 32).is_err());
+        assert!(ecdsa_signature_to_der(&raw, 48).is_err());
+        assert!(ecdsa_signature_to_der(&[], 0).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Conversions of ECDSA signatures between the raw `r || s` form used by
+//! [`Asymmetric::sign_digest`](crate::Asymmetric::sign_digest) and
+//! [`Asymmetric::verify_digest`](crate::Asymmetric::verify_digest), and the
+//! DER form expected by TLS and X.509. The conversions are those of
+//! [`der`](crate::der), with the field size of the curve from [`field_len`].
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::{ecdsa, ElementId, Result};
+//! fn to_tls_signature(raw: &[u8]) -> Result<Vec<u8>> {
+//!     let field_len = ecdsa::field_len(ElementId::EccCurveNistP256)?;
+//!     ecdsa::ecdsa_signature_to_der(raw, field_len)
+//! }
+//! ```
+
+use crate::{ElementId, Error, ErrorKind, Result};
+
+pub use crate::der::{ecdsa_signature_from_der, ecdsa_signature_to_der};
+
+/// Returns the size in bytes of `r` and `s` on `curve`.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `curve` is not a NIST curve usable with ECDSA.
+pub fn field_len(curve: ElementId) -> Result<usize> {
+    match curve {
+        ElementId::EccCurveNistP192 => Ok(24),
+        ElementId::EccCurveNistP224 => Ok(28),
+        ElementId::EccCurveNistP256 => Ok(32),
+        ElementId::EccCurveNistP384 => Ok(48),
+        ElementId::EccCurveNistP521 => Ok(66),
+        _ => Err(Error::new(ErrorKind::BadParameters)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p256_round_trip() {
+        let mut raw = [0x11u8; 64];
+        raw[0] = 0xFF;
+        raw[32] = 0x00;
+
+        let p256 = field_len(ElementId::EccCurveNistP256).unwrap();
+        let p384 = field_len(ElementId::EccCurveNistP384).unwrap();
+        let der = ecdsa_signature_to_der(&raw, p256).unwrap();
+        assert_eq!(ecdsa_signature_from_der(&der, p256).unwrap(), raw);
+        // A P-256 signature fits in the larger P-384 fields once padded.
+        let padded = ecdsa_signature_from_der(&der, p384).unwrap();
+        assert_eq!(padded[16..48], raw[..32]);
+        assert_eq!(padded[64..], raw[32..]);
+    }
+
+    #[test]
+    fn test_length_validation() {
+        let raw = [0x11u8; 96];
+        let p256 = field_len(ElementId::EccCurveNistP256).unwrap();
+        let p384 = field_len(ElementId::EccCurveNistP384).unwrap();
+        assert!(ecdsa_signature_to_der(&raw, p256).is_err());
+        assert!(field_len(ElementId::EccCurve25519).is_err());
+
+        let der = ecdsa_signature_to_der(&raw, p384).unwrap();
+        assert!(ecdsa_signature_from_der(&der, p256).is_err());
+    }
+}
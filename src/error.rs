@@ -15,9 +15,13 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use alloc::boxed::Box;
 #[cfg(not(feature = "std"))]
 use core::error;
-use core::{fmt, result};
+use core::{
+    fmt, ptr, result,
+    sync::atomic::{AtomicPtr, Ordering},
+};
 use optee_utee_sys as raw;
 #[cfg(feature = "std")]
 use std::error;
@@ -42,6 +46,11 @@ pub struct Error {
     origin: Option<ErrorOrigin>,
 }
 
+/// First code of the range reserved by GlobalPlatform. Codes below it (except
+/// `TEE_SUCCESS`) are implementation-defined and reported as
+/// [`ErrorKind::Vendor`].
+const GP_RESERVED_RANGE_START: u32 = 0xF000_0000;
+
 /// A list specifying general categories of TEE error and its corresponding code
 /// in OP-TEE OS.
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -55,6 +64,10 @@ pub enum ErrorKind {
     StorageNotAvailable = raw::TEE_ERROR_STORAGE_NOT_AVAILABLE,
     /// Persistent object storage is not available.
     StorageNotAvailable2 = raw::TEE_ERROR_STORAGE_NOT_AVAILABLE_2,
+    /// The version of the stored data is not supported.
+    UnsupportedVersion = raw::TEE_ERROR_UNSUPPORTED_VERSION,
+    /// The ciphertext could not be authenticated or decrypted.
+    CiphertextInvalid = raw::TEE_ERROR_CIPHERTEXT_INVALID,
     /// Non-specific cause.
     Generic = raw::TEE_ERROR_GENERIC,
     /// Access privileges are not sufficient.
@@ -92,6 +105,8 @@ pub enum ErrorKind {
     /// The operation has been cancelled by an external event which occurred in
     /// the REE while the function was in progress.
     ExternalCancel = raw::TEE_ERROR_EXTERNAL_CANCEL,
+    /// The operation did not complete before its timeout expired.
+    Timeout = raw::TEE_ERROR_TIMEOUT,
     /// Data overflow.
     Overflow = raw::TEE_ERROR_OVERFLOW,
     /// Trusted Application has panicked during the operation.
@@ -110,15 +125,77 @@ pub enum ErrorKind {
     /// Unknown error.
     #[default]
     Unknown,
+    /// Implementation-defined or vendor-specific error code outside of the
+    /// range reserved by GlobalPlatform. A printable name can be attached with
+    /// [`register_vendor_error_names`].
+    Vendor(u32),
 }
 
 impl ErrorKind {
-    pub(crate) fn as_str(&self) -> &'static str {
+    /// Returns the code of this kind of error, as returned by the GP
+    /// functions. Replaces `kind as u32`, which no longer compiles since
+    /// [`ErrorKind::Vendor`] carries its code.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use optee_utee::ErrorKind;
+    /// assert_eq!(ErrorKind::MacInvalid.raw_code(), 0xFFFF_3071);
+    /// assert_eq!(ErrorKind::Vendor(0x8000_0001).raw_code(), 0x8000_0001);
+    /// ```
+    pub const fn raw_code(self) -> u32 {
+        match self {
+            ErrorKind::CorruptObject => raw::TEE_ERROR_CORRUPT_OBJECT,
+            ErrorKind::CorruptObject2 => raw::TEE_ERROR_CORRUPT_OBJECT_2,
+            ErrorKind::StorageNotAvailable => raw::TEE_ERROR_STORAGE_NOT_AVAILABLE,
+            ErrorKind::StorageNotAvailable2 => raw::TEE_ERROR_STORAGE_NOT_AVAILABLE_2,
+            ErrorKind::UnsupportedVersion => raw::TEE_ERROR_UNSUPPORTED_VERSION,
+            ErrorKind::CiphertextInvalid => raw::TEE_ERROR_CIPHERTEXT_INVALID,
+            ErrorKind::Generic => raw::TEE_ERROR_GENERIC,
+            ErrorKind::AccessDenied => raw::TEE_ERROR_ACCESS_DENIED,
+            ErrorKind::Cancel => raw::TEE_ERROR_CANCEL,
+            ErrorKind::AccessConflict => raw::TEE_ERROR_ACCESS_CONFLICT,
+            ErrorKind::ExcessData => raw::TEE_ERROR_EXCESS_DATA,
+            ErrorKind::BadFormat => raw::TEE_ERROR_BAD_FORMAT,
+            ErrorKind::BadParameters => raw::TEE_ERROR_BAD_PARAMETERS,
+            ErrorKind::BadState => raw::TEE_ERROR_BAD_STATE,
+            ErrorKind::ItemNotFound => raw::TEE_ERROR_ITEM_NOT_FOUND,
+            ErrorKind::NotImplemented => raw::TEE_ERROR_NOT_IMPLEMENTED,
+            ErrorKind::NotSupported => raw::TEE_ERROR_NOT_SUPPORTED,
+            ErrorKind::NoData => raw::TEE_ERROR_NO_DATA,
+            ErrorKind::OutOfMemory => raw::TEE_ERROR_OUT_OF_MEMORY,
+            ErrorKind::Busy => raw::TEE_ERROR_BUSY,
+            ErrorKind::Communication => raw::TEE_ERROR_COMMUNICATION,
+            ErrorKind::Security => raw::TEE_ERROR_SECURITY,
+            ErrorKind::ShortBuffer => raw::TEE_ERROR_SHORT_BUFFER,
+            ErrorKind::ExternalCancel => raw::TEE_ERROR_EXTERNAL_CANCEL,
+            ErrorKind::Timeout => raw::TEE_ERROR_TIMEOUT,
+            ErrorKind::Overflow => raw::TEE_ERROR_OVERFLOW,
+            ErrorKind::TargetDead => raw::TEE_ERROR_TARGET_DEAD,
+            ErrorKind::StorageNoSpace => raw::TEE_ERROR_STORAGE_NO_SPACE,
+            ErrorKind::MacInvalid => raw::TEE_ERROR_MAC_INVALID,
+            ErrorKind::SignatureInvalid => raw::TEE_ERROR_SIGNATURE_INVALID,
+            ErrorKind::TimeNotSet => raw::TEE_ERROR_TIME_NOT_SET,
+            ErrorKind::TimeNeedsReset => raw::TEE_ERROR_TIME_NEEDS_RESET,
+            // The code `Unknown` always had, following the last GP code.
+            ErrorKind::Unknown => raw::TEE_ERROR_TIME_NEEDS_RESET + 1,
+            ErrorKind::Vendor(code) => code,
+        }
+    }
+
+    /// Returns the English description of this kind of error, or an empty
+    /// string when the `no_error_strings` feature is enabled.
+    #[cfg(not(feature = "no_error_strings"))]
+    pub fn as_str(&self) -> &'static str {
         match *self {
             ErrorKind::CorruptObject => "Object corruption.",
             ErrorKind::CorruptObject2 => "Persistent object corruption.",
             ErrorKind::StorageNotAvailable => "Object storage is not available.",
             ErrorKind::StorageNotAvailable2 => "Persistent object storage is not available.",
+            ErrorKind::UnsupportedVersion => "The version of the stored data is not supported.",
+            ErrorKind::CiphertextInvalid => {
+                "The ciphertext could not be authenticated or decrypted."
+            }
             ErrorKind::Generic => "Non-specific cause.",
             ErrorKind::AccessDenied => "Access privileges are not sufficient.",
             ErrorKind::Cancel => "The operation was canceled.",
@@ -141,6 +218,7 @@ impl ErrorKind {
             ErrorKind::Security => "A security fault was detected.",
             ErrorKind::ShortBuffer => "The supplied buffer is too short for the generated output.",
             ErrorKind::ExternalCancel => "Undocumented.",
+            ErrorKind::Timeout => "The operation did not complete before its timeout expired.",
             ErrorKind::Overflow => "Data overflow.",
             ErrorKind::TargetDead => "Trusted Application has panicked during the operation.",
             ErrorKind::StorageNoSpace => "Insufficient space is available.",
@@ -151,13 +229,94 @@ impl ErrorKind {
                 "The persistent time has been set but may have been corrupted and SHALL no longer be trusted."
             },
             ErrorKind::Unknown => "Unknown error.",
+            ErrorKind::Vendor(code) => {
+                vendor_error_name(code).unwrap_or("Vendor-specific error.")
+            }
+        }
+    }
+
+    /// Returns the English description of this kind of error, or an empty
+    /// string when the `no_error_strings` feature is enabled.
+    #[cfg(feature = "no_error_strings")]
+    pub fn as_str(&self) -> &'static str {
+        ""
+    }
+
+    /// Returns the language-neutral GP name of this kind of error, such as
+    /// `TEE_ERROR_MAC_INVALID`, or an empty string when the `no_error_strings`
+    /// feature is enabled.
+    #[cfg(not(feature = "no_error_strings"))]
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorKind::CorruptObject => "TEE_ERROR_CORRUPT_OBJECT",
+            ErrorKind::CorruptObject2 => "TEE_ERROR_CORRUPT_OBJECT_2",
+            ErrorKind::StorageNotAvailable => "TEE_ERROR_STORAGE_NOT_AVAILABLE",
+            ErrorKind::StorageNotAvailable2 => "TEE_ERROR_STORAGE_NOT_AVAILABLE_2",
+            ErrorKind::UnsupportedVersion => "TEE_ERROR_UNSUPPORTED_VERSION",
+            ErrorKind::CiphertextInvalid => "TEE_ERROR_CIPHERTEXT_INVALID",
+            ErrorKind::Generic => "TEE_ERROR_GENERIC",
+            ErrorKind::AccessDenied => "TEE_ERROR_ACCESS_DENIED",
+            ErrorKind::Cancel => "TEE_ERROR_CANCEL",
+            ErrorKind::AccessConflict => "TEE_ERROR_ACCESS_CONFLICT",
+            ErrorKind::ExcessData => "TEE_ERROR_EXCESS_DATA",
+            ErrorKind::BadFormat => "TEE_ERROR_BAD_FORMAT",
+            ErrorKind::BadParameters => "TEE_ERROR_BAD_PARAMETERS",
+            ErrorKind::BadState => "TEE_ERROR_BAD_STATE",
+            ErrorKind::ItemNotFound => "TEE_ERROR_ITEM_NOT_FOUND",
+            ErrorKind::NotImplemented => "TEE_ERROR_NOT_IMPLEMENTED",
+            ErrorKind::NotSupported => "TEE_ERROR_NOT_SUPPORTED",
+            ErrorKind::NoData => "TEE_ERROR_NO_DATA",
+            ErrorKind::OutOfMemory => "TEE_ERROR_OUT_OF_MEMORY",
+            ErrorKind::Busy => "TEE_ERROR_BUSY",
+            ErrorKind::Communication => "TEE_ERROR_COMMUNICATION",
+            ErrorKind::Security => "TEE_ERROR_SECURITY",
+            ErrorKind::ShortBuffer => "TEE_ERROR_SHORT_BUFFER",
+            ErrorKind::ExternalCancel => "TEE_ERROR_EXTERNAL_CANCEL",
+            ErrorKind::Timeout => "TEE_ERROR_TIMEOUT",
+            ErrorKind::Overflow => "TEE_ERROR_OVERFLOW",
+            ErrorKind::TargetDead => "TEE_ERROR_TARGET_DEAD",
+            ErrorKind::StorageNoSpace => "TEE_ERROR_STORAGE_NO_SPACE",
+            ErrorKind::MacInvalid => "TEE_ERROR_MAC_INVALID",
+            ErrorKind::SignatureInvalid => "TEE_ERROR_SIGNATURE_INVALID",
+            ErrorKind::TimeNotSet => "TEE_ERROR_TIME_NOT_SET",
+            ErrorKind::TimeNeedsReset => "TEE_ERROR_TIME_NEEDS_RESET",
+            ErrorKind::Unknown => "TEE_ERROR_UNKNOWN",
+            ErrorKind::Vendor(_) => "TEE_ERROR_VENDOR",
+        }
+    }
+
+    /// Returns the language-neutral GP name of this kind of error, such as
+    /// `TEE_ERROR_MAC_INVALID`, or an empty string when the `no_error_strings`
+    /// feature is enabled.
+    #[cfg(feature = "no_error_strings")]
+    pub fn name(&self) -> &'static str {
+        ""
+    }
+}
+
+/// Compact, allocation-free formatting suitable for trace output, e.g.
+/// `TEE_ERROR_MAC_INVALID (0xffff3071)`, or only the code when the
+/// `no_error_strings` feature is enabled.
+///
+/// # Examples
+///
+/// ``` rust,no_run
+/// # use optee_utee::{trace_println, ErrorKind};
+/// trace_println!("verification failed: {}", ErrorKind::MacInvalid);
+/// ```
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = u32::from(*self);
+        match self.name() {
+            "" => write!(f, "0x{:08x}", code),
+            name => write!(f, "{} (0x{:08x})", name, code),
         }
     }
 }
 
 impl From<ErrorKind> for u32 {
     fn from(kind: ErrorKind) -> u32 {
-        kind as u32
+        kind.raw_code()
     }
 }
 
@@ -168,6 +327,8 @@ impl From<u32> for ErrorKind {
             raw::TEE_ERROR_CORRUPT_OBJECT_2 => ErrorKind::CorruptObject2,
             raw::TEE_ERROR_STORAGE_NOT_AVAILABLE => ErrorKind::StorageNotAvailable,
             raw::TEE_ERROR_STORAGE_NOT_AVAILABLE_2 => ErrorKind::StorageNotAvailable2,
+            raw::TEE_ERROR_UNSUPPORTED_VERSION => ErrorKind::UnsupportedVersion,
+            raw::TEE_ERROR_CIPHERTEXT_INVALID => ErrorKind::CiphertextInvalid,
             raw::TEE_ERROR_GENERIC => ErrorKind::Generic,
             raw::TEE_ERROR_ACCESS_DENIED => ErrorKind::AccessDenied,
             raw::TEE_ERROR_CANCEL => ErrorKind::Cancel,
@@ -186,6 +347,7 @@ impl From<u32> for ErrorKind {
             raw::TEE_ERROR_SECURITY => ErrorKind::Security,
             raw::TEE_ERROR_SHORT_BUFFER => ErrorKind::ShortBuffer,
             raw::TEE_ERROR_EXTERNAL_CANCEL => ErrorKind::ExternalCancel,
+            raw::TEE_ERROR_TIMEOUT => ErrorKind::Timeout,
             raw::TEE_ERROR_OVERFLOW => ErrorKind::Overflow,
             raw::TEE_ERROR_TARGET_DEAD => ErrorKind::TargetDead,
             raw::TEE_ERROR_STORAGE_NO_SPACE => ErrorKind::StorageNoSpace,
@@ -193,13 +355,56 @@ impl From<u32> for ErrorKind {
             raw::TEE_ERROR_SIGNATURE_INVALID => ErrorKind::SignatureInvalid,
             raw::TEE_ERROR_TIME_NOT_SET => ErrorKind::TimeNotSet,
             raw::TEE_ERROR_TIME_NEEDS_RESET => ErrorKind::TimeNeedsReset,
+            raw::TEE_SUCCESS => ErrorKind::Unknown,
+            code if code < GP_RESERVED_RANGE_START => ErrorKind::Vendor(code),
             _ => ErrorKind::Unknown,
         }
     }
 }
 
+struct VendorErrorNames(&'static [(u32, &'static str)]);
+
+static VENDOR_ERROR_NAMES: AtomicPtr<VendorErrorNames> = AtomicPtr::new(ptr::null_mut());
+
+/// Registers printable names for implementation-defined error codes, used by
+/// [`Error::message`] for [`ErrorKind::Vendor`] errors.
+///
+/// The table replaces any previously registered one. It is meant to be
+/// registered once when the TA starts, since replaced tables are not freed.
+///
+/// # Examples
+///
+/// ``` rust,no_run
+/// use optee_utee::{register_vendor_error_names, ErrorKind};
+///
+/// register_vendor_error_names(&[(0x8000_0001, "Secure element is locked.")]);
+/// assert_eq!(ErrorKind::from(0x8000_0001), ErrorKind::Vendor(0x8000_0001));
+/// ```
+pub fn register_vendor_error_names(names: &'static [(u32, &'static str)]) {
+    let table = Box::into_raw(Box::new(VendorErrorNames(names)));
+    VENDOR_ERROR_NAMES.store(table, Ordering::Release);
+}
+
+#[cfg_attr(feature = "no_error_strings", allow(dead_code))]
+fn vendor_error_name(code: u32) -> Option<&'static str> {
+    let table = VENDOR_ERROR_NAMES.load(Ordering::Acquire);
+    if table.is_null() {
+        return None;
+    }
+    // SAFETY:
+    // Registered tables are leaked and therefore valid for the rest of the program.
+    let names = unsafe { (*table).0 };
+    names
+        .iter()
+        .find(|(vendor_code, _)| *vendor_code == code)
+        .map(|(_, name)| *name)
+}
+
 impl Error {
+    #[cfg_attr(feature = "error_telemetry", track_caller)]
     pub fn new(kind: ErrorKind) -> Error {
+        #[cfg(feature = "error_telemetry")]
+        crate::telemetry::record(kind, core::panic::Location::caller());
         Error { kind, origin: None }
     }
 
@@ -213,11 +418,9 @@ impl Error {
     /// let error = optee_utee::Error::from_raw_error(0xFFFF000F);
     /// assert_eq!(error.kind(), optee_utee::ErrorKind::Security);
     /// ```
+    #[cfg_attr(feature = "error_telemetry", track_caller)]
     pub fn from_raw_error(code: u32) -> Error {
-        Error {
-            kind: ErrorKind::from(code),
-            origin: None,
-        }
+        Error::new(ErrorKind::from(code))
     }
 
     pub fn with_origin(mut self, origin: ErrorOrigin) -> Self {
@@ -256,10 +459,12 @@ impl Error {
 
 impl fmt::Debug for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if !self.message().is_empty() {
+            write!(fmt, "{} ", self.message())?;
+        }
         write!(
             fmt,
-            "{} (error code 0x{:x}, origin 0x{:x})",
-            self.message(),
+            "(error code 0x{:x}, origin 0x{:x})",
             self.raw_code(),
             self.origin().map(|v| v.into()).unwrap_or(0_u32),
         )
@@ -280,8 +485,9 @@ impl error::Error for Error {
 
 impl From<ErrorKind> for Error {
     #[inline]
+    #[cfg_attr(feature = "error_telemetry", track_caller)]
     fn from(kind: ErrorKind) -> Error {
-        Error { kind, origin: None }
+        Error::new(kind)
     }
 }
 
@@ -313,3 +519,63 @@ impl From<u32> for ErrorOrigin {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gp_code_round_trip() {
+        for code in [
+            raw::TEE_ERROR_CORRUPT_OBJECT,
+            raw::TEE_ERROR_GENERIC,
+            raw::TEE_ERROR_TIMEOUT,
+            raw::TEE_ERROR_TIME_NEEDS_RESET,
+        ] {
+            assert_eq!(u32::from(ErrorKind::from(code)), code);
+        }
+    }
+
+    #[test]
+    fn test_raw_code() {
+        const BUSY: u32 = ErrorKind::Busy.raw_code();
+        assert_eq!(BUSY, raw::TEE_ERROR_BUSY);
+        assert_eq!(ErrorKind::Unknown.raw_code(), 0xFFFF_5002);
+        assert_eq!(ErrorKind::Vendor(0x8000_0001).raw_code(), 0x8000_0001);
+    }
+
+    #[test]
+    fn test_vendor_codes() {
+        static NAMES: [(u32, &str); 1] = [(0x8000_0001, "Secure element is locked.")];
+        register_vendor_error_names(&NAMES);
+
+        let error = Error::from_raw_error(0x8000_0001);
+        assert_eq!(error.kind(), ErrorKind::Vendor(0x8000_0001));
+        assert_eq!(error.raw_code(), 0x8000_0001);
+        if cfg!(not(feature = "no_error_strings")) {
+            assert_eq!(error.message(), "Secure element is locked.");
+            assert_eq!(
+                Error::from_raw_error(0x8000_0002).message(),
+                "Vendor-specific error."
+            );
+        }
+        // Unassigned codes in the GlobalPlatform range stay unknown.
+        assert_eq!(ErrorKind::from(0xFFFF_0FFF), ErrorKind::Unknown);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_error_strings"))]
+    fn test_compact_format() {
+        extern crate alloc;
+        use alloc::string::ToString;
+
+        assert_eq!(
+            ErrorKind::MacInvalid.to_string(),
+            "TEE_ERROR_MAC_INVALID (0xffff3071)"
+        );
+        assert_eq!(
+            ErrorKind::Vendor(0x8000_0002).to_string(),
+            "TEE_ERROR_VENDOR (0x80000002)"
+        );
+    }
+}
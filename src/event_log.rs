@@ -0,0 +1,483 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Append-only log of typed events, kept in secure storage as tamper-evident
+//! audit records that survive TA restarts.
+//!
+//! Every entry is chained into a running SHA-256 hash, which is signed with a
+//! key of the TA every `checkpoint_every` entries, so that entries covered by
+//! a checkpoint cannot be removed or altered without breaking a signature.
+//! Reopening the log verifies it and continues the chain.
+//!
+//! # Format
+//!
+//! The log object holds a sequence of records, integers little endian:
+//!
+//! * entry: `0x01`, sequence number (u64), system time seconds (u32) and
+//!   milliseconds (u32), event length (u32), event as encoded by
+//!   [`Event::encode`].
+//! * checkpoint: `0x02`, number of entries covered (u64), chain hash
+//!   (32 bytes), signature length (u16), signature.
+//!
+//! The chain hash starts as 32 zero bytes and each entry updates it to
+//! `SHA-256(chain || entry)`, the entry including its tag byte. The
+//! signature covers the chain hash as a digest.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::event_log::{Event, EventLog};
+//! # use optee_utee::{AlgorithmId, Error, ErrorKind, Result, TransientObject};
+//! enum Audit {
+//!     KeyUsed(u32),
+//!     PinFailed,
+//! }
+//!
+//! impl Event for Audit {
+//!     fn encode(&self) -> Vec<u8> {
+//!         match self {
+//!             Audit::KeyUsed(id) => [&[1u8][..], &id.to_le_bytes()].concat(),
+//!             Audit::PinFailed => vec![2],
+//!         }
+//!     }
+//!
+//!     fn decode(data: &[u8]) -> Result<Self> {
+//!         match data {
+//!             [1, id @ ..] if id.len() == 4 => {
+//!                 Ok(Audit::KeyUsed(u32::from_le_bytes([id[0], id[1], id[2], id[3]])))
+//!             }
+//!             [2] => Ok(Audit::PinFailed),
+//!             _ => Err(Error::new(ErrorKind::BadFormat)),
+//!         }
+//!     }
+//! }
+//!
+//! fn audit(key: &TransientObject, event: Audit) -> Result<u64> {
+//!     let mut log = EventLog::open(b"audit", key, AlgorithmId::EcDsaSha256, 16)?;
+//!     log.append(&event)
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use crate::{
+    AlgorithmId, Asymmetric, DataFlag, Digest, Error, ErrorKind, GenericObject,
+    ObjectStorageConstants, OperationMode, PersistentObject, Result, Time, Whence,
+};
+
+const ENTRY_TAG: u8 = 0x01;
+const CHECKPOINT_TAG: u8 = 0x02;
+const ENTRY_HEADER_LEN: usize = 1 + 8 + 4 + 4 + 4;
+const CHECKPOINT_HEADER_LEN: usize = 1 + 8 + 32 + 2;
+// Large enough for RSA 4096 signatures.
+const MAX_SIGNATURE_LEN: usize = 512;
+
+/// An event that can be recorded in an [`EventLog`].
+pub trait Event: Sized {
+    /// Serializes the event.
+    fn encode(&self) -> Vec<u8>;
+
+    /// Deserializes an event serialized by [`encode`](Event::encode).
+    fn decode(data: &[u8]) -> Result<Self>;
+}
+
+/// An event read back from a log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoggedEvent<E> {
+    pub sequence: u64,
+    pub seconds: u32,
+    pub millis: u32,
+    pub event: E,
+}
+
+/// The events of a log checked by [`verify`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedLog<E> {
+    pub events: Vec<LoggedEvent<E>>,
+    /// Number of leading events covered by a valid checkpoint. Later events
+    /// may have been removed without notice.
+    pub signed: u64,
+}
+
+/// Log of events of type `E`, appended to a persistent object of the TA
+/// private storage.
+///
+/// Dropping the log does not sign the entries written since the last
+/// checkpoint, call [`checkpoint`](Self::checkpoint) before, e.g. when the
+/// TA instance is destroyed.
+pub struct EventLog<E: Event> {
+    object: PersistentObject,
+    signer: Asymmetric,
+    checkpoint_every: u64,
+    entries: u64,
+    signed: u64,
+    chain: [u8; 32],
+    _event: PhantomData<E>,
+}
+
+impl<E: Event> EventLog<E> {
+    /// Opens the log object `object_id`, or creates it if missing. Its
+    /// checkpoints are signed with `key` using `algorithm`, e.g.
+    /// [`AlgorithmId::EcDsaSha256`], and `key` must be a key pair to verify
+    /// the existing ones. A checkpoint is written every `checkpoint_every`
+    /// entries, at least 1.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadFormat`: If the existing log is truncated or holds an unknown
+    ///    record.
+    /// 2) `SignatureInvalid`: If a checkpoint of the existing log does not
+    ///    match its entries.
+    /// 3) Errors from allocating the operations with `key`, e.g.
+    ///    `BadParameters` if `key` does not match `algorithm`.
+    /// 4) Errors from opening or creating the persistent object.
+    pub fn open<K: GenericObject>(
+        object_id: &[u8],
+        key: &K,
+        algorithm: AlgorithmId,
+        checkpoint_every: u64,
+    ) -> Result<Self> {
+        let key_size = key.info()?.object_size();
+        let signer = Asymmetric::allocate(algorithm, OperationMode::Sign, key_size)?;
+        signer.set_key(key)?;
+        let flags = DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE;
+
+        let (object, state) =
+            match PersistentObject::open(ObjectStorageConstants::Private, object_id, flags) {
+                Ok(object) => {
+                    let state = check(&read_all(&object)?, key, algorithm, |_| Ok(()))?;
+                    (object, state)
+                }
+                Err(e) if e.kind() == ErrorKind::ItemNotFound => {
+                    let object = PersistentObject::create(
+                        ObjectStorageConstants::Private,
+                        object_id,
+                        flags,
+                        None,
+                        &[],
+                    )?;
+                    (object, ChainState::default())
+                }
+                Err(e) => return Err(e),
+            };
+        Ok(Self {
+            object,
+            signer,
+            checkpoint_every: checkpoint_every.max(1),
+            entries: state.entries,
+            signed: state.signed,
+            chain: state.chain,
+            _event: PhantomData,
+        })
+    }
+
+    /// Appends `event`, writes a checkpoint if one is due, and returns the
+    /// sequence number of the event.
+    ///
+    /// # Errors
+    ///
+    /// Errors from hashing, signing or writing to the storage.
+    pub fn append(&mut self, event: &E) -> Result<u64> {
+        let mut time = Time::new();
+        time.system_time();
+        let sequence = self.entries;
+        let entry = encode_entry(sequence, time.seconds, time.millis, &event.encode());
+
+        let chain = chain_hash(&self.chain, &entry)?;
+        self.append_record(&entry)?;
+        self.chain = chain;
+        self.entries += 1;
+        if self.entries - self.signed >= self.checkpoint_every {
+            self.checkpoint()?;
+        }
+        Ok(sequence)
+    }
+
+    /// Signs the entries not covered by a checkpoint yet, if any.
+    ///
+    /// # Errors
+    ///
+    /// Errors from signing or writing to the storage.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        if self.entries == self.signed {
+            return Ok(());
+        }
+        let mut signature = [0u8; MAX_SIGNATURE_LEN];
+        let len = self.signer.sign_digest(&[], &self.chain, &mut signature)?;
+
+        self.append_record(&encode_checkpoint(
+            self.entries,
+            &self.chain,
+            &signature[..len],
+        ))?;
+        self.signed = self.entries;
+        Ok(())
+    }
+
+    /// Returns the number of events in the log.
+    pub fn len(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns whether the log holds no event.
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    fn append_record(&mut self, record: &[u8]) -> Result<()> {
+        self.object.seek(0, Whence::DataSeekEnd)?;
+        self.object.write(record)
+    }
+}
+
+/// Reads the log object `object_id`, checks its chain and the checkpoints
+/// signed with `key` using `algorithm`, and returns its events.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If there is no log `object_id`.
+/// 2) `BadFormat`: If the log is truncated, holds an unknown record or an
+///    event `E` fails to decode.
+/// 3) `SignatureInvalid`: If a checkpoint does not match the entries before
+///    it.
+/// 4) Errors from reading the storage.
+pub fn verify<E: Event, K: GenericObject>(
+    object_id: &[u8],
+    key: &K,
+    algorithm: AlgorithmId,
+) -> Result<VerifiedLog<E>> {
+    let object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        object_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    )?;
+    let mut events = Vec::new();
+    let state = check(&read_all(&object)?, key, algorithm, |record| {
+        if let Record::Entry {
+            sequence,
+            seconds,
+            millis,
+            event,
+        } = record
+        {
+            events.push(LoggedEvent {
+                sequence: *sequence,
+                seconds: *seconds,
+                millis: *millis,
+                event: E::decode(event)?,
+            });
+        }
+        Ok(())
+    })?;
+    Ok(VerifiedLog {
+        events,
+        signed: state.signed,
+    })
+}
+
+// A record of a log object, borrowing from its contents.
+#[derive(Debug, PartialEq, Eq)]
+enum Record<'a> {
+    Entry {
+        sequence: u64,
+        seconds: u32,
+        millis: u32,
+        event: &'a [u8],
+    },
+    Checkpoint {
+        entries: u64,
+        chain: [u8; 32],
+        signature: &'a [u8],
+    },
+}
+
+#[derive(Default)]
+struct ChainState {
+    entries: u64,
+    signed: u64,
+    chain: [u8; 32],
+}
+
+fn read_all(object: &PersistentObject) -> Result<Vec<u8>> {
+    let mut data = vec![0u8; object.info()?.data_size()];
+    let read = object.read(&mut data)? as usize;
+    data.truncate(read);
+    Ok(data)
+}
+
+// Recomputes the chain of `data`, verifies its checkpoints and passes each
+// record to `visit`.
+fn check<K: GenericObject>(
+    data: &[u8],
+    key: &K,
+    algorithm: AlgorithmId,
+    mut visit: impl FnMut(&Record) -> Result<()>,
+) -> Result<ChainState> {
+    let verifier =
+        Asymmetric::allocate(algorithm, OperationMode::Verify, key.info()?.object_size())?;
+    verifier.set_key(key)?;
+
+    let mut state = ChainState::default();
+    for (record, bytes) in parse(data)? {
+        match record {
+            Record::Entry { sequence, .. } => {
+                if sequence != state.entries {
+                    return Err(Error::new(ErrorKind::BadFormat));
+                }
+                state.chain = chain_hash(&state.chain, bytes)?;
+                state.entries += 1;
+            }
+            Record::Checkpoint {
+                entries,
+                chain,
+                signature,
+            } => {
+                if entries != state.entries || chain != state.chain {
+                    return Err(Error::new(ErrorKind::SignatureInvalid));
+                }
+                verifier.verify_digest(&[], &chain, signature)?;
+                state.signed = entries;
+            }
+        }
+        visit(&record)?;
+    }
+    Ok(state)
+}
+
+fn chain_hash(chain: &[u8; 32], entry: &[u8]) -> Result<[u8; 32]> {
+    let digest = Digest::allocate(AlgorithmId::Sha256)?;
+    digest.update(chain);
+    let mut hash = [0u8; 32];
+    digest.do_final(entry, &mut hash)?;
+    Ok(hash)
+}
+
+// Splits the contents of a log object into its records, along with their
+// bytes.
+fn parse(mut data: &[u8]) -> Result<Vec<(Record<'_>, &[u8])>> {
+    let mut records = Vec::new();
+    while let Some(&tag) = data.first() {
+        let len = match tag {
+            ENTRY_TAG if data.len() >= ENTRY_HEADER_LEN => {
+                ENTRY_HEADER_LEN + u32_at(data, 17) as usize
+            }
+            CHECKPOINT_TAG if data.len() >= CHECKPOINT_HEADER_LEN => {
+                CHECKPOINT_HEADER_LEN + u16_at(data, 41) as usize
+            }
+            _ => return Err(Error::new(ErrorKind::BadFormat)),
+        };
+        if data.len() < len {
+            return Err(Error::new(ErrorKind::BadFormat));
+        }
+        let (bytes, rest) = data.split_at(len);
+        let record = match tag {
+            ENTRY_TAG => Record::Entry {
+                sequence: u64_at(bytes, 1),
+                seconds: u32_at(bytes, 9),
+                millis: u32_at(bytes, 13),
+                event: &bytes[ENTRY_HEADER_LEN..],
+            },
+            _ => {
+                let mut chain = [0u8; 32];
+                chain.copy_from_slice(&bytes[9..41]);
+                Record::Checkpoint {
+                    entries: u64_at(bytes, 1),
+                    chain,
+                    signature: &bytes[CHECKPOINT_HEADER_LEN..],
+                }
+            }
+        };
+        records.push((record, bytes));
+        data = rest;
+    }
+    Ok(records)
+}
+
+fn encode_entry(sequence: u64, seconds: u32, millis: u32, event: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(ENTRY_HEADER_LEN + event.len());
+    entry.push(ENTRY_TAG);
+    entry.extend_from_slice(&sequence.to_le_bytes());
+    entry.extend_from_slice(&seconds.to_le_bytes());
+    entry.extend_from_slice(&millis.to_le_bytes());
+    entry.extend_from_slice(&(event.len() as u32).to_le_bytes());
+    entry.extend_from_slice(event);
+    entry
+}
+
+fn encode_checkpoint(entries: u64, chain: &[u8; 32], signature: &[u8]) -> Vec<u8> {
+    let mut checkpoint = Vec::with_capacity(CHECKPOINT_HEADER_LEN + signature.len());
+    checkpoint.push(CHECKPOINT_TAG);
+    checkpoint.extend_from_slice(&entries.to_le_bytes());
+    checkpoint.extend_from_slice(chain);
+    checkpoint.extend_from_slice(&(signature.len() as u16).to_le_bytes());
+    checkpoint.extend_from_slice(signature);
+    checkpoint
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = encode_entry(0, 10, 20, b"event");
+        let checkpoint = encode_checkpoint(1, &[0x55; 32], &[1, 2, 3]);
+        let data = [&entry[..], &checkpoint[..]].concat();
+        assert_eq!(
+            parse(&data).unwrap(),
+            vec![
+                (
+                    Record::Entry {
+                        sequence: 0,
+                        seconds: 10,
+                        millis: 20,
+                        event: &b"event"[..],
+                    },
+                    &entry[..]
+                ),
+                (
+                    Record::Checkpoint {
+                        entries: 1,
+                        chain: [0x55; 32],
+                        signature: &[1, 2, 3][..],
+                    },
+                    &checkpoint[..]
+                ),
+            ]
+        );
+
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(&entry[..entry.len() - 1]).is_err());
+        assert!(parse(&[0x03]).is_err());
+    }
+}
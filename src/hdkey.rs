@@ -0,0 +1,493 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Hierarchical deterministic key derivation, as specified by BIP32 for
+//! secp256k1.
+//!
+//! A master seed is generated or imported once into the TA private storage.
+//! Keys are then derived from it along paths such as `m/44'/0'/0'/0/7`,
+//! e.g. one hardened branch per wallet account or per tenant. The seed and
+//! the intermediate keys never leave the TA: [`derive_key`] hands out the
+//! final private key as a key object restricted to the usages asked for,
+//! and [`derive_public_key`] its compressed public key.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::hdkey;
+//! # use optee_utee::{Result, TransientObject, UsageFlag};
+//! fn tenant_mac_key(tenant: u32) -> Result<TransientObject> {
+//!     let path = [hdkey::ChildNumber::Hardened(tenant)];
+//!     hdkey::derive_key(b"tenants.seed", &path, UsageFlag::MAC)
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::secret::wipe;
+use crate::{
+    AlgorithmId, AttributeId, AttributeMemref, BigInt, DataFlag, Digest, Error, ErrorKind,
+    GenericObject, ObjectStorageConstants, PersistentObject, Random, Result, TransientObject,
+    TransientObjectType, UsageFlag,
+};
+
+/// Shortest master seed accepted, as required by BIP32.
+pub const MIN_SEED_LEN: usize = 16;
+/// Longest master seed accepted, as required by BIP32.
+pub const MAX_SEED_LEN: usize = 64;
+/// Length of a compressed secp256k1 public key.
+pub const PUBLIC_KEY_LEN: usize = 33;
+
+const HARDENED: u32 = 0x8000_0000;
+const MASTER_HMAC_KEY: &[u8] = b"Bitcoin seed";
+const SHA512_BLOCK_LEN: usize = 128;
+
+// Parameters of secp256k1, big-endian.
+const FIELD_PRIME: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xfc, 0x2f,
+];
+const GROUP_ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+const GENERATOR_X: [u8; 32] = [
+    0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+    0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+];
+const GENERATOR_Y: [u8; 32] = [
+    0x48, 0x3a, 0xda, 0x77, 0x26, 0xa3, 0xc4, 0x65, 0x5d, 0xa4, 0xfb, 0xfc, 0x0e, 0x11, 0x08, 0xa8,
+    0xfd, 0x17, 0xb4, 0x48, 0xa6, 0x85, 0x54, 0x19, 0x9c, 0x47, 0xd0, 0x8f, 0xfb, 0x10, 0xd4, 0xb8,
+];
+
+/// Step of a derivation path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChildNumber {
+    /// Child derived from the parent public key, written `i`.
+    Normal(u32),
+    /// Child derived from the parent private key, written `i'` or `ih`.
+    Hardened(u32),
+}
+
+impl ChildNumber {
+    /// Returns the index serialized in the derivation, with the top bit set
+    /// for hardened children.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the index does not fit in 31 bits.
+    pub fn index(self) -> Result<u32> {
+        match self {
+            ChildNumber::Normal(i) if i < HARDENED => Ok(i),
+            ChildNumber::Hardened(i) if i < HARDENED => Ok(i | HARDENED),
+            _ => Err(Error::new(ErrorKind::BadParameters)),
+        }
+    }
+}
+
+/// Parses a derivation path such as `m/44'/0'/0'/0/7`. `m` alone is the
+/// master key.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `path` does not start with `m` or one of its
+///    steps is not an index below 2^31, optionally followed by `'`, `h` or
+///    `H`.
+pub fn parse_path(path: &str) -> Result<Vec<ChildNumber>> {
+    let mut steps = path.split('/');
+    if steps.next() != Some("m") {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    steps
+        .map(|step| {
+            let (digits, hardened) = match step.strip_suffix(['\'', 'h', 'H']) {
+                Some(digits) => (digits, true),
+                None => (step, false),
+            };
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Error::new(ErrorKind::BadParameters));
+            }
+            let index: u32 = digits
+                .parse()
+                .map_err(|_| Error::new(ErrorKind::BadParameters))?;
+            let child = if hardened {
+                ChildNumber::Hardened(index)
+            } else {
+                ChildNumber::Normal(index)
+            };
+            child.index()?;
+            Ok(child)
+        })
+        .collect()
+}
+
+/// Generates a random master seed of `len` bytes and stores it in the
+/// object `seed_id` of the TA private storage.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `len` is not between [`MIN_SEED_LEN`] and
+///    [`MAX_SEED_LEN`].
+/// 2) `AccessConflict`: If the object `seed_id` already exists.
+/// 3) Errors from accessing the persistent storage.
+pub fn generate_seed(seed_id: &[u8], len: usize) -> Result<()> {
+    check_seed_len(len)?;
+    let mut seed = Secret(vec![0u8; len]);
+    Random::generate(&mut seed.0);
+    store_seed(seed_id, &seed.0)
+}
+
+/// Stores `seed` as the master seed in the object `seed_id` of the TA
+/// private storage, e.g. when restoring a wallet from its backup.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `seed` is not between [`MIN_SEED_LEN`] and
+///    [`MAX_SEED_LEN`] bytes long.
+/// 2) `AccessConflict`: If the object `seed_id` already exists.
+/// 3) Errors from accessing the persistent storage.
+pub fn import_seed(seed_id: &[u8], seed: &[u8]) -> Result<()> {
+    check_seed_len(seed.len())?;
+    store_seed(seed_id, seed)
+}
+
+/// Derives the private key at `path` from the master seed `seed_id`, and
+/// returns it as a 256-bit generic secret usable only as `usage` allows.
+///
+/// Pass [`UsageFlag::EXTRACTABLE`] for TAs that sign in software and need
+/// the key bytes.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If the object `seed_id` does not exist.
+/// 2) `CorruptObject`: If the stored seed does not have a valid length.
+/// 3) `BadParameters`: If a step of `path` has an index above 2^31 - 1, or
+///    leads to an invalid key, which BIP32 asks to skip and happens with a
+///    probability below 2^-127.
+/// 4) Errors from accessing the persistent storage.
+pub fn derive_key(
+    seed_id: &[u8],
+    path: &[ChildNumber],
+    usage: UsageFlag,
+) -> Result<TransientObject> {
+    let key = derive(seed_id, path)?;
+    let mut object = TransientObject::allocate(TransientObjectType::GenericSecret, 256)?;
+    object.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &key.key.0).into()])?;
+    object.restrict_usage(usage)?;
+    Ok(object)
+}
+
+/// Derives the compressed public key at `path` from the master seed
+/// `seed_id`.
+///
+/// # Errors
+///
+/// Same as [`derive_key`].
+pub fn derive_public_key(seed_id: &[u8], path: &[ChildNumber]) -> Result<[u8; PUBLIC_KEY_LEN]> {
+    let key = derive(seed_id, path)?;
+    Curve::new()?.public_key(&key.key.0)
+}
+
+fn check_seed_len(len: usize) -> Result<()> {
+    match len {
+        MIN_SEED_LEN..=MAX_SEED_LEN => Ok(()),
+        _ => Err(Error::new(ErrorKind::BadParameters)),
+    }
+}
+
+fn store_seed(seed_id: &[u8], seed: &[u8]) -> Result<()> {
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        seed_id,
+        DataFlag::ACCESS_READ,
+        None,
+        seed,
+    )?;
+    Ok(())
+}
+
+fn load_seed(seed_id: &[u8]) -> Result<Secret> {
+    let object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        seed_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    )?;
+    let mut seed = Secret(vec![0u8; MAX_SEED_LEN + 1]);
+    let read = object.read(&mut seed.0)? as usize;
+    if !(MIN_SEED_LEN..=MAX_SEED_LEN).contains(&read) {
+        return Err(Error::new(ErrorKind::CorruptObject));
+    }
+    wipe(&mut seed.0[read..]);
+    seed.0.truncate(read);
+    Ok(seed)
+}
+
+fn derive(seed_id: &[u8], path: &[ChildNumber]) -> Result<ExtendedKey> {
+    let curve = Curve::new()?;
+    let seed = load_seed(seed_id)?;
+    let mut key = ExtendedKey::from_hmac(&curve, None, hmac_sha512(MASTER_HMAC_KEY, &[&seed.0])?)?;
+    for child in path {
+        key = key.child(&curve, *child)?;
+    }
+    Ok(key)
+}
+
+// Private key and chain code of a node of the tree.
+struct ExtendedKey {
+    key: Secret,
+    chain_code: Secret,
+}
+
+impl ExtendedKey {
+    // Builds a node from `I = IL || IR`, adding `IL` to the key of `parent`
+    // if any, as the master key and CKDpriv do.
+    fn from_hmac(curve: &Curve, parent: Option<&Secret>, mut i: [u8; 64]) -> Result<Self> {
+        let result = (|| {
+            let il = big_int(&i[..32])?;
+            if il.compare_big_int(&curve.n) >= 0 {
+                return Err(Error::new(ErrorKind::BadParameters));
+            }
+            let key = match parent {
+                Some(parent) => BigInt::add_mod(&il, &big_int(&parent.0)?, &curve.n),
+                None => il,
+            };
+            if key.compare_s32(0) == 0 {
+                return Err(Error::new(ErrorKind::BadParameters));
+            }
+            Ok(ExtendedKey {
+                key: Secret(to_bytes(&key)?.to_vec()),
+                chain_code: Secret(i[32..].to_vec()),
+            })
+        })();
+        wipe(&mut i);
+        result
+    }
+
+    fn child(&self, curve: &Curve, child: ChildNumber) -> Result<Self> {
+        let index = child.index()?.to_be_bytes();
+        let i = match child {
+            ChildNumber::Hardened(_) => {
+                hmac_sha512(&self.chain_code.0, &[&[0], &self.key.0, &index])?
+            }
+            ChildNumber::Normal(_) => hmac_sha512(
+                &self.chain_code.0,
+                &[&curve.public_key(&self.key.0)?, &index],
+            )?,
+        };
+        Self::from_hmac(curve, Some(&self.key), i)
+    }
+}
+
+// Key material wiped from memory when dropped.
+struct Secret(Vec<u8>);
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+// HMAC-SHA512 of the concatenation of `parts`, computed over the digest
+// API since GP HMAC keys must be at least 192 bits long and BIP32 keys the
+// master with a 96-bit one.
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> Result<[u8; 64]> {
+    let mut pad = [0u8; SHA512_BLOCK_LEN];
+    pad[..key.len()].copy_from_slice(key);
+    let mut inner_hash = [0u8; 64];
+    let mut out = [0u8; 64];
+    let result = (|| {
+        let inner = Digest::allocate(AlgorithmId::Sha512)?;
+        pad.iter_mut().for_each(|b| *b ^= 0x36);
+        inner.update(&pad);
+        for part in parts {
+            inner.update(part);
+        }
+        inner.do_final(&[], &mut inner_hash)?;
+
+        let outer = Digest::allocate(AlgorithmId::Sha512)?;
+        pad.iter_mut().for_each(|b| *b ^= 0x36 ^ 0x5c);
+        outer.update(&pad);
+        outer.do_final(&inner_hash, &mut out)?;
+        Ok(())
+    })();
+    wipe(&mut pad);
+    wipe(&mut inner_hash);
+    result.map(|_| out)
+}
+
+pub(crate) fn big_int(bytes: &[u8]) -> Result<BigInt> {
+    let mut n = BigInt::new(bytes.len() as u32 * 8);
+    n.convert_from_octet_string(bytes, 0)?;
+    Ok(n)
+}
+
+// Big-endian encoding of `n`, left-padded to 32 bytes.
+fn to_bytes(n: &BigInt) -> Result<[u8; 32]> {
+    let mut bytes = n.convert_to_octet_string()?;
+    let mut out = [0u8; 32];
+    let result = match bytes.len() {
+        len if len <= 32 => {
+            out[32 - len..].copy_from_slice(&bytes);
+            Ok(out)
+        }
+        _ => Err(Error::new(ErrorKind::BadParameters)),
+    };
+    wipe(&mut bytes);
+    result
+}
+
+// Affine point of secp256k1, `None` being the point at infinity.
+type Point = Option<(BigInt, BigInt)>;
+
+struct Curve {
+    p: BigInt,
+    n: BigInt,
+}
+
+impl Curve {
+    fn new() -> Result<Self> {
+        Ok(Curve {
+            p: big_int(&FIELD_PRIME)?,
+            n: big_int(&GROUP_ORDER)?,
+        })
+    }
+
+    // Compressed encoding of `k * G`.
+    fn public_key(&self, k: &[u8]) -> Result<[u8; PUBLIC_KEY_LEN]> {
+        let generator = Some((big_int(&GENERATOR_X)?, big_int(&GENERATOR_Y)?));
+        let (x, y) = self
+            .multiply(&big_int(k)?, generator)
+            .ok_or_else(|| Error::new(ErrorKind::BadParameters))?;
+        let mut key = [0u8; PUBLIC_KEY_LEN];
+        key[0] = if y.get_bit(0) { 0x03 } else { 0x02 };
+        key[1..].copy_from_slice(&to_bytes(&x)?);
+        Ok(key)
+    }
+
+    // Montgomery ladder, doing the same operations whatever the bits of `k`.
+    fn multiply(&self, k: &BigInt, point: Point) -> Point {
+        let mut r0: Point = None;
+        let mut r1 = point;
+        for bit in (0..256).rev() {
+            if k.get_bit(bit) {
+                r0 = self.add(&r0, &r1);
+                r1 = self.double(&r1);
+            } else {
+                r1 = self.add(&r0, &r1);
+                r0 = self.double(&r0);
+            }
+        }
+        r0
+    }
+
+    fn add(&self, a: &Point, b: &Point) -> Point {
+        let p = &self.p;
+        let ((x1, y1), (x2, y2)) = match (a, b) {
+            (None, None) => return None,
+            (None, Some((x, y))) | (Some((x, y)), None) => return Some((copy(x, p), copy(y, p))),
+            (Some(a), Some(b)) => (a, b),
+        };
+        if x1.compare_big_int(x2) == 0 {
+            return if y1.compare_big_int(y2) == 0 {
+                self.double(a)
+            } else {
+                None
+            };
+        }
+        let slope = BigInt::mul_mod(
+            &BigInt::sub_mod(y2, y1, p),
+            &BigInt::inv_mod(&BigInt::sub_mod(x2, x1, p), p),
+            p,
+        );
+        self.finish(&slope, x1, y1, x2)
+    }
+
+    fn double(&self, a: &Point) -> Point {
+        let p = &self.p;
+        let (x, y) = a.as_ref()?;
+        if y.compare_s32(0) == 0 {
+            return None;
+        }
+        let x_squared = BigInt::square_mod(x, p);
+        let three_x_squared =
+            BigInt::add_mod(&BigInt::add_mod(&x_squared, &x_squared, p), &x_squared, p);
+        let slope = BigInt::mul_mod(
+            &three_x_squared,
+            &BigInt::inv_mod(&BigInt::add_mod(y, y, p), p),
+            p,
+        );
+        self.finish(&slope, x, y, x)
+    }
+
+    // Third point on the line of `slope` through `(x1, y1)` and a point of
+    // abscissa `x2`, reflected over the x axis.
+    fn finish(&self, slope: &BigInt, x1: &BigInt, y1: &BigInt, x2: &BigInt) -> Point {
+        let p = &self.p;
+        let x3 = BigInt::sub_mod(
+            &BigInt::sub_mod(&BigInt::square_mod(slope, p), x1, p),
+            x2,
+            p,
+        );
+        let y3 = BigInt::sub_mod(
+            &BigInt::mul_mod(slope, &BigInt::sub_mod(x1, &x3, p), p),
+            y1,
+            p,
+        );
+        Some((x3, y3))
+    }
+}
+
+fn copy(n: &BigInt, p: &BigInt) -> BigInt {
+    BigInt::module(n, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(parse_path("m").unwrap(), vec![]);
+        assert_eq!(
+            parse_path("m/44'/0h/7H/2147483647").unwrap(),
+            vec![
+                ChildNumber::Hardened(44),
+                ChildNumber::Hardened(0),
+                ChildNumber::Hardened(7),
+                ChildNumber::Normal(2147483647),
+            ]
+        );
+        for path in [
+            "",
+            "44'/0",
+            "m/",
+            "m/x",
+            "m/1''",
+            "m/2147483648",
+            "m/+1",
+            "m//1",
+        ] {
+            assert!(parse_path(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn serializes_indices() {
+        assert_eq!(ChildNumber::Normal(3).index().unwrap(), 3);
+        assert_eq!(ChildNumber::Hardened(3).index().unwrap(), 0x8000_0003);
+        assert!(ChildNumber::Hardened(HARDENED).index().is_err());
+    }
+}
@@ -15,7 +15,8 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::Uuid;
+use crate::{Error, ErrorKind, Uuid};
+use core::convert::TryFrom;
 use optee_utee_sys as raw;
 use strum_macros::Display;
 
@@ -25,6 +26,18 @@ pub struct Identity {
 }
 
 impl Identity {
+    /// Creates the identity of a client logged in with `login_type`, e.g. to
+    /// forward the identity of a CA reached through another transport.
+    pub fn new(login_type: LoginType, uuid: Uuid) -> Self {
+        Self {
+            raw: raw::TEE_Identity {
+                login: login_type as u32,
+                // SAFETY: the pointer refers to `uuid`, which outlives the read.
+                uuid: unsafe { *uuid.as_raw_ptr() },
+            },
+        }
+    }
+
     pub fn login_type(&self) -> LoginType {
         match self.raw.login {
             raw::TEE_LOGIN_PUBLIC => LoginType::Public,
@@ -49,7 +62,7 @@ impl From<raw::TEE_Identity> for Identity {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Display)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Display)]
 #[repr(u32)]
 pub enum LoginType {
     Public = raw::TEE_LOGIN_PUBLIC,
@@ -60,3 +73,23 @@ pub enum LoginType {
     ApplicationGroup = raw::TEE_LOGIN_APPLICATION_GROUP,
     TrustedApp = raw::TEE_LOGIN_TRUSTED_APP,
 }
+
+impl TryFrom<u32> for LoginType {
+    type Error = Error;
+
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `login` is not a `TEE_LOGIN_*` value.
+    fn try_from(login: u32) -> Result<Self, Error> {
+        match login {
+            raw::TEE_LOGIN_PUBLIC => Ok(LoginType::Public),
+            raw::TEE_LOGIN_USER => Ok(LoginType::User),
+            raw::TEE_LOGIN_GROUP => Ok(LoginType::Group),
+            raw::TEE_LOGIN_APPLICATION => Ok(LoginType::Application),
+            raw::TEE_LOGIN_APPLICATION_USER => Ok(LoginType::ApplicationUser),
+            raw::TEE_LOGIN_APPLICATION_GROUP => Ok(LoginType::ApplicationGroup),
+            raw::TEE_LOGIN_TRUSTED_APP => Ok(LoginType::TrustedApp),
+            _ => Err(Error::new(ErrorKind::BadParameters)),
+        }
+    }
+}
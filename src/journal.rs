@@ -0,0 +1,373 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Per-session journal of the commands a client invoked, kept in secure
+//! storage so that the requests of a client can be reconstructed after a
+//! security incident.
+//!
+//! The journal records the command id of each invocation and a SHA-256
+//! digest of its input parameters, never their contents. Every record is
+//! chained into a running SHA-256 hash, which is signed with a key of the TA
+//! every `checkpoint_every` records and when the journal is closed, so that
+//! records cannot be removed or altered without breaking a signature.
+//!
+//! # Format
+//!
+//! The journal object holds a sequence of records, integers little endian:
+//!
+//! * entry: `0x01`, sequence number (u64), system time seconds (u32) and
+//!   milliseconds (u32), command id (u32), parameter digest (32 bytes).
+//! * checkpoint: `0x02`, number of entries covered (u64), chain hash
+//!   (32 bytes), signature length (u16), signature.
+//!
+//! The chain hash starts as 32 zero bytes and each entry updates it to
+//! `SHA-256(chain || entry)`, the entry including its tag byte. The
+//! signature covers the chain hash as a digest.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::journal::SessionJournal;
+//! # use optee_utee::{Parameters, Result};
+//! fn invoke(
+//!     journal: &mut SessionJournal,
+//!     cmd_id: u32,
+//!     params: &mut Parameters,
+//! ) -> Result<()> {
+//!     journal.record(cmd_id, params)?;
+//!     // Handle the command.
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{
+    AlgorithmId, Asymmetric, DataFlag, Digest, Error, ErrorKind, GenericObject,
+    ObjectStorageConstants, OperationMode, ParamType, Parameter, Parameters, PersistentObject,
+    Result, Time, Whence,
+};
+
+const ENTRY_TAG: u8 = 0x01;
+const CHECKPOINT_TAG: u8 = 0x02;
+const ENTRY_LEN: usize = 1 + 8 + 4 + 4 + 4 + 32;
+const CHECKPOINT_HEADER_LEN: usize = 1 + 8 + 32 + 2;
+// Large enough for RSA 4096 signatures.
+const MAX_SIGNATURE_LEN: usize = 512;
+
+/// Journal of one session, appended to a persistent object of the TA
+/// private storage.
+///
+/// Dropping the journal does not sign the last records, call
+/// [`close`](Self::close) at the end of the session.
+pub struct SessionJournal {
+    object: PersistentObject,
+    signer: Asymmetric,
+    checkpoint_every: u64,
+    entries: u64,
+    signed: u64,
+    chain: [u8; 32],
+}
+
+impl SessionJournal {
+    /// Creates the journal object `object_id`, whose checkpoints are signed
+    /// with `key` using `algorithm`, e.g. [`AlgorithmId::EcDsaSha256`].
+    /// A checkpoint is written every `checkpoint_every` records, at least 1.
+    ///
+    /// # Errors
+    ///
+    /// 1) `AccessConflict`: If an object `object_id` already exists, so that
+    ///    a journal is never overwritten.
+    /// 2) Errors from allocating the signing operation with `key`, e.g.
+    ///    `BadParameters` if `key` does not match `algorithm`.
+    /// 3) Errors from creating the persistent object.
+    pub fn create<K: GenericObject>(
+        object_id: &[u8],
+        key: &K,
+        algorithm: AlgorithmId,
+        checkpoint_every: u64,
+    ) -> Result<Self> {
+        let signer =
+            Asymmetric::allocate(algorithm, OperationMode::Sign, key.info()?.object_size())?;
+        signer.set_key(key)?;
+        let object = PersistentObject::create(
+            ObjectStorageConstants::Private,
+            object_id,
+            DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE,
+            None,
+            &[],
+        )?;
+        Ok(Self {
+            object,
+            signer,
+            checkpoint_every: checkpoint_every.max(1),
+            entries: 0,
+            signed: 0,
+            chain: [0; 32],
+        })
+    }
+
+    /// Records an invocation of `cmd_id` with `params`, before the command
+    /// handles them, and writes a checkpoint if one is due.
+    ///
+    /// # Errors
+    ///
+    /// Errors from hashing, signing or writing to the storage.
+    pub fn record(&mut self, cmd_id: u32, params: &mut Parameters) -> Result<()> {
+        let mut time = Time::new();
+        time.system_time();
+        let entry = encode_entry(
+            self.entries,
+            time.seconds,
+            time.millis,
+            cmd_id,
+            &params_digest(params)?,
+        );
+
+        let digest = Digest::allocate(AlgorithmId::Sha256)?;
+        digest.update(&self.chain);
+        let mut chain = [0u8; 32];
+        digest.do_final(&entry, &mut chain)?;
+
+        self.append(&entry)?;
+        self.chain = chain;
+        self.entries += 1;
+        if self.entries - self.signed >= self.checkpoint_every {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Signs the records not covered by a checkpoint yet and closes the
+    /// journal.
+    ///
+    /// # Errors
+    ///
+    /// Errors from signing or writing to the storage.
+    pub fn close(mut self) -> Result<()> {
+        if self.entries > self.signed {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self) -> Result<()> {
+        let mut signature = [0u8; MAX_SIGNATURE_LEN];
+        let len = self.signer.sign_digest(&[], &self.chain, &mut signature)?;
+
+        self.append(&encode_checkpoint(
+            self.entries,
+            &self.chain,
+            &signature[..len],
+        ))?;
+        self.signed = self.entries;
+        Ok(())
+    }
+
+    fn append(&mut self, record: &[u8]) -> Result<()> {
+        self.object.seek(0, Whence::DataSeekEnd)?;
+        self.object.write(record)
+    }
+}
+
+/// A record read back from a journal by [`parse`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JournalRecord {
+    Entry {
+        sequence: u64,
+        seconds: u32,
+        millis: u32,
+        cmd_id: u32,
+        params_digest: [u8; 32],
+    },
+    Checkpoint {
+        entries: u64,
+        chain: [u8; 32],
+        signature: Vec<u8>,
+    },
+}
+
+/// Reads the records of the journal object `object_id`.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If there is no journal `object_id`.
+/// 2) `BadFormat`: If the journal is truncated or holds an unknown record.
+/// 3) Errors from reading the storage.
+pub fn load(object_id: &[u8]) -> Result<Vec<JournalRecord>> {
+    let object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        object_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    )?;
+    let mut data = vec![0u8; object.info()?.data_size()];
+    let read = object.read(&mut data)? as usize;
+    data.truncate(read);
+    parse(&data)
+}
+
+/// Parses the contents of a journal object.
+///
+/// # Errors
+///
+/// `BadFormat`: If `data` is truncated or holds an unknown record.
+pub fn parse(mut data: &[u8]) -> Result<Vec<JournalRecord>> {
+    let mut records = Vec::new();
+    while let Some(&tag) = data.first() {
+        let len = match tag {
+            ENTRY_TAG => ENTRY_LEN,
+            CHECKPOINT_TAG if data.len() >= CHECKPOINT_HEADER_LEN => {
+                CHECKPOINT_HEADER_LEN + u16_at(data, 41) as usize
+            }
+            _ => return Err(Error::new(ErrorKind::BadFormat)),
+        };
+        if data.len() < len {
+            return Err(Error::new(ErrorKind::BadFormat));
+        }
+        let record = &data[..len];
+        records.push(match tag {
+            ENTRY_TAG => JournalRecord::Entry {
+                sequence: u64_at(record, 1),
+                seconds: u32_at(record, 9),
+                millis: u32_at(record, 13),
+                cmd_id: u32_at(record, 17),
+                params_digest: array_at(record, 21),
+            },
+            _ => JournalRecord::Checkpoint {
+                entries: u64_at(record, 1),
+                chain: array_at(record, 9),
+                signature: record[CHECKPOINT_HEADER_LEN..].to_vec(),
+            },
+        });
+        data = &data[len..];
+    }
+    Ok(records)
+}
+
+// Hashes the type of each parameter and the contents of the input ones.
+fn params_digest(params: &mut Parameters) -> Result<[u8; 32]> {
+    let digest = Digest::allocate(AlgorithmId::Sha256)?;
+    for param in [&mut params.0, &mut params.1, &mut params.2, &mut params.3] {
+        digest.update(&param_bytes(param)?);
+    }
+    let mut hash = [0u8; 32];
+    digest.do_final(&[], &mut hash)?;
+    Ok(hash)
+}
+
+fn param_bytes(param: &mut Parameter) -> Result<Vec<u8>> {
+    let mut bytes = (param.param_type as u32).to_le_bytes().to_vec();
+    match param.param_type {
+        ParamType::ValueInput | ParamType::ValueInout => {
+            let value = unsafe { param.as_value()? };
+            bytes.extend_from_slice(&value.a().to_le_bytes());
+            bytes.extend_from_slice(&value.b().to_le_bytes());
+        }
+        ParamType::MemrefInput | ParamType::MemrefInout => {
+            let mut memref = unsafe { param.as_memref()? };
+            let buffer = memref.buffer();
+            bytes.extend_from_slice(&(buffer.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(buffer);
+        }
+        ParamType::MemrefOutput => {
+            let mut memref = unsafe { param.as_memref()? };
+            bytes.extend_from_slice(&(memref.buffer().len() as u64).to_le_bytes());
+        }
+        ParamType::None | ParamType::ValueOutput => {}
+    }
+    Ok(bytes)
+}
+
+fn encode_entry(
+    sequence: u64,
+    seconds: u32,
+    millis: u32,
+    cmd_id: u32,
+    params_digest: &[u8; 32],
+) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(ENTRY_LEN);
+    entry.push(ENTRY_TAG);
+    entry.extend_from_slice(&sequence.to_le_bytes());
+    entry.extend_from_slice(&seconds.to_le_bytes());
+    entry.extend_from_slice(&millis.to_le_bytes());
+    entry.extend_from_slice(&cmd_id.to_le_bytes());
+    entry.extend_from_slice(params_digest);
+    entry
+}
+
+fn encode_checkpoint(entries: u64, chain: &[u8; 32], signature: &[u8]) -> Vec<u8> {
+    let mut checkpoint = Vec::with_capacity(CHECKPOINT_HEADER_LEN + signature.len());
+    checkpoint.push(CHECKPOINT_TAG);
+    checkpoint.extend_from_slice(&entries.to_le_bytes());
+    checkpoint.extend_from_slice(chain);
+    checkpoint.extend_from_slice(&(signature.len() as u16).to_le_bytes());
+    checkpoint.extend_from_slice(signature);
+    checkpoint
+}
+
+fn array_at(data: &[u8], offset: usize) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&data[offset..offset + 32]);
+    array
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut data = encode_entry(0, 10, 20, 7, &[0xAA; 32]);
+        data.extend(encode_checkpoint(1, &[0x55; 32], &[1, 2, 3]));
+        assert_eq!(
+            parse(&data).unwrap(),
+            vec![
+                JournalRecord::Entry {
+                    sequence: 0,
+                    seconds: 10,
+                    millis: 20,
+                    cmd_id: 7,
+                    params_digest: [0xAA; 32],
+                },
+                JournalRecord::Checkpoint {
+                    entries: 1,
+                    chain: [0x55; 32],
+                    signature: vec![1, 2, 3],
+                },
+            ]
+        );
+
+        assert!(parse(&data[..data.len() - 1]).is_err());
+        assert!(parse(&[0x03]).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Compact JWS/JWT tokens signed with keys held by the TA.
+//!
+//! Claims are passed and returned as serialized JSON so that TAs can use any
+//! JSON encoder. Only the `exp` and `nbf` claims are interpreted, by
+//! [`validate_claims`].
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::{jwt, Result, TransientObject};
+//! fn issue(key: &TransientObject, subject: &str, now: u32) -> Result<String> {
+//!     let claims = format!(r#"{{"sub":"{}","exp":{}}}"#, subject, now + 3600);
+//!     jwt::sign(jwt::JwsAlgorithm::Es256, key, claims.as_bytes())
+//! }
+//!
+//! fn check(key: &TransientObject, token: &str) -> Result<Vec<u8>> {
+//!     let claims = jwt::verify(jwt::JwsAlgorithm::Es256, key, token)?;
+//!     jwt::validate_claims(&claims, 60)?;
+//!     Ok(claims)
+//! }
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{
+    AlgorithmId, Asymmetric, Digest, Error, ErrorKind, GenericObject, OperationMode, Result, Time,
+};
+
+/// Signature algorithms supported for tokens, named after their JWA `alg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JwsAlgorithm {
+    /// ECDSA on P-256 with SHA-256.
+    Es256,
+    /// Ed25519.
+    EdDsa,
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    Rs256,
+}
+
+impl JwsAlgorithm {
+    /// Returns the JWA name used in the `alg` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JwsAlgorithm::Es256 => "ES256",
+            JwsAlgorithm::EdDsa => "EdDSA",
+            JwsAlgorithm::Rs256 => "RS256",
+        }
+    }
+
+    fn algorithm_id(&self) -> AlgorithmId {
+        match self {
+            JwsAlgorithm::Es256 => AlgorithmId::EcDsaSha256,
+            JwsAlgorithm::EdDsa => AlgorithmId::Ed25519,
+            JwsAlgorithm::Rs256 => AlgorithmId::RsassaPkcs1V15Sha256,
+        }
+    }
+
+    // EdDSA signs the message itself, the others a SHA-256 digest of it.
+    fn prehash(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            JwsAlgorithm::EdDsa => Ok(message.to_vec()),
+            _ => {
+                let digest = Digest::allocate(AlgorithmId::Sha256)?;
+                let mut hash = vec![0u8; 32];
+                digest.do_final(message, &mut hash)?;
+                Ok(hash)
+            }
+        }
+    }
+}
+
+/// Signs `claims`, a serialized JSON object, into a compact token.
+///
+/// # Errors
+///
+/// Errors from allocating the operation or signing with `key`, e.g.
+/// `BadParameters` if `key` does not match `alg`.
+pub fn sign<K: GenericObject>(alg: JwsAlgorithm, key: &K, claims: &[u8]) -> Result<String> {
+    let header = format!(r#"{{"alg":"{}","typ":"JWT"}}"#, alg.name());
+    let mut token = base64url_encode(header.as_bytes());
+    token.push('.');
+    token.push_str(&base64url_encode(claims));
+
+    let key_size = key.info()?.object_size();
+    let op = Asymmetric::allocate(alg.algorithm_id(), OperationMode::Sign, key_size)?;
+    op.set_key(key)?;
+    let mut signature = match alg {
+        JwsAlgorithm::Rs256 => vec![0u8; key_size.div_ceil(8)],
+        _ => vec![0u8; 64],
+    };
+    let len = op.sign_digest(&[], &alg.prehash(token.as_bytes())?, &mut signature)?;
+    signature.truncate(len);
+
+    token.push('.');
+    token.push_str(&base64url_encode(&signature));
+    Ok(token)
+}
+
+/// Verifies a compact token signed with `alg` and returns its claims.
+///
+/// The claims are not validated, see [`validate_claims`].
+///
+/// # Errors
+///
+/// 1) `BadFormat`: If `token` is malformed or its `alg` header is not `alg`.
+/// 2) `SignatureInvalid`: If the signature does not match.
+pub fn verify<K: GenericObject>(alg: JwsAlgorithm, key: &K, token: &str) -> Result<Vec<u8>> {
+    let mut parts = token.split('.');
+    let (header, claims, signature) = match (parts.next(), parts.next(), parts.next(), parts.next())
+    {
+        (Some(header), Some(claims), Some(signature), None) => (header, claims, signature),
+        _ => return Err(Error::new(ErrorKind::BadFormat)),
+    };
+
+    let header_json = base64url_decode(header)?;
+    if json_string(&header_json, "alg")? != Some(alg.name().as_bytes()) {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    let claims_json = base64url_decode(claims)?;
+    let signature = base64url_decode(signature)?;
+
+    let signing_input = &token[..header.len() + 1 + claims.len()];
+    let key_size = key.info()?.object_size();
+    let op = Asymmetric::allocate(alg.algorithm_id(), OperationMode::Verify, key_size)?;
+    op.set_key(key)?;
+    op.verify_digest(&[], &alg.prehash(signing_input.as_bytes())?, &signature)?;
+    Ok(claims_json)
+}
+
+/// Checks the `exp` and `nbf` claims against the TA persistent time,
+/// tolerating a clock skew of `leeway` seconds.
+///
+/// # Errors
+///
+/// 1) `TimeNotSet` or `TimeNeedsReset`: If the TA persistent time is not
+///    usable, see [`Time::ta_time`].
+/// 2) Errors of [`validate_claims_at`].
+pub fn validate_claims(claims: &[u8], leeway: u32) -> Result<()> {
+    let mut now = Time::new();
+    now.ta_time()?;
+    validate_claims_at(claims, now.seconds, leeway)
+}
+
+/// Checks the `exp` and `nbf` claims against `now`, in seconds since the
+/// epoch, tolerating a clock skew of `leeway` seconds.
+///
+/// # Errors
+///
+/// 1) `BadFormat`: If `claims` is not a JSON object or a time claim is not a
+///    number.
+/// 2) `AccessDenied`: If the token is expired or not valid yet.
+pub fn validate_claims_at(claims: &[u8], now: u32, leeway: u32) -> Result<()> {
+    let now = now as u64;
+    let leeway = leeway as u64;
+    if let Some(exp) = json_number(claims, "exp")? {
+        if now >= exp.saturating_add(leeway) {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+    }
+    if let Some(nbf) = json_number(claims, "nbf")? {
+        if now + leeway < nbf {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+    }
+    Ok(())
+}
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64url_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 4).div_ceil(3));
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(BASE64URL[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+        }
+    }
+    out
+}
+
+fn base64url_decode(data: &str) -> Result<Vec<u8>> {
+    if data.len() % 4 == 1 {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.as_bytes().chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let v = BASE64URL
+                .iter()
+                .position(|&b| b == c)
+                .ok_or_else(|| Error::new(ErrorKind::BadFormat))?;
+            n |= (v as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            out.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(out)
+}
+
+// Scans the members of a JSON object without building a document.
+struct JsonScanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonScanner<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.data.get(self.pos) {
+            self.pos += 1;
+        }
+        self.data.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, b: u8) -> Result<()> {
+        if self.peek() != Some(b) {
+            return Err(Error::new(ErrorKind::BadFormat));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    // Returns the raw contents of a string, escapes included.
+    fn string(&mut self) -> Result<&'a [u8]> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while let Some(&b) = self.data.get(self.pos) {
+            match b {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(&self.data[start..self.pos - 1]);
+                }
+                b'\\' => self.pos += 2,
+                _ => self.pos += 1,
+            }
+        }
+        Err(Error::new(ErrorKind::BadFormat))
+    }
+
+    // Skips a value and returns its raw text.
+    fn value(&mut self) -> Result<&'a [u8]> {
+        let start = match self.peek() {
+            Some(_) => self.pos,
+            None => return Err(Error::new(ErrorKind::BadFormat)),
+        };
+        match self.data[start] {
+            b'"' => {
+                self.string()?;
+            }
+            b'{' | b'[' => {
+                let mut depth = 0usize;
+                loop {
+                    match self.peek() {
+                        Some(b'"') => {
+                            self.string()?;
+                            continue;
+                        }
+                        Some(b'{' | b'[') => depth += 1,
+                        Some(b'}' | b']') => depth -= 1,
+                        Some(_) => {}
+                        None => return Err(Error::new(ErrorKind::BadFormat)),
+                    }
+                    self.pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                while let Some(&b) = self.data.get(self.pos) {
+                    if matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r') {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+        Ok(&self.data[start..self.pos])
+    }
+}
+
+// Returns the raw text of the member `key` of the JSON object `json`.
+fn json_field<'a>(json: &'a [u8], key: &str) -> Result<Option<&'a [u8]>> {
+    let mut scanner = JsonScanner { data: json, pos: 0 };
+    scanner.expect(b'{')?;
+    if scanner.peek() == Some(b'}') {
+        return Ok(None);
+    }
+    loop {
+        let name = scanner.string()?;
+        scanner.expect(b':')?;
+        let value = scanner.value()?;
+        if name == key.as_bytes() {
+            return Ok(Some(value));
+        }
+        match scanner.peek() {
+            Some(b',') => scanner.pos += 1,
+            Some(b'}') => return Ok(None),
+            _ => return Err(Error::new(ErrorKind::BadFormat)),
+        }
+    }
+}
+
+fn json_string<'a>(json: &'a [u8], key: &str) -> Result<Option<&'a [u8]>> {
+    match json_field(json, key)? {
+        Some([b'"', inner @ .., b'"']) => Ok(Some(inner)),
+        Some(_) => Err(Error::new(ErrorKind::BadFormat)),
+        None => Ok(None),
+    }
+}
+
+// NumericDate values may have a fractional part, which is ignored.
+fn json_number(json: &[u8], key: &str) -> Result<Option<u64>> {
+    let value = match json_field(json, key)? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let integer = value.split(|&b| b == b'.').next().unwrap_or_default();
+    if integer.is_empty() || !integer.iter().all(u8::is_ascii_digit) {
+        return Err(Error::new(ErrorKind::BadFormat));
+    }
+    integer
+        .iter()
+        .try_fold(0u64, |n, &d| {
+            n.checked_mul(10)?.checked_add((d - b'0') as u64)
+        })
+        .map(Some)
+        .ok_or_else(|| Error::new(ErrorKind::BadFormat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64url() {
+        for (data, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg"),
+            (b"fo", "Zm8"),
+            (b"foo", "Zm9v"),
+            (b"\xfb\xff", "-_8"),
+        ] {
+            assert_eq!(base64url_encode(data), encoded);
+            assert_eq!(base64url_decode(encoded).unwrap(), data);
+        }
+        assert!(base64url_decode("Zm9vY").is_err());
+        assert!(base64url_decode("Zm9+").is_err());
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let json =
+            br#"{"alg": "ES256", "nested": {"exp": "x", "a": [1, "}"]}, "exp": 1700000000.5}"#;
+        assert_eq!(json_string(json, "alg").unwrap(), Some(&b"ES256"[..]));
+        assert_eq!(json_number(json, "exp").unwrap(), Some(1_700_000_000));
+        assert_eq!(json_number(json, "nbf").unwrap(), None);
+        assert!(json_number(json, "alg").is_err());
+        assert!(json_field(b"[1]", "alg").is_err());
+    }
+
+    #[test]
+    fn test_validate_claims_at() {
+        let claims = br#"{"nbf":1000,"exp":2000}"#;
+        assert!(validate_claims_at(claims, 1500, 0).is_ok());
+        assert!(validate_claims_at(claims, 999, 0).is_err());
+        assert!(validate_claims_at(claims, 990, 10).is_ok());
+        assert!(validate_claims_at(claims, 2000, 0).is_err());
+        assert!(validate_claims_at(claims, 2005, 10).is_ok());
+        assert!(validate_claims_at(b"{}", 0, 0).is_ok());
+    }
+
+    #[test]
+    fn test_exp_near_u64_max() {
+        let claims = br#"{"exp":18446744073709551615}"#;
+        assert!(validate_claims_at(claims, u32::MAX, u32::MAX).is_ok());
+    }
+}
@@ -30,11 +30,12 @@ static ALLOCATOR: LibcAlloc = LibcAlloc;
 
 pub use self::arithmetical::*;
 pub use self::crypto_op::*;
-pub use self::error::{Error, ErrorKind, Result};
+pub use self::error::{register_vendor_error_names, Error, ErrorKind, ErrorOrigin, Result};
 pub use self::extension::*;
 pub use self::identity::{Identity, LoginType};
 pub use self::object::*;
 pub use self::parameter::{ParamType, ParamTypes, Parameter, Parameters};
+pub use self::secret::SecretBuffer;
 pub use self::ta_session::{TaSession, TaSessionBuilder};
 pub use self::tee_parameter::{ParamIndex, TeeParams};
 pub use self::time::*;
@@ -44,15 +45,40 @@ pub mod trace;
 #[macro_use]
 mod macros;
 pub mod arithmetical;
+pub mod build_info;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod config;
 pub mod crypto_op;
+pub mod curve25519;
+pub mod der;
+pub mod event_log;
+pub mod ecdsa;
 mod error;
 pub mod extension;
+pub mod hdkey;
 pub mod identity;
+#[cfg(feature = "session_journal")]
+pub mod journal;
+pub mod jwt;
 pub mod net;
 pub mod object;
 mod parameter;
+pub mod platform;
 pub mod property;
+pub mod quota;
+#[cfg(feature = "rustcrypto")]
+pub mod rustcrypto;
+pub mod secret;
+pub mod services;
+pub mod sm2;
 mod ta_session;
+#[cfg(feature = "error_telemetry")]
+pub mod telemetry;
+pub mod tenant;
+#[cfg(feature = "threshold")]
+pub mod threshold;
 mod tee_parameter;
 pub mod time;
+pub mod trash;
 pub mod uuid;
@@ -62,3 +62,34 @@ macro_rules! trace_println {
         $crate::trace::Trace::_print(format_args!(concat!($s, "\n"), $($tt)*));
     };
 }
+
+/// Macro for building the [`BuildManifest`](crate::build_info::BuildManifest)
+/// of the calling crate, from the environment of its compilation.
+///
+/// The TA crate name and version come from Cargo, the other fields from the
+/// `TA_FEATURES`, `TA_TARGET` and `TA_GIT_HASH` variables exported by the
+/// build script of the TA, see [`build_info`](crate::build_info). The
+/// manifest is a constant, so a `#[used]` static keeps it in the binary
+/// even if the TA does not serve it.
+///
+/// # Examples
+///
+/// ``` rust,no_run
+/// # use optee_utee::build_info::BuildManifest;
+/// # use optee_utee::build_manifest;
+/// #[used]
+/// static BUILD_MANIFEST: BuildManifest = build_manifest!();
+/// ```
+#[macro_export]
+macro_rules! build_manifest {
+    () => {
+        $crate::build_info::BuildManifest {
+            crate_name: env!("CARGO_PKG_NAME"),
+            crate_version: env!("CARGO_PKG_VERSION"),
+            optee_utee_version: $crate::build_info::OPTEE_UTEE_VERSION,
+            features: option_env!("TA_FEATURES"),
+            target: option_env!("TA_TARGET"),
+            git_hash: option_env!("TA_GIT_HASH"),
+        }
+    };
+}
@@ -200,6 +200,8 @@ pub enum AttributeId {
     EccPublicValueY = 0xD0000241,
     /// ECC private value: `d`
     EccPrivateValue = 0xC0000341,
+    /// EdDSA context, up to 255 bytes, selecting Ed25519ctx
+    EddsaCtx = 0xD0000643,
     /// Ed25519 public value
     Ed25519PublicValue = 0xD0000743,
     /// Ed25519 private value
@@ -210,6 +212,30 @@ pub enum AttributeId {
     X25519PrivateValue = 0xC0000A44,
     /// ECC Curve algorithm
     EccCurve = 0xF0000441,
+    /// SM2 key exchange: identifier of the initiator
+    Sm2IdInitiator = 0xD0000446,
+    /// SM2 key exchange: identifier of the responder
+    Sm2IdResponder = 0xD0000546,
+    /// SM2 key exchange: role `a`, 0 for the initiator and 1 for the responder
+    Sm2KepUser = 0xF0000646,
+    /// SM2 key exchange: confirmation value received from the peer
+    Sm2KepConfirmationIn = 0xD0000746,
+    /// SM2 key exchange: confirmation value to send to the peer
+    Sm2KepConfirmationOut = 0xD0000846,
+    /// SM2 key exchange: ephemeral public value `x` of the peer
+    EccEphemeralPublicValueX = 0xD0000946,
+    /// SM2 key exchange: ephemeral public value `y` of the peer
+    EccEphemeralPublicValueY = 0xD0000A46,
+    /// EdDSA prehash flag, selecting Ed25519ph when `a` is 1
+    EddsaPrehash = 0xF0000004,
+    /// PBKDF2 password, OP-TEE extension
+    Pbkdf2Password = 0xC00001C2,
+    /// PBKDF2 salt, OP-TEE extension
+    Pbkdf2Salt = 0xD00002C2,
+    /// PBKDF2 iteration count `a`, OP-TEE extension
+    Pbkdf2IterationCount = 0xF00003C2,
+    /// PBKDF2 length in bytes `a` of the derived key, OP-TEE extension
+    Pbkdf2DkLength = 0xF00004C2,
     BitProtected = (1 << 28),
     BitValue = (1 << 29),
 }
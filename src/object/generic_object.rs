@@ -18,7 +18,7 @@
 use core::mem;
 
 use super::{AttributeId, ObjectInfo, UsageFlag};
-use crate::{Error, Result};
+use crate::{Error, Result, SecretBuffer};
 
 use optee_utee_sys as raw;
 
@@ -125,6 +125,44 @@ pub trait GenericObject {
         }
     }
 
+    /// Extract one buffer attribute from an object into a
+    /// [SecretBuffer](crate::SecretBuffer) of its exact size, e.g. the
+    /// `SecretValue` of a key, which is wiped from memory once dropped.
+    ///
+    /// # Errors
+    ///
+    /// Same as [ref_attribute](GenericObject::ref_attribute), without
+    /// `SHORT_BUFFER`.
+    ///
+    /// # Panics
+    ///
+    /// Same as [ref_attribute](GenericObject::ref_attribute).
+    fn secret_attribute(&self, id: AttributeId) -> Result<SecretBuffer> {
+        let id = id as u32;
+        let mut size = 0;
+        match unsafe {
+            raw::TEE_GetObjectBufferAttribute(self.handle(), id, core::ptr::null_mut(), &mut size)
+        } {
+            raw::TEE_SUCCESS | raw::TEE_ERROR_SHORT_BUFFER => {}
+            code => return Err(Error::from_raw_error(code)),
+        }
+        let mut secret = SecretBuffer::new(size);
+        match unsafe {
+            raw::TEE_GetObjectBufferAttribute(
+                self.handle(),
+                id,
+                secret.as_mut_ptr() as _,
+                &mut size,
+            )
+        } {
+            raw::TEE_SUCCESS => {
+                secret.truncate(size);
+                Ok(secret)
+            }
+            code => Err(Error::from_raw_error(code)),
+        }
+    }
+
     /// Extract one value attribute from an object. The attribute is identified
     /// by the argument id.
     ///
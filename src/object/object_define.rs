@@ -122,7 +122,7 @@ bitflags! {
         /// `TEE_SetOperationKey2`.
         /// This happens only if algorithm is set to
         /// [AesXts](crate::AlgorithmId::AesXts)
-        /// or `TEE_ALG_SM2_KEP`(not supported now).
+        /// or [Sm2Kep](crate::AlgorithmId::Sm2Kep).
         const EXPECT_TWO_KEYS = 0x00080000;
     }
 }
@@ -46,6 +46,10 @@ pub enum TransientObjectType {
     HmacSha384 = 0xA0000005,
     /// Between 256 and 1024 bits, multiple of 8 bits
     HmacSha512 = 0xA0000006,
+    /// Between 80 and 1024 bits, multiple of 8 bits
+    HmacSm3 = 0xA0000007,
+    /// 128 bits
+    Sm4 = 0xA0000014,
     /// The number of bits in the modulus. 256, 512, 768, 1024, 1536 and
     /// 2048-bit keys SHALL be supported.
     /// Support for other key sizes including bigger key sizes is
@@ -93,9 +97,31 @@ pub enum TransientObjectType {
     /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_25519
     /// defined in Table 6-14 is supported.
     X25519Keypair = 0xA1000044,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2DsaPublicKey = 0xA0000045,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2DsaKeypair = 0xA1000045,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2KepPublicKey = 0xA0000046,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2KepKeypair = 0xA1000046,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2PkePublicKey = 0xA0000047,
+    /// 256 bits. Conditional: Available only if TEE_ECC_CURVE_SM2 is
+    /// supported.
+    Sm2PkeKeypair = 0xA1000047,
     /// Multiple of 8 bits, up to 4096 bits. This type is intended for secret
     /// data that has been derived from a key derivation scheme.
     GenericSecret = 0xA0000000,
+    /// Multiple of 8 bits, up to 4096 bits. Password of a
+    /// [Pbkdf2HmacSha1DeriveKey](crate::AlgorithmId::Pbkdf2HmacSha1DeriveKey)
+    /// operation, an OP-TEE extension.
+    Pbkdf2Password = 0xA10000C2,
     /// Object is corrupted.
     CorruptedObject = 0xA00000BE,
     /// 0 – All data is in the associated data stream.
@@ -380,6 +406,25 @@ impl GenericObject for TransientObject {
     }
 }
 
+/// Clears the key material of the object with [reset](TransientObject::reset),
+/// leaving it uninitialized, with the `zeroize` feature.
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for TransientObject {
+    fn zeroize(&mut self) {
+        if !self.is_null_object() {
+            self.reset();
+        }
+    }
+}
+
+/// Hands over the handle of the object, e.g. to pass its attributes to
+/// [PersistentObject::create](crate::PersistentObject::create).
+impl From<TransientObject> for ObjectHandle {
+    fn from(object: TransientObject) -> Self {
+        object.0
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use optee_utee_mock::{
@@ -139,9 +139,18 @@ impl Parameter {
     }
 }
 
+/// The `param_types` word of a TA entry point, packing the [`ParamType`] of
+/// the four parameters in its low 16 bits, one nibble each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ParamTypes(u32);
 
 impl ParamTypes {
+    /// Packs the types of the four parameters, the inverse of
+    /// [`into_flags`](Self::into_flags).
+    pub fn new(p0: ParamType, p1: ParamType, p2: ParamType, p3: ParamType) -> Self {
+        ParamTypes(u32::from(p0) | u32::from(p1) << 4 | u32::from(p2) << 8 | u32::from(p3) << 12)
+    }
+
     pub fn into_flags(&self) -> (ParamType, ParamType, ParamType, ParamType) {
         (
             (0x000fu32 & self.0).into(),
@@ -150,6 +159,26 @@ impl ParamTypes {
             ((0xf000u32 & self.0) >> 12).into(),
         )
     }
+
+    /// Returns the packed word.
+    pub fn raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Checks that the word round-trips through [`into_flags`](Self::into_flags)
+    /// and [`new`](Self::new), which [`From<u32>`] does not guarantee: every
+    /// nibble holds a defined [`ParamType`] and the high 16 bits are zero.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the word holds an undefined type, e.g. 4, or
+    ///    bits above the fourth parameter.
+    pub fn validate(&self) -> Result<()> {
+        if self.0 >> 16 != 0 {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        (0..4).try_for_each(|i| ParamType::from_u32(self.0 >> (4 * i) & 0xf).map(|_| ()))
+    }
 }
 
 impl From<u32> for ParamTypes {
@@ -158,7 +187,28 @@ impl From<u32> for ParamTypes {
     }
 }
 
-#[derive(Copy, Clone)]
+impl From<ParamTypes> for u32 {
+    fn from(types: ParamTypes) -> Self {
+        types.0
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ParamTypes {
+    // Only generates valid words, use `From<u32>` on an arbitrary `u32` for
+    // the others.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(ParamTypes::new(
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+            u.arbitrary()?,
+        ))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ParamType {
     None = 0,
     ValueInput = 1,
@@ -169,17 +219,87 @@ pub enum ParamType {
     MemrefInout = 7,
 }
 
+impl ParamType {
+    /// Converts a `TEE_PARAM_TYPE_*` value, unlike [`From<u32>`] refusing the
+    /// values no type has instead of reading them as [`ParamType::None`].
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `value` is not the value of a type.
+    pub fn from_u32(value: u32) -> Result<Self> {
+        match value {
+            0 => Ok(ParamType::None),
+            1 => Ok(ParamType::ValueInput),
+            2 => Ok(ParamType::ValueOutput),
+            3 => Ok(ParamType::ValueInout),
+            5 => Ok(ParamType::MemrefInput),
+            6 => Ok(ParamType::MemrefOutput),
+            7 => Ok(ParamType::MemrefInout),
+            _ => Err(Error::new(ErrorKind::BadParameters)),
+        }
+    }
+}
+
 impl From<u32> for ParamType {
     fn from(value: u32) -> Self {
-        match value {
-            0 => ParamType::None,
-            1 => ParamType::ValueInput,
-            2 => ParamType::ValueOutput,
-            3 => ParamType::ValueInout,
-            5 => ParamType::MemrefInput,
-            6 => ParamType::MemrefOutput,
-            7 => ParamType::MemrefInout,
-            _ => ParamType::None,
+        ParamType::from_u32(value).unwrap_or(ParamType::None)
+    }
+}
+
+impl From<ParamType> for u32 {
+    fn from(param_type: ParamType) -> Self {
+        param_type as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn param_type() -> impl Strategy<Value = ParamType> {
+        prop_oneof![
+            Just(ParamType::None),
+            Just(ParamType::ValueInput),
+            Just(ParamType::ValueOutput),
+            Just(ParamType::ValueInout),
+            Just(ParamType::MemrefInput),
+            Just(ParamType::MemrefOutput),
+            Just(ParamType::MemrefInout),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_param_type_round_trip(value in any::<u32>()) {
+            match ParamType::from_u32(value) {
+                Ok(param_type) => prop_assert_eq!(u32::from(param_type), value),
+                Err(e) => {
+                    prop_assert_eq!(e.kind(), ErrorKind::BadParameters);
+                    prop_assert!(value == 4 || value > 7);
+                }
+            }
+        }
+
+        #[test]
+        fn test_param_types_round_trip(
+            p0 in param_type(),
+            p1 in param_type(),
+            p2 in param_type(),
+            p3 in param_type(),
+        ) {
+            let types = ParamTypes::new(p0, p1, p2, p3);
+            prop_assert!(types.validate().is_ok());
+            prop_assert_eq!(types.into_flags(), (p0, p1, p2, p3));
+            prop_assert_eq!(ParamTypes::from(types.raw()), types);
+        }
+
+        #[test]
+        fn test_param_types_validate(raw in any::<u32>()) {
+            let types = ParamTypes::from(raw);
+            let (p0, p1, p2, p3) = types.into_flags();
+            let round_trip = ParamTypes::new(p0, p1, p2, p3) == types;
+            prop_assert_eq!(types.validate().is_ok(), round_trip);
         }
     }
 }
@@ -0,0 +1,207 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Capabilities of the environment a TA runs in, and whether each is
+//! provided by OP-TEE itself, emulated by a host or missing.
+//!
+//! A TA can run under OP-TEE or be hosted in a normal-world process by a
+//! manager such as `ta_manager`, which emulates part of the TEE. Rather than
+//! discovering the differences through errors after deployment, a TA can
+//! query [`current`] and branch explicitly:
+//!
+//! ``` rust,no_run
+//! use optee_utee::platform::{self, Capability};
+//! use optee_utee::ElementId;
+//!
+//! if platform::current().supports(Capability::EccCurve(ElementId::EccCurve25519)) {
+//!     // Use X25519.
+//! } else {
+//!     // Fall back to P-256.
+//! }
+//! ```
+//!
+//! Without a host installing its own profile with [`install`], the TA is
+//! taken to run under OP-TEE, [`Platform::OPTEE`].
+
+use alloc::boxed::Box;
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::ElementId;
+
+/// Environment a TA runs in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Environment {
+    /// Under OP-TEE, in the secure world.
+    OpTee,
+    /// In a normal-world process emulating the TEE.
+    Hosted,
+}
+
+/// How a capability is provided.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Support {
+    /// Provided by OP-TEE, with its security properties.
+    Native,
+    /// Provided by the host with the same interface, without the security
+    /// properties of the TEE, e.g. storage in the normal-world file system.
+    Emulated,
+    /// Not provided, the related calls fail.
+    Unavailable,
+}
+
+impl Support {
+    /// Returns `true` unless the capability is [`Unavailable`](Self::Unavailable).
+    pub fn is_available(&self) -> bool {
+        *self != Support::Unavailable
+    }
+}
+
+/// Capability that differs between environments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Persistent objects in the private storage of the TA.
+    SecureStorage,
+    /// Persistent objects in the Replay Protected Memory Block storage.
+    Rpmb,
+    /// Elliptic curve operations on the given curve.
+    EccCurve(ElementId),
+    /// Plugins of tee-supplicant, or their equivalent in a host.
+    SupplicantPlugins,
+    /// Cancellation of running commands by the client.
+    Cancellation,
+    /// Sessions opened by the TA on other TAs.
+    TaSessions,
+}
+
+/// Profile of the capabilities of an environment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Platform {
+    pub environment: Environment,
+    pub secure_storage: Support,
+    pub rpmb: Support,
+    /// Curves with their support, curves not listed being
+    /// [`Unavailable`](Support::Unavailable).
+    pub ecc_curves: &'static [(ElementId, Support)],
+    pub supplicant_plugins: Support,
+    pub cancellation: Support,
+    pub ta_sessions: Support,
+}
+
+impl Platform {
+    /// Profile of OP-TEE, with all capabilities native.
+    ///
+    /// RPMB storage, Curve25519 and SM2 depend on the configuration OP-TEE was
+    /// built with, and fail at runtime if it left them out.
+    pub const OPTEE: Platform = Platform {
+        environment: Environment::OpTee,
+        secure_storage: Support::Native,
+        rpmb: Support::Native,
+        ecc_curves: &[
+            (ElementId::EccCurveNistP192, Support::Native),
+            (ElementId::EccCurveNistP224, Support::Native),
+            (ElementId::EccCurveNistP256, Support::Native),
+            (ElementId::EccCurveNistP384, Support::Native),
+            (ElementId::EccCurveNistP521, Support::Native),
+            (ElementId::EccCurve25519, Support::Native),
+            (ElementId::EccCurveSm2, Support::Native),
+        ],
+        supplicant_plugins: Support::Native,
+        cancellation: Support::Native,
+        ta_sessions: Support::Native,
+    };
+
+    /// Returns how `capability` is provided.
+    pub fn support(&self, capability: Capability) -> Support {
+        match capability {
+            Capability::SecureStorage => self.secure_storage,
+            Capability::Rpmb => self.rpmb,
+            Capability::EccCurve(curve) => self
+                .ecc_curves
+                .iter()
+                .find(|(id, _)| *id == curve)
+                .map_or(Support::Unavailable, |(_, support)| *support),
+            Capability::SupplicantPlugins => self.supplicant_plugins,
+            Capability::Cancellation => self.cancellation,
+            Capability::TaSessions => self.ta_sessions,
+        }
+    }
+
+    /// Returns `true` if `capability` is provided, natively or emulated.
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.support(capability).is_available()
+    }
+
+    /// Returns `true` if `capability` is provided by OP-TEE itself.
+    pub fn is_native(&self, capability: Capability) -> bool {
+        self.support(capability) == Support::Native
+    }
+}
+
+static PLATFORM: AtomicPtr<Platform> = AtomicPtr::new(ptr::null_mut());
+
+/// Returns the profile of the environment the TA runs in.
+pub fn current() -> &'static Platform {
+    let platform = PLATFORM.load(Ordering::Acquire);
+    if platform.is_null() {
+        return &Platform::OPTEE;
+    }
+    // SAFETY:
+    // Installed profiles are leaked and therefore valid for the rest of the program.
+    unsafe { &*platform }
+}
+
+/// Installs the profile returned by [`current`], replacing any previously
+/// installed one.
+///
+/// Meant for hosts emulating the TEE, once when they start the TA, since
+/// replaced profiles are not freed.
+pub fn install(platform: Platform) {
+    let platform = Box::into_raw(Box::new(platform));
+    PLATFORM.store(platform, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_support() {
+        let hosted = Platform {
+            environment: Environment::Hosted,
+            secure_storage: Support::Emulated,
+            rpmb: Support::Unavailable,
+            ecc_curves: &[(ElementId::EccCurveNistP256, Support::Emulated)],
+            ..Platform::OPTEE
+        };
+        assert!(hosted.supports(Capability::SecureStorage));
+        assert!(!hosted.is_native(Capability::SecureStorage));
+        assert!(!hosted.supports(Capability::Rpmb));
+        assert_eq!(
+            hosted.support(Capability::EccCurve(ElementId::EccCurveNistP256)),
+            Support::Emulated
+        );
+        assert_eq!(
+            hosted.support(Capability::EccCurve(ElementId::EccCurve25519)),
+            Support::Unavailable
+        );
+        assert!(Platform::OPTEE.is_native(Capability::EccCurve(ElementId::EccCurve25519)));
+        assert!(Platform::OPTEE.is_native(Capability::Cancellation));
+    }
+}
@@ -0,0 +1,230 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Usage quotas of keys, metered in the TA private storage.
+//!
+//! A TA calls [`charge`] with the id of a key before every operation with
+//! it. The usage of each key, operations of the current day and over its
+//! lifetime, is kept in its own persistent object and updated with a single
+//! write, so that a charge is either fully recorded or not at all.
+//!
+//! Days are counted in UTC from the REE time, as returned by
+//! [`Time::system_time`].
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::quota::{self, UsageQuota};
+//! # use optee_utee::Result;
+//! fn before_signing(key_id: &[u8]) -> Result<()> {
+//!     let quota = UsageQuota {
+//!         max_per_day: Some(1000),
+//!         max_lifetime: Some(1_000_000),
+//!     };
+//!     quota::charge(key_id, &quota, 1)?;
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{
+    DataFlag, Error, ErrorKind, ObjectStorageConstants, PersistentObject, Result, Time, Whence,
+};
+
+/// Vendor error code, as in [`ErrorKind::Vendor`], of the error returned
+/// when a charge would exceed a quota.
+pub const QUOTA_EXCEEDED: u32 = 0x8000_0100;
+
+const OBJECT_ID_PREFIX: &[u8] = b"fw.quota.";
+/// Longest key id accepted by [`charge`] and [`usage`].
+pub const MAX_KEY_ID_LEN: usize = 64 - OBJECT_ID_PREFIX.len();
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+const RECORD_LEN: usize = 4 + 8 + 8;
+
+/// Maximum number of operations with a key. `None` sets no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UsageQuota {
+    /// Operations per UTC day.
+    pub max_per_day: Option<u64>,
+    /// Operations over the lifetime of the key.
+    pub max_lifetime: Option<u64>,
+}
+
+/// Operations recorded for a key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyUsage {
+    /// Day of the last operation, in days since the Unix epoch.
+    pub day: u32,
+    /// Operations during `day`.
+    pub today: u64,
+    pub lifetime: u64,
+}
+
+impl KeyUsage {
+    // Records `operations` more operations on `day`, unless they exceed
+    // `quota`.
+    fn charge(&mut self, quota: &UsageQuota, day: u32, operations: u64) -> Result<()> {
+        let today = if day == self.day { self.today } else { 0 };
+        let today = today.checked_add(operations);
+        let lifetime = self.lifetime.checked_add(operations);
+        match (today, lifetime) {
+            (Some(today), Some(lifetime))
+                if quota.max_per_day.is_none_or(|max| today <= max)
+                    && quota.max_lifetime.is_none_or(|max| lifetime <= max) =>
+            {
+                *self = KeyUsage {
+                    day,
+                    today,
+                    lifetime,
+                };
+                Ok(())
+            }
+            _ => Err(Error::new(ErrorKind::Vendor(QUOTA_EXCEEDED))),
+        }
+    }
+
+    fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0u8; RECORD_LEN];
+        record[..4].copy_from_slice(&self.day.to_le_bytes());
+        record[4..12].copy_from_slice(&self.today.to_le_bytes());
+        record[12..].copy_from_slice(&self.lifetime.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8]) -> Result<Self> {
+        if record.len() != RECORD_LEN {
+            return Err(Error::new(ErrorKind::CorruptObject));
+        }
+        let mut day = [0u8; 4];
+        let mut today = [0u8; 8];
+        let mut lifetime = [0u8; 8];
+        day.copy_from_slice(&record[..4]);
+        today.copy_from_slice(&record[4..12]);
+        lifetime.copy_from_slice(&record[12..]);
+        Ok(KeyUsage {
+            day: u32::from_le_bytes(day),
+            today: u64::from_le_bytes(today),
+            lifetime: u64::from_le_bytes(lifetime),
+        })
+    }
+}
+
+/// Records `operations` operations with the key `key_id` and returns its
+/// usage including them.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `key_id` is empty or longer than
+///    [`MAX_KEY_ID_LEN`].
+/// 2) `Vendor(QUOTA_EXCEEDED)`: If the operations would exceed `quota`.
+///    Nothing is recorded then.
+/// 3) `CorruptObject`: If the recorded usage is malformed.
+/// 4) Errors from accessing the persistent storage.
+pub fn charge(key_id: &[u8], quota: &UsageQuota, operations: u64) -> Result<KeyUsage> {
+    let object_id = object_id(key_id)?;
+    let flags = DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE;
+    let mut object =
+        match PersistentObject::open(ObjectStorageConstants::Private, &object_id, flags) {
+            Err(e) if e.kind() == ErrorKind::ItemNotFound => PersistentObject::create(
+                ObjectStorageConstants::Private,
+                &object_id,
+                flags,
+                None,
+                &KeyUsage::default().encode(),
+            )?,
+            result => result?,
+        };
+
+    let mut usage = read_usage(&object)?;
+    usage.charge(quota, today(), operations)?;
+    object.seek(0, Whence::DataSeekSet)?;
+    object.write(&usage.encode())?;
+    Ok(usage)
+}
+
+/// Returns the usage recorded for the key `key_id`, all zeros if it was
+/// never charged.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `key_id` is empty or longer than
+///    [`MAX_KEY_ID_LEN`].
+/// 2) `CorruptObject`: If the recorded usage is malformed.
+/// 3) Errors from accessing the persistent storage.
+pub fn usage(key_id: &[u8]) -> Result<KeyUsage> {
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &object_id(key_id)?,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    ) {
+        Ok(object) => read_usage(&object),
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => Ok(KeyUsage::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn object_id(key_id: &[u8]) -> Result<Vec<u8>> {
+    if key_id.is_empty() || key_id.len() > MAX_KEY_ID_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut object_id = OBJECT_ID_PREFIX.to_vec();
+    object_id.extend_from_slice(key_id);
+    Ok(object_id)
+}
+
+fn read_usage(object: &PersistentObject) -> Result<KeyUsage> {
+    let mut record = [0u8; RECORD_LEN + 1];
+    let read = object.read(&mut record)? as usize;
+    KeyUsage::decode(&record[..read])
+}
+
+fn today() -> u32 {
+    let mut now = Time::new();
+    now.system_time();
+    now.seconds / SECONDS_PER_DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charge() {
+        let quota = UsageQuota {
+            max_per_day: Some(2),
+            max_lifetime: Some(3),
+        };
+        let mut usage = KeyUsage::default();
+        assert!(usage.charge(&quota, 10, 2).is_ok());
+        let error = usage.charge(&quota, 10, 1).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Vendor(QUOTA_EXCEEDED));
+
+        // The daily count restarts on a new day, the lifetime one does not.
+        assert!(usage.charge(&quota, 11, 1).is_ok());
+        assert!(usage.charge(&quota, 12, 1).is_err());
+        assert_eq!(
+            usage,
+            KeyUsage {
+                day: 11,
+                today: 1,
+                lifetime: 3,
+            }
+        );
+        assert_eq!(KeyUsage::decode(&usage.encode()).unwrap(), usage);
+    }
+}
@@ -0,0 +1,180 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Implementations of the [RustCrypto](https://github.com/RustCrypto)
+//! traits backed by the TEE, available with the `rustcrypto` feature, so
+//! that generic code written against them runs inside a TA.
+//!
+//! [`TeeDigest`] hashes with a [`Digest`] operation and implements the
+//! traits of the [`digest`] crate, e.g. for the `hmac` crate or a Merkle
+//! tree generic over its hash.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! use optee_utee::rustcrypto::{digest::Digest, TeeSha256};
+//!
+//! let hash = TeeSha256::digest(b"message");
+//! assert_eq!(hash.len(), 32);
+//! ```
+
+use core::marker::PhantomData;
+
+pub use digest;
+use digest::consts::{U104, U128, U136, U144, U28, U32, U48, U64, U72};
+use digest::crypto_common::BlockSizeUser;
+use digest::generic_array::ArrayLength;
+use digest::{FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset, Update};
+
+use crate::{AlgorithmId, Digest, Result};
+
+/// A hash algorithm of the TEE, with its output and block sizes.
+pub trait DigestAlgorithm {
+    /// The algorithm [`Digest`] operations are allocated with.
+    const ALGORITHM: AlgorithmId;
+    /// Size of the hash in bytes.
+    type OutputSize: ArrayLength<u8> + 'static;
+    /// Size of the blocks the algorithm processes in bytes, as HMAC needs.
+    type BlockSize: ArrayLength<u8> + 'static;
+}
+
+macro_rules! digest_algorithm {
+    ($name:ident, $algorithm:ident, $output:ty, $block:ty, $alias:ident) => {
+        #[doc = concat!("[`AlgorithmId::", stringify!($algorithm), "`].")]
+        pub struct $name;
+
+        impl DigestAlgorithm for $name {
+            const ALGORITHM: AlgorithmId = AlgorithmId::$algorithm;
+            type OutputSize = $output;
+            type BlockSize = $block;
+        }
+
+        #[doc = concat!("[`TeeDigest`] computing [`AlgorithmId::", stringify!($algorithm), "`].")]
+        pub type $alias = TeeDigest<$name>;
+    };
+}
+
+digest_algorithm!(Sha224, Sha224, U28, U64, TeeSha224);
+digest_algorithm!(Sha256, Sha256, U32, U64, TeeSha256);
+digest_algorithm!(Sha384, Sha384, U48, U128, TeeSha384);
+digest_algorithm!(Sha512, Sha512, U64, U128, TeeSha512);
+digest_algorithm!(Sha3_224, Sha3_224, U28, U144, TeeSha3_224);
+digest_algorithm!(Sha3_256, Sha3_256, U32, U136, TeeSha3_256);
+digest_algorithm!(Sha3_384, Sha3_384, U48, U104, TeeSha3_384);
+digest_algorithm!(Sha3_512, Sha3_512, U64, U72, TeeSha3_512);
+digest_algorithm!(Sm3, Sm3, U32, U64, TeeSm3);
+
+/// A [`Digest`] operation computing `A`, implementing the traits of the
+/// [`digest`] crate and thereby [`digest::Digest`].
+///
+/// The operation is allocated by [`new`](Self::new), or by
+/// [`Default::default`] as the `digest` traits require, which panics if the
+/// TEE cannot allocate it.
+pub struct TeeDigest<A: DigestAlgorithm> {
+    op: Digest,
+    algorithm: PhantomData<A>,
+}
+
+impl<A: DigestAlgorithm> TeeDigest<A> {
+    /// Allocates the operation.
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the operation.
+    /// 2) `NotSupported`: If the TEE does not implement `A`.
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            op: Digest::allocate(A::ALGORITHM)?,
+            algorithm: PhantomData,
+        })
+    }
+
+    /// Returns the underlying operation.
+    pub fn into_inner(self) -> Digest {
+        self.op
+    }
+}
+
+impl<A: DigestAlgorithm> Default for TeeDigest<A> {
+    fn default() -> Self {
+        Self::new().expect("failed to allocate a digest operation")
+    }
+}
+
+impl<A: DigestAlgorithm> HashMarker for TeeDigest<A> {}
+
+impl<A: DigestAlgorithm> OutputSizeUser for TeeDigest<A> {
+    type OutputSize = A::OutputSize;
+}
+
+impl<A: DigestAlgorithm> BlockSizeUser for TeeDigest<A> {
+    type BlockSize = A::BlockSize;
+}
+
+impl<A: DigestAlgorithm> Update for TeeDigest<A> {
+    fn update(&mut self, data: &[u8]) {
+        self.op.update(data);
+    }
+}
+
+impl<A: DigestAlgorithm> FixedOutput for TeeDigest<A> {
+    fn finalize_into(mut self, out: &mut Output<Self>) {
+        self.finalize_into_reset(out);
+    }
+}
+
+impl<A: DigestAlgorithm> Reset for TeeDigest<A> {
+    fn reset(&mut self) {
+        self.op.reset();
+    }
+}
+
+impl<A: DigestAlgorithm> FixedOutputReset for TeeDigest<A> {
+    // The TEE resets the operation once it produced the hash.
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        // `out` holds exactly the hash of `A`, so it cannot be too short.
+        let len = self
+            .op
+            .do_final(&[], out)
+            .expect("the hash fits its output size");
+        debug_assert_eq!(len, out.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What `hmac::SimpleHmac` requires of its hash.
+    fn hmac_sizes<D: digest::Digest + BlockSizeUser>() -> (usize, usize) {
+        (<D as OutputSizeUser>::output_size(), D::block_size())
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(hmac_sizes::<TeeSha224>(), (28, 64));
+        assert_eq!(hmac_sizes::<TeeSha256>(), (32, 64));
+        assert_eq!(hmac_sizes::<TeeSha384>(), (48, 128));
+        assert_eq!(hmac_sizes::<TeeSha512>(), (64, 128));
+        assert_eq!(hmac_sizes::<TeeSha3_224>(), (28, 144));
+        assert_eq!(hmac_sizes::<TeeSha3_256>(), (32, 136));
+        assert_eq!(hmac_sizes::<TeeSha3_384>(), (48, 104));
+        assert_eq!(hmac_sizes::<TeeSha3_512>(), (64, 72));
+        assert_eq!(hmac_sizes::<TeeSm3>(), (32, 64));
+    }
+}
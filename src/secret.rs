@@ -0,0 +1,138 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Buffers of key material wiped from memory once no longer needed.
+//!
+//! [`SecretBuffer`] holds bytes such as a key read from an object with
+//! [`secret_attribute`](crate::GenericObject::secret_attribute) or derived
+//! by [`hkdf`](crate::hkdf), and overwrites them with zeros when dropped or
+//! truncated, so that they do not linger on the heap of the TA.
+//!
+//! With the `zeroize` feature, [`SecretBuffer`] implements `Zeroize` and
+//! `ZeroizeOnDrop` of the [`zeroize`](https://docs.rs/zeroize) crate, so that
+//! it can be held by types deriving them, and so do the operations and
+//! transient objects holding keys in the TEE, zeroizing them clearing their
+//! state and key material.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+/// Bytes overwritten with zeros when dropped.
+pub struct SecretBuffer(Vec<u8>);
+
+impl SecretBuffer {
+    /// Returns a buffer of `len` zeros, to be filled with a secret.
+    pub fn new(len: usize) -> Self {
+        Self(vec![0u8; len])
+    }
+
+    /// Shortens the buffer to `len` bytes, wiping the bytes removed.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.0.len() {
+            wipe(&mut self.0[len..]);
+            self.0.truncate(len);
+        }
+    }
+}
+
+/// Takes ownership of `bytes`, which are wiped when the buffer is dropped.
+/// Copies left behind by earlier reallocations of the vector are not.
+impl From<Vec<u8>> for SecretBuffer {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Deref for SecretBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8]> for SecretBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+/// Shows the length of the buffer only, for secrets not to end up in logs.
+impl fmt::Debug for SecretBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBuffer({} bytes)", self.0.len())
+    }
+}
+
+impl Drop for SecretBuffer {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::Zeroize for SecretBuffer {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl zeroize::ZeroizeOnDrop for SecretBuffer {}
+
+// Overwrites `buf` with zeros, in a way the compiler does not optimize out.
+pub(crate) fn wipe(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid reference; the volatile write keeps the
+        // compiler from eliding the store to memory about to be freed.
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_keeps_prefix() {
+        let mut secret = SecretBuffer::from(vec![1, 2, 3, 4]);
+        secret.truncate(2);
+        assert_eq!(&secret[..], &[1, 2]);
+        secret.truncate(8);
+        assert_eq!(secret.len(), 2);
+    }
+
+    #[test]
+    fn debug_hides_bytes() {
+        let secret = SecretBuffer::from(vec![0x42; 3]);
+        assert_eq!(format!("{:?}", secret), "SecretBuffer(3 bytes)");
+    }
+}
@@ -0,0 +1,90 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Build manifest of the TA for REE clients, e.g. a pipeline checking that
+//! a deployed TA was built reproducibly from known sources.
+//!
+//! [`CMD_BUILD_INFO`] writes the manifest, as encoded by
+//! [`BuildManifest::encode`], to the memref output at index 0. If index 1
+//! is a memref output, it receives the 32-byte SHA-256 digest of the
+//! manifest.
+
+use super::{ClientAcl, FRAMEWORK_CMD_BASE};
+use crate::build_info::BuildManifest;
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::{Error, ErrorKind, ParamType, Parameters, Result};
+
+/// Returns the build manifest of the TA.
+pub const CMD_BUILD_INFO: u32 = FRAMEWORK_CMD_BASE + 0x500;
+
+/// Serves [`CMD_BUILD_INFO`] to the clients allowed by its access list.
+pub struct BuildInfoService {
+    acl: ClientAcl,
+    manifest: &'static BuildManifest,
+}
+
+impl BuildInfoService {
+    pub fn new(acl: ClientAcl, manifest: &'static BuildManifest) -> Self {
+        Self { acl, manifest }
+    }
+
+    /// Handles `cmd_id` if it is the build info command, for the client of
+    /// the current session. Returns `None` for other commands.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If parameter 0 is not a memref output.
+    /// 2) `AccessDenied`: If the client is not allowed to read the manifest.
+    /// 3) `ShortBuffer`: If the manifest does not fit in parameter 0, or
+    ///    the digest in parameter 1, whose size is then set to the size
+    ///    needed.
+    pub fn handle(&self, cmd_id: u32, params: &mut Parameters) -> Option<Result<()>> {
+        if cmd_id != CMD_BUILD_INFO {
+            return None;
+        }
+        Some(ClientIdentity.get().and_then(|identity| {
+            if self.acl.allows(&identity) {
+                self.build_info(params)
+            } else {
+                Err(Error::new(ErrorKind::AccessDenied))
+            }
+        }))
+    }
+
+    fn build_info(&self, params: &mut Parameters) -> Result<()> {
+        let manifest = self.manifest.encode();
+        let mut out = unsafe { params.0.as_memref()? };
+        if out.buffer().len() < manifest.len() {
+            out.set_updated_size(manifest.len());
+            return Err(Error::new(ErrorKind::ShortBuffer));
+        }
+
+        if let ParamType::MemrefOutput = params.1.param_type {
+            let mut digest_out = unsafe { params.1.as_memref()? };
+            if digest_out.buffer().len() < 32 {
+                digest_out.set_updated_size(32);
+                return Err(Error::new(ErrorKind::ShortBuffer));
+            }
+            digest_out.buffer()[..32].copy_from_slice(&self.manifest.digest()?);
+            digest_out.set_updated_size(32);
+        }
+
+        out.buffer()[..manifest.len()].copy_from_slice(manifest.as_bytes());
+        out.set_updated_size(manifest.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Named monotonic counters, e.g. for anti-rollback protection of REE
+//! components, stored in the TA private storage.
+//!
+//! Both commands take the counter name in a memref input at index 0 and
+//! return the counter value in a value output at index 1, low 32 bits in `a`
+//! and high 32 bits in `b`. A counter that was never incremented reads as 0.
+//!
+//! With [`CounterService::with_per_tenant`] every client tenant gets its own
+//! set of counters, see [`tenant`](crate::tenant).
+
+use super::{ClientAcl, FRAMEWORK_CMD_BASE};
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::tenant::TenantContext;
+use crate::{
+    DataFlag, Error, ErrorKind, Identity, ObjectStorageConstants, Parameters, PersistentObject,
+    Result, Whence,
+};
+
+/// Increments a counter and returns its new value.
+pub const CMD_COUNTER_INCREMENT: u32 = FRAMEWORK_CMD_BASE + 0x100;
+/// Returns the current value of a counter.
+pub const CMD_COUNTER_READ: u32 = FRAMEWORK_CMD_BASE + 0x101;
+
+const OBJECT_ID_PREFIX: &[u8] = b"fw.counter.";
+const MAX_NAME_LEN: usize = 64 - OBJECT_ID_PREFIX.len();
+
+/// Serves [`CMD_COUNTER_INCREMENT`] and [`CMD_COUNTER_READ`] to the clients
+/// allowed by its access lists. Clients that may increment may also read.
+#[derive(Clone, Default)]
+pub struct CounterService {
+    readers: ClientAcl,
+    writers: ClientAcl,
+    per_tenant: bool,
+}
+
+impl CounterService {
+    pub fn new(readers: ClientAcl, writers: ClientAcl) -> Self {
+        Self {
+            readers,
+            writers,
+            per_tenant: false,
+        }
+    }
+
+    /// Keeps separate counters for each client tenant, so that clients of
+    /// different tenants neither see nor move the counters of each other.
+    pub fn with_per_tenant(mut self) -> Self {
+        self.per_tenant = true;
+        self
+    }
+
+    /// Handles `cmd_id` if it is a counter command, for the client of the
+    /// current session. Returns `None` for other commands.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the parameters do not follow the layout of the
+    ///    commands or the counter name is empty or longer than 53 bytes, 27
+    ///    bytes with per-tenant counters.
+    /// 2) `AccessDenied`: If the client is not allowed to run the command.
+    /// 3) `Overflow`: If the counter reached `u64::MAX`.
+    /// 4) Errors from accessing the persistent storage.
+    pub fn handle(&self, cmd_id: u32, params: &mut Parameters) -> Option<Result<()>> {
+        let increment = match cmd_id {
+            CMD_COUNTER_INCREMENT => true,
+            CMD_COUNTER_READ => false,
+            _ => return None,
+        };
+        Some(
+            ClientIdentity
+                .get()
+                .and_then(|identity| self.run(increment, &identity, params)),
+        )
+    }
+
+    fn run(&self, increment: bool, identity: &Identity, params: &mut Parameters) -> Result<()> {
+        self.check_access(increment, identity)?;
+
+        let mut name = unsafe { params.0.as_memref()? };
+        let name = name.buffer();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let mut object_id = OBJECT_ID_PREFIX.to_vec();
+        object_id.extend_from_slice(name);
+        if self.per_tenant {
+            object_id = TenantContext::new(identity).object_id(&object_id)?;
+        }
+
+        let value = if increment {
+            increment_counter(&object_id)?
+        } else {
+            read_counter(&object_id)?
+        };
+
+        let mut out = unsafe { params.1.as_value()? };
+        out.set_a(value as u32);
+        out.set_b((value >> 32) as u32);
+        Ok(())
+    }
+
+    fn check_access(&self, increment: bool, identity: &Identity) -> Result<()> {
+        let allowed =
+            self.writers.allows(identity) || (!increment && self.readers.allows(identity));
+        if allowed {
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::AccessDenied))
+        }
+    }
+}
+
+fn read_counter(object_id: &[u8]) -> Result<u64> {
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        object_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    ) {
+        Ok(object) => read_value(&object),
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+fn increment_counter(object_id: &[u8]) -> Result<u64> {
+    let flags = DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE;
+    let mut object = match PersistentObject::open(ObjectStorageConstants::Private, object_id, flags)
+    {
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => PersistentObject::create(
+            ObjectStorageConstants::Private,
+            object_id,
+            flags,
+            None,
+            &0u64.to_le_bytes(),
+        )?,
+        result => result?,
+    };
+
+    let value = read_value(&object)?
+        .checked_add(1)
+        .ok_or_else(|| Error::new(ErrorKind::Overflow))?;
+    object.seek(0, Whence::DataSeekSet)?;
+    object.write(&value.to_le_bytes())?;
+    Ok(value)
+}
+
+fn read_value(object: &PersistentObject) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    if object.read(&mut buf)? as usize != buf.len() {
+        return Err(Error::new(ErrorKind::CorruptObject));
+    }
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LoginType, Uuid};
+
+    #[test]
+    fn test_check_access() {
+        let ta = Uuid::from_bytes([1; 16]);
+        let service = CounterService::new(
+            ClientAcl::new().allow_login(LoginType::Public),
+            ClientAcl::new().allow_client(LoginType::TrustedApp, ta),
+        );
+
+        let public = Identity::new(LoginType::Public, Uuid::from_bytes([0; 16]));
+        assert!(service.check_access(false, &public).is_ok());
+        assert!(service.check_access(true, &public).is_err());
+
+        let writer = Identity::new(LoginType::TrustedApp, ta);
+        assert!(service.check_access(false, &writer).is_ok());
+        assert!(service.check_access(true, &writer).is_ok());
+
+        let other = Identity::new(LoginType::TrustedApp, Uuid::from_bytes([2; 16]));
+        assert!(service.check_access(false, &other).is_err());
+    }
+}
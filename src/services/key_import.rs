@@ -0,0 +1,343 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Import of keys from REE clients into the TA private storage, accepted
+//! only with a proof that the client holds the key.
+//!
+//! A client first asks for a challenge with [`CMD_KEY_IMPORT_CHALLENGE`],
+//! passing the key name in a memref input at index 0. The TA writes 32
+//! random bytes to the memref output at index 1. The client then sends
+//! [`CMD_KEY_IMPORT`] with:
+//!
+//! - the key name in a memref input at index 0,
+//! - the [`KeyType`] in `a` of a value input at index 1,
+//! - the key material in a memref input at index 2,
+//! - the proof, computed with the key over the challenge, in a memref input
+//!   at index 3.
+//!
+//! The key is stored only if the proof verifies, so that a truncated,
+//! garbled or mismatched key never lands in the keystore. A challenge is
+//! bound to the client identity and key name it was issued for, expires
+//! after a while and is consumed by the first import attempt, whatever its
+//! outcome.
+
+use alloc::vec::Vec;
+
+use super::{ClientAcl, FRAMEWORK_CMD_BASE};
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::{
+    AlgorithmId, Asymmetric, AttributeId, AttributeMemref, AttributeValue, DataFlag, Digest,
+    ElementId, Error, ErrorKind, Identity, LoginType, Mac, ObjectStorageConstants, OperationMode,
+    Parameters, PersistentObject, Random, Result, Time, TransientObject, TransientObjectType, Uuid,
+};
+
+/// Issues a challenge for the import of a key.
+pub const CMD_KEY_IMPORT_CHALLENGE: u32 = FRAMEWORK_CMD_BASE + 0x400;
+/// Imports a key, given a proof of possession over its challenge.
+pub const CMD_KEY_IMPORT: u32 = FRAMEWORK_CMD_BASE + 0x401;
+
+/// Size of a challenge, in bytes.
+pub const CHALLENGE_LEN: usize = 32;
+
+const OBJECT_ID_PREFIX: &[u8] = b"fw.key.";
+const MAX_NAME_LEN: usize = 64 - OBJECT_ID_PREFIX.len();
+const DEFAULT_CHALLENGE_TTL_MS: u64 = 60_000;
+// Challenges kept at most, the oldest being dropped past that.
+const MAX_PENDING: usize = 16;
+// Bounds of the HMAC-SHA256 key sizes accepted by the TEE, in bytes.
+const MIN_HMAC_KEY_LEN: usize = 24;
+const MAX_HMAC_KEY_LEN: usize = 128;
+const P256_LEN: usize = 32;
+
+/// Types of the keys that can be imported, passed in `a` of parameter 1 of
+/// [`CMD_KEY_IMPORT`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum KeyType {
+    /// HMAC-SHA256 secret, given as its raw bytes. The proof is the
+    /// HMAC-SHA256 of the challenge.
+    HmacSha256 = 1,
+    /// ECDSA key pair on P-256, given as `d || x || y`, 32 bytes each. The
+    /// proof is the raw `r || s` signature of the SHA-256 digest of the
+    /// challenge.
+    EcdsaP256 = 2,
+}
+
+impl KeyType {
+    fn from_raw(raw: u32) -> Result<Self> {
+        match raw {
+            1 => Ok(KeyType::HmacSha256),
+            2 => Ok(KeyType::EcdsaP256),
+            _ => Err(Error::new(ErrorKind::NotSupported)),
+        }
+    }
+}
+
+// Challenge issued to a client for the import of a key.
+struct Challenge {
+    login_type: LoginType,
+    uuid: Uuid,
+    name: Vec<u8>,
+    nonce: [u8; CHALLENGE_LEN],
+    issued: u64,
+}
+
+/// Serves [`CMD_KEY_IMPORT_CHALLENGE`] and [`CMD_KEY_IMPORT`] to the clients
+/// allowed by its access list.
+pub struct KeyImportService {
+    acl: ClientAcl,
+    ttl_ms: u64,
+    challenges: Vec<Challenge>,
+}
+
+impl KeyImportService {
+    pub fn new(acl: ClientAcl) -> Self {
+        Self {
+            acl,
+            ttl_ms: DEFAULT_CHALLENGE_TTL_MS,
+            challenges: Vec::new(),
+        }
+    }
+
+    /// Sets how long a challenge stays valid, 60 seconds by default.
+    pub fn with_challenge_ttl(mut self, ttl_ms: u64) -> Self {
+        self.ttl_ms = ttl_ms;
+        self
+    }
+
+    /// Opens the key imported under `name` for reading.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If no key was imported under `name`.
+    /// 2) Errors from accessing the persistent storage.
+    pub fn open_key(name: &[u8]) -> Result<PersistentObject> {
+        PersistentObject::open(
+            ObjectStorageConstants::Private,
+            &object_id(name)?,
+            DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+        )
+    }
+
+    /// Handles `cmd_id` if it is a key import command, for the client of the
+    /// current session. Returns `None` for other commands.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the parameters do not follow the layout of the
+    ///    commands, the key name is empty or longer than 57 bytes, or the key
+    ///    material has the wrong size for its type.
+    /// 2) `AccessDenied`: If the client is not allowed to import keys.
+    /// 3) `ShortBuffer`: If the challenge output holds less than
+    ///    [`CHALLENGE_LEN`] bytes.
+    /// 4) `NotSupported`: If the key type is unknown.
+    /// 5) `ItemNotFound`: If no valid challenge was issued to the client for
+    ///    this key name.
+    /// 6) `MacInvalid` or `SignatureInvalid`: If the proof does not verify
+    ///    with the key.
+    /// 7) `AccessConflict`: If a key was already imported under this name.
+    /// 8) Errors from accessing the persistent storage.
+    pub fn handle(&mut self, cmd_id: u32, params: &mut Parameters) -> Option<Result<()>> {
+        let import = match cmd_id {
+            CMD_KEY_IMPORT_CHALLENGE => false,
+            CMD_KEY_IMPORT => true,
+            _ => return None,
+        };
+        Some(ClientIdentity.get().and_then(|identity| {
+            if !self.acl.allows(&identity) {
+                return Err(Error::new(ErrorKind::AccessDenied));
+            }
+            if import {
+                self.import(&identity, params)
+            } else {
+                self.challenge(&identity, params)
+            }
+        }))
+    }
+
+    fn challenge(&mut self, identity: &Identity, params: &mut Parameters) -> Result<()> {
+        let mut name = unsafe { params.0.as_memref()? };
+        let name = name.buffer();
+        object_id(name)?;
+        let mut out = unsafe { params.1.as_memref()? };
+        if out.buffer().len() < CHALLENGE_LEN {
+            out.set_updated_size(CHALLENGE_LEN);
+            return Err(Error::new(ErrorKind::ShortBuffer));
+        }
+
+        let mut nonce = [0u8; CHALLENGE_LEN];
+        Random::generate(&mut nonce);
+        self.issue(identity, name, nonce, now_ms());
+
+        out.buffer()[..CHALLENGE_LEN].copy_from_slice(&nonce);
+        out.set_updated_size(CHALLENGE_LEN);
+        Ok(())
+    }
+
+    fn import(&mut self, identity: &Identity, params: &mut Parameters) -> Result<()> {
+        let mut name = unsafe { params.0.as_memref()? };
+        let name = name.buffer();
+        let object_id = object_id(name)?;
+        let key_type = unsafe { params.1.as_value()? }.a();
+        let mut material = unsafe { params.2.as_memref()? };
+        let mut proof = unsafe { params.3.as_memref()? };
+
+        // The challenge goes whatever the outcome, so that a client cannot
+        // try several keys against the same one.
+        let nonce = self.take(identity, name, now_ms())?;
+        let key = match KeyType::from_raw(key_type)? {
+            KeyType::HmacSha256 => verify_hmac(material.buffer(), &nonce, proof.buffer())?,
+            KeyType::EcdsaP256 => verify_ecdsa(material.buffer(), &nonce, proof.buffer())?,
+        };
+
+        PersistentObject::create(
+            ObjectStorageConstants::Private,
+            &object_id,
+            DataFlag::ACCESS_READ,
+            Some(key.into()),
+            &[],
+        )?;
+        Ok(())
+    }
+
+    // Records a challenge for `name`, replacing any previous one of the same
+    // client for that name.
+    fn issue(&mut self, identity: &Identity, name: &[u8], nonce: [u8; CHALLENGE_LEN], now: u64) {
+        let (login_type, uuid) = (identity.login_type(), identity.uuid());
+        let ttl_ms = self.ttl_ms;
+        self.challenges.retain(|c| {
+            now.saturating_sub(c.issued) < ttl_ms
+                && !(c.login_type == login_type && c.uuid == uuid && c.name == name)
+        });
+        if self.challenges.len() >= MAX_PENDING {
+            self.challenges.remove(0);
+        }
+        self.challenges.push(Challenge {
+            login_type,
+            uuid,
+            name: name.to_vec(),
+            nonce,
+            issued: now,
+        });
+    }
+
+    // Removes and returns the challenge of the client for `name`, if it did
+    // not expire.
+    fn take(&mut self, identity: &Identity, name: &[u8], now: u64) -> Result<[u8; CHALLENGE_LEN]> {
+        let (login_type, uuid) = (identity.login_type(), identity.uuid());
+        let index = self
+            .challenges
+            .iter()
+            .position(|c| c.login_type == login_type && c.uuid == uuid && c.name == name)
+            .ok_or_else(|| Error::new(ErrorKind::ItemNotFound))?;
+        let challenge = self.challenges.remove(index);
+        if now.saturating_sub(challenge.issued) >= self.ttl_ms {
+            return Err(Error::new(ErrorKind::ItemNotFound));
+        }
+        Ok(challenge.nonce)
+    }
+}
+
+fn object_id(name: &[u8]) -> Result<Vec<u8>> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut object_id = OBJECT_ID_PREFIX.to_vec();
+    object_id.extend_from_slice(name);
+    Ok(object_id)
+}
+
+fn now_ms() -> u64 {
+    let mut now = Time::new();
+    now.system_time();
+    now.seconds as u64 * 1000 + now.millis as u64
+}
+
+fn verify_hmac(secret: &[u8], nonce: &[u8], proof: &[u8]) -> Result<TransientObject> {
+    if !(MIN_HMAC_KEY_LEN..=MAX_HMAC_KEY_LEN).contains(&secret.len()) {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let bits = secret.len() * 8;
+    let mut key = TransientObject::allocate(TransientObjectType::HmacSha256, bits)?;
+    key.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, secret).into()])?;
+
+    let mac = Mac::allocate(AlgorithmId::HmacSha256, bits)?;
+    mac.set_key(&key)?;
+    mac.init(&[]);
+    mac.compare_final(nonce, proof)?;
+    Ok(key)
+}
+
+fn verify_ecdsa(material: &[u8], nonce: &[u8], proof: &[u8]) -> Result<TransientObject> {
+    if material.len() != 3 * P256_LEN || proof.len() != 2 * P256_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let (d, xy) = material.split_at(P256_LEN);
+    let (x, y) = xy.split_at(P256_LEN);
+    let mut key = TransientObject::allocate(TransientObjectType::EcdsaKeypair, 256)?;
+    key.populate(&[
+        AttributeMemref::from_ref(AttributeId::EccPrivateValue, d).into(),
+        AttributeMemref::from_ref(AttributeId::EccPublicValueX, x).into(),
+        AttributeMemref::from_ref(AttributeId::EccPublicValueY, y).into(),
+        AttributeValue::from_value(AttributeId::EccCurve, ElementId::EccCurveNistP256 as u32, 0)
+            .into(),
+    ])?;
+
+    let mut digest = [0u8; 32];
+    Digest::allocate(AlgorithmId::Sha256)?.do_final(nonce, &mut digest)?;
+    let op = Asymmetric::allocate(AlgorithmId::EcDsaSha256, OperationMode::Verify, 256)?;
+    op.set_key(&key)?;
+    op.verify_digest(&[], &digest, proof)?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_bookkeeping() {
+        let mut service = KeyImportService::new(ClientAcl::new().allow_login(LoginType::Public))
+            .with_challenge_ttl(1000);
+        let a = Identity::new(LoginType::Public, Uuid::from_bytes([1; 16]));
+        let b = Identity::new(LoginType::Public, Uuid::from_bytes([2; 16]));
+
+        service.issue(&a, b"k", [1; CHALLENGE_LEN], 0);
+        // Bound to the client and the key name.
+        assert!(service.take(&b, b"k", 10).is_err());
+        assert!(service.take(&a, b"other", 10).is_err());
+        assert_eq!(service.take(&a, b"k", 10).unwrap(), [1; CHALLENGE_LEN]);
+        // Single use.
+        assert!(service.take(&a, b"k", 20).is_err());
+
+        // A new challenge replaces the previous one.
+        service.issue(&a, b"k", [2; CHALLENGE_LEN], 100);
+        service.issue(&a, b"k", [3; CHALLENGE_LEN], 200);
+        assert_eq!(service.take(&a, b"k", 300).unwrap(), [3; CHALLENGE_LEN]);
+        assert!(service.take(&a, b"k", 300).is_err());
+
+        service.issue(&a, b"k", [4; CHALLENGE_LEN], 1000);
+        assert!(service.take(&a, b"k", 2000).is_err());
+    }
+
+    #[test]
+    fn test_object_id() {
+        assert_eq!(object_id(b"k").unwrap(), b"fw.key.k");
+        assert!(object_id(b"").is_err());
+        assert!(object_id(&[b'k'; MAX_NAME_LEN + 1]).is_err());
+    }
+}
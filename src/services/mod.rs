@@ -0,0 +1,94 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Framework command sets that any TA built with this crate can expose to
+//! the REE next to its own commands.
+//!
+//! Framework commands use ids from [`FRAMEWORK_CMD_BASE`] upwards, which TAs
+//! must not use for their own commands. Each service returns `None` from its
+//! `handle` method for ids it does not own, so a TA tries the services it
+//! enables before its own dispatch:
+//!
+//! ``` rust,no_run
+//! # use optee_utee::{ErrorKind, Parameters, Result};
+//! # struct Service;
+//! # impl Service {
+//! #     fn handle(&self, _: u32, _: &mut Parameters) -> Option<Result<()>> { None }
+//! # }
+//! # static COUNTERS: Service = Service;
+//! fn invoke_command(cmd_id: u32, params: &mut Parameters) -> Result<()> {
+//!     if let Some(result) = COUNTERS.handle(cmd_id, params) {
+//!         return result;
+//!     }
+//!     match cmd_id {
+//!         // ...
+//!         _ => Err(ErrorKind::BadParameters.into()),
+//!     }
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{Identity, LoginType, Uuid};
+
+#[cfg(feature = "build_info_service")]
+pub mod build_info;
+#[cfg(feature = "counter_service")]
+pub mod counters;
+#[cfg(feature = "key_import_service")]
+pub mod key_import;
+#[cfg(feature = "random_service")]
+pub mod random;
+#[cfg(feature = "selftest_service")]
+pub mod selftest;
+
+/// First command id reserved for framework commands.
+pub const FRAMEWORK_CMD_BASE: u32 = 0xFFFF_0000;
+
+/// Clients allowed to use a framework service, matched on the client
+/// identity of the session.
+#[derive(Clone, Default)]
+pub struct ClientAcl {
+    rules: Vec<(LoginType, Option<Uuid>)>,
+}
+
+impl ClientAcl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows every client logged in with `login_type`.
+    pub fn allow_login(mut self, login_type: LoginType) -> Self {
+        self.rules.push((login_type, None));
+        self
+    }
+
+    /// Allows the client identified by `uuid` logged in with `login_type`,
+    /// e.g. a given TA with [`LoginType::TrustedApp`].
+    pub fn allow_client(mut self, login_type: LoginType, uuid: Uuid) -> Self {
+        self.rules.push((login_type, Some(uuid)));
+        self
+    }
+
+    /// Returns `true` if `identity` matches one of the rules.
+    pub fn allows(&self, identity: &Identity) -> bool {
+        let login_type = identity.login_type();
+        self.rules.iter().any(|(login, uuid)| {
+            *login == login_type && uuid.is_none_or(|uuid| uuid == identity.uuid())
+        })
+    }
+}
@@ -0,0 +1,166 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Random bytes from the TEE generator for REE clients.
+//!
+//! [`CMD_RANDOM_GENERATE`] fills the memref output at index 0 with random
+//! bytes and sets its size to the number of bytes written.
+
+use alloc::vec::Vec;
+
+use super::{ClientAcl, FRAMEWORK_CMD_BASE};
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::{Error, ErrorKind, Identity, LoginType, Parameters, Random, Result, Time, Uuid};
+
+/// Fills a buffer with random bytes.
+pub const CMD_RANDOM_GENERATE: u32 = FRAMEWORK_CMD_BASE + 0x200;
+
+/// Number of bytes a single client may draw per period.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub max_bytes: u64,
+    pub period_ms: u64,
+}
+
+// Bytes drawn by a client in its current period.
+struct Usage {
+    login_type: LoginType,
+    uuid: Uuid,
+    period_start: u64,
+    bytes: u64,
+}
+
+/// Serves [`CMD_RANDOM_GENERATE`] to the clients allowed by its access list,
+/// optionally limiting how many bytes each client identity may draw.
+pub struct RandomService {
+    acl: ClientAcl,
+    limit: Option<RateLimit>,
+    usage: Vec<Usage>,
+}
+
+impl RandomService {
+    pub fn new(acl: ClientAcl) -> Self {
+        Self {
+            acl,
+            limit: None,
+            usage: Vec::new(),
+        }
+    }
+
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Handles `cmd_id` if it is a random command, for the client of the
+    /// current session. Returns `None` for other commands.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If parameter 0 is not a memref output.
+    /// 2) `AccessDenied`: If the client is not allowed to draw random bytes.
+    /// 3) `Busy`: If the request exceeds the rate limit of the client.
+    pub fn handle(&mut self, cmd_id: u32, params: &mut Parameters) -> Option<Result<()>> {
+        if cmd_id != CMD_RANDOM_GENERATE {
+            return None;
+        }
+        Some(
+            ClientIdentity
+                .get()
+                .and_then(|identity| self.generate(&identity, params)),
+        )
+    }
+
+    fn generate(&mut self, identity: &Identity, params: &mut Parameters) -> Result<()> {
+        if !self.acl.allows(identity) {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+
+        let mut out = unsafe { params.0.as_memref()? };
+        let len = out.buffer().len();
+        let mut now = Time::new();
+        now.system_time();
+        self.charge(
+            identity,
+            len as u64,
+            now.seconds as u64 * 1000 + now.millis as u64,
+        )?;
+
+        Random::generate(out.buffer());
+        out.set_updated_size(len);
+        Ok(())
+    }
+
+    // Accounts `bytes` drawn by `identity` at `now`, in milliseconds.
+    fn charge(&mut self, identity: &Identity, bytes: u64, now: u64) -> Result<()> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        // Forget clients whose period is over.
+        self.usage
+            .retain(|usage| now.saturating_sub(usage.period_start) < limit.period_ms);
+
+        let (login_type, uuid) = (identity.login_type(), identity.uuid());
+        let index = match self
+            .usage
+            .iter()
+            .position(|usage| usage.login_type == login_type && usage.uuid == uuid)
+        {
+            Some(index) => index,
+            None => {
+                self.usage.push(Usage {
+                    login_type,
+                    uuid,
+                    period_start: now,
+                    bytes: 0,
+                });
+                self.usage.len() - 1
+            }
+        };
+
+        let usage = &mut self.usage[index];
+        if usage.bytes.saturating_add(bytes) > limit.max_bytes {
+            return Err(Error::new(ErrorKind::Busy));
+        }
+        usage.bytes += bytes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut service = RandomService::new(ClientAcl::new().allow_login(LoginType::Public))
+            .with_rate_limit(RateLimit {
+                max_bytes: 64,
+                period_ms: 1000,
+            });
+        let a = Identity::new(LoginType::Public, Uuid::from_bytes([1; 16]));
+        let b = Identity::new(LoginType::Public, Uuid::from_bytes([2; 16]));
+
+        assert!(service.charge(&a, 48, 0).is_ok());
+        assert!(service.charge(&a, 32, 10).is_err());
+        assert!(service.charge(&a, 16, 20).is_ok());
+        assert!(service.charge(&b, 64, 30).is_ok());
+        // A new period starts once the previous one is over.
+        assert!(service.charge(&a, 64, 1000).is_ok());
+    }
+}
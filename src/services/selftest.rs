@@ -0,0 +1,330 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! A subset of the GlobalPlatform TEE Internal Core API conformance checks,
+//! run against the live TEE to qualify new TEE firmware quickly.
+//!
+//! [`CMD_SELFTEST_RUN`] runs every check and returns in a value output at
+//! index 0 the number of checks that passed in `a` and failed in `b`. If
+//! index 1 is a memref output, it receives a report with one line per check,
+//! `ok <name>` or `FAILED <name>: <reason>`. If index 2 is a value inout,
+//! its fields are returned bitwise inverted, for the CA to check that value
+//! parameters are copied back.
+//!
+//! The storage checks create and delete an object with the id
+//! `fw.selftest.storage` in the TA private storage.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use super::{ClientAcl, FRAMEWORK_CMD_BASE};
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::{
+    AlgorithmId, AttributeId, AttributeMemref, Cipher, DataFlag, Digest, Error, ErrorKind, Mac,
+    ObjectStorageConstants, OperationMode, ParamType, Parameters, PersistentObject, Random, Result,
+    Time, TransientObject, TransientObjectType,
+};
+
+/// Runs the self-test checks.
+pub const CMD_SELFTEST_RUN: u32 = FRAMEWORK_CMD_BASE + 0x300;
+
+const STORAGE_OBJECT_ID: &[u8] = b"fw.selftest.storage";
+
+// SHA-256("abc"), FIPS 180-2 appendix B.1.
+const SHA256_ABC: [u8; 32] = [
+    0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae, 0x22, 0x23,
+    0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61, 0xf2, 0x00, 0x15, 0xad,
+];
+
+// HMAC-SHA-256 of 50 bytes 0xcd keyed with 0x01..=0x19, RFC 4231 test case 4.
+const HMAC_SHA256_TC4: [u8; 32] = [
+    0x82, 0x55, 0x8a, 0x38, 0x9a, 0x44, 0x3c, 0x0e, 0xa4, 0xcc, 0x81, 0x98, 0x99, 0xf2, 0x08, 0x3a,
+    0x85, 0xf0, 0xfa, 0xa3, 0xe5, 0x78, 0xf8, 0x07, 0x7a, 0x2e, 0x3f, 0xf4, 0x67, 0x29, 0x66, 0x5b,
+];
+
+// AES-128 encryption of 00112233..ff under 00010203..0f, FIPS 197 appendix C.1.
+const AES128_CIPHERTEXT: [u8; 16] = [
+    0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a,
+];
+
+/// Why a check failed.
+#[derive(Debug)]
+pub enum Failure {
+    /// The TEE returned an error.
+    Tee(Error),
+    /// The TEE returned something else than the expected value.
+    Mismatch(&'static str),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure::Tee(e)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Tee(e) => write!(f, "{}", e),
+            Failure::Mismatch(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+type Check = fn() -> core::result::Result<(), Failure>;
+
+const CHECKS: [(&str, Check); 8] = [
+    ("storage.create_read", check_storage_create_read),
+    ("storage.exclusive_create", check_storage_exclusive_create),
+    ("storage.delete", check_storage_delete),
+    ("crypto.sha256", check_sha256),
+    ("crypto.hmac_sha256", check_hmac_sha256),
+    ("crypto.aes128_ecb", check_aes128_ecb),
+    ("random.distinct", check_random),
+    ("time.monotonic", check_system_time),
+];
+
+/// Serves [`CMD_SELFTEST_RUN`] to the clients allowed by its access list.
+#[derive(Clone, Default)]
+pub struct SelfTestService {
+    acl: ClientAcl,
+}
+
+impl SelfTestService {
+    pub fn new(acl: ClientAcl) -> Self {
+        Self { acl }
+    }
+
+    /// Handles `cmd_id` if it is the self-test command, for the client of
+    /// the current session. Returns `None` for other commands.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If parameter 0 is not a value output.
+    /// 2) `AccessDenied`: If the client is not allowed to run the self-test.
+    /// 3) `ShortBuffer`: If the report does not fit in parameter 1, whose
+    ///    size is then set to the size of the report.
+    pub fn handle(&self, cmd_id: u32, params: &mut Parameters) -> Option<Result<()>> {
+        if cmd_id != CMD_SELFTEST_RUN {
+            return None;
+        }
+        Some(ClientIdentity.get().and_then(|identity| {
+            if self.acl.allows(&identity) {
+                run(params)
+            } else {
+                Err(Error::new(ErrorKind::AccessDenied))
+            }
+        }))
+    }
+}
+
+/// Runs every check and returns the name and outcome of each.
+pub fn run_checks() -> Vec<(&'static str, core::result::Result<(), Failure>)> {
+    CHECKS
+        .iter()
+        .map(|(name, check)| (*name, check()))
+        .collect()
+}
+
+fn run(params: &mut Parameters) -> Result<()> {
+    let results = run_checks();
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+    let mut counts = unsafe { params.0.as_value()? };
+    counts.set_a((results.len() - failed) as u32);
+    counts.set_b(failed as u32);
+
+    if let ParamType::ValueInout = params.2.param_type {
+        let mut echo = unsafe { params.2.as_value()? };
+        let (a, b) = (echo.a(), echo.b());
+        echo.set_a(!a);
+        echo.set_b(!b);
+    }
+
+    if let ParamType::MemrefOutput | ParamType::MemrefInout = params.1.param_type {
+        let report = report(&results);
+        let mut out = unsafe { params.1.as_memref()? };
+        if out.buffer().len() < report.len() {
+            out.set_updated_size(report.len());
+            return Err(Error::new(ErrorKind::ShortBuffer));
+        }
+        out.buffer()[..report.len()].copy_from_slice(report.as_bytes());
+        out.set_updated_size(report.len());
+    }
+    Ok(())
+}
+
+fn report(results: &[(&'static str, core::result::Result<(), Failure>)]) -> String {
+    results
+        .iter()
+        .map(|(name, result)| match result {
+            Ok(()) => format!("ok {}\n", name),
+            Err(failure) => format!("FAILED {}: {}\n", name, failure),
+        })
+        .collect()
+}
+
+fn expect(condition: bool, what: &'static str) -> core::result::Result<(), Failure> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Failure::Mismatch(what))
+    }
+}
+
+fn storage_flags() -> DataFlag {
+    DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE | DataFlag::ACCESS_WRITE_META
+}
+
+fn create_test_object(data: &[u8]) -> Result<PersistentObject> {
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        STORAGE_OBJECT_ID,
+        storage_flags() | DataFlag::OVERWRITE,
+        None,
+        data,
+    )
+}
+
+// Data written at creation reads back unchanged.
+fn check_storage_create_read() -> core::result::Result<(), Failure> {
+    let object = create_test_object(b"selftest")?;
+    let mut buf = [0u8; 16];
+    let read = object.read(&mut buf)? as usize;
+    object.close_and_delete()?;
+    expect(&buf[..read] == b"selftest", "read back other data")
+}
+
+// Creating an existing object without OVERWRITE fails with AccessConflict.
+fn check_storage_exclusive_create() -> core::result::Result<(), Failure> {
+    drop(create_test_object(b"")?);
+    let result = PersistentObject::create(
+        ObjectStorageConstants::Private,
+        STORAGE_OBJECT_ID,
+        storage_flags(),
+        None,
+        b"",
+    );
+    let outcome = match result {
+        Err(e) if e.kind() == ErrorKind::AccessConflict => Ok(()),
+        Err(e) => Err(Failure::Tee(e)),
+        Ok(object) => {
+            drop(object);
+            Err(Failure::Mismatch("created an existing object"))
+        }
+    };
+    PersistentObject::open(
+        ObjectStorageConstants::Private,
+        STORAGE_OBJECT_ID,
+        storage_flags(),
+    )?
+    .close_and_delete()?;
+    outcome
+}
+
+// A deleted object can no longer be opened.
+fn check_storage_delete() -> core::result::Result<(), Failure> {
+    create_test_object(b"")?.close_and_delete()?;
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        STORAGE_OBJECT_ID,
+        DataFlag::ACCESS_READ,
+    ) {
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => Ok(()),
+        Err(e) => Err(Failure::Tee(e)),
+        Ok(_) => Err(Failure::Mismatch("opened a deleted object")),
+    }
+}
+
+fn check_sha256() -> core::result::Result<(), Failure> {
+    let digest = Digest::allocate(AlgorithmId::Sha256)?;
+    let mut hash = [0u8; 32];
+    let len = digest.do_final(b"abc", &mut hash)?;
+    expect(len == hash.len() && hash == SHA256_ABC, "wrong digest")
+}
+
+fn check_hmac_sha256() -> core::result::Result<(), Failure> {
+    let key: Vec<u8> = (1..=25).collect();
+    let mut object = TransientObject::allocate(TransientObjectType::HmacSha256, key.len() * 8)?;
+    object.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &key).into()])?;
+    let mac = Mac::allocate(AlgorithmId::HmacSha256, key.len() * 8)?;
+    mac.set_key(&object)?;
+    mac.init(&[]);
+    let mut out = [0u8; 32];
+    let len = mac.compute_final(&[0xcd; 50], &mut out)?;
+    expect(len == out.len() && out == HMAC_SHA256_TC4, "wrong MAC")
+}
+
+fn check_aes128_ecb() -> core::result::Result<(), Failure> {
+    let key: Vec<u8> = (0..16).collect();
+    let plaintext: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+    let mut object = TransientObject::allocate(TransientObjectType::Aes, 128)?;
+    object.populate(&[AttributeMemref::from_ref(AttributeId::SecretValue, &key).into()])?;
+    let cipher = Cipher::allocate(AlgorithmId::AesEcbNopad, OperationMode::Encrypt, 128)?;
+    cipher.set_key(&object)?;
+    cipher.init(&[]);
+    let mut out = [0u8; 16];
+    let len = cipher.do_final(&plaintext, &mut out)?;
+    expect(
+        len == out.len() && out == AES128_CIPHERTEXT,
+        "wrong ciphertext",
+    )
+}
+
+// Two draws of random bytes differ and are not all zeros.
+fn check_random() -> core::result::Result<(), Failure> {
+    let mut first = [0u8; 32];
+    let mut second = [0u8; 32];
+    Random::generate(&mut first);
+    Random::generate(&mut second);
+    expect(first != second, "repeated random bytes")?;
+    expect(first.iter().any(|b| *b != 0), "all-zero random bytes")
+}
+
+// System time never goes backwards.
+fn check_system_time() -> core::result::Result<(), Failure> {
+    let mut earlier = Time::new();
+    earlier.system_time();
+    let mut later = Time::new();
+    later.system_time();
+    expect(
+        (later.seconds, later.millis) >= (earlier.seconds, earlier.millis),
+        "system time went backwards",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let results = [
+            ("crypto.sha256", Ok(())),
+            (
+                "random.distinct",
+                Err(Failure::Mismatch("repeated random bytes")),
+            ),
+        ];
+        assert_eq!(
+            report(&results),
+            "ok crypto.sha256\nFAILED random.distinct: repeated random bytes\n"
+        );
+    }
+}
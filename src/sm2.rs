@@ -0,0 +1,398 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! SM2 signatures, encryption and key exchange (GB/T 32918), over the
+//! [`AlgorithmId::Sm2DsaSm3`], [`AlgorithmId::Sm2Pke`] and
+//! [`AlgorithmId::Sm2Kep`] operations of the TEE.
+//!
+//! The TEE signs and verifies the hash `e = SM3(Z || M)` rather than the
+//! message `M`, where `Z` binds the identifier of the signer and its public
+//! key. The functions here compute it from the identifier, usually
+//! [`DEFAULT_ID`], so that signatures interoperate with other SM2
+//! implementations.
+//!
+//! OP-TEE only provides SM2 when built with it. Every function checks for it
+//! first and fails with `NotSupported` rather than reaching a TEE call that
+//! would panic the TA.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::sm2::{self, Sm2DecryptionKey, Sm2SigningKey, DEFAULT_ID};
+//! # fn main() -> optee_utee::Result<()> {
+//! let signing = Sm2SigningKey::generate()?;
+//! let signature = signing.sign(DEFAULT_ID, b"message")?;
+//! sm2::sm2_verify(&signing.public_key()?, DEFAULT_ID, b"message", &signature)?;
+//!
+//! let decryption = Sm2DecryptionKey::generate()?;
+//! let ciphertext = sm2::sm2_encrypt(&decryption.public_key()?, b"secret")?;
+//! assert_eq!(decryption.decrypt(&ciphertext)?, b"secret");
+//! # Ok(())
+//! # }
+//! ```
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::platform::{self, Capability};
+use crate::{
+    is_algorithm_supported, AlgorithmId, Asymmetric, AttributeId, AttributeMemref, AttributeValue,
+    DeriveKey, Digest, ElementId, Error, ErrorKind, GenericObject, OperationMode, Result,
+    TransientObject, TransientObjectType,
+};
+
+/// Size in bits of SM2 keys, as objects and operations are allocated with.
+pub const KEY_SIZE: usize = 256;
+/// Size in bytes of a coordinate of an SM2 public key.
+pub const COORDINATE_LEN: usize = 32;
+/// Size in bytes of SM2 signatures, `r || s`.
+pub const SIGNATURE_LEN: usize = 64;
+/// Size in bytes of SM3 hashes.
+pub const DIGEST_LEN: usize = 32;
+/// Identifier GM/T 0009 sets for signers that have no other.
+pub const DEFAULT_ID: &[u8] = b"1234567812345678";
+
+// Coefficients and base point of the curve, which `Z` hashes.
+const CURVE_A: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfc,
+];
+const CURVE_B: [u8; 32] = [
+    0x28, 0xe9, 0xfa, 0x9e, 0x9d, 0x9f, 0x5e, 0x34, 0x4d, 0x5a, 0x9e, 0x4b, 0xcf, 0x65, 0x09, 0xa7,
+    0xf3, 0x97, 0x89, 0xf5, 0x15, 0xab, 0x8f, 0x92, 0xdd, 0xbc, 0xbd, 0x41, 0x4d, 0x94, 0x0e, 0x93,
+];
+const BASE_X: [u8; 32] = [
+    0x32, 0xc4, 0xae, 0x2c, 0x1f, 0x19, 0x81, 0x19, 0x5f, 0x99, 0x04, 0x46, 0x6a, 0x39, 0xc9, 0x94,
+    0x8f, 0xe3, 0x0b, 0xbf, 0xf2, 0x66, 0x0b, 0xe1, 0x71, 0x5a, 0x45, 0x89, 0x33, 0x4c, 0x74, 0xc7,
+];
+const BASE_Y: [u8; 32] = [
+    0xbc, 0x37, 0x36, 0xa2, 0xf4, 0xf6, 0x77, 0x9c, 0x59, 0xbd, 0xce, 0xe3, 0x6b, 0x69, 0x21, 0x53,
+    0xd0, 0xa9, 0x87, 0x7c, 0xc6, 0x2a, 0x47, 0x40, 0x02, 0xdf, 0x32, 0xe5, 0x21, 0x39, 0xf0, 0xa0,
+];
+
+/// An SM2 public key, as the affine coordinates of its point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sm2PublicKey {
+    pub x: [u8; COORDINATE_LEN],
+    pub y: [u8; COORDINATE_LEN],
+}
+
+/// Checks that the TEE implements `algorithm` on the SM2 curve.
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the platform lacks the SM2 curve or the TEE does not
+///    implement `algorithm` on it.
+pub fn ensure_supported(algorithm: AlgorithmId) -> Result<()> {
+    if !platform::current().supports(Capability::EccCurve(ElementId::EccCurveSm2)) {
+        return Err(Error::new(ErrorKind::NotSupported));
+    }
+    is_algorithm_supported(algorithm as u32, ElementId::EccCurveSm2 as u32)
+}
+
+/// Computes the hash `e = SM3(Z || message)` that SM2 signs, `Z` being the
+/// SM3 hash of `id`, the curve and `public_key`.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `id` is longer than 8191 bytes.
+/// 2) `OutOfMemory`: If not enough resources are available to allocate the
+///    operation.
+pub fn sm2_digest(
+    id: &[u8],
+    public_key: &Sm2PublicKey,
+    message: &[u8],
+) -> Result<[u8; DIGEST_LEN]> {
+    // `Z` starts with the length of `id` in bits, on two bytes.
+    let id_bits = u16::try_from(id.len() * 8).map_err(|_| Error::new(ErrorKind::BadParameters))?;
+    let op = Digest::allocate(AlgorithmId::Sm3)?;
+    let mut z = [0u8; DIGEST_LEN];
+    op.update(&id_bits.to_be_bytes());
+    op.update(id);
+    for part in [
+        &CURVE_A,
+        &CURVE_B,
+        &BASE_X,
+        &BASE_Y,
+        &public_key.x,
+        &public_key.y,
+    ] {
+        op.update(part);
+    }
+    op.do_final(&[], &mut z)?;
+
+    let mut e = [0u8; DIGEST_LEN];
+    op.update(&z);
+    op.do_final(message, &mut e)?;
+    Ok(e)
+}
+
+// Allocates an object of `object_type` holding a new key pair for
+// `algorithm`.
+fn generate(algorithm: AlgorithmId, object_type: TransientObjectType) -> Result<TransientObject> {
+    ensure_supported(algorithm)?;
+    let key = TransientObject::allocate(object_type, KEY_SIZE)?;
+    key.generate_key(KEY_SIZE, &[])?;
+    Ok(key)
+}
+
+// Reads the public key of `key`.
+fn public_key(key: &TransientObject) -> Result<Sm2PublicKey> {
+    let mut public = Sm2PublicKey {
+        x: [0u8; COORDINATE_LEN],
+        y: [0u8; COORDINATE_LEN],
+    };
+    let x = key.ref_attribute(AttributeId::EccPublicValueX, &mut public.x)?;
+    let y = key.ref_attribute(AttributeId::EccPublicValueY, &mut public.y)?;
+    match (x, y) {
+        (COORDINATE_LEN, COORDINATE_LEN) => Ok(public),
+        _ => Err(Error::new(ErrorKind::BadFormat)),
+    }
+}
+
+// Allocates an object of `object_type` holding `public_key`.
+fn public_object(
+    object_type: TransientObjectType,
+    public_key: &Sm2PublicKey,
+) -> Result<TransientObject> {
+    let mut key = TransientObject::allocate(object_type, KEY_SIZE)?;
+    key.populate(&[
+        AttributeMemref::from_ref(AttributeId::EccPublicValueX, &public_key.x).into(),
+        AttributeMemref::from_ref(AttributeId::EccPublicValueY, &public_key.y).into(),
+    ])?;
+    Ok(key)
+}
+
+/// An SM2 key pair for signatures, held in a transient object.
+pub struct Sm2SigningKey(TransientObject);
+
+impl Sm2SigningKey {
+    /// Generates a key pair.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 signatures.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the object.
+    pub fn generate() -> Result<Self> {
+        generate(AlgorithmId::Sm2DsaSm3, TransientObjectType::Sm2DsaKeypair).map(Self)
+    }
+
+    /// Returns the public key, to verify signatures with.
+    pub fn public_key(&self) -> Result<Sm2PublicKey> {
+        public_key(&self.0)
+    }
+
+    /// Signs `message` as the signer identified by `id`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 signatures.
+    /// 2) `BadParameters`: If `id` is longer than 8191 bytes.
+    /// 3) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the operations.
+    pub fn sign(&self, id: &[u8], message: &[u8]) -> Result<[u8; SIGNATURE_LEN]> {
+        ensure_supported(AlgorithmId::Sm2DsaSm3)?;
+        let digest = sm2_digest(id, &self.public_key()?, message)?;
+        let op = Asymmetric::allocate(AlgorithmId::Sm2DsaSm3, OperationMode::Sign, KEY_SIZE)?;
+        op.set_key(&self.0)?;
+        let mut signature = [0u8; SIGNATURE_LEN];
+        match op.sign_digest(&[], &digest, &mut signature)? {
+            SIGNATURE_LEN => Ok(signature),
+            _ => Err(Error::new(ErrorKind::BadFormat)),
+        }
+    }
+
+    /// Returns the object holding the key pair, e.g. to store it.
+    pub fn object(&self) -> &TransientObject {
+        &self.0
+    }
+}
+
+/// Verifies the SM2 `signature` of `message` by the signer identified by
+/// `id` and holding `public_key`.
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the TEE does not implement SM2 signatures.
+/// 2) `SignatureInvalid`: If the signature does not match.
+/// 3) `BadParameters`: If `id` is longer than 8191 bytes or `public_key` is
+///    not a valid key.
+pub fn sm2_verify(
+    public_key: &Sm2PublicKey,
+    id: &[u8],
+    message: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> Result<()> {
+    ensure_supported(AlgorithmId::Sm2DsaSm3)?;
+    let digest = sm2_digest(id, public_key, message)?;
+    let key = public_object(TransientObjectType::Sm2DsaPublicKey, public_key)?;
+    let op = Asymmetric::allocate(AlgorithmId::Sm2DsaSm3, OperationMode::Verify, KEY_SIZE)?;
+    op.set_key(&key)?;
+    op.verify_digest(&[], &digest, signature)
+}
+
+/// An SM2 key pair for encryption, held in a transient object.
+pub struct Sm2DecryptionKey(TransientObject);
+
+impl Sm2DecryptionKey {
+    /// Generates a key pair.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 encryption.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the object.
+    pub fn generate() -> Result<Self> {
+        generate(AlgorithmId::Sm2Pke, TransientObjectType::Sm2PkeKeypair).map(Self)
+    }
+
+    /// Returns the public key, to encrypt with.
+    pub fn public_key(&self) -> Result<Sm2PublicKey> {
+        public_key(&self.0)
+    }
+
+    /// Decrypts a `ciphertext` produced by [`sm2_encrypt`].
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 encryption.
+    /// 2) `CiphertextInvalid`: If `ciphertext` was not encrypted to this key
+    ///    or was modified.
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        ensure_supported(AlgorithmId::Sm2Pke)?;
+        let op = Asymmetric::allocate(AlgorithmId::Sm2Pke, OperationMode::Decrypt, KEY_SIZE)?;
+        op.set_key(&self.0)?;
+        op.decrypt(&[], ciphertext)
+    }
+
+    /// Returns the object holding the key pair, e.g. to store it.
+    pub fn object(&self) -> &TransientObject {
+        &self.0
+    }
+}
+
+/// Encrypts `plaintext` to `public_key`, as `C1 || C3 || C2`: the ephemeral
+/// point, the SM3 hash and the encrypted plaintext.
+///
+/// # Errors
+///
+/// 1) `NotSupported`: If the TEE does not implement SM2 encryption.
+/// 2) `BadParameters`: If `public_key` is not a valid key.
+pub fn sm2_encrypt(public_key: &Sm2PublicKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+    ensure_supported(AlgorithmId::Sm2Pke)?;
+    let key = public_object(TransientObjectType::Sm2PkePublicKey, public_key)?;
+    let op = Asymmetric::allocate(AlgorithmId::Sm2Pke, OperationMode::Encrypt, KEY_SIZE)?;
+    op.set_key(&key)?;
+    op.encrypt(&[], plaintext)
+}
+
+/// Role of a party in an SM2 key exchange.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sm2Role {
+    /// The party that sent its ephemeral public key first.
+    Initiator,
+    /// The other party.
+    Responder,
+}
+
+/// The other party of an SM2 key exchange.
+#[derive(Clone, Copy, Debug)]
+pub struct Sm2Peer<'a> {
+    /// Identifier of the peer.
+    pub id: &'a [u8],
+    /// Static public key of the peer.
+    pub public_key: Sm2PublicKey,
+    /// Ephemeral public key of the peer, for this exchange.
+    pub ephemeral: Sm2PublicKey,
+}
+
+/// An SM2 key pair for key exchanges, held in a transient object. A party
+/// holds a static one, and generates an ephemeral one for each exchange.
+pub struct Sm2ExchangeKey(TransientObject);
+
+impl Sm2ExchangeKey {
+    /// Generates a key pair.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 key exchange.
+    /// 2) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the object.
+    pub fn generate() -> Result<Self> {
+        generate(AlgorithmId::Sm2Kep, TransientObjectType::Sm2KepKeypair).map(Self)
+    }
+
+    /// Returns the public key, to send to the peer.
+    pub fn public_key(&self) -> Result<Sm2PublicKey> {
+        public_key(&self.0)
+    }
+
+    /// Agrees with `peer` on a key of `key_size` bits, as a
+    /// [`GenericSecret`](TransientObjectType::GenericSecret) object, this key
+    /// pair being the static one of the party identified by `id` in `role`,
+    /// and `ephemeral` the one generated for this exchange.
+    ///
+    /// The optional key confirmation is left to the protocol using the key.
+    ///
+    /// # Errors
+    ///
+    /// 1) `NotSupported`: If the TEE does not implement SM2 key exchange.
+    /// 2) `BadParameters`: If `key_size` is not a multiple of 8.
+    /// 3) `OutOfMemory`: If not enough resources are available to allocate
+    ///    the operation or the object.
+    pub fn agree(
+        &self,
+        ephemeral: &Sm2ExchangeKey,
+        role: Sm2Role,
+        id: &[u8],
+        peer: &Sm2Peer,
+        key_size: usize,
+    ) -> Result<TransientObject> {
+        ensure_supported(AlgorithmId::Sm2Kep)?;
+        if !key_size.is_multiple_of(8) {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let (initiator, responder, user) = match role {
+            Sm2Role::Initiator => (id, peer.id, 0),
+            Sm2Role::Responder => (peer.id, id, 1),
+        };
+        let op = DeriveKey::allocate(AlgorithmId::Sm2Kep, KEY_SIZE)?;
+        op.set_key_2(&self.0, &ephemeral.0)?;
+        let mut shared = TransientObject::allocate(TransientObjectType::GenericSecret, key_size)?;
+        op.derive(
+            &[
+                AttributeMemref::from_ref(AttributeId::EccPublicValueX, &peer.public_key.x).into(),
+                AttributeMemref::from_ref(AttributeId::EccPublicValueY, &peer.public_key.y).into(),
+                AttributeMemref::from_ref(AttributeId::EccEphemeralPublicValueX, &peer.ephemeral.x)
+                    .into(),
+                AttributeMemref::from_ref(AttributeId::EccEphemeralPublicValueY, &peer.ephemeral.y)
+                    .into(),
+                AttributeMemref::from_ref(AttributeId::Sm2IdInitiator, initiator).into(),
+                AttributeMemref::from_ref(AttributeId::Sm2IdResponder, responder).into(),
+                AttributeValue::from_value(AttributeId::Sm2KepUser, user, 0).into(),
+            ],
+            &mut shared,
+        );
+        Ok(shared)
+    }
+
+    /// Returns the object holding the key pair, e.g. to store it.
+    pub fn object(&self) -> &TransientObject {
+        &self.0
+    }
+}
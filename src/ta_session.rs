@@ -15,7 +15,7 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::{Error, Result, TeeParams, Uuid};
+use crate::{Error, ErrorKind, ErrorOrigin, Result, TeeParams, Uuid};
 use optee_utee_sys as raw;
 
 pub struct TaSessionBuilder<'a> {
@@ -48,50 +48,92 @@ impl<'a> TaSessionBuilder<'a> {
 
     /// Builds and opens the `TaSession`. Returns an error if the session fails to open.
     pub fn build(mut self) -> Result<TaSession> {
-        let mut err_origin: u32 = 0;
-        let mut raw_session: raw::TEE_TASessionHandle = core::ptr::null_mut();
-        // Check if the parameters are provided and prepare them for the C API call.
-        let (raw_param_types, raw_params_ptr, raw_params_opt) =
-            if let Some(params) = &mut self.params {
-                let mut raw_params = params.as_raw();
-                let raw_ptr = raw_params.as_mut_ptr();
-                (params.raw_param_types(), raw_ptr, Some(raw_params))
-            } else {
-                (0, core::ptr::null_mut(), None)
-            };
+        let raw = open_session(self.target_uuid, self.timeout, self.params.as_mut())?;
+        Ok(TaSession {
+            raw,
+            target_uuid: self.target_uuid,
+            timeout: self.timeout,
+        })
+    }
+}
 
-        // SAFETY:
-        // self.target_uuid.as_raw_ptr() provides a valid pointer to the UUID.
-        // raw_params.as_mut_ptr() provides a valid pointer to the parameters.
-        // The remaining arguments are either valid values or null/mut pointers as expected by the C API.
-        // For parameters that are intended to be modified by the call, the buffer constraints are checked later in update_from_raw().
-        match unsafe {
-            raw::TEE_OpenTASession(
-                self.target_uuid.as_raw_ptr(),
-                self.timeout,
-                raw_param_types,
-                raw_params_ptr,
-                &mut raw_session,
-                &mut err_origin,
-            )
-        } {
-            raw::TEE_SUCCESS => {
-                if let (Some(params), Some(raw_params)) = (&mut self.params, raw_params_opt) {
-                    params.update_from_raw(&raw_params)?;
-                }
+fn open_session(
+    target_uuid: Uuid,
+    timeout: u32,
+    mut params: Option<&mut TeeParams>,
+) -> Result<raw::TEE_TASessionHandle> {
+    let mut err_origin: u32 = 0;
+    let mut raw_session: raw::TEE_TASessionHandle = core::ptr::null_mut();
+    // Check if the parameters are provided and prepare them for the C API call.
+    let (raw_param_types, raw_params_ptr, raw_params_opt) = if let Some(params) = &mut params {
+        let mut raw_params = params.as_raw();
+        let raw_ptr = raw_params.as_mut_ptr();
+        (params.raw_param_types(), raw_ptr, Some(raw_params))
+    } else {
+        (0, core::ptr::null_mut(), None)
+    };
 
-                Ok(TaSession { raw: raw_session })
+    // SAFETY:
+    // target_uuid.as_raw_ptr() provides a valid pointer to the UUID.
+    // raw_params.as_mut_ptr() provides a valid pointer to the parameters.
+    // The remaining arguments are either valid values or null/mut pointers as expected by the C API.
+    // For parameters that are intended to be modified by the call, the buffer constraints are checked later in update_from_raw().
+    match unsafe {
+        raw::TEE_OpenTASession(
+            target_uuid.as_raw_ptr(),
+            timeout,
+            raw_param_types,
+            raw_params_ptr,
+            &mut raw_session,
+            &mut err_origin,
+        )
+    } {
+        raw::TEE_SUCCESS => {
+            if let (Some(params), Some(raw_params)) = (params, raw_params_opt) {
+                params.update_from_raw(&raw_params)?;
             }
-            code => Err(Error::from_raw_error(code).with_origin(err_origin.into())),
+
+            Ok(raw_session)
         }
+        code => Err(Error::from_raw_error(code).with_origin(err_origin.into())),
     }
 }
 
+/// A session opened by the TA on another TA.
+///
+/// Once a command fails with `TargetDead`, the session is poisoned: its
+/// handle is closed right away and later commands fail with `TargetDead`,
+/// origin `Api`, without reaching the target. [`try_reopen`](Self::try_reopen)
+/// opens a new session on the same TA in its place.
 pub struct TaSession {
+    // Null once poisoned.
     raw: raw::TEE_TASessionHandle,
+    target_uuid: Uuid,
+    timeout: u32,
 }
 
 impl TaSession {
+    /// Returns whether the target TA died under the session.
+    pub fn is_poisoned(&self) -> bool {
+        self.raw.is_null()
+    }
+
+    /// Replaces a poisoned session with a new session on the same TA, opened
+    /// with `params` and the timeout of the original one. The session does
+    /// not keep the parameters it was first opened with, so pass them again
+    /// if the target needs them. Does nothing if the session is not poisoned.
+    ///
+    /// # Errors
+    ///
+    /// Errors from opening the session, in which case it stays poisoned.
+    pub fn try_reopen(&mut self, params: Option<&mut TeeParams>) -> Result<()> {
+        if !self.is_poisoned() {
+            return Ok(());
+        }
+        self.raw = open_session(self.target_uuid, self.timeout, params)?;
+        Ok(())
+    }
+
     /// Invokes a command with the provided parameters using the session's default timeout.
     /// Returns the result directly without allowing further method chaining.
     pub fn invoke_command(&mut self, command_id: u32, params: &mut TeeParams) -> Result<()> {
@@ -104,6 +146,9 @@ impl TaSession {
         params: &mut TeeParams,
         timeout: u32,
     ) -> Result<()> {
+        if self.is_poisoned() {
+            return Err(Error::new(ErrorKind::TargetDead).with_origin(ErrorOrigin::Api));
+        }
         let mut err_origin: u32 = 0;
         let mut raw_params = params.as_raw();
         let param_types = params.raw_param_types();
@@ -128,14 +173,34 @@ impl TaSession {
                 params.update_from_raw(&raw_params)?;
                 Ok(())
             }
-            code => Err(Error::from_raw_error(code).with_origin(err_origin.into())),
+            code => {
+                let error = Error::from_raw_error(code).with_origin(err_origin.into());
+                if error.kind() == ErrorKind::TargetDead {
+                    self.poison();
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // Releases the handle of a session whose target died.
+    fn poison(&mut self) {
+        // SAFETY:
+        // self.raw is a valid pointer to an active session handle, nulled
+        // right after so that it is not closed twice.
+        unsafe {
+            raw::TEE_CloseTASession(self.raw);
         }
+        self.raw = core::ptr::null_mut();
     }
 }
 
 // Drop implementation to close the session
 impl Drop for TaSession {
     fn drop(&mut self) {
+        if self.is_poisoned() {
+            return;
+        }
         // SAFETY:
         // self.raw is a valid pointer to an active session handle.
         // The function call is expected to clean up the session resources.
@@ -144,3 +209,140 @@ impl Drop for TaSession {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use optee_utee_mock::mockall;
+    use optee_utee_mock::session::{
+        set_global_session_mock, MockSessionController, SERIAL_TEST_LOCK,
+    };
+
+    use super::*;
+    use crate::ParamIndex;
+
+    fn target() -> Uuid {
+        Uuid::parse_str("8abcf200-2450-11e4-abe2-0002a5d5c51b").unwrap()
+    }
+
+    fn dead(session: &mut TaSession) -> Error {
+        session
+            .invoke_command(0, &mut TeeParams::new())
+            .expect_err("the target is dead")
+    }
+
+    #[test]
+    fn test_poisoned_after_target_dead() {
+        let _lock = SERIAL_TEST_LOCK.lock();
+
+        let mut handle_struct = MockSessionController::new_valid_test_handle_struct();
+        let handle = MockSessionController::new_valid_test_handle(&mut handle_struct);
+        let mut mock = MockSessionController::new();
+        mock.expect_TEE_OpenTASession_success_once(handle.clone());
+        mock.expect_TEE_InvokeTACommand_fail_once(
+            handle.clone(),
+            raw::TEE_ERROR_TARGET_DEAD,
+            raw::TEE_ORIGIN_TEE,
+        );
+        mock.expect_TEE_CloseTASession_once(handle.clone());
+        set_global_session_mock(mock);
+
+        let mut session = TaSessionBuilder::new(target()).build().unwrap();
+        assert!(!session.is_poisoned());
+
+        let error = dead(&mut session);
+        assert_eq!(error.kind(), ErrorKind::TargetDead);
+        assert_eq!(error.origin(), Some(ErrorOrigin::Tee));
+        assert!(session.is_poisoned());
+
+        // Later commands fail without reaching the target, with an origin
+        // telling them apart from the death itself.
+        let error = dead(&mut session);
+        assert_eq!(error.kind(), ErrorKind::TargetDead);
+        assert_eq!(error.origin(), Some(ErrorOrigin::Api));
+    }
+
+    #[test]
+    fn test_other_errors_do_not_poison() {
+        let _lock = SERIAL_TEST_LOCK.lock();
+
+        let mut handle_struct = MockSessionController::new_valid_test_handle_struct();
+        let handle = MockSessionController::new_valid_test_handle(&mut handle_struct);
+        let mut mock = MockSessionController::new();
+        mock.expect_TEE_OpenTASession_success_once(handle.clone());
+        mock.expect_TEE_InvokeTACommand_fail_once(
+            handle.clone(),
+            raw::TEE_ERROR_BAD_PARAMETERS,
+            raw::TEE_ORIGIN_TRUSTED_APP,
+        );
+        mock.expect_TEE_CloseTASession_once(handle.clone());
+        set_global_session_mock(mock);
+
+        let mut session = TaSessionBuilder::new(target()).build().unwrap();
+        assert_eq!(dead(&mut session).kind(), ErrorKind::BadParameters);
+        assert!(!session.is_poisoned());
+        // Not poisoned: there is nothing to reopen.
+        session.try_reopen(None).unwrap();
+    }
+
+    #[test]
+    fn test_try_reopen() {
+        let _lock = SERIAL_TEST_LOCK.lock();
+
+        let mut first_struct = MockSessionController::new_valid_test_handle_struct();
+        let first = MockSessionController::new_valid_test_handle(&mut first_struct);
+        let mut second_struct = MockSessionController::new_valid_test_handle_struct();
+        let second = MockSessionController::new_valid_test_handle(&mut second_struct);
+        let mut mock = MockSessionController::new();
+        let mut sequence = mockall::Sequence::new();
+        mock.expect_TEE_OpenTASession()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(move |_, _, _, _, session, _| {
+                unsafe { *session = *first.get() };
+                raw::TEE_SUCCESS
+            });
+        mock.expect_TEE_InvokeTACommand()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(|_, _, _, _, _, _| raw::TEE_ERROR_TARGET_DEAD);
+        mock.expect_TEE_CloseTASession()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(|_| ());
+        // A failed reopen leaves the session poisoned.
+        mock.expect_TEE_OpenTASession()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(|_, _, _, _, _, _| raw::TEE_ERROR_BUSY);
+        // The parameters given to `try_reopen` reach the target.
+        let expected = second.clone();
+        mock.expect_TEE_OpenTASession()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(move |_, _, param_types, params, session, _| {
+                assert_eq!(param_types, raw::TEE_PARAM_TYPE_VALUE_INPUT);
+                assert_eq!(unsafe { (*params).value.a }, 7);
+                unsafe { *session = *second.get() };
+                raw::TEE_SUCCESS
+            });
+        mock.expect_TEE_CloseTASession()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .return_once_st(move |session| {
+                assert_eq!(session, unsafe { *expected.get() });
+            });
+        set_global_session_mock(mock);
+
+        let mut session = TaSessionBuilder::new(target()).build().unwrap();
+        dead(&mut session);
+        let error = session.try_reopen(None).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Busy);
+        assert!(session.is_poisoned());
+
+        let mut params = TeeParams::new().with_value_in(ParamIndex::Arg0, 7, 0);
+        session.try_reopen(Some(&mut params)).unwrap();
+        assert!(!session.is_poisoned());
+    }
+}
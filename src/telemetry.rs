@@ -0,0 +1,277 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Opt-in error telemetry, enabled by the `error_telemetry` feature.
+//!
+//! Every [`Error`](crate::Error) created by this crate is counted per
+//! (module, [`ErrorKind`]) pair, so that a TA can report spikes of e.g.
+//! `MacInvalid` or `StorageNoSpace` through a debug command instead of having
+//! its logs parsed. Errors created outside of this crate are accounted to the
+//! [`TA_MODULE`] pseudo-module.
+
+use alloc::vec::Vec;
+use core::panic::Location;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{Error, ErrorKind, Result};
+
+/// Pseudo-module of the errors created by the TA itself.
+pub const TA_MODULE: &str = "ta";
+
+/// Modules errors are accounted to, indexed by [`ErrorCount::module`] in
+/// [`export`]: every module of this crate, whether or not its feature is
+/// enabled, then [`TA_MODULE`].
+pub const MODULES: [&str; 36] = [
+    "arithmetical",
+    "build_info",
+    "cbor",
+    "config",
+    "crypto_op",
+    "curve25519",
+    "der",
+    "ecdsa",
+    "error",
+    "event_log",
+    "extension",
+    "hdkey",
+    "identity",
+    "journal",
+    "jwt",
+    "macros",
+    "net",
+    "object",
+    "parameter",
+    "platform",
+    "property",
+    "quota",
+    "rustcrypto",
+    "secret",
+    "services",
+    "sm2",
+    "ta_session",
+    "tee_parameter",
+    "telemetry",
+    "tenant",
+    "threshold",
+    "time",
+    "trace",
+    "trash",
+    "uuid",
+    TA_MODULE,
+];
+
+// Every `ErrorKind` gets a slot; vendor-specific codes share the last one.
+const KINDS: [ErrorKind; 34] = [
+    ErrorKind::CorruptObject,
+    ErrorKind::CorruptObject2,
+    ErrorKind::StorageNotAvailable,
+    ErrorKind::StorageNotAvailable2,
+    ErrorKind::UnsupportedVersion,
+    ErrorKind::CiphertextInvalid,
+    ErrorKind::Generic,
+    ErrorKind::AccessDenied,
+    ErrorKind::Cancel,
+    ErrorKind::AccessConflict,
+    ErrorKind::ExcessData,
+    ErrorKind::BadFormat,
+    ErrorKind::BadParameters,
+    ErrorKind::BadState,
+    ErrorKind::ItemNotFound,
+    ErrorKind::NotImplemented,
+    ErrorKind::NotSupported,
+    ErrorKind::NoData,
+    ErrorKind::OutOfMemory,
+    ErrorKind::Busy,
+    ErrorKind::Communication,
+    ErrorKind::Security,
+    ErrorKind::ShortBuffer,
+    ErrorKind::ExternalCancel,
+    ErrorKind::Timeout,
+    ErrorKind::Overflow,
+    ErrorKind::TargetDead,
+    ErrorKind::StorageNoSpace,
+    ErrorKind::MacInvalid,
+    ErrorKind::SignatureInvalid,
+    ErrorKind::TimeNotSet,
+    ErrorKind::TimeNeedsReset,
+    ErrorKind::Unknown,
+    ErrorKind::Vendor(0),
+];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU32 = AtomicU32::new(0);
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO_ROW: [AtomicU32; KINDS.len()] = [ZERO; KINDS.len()];
+
+static COUNTERS: [[AtomicU32; KINDS.len()]; MODULES.len()] = [ZERO_ROW; MODULES.len()];
+
+/// Number of errors of one kind raised in one module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorCount {
+    /// Index of the module in [`MODULES`].
+    pub module: usize,
+    /// Kind of the errors. Vendor-specific errors are reported as
+    /// `ErrorKind::Vendor(0)`.
+    pub kind: ErrorKind,
+    pub count: u32,
+}
+
+impl ErrorCount {
+    /// Returns the name of the module the errors were raised in.
+    pub fn module_name(&self) -> &'static str {
+        MODULES[self.module]
+    }
+}
+
+/// Size of a record written by [`export`].
+pub const RECORD_SIZE: usize = 12;
+
+pub(crate) fn record(kind: ErrorKind, location: &Location<'static>) {
+    let module = module_index(location.file());
+    let kind = kind_index(kind);
+    COUNTERS[module][kind].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the non-zero error counters.
+pub fn snapshot() -> Vec<ErrorCount> {
+    let mut counts = Vec::new();
+    for (module, row) in COUNTERS.iter().enumerate() {
+        for (kind, counter) in row.iter().enumerate() {
+            let count = counter.load(Ordering::Relaxed);
+            if count != 0 {
+                counts.push(ErrorCount {
+                    module,
+                    kind: KINDS[kind],
+                    count,
+                });
+            }
+        }
+    }
+    counts
+}
+
+/// Resets every error counter to zero.
+pub fn reset() {
+    COUNTERS
+        .iter()
+        .flat_map(|row| row.iter())
+        .for_each(|counter| counter.store(0, Ordering::Relaxed));
+}
+
+/// Writes the non-zero error counters into `buf`, typically the output memref
+/// of a stats command, and returns the number of bytes written.
+///
+/// Every counter is encoded as a [`RECORD_SIZE`] bytes record of three
+/// little-endian `u32`: the module index in [`MODULES`], the raw error code
+/// and the count.
+///
+/// # Errors
+///
+/// 1) `ShortBuffer`: If `buf` cannot hold every record.
+pub fn export(buf: &mut [u8]) -> Result<usize> {
+    let counts = snapshot();
+    let size = counts.len() * RECORD_SIZE;
+    if buf.len() < size {
+        return Err(Error::new(ErrorKind::ShortBuffer));
+    }
+
+    for (count, record) in counts.iter().zip(buf.chunks_exact_mut(RECORD_SIZE)) {
+        record[0..4].copy_from_slice(&(count.module as u32).to_le_bytes());
+        record[4..8].copy_from_slice(&u32::from(count.kind).to_le_bytes());
+        record[8..12].copy_from_slice(&count.count.to_le_bytes());
+    }
+    Ok(size)
+}
+
+// Source files of this crate share the directory of this file, which lets us
+// tell errors of the crate apart from the ones created by the TA.
+fn module_index(file: &str) -> usize {
+    let ta_module = MODULES.len() - 1;
+    let src_dir = file!().trim_end_matches("telemetry.rs");
+    let Some(path) = file.strip_prefix(src_dir) else {
+        return ta_module;
+    };
+    let module = path
+        .split(['/', '\\'])
+        .next()
+        .unwrap_or(path)
+        .trim_end_matches(".rs");
+    MODULES[..ta_module]
+        .iter()
+        .position(|name| *name == module)
+        .unwrap_or(ta_module)
+}
+
+fn kind_index(kind: ErrorKind) -> usize {
+    match kind {
+        ErrorKind::Vendor(_) => KINDS.len() - 1,
+        kind => KINDS
+            .iter()
+            .position(|k| *k == kind)
+            .unwrap_or(KINDS.len() - 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_module_index() {
+        assert_eq!(MODULES[module_index(file!())], "telemetry");
+        let crypto_op = file!().replace("telemetry.rs", "crypto_op.rs");
+        assert_eq!(MODULES[module_index(&crypto_op)], "crypto_op");
+        let object = file!().replace("telemetry.rs", "object/persistent_object.rs");
+        assert_eq!(MODULES[module_index(&object)], "object");
+        assert_eq!(MODULES[module_index("src/main.rs")], TA_MODULE);
+    }
+
+    #[test]
+    fn test_kind_index() {
+        assert_eq!(
+            KINDS[kind_index(ErrorKind::MacInvalid)],
+            ErrorKind::MacInvalid
+        );
+        assert_eq!(
+            KINDS[kind_index(ErrorKind::Vendor(0x8000_0001))],
+            ErrorKind::Vendor(0)
+        );
+    }
+
+    #[test]
+    fn test_modules_list_every_module() {
+        let src = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        for entry in fs::read_dir(src).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().into_string().unwrap();
+            let module = match name.strip_suffix(".rs") {
+                Some("lib") => continue,
+                Some(module) => module,
+                None if entry.file_type().unwrap().is_dir() => &name,
+                None => continue,
+            };
+            assert!(
+                MODULES.contains(&module),
+                "module `{}` is missing from MODULES",
+                module
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Namespaces isolating the data of the clients of a TA that serves several
+//! applications.
+//!
+//! A tenant is identified by the login type and uuid of the client identity
+//! of a session. [`TenantContext::object_id`] maps an object id chosen by the
+//! TA into the namespace of the tenant, so that the objects of a tenant can
+//! neither be read nor overwritten through the ids of another. Everything
+//! the framework keeps under an object id supplied by the TA, e.g. key usage
+//! [quotas](crate::quota), HD wallet [seeds](crate::hdkey) or
+//! `threshold` signing shares, gets isolated by passing it a
+//! namespaced id, and the [counter service](crate::services) keeps per-tenant
+//! counters when enabled.
+//!
+//! A namespaced id is `fw.t.`, the login type (u32 little endian), the
+//! client uuid, `.` and the id of the TA.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::tenant::TenantContext;
+//! # use optee_utee::{DataFlag, ObjectStorageConstants, PersistentObject, Result};
+//! fn save_settings(settings: &[u8]) -> Result<()> {
+//!     let tenant = TenantContext::current()?;
+//!     PersistentObject::create(
+//!         ObjectStorageConstants::Private,
+//!         &tenant.object_id(b"settings")?,
+//!         DataFlag::ACCESS_WRITE | DataFlag::OVERWRITE,
+//!         None,
+//!         settings,
+//!     )?;
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use optee_utee_sys as raw;
+
+use crate::property::{ClientIdentity, PropertyKey};
+use crate::{Error, ErrorKind, Identity, Result};
+
+const PREFIX: &[u8] = b"fw.t.";
+const NAMESPACE_LEN: usize = PREFIX.len() + 4 + 16 + 1;
+/// Longest id accepted by [`TenantContext::object_id`].
+pub const MAX_OBJECT_ID_LEN: usize = raw::TEE_OBJECT_ID_MAX_LEN as usize - NAMESPACE_LEN;
+
+/// The tenant a session runs on behalf of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TenantContext {
+    login: u32,
+    uuid: [u8; 16],
+}
+
+impl TenantContext {
+    /// Returns the tenant of the client `identity`.
+    pub fn new(identity: &Identity) -> Self {
+        Self {
+            login: identity.login_type() as u32,
+            uuid: identity.uuid().to_bytes(),
+        }
+    }
+
+    /// Returns the tenant of the client of the current session.
+    ///
+    /// # Errors
+    ///
+    /// Errors from reading the `gpd.client.identity` property.
+    pub fn current() -> Result<Self> {
+        Ok(Self::new(&ClientIdentity.get()?))
+    }
+
+    /// Returns the prefix of the ids in the namespace of the tenant.
+    pub fn namespace(&self) -> Vec<u8> {
+        let mut namespace = Vec::with_capacity(NAMESPACE_LEN);
+        namespace.extend_from_slice(PREFIX);
+        namespace.extend_from_slice(&self.login.to_le_bytes());
+        namespace.extend_from_slice(&self.uuid);
+        namespace.push(b'.');
+        namespace
+    }
+
+    /// Maps `id` into the namespace of the tenant.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If `id` is longer than [`MAX_OBJECT_ID_LEN`].
+    pub fn object_id(&self, id: &[u8]) -> Result<Vec<u8>> {
+        if id.len() > MAX_OBJECT_ID_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let mut object_id = self.namespace();
+        object_id.extend_from_slice(id);
+        Ok(object_id)
+    }
+
+    /// Returns the id `object_id` was mapped from if it belongs to the
+    /// namespace of the tenant, e.g. to filter an enumeration of the objects.
+    pub fn strip<'a>(&self, object_id: &'a [u8]) -> Option<&'a [u8]> {
+        object_id.strip_prefix(self.namespace().as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LoginType, Uuid};
+
+    #[test]
+    fn test_object_id() {
+        let a = TenantContext::new(&Identity::new(LoginType::User, Uuid::from_bytes([1; 16])));
+        let b = TenantContext::new(&Identity::new(LoginType::Group, Uuid::from_bytes([1; 16])));
+
+        let id = a.object_id(b"key").unwrap();
+        assert_eq!(id.len(), NAMESPACE_LEN + 3);
+        assert_eq!(a.strip(&id), Some(&b"key"[..]));
+        assert_eq!(b.strip(&id), None);
+        assert_ne!(b.object_id(b"key").unwrap(), id);
+
+        assert!(a.object_id(&[0; MAX_OBJECT_ID_LEN]).is_ok());
+        assert!(a.object_id(&[0; MAX_OBJECT_ID_LEN + 1]).is_err());
+    }
+}
@@ -0,0 +1,1050 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Two-party (2-of-2) ECDSA over P-256, where the TA holds one share of the
+//! key and a peer, such as a server, holds the other.
+//!
+//! The scheme is the one of Lindell, "Fast Secure Two-Party ECDSA Signing"
+//! (CRYPTO 2017), with the TA as the party `P2`: the peer holds a Paillier
+//! key, gives the TA its share encrypted under it, and finishes the
+//! signatures, which verify as plain ECDSA signatures under the joint
+//! public key. Messages between the parties go through the CA; this module
+//! only produces and consumes the ones of the TA, and checks every proof of
+//! the peer, which could otherwise learn the share of the TA.
+//!
+//! `G` is the generator and `q` the order of P-256, `Enc` the encryption
+//! under the Paillier key `N` of the peer, `⊕` and `⊙` the homomorphic
+//! addition and multiplication by a scalar, and `H` SHA-256. Points are
+//! compressed SEC1 encodings and integers big-endian, of fixed lengths. The
+//! proof of knowledge of the logarithm `x` of `X = x·G` is `T || z`, with
+//! `T = k·G` and `z = k + H("fw.threshold.ecdsa.dlog" || X || T)·x`, and
+//! the commitment to an opening `X || T || z || salt` is `H(tag || opening)`
+//! with a 32-byte random salt.
+//!
+//! Key generation:
+//!
+//! 1) The peer draws `x_1 < q/3` and sends a commitment, tagged
+//!    `"fw.threshold.ecdsa.key"`, to `Q_1 = x_1·G` and its proof.
+//! 2) The TA draws `x_2` and sends `Q_2 = x_2·G` and its proof
+//!    ([`KeyGeneration::start`]).
+//! 3) The peer sends its opening, `N`, `c_key = Enc(x_1)`, the
+//!    [`MODULUS_PROOF_ROOTS`] `N`-th roots modulo `N` proving that
+//!    `gcd(N, φ(N)) = 1`, and the pairs of ciphertexts of the
+//!    [`RANGE_PROOF_ROUNDS`] rounds of a proof that `x_1` is in `[0, q/3[`,
+//!    each encrypting `w` in `[q/3, 2q/3[` and `w - q/3` in any order.
+//! 4) The TA checks them and sends a bit per round, then the first message
+//!    of a proof that `c_key` encrypts the logarithm of `Q_1`:
+//!    `c' = a ⊙ c_key ⊕ Enc(b)`, with `a < q` and `b < q²`, and a
+//!    commitment to `a || b || salt` tagged `"fw.threshold.ecdsa.pdl.challenge"`
+//!    ([`KeyGeneration::challenge`]).
+//! 5) The peer answers each round: for a 0, both plaintexts and their
+//!    randomness; for a 1, the index `j` of the ciphertext `c_j` of the pair
+//!    such that `z = x_1 + w_j` is in `[q/3, 2q/3[`, `z`, and the randomness
+//!    of `c_key ⊕ c_j`. It then sends a commitment, tagged
+//!    `"fw.threshold.ecdsa.pdl.answer"`, to `Q^ = Dec(c')·G`.
+//! 6) The TA checks the answers and opens `a`, `b` ([`KeyGeneration::open`]).
+//! 7) The peer checks that `Dec(c') = a·x_1 + b` and opens `Q^`.
+//! 8) The TA checks that `Q^ = a·Q_1 + b·G` and stores its share
+//!    ([`KeyGeneration::finish`]). The public key is `Q = x_1·x_2·G`.
+//!
+//! Signing a message `M`:
+//!
+//! 1) The peer draws `k_1` and sends a commitment, tagged
+//!    `"fw.threshold.ecdsa.nonce"`, to `R_1 = k_1·G` and its proof.
+//! 2) The TA draws `k_2` and sends `R_2 = k_2·G` and its proof
+//!    ([`SigningSession::start`]).
+//! 3) The peer sends its opening. The TA checks it, computes `r`, the
+//!    x-coordinate of `k_2·R_1` modulo `q`, and sends
+//!    `c_3 = Enc(ρ·q + k_2⁻¹·H(M)) ⊕ (k_2⁻¹·r·x_2) ⊙ c_key`, with `ρ < q²`
+//!    ([`SigningSession::sign`]).
+//! 4) The peer computes `s = k_1⁻¹·Dec(c_3) mod q`, and outputs `(r, s)`
+//!    once it verifies.
+//!
+//! Checking the proofs of a key costs the TA about a hundred Paillier
+//! encryptions, but is done once per key.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::threshold::ecdsa::{self, SigningSession};
+//! # use optee_utee::Result;
+//! # fn send(_: &[u8]) {}
+//! # fn receive() -> [u8; 32] { [0; 32] }
+//! # fn receive_opening() -> [u8; ecdsa::OPENING_LEN] { [0; ecdsa::OPENING_LEN] }
+//! fn sign(message: &[u8]) -> Result<()> {
+//!     let (session, nonce) = SigningSession::start(b"device.share", receive())?;
+//!     send(&nonce);
+//!     send(&session.sign(&receive_opening(), message)?);
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::iter;
+
+use crypto_bigint::{Encoding, NonZero, U1024, U2048, U256, U512};
+use p256::elliptic_curve::ops::Reduce;
+use p256::elliptic_curve::point::AffineCoordinates;
+use p256::elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint};
+use p256::elliptic_curve::{Curve, Field, PrimeField};
+use p256::{AffinePoint, EncodedPoint, FieldBytes, NistP256, ProjectivePoint, Scalar};
+use sha2::{Digest as _, Sha256};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::paillier::{Ciphertext, PublicKey, CIPHERTEXT_LEN, MODULUS_LEN, MODULUS_PROOF_LEN};
+use crate::secret::wipe;
+use crate::{DataFlag, Error, ErrorKind, ObjectStorageConstants, PersistentObject, Random, Result};
+
+pub use super::paillier::MODULUS_PROOF_ROOTS;
+
+/// Length of an encoded point.
+pub const POINT_LEN: usize = 33;
+/// Length of an encoded scalar.
+pub const SCALAR_LEN: usize = 32;
+/// Length of a commitment, and of the salt of its opening.
+pub const COMMITMENT_LEN: usize = 32;
+/// Length of a point followed by the proof of knowledge of its logarithm,
+/// as the TA sends its public share and its nonce.
+pub const PROVEN_POINT_LEN: usize = 2 * POINT_LEN + SCALAR_LEN;
+/// Length of the opening of a commitment of the peer.
+pub const OPENING_LEN: usize = PROVEN_POINT_LEN + COMMITMENT_LEN;
+/// Number of rounds of the range proof of the peer, each of which a peer
+/// encrypting a share out of range fails with a probability of 1/2.
+pub const RANGE_PROOF_ROUNDS: usize = 40;
+/// Length of the key of the peer: its opening, `N`, `c_key`, the modulus
+/// proof and the ciphertexts of the range proof.
+pub const PEER_KEY_LEN: usize = OPENING_LEN
+    + MODULUS_LEN
+    + CIPHERTEXT_LEN
+    + MODULUS_PROOF_LEN
+    + RANGE_PROOF_ROUNDS * 2 * CIPHERTEXT_LEN;
+/// Length of the challenges of the TA: the bits of the range proof, `c'`
+/// and the commitment to `a || b`.
+pub const CHALLENGE_LEN: usize = RANGE_CHALLENGE_LEN + CIPHERTEXT_LEN + COMMITMENT_LEN;
+/// Length of the opening of the TA of `a || b`, `a` and `b` being 32 and 64
+/// bytes long.
+pub const CHALLENGE_OPENING_LEN: usize = 3 * SCALAR_LEN + COMMITMENT_LEN;
+/// Length of the opening of the peer of `Q^`.
+pub const ANSWER_LEN: usize = POINT_LEN + COMMITMENT_LEN;
+/// Length of the encrypted partial signature `c_3`.
+pub const PARTIAL_SIGNATURE_LEN: usize = CIPHERTEXT_LEN;
+
+const KEY_TAG: &[u8] = b"fw.threshold.ecdsa.key";
+const NONCE_TAG: &[u8] = b"fw.threshold.ecdsa.nonce";
+const PROOF_TAG: &[u8] = b"fw.threshold.ecdsa.dlog";
+const CHALLENGE_TAG: &[u8] = b"fw.threshold.ecdsa.pdl.challenge";
+const ANSWER_TAG: &[u8] = b"fw.threshold.ecdsa.pdl.answer";
+const RANGE_CHALLENGE_LEN: usize = RANGE_PROOF_ROUNDS.div_ceil(8);
+// x_2, Q_1, N and c_key.
+const SHARE_LEN: usize = SCALAR_LEN + POINT_LEN + MODULUS_LEN + CIPHERTEXT_LEN;
+// Bits drawn beyond a bound for uniform randomness.
+const STATISTICAL_BYTES: usize = 16;
+
+/// Generation of the share of the TA, through the steps of the protocol
+/// that involve it. Every step consumes the generation, which is abandoned
+/// on the first error.
+pub struct KeyGeneration {
+    share_id: Vec<u8>,
+    secret: Zeroizing<Scalar>,
+    stage: Stage,
+}
+
+enum Stage {
+    // Waiting for the key of the peer, with the commitment to its opening.
+    Started([u8; COMMITMENT_LEN]),
+    // Waiting for the answers to the range proof.
+    Challenged(Box<PeerKey>),
+    // Waiting for the opening of `Q^`, with its commitment.
+    Opened(Box<PeerKey>, [u8; COMMITMENT_LEN]),
+}
+
+// Key of the peer being checked, with the challenges of the TA.
+struct PeerKey {
+    public: AffinePoint,
+    paillier: PublicKey,
+    encrypted_share: Ciphertext,
+    range_proof: Vec<[Ciphertext; 2]>,
+    range_challenge: [u8; RANGE_CHALLENGE_LEN],
+    // `a`, `b` and the salt of the commitment to them, and `a·Q_1 + b·G`.
+    a: Scalar,
+    b: U512,
+    salt: [u8; COMMITMENT_LEN],
+    expected: ProjectivePoint,
+}
+
+impl KeyGeneration {
+    /// Starts the generation of the share `share_id` with the commitment of
+    /// the peer to its public share, and returns the public share of the TA
+    /// and its proof, to send to the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `AccessConflict`: If the object `share_id` already exists.
+    /// 2) Errors from accessing the persistent storage.
+    pub fn start(
+        share_id: &[u8],
+        peer_commitment: [u8; COMMITMENT_LEN],
+    ) -> Result<(Self, [u8; PROVEN_POINT_LEN])> {
+        match PersistentObject::open(
+            ObjectStorageConstants::Private,
+            share_id,
+            DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+        ) {
+            Ok(_) => return Err(Error::new(ErrorKind::AccessConflict)),
+            Err(e) if e.kind() == ErrorKind::ItemNotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(Self::start_with(
+            share_id,
+            peer_commitment,
+            &mut Random::generate,
+        ))
+    }
+
+    fn start_with(
+        share_id: &[u8],
+        peer_commitment: [u8; COMMITMENT_LEN],
+        random: &mut dyn FnMut(&mut [u8]),
+    ) -> (Self, [u8; PROVEN_POINT_LEN]) {
+        let secret = Zeroizing::new(random_scalar(random));
+        let message = prove(&secret, random);
+        let generation = KeyGeneration {
+            share_id: share_id.to_vec(),
+            secret,
+            stage: Stage::Started(peer_commitment),
+        };
+        (generation, message)
+    }
+
+    /// Takes the key of the peer, of [`PEER_KEY_LEN`] bytes, and returns
+    /// the challenges of the TA, of [`CHALLENGE_LEN`] bytes, to send to the
+    /// peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If it is not the step following
+    ///    [`start`](Self::start).
+    /// 2) `BadParameters`: If the key is malformed, or the modulus is not
+    ///    one of 2048 bits without small factors.
+    /// 3) `Security`: If the opening does not match the commitment, or a
+    ///    proof is wrong.
+    pub fn challenge(self, peer_key: &[u8]) -> Result<(Self, Vec<u8>)> {
+        self.challenge_with(peer_key, &mut Random::generate)
+    }
+
+    fn challenge_with(
+        self,
+        peer_key: &[u8],
+        random: &mut dyn FnMut(&mut [u8]),
+    ) -> Result<(Self, Vec<u8>)> {
+        let commitment = match self.stage {
+            Stage::Started(commitment) => commitment,
+            _ => return Err(Error::new(ErrorKind::BadState)),
+        };
+        if peer_key.len() != PEER_KEY_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let mut reader = Reader(peer_key);
+        let public = open(KEY_TAG, &commitment, reader.take(OPENING_LEN)?)?;
+        let paillier = PublicKey::from_bytes(reader.take(MODULUS_LEN)?)?;
+        let encrypted_share = paillier.ciphertext(reader.take(CIPHERTEXT_LEN)?)?;
+        paillier.verify_modulus_proof(reader.take(MODULUS_PROOF_LEN)?)?;
+        let mut range_proof = Vec::with_capacity(RANGE_PROOF_ROUNDS);
+        for _ in 0..RANGE_PROOF_ROUNDS {
+            range_proof.push([
+                paillier.ciphertext(reader.take(CIPHERTEXT_LEN)?)?,
+                paillier.ciphertext(reader.take(CIPHERTEXT_LEN)?)?,
+            ]);
+        }
+        paillier
+            .check_invertible(iter::once(&encrypted_share).chain(range_proof.iter().flatten()))?;
+
+        let mut range_challenge = [0u8; RANGE_CHALLENGE_LEN];
+        random(&mut range_challenge);
+        let a = random_scalar(random);
+        let b = random_below(&order().square(), random);
+        let mut salt = [0u8; COMMITMENT_LEN];
+        random(&mut salt);
+        let c = paillier.add(
+            &paillier.mul(&encrypted_share, &integer(&a)),
+            &paillier.encrypt_random(&b.resize(), random),
+        );
+        let expected = ProjectivePoint::from(public) * a + ProjectivePoint::GENERATOR * reduce(&b);
+
+        let mut message = Vec::with_capacity(CHALLENGE_LEN);
+        message.extend_from_slice(&range_challenge);
+        message.extend_from_slice(&c.to_bytes());
+        message.extend_from_slice(&commit(
+            CHALLENGE_TAG,
+            &[&a.to_repr(), &b.to_be_bytes(), &salt],
+        ));
+        let peer = PeerKey {
+            public,
+            paillier,
+            encrypted_share,
+            range_proof,
+            range_challenge,
+            a,
+            b,
+            salt,
+            expected,
+        };
+        let generation = KeyGeneration {
+            stage: Stage::Challenged(Box::new(peer)),
+            ..self
+        };
+        Ok((generation, message))
+    }
+
+    /// Takes the answers of the peer to the range proof, followed by its
+    /// commitment to `Q^`, and returns the opening of the TA, to send to
+    /// the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If it is not the step following
+    ///    [`challenge`](Self::challenge).
+    /// 2) `BadParameters`: If the answers are malformed.
+    /// 3) `Security`: If an answer is wrong.
+    pub fn open(self, answers: &[u8]) -> Result<(Self, [u8; CHALLENGE_OPENING_LEN])> {
+        let peer = match self.stage {
+            Stage::Challenged(peer) => peer,
+            _ => return Err(Error::new(ErrorKind::BadState)),
+        };
+        let answer_commitment = peer.check_range_proof(answers)?;
+        let mut opening = [0u8; CHALLENGE_OPENING_LEN];
+        opening[..SCALAR_LEN].copy_from_slice(&peer.a.to_repr());
+        opening[SCALAR_LEN..3 * SCALAR_LEN].copy_from_slice(&peer.b.to_be_bytes());
+        opening[3 * SCALAR_LEN..].copy_from_slice(&peer.salt);
+        let generation = KeyGeneration {
+            stage: Stage::Opened(peer, answer_commitment),
+            ..self
+        };
+        Ok((generation, opening))
+    }
+
+    /// Takes the opening of the peer of `Q^`, stores the share of the TA in
+    /// the object `share_id` of the TA private storage and returns the
+    /// joint public key.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If it is not the step following [`open`](Self::open).
+    /// 2) `BadParameters`: If `Q^` is not the encoding of a point.
+    /// 3) `Security`: If the opening does not match the commitment, or
+    ///    `c_key` does not encrypt the logarithm of `Q_1`.
+    /// 4) `AccessConflict`: If the object `share_id` already exists.
+    /// 5) Errors from accessing the persistent storage.
+    pub fn finish(self, answer: &[u8; ANSWER_LEN]) -> Result<[u8; POINT_LEN]> {
+        let share = self.conclude(answer)?;
+        let mut bytes = share.to_bytes();
+        let result = PersistentObject::create(
+            ObjectStorageConstants::Private,
+            &self.share_id,
+            DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE,
+            None,
+            &bytes,
+        )
+        .map(|_| share.public_key());
+        wipe(&mut bytes);
+        result
+    }
+
+    // Checks the opening of `Q^` and returns the share.
+    fn conclude(&self, answer: &[u8; ANSWER_LEN]) -> Result<Share> {
+        let (peer, commitment) = match &self.stage {
+            Stage::Opened(peer, commitment) => (peer, commitment),
+            _ => return Err(Error::new(ErrorKind::BadState)),
+        };
+        if commit(ANSWER_TAG, &[answer]) != *commitment {
+            return Err(Error::new(ErrorKind::Security));
+        }
+        if ProjectivePoint::from(decode(&answer[..POINT_LEN])?) != peer.expected {
+            return Err(Error::new(ErrorKind::Security));
+        }
+        Ok(Share {
+            secret: self.secret.clone(),
+            peer_public: peer.public,
+            paillier: peer.paillier.clone(),
+            encrypted_share: peer.encrypted_share,
+        })
+    }
+}
+
+impl PeerKey {
+    // Checks the answers to the range proof, which show that `c_key`
+    // encrypts a value in `]-q/3, 2q/3[`, and returns the commitment to
+    // `Q^` following them.
+    fn check_range_proof(&self, answers: &[u8]) -> Result<[u8; COMMITMENT_LEN]> {
+        let third = order().wrapping_div(&U256::from_u8(3));
+        let in_range = |value: &U256| *value >= third && *value < third.wrapping_add(&third);
+        let mut reader = Reader(answers);
+        for (round, pair) in self.range_proof.iter().enumerate() {
+            if self.range_challenge[round / 8] >> (round % 8) & 1 == 0 {
+                let mut opened = [(U256::ZERO, U2048::ZERO); 2];
+                for (w, r) in opened.iter_mut() {
+                    *w = U256::from_be_slice(reader.take(SCALAR_LEN)?);
+                    *r = U2048::from_be_slice(reader.take(MODULUS_LEN)?);
+                }
+                let [(w_1, _), (w_2, _)] = opened;
+                let paired = (in_range(&w_1) && w_2.wrapping_add(&third) == w_1)
+                    || (in_range(&w_2) && w_1.wrapping_add(&third) == w_2);
+                if !paired
+                    || opened
+                        .iter()
+                        .zip(pair)
+                        .any(|((w, r), c)| self.paillier.encrypt(&w.resize(), r) != *c)
+                {
+                    return Err(Error::new(ErrorKind::Security));
+                }
+            } else {
+                let c = pair
+                    .get(reader.take(1)?[0] as usize)
+                    .ok_or_else(|| Error::new(ErrorKind::BadParameters))?;
+                let z = U256::from_be_slice(reader.take(SCALAR_LEN)?);
+                let r = U2048::from_be_slice(reader.take(MODULUS_LEN)?);
+                if !in_range(&z)
+                    || self.paillier.add(&self.encrypted_share, c)
+                        != self.paillier.encrypt(&z.resize(), &r)
+                {
+                    return Err(Error::new(ErrorKind::Security));
+                }
+            }
+        }
+        let mut commitment = [0u8; COMMITMENT_LEN];
+        commitment.copy_from_slice(reader.take(COMMITMENT_LEN)?);
+        if !reader.0.is_empty() {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        Ok(commitment)
+    }
+}
+
+/// One signature in progress. Every session draws a fresh nonce and is
+/// consumed by the step producing the next message, so a nonce can never
+/// sign twice.
+pub struct SigningSession {
+    share_id: Vec<u8>,
+    nonce: Zeroizing<Scalar>,
+    peer_commitment: [u8; COMMITMENT_LEN],
+}
+
+impl SigningSession {
+    /// Starts a signature with the share `share_id` and the commitment of
+    /// the peer to its nonce, and returns the nonce of the TA and its
+    /// proof, to send to the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If the object `share_id` does not exist.
+    /// 2) `CorruptObject`: If the stored share is malformed.
+    /// 3) Errors from accessing the persistent storage.
+    pub fn start(
+        share_id: &[u8],
+        peer_commitment: [u8; COMMITMENT_LEN],
+    ) -> Result<(Self, [u8; PROVEN_POINT_LEN])> {
+        load(share_id)?;
+        Ok(Self::start_with(
+            share_id,
+            peer_commitment,
+            &mut Random::generate,
+        ))
+    }
+
+    fn start_with(
+        share_id: &[u8],
+        peer_commitment: [u8; COMMITMENT_LEN],
+        random: &mut dyn FnMut(&mut [u8]),
+    ) -> (Self, [u8; PROVEN_POINT_LEN]) {
+        let nonce = Zeroizing::new(random_scalar(random));
+        let message = prove(&nonce, random);
+        let session = SigningSession {
+            share_id: share_id.to_vec(),
+            nonce,
+            peer_commitment,
+        };
+        (session, message)
+    }
+
+    /// Takes the opening of the peer of its nonce, and returns the partial
+    /// signature of `message` by the TA, `c_3`, of
+    /// [`PARTIAL_SIGNATURE_LEN`] bytes, to send to the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the nonce of the peer is not the encoding of
+    ///    a point.
+    /// 2) `Security`: If the opening does not match the commitment, or the
+    ///    proof is wrong.
+    /// 3) Errors from reading the share.
+    pub fn sign(self, peer_opening: &[u8; OPENING_LEN], message: &[u8]) -> Result<Vec<u8>> {
+        let share = load(&self.share_id)?;
+        let signature = self.sign_with(&share, peer_opening, message, &mut Random::generate)?;
+        Ok(signature.to_bytes().to_vec())
+    }
+
+    fn sign_with(
+        &self,
+        share: &Share,
+        peer_opening: &[u8; OPENING_LEN],
+        message: &[u8],
+        random: &mut dyn FnMut(&mut [u8]),
+    ) -> Result<Ciphertext> {
+        let peer_nonce = open(NONCE_TAG, &self.peer_commitment, peer_opening)?;
+        let point = (ProjectivePoint::from(peer_nonce) * *self.nonce).to_affine();
+        let r = <Scalar as Reduce<U256>>::reduce_bytes(&point.x());
+        let m = <Scalar as Reduce<U256>>::reduce_bytes(&Sha256::digest(message));
+        // The nonce was drawn nonzero.
+        let inverse = Zeroizing::new(self.nonce.invert().unwrap());
+
+        // ρ·q + k_2⁻¹·m, below q³ + q, so that ρ·q hides the plaintext
+        // added by c_key.
+        let rho = random_below(&order().square(), random);
+        let masked = rho
+            .resize::<{ U2048::LIMBS }>()
+            .wrapping_mul(&order())
+            .wrapping_add(&integer(&(*inverse * m)).resize());
+        let mut weight = integer(&(*inverse * r * *share.secret));
+        let signature = share.paillier.add(
+            &share.paillier.encrypt_random(&masked, random),
+            &share.paillier.mul(&share.encrypted_share, &weight),
+        );
+        weight.zeroize();
+        Ok(signature)
+    }
+}
+
+/// Returns the joint public key of the share `share_id`.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If the object `share_id` does not exist.
+/// 2) `CorruptObject`: If the stored share is malformed.
+/// 3) Errors from accessing the persistent storage.
+pub fn public_key(share_id: &[u8]) -> Result<[u8; POINT_LEN]> {
+    Ok(load(share_id)?.public_key())
+}
+
+// Share of the TA: its secret, and the public share and Paillier key of the
+// peer with the share of the peer encrypted under it.
+struct Share {
+    secret: Zeroizing<Scalar>,
+    peer_public: AffinePoint,
+    paillier: PublicKey,
+    encrypted_share: Ciphertext,
+}
+
+impl Share {
+    fn public_key(&self) -> [u8; POINT_LEN] {
+        encode(&(ProjectivePoint::from(self.peer_public) * *self.secret))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SHARE_LEN);
+        bytes.extend_from_slice(&self.secret.to_repr());
+        bytes.extend_from_slice(&encode(&self.peer_public.into()));
+        bytes.extend_from_slice(&self.paillier.to_bytes());
+        bytes.extend_from_slice(&self.encrypted_share.to_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader(bytes);
+        let secret = Zeroizing::new(scalar(reader.take(SCALAR_LEN)?)?);
+        let peer_public = decode(reader.take(POINT_LEN)?)?;
+        let paillier = PublicKey::from_bytes(reader.take(MODULUS_LEN)?)?;
+        let encrypted_share = paillier.ciphertext(reader.take(CIPHERTEXT_LEN)?)?;
+        Ok(Share {
+            secret,
+            peer_public,
+            paillier,
+            encrypted_share,
+        })
+    }
+}
+
+fn load(share_id: &[u8]) -> Result<Share> {
+    let object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        share_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    )?;
+    let mut bytes = vec![0u8; SHARE_LEN + 1];
+    let read = object.read(&mut bytes)? as usize;
+    let share = match read {
+        SHARE_LEN => {
+            Share::from_bytes(&bytes[..read]).map_err(|_| Error::new(ErrorKind::CorruptObject))
+        }
+        _ => Err(Error::new(ErrorKind::CorruptObject)),
+    };
+    wipe(&mut bytes);
+    share
+}
+
+// Reads the consecutive fields of a message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(field)
+    }
+}
+
+// `x·G` followed by the proof of knowledge of `x`.
+fn prove(secret: &Scalar, random: &mut dyn FnMut(&mut [u8])) -> [u8; PROVEN_POINT_LEN] {
+    let public = encode(&(ProjectivePoint::GENERATOR * secret));
+    let k = Zeroizing::new(random_scalar(random));
+    let t = encode(&(ProjectivePoint::GENERATOR * *k));
+    let z = *k + proof_challenge(&public, &t) * secret;
+    let mut message = [0u8; PROVEN_POINT_LEN];
+    message[..POINT_LEN].copy_from_slice(&public);
+    message[POINT_LEN..2 * POINT_LEN].copy_from_slice(&t);
+    message[2 * POINT_LEN..].copy_from_slice(&z.to_repr());
+    message
+}
+
+// Checks an opening of `commitment` and the proof in it, and returns the
+// point.
+fn open(tag: &[u8], commitment: &[u8; COMMITMENT_LEN], opening: &[u8]) -> Result<AffinePoint> {
+    if commit(tag, &[opening]) != *commitment {
+        return Err(Error::new(ErrorKind::Security));
+    }
+    let mut reader = Reader(opening);
+    let public_bytes = reader.take(POINT_LEN)?;
+    let t_bytes = reader.take(POINT_LEN)?;
+    let public = decode(public_bytes)?;
+    let t = decode(t_bytes)?;
+    let z = scalar(reader.take(SCALAR_LEN)?)?;
+    let challenge = proof_challenge(public_bytes, t_bytes);
+    if ProjectivePoint::GENERATOR * z != ProjectivePoint::from(public) * challenge + t {
+        return Err(Error::new(ErrorKind::Security));
+    }
+    Ok(public)
+}
+
+fn proof_challenge(public: &[u8], t: &[u8]) -> Scalar {
+    let hash = Sha256::new()
+        .chain_update(PROOF_TAG)
+        .chain_update(public)
+        .chain_update(t)
+        .finalize();
+    <Scalar as Reduce<U256>>::reduce_bytes(&hash)
+}
+
+fn commit(tag: &[u8], parts: &[&[u8]]) -> [u8; COMMITMENT_LEN] {
+    let mut digest = Sha256::new_with_prefix(tag);
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize().into()
+}
+
+fn encode(point: &ProjectivePoint) -> [u8; POINT_LEN] {
+    let mut encoded = [0u8; POINT_LEN];
+    encoded.copy_from_slice(point.to_affine().to_encoded_point(true).as_bytes());
+    encoded
+}
+
+fn decode(encoded: &[u8]) -> Result<AffinePoint> {
+    EncodedPoint::from_bytes(encoded)
+        .ok()
+        .filter(|point| point.is_compressed())
+        .and_then(|point| Option::from(AffinePoint::from_encoded_point(&point)))
+        .ok_or_else(|| Error::new(ErrorKind::BadParameters))
+}
+
+fn scalar(encoded: &[u8]) -> Result<Scalar> {
+    Option::from(Scalar::from_repr(*FieldBytes::from_slice(encoded)))
+        .ok_or_else(|| Error::new(ErrorKind::BadParameters))
+}
+
+fn order() -> U256 {
+    NistP256::ORDER
+}
+
+fn integer(scalar: &Scalar) -> U256 {
+    U256::from_be_slice(&scalar.to_repr())
+}
+
+// `value` modulo the group order.
+fn reduce(value: &U512) -> Scalar {
+    let reduced = value.rem(&NonZero::new(order().resize()).unwrap());
+    <Scalar as Reduce<U256>>::reduce(reduced.resize())
+}
+
+// Uniform integer below `bound`, of at most 512 bits, from 128 bits more
+// reduced modulo `bound`.
+fn random_below(bound: &U512, random: &mut dyn FnMut(&mut [u8])) -> U512 {
+    let mut bytes = [0u8; U1024::BYTES];
+    random(&mut bytes[U1024::BYTES - U512::BYTES - STATISTICAL_BYTES..]);
+    let mut wide = U1024::from_be_slice(&bytes);
+    wipe(&mut bytes);
+    let reduced = wide.rem(&NonZero::new(bound.resize()).unwrap()).resize();
+    wide.zeroize();
+    reduced
+}
+
+// Uniform nonzero scalar.
+fn random_scalar(random: &mut dyn FnMut(&mut [u8])) -> Scalar {
+    loop {
+        let mut value = random_below(&order().resize(), random);
+        let scalar = reduce(&value);
+        value.zeroize();
+        if !bool::from(scalar.is_zero()) {
+            return scalar;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+    use crypto_bigint::{Limb, U4096};
+    use once_cell::sync::Lazy;
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::{Signature, VerifyingKey};
+    use rand::RngCore;
+
+    // Primes of the Paillier key of the peer.
+    const P: &str = concat!(
+        "e281b60238014cf6ddb1776b8f44a3137a2d8d90481774f01a2f81cd48f04841",
+        "aaf7018670244a76d97ca70f229bfb67a3dec231939d3b52ed53604ee025abfc",
+        "e461908441410dc27e94e189ce1a145e777e809bb1d92dc0f4d85c64ad55babb",
+        "bc0cd7e25808fdf27cd1f1c2ea8f28f2e353db60eb9448369695193f701988d1",
+    );
+    const Q: &str = concat!(
+        "fa4a0fc37e0daa81f46d56fd1d4d40ecdac66bbfcecfdcbe95f9f15045631511",
+        "2dceda8423460a4721aae62dd6f2f0ff916c48cfa59a51beaa5ae6e8d6880849",
+        "7465c7257b4d2246954e81cdd7e5907a3194ae57ba2517468e75565df032489f",
+        "3ff3163514ad0cf38a2fdd5cbe79d8d04f81c2c7a094231a6c8208d28674b865",
+    );
+
+    // Computing the key of the peer takes a hundred encryptions, so the
+    // tests share it.
+    static PEER: Lazy<Peer> = Lazy::new(Peer::new);
+
+    fn fill(buf: &mut [u8]) {
+        rand::thread_rng().fill_bytes(buf);
+    }
+
+    fn third() -> U256 {
+        order().wrapping_div(&U256::from_u8(3))
+    }
+
+    fn scalar_of(value: &U2048) -> Scalar {
+        let reduced = value.rem(&NonZero::new(order().resize()).unwrap());
+        <Scalar as Reduce<U256>>::reduce(reduced.resize())
+    }
+
+    // The party `P1`, which holds the Paillier key and finishes the
+    // signatures.
+    struct Peer {
+        secret: U256,
+        n: U2048,
+        phi: U2048,
+        commitment: [u8; COMMITMENT_LEN],
+        key: Vec<u8>,
+        share_randomness: U2048,
+        // Plaintexts and randomness of the ciphertexts of the range proof.
+        range_proof: Vec<[(U256, U2048); 2]>,
+    }
+
+    impl Peer {
+        fn new() -> Self {
+            let secret = random_below(&third().resize(), &mut fill).resize();
+            let (p, q) = (U1024::from_be_hex(P), U1024::from_be_hex(Q));
+            let n: U2048 = p.mul(&q);
+            let phi: U2048 = p
+                .wrapping_sub(&U1024::ONE)
+                .mul(&q.wrapping_sub(&U1024::ONE));
+            let paillier = PublicKey::from_bytes(&n.to_be_bytes()).unwrap();
+
+            let mut key = prove(&reduce(&secret.resize()), &mut fill).to_vec();
+            key.extend_from_slice(&[0x5a; COMMITMENT_LEN]);
+            let commitment = commit(KEY_TAG, &[&key]);
+            key.extend_from_slice(&n.to_be_bytes());
+            let share_randomness = paillier.random_below_n(&mut fill);
+            let encrypted_share = paillier.encrypt(&secret.resize(), &share_randomness);
+            key.extend_from_slice(&encrypted_share.to_bytes());
+
+            let (root, invertible) = n.inv_mod(&phi);
+            assert!(bool::from(invertible));
+            for index in 0..MODULUS_PROOF_ROOTS {
+                let challenge = paillier.modulus_proof_challenge(index);
+                let params = DynResidueParams::new(&n);
+                key.extend_from_slice(
+                    &DynResidue::new(&challenge, params)
+                        .pow(&root)
+                        .retrieve()
+                        .to_be_bytes(),
+                );
+            }
+
+            let mut range_proof = Vec::new();
+            for _ in 0..RANGE_PROOF_ROUNDS {
+                let w = third().wrapping_add(&random_below(&third().resize(), &mut fill).resize());
+                let mut pair =
+                    [w, w.wrapping_sub(&third())].map(|w| (w, paillier.random_below_n(&mut fill)));
+                if rand::random() {
+                    pair.swap(0, 1);
+                }
+                for (w, r) in &pair {
+                    key.extend_from_slice(&paillier.encrypt(&w.resize(), r).to_bytes());
+                }
+                range_proof.push(pair);
+            }
+            assert_eq!(key.len(), PEER_KEY_LEN);
+            Peer {
+                secret,
+                n,
+                phi,
+                commitment,
+                key,
+                share_randomness,
+                range_proof,
+            }
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> U2048 {
+            let params = DynResidueParams::new(&self.n.square());
+            let c = DynResidue::new(&U4096::from_be_slice(ciphertext), params);
+            let l = c
+                .pow(&self.phi)
+                .retrieve()
+                .wrapping_sub(&U4096::ONE)
+                .wrapping_div(&self.n.resize())
+                .resize();
+            let params = DynResidueParams::new(&self.n);
+            let (phi_inverse, _) = self.phi.inv_odd_mod(&self.n);
+            DynResidue::new(&l, params)
+                .mul(&DynResidue::new(&phi_inverse, params))
+                .retrieve()
+        }
+
+        // Answers the challenges of the TA. Returns the answers to the range
+        // proof followed by the commitment to `Q^`, the opening of `Q^` and
+        // the decryption of `c'`.
+        fn answer(&self, challenges: &[u8]) -> (Vec<u8>, [u8; ANSWER_LEN], U2048) {
+            let mut answers = Vec::new();
+            for (round, pair) in self.range_proof.iter().enumerate() {
+                if challenges[round / 8] >> (round % 8) & 1 == 0 {
+                    for (w, r) in pair {
+                        answers.extend_from_slice(&w.to_be_bytes());
+                        answers.extend_from_slice(&r.to_be_bytes());
+                    }
+                    continue;
+                }
+                let in_range = |z: &U256| *z >= third() && *z < third().wrapping_add(&third());
+                let (index, (w, r)) = pair
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (w, _))| in_range(&self.secret.wrapping_add(w)))
+                    .unwrap();
+                let params = DynResidueParams::new(&self.n);
+                let randomness = DynResidue::new(&self.share_randomness, params)
+                    .mul(&DynResidue::new(r, params));
+                answers.push(index as u8);
+                answers.extend_from_slice(&self.secret.wrapping_add(w).to_be_bytes());
+                answers.extend_from_slice(&randomness.retrieve().to_be_bytes());
+            }
+            let alpha = self.decrypt(&challenges[RANGE_CHALLENGE_LEN..][..CIPHERTEXT_LEN]);
+            let mut answer = [0u8; ANSWER_LEN];
+            answer[..POINT_LEN]
+                .copy_from_slice(&encode(&(ProjectivePoint::GENERATOR * scalar_of(&alpha))));
+            fill(&mut answer[POINT_LEN..]);
+            answers.extend_from_slice(&commit(ANSWER_TAG, &[&answer]));
+            (answers, answer, alpha)
+        }
+
+        // Checks the opening of the TA of `a` and `b`, before opening `Q^`.
+        fn accepts(
+            &self,
+            challenges: &[u8],
+            opening: &[u8; CHALLENGE_OPENING_LEN],
+            alpha: &U2048,
+        ) -> bool {
+            let a = U256::from_be_slice(&opening[..SCALAR_LEN]);
+            let b = U512::from_be_slice(&opening[SCALAR_LEN..3 * SCALAR_LEN]);
+            let expected: U512 = a.mul(&self.secret);
+            commit(CHALLENGE_TAG, &[opening]) == challenges[RANGE_CHALLENGE_LEN + CIPHERTEXT_LEN..]
+                && expected.wrapping_add(&b).resize() == *alpha
+        }
+
+        // Draws a nonce, and returns it with the opening of the commitment
+        // to it.
+        fn nonce(&self) -> (Scalar, [u8; OPENING_LEN]) {
+            let nonce = random_scalar(&mut fill);
+            let mut opening = [0u8; OPENING_LEN];
+            opening[..PROVEN_POINT_LEN].copy_from_slice(&prove(&nonce, &mut fill));
+            fill(&mut opening[PROVEN_POINT_LEN..]);
+            (nonce, opening)
+        }
+
+        fn finish(
+            &self,
+            nonce: &Scalar,
+            ta_nonce: &[u8; PROVEN_POINT_LEN],
+            partial: &[u8],
+        ) -> Signature {
+            let point = ProjectivePoint::from(decode(&ta_nonce[..POINT_LEN]).unwrap()) * nonce;
+            let r = <Scalar as Reduce<U256>>::reduce_bytes(&point.to_affine().x());
+            let s = scalar_of(&self.decrypt(partial)) * nonce.invert().unwrap();
+            let signature = Signature::from_scalars(r.to_repr(), s.to_repr()).unwrap();
+            signature.normalize_s().unwrap_or(signature)
+        }
+    }
+
+    fn challenged() -> (KeyGeneration, [u8; PROVEN_POINT_LEN], Vec<u8>) {
+        let (generation, public_share) =
+            KeyGeneration::start_with(b"share", PEER.commitment, &mut fill);
+        let (generation, challenges) = generation.challenge_with(&PEER.key, &mut fill).unwrap();
+        (generation, public_share, challenges)
+    }
+
+    #[test]
+    fn shares_sign_under_the_joint_key() {
+        let (generation, public_share, challenges) = challenged();
+        let (answers, answer, alpha) = PEER.answer(&challenges);
+        let (mut generation, opening) = generation.open(&answers).unwrap();
+        assert!(PEER.accepts(&challenges, &opening, &alpha));
+
+        let mut wrong_salt = answer;
+        wrong_salt[ANSWER_LEN - 1] ^= 1;
+        let refused = generation.conclude(&wrong_salt).err().unwrap();
+        assert_eq!(refused.kind(), ErrorKind::Security);
+        // A peer committing to the wrong point, as a peer whose c_key does
+        // not encrypt x_1 would.
+        let mut wrong_point = answer;
+        wrong_point[..POINT_LEN].copy_from_slice(&encode(&ProjectivePoint::GENERATOR));
+        if let Stage::Opened(_, commitment) = &mut generation.stage {
+            let committed = *commitment;
+            *commitment = commit(ANSWER_TAG, &[&wrong_point]);
+            let refused = generation.conclude(&wrong_point).err().unwrap();
+            assert_eq!(refused.kind(), ErrorKind::Security);
+            generation.stage = match generation.stage {
+                Stage::Opened(peer, _) => Stage::Opened(peer, committed),
+                _ => unreachable!(),
+            };
+        }
+
+        let share = generation.conclude(&answer).unwrap();
+        let share = Share::from_bytes(&share.to_bytes()).unwrap();
+        let peer_secret = reduce(&PEER.secret.resize());
+        let joint =
+            ProjectivePoint::from(decode(&public_share[..POINT_LEN]).unwrap()) * peer_secret;
+        assert_eq!(share.public_key(), encode(&joint));
+
+        let key = VerifyingKey::from_sec1_bytes(&share.public_key()).unwrap();
+        for message in [&b""[..], b"abc", &[0x5a; 1000]] {
+            let (nonce, opening) = PEER.nonce();
+            let commitment = commit(NONCE_TAG, &[&opening]);
+            let (session, ta_nonce) = SigningSession::start_with(b"share", commitment, &mut fill);
+            let partial = session
+                .sign_with(&share, &opening, message, &mut fill)
+                .unwrap();
+            let signature = PEER.finish(&nonce, &ta_nonce, &partial.to_bytes());
+            key.verify(message, &signature).unwrap();
+            assert!(key.verify(b"other", &signature).is_err());
+
+            let (session, _) = SigningSession::start_with(b"share", [0; COMMITMENT_LEN], &mut fill);
+            let refused = session
+                .sign_with(&share, &opening, message, &mut fill)
+                .err()
+                .unwrap();
+            assert_eq!(refused.kind(), ErrorKind::Security);
+        }
+    }
+
+    #[test]
+    fn key_generation_checks_the_opening_of_the_peer() {
+        let (generation, _) = KeyGeneration::start_with(b"share", [0; COMMITMENT_LEN], &mut fill);
+        let refused = generation
+            .challenge_with(&PEER.key, &mut fill)
+            .err()
+            .unwrap();
+        assert_eq!(refused.kind(), ErrorKind::Security);
+
+        // A valid opening with a wrong proof.
+        let mut key = PEER.key.clone();
+        key[PROVEN_POINT_LEN - 1] ^= 1;
+        let commitment = commit(KEY_TAG, &[&key[..OPENING_LEN]]);
+        let (generation, _) = KeyGeneration::start_with(b"share", commitment, &mut fill);
+        let refused = generation.challenge_with(&key, &mut fill).err().unwrap();
+        assert_eq!(refused.kind(), ErrorKind::Security);
+    }
+
+    #[test]
+    fn key_generation_checks_the_modulus() {
+        let with_modulus = |n: &U2048| {
+            let mut key = PEER.key.clone();
+            key[OPENING_LEN..][..MODULUS_LEN].copy_from_slice(&n.to_be_bytes());
+            key
+        };
+        let even = PEER.n.wrapping_sub(&U2048::ONE);
+        let (_, rest) = PEER.n.div_rem_limb(NonZero::new(Limb::from(3u32)).unwrap());
+        let multiple_of_three = PEER
+            .n
+            .wrapping_sub(&U2048::from(rest.0))
+            .wrapping_sub(&U2048::from_u8(3));
+        let mut wrong_proof = PEER.key.clone();
+        wrong_proof[OPENING_LEN + MODULUS_LEN + CIPHERTEXT_LEN + 1] ^= 1;
+        for (key, kind) in [
+            (with_modulus(&even), ErrorKind::BadParameters),
+            (with_modulus(&multiple_of_three), ErrorKind::BadParameters),
+            (
+                PEER.key[..PEER_KEY_LEN - 1].to_vec(),
+                ErrorKind::BadParameters,
+            ),
+            (wrong_proof, ErrorKind::Security),
+        ] {
+            let (generation, _) = KeyGeneration::start_with(b"share", PEER.commitment, &mut fill);
+            let refused = generation.challenge_with(&key, &mut fill).err().unwrap();
+            assert_eq!(refused.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn key_generation_checks_the_range_proof() {
+        let (generation, _, challenges) = challenged();
+        let (answers, ..) = PEER.answer(&challenges);
+        let peer = match &generation.stage {
+            Stage::Challenged(peer) => peer,
+            _ => unreachable!(),
+        };
+        // The last byte of the first plaintext of the first round.
+        let mut wrong = answers;
+        wrong[SCALAR_LEN - 1 + (challenges[0] & 1) as usize] ^= 1;
+        let refused = peer.check_range_proof(&wrong).unwrap_err();
+        assert_eq!(refused.kind(), ErrorKind::Security);
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let (generation, _) = KeyGeneration::start_with(b"share", PEER.commitment, &mut fill);
+        let refused = generation.open(&[]).err().unwrap();
+        assert_eq!(refused.kind(), ErrorKind::BadState);
+        let (generation, _) = KeyGeneration::start_with(b"share", PEER.commitment, &mut fill);
+        let refused = generation.conclude(&[0; ANSWER_LEN]).err().unwrap();
+        assert_eq!(refused.kind(), ErrorKind::BadState);
+    }
+}
@@ -0,0 +1,471 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Two-party (2-of-2) Ed25519 signing, where the TA holds one share of the
+//! key and a peer, such as a server, holds the other. Two-party ECDSA is in
+//! [`ecdsa`]. Requires the `threshold` feature.
+//!
+//! The TA never sees the share of the peer nor the full private key, yet
+//! the combined signatures verify as plain Ed25519 signatures under the
+//! aggregate public key. Messages between the parties go through the CA;
+//! this module only produces and consumes them. The scheme is MuSig with
+//! nonce commitments, over the Ed25519 group, with `H` being SHA-512 read
+//! as a little-endian integer modulo the group order `L`:
+//!
+//! 1) Each party generates a share `x_i` and sends its public share
+//!    `X_i = x_i·B`. The aggregate key is `A = a_1·X_1 + a_2·X_2`, with
+//!    `a_i = H("fw.threshold.agg" || X_1 || X_2 || X_i)`, `X_1` and `X_2`
+//!    sorted bytewise.
+//! 2) To sign, each party draws a nonce `r_i` and sends the first 32 bytes
+//!    of `SHA-512(R_i)`, with `R_i = r_i·B`.
+//! 3) Once it has the commitment of the other, each party sends `R_i`.
+//! 4) Each party checks the nonce of the other against its commitment and
+//!    sends `s_i = r_i + H(R || A || M)·a_i·x_i`, with `R = R_1 + R_2`.
+//! 5) The signature is `R || s_1 + s_2`.
+//!
+//! The group arithmetic is the constant-time one of `curve25519-dalek`.
+//! Public shares and nonces of the peer must be points of the prime-order
+//! subgroup, so that the signatures also pass strict RFC 8032 verifiers.
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::threshold::{self, SigningSession};
+//! # use optee_utee::Result;
+//! # fn send(_: &[u8]) {}
+//! # fn receive() -> [u8; 32] { [0; 32] }
+//! fn sign(message: &[u8]) -> Result<[u8; 64]> {
+//!     let (session, commitment) = SigningSession::start(b"device.share")?;
+//!     send(&commitment);
+//!     let (session, nonce) = session.reveal(receive())?;
+//!     send(&nonce);
+//!     let own = session.sign(receive(), message)?;
+//!     send(&own.s);
+//!     let peer = threshold::PartialSignature { r: own.r, s: receive() };
+//!     threshold::combine(&own, &peer)
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use sha2::{Digest as _, Sha512};
+use zeroize::Zeroize;
+
+use crate::secret::wipe;
+use crate::{DataFlag, Error, ErrorKind, ObjectStorageConstants, PersistentObject, Random, Result};
+
+pub mod ecdsa;
+mod paillier;
+
+/// Length of an encoded point, public share or nonce.
+pub const PUBLIC_LEN: usize = 32;
+/// Length of a scalar, partial signature or commitment.
+pub const SCALAR_LEN: usize = 32;
+
+const AGGREGATION_TAG: &[u8] = b"fw.threshold.agg";
+const SHARE_LEN: usize = SCALAR_LEN;
+const PAIRED_SHARE_LEN: usize = SCALAR_LEN + PUBLIC_LEN;
+
+/// Share of a signature produced by [`SigningSession::sign`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialSignature {
+    /// Encoding of the aggregate nonce `R`, the same for both parties.
+    pub r: [u8; PUBLIC_LEN],
+    /// Little-endian encoding of the share `s_i`.
+    pub s: [u8; SCALAR_LEN],
+}
+
+/// Generates the key share of the TA, stores it in the object `share_id`
+/// of the TA private storage and returns the public share to send to the
+/// peer.
+///
+/// # Errors
+///
+/// 1) `AccessConflict`: If the object `share_id` already exists.
+/// 2) Errors from accessing the persistent storage.
+pub fn generate_share(share_id: &[u8]) -> Result<[u8; PUBLIC_LEN]> {
+    let mut share = random_scalar(&mut Random::generate);
+    let result = PersistentObject::create(
+        ObjectStorageConstants::Private,
+        share_id,
+        DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE,
+        None,
+        share.as_bytes(),
+    )
+    .map(|_| public_share(&share));
+    share.zeroize();
+    result
+}
+
+/// Records the public share of the peer next to the share `share_id` and
+/// returns the aggregate public key, under which the combined signatures
+/// verify.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If the object `share_id` does not exist.
+/// 2) `BadParameters`: If `peer_public` is not the encoding of a point of
+///    the prime-order subgroup.
+/// 3) `BadState`: If the share was already paired with another peer.
+/// 4) `CorruptObject`: If the stored share is malformed.
+/// 5) Errors from accessing the persistent storage.
+pub fn pair(share_id: &[u8], peer_public: [u8; PUBLIC_LEN]) -> Result<[u8; PUBLIC_LEN]> {
+    decode(&peer_public)?;
+    let mut object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        share_id,
+        DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE,
+    )?;
+    let mut share = load(&object)?;
+    let result = (|| {
+        match share.len() {
+            SHARE_LEN => object.write(&peer_public)?,
+            _ if share[SHARE_LEN..] == peer_public => {}
+            _ => return Err(Error::new(ErrorKind::BadState)),
+        }
+        let mut secret = secret(&share)?;
+        let key = aggregate(&public_share(&secret), &peer_public);
+        secret.zeroize();
+        Ok(key?.public)
+    })();
+    wipe(&mut share);
+    result
+}
+
+/// One signature in progress. Every session draws a fresh nonce and is
+/// consumed by the step producing the next message, so a nonce can never
+/// sign twice.
+pub struct SigningSession {
+    share_id: Vec<u8>,
+    nonce: Scalar,
+    peer_commitment: Option<[u8; SCALAR_LEN]>,
+}
+
+impl SigningSession {
+    /// Starts a signature with the share `share_id` and returns the
+    /// commitment to the nonce of the TA, to send to the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If the object `share_id` does not exist.
+    /// 2) `BadState`: If the share is not paired yet.
+    /// 3) Errors from accessing the persistent storage.
+    pub fn start(share_id: &[u8]) -> Result<(Self, [u8; SCALAR_LEN])> {
+        let mut share = load_paired(share_id)?;
+        wipe(&mut share);
+        let session = SigningSession {
+            share_id: share_id.to_vec(),
+            nonce: random_scalar(&mut Random::generate),
+            peer_commitment: None,
+        };
+        let commitment = commitment(&session.public_nonce());
+        Ok((session, commitment))
+    }
+
+    /// Takes the commitment of the peer and returns the nonce of the TA, to
+    /// send to the peer.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If the commitment of the peer was already received.
+    pub fn reveal(mut self, peer_commitment: [u8; SCALAR_LEN]) -> Result<(Self, [u8; PUBLIC_LEN])> {
+        if self.peer_commitment.is_some() {
+            return Err(Error::new(ErrorKind::BadState));
+        }
+        self.peer_commitment = Some(peer_commitment);
+        let nonce = self.public_nonce();
+        Ok((self, nonce))
+    }
+
+    /// Takes the nonce of the peer and returns the share of the TA of the
+    /// signature of `message`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadState`: If [`reveal`](Self::reveal) was not called first.
+    /// 2) `Security`: If `peer_nonce` does not match the commitment of the
+    ///    peer.
+    /// 3) `BadParameters`: If `peer_nonce` is not the encoding of a point of
+    ///    the prime-order subgroup.
+    /// 4) Errors from reading the share.
+    pub fn sign(self, peer_nonce: [u8; PUBLIC_LEN], message: &[u8]) -> Result<PartialSignature> {
+        if self.peer_commitment.is_none() {
+            return Err(Error::new(ErrorKind::BadState));
+        }
+        let mut share = load_paired(&self.share_id)?;
+        let result = (|| {
+            let mut secret = secret(&share)?;
+            let mut peer_public = [0u8; PUBLIC_LEN];
+            peer_public.copy_from_slice(&share[SHARE_LEN..]);
+            let signature = self.sign_with(&secret, &peer_public, &peer_nonce, message);
+            secret.zeroize();
+            signature
+        })();
+        wipe(&mut share);
+        result
+    }
+
+    // Signs `message` with the share `secret`, paired with `peer_public`.
+    fn sign_with(
+        &self,
+        secret: &Scalar,
+        peer_public: &[u8; PUBLIC_LEN],
+        peer_nonce: &[u8; PUBLIC_LEN],
+        message: &[u8],
+    ) -> Result<PartialSignature> {
+        let peer_commitment = self
+            .peer_commitment
+            .ok_or_else(|| Error::new(ErrorKind::BadState))?;
+        if commitment(peer_nonce) != peer_commitment {
+            return Err(Error::new(ErrorKind::Security));
+        }
+        let key = aggregate(&public_share(secret), peer_public)?;
+        let r = (EdwardsPoint::mul_base(&self.nonce) + decode(peer_nonce)?)
+            .compress()
+            .to_bytes();
+        let challenge = hash_scalar(&[&r, &key.public, message]);
+        // s_i = r_i + c·a_i·x_i
+        let s = self.nonce + challenge * key.coefficient * secret;
+        Ok(PartialSignature { r, s: s.to_bytes() })
+    }
+
+    fn public_nonce(&self) -> [u8; PUBLIC_LEN] {
+        EdwardsPoint::mul_base(&self.nonce).compress().to_bytes()
+    }
+}
+
+impl Drop for SigningSession {
+    fn drop(&mut self) {
+        self.nonce.zeroize();
+    }
+}
+
+/// Combines the partial signatures of both parties into an Ed25519
+/// signature. Either party can do it; it involves no secret.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If the partial signatures are not for the same
+///    aggregate nonce, or a share is not a reduced scalar.
+pub fn combine(own: &PartialSignature, peer: &PartialSignature) -> Result<[u8; 64]> {
+    if own.r != peer.r {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let share = |s: &[u8; SCALAR_LEN]| {
+        Option::<Scalar>::from(Scalar::from_canonical_bytes(*s))
+            .ok_or_else(|| Error::new(ErrorKind::BadParameters))
+    };
+    let s = share(&own.s)? + share(&peer.s)?;
+    let mut signature = [0u8; 64];
+    signature[..32].copy_from_slice(&own.r);
+    signature[32..].copy_from_slice(s.as_bytes());
+    Ok(signature)
+}
+
+fn load(object: &PersistentObject) -> Result<Vec<u8>> {
+    let mut share = vec![0u8; PAIRED_SHARE_LEN + 1];
+    let read = object.read(&mut share)? as usize;
+    if read != SHARE_LEN && read != PAIRED_SHARE_LEN {
+        wipe(&mut share);
+        return Err(Error::new(ErrorKind::CorruptObject));
+    }
+    share.truncate(read);
+    Ok(share)
+}
+
+fn load_paired(share_id: &[u8]) -> Result<Vec<u8>> {
+    let object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        share_id,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    )?;
+    let mut share = load(&object)?;
+    if share.len() != PAIRED_SHARE_LEN {
+        wipe(&mut share);
+        return Err(Error::new(ErrorKind::BadState));
+    }
+    Ok(share)
+}
+
+// Reads the scalar at the start of a stored share.
+fn secret(share: &[u8]) -> Result<Scalar> {
+    let mut bytes = [0u8; SCALAR_LEN];
+    bytes.copy_from_slice(&share[..SHARE_LEN]);
+    let secret = Option::<Scalar>::from(Scalar::from_canonical_bytes(bytes));
+    wipe(&mut bytes);
+    secret.ok_or_else(|| Error::new(ErrorKind::CorruptObject))
+}
+
+fn public_share(secret: &Scalar) -> [u8; PUBLIC_LEN] {
+    EdwardsPoint::mul_base(secret).compress().to_bytes()
+}
+
+// Decodes a point of the peer, which must be in the prime-order subgroup.
+fn decode(encoded: &[u8; PUBLIC_LEN]) -> Result<EdwardsPoint> {
+    CompressedEdwardsY(*encoded)
+        .decompress()
+        .filter(|point| !point.is_small_order() && point.is_torsion_free())
+        .ok_or_else(|| Error::new(ErrorKind::BadParameters))
+}
+
+// Aggregate public key and the coefficient of the share of the TA in it.
+struct AggregateKey {
+    public: [u8; PUBLIC_LEN],
+    coefficient: Scalar,
+}
+
+fn aggregate(own: &[u8; PUBLIC_LEN], peer: &[u8; PUBLIC_LEN]) -> Result<AggregateKey> {
+    let (first, second) = if own <= peer {
+        (own, peer)
+    } else {
+        (peer, own)
+    };
+    let coefficient = |public: &[u8]| hash_scalar(&[AGGREGATION_TAG, first, second, public]);
+    let own_coefficient = coefficient(own);
+    let key = own_coefficient * decode(own)? + coefficient(peer) * decode(peer)?;
+    Ok(AggregateKey {
+        public: key.compress().to_bytes(),
+        coefficient: own_coefficient,
+    })
+}
+
+fn commitment(nonce: &[u8; PUBLIC_LEN]) -> [u8; SCALAR_LEN] {
+    let hash = Sha512::digest(nonce);
+    let mut commitment = [0u8; SCALAR_LEN];
+    commitment.copy_from_slice(&hash[..SCALAR_LEN]);
+    commitment
+}
+
+// SHA-512 of the concatenation of `parts`, reduced modulo the group order.
+fn hash_scalar(parts: &[&[u8]]) -> Scalar {
+    let mut digest = Sha512::new();
+    for part in parts {
+        digest.update(part);
+    }
+    Scalar::from_bytes_mod_order_wide(&digest.finalize().into())
+}
+
+// Uniform scalar, from 512 random bits reduced modulo the group order.
+fn random_scalar(random: &mut dyn FnMut(&mut [u8])) -> Scalar {
+    let mut bytes = [0u8; 64];
+    random(&mut bytes);
+    let scalar = Scalar::from_bytes_mod_order_wide(&bytes);
+    wipe(&mut bytes);
+    scalar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use curve25519_dalek::constants::EIGHT_TORSION;
+    use ed25519_dalek::{Signature, VerifyingKey};
+    use rand::RngCore;
+
+    fn fill(buf: &mut [u8]) {
+        rand::thread_rng().fill_bytes(buf);
+    }
+
+    // Signs `message` with the shares of both parties, the way each would
+    // with its own session.
+    fn sign(secrets: [&Scalar; 2], message: &[u8]) -> [u8; 64] {
+        let publics = [public_share(secrets[0]), public_share(secrets[1])];
+        let mut sessions = [0, 1].map(|_| SigningSession {
+            share_id: Vec::new(),
+            nonce: random_scalar(&mut fill),
+            peer_commitment: None,
+        });
+        let nonces = [sessions[0].public_nonce(), sessions[1].public_nonce()];
+        sessions[0].peer_commitment = Some(commitment(&nonces[1]));
+        sessions[1].peer_commitment = Some(commitment(&nonces[0]));
+        let own = sessions[0].sign_with(secrets[0], &publics[1], &nonces[1], message);
+        let peer = sessions[1].sign_with(secrets[1], &publics[0], &nonces[0], message);
+        combine(&own.unwrap(), &peer.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn combined_signatures_verify() {
+        let secrets = [random_scalar(&mut fill), random_scalar(&mut fill)];
+        let publics = [public_share(&secrets[0]), public_share(&secrets[1])];
+        let key = aggregate(&publics[0], &publics[1]).unwrap().public;
+        assert_eq!(key, aggregate(&publics[1], &publics[0]).unwrap().public);
+
+        let key = VerifyingKey::from_bytes(&key).unwrap();
+        for message in [&b""[..], b"abc", &[0x5a; 1000]] {
+            let signature = Signature::from_bytes(&sign([&secrets[0], &secrets[1]], message));
+            key.verify_strict(message, &signature).unwrap();
+            assert!(key.verify_strict(b"other", &signature).is_err());
+        }
+        // Neither share signs alone.
+        let alone = sign([&secrets[0], &random_scalar(&mut fill)], b"abc");
+        assert!(key
+            .verify_strict(b"abc", &Signature::from_bytes(&alone))
+            .is_err());
+    }
+
+    #[test]
+    fn nonces_must_match_their_commitment() {
+        let secret = random_scalar(&mut fill);
+        let peer_public = public_share(&random_scalar(&mut fill));
+        let peer_nonce = public_share(&random_scalar(&mut fill));
+        let session = SigningSession {
+            share_id: Vec::new(),
+            nonce: random_scalar(&mut fill),
+            peer_commitment: Some(commitment(&peer_nonce)),
+        };
+        let other_nonce = public_share(&random_scalar(&mut fill));
+        let refused = session.sign_with(&secret, &peer_public, &other_nonce, b"abc");
+        assert_eq!(refused.unwrap_err().kind(), ErrorKind::Security);
+        assert!(session
+            .sign_with(&secret, &peer_public, &peer_nonce, b"abc")
+            .is_ok());
+    }
+
+    #[test]
+    fn points_outside_the_prime_subgroup_are_refused() {
+        let identity = EdwardsPoint::default().compress().to_bytes();
+        let torsion = EIGHT_TORSION[1];
+        let mixed = (EdwardsPoint::mul_base(&random_scalar(&mut fill)) + torsion)
+            .compress()
+            .to_bytes();
+        let torsion = torsion.compress().to_bytes();
+        for point in [identity, torsion, mixed] {
+            assert_eq!(decode(&point).unwrap_err().kind(), ErrorKind::BadParameters);
+        }
+        assert!(decode(&public_share(&random_scalar(&mut fill))).is_ok());
+    }
+
+    #[test]
+    fn combine_refuses_mismatched_shares() {
+        let own = PartialSignature {
+            r: public_share(&random_scalar(&mut fill)),
+            s: random_scalar(&mut fill).to_bytes(),
+        };
+        let other_nonce = PartialSignature {
+            r: public_share(&random_scalar(&mut fill)),
+            ..own
+        };
+        let unreduced = PartialSignature {
+            s: [0xff; 32],
+            ..own
+        };
+        for peer in [other_nonce, unreduced] {
+            let refused = combine(&own, &peer).unwrap_err();
+            assert_eq!(refused.kind(), ErrorKind::BadParameters);
+        }
+    }
+}
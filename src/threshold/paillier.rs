@@ -0,0 +1,205 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+//! Public-key side of the Paillier cryptosystem, with `g = N + 1`, for the
+//! party of two-party ECDSA that computes on the encrypted share of the
+//! other. The arithmetic is the constant-time one of `crypto-bigint`.
+
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Encoding, Limb, NonZero, U2048, U4096};
+use sha2::{Digest as _, Sha256};
+
+use crate::secret::wipe;
+use crate::{Error, ErrorKind, Result};
+
+/// Length of a modulus `N`.
+pub(super) const MODULUS_LEN: usize = 256;
+/// Length of a ciphertext, modulo `N²`.
+pub(super) const CIPHERTEXT_LEN: usize = 512;
+/// Number of `N`-th roots in a proof that `gcd(N, φ(N)) = 1`. A modulus
+/// without factors below `SMALL_FACTOR_BOUND` that fails it has each root
+/// with a probability below 2^-15, even with the bias of the challenges.
+pub const MODULUS_PROOF_ROOTS: usize = 9;
+/// Length of a proof that `gcd(N, φ(N)) = 1`.
+pub(super) const MODULUS_PROOF_LEN: usize = MODULUS_PROOF_ROOTS * MODULUS_LEN;
+
+const MODULUS_PROOF_TAG: &[u8] = b"fw.threshold.paillier.modulus";
+// Moduli are checked for the factors below it, which bounds the soundness
+// error of each root of a modulus proof.
+const SMALL_FACTOR_BOUND: u64 = 1 << 16;
+// Bits drawn beyond the modulus for uniform randomness.
+const STATISTICAL_BITS: usize = 128;
+
+type Residue = DynResidue<{ U4096::LIMBS }>;
+
+/// Ciphertext, an invertible residue modulo `N²` once checked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Ciphertext(Residue);
+
+impl Ciphertext {
+    pub(super) fn to_bytes(self) -> [u8; CIPHERTEXT_LEN] {
+        self.0.retrieve().to_be_bytes()
+    }
+}
+
+/// Public key of the other party.
+#[derive(Clone)]
+pub(super) struct PublicKey {
+    n: U2048,
+    params: DynResidueParams<{ U4096::LIMBS }>,
+}
+
+impl PublicKey {
+    /// Reads a big-endian modulus, of exactly 2048 bits.
+    ///
+    /// Fails with `BadParameters` if it has another size, is even or has a
+    /// factor below `SMALL_FACTOR_BOUND`.
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != MODULUS_LEN || bytes[0] & 0x80 == 0 || bytes[MODULUS_LEN - 1] & 1 == 0 {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let n = U2048::from_be_slice(bytes);
+        for divisor in (3..SMALL_FACTOR_BOUND).step_by(2) {
+            let divisor = NonZero::new(Limb::from(divisor)).unwrap();
+            if n.div_rem_limb(divisor).1 == Limb::ZERO {
+                return Err(Error::new(ErrorKind::BadParameters));
+            }
+        }
+        Ok(Self {
+            n,
+            params: DynResidueParams::new(&n.square()),
+        })
+    }
+
+    pub(super) fn to_bytes(&self) -> [u8; MODULUS_LEN] {
+        self.n.to_be_bytes()
+    }
+
+    /// Reads a big-endian ciphertext.
+    ///
+    /// Fails with `BadParameters` if it is not smaller than `N²`.
+    pub(super) fn ciphertext(&self, bytes: &[u8]) -> Result<Ciphertext> {
+        if bytes.len() != CIPHERTEXT_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let value = U4096::from_be_slice(bytes);
+        if value >= *self.params.modulus() {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        Ok(Ciphertext(Residue::new(&value, self.params)))
+    }
+
+    /// Checks that all of `ciphertexts` are invertible, i.e. encryptions,
+    /// at the cost of a single inversion.
+    ///
+    /// Fails with `Security` otherwise.
+    pub(super) fn check_invertible<'a>(
+        &self,
+        ciphertexts: impl IntoIterator<Item = &'a Ciphertext>,
+    ) -> Result<()> {
+        let product = ciphertexts
+            .into_iter()
+            .fold(Residue::one(self.params), |product, c| product.mul(&c.0));
+        match bool::from(product.invert().1) {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::Security)),
+        }
+    }
+
+    /// Encrypts `m` with the randomness `r`, as `(1 + m·N)·r^N mod N²`.
+    pub(super) fn encrypt(&self, m: &U2048, r: &U2048) -> Ciphertext {
+        let g_m = m.mul(&self.n).wrapping_add(&U4096::ONE);
+        let r_n = Residue::new(&r.resize(), self.params).pow(&self.n);
+        Ciphertext(Residue::new(&g_m, self.params).mul(&r_n))
+    }
+
+    /// Encrypts `m` with fresh randomness.
+    pub(super) fn encrypt_random(
+        &self,
+        m: &U2048,
+        random: &mut dyn FnMut(&mut [u8]),
+    ) -> Ciphertext {
+        self.encrypt(m, &self.random_below_n(random))
+    }
+
+    /// Returns the encryption of the sum of the plaintexts of `a` and `b`.
+    pub(super) fn add(&self, a: &Ciphertext, b: &Ciphertext) -> Ciphertext {
+        Ciphertext(a.0.mul(&b.0))
+    }
+
+    /// Returns the encryption of the plaintext of `c` times `k`. Takes the
+    /// same time whatever `k`.
+    pub(super) fn mul<const LIMBS: usize>(
+        &self,
+        c: &Ciphertext,
+        k: &crypto_bigint::Uint<LIMBS>,
+    ) -> Ciphertext {
+        Ciphertext(c.0.pow(k))
+    }
+
+    /// Checks a proof that `gcd(N, φ(N)) = 1`, which makes the encryption
+    /// a bijection: the `N`-th roots modulo `N` of the challenges of
+    /// [`modulus_proof_challenge`](Self::modulus_proof_challenge).
+    ///
+    /// Fails with `BadParameters` if the proof is malformed, and with
+    /// `Security` if it is wrong.
+    pub(super) fn verify_modulus_proof(&self, proof: &[u8]) -> Result<()> {
+        if proof.len() != MODULUS_PROOF_LEN {
+            return Err(Error::new(ErrorKind::BadParameters));
+        }
+        let params = DynResidueParams::new(&self.n);
+        for (index, root) in proof.chunks(MODULUS_LEN).enumerate() {
+            let root = U2048::from_be_slice(root);
+            let challenge = self.modulus_proof_challenge(index);
+            if root >= self.n || DynResidue::new(&root, params).pow(&self.n).retrieve() != challenge
+            {
+                return Err(Error::new(ErrorKind::Security));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `index`-th challenge of a modulus proof, derived from
+    /// `N` so that the owner of the key cannot choose it.
+    pub(super) fn modulus_proof_challenge(&self, index: usize) -> U2048 {
+        let mut bytes = [0u8; MODULUS_LEN];
+        for (block, chunk) in bytes.chunks_mut(32).enumerate() {
+            let hash = Sha256::new()
+                .chain_update(MODULUS_PROOF_TAG)
+                .chain_update(self.to_bytes())
+                .chain_update((index as u32).to_be_bytes())
+                .chain_update([block as u8])
+                .finalize();
+            chunk.copy_from_slice(&hash);
+        }
+        // N has its top bit set, so one subtraction reduces.
+        let challenge = U2048::from_be_slice(&bytes);
+        match challenge >= self.n {
+            true => challenge.wrapping_sub(&self.n),
+            false => challenge,
+        }
+    }
+
+    /// Uniform integer below `N`, from 128 bits more reduced modulo `N`.
+    pub(super) fn random_below_n(&self, random: &mut dyn FnMut(&mut [u8])) -> U2048 {
+        let mut bytes = [0u8; CIPHERTEXT_LEN];
+        random(&mut bytes[CIPHERTEXT_LEN - MODULUS_LEN - STATISTICAL_BITS / 8..]);
+        let wide = U4096::from_be_slice(&bytes);
+        wipe(&mut bytes);
+        let n = NonZero::new(self.n.resize()).unwrap();
+        wide.rem(&n).resize()
+    }
+}
@@ -0,0 +1,280 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Two-phase deletion of persistent objects of the TA private storage.
+//!
+//! [`mark_for_deletion`] moves an object out of the way instead of deleting
+//! it: it can no longer be opened under its id, but it can be brought back
+//! with [`restore`] until its grace period is over. It is deleted for good
+//! by [`confirm`], or by [`purge_expired`] once the grace period is over.
+//!
+//! Grace periods are measured with the REE time, as returned by
+//! [`Time::system_time`].
+//!
+//! # Examples
+//!
+//! ``` rust,no_run
+//! # use optee_utee::{trash, Result};
+//! fn delete_key(key_id: &[u8]) -> Result<()> {
+//!     // Keep the key restorable for a week.
+//!     trash::mark_for_deletion(key_id, 7 * 24 * 60 * 60)?;
+//!     Ok(())
+//! }
+//!
+//! fn on_startup() -> Result<()> {
+//!     trash::purge_expired()?;
+//!     Ok(())
+//! }
+//! ```
+
+use alloc::vec::Vec;
+
+use crate::{
+    DataFlag, Error, ErrorKind, GenericObject, ObjectStorageConstants, PersistentObject, Result,
+    Time,
+};
+
+const INDEX_ID: &[u8] = b"fw.trash";
+const OBJECT_ID_PREFIX: &[u8] = b"fw.trash.";
+/// Longest id of an object that can be marked for deletion.
+pub const MAX_OBJECT_ID_LEN: usize = 64 - OBJECT_ID_PREFIX.len();
+
+/// An object marked for deletion.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingDeletion {
+    pub object_id: Vec<u8>,
+    /// REE time, in seconds, after which the object may be purged.
+    pub expires_at: u32,
+}
+
+/// Marks the object `object_id` for deletion, restorable for
+/// `grace_period` seconds.
+///
+/// # Errors
+///
+/// 1) `BadParameters`: If `object_id` is empty or longer than
+///    [`MAX_OBJECT_ID_LEN`].
+/// 2) `ItemNotFound`: If there is no object `object_id`.
+/// 3) `AccessConflict`: If the object is open, or an object `object_id` is
+///    already marked for deletion.
+/// 4) Errors from accessing the persistent storage.
+pub fn mark_for_deletion(object_id: &[u8], grace_period: u32) -> Result<()> {
+    let trash_id = trash_id(object_id)?;
+    let mut object = PersistentObject::open(
+        ObjectStorageConstants::Private,
+        object_id,
+        DataFlag::ACCESS_WRITE_META,
+    )?;
+
+    let mut index = read_index()?;
+    if index.iter().any(|entry| entry.object_id == object_id) {
+        return Err(Error::new(ErrorKind::AccessConflict));
+    }
+    index.push(PendingDeletion {
+        object_id: object_id.to_vec(),
+        expires_at: now().saturating_add(grace_period),
+    });
+    // Record the entry first, so that a trashed object is always listed.
+    write_index(&index)?;
+    object.rename(&trash_id)
+}
+
+/// Brings back the object `object_id` marked for deletion.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If no object `object_id` is marked for deletion.
+/// 2) `AccessConflict`: If a new object `object_id` was created meanwhile.
+/// 3) Errors from accessing the persistent storage.
+pub fn restore(object_id: &[u8]) -> Result<()> {
+    let mut index = read_index()?;
+    let position = find(&index, object_id)?;
+    PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &trash_id(object_id)?,
+        DataFlag::ACCESS_WRITE_META,
+    )?
+    .rename(object_id)?;
+    index.remove(position);
+    write_index(&index)
+}
+
+/// Deletes the object `object_id` marked for deletion, without waiting for
+/// its grace period to be over.
+///
+/// # Errors
+///
+/// 1) `ItemNotFound`: If no object `object_id` is marked for deletion.
+/// 2) Errors from accessing the persistent storage.
+pub fn confirm(object_id: &[u8]) -> Result<()> {
+    let mut index = read_index()?;
+    let position = find(&index, object_id)?;
+    delete_trashed(object_id)?;
+    index.remove(position);
+    write_index(&index)
+}
+
+/// Deletes the objects whose grace period is over and returns their ids.
+///
+/// # Errors
+///
+/// Errors from accessing the persistent storage. Objects deleted before the
+/// error stay deleted.
+pub fn purge_expired() -> Result<Vec<Vec<u8>>> {
+    let mut index = read_index()?;
+    let expired = split_expired(&mut index, now());
+    for entry in &expired {
+        delete_trashed(&entry.object_id)?;
+    }
+    if !expired.is_empty() {
+        write_index(&index)?;
+    }
+    Ok(expired.into_iter().map(|entry| entry.object_id).collect())
+}
+
+/// Lists the objects marked for deletion.
+///
+/// # Errors
+///
+/// Errors from reading the persistent storage.
+pub fn pending() -> Result<Vec<PendingDeletion>> {
+    read_index()
+}
+
+fn trash_id(object_id: &[u8]) -> Result<Vec<u8>> {
+    if object_id.is_empty() || object_id.len() > MAX_OBJECT_ID_LEN {
+        return Err(Error::new(ErrorKind::BadParameters));
+    }
+    let mut trash_id = OBJECT_ID_PREFIX.to_vec();
+    trash_id.extend_from_slice(object_id);
+    Ok(trash_id)
+}
+
+fn find(index: &[PendingDeletion], object_id: &[u8]) -> Result<usize> {
+    index
+        .iter()
+        .position(|entry| entry.object_id == object_id)
+        .ok_or_else(|| Error::new(ErrorKind::ItemNotFound))
+}
+
+// A trashed object already gone, e.g. after an interrupted purge, counts as
+// deleted.
+fn delete_trashed(object_id: &[u8]) -> Result<()> {
+    match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        &trash_id(object_id)?,
+        DataFlag::ACCESS_WRITE_META,
+    ) {
+        Ok(object) => object.close_and_delete(),
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Removes the entries expired at `now` from `index` and returns them.
+fn split_expired(index: &mut Vec<PendingDeletion>, now: u32) -> Vec<PendingDeletion> {
+    let (expired, kept) = index
+        .drain(..)
+        .partition(|entry: &PendingDeletion| entry.expires_at <= now);
+    *index = kept;
+    expired
+}
+
+fn read_index() -> Result<Vec<PendingDeletion>> {
+    let object = match PersistentObject::open(
+        ObjectStorageConstants::Private,
+        INDEX_ID,
+        DataFlag::ACCESS_READ | DataFlag::SHARE_READ,
+    ) {
+        Ok(object) => object,
+        Err(e) if e.kind() == ErrorKind::ItemNotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut data = vec![0u8; object.info()?.data_size()];
+    let read = object.read(&mut data)? as usize;
+    decode_index(&data[..read])
+}
+
+// Replaces the index in a single operation.
+fn write_index(index: &[PendingDeletion]) -> Result<()> {
+    PersistentObject::create(
+        ObjectStorageConstants::Private,
+        INDEX_ID,
+        DataFlag::ACCESS_READ | DataFlag::ACCESS_WRITE | DataFlag::OVERWRITE,
+        None,
+        &encode_index(index),
+    )?;
+    Ok(())
+}
+
+// Each entry is the id length (u8), the id and the expiry (u32 LE).
+fn encode_index(index: &[PendingDeletion]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for entry in index {
+        data.push(entry.object_id.len() as u8);
+        data.extend_from_slice(&entry.object_id);
+        data.extend_from_slice(&entry.expires_at.to_le_bytes());
+    }
+    data
+}
+
+fn decode_index(mut data: &[u8]) -> Result<Vec<PendingDeletion>> {
+    let mut index = Vec::new();
+    while let Some(&len) = data.first() {
+        let len = len as usize;
+        if data.len() < 1 + len + 4 {
+            return Err(Error::new(ErrorKind::CorruptObject));
+        }
+        let mut expires_at = [0u8; 4];
+        expires_at.copy_from_slice(&data[1 + len..1 + len + 4]);
+        index.push(PendingDeletion {
+            object_id: data[1..1 + len].to_vec(),
+            expires_at: u32::from_le_bytes(expires_at),
+        });
+        data = &data[1 + len + 4..];
+    }
+    Ok(index)
+}
+
+fn now() -> u32 {
+    let mut now = Time::new();
+    now.system_time();
+    now.seconds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(object_id: &[u8], expires_at: u32) -> PendingDeletion {
+        PendingDeletion {
+            object_id: object_id.to_vec(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        let mut index = vec![entry(b"a", 100), entry(b"key", 200), entry(b"b", 150)];
+        assert_eq!(decode_index(&encode_index(&index)).unwrap(), index);
+        assert!(decode_index(&encode_index(&index)[..5]).is_err());
+
+        let expired = split_expired(&mut index, 150);
+        assert_eq!(expired, vec![entry(b"a", 100), entry(b"b", 150)]);
+        assert_eq!(index, vec![entry(b"key", 200)]);
+    }
+}
@@ -90,6 +90,17 @@ impl Uuid {
         ))
     }
 
+    /// Returns the big-endian bytes of the uuid, as taken by
+    /// [`from_bytes`](Self::from_bytes).
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[0..4].copy_from_slice(&self.raw.timeLow.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.raw.timeMid.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.raw.timeHiAndVersion.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.raw.clockSeqAndNode);
+        bytes
+    }
+
     /// Creates a raw TEE client uuid object with specified parameters.
     pub fn new_raw(
         time_low: u32,
@@ -126,6 +137,17 @@ impl fmt::Display for Uuid {
     }
 }
 
+impl PartialEq for Uuid {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw.timeLow == other.raw.timeLow
+            && self.raw.timeMid == other.raw.timeMid
+            && self.raw.timeHiAndVersion == other.raw.timeHiAndVersion
+            && self.raw.clockSeqAndNode == other.raw.clockSeqAndNode
+    }
+}
+
+impl Eq for Uuid {}
+
 impl From<raw::TEE_UUID> for Uuid {
     fn from(raw: raw::TEE_UUID) -> Self {
         Uuid { raw }
@@ -152,4 +174,12 @@ mod tests {
             assert_eq!(*origin, formatted);
         }
     }
+
+    #[test]
+    fn test_to_bytes() {
+        let bytes = [
+            70, 235, 208, 238, 14, 109, 67, 201, 185, 13, 204, 195, 90, 145, 63, 62,
+        ];
+        assert_eq!(Uuid::from_bytes(bytes).to_bytes(), bytes);
+    }
 }
@@ -0,0 +1,12 @@
+//! Serves arbitrary bytes as a CA connection, through the framing, decoding
+//! and dispatch of the manager.
+//!
+//!     cargo fuzz run connection
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    ta_manager::fuzz::serve_connection(data);
+});
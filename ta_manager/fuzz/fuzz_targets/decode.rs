@@ -0,0 +1,12 @@
+//! Decodes arbitrary frame bodies with every codec, which must fail cleanly
+//! on malformed ones.
+//!
+//!     cargo fuzz run decode
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    ta_manager::fuzz::decode(data);
+});
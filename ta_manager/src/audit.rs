@@ -0,0 +1,455 @@
+//! Audit trail of the security-relevant events of a manager.
+//!
+//! A [`TAManager`](crate::TAManager) configured with an [`AuditSink`], see
+//! [`TAManagerConfig::with_audit_sink`](crate::TAManagerConfig::with_audit_sink),
+//! records its registrations, the sessions opened and closed, the commands
+//! run and the requests refused for lack of authorization.
+//!
+//! With the `audit_log` feature, [`HashChainAuditLog`] appends the events to
+//! a file in which every record carries an HMAC-SHA256, under a key kept out
+//! of the file, chained to that of the record before it. Without the key,
+//! altering, removing or reordering records is detected by
+//! [`HashChainAuditLog::verify`], and truncating the log by comparing it
+//! with an [`AuditCheckpoint`] exported beforehand.
+
+use std::fmt::{self, Debug, Display};
+#[cfg(feature = "audit_log")]
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "audit_log")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "audit_log")]
+use sha2::Sha256;
+#[cfg(feature = "audit_log")]
+use tracing::warn;
+
+use crate::authz::Action;
+use crate::peer::PeerCredentials;
+
+/// Security-relevant event of a manager.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum AuditEvent {
+    /// The TA registered with the TA Manager server, or registered again
+    /// after losing its registration.
+    Registered { uuid: String },
+    /// The TA was asked to open a session for a CA, and did if `result` is
+    /// 0.
+    SessionOpened {
+        session_id: u32,
+        peer: PeerCredentials,
+        /// Login type of the client identity.
+        login: u32,
+        result: u32,
+    },
+    /// A session was closed, by its CA or by the manager.
+    SessionClosed { session_id: u32 },
+    /// A command ran on a session, on its own or in a batch, and returned
+    /// `result`.
+    CommandInvoked {
+        session_id: u32,
+        cmd_id: u32,
+        peer: PeerCredentials,
+        result: u32,
+    },
+    /// A request of a CA was refused with `result` for lack of
+    /// authorization. `action` is `None` for a connection refused as a
+    /// whole, or for requests other than opening sessions and running
+    /// commands.
+    AccessDenied {
+        peer: PeerCredentials,
+        session_id: Option<u32>,
+        action: Option<Action>,
+        result: u32,
+    },
+}
+
+// Writes the event as a name followed by `key=value` fields, labels being
+// quoted.
+impl Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let peer = |f: &mut fmt::Formatter<'_>, peer: &PeerCredentials| {
+            write!(f, " uid={} gid={} pid={}", peer.uid, peer.gid, peer.pid)?;
+            match &peer.label {
+                Some(label) => write!(f, " label={:?}", label.as_str()),
+                None => Ok(()),
+            }
+        };
+        match self {
+            AuditEvent::Registered { uuid } => write!(f, "registered uuid={}", uuid),
+            AuditEvent::SessionOpened {
+                session_id,
+                peer: credentials,
+                login,
+                result,
+            } => {
+                write!(
+                    f,
+                    "session_opened session={} login={:#x}",
+                    session_id, login
+                )?;
+                peer(f, credentials)?;
+                write!(f, " result={:#x}", result)
+            }
+            AuditEvent::SessionClosed { session_id } => {
+                write!(f, "session_closed session={}", session_id)
+            }
+            AuditEvent::CommandInvoked {
+                session_id,
+                cmd_id,
+                peer: credentials,
+                result,
+            } => {
+                write!(
+                    f,
+                    "command_invoked session={} cmd={:#x}",
+                    session_id, cmd_id
+                )?;
+                peer(f, credentials)?;
+                write!(f, " result={:#x}", result)
+            }
+            AuditEvent::AccessDenied {
+                peer: credentials,
+                session_id,
+                action,
+                result,
+            } => {
+                f.write_str("access_denied")?;
+                if let Some(session_id) = session_id {
+                    write!(f, " session={}", session_id)?;
+                }
+                match action {
+                    Some(Action::OpenSession) => f.write_str(" action=open")?,
+                    Some(Action::InvokeCommand(cmd_id)) => write!(f, " cmd={:#x}", cmd_id)?,
+                    None => {}
+                }
+                peer(f, credentials)?;
+                write!(f, " result={:#x}", result)
+            }
+        }
+    }
+}
+
+/// Destination of the [`AuditEvent`]s of a manager.
+///
+/// Events are recorded on the threads serving CAs as they happen, so a sink
+/// should not block for long.
+pub trait AuditSink: Debug + Send + Sync + 'static {
+    fn record(&self, event: &AuditEvent);
+}
+
+// Hash preceding the first record of a log.
+#[cfg(feature = "audit_log")]
+const GENESIS: [u8; 32] = [0; 32];
+
+/// [`AuditSink`] appending events to a tamper-evident file.
+///
+/// Every event is written as one line:
+///
+/// ```text
+/// <sequence> <unix time in ms> <event> <hash>
+/// ```
+///
+/// where the sequence number counts the records from 0, the event is
+/// written as by its [`Display`] implementation, and the hash is the
+/// HMAC-SHA256, under the key of the log, of the hash of the previous
+/// record, zeros for the first one, followed by the rest of the line, in
+/// lowercase hexadecimal. A log opened again continues the chain of its last
+/// record.
+///
+/// The key must be kept where whoever can write the file cannot read it,
+/// e.g. in secure storage or in the configuration of the verifier, or
+/// rewriting the log is as easy as with a plain hash. The key authenticates
+/// the records but not their number: records removed from the end of the
+/// log are only detected against a [`checkpoint`](Self::checkpoint)
+/// exported beforehand.
+///
+/// Records are written as events happen, and synced to disk after the
+/// sessions opened and closed and the requests refused, so that a crash
+/// cannot lose them. Failures to write are reported in the log of the
+/// manager.
+#[cfg(feature = "audit_log")]
+pub struct HashChainAuditLog {
+    path: PathBuf,
+    mac: Hmac<Sha256>,
+    chain: Mutex<Chain>,
+}
+
+// End of the chain of a log, to which records are appended.
+#[cfg(feature = "audit_log")]
+#[derive(Debug)]
+struct Chain {
+    file: File,
+    next: u64,
+    hash: [u8; 32],
+}
+
+/// Head of the chain of a [`HashChainAuditLog`]: its number of records and
+/// the hash of the last one.
+///
+/// Exported out of reach of the log, e.g. to a remote collector, a
+/// checkpoint detects the removal of the records it covers.
+#[cfg(feature = "audit_log")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditCheckpoint {
+    pub records: u64,
+    pub hash: [u8; 32],
+}
+
+#[cfg(feature = "audit_log")]
+impl HashChainAuditLog {
+    /// Opens the log at `path`, created with mode 0600 if missing, whose
+    /// records are authenticated with `key`.
+    ///
+    /// # Errors
+    ///
+    /// 1) Errors from opening or reading the file.
+    /// 2) `InvalidData`: If the last record of the file is malformed or was
+    ///    not written under `key`.
+    pub fn open(path: impl AsRef<Path>, key: &[u8]) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path)?;
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines();
+        let (next, hash) = match (lines.next_back(), lines.next_back()) {
+            (Some(line), previous) => {
+                let record = Record::parse(line)?;
+                let previous = match previous {
+                    Some(previous) => Record::parse(previous)?.hash,
+                    None => GENESIS,
+                };
+                if chain_hash(&mac, &previous, record.body) != record.hash {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "audit record not written under this key",
+                    ));
+                }
+                (record.sequence + 1, record.hash)
+            }
+            (None, _) => (0, GENESIS),
+        };
+        Ok(Self {
+            path: path.into(),
+            mac,
+            chain: Mutex::new(Chain { file, next, hash }),
+        })
+    }
+
+    /// Checks the chain of the log at `path` under `key`, and returns its
+    /// head.
+    ///
+    /// # Errors
+    ///
+    /// 1) Errors from reading the file.
+    /// 2) `InvalidData`: If a record is malformed, out of sequence or does
+    ///    not hash to the hash it carries, the message giving its line.
+    pub fn verify(path: impl AsRef<Path>, key: &[u8]) -> io::Result<AuditCheckpoint> {
+        let mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+        let mut hash = GENESIS;
+        let mut count = 0;
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let invalid = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            let record = Record::parse(line).map_err(|_| invalid("malformed record"))?;
+            if record.sequence != count {
+                return Err(invalid("record out of sequence"));
+            }
+            if chain_hash(&mac, &hash, record.body) != record.hash {
+                return Err(invalid("hash mismatch"));
+            }
+            hash = record.hash;
+            count += 1;
+        }
+        Ok(AuditCheckpoint {
+            records: count,
+            hash,
+        })
+    }
+
+    /// Returns the head of the chain, to export as a checkpoint.
+    pub fn checkpoint(&self) -> AuditCheckpoint {
+        let chain = self.chain.lock().unwrap();
+        AuditCheckpoint {
+            records: chain.next,
+            hash: chain.hash,
+        }
+    }
+
+    fn append(&self, event: &AuditEvent) -> io::Result<()> {
+        let mut chain = self.chain.lock().unwrap();
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis());
+        // Line breaks would split the record.
+        let line = event.to_string().replace(['\n', '\r'], " ");
+        let body = format!("{} {} {}", chain.next, time, line);
+        let hash = chain_hash(&self.mac, &chain.hash, &body);
+        chain
+            .file
+            .write_all(format!("{} {}\n", body, hex(&hash)).as_bytes())?;
+        chain.next += 1;
+        chain.hash = hash;
+        match event {
+            AuditEvent::SessionOpened { .. }
+            | AuditEvent::SessionClosed { .. }
+            | AuditEvent::AccessDenied { .. } => chain.file.sync_data(),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Leaves the key out.
+#[cfg(feature = "audit_log")]
+impl Debug for HashChainAuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HashChainAuditLog")
+            .field("path", &self.path)
+            .field("chain", &self.chain)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "audit_log")]
+impl AuditSink for HashChainAuditLog {
+    fn record(&self, event: &AuditEvent) {
+        if let Err(e) = self.append(event) {
+            warn!(path = ?self.path, error = ?e, "Failed to write an audit record");
+        }
+    }
+}
+
+// Line of a log, split into the hashed part and its hash.
+#[cfg(feature = "audit_log")]
+struct Record<'a> {
+    sequence: u64,
+    body: &'a str,
+    hash: [u8; 32],
+}
+
+#[cfg(feature = "audit_log")]
+impl<'a> Record<'a> {
+    fn parse(line: &'a str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed audit record");
+        let (body, hash) = line.rsplit_once(' ').ok_or_else(invalid)?;
+        let sequence = body
+            .split(' ')
+            .next()
+            .and_then(|sequence| sequence.parse().ok())
+            .ok_or_else(invalid)?;
+        let hash = parse_hex(hash).ok_or_else(invalid)?;
+        Ok(Self {
+            sequence,
+            body,
+            hash,
+        })
+    }
+}
+
+#[cfg(feature = "audit_log")]
+fn chain_hash(mac: &Hmac<Sha256>, previous: &[u8; 32], body: &str) -> [u8; 32] {
+    mac.clone()
+        .chain_update(previous)
+        .chain_update(body)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+#[cfg(feature = "audit_log")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(feature = "audit_log")]
+fn parse_hex(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+#[cfg(all(test, feature = "audit_log"))]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"audit key";
+
+    fn registered(uuid: &str) -> AuditEvent {
+        AuditEvent::Registered { uuid: uuid.into() }
+    }
+
+    fn closed(session_id: u32) -> AuditEvent {
+        AuditEvent::SessionClosed { session_id }
+    }
+
+    #[test]
+    fn log_continues_its_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = HashChainAuditLog::open(&path, KEY).unwrap();
+        log.append(&registered("a")).unwrap();
+        log.append(&closed(1)).unwrap();
+        drop(log);
+
+        let log = HashChainAuditLog::open(&path, KEY).unwrap();
+        log.append(&closed(2)).unwrap();
+        let checkpoint = log.checkpoint();
+        assert_eq!(checkpoint.records, 3);
+        assert_eq!(HashChainAuditLog::verify(&path, KEY).unwrap(), checkpoint);
+    }
+
+    #[test]
+    fn rewritten_logs_are_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = HashChainAuditLog::open(&path, KEY).unwrap();
+        for session_id in 0..3 {
+            log.append(&closed(session_id)).unwrap();
+        }
+        let checkpoint = log.checkpoint();
+        drop(log);
+        let contents = fs::read_to_string(&path).unwrap();
+
+        let refused = HashChainAuditLog::verify(&path, b"other key").unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::InvalidData);
+        let refused = HashChainAuditLog::open(&path, b"other key").unwrap_err();
+        assert_eq!(refused.kind(), io::ErrorKind::InvalidData);
+
+        // Rehashing the altered records is of no use without the key.
+        let mut hash = GENESIS;
+        let mut forged = String::new();
+        for line in contents.replace("session=1", "session=7").lines() {
+            let (body, _) = line.rsplit_once(' ').unwrap();
+            hash = chain_hash(&Hmac::new_from_slice(b"guess").unwrap(), &hash, body);
+            forged.push_str(&format!("{} {}\n", body, hex(&hash)));
+        }
+        fs::write(&path, forged).unwrap();
+        let refused = HashChainAuditLog::verify(&path, KEY).unwrap_err();
+        assert!(refused.to_string().starts_with("line 1:"));
+
+        // Truncated logs still verify, but not to the checkpoint.
+        let truncated: Vec<&str> = contents.lines().take(2).collect();
+        fs::write(&path, truncated.join("\n")).unwrap();
+        let head = HashChainAuditLog::verify(&path, KEY).unwrap();
+        assert_ne!(head, checkpoint);
+    }
+}
@@ -0,0 +1,822 @@
+//! Authorization of the requests of CAs, beyond the
+//! [`AccessPolicy`](crate::AccessPolicy) checked before opening sessions,
+//! which is decided by the same rules.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use optee_utee::{Error, ErrorKind, Identity, LoginType, Result, Uuid};
+use tracing::{info, warn};
+
+use crate::peer::PeerCredentials;
+use crate::policy::AccessPolicy;
+
+// Interval at which the modification time of a policy file is checked.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Number of rate windows tracked past which the expired ones are dropped.
+const PRUNE_THRESHOLD: usize = 256;
+
+/// CA making a request.
+#[derive(Clone, Copy)]
+pub struct Subject<'a> {
+    /// Credentials of the CA process.
+    pub peer: &'a PeerCredentials,
+    /// Identity the CA opened the session with, or is opening it with.
+    pub identity: &'a Identity,
+}
+
+/// Request of a CA subject to authorization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    OpenSession,
+    /// Running the command of the given id, on its own or in a batch.
+    InvokeCommand(u32),
+}
+
+/// Decides which requests CAs may make.
+///
+/// A [`TAManager`](crate::TAManager) asks its authorizer, set with
+/// [`TAManagerConfig::with_authorizer`](crate::TAManagerConfig::with_authorizer),
+/// before opening a session, once the [`AccessPolicy`](crate::AccessPolicy)
+/// let it through, and before running each command.
+pub trait Authorizer: Debug + Send + Sync + 'static {
+    /// Returns `Ok` if `subject` may perform `action`, or the error its
+    /// request fails with.
+    fn authorize(&self, subject: &Subject<'_>, action: Action) -> Result<()>;
+}
+
+/// [`Authorizer`] enforcing a declarative policy, typically kept in a file
+/// shipped with the TA and read again whenever it changes, so that the policy
+/// can be changed without rebuilding the TA.
+///
+/// # Policy language
+///
+/// A policy is a list of rules, one per line. Blank lines and text after a
+/// `#` are ignored. A rule is an effect, `allow` or `deny`, followed by
+/// terms restricting the requests it applies to:
+///
+/// ```text
+/// # Administrators may do anything.
+/// allow gid=0
+/// # The web front-end opens sessions and signs, during office hours.
+/// allow label=httpd_t open
+/// allow label=httpd_t cmd=0x10,0x11 time=08:00-18:00 rate=20/s
+/// # Nobody else signs.
+/// deny cmd=0x10,0x11
+/// default allow
+/// ```
+///
+/// Subjects, which all must match, each listing the values it accepts:
+///
+/// * `uid=<uids>`: uid of the CA.
+/// * `gid=<gids>`: primary or supplementary group of the CA.
+/// * `login=<login types>`: among `public`, `user`, `group`, `application`,
+///   `application_user`, `application_group` and `trusted_app`.
+/// * `uuid=<uuids>`: UUID of the client identity.
+/// * `label=<labels>`: security label of the CA, or its
+///   [domain](crate::SecurityLabel::domain).
+///
+/// Actions, a rule without any applying to all of them:
+///
+/// * `open`: opening a session.
+/// * `cmd=<command ids>`: running one of the commands, in decimal or in
+///   hexadecimal with a `0x` prefix, `*` standing for any command.
+///
+/// Conditions:
+///
+/// * `time=HH:MM-HH:MM`: the request is made in the window, in UTC, which
+///   wraps around midnight if it ends before it starts.
+/// * `rate=<count>/<s|m|h>`: for `allow` rules only, at most `count`
+///   requests of a uid are allowed by the rule per second, minute or hour.
+///   Requests past it fail with `Busy`.
+///
+/// The first rule applying to a request decides it: `deny` fails it with
+/// `AccessDenied`. Requests no rule applies to are decided by the last
+/// `default allow` or `default deny` line, denied without one.
+#[derive(Debug)]
+pub struct PolicyAuthorizer {
+    // File the policy is read from, if any.
+    path: Option<PathBuf>,
+    policy: RwLock<Arc<Policy>>,
+    watch: Mutex<Watch>,
+    // Requests allowed by each rule with a rate, by rule index and uid.
+    windows: Mutex<HashMap<(usize, u32), RateWindow>>,
+}
+
+impl PolicyAuthorizer {
+    /// Creates an authorizer enforcing `policy`.
+    ///
+    /// # Errors
+    ///
+    /// 1) `InvalidData`: If `policy` is not valid, the message giving the
+    ///    line at fault.
+    pub fn new(policy: &str) -> io::Result<Self> {
+        Ok(Self::with_policy(None, Policy::parse(policy)?, None))
+    }
+
+    /// Creates an authorizer enforcing the policy in the file at `path`,
+    /// read again when its modification time changes. A file that can no
+    /// longer be read or that holds an invalid policy is reported in the log
+    /// and the policy read last stays in force.
+    ///
+    /// # Errors
+    ///
+    /// 1) Errors from reading the file.
+    /// 2) `InvalidData`: If the file does not hold a valid policy.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let modified = modified(path);
+        let policy = Policy::parse(&fs::read_to_string(path)?)?;
+        Ok(Self::with_policy(Some(path.into()), policy, modified))
+    }
+
+    fn with_policy(path: Option<PathBuf>, policy: Policy, modified: Option<SystemTime>) -> Self {
+        Self {
+            path,
+            policy: RwLock::new(Arc::new(policy)),
+            watch: Mutex::new(Watch {
+                modified,
+                checked: Instant::now(),
+            }),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads the policy file again, whether or not it changed. Does nothing
+    /// for an authorizer not created with [`from_file`](Self::from_file).
+    ///
+    /// # Errors
+    ///
+    /// Same as [`from_file`](Self::from_file), the current policy then
+    /// staying in force.
+    pub fn reload(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let policy = Policy::parse(&fs::read_to_string(path)?)?;
+        *self.policy.write().unwrap() = Arc::new(policy);
+        self.windows.lock().unwrap().clear();
+        info!(?path, "Authorization policy loaded");
+        Ok(())
+    }
+
+    // Reloads the policy file if it changed since it was last read.
+    fn reload_if_changed(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut watch = self.watch.lock().unwrap();
+        if watch.checked.elapsed() < RELOAD_CHECK_INTERVAL {
+            return;
+        }
+        watch.checked = Instant::now();
+        let modified = modified(path);
+        if modified == watch.modified {
+            return;
+        }
+        watch.modified = modified;
+        if let Err(e) = self.reload() {
+            warn!(
+                ?path,
+                error = ?e,
+                "Failed to reload the authorization policy, keeping the previous one"
+            );
+        }
+    }
+
+    // Counts a request of `uid` allowed by the rule at `index`. Returns
+    // whether the rate of the rule lets it through.
+    fn take(&self, index: usize, uid: u32, rate: &Rate) -> bool {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= PRUNE_THRESHOLD && !windows.contains_key(&(index, uid)) {
+            windows.retain(|_, window| now.duration_since(window.start) < window.period);
+        }
+        let window = windows.entry((index, uid)).or_insert(RateWindow {
+            start: now,
+            period: rate.period,
+            count: 0,
+        });
+        if now.duration_since(window.start) >= rate.period {
+            window.start = now;
+            window.count = 0;
+        }
+        if window.count >= rate.count {
+            return false;
+        }
+        window.count += 1;
+        true
+    }
+}
+
+impl Authorizer for PolicyAuthorizer {
+    fn authorize(&self, subject: &Subject<'_>, action: Action) -> Result<()> {
+        self.reload_if_changed();
+        let policy = self.policy.read().unwrap().clone();
+        let Some((index, rule)) = policy.rule_for(subject, action, minute_of_day()) else {
+            return policy.default.result();
+        };
+        match (rule.effect, &rule.rate) {
+            (Effect::Deny, _) => Err(Error::new(ErrorKind::AccessDenied)),
+            (Effect::Allow, Some(rate)) if !self.take(index, subject.peer.uid, rate) => {
+                Err(Error::new(ErrorKind::Busy))
+            }
+            (Effect::Allow, _) => Ok(()),
+        }
+    }
+}
+
+// Decides whether `subject` may perform `action` under `access`, through the
+// rules it stands for: every command is allowed, and sessions are opened by
+// the subjects meeting all of its restrictions.
+pub(crate) fn authorize_access(
+    access: &AccessPolicy,
+    subject: &Subject<'_>,
+    action: Action,
+) -> Result<()> {
+    let rule = |subjects| Rule {
+        effect: Effect::Allow,
+        subjects,
+        open: true,
+        commands: Some(Vec::new()),
+        time: None,
+        rate: None,
+    };
+    let mut conditions = Vec::new();
+    if !access.allowed_login_types.is_empty() {
+        conditions.push(Matcher::Login(access.allowed_login_types.clone()));
+    }
+    if !access.allowed_labels.is_empty() {
+        conditions.push(Matcher::Label(access.allowed_labels.clone()));
+    }
+    let mut policy = Policy {
+        rules: vec![Rule {
+            open: false,
+            commands: None,
+            ..rule(Vec::new())
+        }],
+        default: Effect::Deny,
+    };
+    // Allowed uids and gids each let subjects in.
+    let mut alternatives = Vec::new();
+    if !access.allowed_uids.is_empty() {
+        alternatives.push(vec![Matcher::Uid(access.allowed_uids.clone())]);
+    }
+    if !access.allowed_gids.is_empty() {
+        alternatives.push(vec![Matcher::Gid(access.allowed_gids.clone())]);
+    }
+    if alternatives.is_empty() {
+        alternatives.push(Vec::new());
+    }
+    for mut subjects in alternatives {
+        subjects.extend(conditions.iter().cloned());
+        policy.rules.push(rule(subjects));
+    }
+    match policy.rule_for(subject, action, minute_of_day()) {
+        Some((_, rule)) => rule.effect.result(),
+        None => policy.default.result(),
+    }
+}
+
+// Modification time of the file at `path`, `None` if it cannot be read.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Minutes elapsed since midnight UTC.
+fn minute_of_day() -> u32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    (secs % 86_400 / 60) as u32
+}
+
+#[derive(Debug)]
+struct Watch {
+    // Modification time of the policy file when it was last read.
+    modified: Option<SystemTime>,
+    // When `modified` was last compared with that of the file.
+    checked: Instant,
+}
+
+// Requests counted by a rate since `start`.
+#[derive(Debug)]
+struct RateWindow {
+    start: Instant,
+    period: Duration,
+    count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Effect {
+    Allow,
+    Deny,
+}
+
+impl Effect {
+    fn result(self) -> Result<()> {
+        match self {
+            Effect::Allow => Ok(()),
+            Effect::Deny => Err(Error::new(ErrorKind::AccessDenied)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Policy {
+    rules: Vec<Rule>,
+    default: Effect,
+}
+
+impl Policy {
+    fn parse(text: &str) -> io::Result<Self> {
+        let mut policy = Self {
+            rules: Vec::new(),
+            default: Effect::Deny,
+        };
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(rule, _)| rule);
+            let mut words = line.split_whitespace();
+            let Some(first) = words.next() else {
+                continue;
+            };
+            let error = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, message),
+                )
+            };
+            if first == "default" {
+                policy.default = match (words.next(), words.next()) {
+                    (Some(effect), None) => parse_effect(effect).map_err(error)?,
+                    _ => return Err(error("expected `default allow` or `default deny`".into())),
+                };
+                continue;
+            }
+            let effect = parse_effect(first).map_err(error)?;
+            policy
+                .rules
+                .push(Rule::parse(effect, words).map_err(error)?);
+        }
+        Ok(policy)
+    }
+
+    // Returns the first rule applying to `action` of `subject` at `minute`,
+    // with its index.
+    fn rule_for(
+        &self,
+        subject: &Subject<'_>,
+        action: Action,
+        minute: u32,
+    ) -> Option<(usize, &Rule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.applies(subject, action, minute))
+    }
+}
+
+fn parse_effect(word: &str) -> std::result::Result<Effect, String> {
+    match word {
+        "allow" => Ok(Effect::Allow),
+        "deny" => Ok(Effect::Deny),
+        _ => Err(format!("expected `allow` or `deny`, found `{}`", word)),
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Matcher {
+    Uid(Vec<u32>),
+    Gid(Vec<u32>),
+    Login(Vec<LoginType>),
+    Uuid(Vec<[u8; 16]>),
+    Label(Vec<String>),
+}
+
+impl Matcher {
+    fn matches(&self, subject: &Subject<'_>) -> bool {
+        let peer = subject.peer;
+        match self {
+            Matcher::Uid(uids) => uids.contains(&peer.uid),
+            Matcher::Gid(gids) => {
+                gids.contains(&peer.gid) || peer.groups.iter().any(|gid| gids.contains(gid))
+            }
+            Matcher::Login(login_types) => login_types.contains(&subject.identity.login_type()),
+            Matcher::Uuid(uuids) => uuids.contains(&subject.identity.uuid().to_bytes()),
+            Matcher::Label(labels) => peer.label.as_ref().is_some_and(|label| {
+                labels
+                    .iter()
+                    .any(|allowed| allowed == label.as_str() || allowed == label.domain())
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rate {
+    count: u32,
+    period: Duration,
+}
+
+#[derive(Debug)]
+struct Rule {
+    effect: Effect,
+    subjects: Vec<Matcher>,
+    // Whether the rule applies to opening sessions.
+    open: bool,
+    // Commands the rule applies to, `None` standing for all of them.
+    commands: Option<Vec<u32>>,
+    // Window of the day the rule applies in, in minutes since midnight UTC.
+    time: Option<(u32, u32)>,
+    rate: Option<Rate>,
+}
+
+impl Rule {
+    fn parse<'a>(
+        effect: Effect,
+        terms: impl Iterator<Item = &'a str>,
+    ) -> std::result::Result<Self, String> {
+        let mut rule = Self {
+            effect,
+            subjects: Vec::new(),
+            open: false,
+            commands: Some(Vec::new()),
+            time: None,
+            rate: None,
+        };
+        let mut has_action = false;
+        let mut keys = Vec::new();
+        for term in terms {
+            let (key, value) = term.split_once('=').unwrap_or((term, ""));
+            if keys.contains(&key) {
+                return Err(format!("`{}` given twice", key));
+            }
+            keys.push(key);
+            match key {
+                "open" if value.is_empty() => {
+                    rule.open = true;
+                    has_action = true;
+                }
+                "cmd" => {
+                    rule.commands = match value {
+                        "*" => None,
+                        _ => Some(parse_list(value, parse_u32)?),
+                    };
+                    has_action = true;
+                }
+                "uid" => rule
+                    .subjects
+                    .push(Matcher::Uid(parse_list(value, parse_u32)?)),
+                "gid" => rule
+                    .subjects
+                    .push(Matcher::Gid(parse_list(value, parse_u32)?)),
+                "login" => rule
+                    .subjects
+                    .push(Matcher::Login(parse_list(value, parse_login_type)?)),
+                "uuid" => rule
+                    .subjects
+                    .push(Matcher::Uuid(parse_list(value, parse_uuid)?)),
+                "label" => rule
+                    .subjects
+                    .push(Matcher::Label(parse_list(value, |s| Ok(s.to_string()))?)),
+                "time" => rule.time = Some(parse_window(value)?),
+                "rate" if effect == Effect::Allow => rule.rate = Some(parse_rate(value)?),
+                "rate" => return Err("`rate` only applies to `allow` rules".into()),
+                _ => return Err(format!("unknown term `{}`", term)),
+            }
+        }
+        if !has_action {
+            rule.open = true;
+            rule.commands = None;
+        }
+        Ok(rule)
+    }
+
+    fn applies(&self, subject: &Subject<'_>, action: Action, minute: u32) -> bool {
+        let action_matches = match action {
+            Action::OpenSession => self.open,
+            Action::InvokeCommand(cmd_id) => self
+                .commands
+                .as_ref()
+                .is_none_or(|commands| commands.contains(&cmd_id)),
+        };
+        let in_window = self.time.is_none_or(|(start, end)| {
+            if start <= end {
+                (start..end).contains(&minute)
+            } else {
+                minute >= start || minute < end
+            }
+        });
+        action_matches && in_window && self.subjects.iter().all(|matcher| matcher.matches(subject))
+    }
+}
+
+fn parse_list<T>(
+    value: &str,
+    parse: impl Fn(&str) -> std::result::Result<T, String>,
+) -> std::result::Result<Vec<T>, String> {
+    if value.is_empty() {
+        return Err("missing value".into());
+    }
+    value.split(',').map(parse).collect()
+}
+
+fn parse_u32(s: &str) -> std::result::Result<u32, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("invalid number `{}`", s))
+}
+
+fn parse_login_type(s: &str) -> std::result::Result<LoginType, String> {
+    Ok(match s {
+        "public" => LoginType::Public,
+        "user" => LoginType::User,
+        "group" => LoginType::Group,
+        "application" => LoginType::Application,
+        "application_user" => LoginType::ApplicationUser,
+        "application_group" => LoginType::ApplicationGroup,
+        "trusted_app" => LoginType::TrustedApp,
+        _ => return Err(format!("unknown login type `{}`", s)),
+    })
+}
+
+fn parse_uuid(s: &str) -> std::result::Result<[u8; 16], String> {
+    Uuid::parse_str(s)
+        .map(|uuid| uuid.to_bytes())
+        .map_err(|_| format!("invalid UUID `{}`", s))
+}
+
+fn parse_window(s: &str) -> std::result::Result<(u32, u32), String> {
+    let minute = |s: &str| {
+        let (hours, minutes) = s.split_once(':')?;
+        let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    };
+    s.split_once('-')
+        .and_then(|(start, end)| Some((minute(start)?, minute(end)?)))
+        .ok_or_else(|| format!("invalid time window `{}`, expected HH:MM-HH:MM", s))
+}
+
+fn parse_rate(s: &str) -> std::result::Result<Rate, String> {
+    let invalid = || format!("invalid rate `{}`, expected <count>/<s|m|h>", s);
+    let (count, unit) = s.split_once('/').ok_or_else(invalid)?;
+    let count = count.parse().map_err(|_| invalid())?;
+    let period = match unit {
+        "s" => Duration::from_secs(1),
+        "m" => Duration::from_secs(60),
+        "h" => Duration::from_secs(3600),
+        _ => return Err(invalid()),
+    };
+    Ok(Rate { count, period })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::SecurityLabel;
+
+    fn peer(uid: u32, groups: &[u32], label: Option<&str>) -> PeerCredentials {
+        PeerCredentials {
+            uid,
+            gid: uid,
+            pid: 42,
+            groups: groups.to_vec(),
+            label: label.map(SecurityLabel::new),
+        }
+    }
+
+    fn user() -> Identity {
+        Identity::new(LoginType::User, Uuid::from_bytes([1; 16]))
+    }
+
+    // Whether the first rule of `rule` applies to `peer` performing
+    // `action` at `minute`.
+    fn applies(rule: &str, peer: &PeerCredentials, action: Action, minute: u32) -> bool {
+        let policy = Policy::parse(rule).unwrap();
+        let identity = user();
+        let subject = Subject {
+            peer,
+            identity: &identity,
+        };
+        policy.rules[0].applies(&subject, action, minute)
+    }
+
+    fn authorize(
+        authorizer: &impl Authorizer,
+        peer: &PeerCredentials,
+        action: Action,
+    ) -> Result<()> {
+        let identity = user();
+        authorizer.authorize(
+            &Subject {
+                peer,
+                identity: &identity,
+            },
+            action,
+        )
+    }
+
+    fn kind(result: Result<()>) -> Option<ErrorKind> {
+        result.err().map(|e| e.kind())
+    }
+
+    #[test]
+    fn policies_parse() {
+        let policy = Policy::parse(
+            "# comment\n\
+             \n\
+             allow uid=0,1000 gid=0x10 login=user,public open cmd=1,0x2 # trailing\n\
+             deny label=httpd_t time=22:00-06:00\n\
+             allow uuid=8aaaf200-2450-11e4-abe2-0002a5d5c51b rate=5/m\n\
+             default allow\n",
+        )
+        .unwrap();
+        assert_eq!(policy.rules.len(), 3);
+        assert_eq!(policy.default, Effect::Allow);
+        let rule = &policy.rules[0];
+        assert_eq!(rule.effect, Effect::Allow);
+        assert_eq!(rule.subjects.len(), 3);
+        assert!(rule.open);
+        assert_eq!(rule.commands, Some(vec![1, 2]));
+        assert_eq!(policy.rules[1].time, Some((22 * 60, 6 * 60)));
+        let rate = policy.rules[2].rate.as_ref().unwrap();
+        assert_eq!((rate.count, rate.period), (5, Duration::from_secs(60)));
+        assert_eq!(Policy::parse("").unwrap().default, Effect::Deny);
+    }
+
+    #[test]
+    fn invalid_policies_name_their_line() {
+        for (text, message) in [
+            ("allow uid=0\nmaybe", "line 2: expected `allow` or `deny`"),
+            ("allow uid=0 uid=1", "line 1: `uid` given twice"),
+            ("deny rate=1/s", "line 1: `rate` only applies"),
+            ("allow rate=1/d", "line 1: invalid rate"),
+            ("allow time=24:00-01:00", "line 1: invalid time window"),
+            ("allow login=root", "line 1: unknown login type"),
+            ("allow uuid=1234", "line 1: invalid UUID"),
+            ("allow cmd=", "line 1: missing value"),
+            ("allow user=1", "line 1: unknown term"),
+            ("default", "line 1: expected `default allow`"),
+        ] {
+            let e = Policy::parse(text).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().starts_with(message), "{}: {}", text, e);
+        }
+    }
+
+    #[test]
+    fn rules_apply_to_their_subjects_and_actions() {
+        let admin = peer(0, &[], None);
+        let member = peer(1000, &[10, 27], None);
+        let web = peer(33, &[], Some("system_u:system_r:httpd_t:s0"));
+        let open = Action::OpenSession;
+        let sign = Action::InvokeCommand(0x10);
+
+        // Without actions, a rule applies to all of them.
+        assert!(applies("allow uid=0", &admin, open, 0));
+        assert!(applies("allow uid=0", &admin, sign, 0));
+        assert!(!applies("allow uid=0", &member, open, 0));
+        // Supplementary groups count.
+        assert!(applies("allow gid=27", &member, sign, 0));
+        assert!(!applies("allow gid=27", &admin, sign, 0));
+        // Labels match whole or by domain, and no label matches none.
+        assert!(applies("allow label=httpd_t", &web, open, 0));
+        assert!(applies(
+            "allow label=system_u:system_r:httpd_t:s0",
+            &web,
+            open,
+            0
+        ));
+        assert!(!applies("allow label=httpd_t", &admin, open, 0));
+        assert!(applies("allow login=user", &admin, open, 0));
+        assert!(!applies("allow login=public", &admin, open, 0));
+        // All subjects must match.
+        assert!(!applies("allow uid=1000 gid=0", &member, open, 0));
+
+        assert!(applies("allow open", &admin, open, 0));
+        assert!(!applies("allow open", &admin, sign, 0));
+        assert!(applies("allow cmd=0x10", &admin, sign, 0));
+        assert!(!applies("allow cmd=0x11", &admin, sign, 0));
+        assert!(!applies("allow cmd=0x10", &admin, open, 0));
+        assert!(applies("allow cmd=*", &admin, Action::InvokeCommand(7), 0));
+    }
+
+    #[test]
+    fn time_windows_wrap_around_midnight() {
+        let admin = peer(0, &[], None);
+        let office = "allow time=08:00-18:00";
+        let night = "allow time=22:00-06:00";
+        for (minute, in_office, at_night) in [
+            (0, false, true),
+            (5 * 60 + 59, false, true),
+            (6 * 60, false, false),
+            (8 * 60, true, false),
+            (17 * 60 + 59, true, false),
+            (18 * 60, false, false),
+            (22 * 60, false, true),
+            (23 * 60 + 59, false, true),
+        ] {
+            let action = Action::OpenSession;
+            assert_eq!(
+                applies(office, &admin, action, minute),
+                in_office,
+                "{}",
+                minute
+            );
+            assert_eq!(
+                applies(night, &admin, action, minute),
+                at_night,
+                "{}",
+                minute
+            );
+        }
+    }
+
+    #[test]
+    fn rates_limit_each_uid_per_window() {
+        let authorizer = PolicyAuthorizer::new("allow rate=2/s").unwrap();
+        let (first, second) = (peer(1, &[], None), peer(2, &[], None));
+        let open = Action::OpenSession;
+        assert!(authorize(&authorizer, &first, open).is_ok());
+        assert!(authorize(&authorizer, &first, open).is_ok());
+        assert_eq!(
+            kind(authorize(&authorizer, &first, open)),
+            Some(ErrorKind::Busy)
+        );
+        assert!(authorize(&authorizer, &second, open).is_ok());
+
+        // A new window starts once the period elapsed.
+        let rate = Rate {
+            count: 1,
+            period: Duration::from_millis(20),
+        };
+        assert!(authorizer.take(1, 3, &rate));
+        assert!(!authorizer.take(1, 3, &rate));
+        std::thread::sleep(rate.period);
+        assert!(authorizer.take(1, 3, &rate));
+    }
+
+    #[test]
+    fn changed_policy_files_are_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("policy");
+        fs::write(&path, "allow uid=0").unwrap();
+        let authorizer = PolicyAuthorizer::from_file(&path).unwrap();
+        let user = peer(1000, &[], None);
+        let open = Action::OpenSession;
+        assert_eq!(
+            kind(authorize(&authorizer, &user, open)),
+            Some(ErrorKind::AccessDenied)
+        );
+
+        fs::write(&path, "allow uid=1000").unwrap();
+        // As if the file changed after the last check, a while ago.
+        {
+            let mut watch = authorizer.watch.lock().unwrap();
+            watch.modified = None;
+            watch.checked -= RELOAD_CHECK_INTERVAL;
+        }
+        assert!(authorize(&authorizer, &user, open).is_ok());
+
+        // An invalid policy leaves the previous one in force.
+        fs::write(&path, "allow uid=").unwrap();
+        assert!(authorizer.reload().is_err());
+        assert!(authorize(&authorizer, &user, open).is_ok());
+    }
+
+    #[test]
+    fn access_policies_are_decided_by_rules() {
+        let policy = AccessPolicy::new()
+            .allow_uid(0)
+            .allow_gid(27)
+            .allow_login_type(LoginType::User)
+            .allow_label("httpd_t");
+        let open = Action::OpenSession;
+        let web = "system_u:system_r:httpd_t:s0";
+        assert!(authorize(&policy, &peer(0, &[], Some(web)), open).is_ok());
+        assert!(authorize(&policy, &peer(1000, &[27], Some(web)), open).is_ok());
+        let denied = [
+            peer(1000, &[], Some(web)),
+            peer(0, &[], None),
+            peer(0, &[], Some("unconfined_t")),
+        ];
+        for peer in &denied {
+            assert_eq!(
+                kind(authorize(&policy, peer, open)),
+                Some(ErrorKind::AccessDenied)
+            );
+            // Commands are never restricted.
+            assert!(authorize(&policy, peer, Action::InvokeCommand(1)).is_ok());
+        }
+        assert!(authorize(&AccessPolicy::new(), &peer(1000, &[], None), open).is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+//! Inspects a running manager through its control socket, see
+//! `TAManagerConfig::with_control_socket`.
+//!
+//! ```text
+//! ta_managerctl [--socket PATH] status
+//! ta_managerctl [--socket PATH] list
+//! ta_managerctl [--socket PATH] sessions [UUID]
+//! ta_managerctl [--socket PATH] close UUID SESSION_ID
+//! ta_managerctl schema
+//! ```
+//!
+//! The socket defaults to `TA_MANAGER_CONTROL_SOCKET`, else `control.sock`
+//! in the default socket directory of the manager. `schema`, available when
+//! built with the `schema` feature, prints the CA protocol as JSON without
+//! connecting to a manager, see `ta_manager::protocol::schema`.
+
+use std::{env, io, path::PathBuf, process::ExitCode, time::Duration};
+
+use ta_manager::ControlClient;
+
+const USAGE: &str = "\
+usage: ta_managerctl [--socket PATH] COMMAND
+
+commands:
+  status                    show the version, pid and uptime of the manager
+  list                      list the hosted TAs
+  sessions [UUID]           list the open sessions, of every TA by default
+  close UUID SESSION_ID     close a session as if its CA had closed it
+  schema                    print the CA protocol schema as JSON";
+
+enum Command {
+    Status,
+    List,
+    Sessions(Option<String>),
+    Close(String, u32),
+    #[cfg(feature = "schema")]
+    Schema,
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Option<(PathBuf, Command)> {
+    let mut socket = ControlClient::default_socket();
+    let mut command = args.next()?;
+    if command == "--socket" {
+        socket = args.next()?.into();
+        command = args.next()?;
+    }
+    let command = match command.as_str() {
+        "status" => Command::Status,
+        "list" => Command::List,
+        "sessions" => Command::Sessions(args.next()),
+        "close" => Command::Close(args.next()?, args.next()?.parse().ok()?),
+        #[cfg(feature = "schema")]
+        "schema" => Command::Schema,
+        _ => return None,
+    };
+    match args.next() {
+        Some(_) => None,
+        None => Some((socket, command)),
+    }
+}
+
+fn run(socket: PathBuf, command: Command) -> io::Result<()> {
+    #[cfg(feature = "schema")]
+    if let Command::Schema = command {
+        let schema = ta_manager::protocol::schema::Schema::new().map_err(io::Error::other)?;
+        println!("{}", schema.to_json());
+        return Ok(());
+    }
+    let mut client = ControlClient::connect(&socket)
+        .map_err(|e| io::Error::new(e.kind(), format!("cannot connect to {:?}: {}", socket, e)))?;
+    match command {
+        Command::Status => {
+            let status = client.status()?;
+            println!("pid:      {}", status.pid);
+            println!(
+                "uptime:   {}",
+                format_duration(Duration::from_millis(status.uptime_ms))
+            );
+            println!("TAs:      {}", status.tas);
+            println!("protocol: {}", status.version);
+        }
+        Command::List => {
+            println!(
+                "{:<36}  {:<10}  {:>8}  {:>8}  {:>8}  NAME",
+                "UUID", "STATE", "SESSIONS", "COMMANDS", "ERRORS"
+            );
+            for ta in client.list_tas()? {
+                println!(
+                    "{:<36}  {:<10}  {:>8}  {:>8}  {:>8}  {} {}",
+                    ta.uuid,
+                    ta.state,
+                    ta.open_sessions,
+                    ta.commands_invoked,
+                    ta.errors,
+                    ta.name,
+                    ta.version
+                );
+            }
+        }
+        Command::Sessions(uuid) => {
+            let uuids = match uuid {
+                Some(uuid) => vec![uuid],
+                None => client.list_tas()?.into_iter().map(|ta| ta.uuid).collect(),
+            };
+            let mut sessions = Vec::new();
+            for uuid in uuids {
+                sessions.push((client.list_sessions(&uuid)?, uuid));
+            }
+            println!(
+                "{:<36}  {:>10}  {:>6}  {:>6}  {:>8}  {:>10}  BUSY",
+                "UUID", "SESSION", "UID", "GID", "PID", "IDLE"
+            );
+            for (sessions, uuid) in sessions {
+                for session in sessions {
+                    let busy = match session.busy_ms {
+                        Some(ms) if session.stuck => {
+                            format!("{} (stuck)", format_duration(Duration::from_millis(ms)))
+                        }
+                        Some(ms) => format_duration(Duration::from_millis(ms)),
+                        None => "-".to_string(),
+                    };
+                    println!(
+                        "{:<36}  {:>10}  {:>6}  {:>6}  {:>8}  {:>10}  {}",
+                        uuid,
+                        session.session_id,
+                        session.uid,
+                        session.gid,
+                        session.pid,
+                        format_duration(Duration::from_millis(session.idle_ms)),
+                        busy
+                    );
+                }
+            }
+        }
+        Command::Close(uuid, session_id) => {
+            client.close_session(&uuid, session_id)?;
+            println!("closed session {} of {}", session_id, uuid);
+        }
+        #[cfg(feature = "schema")]
+        Command::Schema => unreachable!("printed without connecting"),
+    }
+    Ok(())
+}
+
+// Formats `duration` as e.g. `3d04h05m06s`, or in milliseconds below a
+// second.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d{:02}h{:02}m{:02}s", days, hours, minutes, secs)
+    } else if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, secs)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, secs)
+    } else if secs > 0 {
+        format!("{}s", secs)
+    } else {
+        format!("{}ms", duration.as_millis())
+    }
+}
+
+fn main() -> ExitCode {
+    let Some((socket, command)) = parse(env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+    match run(socket, command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ta_managerctl: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
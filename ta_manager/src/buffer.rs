@@ -0,0 +1,258 @@
+//! Aligned buffers for TAs handing memref contents to devices, e.g. crypto
+//! accelerators, that read memory directly and expect it page-aligned.
+//!
+//! Memref data arrives in a plain `Vec<u8>`, which carries no alignment
+//! guarantee. A TA copies it into an [`AlignedBuffer`], usually through
+//! [`Parameter::to_aligned`](crate::protocol::Parameter::to_aligned), which
+//! honours the alignment the CA asked for with
+//! [`Parameter::with_alignment`](crate::protocol::Parameter::with_alignment),
+//! and copies the results of output memrefs back into their data.
+
+use std::{
+    alloc::{self, Layout},
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    slice,
+    sync::OnceLock,
+};
+
+use optee_utee::{Error, ErrorKind, ErrorOrigin, Result};
+
+/// Size of the huge pages backing buffers created by
+/// [`AlignedBuffer::huge`], 2 MiB.
+pub const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// Returns the size of the memory pages of the system.
+pub fn page_size() -> usize {
+    static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
+    *PAGE_SIZE.get_or_init(|| match sys::page_size() {
+        size if size > 0 => size,
+        _ => 4096,
+    })
+}
+
+/// Zero-initialized byte buffer starting at an address aligned to a power of
+/// two, optionally pinned in memory.
+pub struct AlignedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    pinned: bool,
+}
+
+// SAFETY: the buffer owns its memory, like a `Vec<u8>`.
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Allocates a page-aligned buffer of `len` bytes.
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: the buffer could not be allocated.
+    pub fn new(len: usize) -> Result<Self> {
+        Self::with_alignment(len, page_size())
+    }
+
+    /// Allocates a buffer of `len` bytes aligned to `align` bytes.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: `align` is not a power of two.
+    /// 2) `OutOfMemory`: the buffer could not be allocated.
+    pub fn with_alignment(len: usize, align: usize) -> Result<Self> {
+        let layout = Layout::from_size_align(len.max(1), align)
+            .map_err(|_| Error::new(ErrorKind::BadParameters).with_origin(ErrorOrigin::Api))?;
+        // SAFETY: `layout` has a non-zero size.
+        let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .ok_or_else(|| Error::new(ErrorKind::OutOfMemory).with_origin(ErrorOrigin::Tee))?;
+        Ok(Self {
+            ptr,
+            len,
+            layout,
+            pinned: false,
+        })
+    }
+
+    /// Allocates a buffer of `len` bytes aligned to [`HUGE_PAGE_SIZE`], and
+    /// asks the kernel to back it with huge pages. The kernel may not, e.g.
+    /// when transparent huge pages are disabled or on Windows, which leaves
+    /// the buffer usable on regular pages.
+    ///
+    /// # Errors
+    ///
+    /// 1) `OutOfMemory`: the buffer could not be allocated.
+    pub fn huge(len: usize) -> Result<Self> {
+        let buffer = Self::with_alignment(len, HUGE_PAGE_SIZE)?;
+        // SAFETY: the range lies within the allocation, whose start is page
+        // aligned.
+        unsafe { sys::advise_huge_pages(buffer.ptr.as_ptr(), buffer.layout.size()) };
+        Ok(buffer)
+    }
+
+    /// Allocates a buffer aligned to `align` bytes holding a copy of `data`.
+    ///
+    /// # Errors
+    ///
+    /// See [`AlignedBuffer::with_alignment`].
+    pub fn from_slice(data: &[u8], align: usize) -> Result<Self> {
+        let mut buffer = Self::with_alignment(data.len(), align)?;
+        buffer.copy_from_slice(data);
+        Ok(buffer)
+    }
+
+    /// Locks the buffer in memory, so that it is never paged out and stays
+    /// at the same physical address while a device accesses it. Unlocked
+    /// when dropped.
+    ///
+    /// # Errors
+    ///
+    /// 1) `AccessDenied`: the process may not lock memory.
+    /// 2) `OutOfMemory`: locking the buffer would exceed `RLIMIT_MEMLOCK`,
+    ///    or the working set of the process on Windows.
+    pub fn pin(&mut self) -> Result<()> {
+        if self.pinned {
+            return Ok(());
+        }
+        // SAFETY: the range lies within the allocation.
+        if let Err(kind) = unsafe { sys::lock(self.ptr.as_ptr(), self.layout.size()) } {
+            return Err(Error::new(kind).with_origin(ErrorOrigin::Tee));
+        }
+        self.pinned = true;
+        Ok(())
+    }
+
+    /// Returns whether the buffer is locked in memory.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Returns the alignment of the start of the buffer, in bytes.
+    pub fn alignment(&self) -> usize {
+        self.layout.align()
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: the memory was allocated with `layout`, and was locked by
+        // `pin` if `pinned` is set.
+        unsafe {
+            if self.pinned {
+                sys::unlock(self.ptr.as_ptr(), self.layout.size());
+            }
+            alloc::dealloc(self.ptr.as_ptr(), self.layout);
+        }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the allocation holds at least `len` initialized bytes.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: the allocation holds at least `len` initialized bytes, only
+        // reachable through `self`.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl AsRef<[u8]> for AlignedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for AlignedBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl fmt::Debug for AlignedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlignedBuffer")
+            .field("len", &self.len)
+            .field("alignment", &self.alignment())
+            .field("pinned", &self.pinned)
+            .finish()
+    }
+}
+
+// Memory management calls of the host.
+#[cfg(target_os = "linux")]
+mod sys {
+    use optee_utee::ErrorKind;
+
+    pub(super) fn page_size() -> usize {
+        // SAFETY: sysconf only reads a system setting.
+        match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            size if size > 0 => size as usize,
+            _ => 0,
+        }
+    }
+
+    // SAFETY: `ptr` must start a page-aligned range of `len` bytes the
+    // caller owns. The advice does not change its contents.
+    pub(super) unsafe fn advise_huge_pages(ptr: *mut u8, len: usize) {
+        unsafe { libc::madvise(ptr.cast(), len, libc::MADV_HUGEPAGE) };
+    }
+
+    // SAFETY: `ptr` must start a range of `len` bytes the caller owns.
+    pub(super) unsafe fn lock(ptr: *mut u8, len: usize) -> Result<(), ErrorKind> {
+        if unsafe { libc::mlock(ptr.cast(), len) } == 0 {
+            return Ok(());
+        }
+        match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EPERM) => Err(ErrorKind::AccessDenied),
+            _ => Err(ErrorKind::OutOfMemory),
+        }
+    }
+
+    // SAFETY: `ptr` must start a range of `len` bytes locked by `lock`.
+    pub(super) unsafe fn unlock(ptr: *mut u8, len: usize) {
+        unsafe { libc::munlock(ptr.cast(), len) };
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use optee_utee::ErrorKind;
+    use windows_sys::Win32::System::{
+        Memory::{VirtualLock, VirtualUnlock},
+        SystemInformation::{GetSystemInfo, SYSTEM_INFO},
+    };
+
+    pub(super) fn page_size() -> usize {
+        // SAFETY: `SYSTEM_INFO` is plain data, filled in by the call.
+        let info = unsafe {
+            let mut info: SYSTEM_INFO = std::mem::zeroed();
+            GetSystemInfo(&mut info);
+            info
+        };
+        info.dwPageSize as usize
+    }
+
+    // Windows only backs memory allocated as such with large pages.
+    pub(super) unsafe fn advise_huge_pages(_ptr: *mut u8, _len: usize) {}
+
+    // SAFETY: `ptr` must start a range of `len` bytes the caller owns.
+    pub(super) unsafe fn lock(ptr: *mut u8, len: usize) -> Result<(), ErrorKind> {
+        match unsafe { VirtualLock(ptr.cast(), len) } {
+            0 => Err(ErrorKind::OutOfMemory),
+            _ => Ok(()),
+        }
+    }
+
+    // SAFETY: `ptr` must start a range of `len` bytes locked by `lock`.
+    pub(super) unsafe fn unlock(ptr: *mut u8, len: usize) {
+        unsafe { VirtualUnlock(ptr.cast(), len) };
+    }
+}
@@ -0,0 +1,155 @@
+//! Client Application API for the TAs served by [`TAManager`](crate::TAManager)s,
+//! shaped like that of `optee-teec`.
+//!
+//! ```no_run
+//! use ta_manager::ca_client::Context;
+//! use ta_manager::protocol::{ParamType, Parameters};
+//!
+//! let ctx = Context::new();
+//! let mut session = ctx.open_session("my-ta-uuid", Parameters::default())?;
+//! let mut params = Parameters::default();
+//! params.0.param_type = ParamType::ValueInout;
+//! params.0.param.values.a = 29;
+//! session.invoke_command(1, &mut params)?;
+//! println!("{}", params.0.param.values.a);
+//! session.close()?;
+//! # Ok::<(), ta_manager::ClientError>(())
+//! ```
+//!
+//! Failures are reported as [`ClientError`]s, which tell whether the request
+//! is worth sending again, and convert into `optee_utee::Error`s.
+
+use std::{mem, sync::Arc, time::Duration};
+
+use crate::client::{ClientError, ClientPool};
+use crate::protocol::{BatchResult, ClientIdentity, Parameters};
+
+/// Connection of a CA to the hosted TAs, like a `TEEC_Context`.
+///
+/// Contexts are cheap to clone, and clones share their connections.
+#[derive(Clone)]
+pub struct Context {
+    pool: Arc<ClientPool>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    /// Creates a context reaching the TAs through their sockets in the
+    /// default socket directory.
+    pub fn new() -> Self {
+        Self::with_pool(ClientPool::new(1))
+    }
+
+    /// Creates a context sending its requests through `pool`, e.g. to change
+    /// the socket directory or the codec.
+    pub fn with_pool(pool: ClientPool) -> Self {
+        Self {
+            pool: Arc::new(pool),
+        }
+    }
+
+    /// Opens a session on the TA `uuid` with the public login.
+    pub fn open_session(&self, uuid: &str, params: Parameters) -> Result<Session, ClientError> {
+        self.open_session_with_login(uuid, params, ClientIdentity::default())
+    }
+
+    /// Opens a session on the TA `uuid` on behalf of the client `identity`.
+    pub fn open_session_with_login(
+        &self,
+        uuid: &str,
+        params: Parameters,
+        identity: ClientIdentity,
+    ) -> Result<Session, ClientError> {
+        let session_id = self.pool.open_session(uuid, params, identity)?;
+        Ok(Session {
+            pool: self.pool.clone(),
+            uuid: uuid.to_string(),
+            session_id,
+            open: true,
+        })
+    }
+}
+
+/// Session opened on a TA, like a `TEEC_Session`. It is closed when dropped.
+pub struct Session {
+    pool: Arc<ClientPool>,
+    uuid: String,
+    session_id: u32,
+    open: bool,
+}
+
+impl Session {
+    /// Returns the id the TA manager gave to the session.
+    pub fn id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Returns the uuid of the TA the session is opened on.
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// Invokes the command `cmd_id` on the TA and replaces `params` with the
+    /// parameters updated by the TA. The TA manager does not send them back
+    /// when the command fails, so `params` is then reset to its default.
+    pub fn invoke_command(
+        &mut self,
+        cmd_id: u32,
+        params: &mut Parameters,
+    ) -> Result<(), ClientError> {
+        let sent = mem::take(params);
+        *params = self
+            .pool
+            .invoke_command(&self.uuid, self.session_id, cmd_id, sent)?;
+        Ok(())
+    }
+
+    /// Invokes a command like [`invoke_command`](Self::invoke_command), but
+    /// fails with `Timeout` if the TA did not complete it within `timeout`.
+    pub fn invoke_command_with_timeout(
+        &mut self,
+        cmd_id: u32,
+        params: &mut Parameters,
+        timeout: Duration,
+    ) -> Result<(), ClientError> {
+        let sent = mem::take(params);
+        *params = self.pool.invoke_command_with_timeout(
+            &self.uuid,
+            self.session_id,
+            cmd_id,
+            sent,
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Invokes `commands`, given as command id and parameters, one after the
+    /// other in a single round trip to the TA manager, see
+    /// [`ClientPool::invoke_batch`].
+    pub fn invoke_batch(
+        &mut self,
+        commands: Vec<(u32, Parameters)>,
+    ) -> Result<Vec<BatchResult>, ClientError> {
+        self.pool
+            .invoke_batch(&self.uuid, self.session_id, commands)
+    }
+
+    /// Closes the session, reporting the error the TA returned if any.
+    pub fn close(mut self) -> Result<(), ClientError> {
+        self.open = false;
+        self.pool.close_session(&self.uuid, self.session_id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.pool.close_session(&self.uuid, self.session_id);
+        }
+    }
+}
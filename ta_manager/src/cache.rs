@@ -0,0 +1,68 @@
+//! Responses of the commands the TA marks idempotent, replayed without
+//! running the command again until they expire, see
+//! [`TAManagerConfig::response_cache_ttl`](crate::TAManagerConfig::response_cache_ttl).
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::CanonicalCodec;
+use crate::protocol::Parameters;
+
+// Number of responses cached past which the expired ones are dropped.
+const PRUNE_THRESHOLD: usize = 256;
+
+// Command id and canonical encoding of the parameters it was invoked with.
+#[derive(PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey(u32, Vec<u8>);
+
+impl CacheKey {
+    // Returns the key of `cmd_id` invoked with `params`, unless they cannot
+    // be encoded.
+    pub(crate) fn new(cmd_id: u32, params: &Parameters) -> Option<Self> {
+        CanonicalCodec::encode(params)
+            .ok()
+            .map(|params| Self(cmd_id, params))
+    }
+}
+
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    // Parameters returned by the TA, with when they expire.
+    entries: Mutex<HashMap<CacheKey, (Parameters, Instant)>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns the parameters cached under `key`, unless they expired.
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Parameters> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, expires)| Instant::now() < *expires)
+            .map(|(params, _)| params.clone())
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, params: Parameters) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= PRUNE_THRESHOLD {
+            entries.retain(|_, (_, expires)| now < *expires);
+        }
+        entries.insert(key, (params, now + self.ttl));
+    }
+
+    // Drops every response, e.g. once the instance that returned them is
+    // gone.
+    pub(crate) fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
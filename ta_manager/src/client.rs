@@ -0,0 +1,835 @@
+use std::{
+    collections::HashMap,
+    fmt, io, iter,
+    path::PathBuf,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use optee_utee::{Error, ErrorKind, ErrorOrigin};
+use tracing::warn;
+
+use crate::ca_socket_path;
+use crate::codec::{BincodeCodec, Codec};
+use crate::config::{default_server_socket, default_socket_dir};
+use crate::ipc::connect;
+use crate::protocol::{
+    BatchResult, CAPABILITIES, ClientIdentity, MAX_FRAME_SIZE, PROTOCOL_VERSION, ParamDelta,
+    Parameters, ReturnOrigin, TARequest, TAResponse, TaInfo, TeeRequest, TeeResponse,
+};
+use crate::stream;
+use crate::transport::Connection;
+#[cfg(feature = "encrypted_transport")]
+use crate::{
+    protocol::CAP_ENCRYPTION,
+    transport::{SecureChannel, Side, TransportKey, key_exchange_nonce},
+};
+
+const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+
+/// Failure of a request sent through a [`ClientPool`].
+#[derive(Debug)]
+pub enum ClientError {
+    /// The TA or the TA Manager server could not be reached, or the
+    /// connection broke.
+    Transport(anyhow::Error),
+    /// The peer sent something that does not follow the protocol, or refused
+    /// its version.
+    Protocol(anyhow::Error),
+    /// The request reached the TA manager, which answered with an error.
+    Tee {
+        kind: ErrorKind,
+        origin: ErrorOrigin,
+        /// Set along `TargetDead` when a standby instance took over from the
+        /// TA instance that died, see
+        /// [`TAManager::with_standby`](crate::TAManager::with_standby).
+        retry: bool,
+    },
+}
+
+impl ClientError {
+    /// Returns whether sending the request again may succeed: after
+    /// transport failures, which the pool recovers from by reconnecting,
+    /// `Busy` answers, e.g. from session or concurrency limits, and
+    /// `TargetDead` answers carrying the retry hint, on a newly opened
+    /// session. Errors returned by the TA itself are final, as is a
+    /// `Timeout`, since the command may have run partly.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::Transport(_) => true,
+            ClientError::Protocol(_) => false,
+            ClientError::Tee {
+                kind: ErrorKind::Busy,
+                ..
+            } => true,
+            ClientError::Tee {
+                kind: ErrorKind::TargetDead,
+                retry,
+                ..
+            } => *retry,
+            ClientError::Tee { .. } => false,
+        }
+    }
+
+    /// Returns the kind of the error as a TEE error: `Communication` for
+    /// transport failures and `BadFormat` for protocol violations.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ClientError::Transport(_) => ErrorKind::Communication,
+            ClientError::Protocol(_) => ErrorKind::BadFormat,
+            ClientError::Tee { kind, .. } => *kind,
+        }
+    }
+
+    // Sorts the failure to exchange a request by where it happened.
+    fn from_exchange(e: anyhow::Error) -> Self {
+        if e.chain().any(|cause| cause.is::<io::Error>()) {
+            ClientError::Transport(e)
+        } else {
+            ClientError::Protocol(e)
+        }
+    }
+
+    fn from_response(result: u32, origin: ReturnOrigin, retry: bool) -> Self {
+        ClientError::Tee {
+            kind: Error::from_raw_error(result).kind(),
+            origin: origin.into(),
+            retry,
+        }
+    }
+
+    fn unexpected(request: &str) -> Self {
+        ClientError::Protocol(anyhow::anyhow!("unexpected response to {}", request))
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(e) => write!(f, "transport failure: {:#}", e),
+            ClientError::Protocol(e) => write!(f, "protocol violation: {:#}", e),
+            ClientError::Tee { kind, origin, .. } => {
+                write!(f, "{} from {:?}", kind, origin)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Transport(e) | ClientError::Protocol(e) => Some(e.as_ref()),
+            ClientError::Tee { .. } => None,
+        }
+    }
+}
+
+/// Lets CAs written against `optee_utee::Result` keep using `?`: transport
+/// failures become `Communication` and protocol violations `BadFormat`, both
+/// with the `Comms` origin.
+impl From<ClientError> for Error {
+    fn from(e: ClientError) -> Error {
+        let kind = e.kind();
+        match e {
+            ClientError::Tee { origin, .. } => Error::new(kind).with_origin(origin),
+            _ => Error::new(kind).with_origin(ErrorOrigin::Comms),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+/// Connections from a CA to the TAs served by [`TAManager`](crate::TAManager)s.
+///
+/// Up to `connections_per_ta` connections are kept open to each TA and shared
+/// by the threads sending it requests: a request waits for a free connection,
+/// and waiting requests are served in arrival order. A connection that breaks
+/// is dropped and replaced by a new one on the next request.
+///
+/// Input memrefs larger than the chunk size, 1 MiB by default, are sent in
+/// chunks with the `InvokeStream*` requests rather than in a single frame.
+pub struct ClientPool {
+    connections_per_ta: usize,
+    chunk_size: usize,
+    codec: Arc<dyn Codec>,
+    socket_dir: PathBuf,
+    server_socket: PathBuf,
+    abstract_sockets: bool,
+    reconnect_attempts: u32,
+    reconnect_delay: Duration,
+    #[cfg(feature = "encrypted_transport")]
+    transport_key: Option<TransportKey>,
+    operation_id: AtomicU32,
+    tas: Mutex<HashMap<String, Arc<TaConnections>>>,
+}
+
+// Connections to one TA.
+#[derive(Default)]
+struct TaConnections {
+    state: Mutex<ConnectionState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ConnectionState {
+    idle: Vec<Connection>,
+    // Connections open, whether idle or in use.
+    open: usize,
+    // Requests take a ticket and get a connection once `serving` reaches it.
+    next_ticket: u64,
+    serving: u64,
+}
+
+impl TaConnections {
+    // Waits for the turn of the caller and returns an idle connection, or
+    // `None` if the caller may open a new one.
+    fn acquire(&self, max: usize) -> Option<Connection> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        while ticket != state.serving || (state.idle.is_empty() && state.open >= max) {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.serving += 1;
+        self.changed.notify_all();
+
+        let stream = state.idle.pop();
+        if stream.is_none() {
+            state.open += 1;
+        }
+        stream
+    }
+
+    // Gives a connection back, or `None` if it broke and was dropped.
+    fn release(&self, stream: Option<Connection>) {
+        let mut state = self.state.lock().unwrap();
+        match stream {
+            Some(stream) => state.idle.push(stream),
+            None => state.open -= 1,
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl ClientPool {
+    /// Creates a pool keeping up to `connections_per_ta` connections to each
+    /// TA, at least one.
+    pub fn new(connections_per_ta: usize) -> Self {
+        Self {
+            connections_per_ta: connections_per_ta.max(1),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            codec: Arc::new(BincodeCodec),
+            socket_dir: default_socket_dir(),
+            server_socket: default_server_socket(),
+            abstract_sockets: false,
+            reconnect_attempts: 3,
+            reconnect_delay: Duration::from_millis(100),
+            #[cfg(feature = "encrypted_transport")]
+            transport_key: None,
+            operation_id: AtomicU32::new(1),
+            tas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the codec used to talk to the TA managers, [`BincodeCodec`] by
+    /// default. It must match theirs.
+    pub fn with_codec(mut self, codec: impl Codec) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    // Sets a codec already shared with a manager, e.g. that of its config.
+    pub(crate) fn with_shared_codec(mut self, codec: Arc<dyn Codec>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Sets the directory holding the TA sockets, by default the one
+    /// [`TAManagerConfig::socket_dir`](crate::TAManagerConfig::socket_dir)
+    /// defaults to.
+    pub fn with_socket_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.socket_dir = dir.into();
+        self
+    }
+
+    /// Sets the socket of the TA Manager server queried by
+    /// [`discover_tas`](Self::discover_tas), by default the one
+    /// [`TAManagerConfig::server_socket`](crate::TAManagerConfig::server_socket)
+    /// defaults to.
+    pub fn with_server_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.server_socket = path.into();
+        self
+    }
+
+    /// Reaches the TA sockets and the TA Manager server in the abstract
+    /// namespace, as the managers do with
+    /// [`TAManagerConfig::abstract_sockets`](crate::TAManagerConfig::abstract_sockets).
+    pub fn with_abstract_sockets(self) -> Self {
+        self.with_abstract_namespace(true)
+    }
+
+    // Sets whether the sockets are in the abstract namespace, e.g. as in the
+    // config of a manager.
+    pub(crate) fn with_abstract_namespace(mut self, abstract_sockets: bool) -> Self {
+        self.abstract_sockets = abstract_sockets;
+        self
+    }
+
+    /// Sets how many more times connecting to a TA is attempted, waiting
+    /// `delay` in between, e.g. while its manager restarts. Three attempts
+    /// 100 ms apart by default.
+    pub fn with_reconnect(mut self, attempts: u32, delay: Duration) -> Self {
+        self.reconnect_attempts = attempts;
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Seals the connections to the TA managers with keys derived from `key`,
+    /// which they must share, see
+    /// [`TAManagerConfig::with_transport_key`](crate::TAManagerConfig::with_transport_key).
+    /// Connecting to a manager that does not offer encryption then fails.
+    #[cfg(feature = "encrypted_transport")]
+    pub fn with_transport_key(mut self, key: TransportKey) -> Self {
+        self.transport_key = Some(key);
+        self
+    }
+
+    // Sets a transport key that may be missing, e.g. that of a config.
+    #[cfg(feature = "encrypted_transport")]
+    pub(crate) fn with_optional_transport_key(mut self, key: Option<TransportKey>) -> Self {
+        self.transport_key = key;
+        self
+    }
+
+    /// Sets the size above which input memrefs are sent in chunks, and the
+    /// size of the chunks. Sizes above half of
+    /// [`MAX_FRAME_SIZE`](crate::protocol::MAX_FRAME_SIZE) are lowered to it.
+    pub fn with_chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.clamp(1, MAX_FRAME_SIZE as usize / 2);
+        self
+    }
+
+    /// Opens a session on the TA `uuid` and returns its id.
+    pub fn open_session(
+        &self,
+        uuid: &str,
+        params: Parameters,
+        identity: ClientIdentity,
+    ) -> Result<u32> {
+        let req = TeeRequest::OpenSession {
+            uuid: uuid.to_string(),
+            connection_method: identity.login,
+            params,
+            identity,
+        };
+        match self.call(uuid, req)? {
+            TeeResponse::OpenSession {
+                session_id,
+                result: 0,
+                ..
+            } => Ok(session_id),
+            TeeResponse::OpenSession { result, origin, .. } => {
+                Err(ClientError::from_response(result, origin, false))
+            }
+            _ => Err(ClientError::unexpected("OpenSession")),
+        }
+    }
+
+    /// Invokes a command on a session of the TA `uuid` and returns the
+    /// parameters updated by the TA.
+    pub fn invoke_command(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        cmd_id: u32,
+        params: Parameters,
+    ) -> Result<Parameters> {
+        self.invoke(uuid, session_id, cmd_id, params, None)
+    }
+
+    /// Invokes a command like [`invoke_command`](Self::invoke_command), but
+    /// gives up after `timeout`. The TA sees the deadline in its
+    /// [`CommandContext`](crate::CommandContext) and the command as cancelled
+    /// once it passed.
+    ///
+    /// # Errors
+    ///
+    /// 1) `Timeout`: If the TA did not complete the command in time.
+    /// 2) Errors of [`invoke_command`](Self::invoke_command).
+    pub fn invoke_command_with_timeout(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        cmd_id: u32,
+        params: Parameters,
+        timeout: Duration,
+    ) -> Result<Parameters> {
+        let timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        self.invoke(uuid, session_id, cmd_id, params, Some(timeout_ms))
+    }
+
+    /// Invokes `commands`, given as command id and parameters, one after the
+    /// other on a session of the TA `uuid` in a single round trip, and
+    /// returns the outcome of each. A command failing does not stop the next
+    /// ones. The whole batch must fit in a frame of
+    /// [`MAX_FRAME_SIZE`](crate::protocol::MAX_FRAME_SIZE) bytes.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If a parameter does not match its type.
+    /// 2) `ItemNotFound`: If the session does not exist.
+    /// 3) `TargetDead`: If the TA panicked, which also ends the session.
+    ///    The outcome of the commands that completed before is lost.
+    pub fn invoke_batch(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        commands: Vec<(u32, Parameters)>,
+    ) -> Result<Vec<BatchResult>> {
+        let req = TeeRequest::InvokeBatch {
+            session_id,
+            operation_id: self.operation_id.fetch_add(1, Ordering::Relaxed),
+            commands,
+            timeout_ms: None,
+        };
+        match self.call(uuid, req)? {
+            TeeResponse::InvokeBatch {
+                results, result: 0, ..
+            } => Ok(results),
+            TeeResponse::InvokeBatch {
+                result,
+                origin,
+                retry,
+                ..
+            } => Err(ClientError::from_response(result, origin, retry)),
+            _ => Err(ClientError::unexpected("InvokeBatch")),
+        }
+    }
+
+    /// Registers `params` as a template of the parameters of the commands
+    /// invoked on a session of the TA `uuid`, and returns its id for
+    /// [`invoke_template`](Self::invoke_template). The template is kept by
+    /// the manager until the session is closed, so a large memref is sent
+    /// only once.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If a parameter does not match its type.
+    /// 2) `ItemNotFound`: If the session does not exist.
+    /// 3) `OutOfMemory`: If the session already has
+    ///    [`MAX_TEMPLATES`](crate::protocol::MAX_TEMPLATES) templates.
+    pub fn register_template(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        params: Parameters,
+    ) -> Result<u32> {
+        match self.call(uuid, TeeRequest::RegisterTemplate { session_id, params })? {
+            TeeResponse::RegisterTemplate {
+                template_id,
+                result: 0,
+                ..
+            } => Ok(template_id),
+            TeeResponse::RegisterTemplate { result, origin, .. } => {
+                Err(ClientError::from_response(result, origin, false))
+            }
+            _ => Err(ClientError::unexpected("RegisterTemplate")),
+        }
+    }
+
+    /// Invokes a command on a session of the TA `uuid` with the parameters
+    /// of the template `template_id` updated by `deltas`, and returns the
+    /// parameters updated by the TA. The input memrefs are returned empty.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If the template does not exist, or a delta does
+    ///    not match the parameter it updates or falls outside its memref.
+    /// 2) Errors of [`invoke_command`](Self::invoke_command).
+    pub fn invoke_template(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        cmd_id: u32,
+        template_id: u32,
+        deltas: Vec<ParamDelta>,
+    ) -> Result<Parameters> {
+        let req = TeeRequest::InvokeTemplate {
+            session_id,
+            cmd_id,
+            operation_id: self.operation_id.fetch_add(1, Ordering::Relaxed),
+            template_id,
+            deltas,
+            timeout_ms: None,
+        };
+        match self.call(uuid, req)? {
+            TeeResponse::InvokeCommand {
+                params, result: 0, ..
+            } => Ok(params),
+            TeeResponse::InvokeCommand {
+                result,
+                origin,
+                retry,
+                ..
+            } => Err(ClientError::from_response(result, origin, retry)),
+            _ => Err(ClientError::unexpected("InvokeTemplate")),
+        }
+    }
+
+    fn invoke(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        cmd_id: u32,
+        params: Parameters,
+        timeout_ms: Option<u32>,
+    ) -> Result<Parameters> {
+        let operation_id = self.operation_id.fetch_add(1, Ordering::Relaxed);
+        match self.send_invoke(uuid, session_id, cmd_id, operation_id, params, timeout_ms)? {
+            TeeResponse::InvokeCommand {
+                params, result: 0, ..
+            } => Ok(params),
+            TeeResponse::InvokeCommand {
+                result,
+                origin,
+                retry,
+                ..
+            } => Err(ClientError::from_response(result, origin, retry)),
+            TeeResponse::InvokeStream { result, origin } => {
+                Err(ClientError::from_response(result, origin, false))
+            }
+            _ => Err(ClientError::unexpected("InvokeCommand")),
+        }
+    }
+
+    // Sends a command with the given operation id, in chunks if its
+    // parameters do not fit in a frame, and returns the response as is.
+    pub(crate) fn send_invoke(
+        &self,
+        uuid: &str,
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
+        params: Parameters,
+        timeout_ms: Option<u32>,
+    ) -> Result<TeeResponse> {
+        if stream::oversized(&params, self.chunk_size) {
+            let reqs = stream::requests(
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+                self.chunk_size,
+            );
+            self.call_all(uuid, reqs)
+        } else {
+            let req = TeeRequest::InvokeCommand {
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+            };
+            self.call(uuid, req)
+        }
+    }
+
+    /// Closes a session of the TA `uuid`.
+    pub fn close_session(&self, uuid: &str, session_id: u32) -> Result<()> {
+        match self.call(uuid, TeeRequest::CloseSession { session_id })? {
+            TeeResponse::CloseSession { result: 0, .. } => Ok(()),
+            TeeResponse::CloseSession { result, origin } => {
+                Err(ClientError::from_response(result, origin, false))
+            }
+            _ => Err(ClientError::unexpected("CloseSession")),
+        }
+    }
+
+    /// Subscribes to the events the TA `uuid` emits while the session
+    /// `session_id` is open, see
+    /// [`NotificationSender`](crate::NotificationSender). The subscription
+    /// has a connection of its own, outside of the pool.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If the session does not exist.
+    /// 2) `Communication`: If the TA could not be reached.
+    pub fn subscribe(&self, uuid: &str, session_id: u32) -> Result<Subscription> {
+        let subscribed = self.connect(uuid).and_then(|mut stream| {
+            let req = TeeRequest::Subscribe { session_id };
+            stream.write_frame(&self.codec.encode_request(&req)?)?;
+            let resp = self.read_response(&mut stream)?;
+            Ok((stream, resp))
+        });
+        let (stream, resp) = subscribed.map_err(|e| {
+            warn!(uuid, error = ?e, "Failed to reach TA");
+            ClientError::from_exchange(e)
+        })?;
+        match resp {
+            TeeResponse::Subscribe { result: 0, .. } => Ok(Subscription {
+                stream,
+                codec: self.codec.clone(),
+            }),
+            TeeResponse::Subscribe { result, origin } => {
+                Err(ClientError::from_response(result, origin, false))
+            }
+            _ => Err(ClientError::unexpected("Subscribe")),
+        }
+    }
+
+    /// Asks the TA Manager server which TAs are currently registered.
+    pub fn discover_tas(&self) -> Result<Vec<TaInfo>> {
+        self.list_tas().map_err(|e| {
+            warn!(error = ?e, "Failed to reach the TA Manager server");
+            ClientError::from_exchange(e)
+        })
+    }
+
+    fn list_tas(&self) -> anyhow::Result<Vec<TaInfo>> {
+        let config = bincode::config::standard();
+        let mut stream = connect(&self.server_socket, self.abstract_sockets)?;
+        bincode::encode_into_std_write(TARequest::List, &mut stream, config)?;
+        match bincode::decode_from_std_read(&mut stream, config)? {
+            TAResponse::List { tas } => Ok(tas),
+            _ => anyhow::bail!("unexpected response to List"),
+        }
+    }
+
+    /// Sends `req` to the TA `uuid` on one of the pooled connections and
+    /// returns the response of its manager.
+    pub fn request(&self, uuid: &str, req: TeeRequest) -> anyhow::Result<TeeResponse> {
+        self.request_all(uuid, iter::once(req))
+    }
+
+    // Sends `reqs` one after the other on the same connection, stopping at
+    // the first one that fails, and returns the last response.
+    fn request_all(
+        &self,
+        uuid: &str,
+        reqs: impl IntoIterator<Item = TeeRequest>,
+    ) -> anyhow::Result<TeeResponse> {
+        let mut reqs = reqs.into_iter();
+        let Some(first) = reqs.next() else {
+            anyhow::bail!("no request to send");
+        };
+        let body = self.codec.encode_request(&first)?;
+        let connections = self.connections(uuid);
+        let stream = match connections.acquire(self.connections_per_ta) {
+            Some(stream) => stream,
+            None => match self.connect(uuid) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    connections.release(None);
+                    return Err(e);
+                }
+            },
+        };
+
+        let exchanged = self
+            .exchange(uuid, stream, &body)
+            .and_then(|(mut stream, mut resp)| {
+                for req in reqs {
+                    if resp.result() != 0 {
+                        break;
+                    }
+                    stream.write_frame(&self.codec.encode_request(&req)?)?;
+                    resp = self.read_response(&mut stream)?;
+                }
+                Ok((stream, resp))
+            });
+        match exchanged {
+            Ok((stream, resp)) => {
+                connections.release(Some(stream));
+                Ok(resp)
+            }
+            Err(e) => {
+                connections.release(None);
+                Err(e)
+            }
+        }
+    }
+
+    // Sends a request frame and reads the response. An idle connection may
+    // have been closed by the manager meanwhile, e.g. because it restarted, so
+    // the request is sent again on a new connection if writing fails.
+    fn exchange(
+        &self,
+        uuid: &str,
+        mut stream: Connection,
+        body: &[u8],
+    ) -> anyhow::Result<(Connection, TeeResponse)> {
+        if stream.write_frame(body).is_err() {
+            stream = self.connect(uuid)?;
+            stream.write_frame(body)?;
+        }
+        let resp = self.read_response(&mut stream)?;
+        Ok((stream, resp))
+    }
+
+    fn call(&self, uuid: &str, req: TeeRequest) -> Result<TeeResponse> {
+        self.call_all(uuid, iter::once(req))
+    }
+
+    fn call_all(
+        &self,
+        uuid: &str,
+        reqs: impl IntoIterator<Item = TeeRequest>,
+    ) -> Result<TeeResponse> {
+        self.request_all(uuid, reqs).map_err(|e| {
+            warn!(uuid, error = ?e, "Failed to reach TA");
+            ClientError::from_exchange(e)
+        })
+    }
+
+    fn connections(&self, uuid: &str) -> Arc<TaConnections> {
+        self.tas
+            .lock()
+            .unwrap()
+            .entry(uuid.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // Connects to the TA `uuid`, negotiates the protocol version and seals
+    // the connection if the pool has a transport key.
+    fn connect(&self, uuid: &str) -> anyhow::Result<Connection> {
+        let path = ca_socket_path(&self.socket_dir, uuid);
+        let mut attempt = 0;
+        let stream = loop {
+            match connect(&path, self.abstract_sockets) {
+                Ok(stream) => break stream,
+                Err(e) if attempt < self.reconnect_attempts => {
+                    warn!(uuid, error = %e, "Failed to connect to TA, retrying");
+                    attempt += 1;
+                    thread::sleep(self.reconnect_delay);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut stream = Connection::new(stream);
+
+        let hello = self.codec.encode_request(&TeeRequest::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: self.capabilities(),
+        })?;
+        stream.write_frame(&hello)?;
+        #[cfg_attr(not(feature = "encrypted_transport"), allow(unused_variables))]
+        let capabilities = match self.read_response(&mut stream)? {
+            TeeResponse::Hello {
+                version,
+                capabilities,
+                result: 0,
+                ..
+            } if version == PROTOCOL_VERSION => capabilities,
+            TeeResponse::Hello {
+                version, result, ..
+            } => anyhow::bail!(
+                "TA manager refused protocol version {} (result {:#x}, offered {})",
+                PROTOCOL_VERSION,
+                result,
+                version
+            ),
+            _ => anyhow::bail!("unexpected response to Hello"),
+        };
+        #[cfg(feature = "encrypted_transport")]
+        if let Some(key) = &self.transport_key {
+            anyhow::ensure!(
+                capabilities & CAP_ENCRYPTION != 0,
+                "TA manager does not offer encryption"
+            );
+            self.exchange_keys(&mut stream, key, capabilities)?;
+        }
+        Ok(stream)
+    }
+
+    // Capabilities offered in `Hello`.
+    fn capabilities(&self) -> u32 {
+        #[cfg(feature = "encrypted_transport")]
+        if self.transport_key.is_some() {
+            return CAPABILITIES | CAP_ENCRYPTION;
+        }
+        CAPABILITIES
+    }
+
+    // Agrees on the keys sealing `stream` with its manager, which agreed on
+    // `capabilities`.
+    #[cfg(feature = "encrypted_transport")]
+    fn exchange_keys(
+        &self,
+        stream: &mut Connection,
+        key: &TransportKey,
+        capabilities: u32,
+    ) -> anyhow::Result<()> {
+        let nonce = key_exchange_nonce();
+        let req = TeeRequest::KeyExchange {
+            nonce: nonce.clone(),
+        };
+        stream.write_frame(&self.codec.encode_request(&req)?)?;
+        match self.read_response(stream)? {
+            TeeResponse::KeyExchange {
+                nonce: manager_nonce,
+                result: 0,
+                ..
+            } => {
+                stream.seal(SecureChannel::new(
+                    key,
+                    Side::Client,
+                    &nonce,
+                    &manager_nonce,
+                    PROTOCOL_VERSION,
+                    capabilities,
+                )?);
+                Ok(())
+            }
+            TeeResponse::KeyExchange { result, .. } => {
+                anyhow::bail!("TA manager refused the key exchange (result {:#x})", result)
+            }
+            _ => anyhow::bail!("unexpected response to KeyExchange"),
+        }
+    }
+
+    fn read_response(&self, stream: &mut Connection) -> anyhow::Result<TeeResponse> {
+        match stream.read_frame()? {
+            Some(buf) => self.codec.decode_response(&buf),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by the TA manager",
+            )
+            .into()),
+        }
+    }
+}
+
+/// Events of a TA received by a CA, returned by
+/// [`ClientPool::subscribe`].
+pub struct Subscription {
+    stream: Connection,
+    codec: Arc<dyn Codec>,
+}
+
+impl Subscription {
+    /// Waits for the next event of the TA and returns its id and data, or
+    /// `None` once the subscription ended, e.g. because the session was
+    /// closed.
+    pub fn recv(&mut self) -> Result<Option<(u32, Vec<u8>)>> {
+        let buf = match self.stream.read_frame() {
+            Ok(Some(buf)) => buf,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(ClientError::Transport(e.into())),
+        };
+        match self.codec.decode_response(&buf) {
+            Ok(TeeResponse::Notification { event, data }) => Ok(Some((event, data))),
+            Ok(_) => Err(ClientError::unexpected("Subscribe")),
+            Err(e) => Err(ClientError::Protocol(e)),
+        }
+    }
+}
+
+pub(crate) fn response_error(result: u32, origin: ReturnOrigin) -> Error {
+    Error::from_raw_error(result).with_origin(origin.into())
+}
@@ -0,0 +1,250 @@
+use std::{
+    fmt::Debug,
+    io::{self, Read, Write},
+};
+
+use bincode::{
+    Decode, Encode,
+    config::{self, Config},
+};
+
+use crate::protocol::{MAX_FRAME_SIZE, TeeRequest, TeeResponse};
+
+/// Encoding of the frames exchanged with CAs.
+///
+/// Frames keep their 4-byte length prefix whatever the codec; the codec only
+/// turns the frame body into a [`TeeRequest`] or a [`TeeResponse`] and back.
+pub trait Codec: Debug + Send + Sync + 'static {
+    fn encode_request(&self, req: &TeeRequest) -> anyhow::Result<Vec<u8>>;
+
+    fn decode_request(&self, buf: &[u8]) -> anyhow::Result<TeeRequest>;
+
+    fn encode_response(&self, resp: &TeeResponse) -> anyhow::Result<Vec<u8>>;
+
+    fn decode_response(&self, buf: &[u8]) -> anyhow::Result<TeeResponse>;
+}
+
+// Most bytes a decoded message may take in memory, whatever lengths its
+// buffers and vectors announce, so that a frame cannot make its peer
+// allocate much more than a frame of data. bincode counts the in-memory
+// size of every integer, several times the size of its encoding.
+const DECODE_LIMIT: usize = 4 * MAX_FRAME_SIZE as usize;
+
+// Index of `TeeRequest::Traced` among the variants of `TeeRequest`, which
+// bincode encodes first.
+const TRACED_VARIANT: u32 = 10;
+
+// Most `Traced` requests decoded nested in one another. The manager answers
+// nested ones with an error, but decoding them recurses.
+const MAX_TRACED_DEPTH: usize = 8;
+
+// Configuration `BincodeCodec` decodes with.
+const STANDARD_LIMITED: config::Configuration<
+    config::LittleEndian,
+    config::Varint,
+    config::Limit<DECODE_LIMIT>,
+> = config::standard().with_limit::<DECODE_LIMIT>();
+
+// Decodes a message spanning all of `buf`.
+fn decode_strict<T: Decode<()>, C: Config>(buf: &[u8], config: C) -> anyhow::Result<T> {
+    let (value, len) = bincode::decode_from_slice(buf, config)?;
+    anyhow::ensure!(
+        len == buf.len(),
+        "{} trailing bytes after the message",
+        buf.len() - len
+    );
+    Ok(value)
+}
+
+// Decodes a request, refusing `Traced` requests nested deeper than
+// `MAX_TRACED_DEPTH` before decoding them rather than recursing as deep as
+// the frame goes.
+fn decode_request_strict<C: Config>(buf: &[u8], config: C) -> anyhow::Result<TeeRequest> {
+    let mut rest = buf;
+    for _ in 0..=MAX_TRACED_DEPTH {
+        let (variant, len): (u32, _) = bincode::decode_from_slice(rest, config)?;
+        if variant != TRACED_VARIANT {
+            return decode_strict(buf, config);
+        }
+        let (_, trace_id_len): (u64, _) = bincode::decode_from_slice(&rest[len..], config)?;
+        rest = &rest[len + trace_id_len..];
+    }
+    anyhow::bail!("Traced requests nested more than {} deep", MAX_TRACED_DEPTH)
+}
+
+/// bincode with its standard configuration, the default codec.
+///
+/// Like [`CanonicalCodec`], it decodes strictly: a message must span its
+/// whole frame, it may not announce buffers and vectors much larger than a
+/// frame, and `Traced` requests may not be nested more than 8 deep.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+impl Codec for BincodeCodec {
+    fn encode_request(&self, req: &TeeRequest) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(req, bincode::config::standard())?)
+    }
+
+    fn decode_request(&self, buf: &[u8]) -> anyhow::Result<TeeRequest> {
+        decode_request_strict(buf, STANDARD_LIMITED)
+    }
+
+    fn encode_response(&self, resp: &TeeResponse) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(resp, bincode::config::standard())?)
+    }
+
+    fn decode_response(&self, buf: &[u8]) -> anyhow::Result<TeeResponse> {
+        decode_strict(buf, STANDARD_LIMITED)
+    }
+}
+
+/// bincode with fixed-width integers, for messages that get signed or
+/// MAC'd: a message has a single encoding, which does not change across
+/// versions of the protocol as long as the message does not.
+///
+/// Integers are encoded little-endian on their full width, lengths as
+/// `u64`, enum variants as their `u32` index, `Option`s as a 0 or 1 byte
+/// followed by the value, and struct fields in declaration order. Decoding
+/// refuses anything but the canonical encoding, e.g. trailing bytes, as well
+/// as buffers and vectors announced much larger than a frame.
+///
+/// Besides its use as the codec of a connection, any protocol type can be
+/// encoded on its own, e.g. the identity of a client:
+///
+/// ```
+/// use ta_manager::CanonicalCodec;
+/// use ta_manager::protocol::ClientIdentity;
+///
+/// let identity = ClientIdentity { login: 4, uuid: [0xAA; 16] };
+/// let bytes = CanonicalCodec::encode(&identity)?;
+/// assert_eq!(&bytes[..4], &[4, 0, 0, 0]);
+/// assert_eq!(&bytes[4..], &[0xAA; 16]);
+/// let decoded: ClientIdentity = CanonicalCodec::decode(&bytes)?;
+/// assert_eq!(decoded.login, 4);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CanonicalCodec;
+
+impl CanonicalCodec {
+    const CONFIG: config::Configuration<config::LittleEndian, config::Fixint> =
+        config::standard().with_fixed_int_encoding();
+    const DECODE_CONFIG: config::Configuration<
+        config::LittleEndian,
+        config::Fixint,
+        config::Limit<DECODE_LIMIT>,
+    > = Self::CONFIG.with_limit::<DECODE_LIMIT>();
+
+    /// Returns the canonical encoding of `value`.
+    pub fn encode<T: Encode>(value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(value, Self::CONFIG)?)
+    }
+
+    /// Decodes a value from its canonical encoding, which must span all of
+    /// `buf`.
+    pub fn decode<T: Decode<()>>(buf: &[u8]) -> anyhow::Result<T> {
+        decode_strict(buf, Self::DECODE_CONFIG)
+    }
+}
+
+impl Codec for CanonicalCodec {
+    fn encode_request(&self, req: &TeeRequest) -> anyhow::Result<Vec<u8>> {
+        Self::encode(req)
+    }
+
+    fn decode_request(&self, buf: &[u8]) -> anyhow::Result<TeeRequest> {
+        decode_request_strict(buf, Self::DECODE_CONFIG)
+    }
+
+    fn encode_response(&self, resp: &TeeResponse) -> anyhow::Result<Vec<u8>> {
+        Self::encode(resp)
+    }
+
+    fn decode_response(&self, buf: &[u8]) -> anyhow::Result<TeeResponse> {
+        Self::decode(buf)
+    }
+}
+
+/// CBOR, for CAs written in other languages. Enum variants are encoded as
+/// single-entry maps keyed by the variant name and structs as maps keyed by
+/// field name, e.g. `{"CloseSession": {"session_id": 1}}`.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl Codec for CborCodec {
+    fn encode_request(&self, req: &TeeRequest) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(req, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn decode_request(&self, buf: &[u8]) -> anyhow::Result<TeeRequest> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+
+    fn encode_response(&self, resp: &TeeResponse) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(resp, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn decode_response(&self, buf: &[u8]) -> anyhow::Result<TeeResponse> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+/// JSON, mostly for test harnesses. Buffers are encoded as arrays of bytes.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    fn encode_request(&self, req: &TeeRequest) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(req)?)
+    }
+
+    fn decode_request(&self, buf: &[u8]) -> anyhow::Result<TeeRequest> {
+        Ok(serde_json::from_slice(buf)?)
+    }
+
+    fn encode_response(&self, resp: &TeeResponse) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(resp)?)
+    }
+
+    fn decode_response(&self, buf: &[u8]) -> anyhow::Result<TeeResponse> {
+        Ok(serde_json::from_slice(buf)?)
+    }
+}
+
+// Reads the body of a length-prefixed frame, or `None` if the peer closed the
+// stream instead of starting a new frame. Frames larger than `MAX_FRAME_SIZE`
+// are refused before reading their body.
+pub(crate) fn read_frame(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_ne_bytes(len_buf);
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the maximum size", len),
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf)?;
+    Ok(Some(buf))
+}
+
+// Writes `body` as a length-prefixed frame.
+pub(crate) fn write_frame(stream: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(4 + body.len());
+    message.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+    message.extend_from_slice(body);
+    stream.write_all(&message)
+}
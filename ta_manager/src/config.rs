@@ -0,0 +1,543 @@
+use std::{collections::HashMap, env, path::PathBuf, sync::Arc, time::Duration};
+
+use crate::audit::AuditSink;
+use crate::authz::Authorizer;
+use crate::codec::{BincodeCodec, Codec};
+use crate::policy::AccessPolicy;
+#[cfg(feature = "secure_storage")]
+use crate::storage::SecureStorage;
+use crate::supplicant::SupplicantPlugin;
+#[cfg(feature = "encrypted_transport")]
+use crate::transport::TransportKey;
+
+/// Instance semantics declared by a GP TA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaFlags {
+    /// All sessions share one instance. A multi-instance TA gets its
+    /// instance destroyed as soon as no session is left.
+    pub single_instance: bool,
+    /// Several sessions may be open at once. Otherwise opening a session
+    /// while another one is open fails with `Busy`.
+    pub multi_session: bool,
+    /// A single-instance TA keeps its instance once the last session is
+    /// closed. Otherwise the instance is destroyed and created again for the
+    /// next session.
+    pub instance_keep_alive: bool,
+}
+
+impl Default for TaFlags {
+    fn default() -> Self {
+        Self {
+            single_instance: true,
+            multi_session: true,
+            instance_keep_alive: true,
+        }
+    }
+}
+
+impl TaFlags {
+    pub(crate) fn keeps_instance_alive(&self) -> bool {
+        self.single_instance && self.instance_keep_alive
+    }
+}
+
+/// What a session does with a command arriving while its queue holds
+/// [`TAManagerConfig::session_queue_depth`] commands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// The command waits for room in the queue.
+    #[default]
+    Block,
+    /// The command fails with `Busy`.
+    Reject,
+    /// The oldest queued command fails with `Busy` and the new one is queued.
+    DropOldest,
+}
+
+/// How the TA executes the commands of sessions, see
+/// [`TAManagerConfig::execution_model`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionModel {
+    /// Every session is served by a thread of its own.
+    #[default]
+    ThreadPerSession,
+    /// The sessions are shared by a fixed number of worker threads, at least
+    /// one. Sessions with commands waiting take turns on the first worker
+    /// free, each running as many commands in a turn as its
+    /// [weight](TAManagerConfig::session_weights), and the commands of a
+    /// session still run in order. Sessions then cost no thread, but a
+    /// command holds up a worker until it returns, including a command the
+    /// watchdog reported as stuck.
+    WorkerPool { workers: usize },
+}
+
+/// Token bucket limiting the rate of the requests of CAs, see
+/// [`TAManagerConfig::with_rate_limit`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Requests allowed per second on average.
+    pub requests_per_sec: u32,
+    /// Requests allowed in a row after a quiet period.
+    pub burst: u32,
+    /// What the limit applies to.
+    pub key: RateLimitKey,
+}
+
+/// Whose requests share a [`RateLimit`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// All the connections of CAs running as the same uid.
+    #[default]
+    Client,
+    /// Each connection on its own.
+    Connection,
+}
+
+pub(crate) const DEFAULT_SESSION_QUEUE_DEPTH: usize = 64;
+pub(crate) const DEFAULT_MAX_STREAM_SIZE: usize = 256 << 20;
+
+// Directory holding the sockets of the TAs: `TA_MANAGER_SOCKET_DIR`, else
+// `XDG_RUNTIME_DIR`, else /tmp.
+pub(crate) fn default_socket_dir() -> PathBuf {
+    env::var_os("TA_MANAGER_SOCKET_DIR")
+        .or_else(|| env::var_os("XDG_RUNTIME_DIR"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+}
+
+// Socket of the TA Manager server: `TA_MANAGER_SERVER_SOCKET`, else
+// `server.sock` in the socket directory.
+pub(crate) fn default_server_socket() -> PathBuf {
+    env::var_os("TA_MANAGER_SERVER_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_socket_dir().join("server.sock"))
+}
+
+/// Runtime configuration of a [`TAManager`](crate::TAManager).
+#[derive(Clone, Debug)]
+pub struct TAManagerConfig {
+    /// Sessions that receive no request for this long are closed on the TA
+    /// and evicted. `None` keeps idle sessions forever.
+    pub idle_timeout: Option<Duration>,
+    /// Encoding of the frames exchanged with CAs.
+    pub codec: Arc<dyn Codec>,
+    /// Who may open sessions on the TA.
+    pub policy: AccessPolicy,
+    /// Authorizer asked, after `policy`, before opening a session or
+    /// running a command. `None` lets every request through.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// Sink recording the security-relevant events of the manager. Not
+    /// recorded by default.
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    /// Instance semantics of the TA, those of a multi-session keep-alive TA
+    /// by default.
+    pub ta_flags: TaFlags,
+    /// Maximum number of sessions open at once. Opening more fails with
+    /// `Busy`. `None` sets no limit.
+    pub max_sessions: Option<usize>,
+    /// Maximum number of sessions open at once by CAs running as the same
+    /// uid. Opening more fails with `Busy`. `None` sets no limit.
+    pub max_sessions_per_client: Option<usize>,
+    /// Maximum number of concurrent executions of a command, by command id,
+    /// across all sessions. Invoking a command already running that many
+    /// times fails with `Busy`. Commands not listed are not limited.
+    pub command_limits: HashMap<u32, usize>,
+    /// Maximum number of commands queued or executing on one session. The
+    /// commands of a session run one after the other, so a CA sending more
+    /// of them at once gets `Busy` instead of growing the queue of the
+    /// session. `None` sets no limit.
+    pub max_in_flight_per_session: Option<usize>,
+    /// Rate at which CAs may open sessions and invoke commands. Requests
+    /// past it fail with `Busy`, while closing sessions and cancelling
+    /// commands are never limited. `None` sets no limit.
+    pub rate_limit: Option<RateLimit>,
+    /// How long the responses of the commands the TA marks idempotent,
+    /// through
+    /// [`TrustedApplication::is_idempotent`](crate::TrustedApplication::is_idempotent),
+    /// are replayed to CAs invoking them again with the same parameters.
+    /// Only successful responses are kept, and only until the TA instance is
+    /// destroyed. `None` runs every command.
+    pub response_cache_ttl: Option<Duration>,
+    /// Maximum number of commands waiting for a session to take them, 64 by
+    /// default.
+    pub session_queue_depth: usize,
+    /// What happens to commands sent to a session whose queue is full,
+    /// [`QueuePolicy::Block`] by default.
+    pub queue_policy: QueuePolicy,
+    /// How the commands of sessions are executed,
+    /// [`ExecutionModel::ThreadPerSession`] by default.
+    pub execution_model: ExecutionModel,
+    /// Number of commands the sessions of CAs running as a uid run in a turn
+    /// under [`ExecutionModel::WorkerPool`], by uid, before the sessions
+    /// waiting after them, 1 for the uids not listed.
+    pub session_weights: HashMap<u32, usize>,
+    /// Maximum number of bytes of the memrefs of a command sent in chunks,
+    /// see [`TeeRequest::InvokeStreamBegin`](crate::protocol::TeeRequest::InvokeStreamBegin).
+    /// 256 MiB by default.
+    pub max_stream_size: usize,
+    /// Directory in which the TA socket, `<uuid>.sock`, is created. Taken
+    /// from `TA_MANAGER_SOCKET_DIR` or `XDG_RUNTIME_DIR` by default, /tmp
+    /// when neither is set. Created with mode 0700 if missing. On Windows,
+    /// the socket is the named pipe `\\.\pipe\<path>` instead, and the
+    /// directory is not created.
+    pub socket_dir: PathBuf,
+    /// Socket of the TA Manager server the TA registers with. Taken from
+    /// `TA_MANAGER_SERVER_SOCKET` by default, `server.sock` in the default
+    /// socket directory when it is not set.
+    pub server_socket: PathBuf,
+    /// Permissions of the TA socket, 0600 by default so that only CAs
+    /// running as the same user may connect. Ignored on Windows, where
+    /// pipes only accept CAs of the same user, administrators and
+    /// LocalSystem.
+    pub socket_mode: u32,
+    /// Whether the TA socket, the sockets used to hand the TA over and the
+    /// socket of the TA Manager server are in the Linux abstract namespace,
+    /// named after their paths, rather than files. They then leave nothing
+    /// to clean up once closed, and no stale file can take their place.
+    /// Abstract sockets have no permissions: `socket_mode` is ignored and
+    /// any process of the same network namespace may connect, subject to
+    /// the [`policy`](Self::policy) and [`authorizer`](Self::authorizer).
+    /// CAs reach them with
+    /// [`ClientPool::with_abstract_sockets`](crate::ClientPool::with_abstract_sockets).
+    /// The control socket stays a file. Off by default, and without effect
+    /// on Windows, whose pipes leave no file behind either.
+    pub abstract_sockets: bool,
+    /// Whether the TA socket is handed to the fd store of systemd once the
+    /// manager drained, for the service restarted by systemd to serve it
+    /// again without closing it in between. Requires
+    /// `FileDescriptorStoreMax=` in the service unit. Whether or not it is
+    /// set, a manager started by systemd with a socket named after the TA
+    /// uuid in `LISTEN_FDNAMES`, or with a single socket in `LISTEN_FDS`,
+    /// serves the TA on that socket, whose permissions are then those set by
+    /// systemd rather than [`socket_mode`](Self::socket_mode). Off by
+    /// default, and without effect on Windows.
+    pub fd_store: bool,
+    /// Name of the TA, reported to CAs discovering the registered TAs.
+    /// Empty by default.
+    pub ta_name: String,
+    /// Version of the TA, reported along its name. Empty by default.
+    pub ta_version: String,
+    /// Interval between the heartbeats sent to the TA Manager server, 5
+    /// seconds by default.
+    pub heartbeat_interval: Duration,
+    /// Longest wait between attempts to register again with the TA Manager
+    /// server after losing the registration, 30 seconds by default. The
+    /// first attempt is made right away, then the wait doubles from 100 ms.
+    pub max_reregister_delay: Duration,
+    /// Plugins servicing the normal-world requests of the TA, see
+    /// [`CommandContext::ree_service`](crate::CommandContext::ree_service).
+    pub supplicant_plugins: Vec<Arc<dyn SupplicantPlugin>>,
+    /// File to write the timing of every request to, in the JSON trace
+    /// format of chrome://tracing, which Perfetto also opens. Each request
+    /// is broken down into its decoding, its wait in the session queue, its
+    /// execution by the TA, the encoding of its response and the writing of
+    /// the response. Requests are appended as they are answered, until the
+    /// manager stops. Not written by default.
+    pub trace_file: Option<PathBuf>,
+    /// Time after which a command still executing is reported as stuck, in
+    /// the log and in [`SessionInfo::stuck`](crate::SessionInfo::stuck).
+    /// `None` does not watch commands.
+    pub stuck_threshold: Option<Duration>,
+    /// Whether the sessions of stuck commands are closed. Their commands
+    /// then fail with `TargetDead`, and so do later requests on them. The
+    /// TA is not told: its thread stays blocked in the command until it
+    /// returns, and `close_session` is never called on the session. Off by
+    /// default.
+    pub close_stuck_sessions: bool,
+    /// File the sessions are saved to, so that a restarted manager serves
+    /// them again under the same ids. Only the sessions whose context the TA
+    /// serializes, through
+    /// [`TrustedApplication::serialize_session`](crate::TrustedApplication::serialize_session),
+    /// are saved. The file is rewritten whenever a session is opened, runs a
+    /// command or is closed. A manager taking over the TA from another
+    /// saves its own sessions in place of those of the other. Not written by
+    /// default.
+    pub session_state_file: Option<PathBuf>,
+    /// Socket on which the manager answers the requests of `ta_managerctl`,
+    /// see [`protocol::control`](crate::protocol::control). Only processes
+    /// running as the same user as the manager, or as root, may connect.
+    /// Not opened by default.
+    pub control_socket: Option<PathBuf>,
+    /// Where the persistent objects of the TA are kept. Without it, opening
+    /// or creating them fails with `StorageNotAvailable`.
+    #[cfg(feature = "secure_storage")]
+    pub secure_storage: Option<SecureStorage>,
+    /// Gives the client of every session a separate namespace of persistent
+    /// objects, that of its
+    /// [`TenantContext`](optee_utee::tenant::TenantContext). Off by default.
+    #[cfg(feature = "secure_storage")]
+    pub tenant_isolation: bool,
+    /// Key shared with the CAs, sealing every connection. Connections are
+    /// in clear by default.
+    #[cfg(feature = "encrypted_transport")]
+    pub transport_key: Option<TransportKey>,
+}
+
+impl Default for TAManagerConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            codec: Arc::new(BincodeCodec),
+            policy: AccessPolicy::default(),
+            authorizer: None,
+            audit_sink: None,
+            ta_flags: TaFlags::default(),
+            max_sessions: None,
+            max_sessions_per_client: None,
+            command_limits: HashMap::new(),
+            max_in_flight_per_session: None,
+            rate_limit: None,
+            response_cache_ttl: None,
+            session_queue_depth: DEFAULT_SESSION_QUEUE_DEPTH,
+            queue_policy: QueuePolicy::default(),
+            execution_model: ExecutionModel::default(),
+            session_weights: HashMap::new(),
+            max_stream_size: DEFAULT_MAX_STREAM_SIZE,
+            socket_dir: default_socket_dir(),
+            server_socket: default_server_socket(),
+            socket_mode: 0o600,
+            abstract_sockets: false,
+            fd_store: false,
+            ta_name: String::new(),
+            ta_version: String::new(),
+            heartbeat_interval: Duration::from_secs(5),
+            max_reregister_delay: Duration::from_secs(30),
+            supplicant_plugins: Vec::new(),
+            trace_file: None,
+            stuck_threshold: None,
+            close_stuck_sessions: false,
+            session_state_file: None,
+            control_socket: None,
+            #[cfg(feature = "secure_storage")]
+            secure_storage: None,
+            #[cfg(feature = "secure_storage")]
+            tenant_isolation: false,
+            #[cfg(feature = "encrypted_transport")]
+            transport_key: None,
+        }
+    }
+}
+
+impl TAManagerConfig {
+    /// Sets the idle timeout after which sessions are evicted.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how often the registration with the TA Manager server is checked
+    /// and how long to wait at most between attempts to restore it.
+    pub fn with_heartbeat(mut self, interval: Duration, max_reregister_delay: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self.max_reregister_delay = max_reregister_delay;
+        self
+    }
+
+    /// Sets the codec used to talk to CAs, [`BincodeCodec`] by default.
+    pub fn with_codec(mut self, codec: impl Codec) -> Self {
+        self.codec = Arc::new(codec);
+        self
+    }
+
+    /// Sets the access-control policy enforced before opening sessions.
+    pub fn with_policy(mut self, policy: AccessPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the authorizer deciding which sessions CAs may open and which
+    /// commands they may run, e.g. a
+    /// [`PolicyAuthorizer`](crate::PolicyAuthorizer) reading its policy from
+    /// a file.
+    pub fn with_authorizer(mut self, authorizer: impl Authorizer) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// Records the registrations, sessions, commands and refused requests
+    /// of the manager to `sink`, e.g. a
+    /// [`HashChainAuditLog`](crate::HashChainAuditLog).
+    pub fn with_audit_sink(mut self, sink: impl AuditSink) -> Self {
+        self.audit_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Sets the instance semantics of the TA.
+    pub fn with_ta_flags(mut self, ta_flags: TaFlags) -> Self {
+        self.ta_flags = ta_flags;
+        self
+    }
+
+    /// Sets the maximum number of sessions open at once.
+    pub fn with_max_sessions(mut self, max: usize) -> Self {
+        self.max_sessions = Some(max);
+        self
+    }
+
+    /// Sets the maximum number of sessions open at once by a single uid.
+    pub fn with_max_sessions_per_client(mut self, max: usize) -> Self {
+        self.max_sessions_per_client = Some(max);
+        self
+    }
+
+    /// Limits the command `cmd_id` to `max` concurrent executions, e.g. 1 for
+    /// a command that must not run twice at once.
+    pub fn with_command_limit(mut self, cmd_id: u32, max: usize) -> Self {
+        self.command_limits.insert(cmd_id, max);
+        self
+    }
+
+    /// Sets the maximum number of commands queued or executing on one session.
+    pub fn with_max_in_flight_per_session(mut self, max: usize) -> Self {
+        self.max_in_flight_per_session = Some(max);
+        self
+    }
+
+    /// Limits each CA to `requests_per_sec` requests per second on average
+    /// and `burst` in a row, counted per uid or per connection as `key`
+    /// says.
+    pub fn with_rate_limit(mut self, requests_per_sec: u32, burst: u32, key: RateLimitKey) -> Self {
+        self.rate_limit = Some(RateLimit {
+            requests_per_sec,
+            burst,
+            key,
+        });
+        self
+    }
+
+    /// Replays the responses of idempotent commands for `ttl`.
+    pub fn with_response_cache(mut self, ttl: Duration) -> Self {
+        self.response_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Bounds the queue of each session to `depth` commands, handling those
+    /// sent to a full queue according to `policy`.
+    pub fn with_session_queue(mut self, depth: usize, policy: QueuePolicy) -> Self {
+        self.session_queue_depth = depth;
+        self.queue_policy = policy;
+        self
+    }
+
+    /// Sets how the commands of sessions are executed, e.g. on a pool of
+    /// workers for a TA serving many mostly idle sessions.
+    pub fn with_execution_model(mut self, model: ExecutionModel) -> Self {
+        self.execution_model = model;
+        self
+    }
+
+    /// Lets the sessions of CAs running as `uid` run `weight` commands in a
+    /// turn of the worker pool, e.g. more for a CA that must keep up with
+    /// many others.
+    pub fn with_session_weight(mut self, uid: u32, weight: usize) -> Self {
+        self.session_weights.insert(uid, weight);
+        self
+    }
+
+    /// Sets the maximum size of the memrefs of a command sent in chunks.
+    pub fn with_max_stream_size(mut self, size: usize) -> Self {
+        self.max_stream_size = size;
+        self
+    }
+
+    /// Sets the directory in which the TA socket is created.
+    pub fn with_socket_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.socket_dir = dir.into();
+        self
+    }
+
+    /// Sets the socket of the TA Manager server.
+    pub fn with_server_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.server_socket = path.into();
+        self
+    }
+
+    /// Sets the permissions of the TA socket, e.g. 0660 to let CAs of the
+    /// same group connect.
+    pub fn with_socket_mode(mut self, mode: u32) -> Self {
+        self.socket_mode = mode;
+        self
+    }
+
+    /// Binds the TA socket and reaches the TA Manager server in the abstract
+    /// namespace.
+    pub fn with_abstract_sockets(mut self) -> Self {
+        self.abstract_sockets = true;
+        self
+    }
+
+    /// Hands the TA socket to the systemd fd store once the manager drained.
+    pub fn with_fd_store(mut self) -> Self {
+        self.fd_store = true;
+        self
+    }
+
+    /// Sets the name and version under which the TA is registered.
+    pub fn with_ta_info(mut self, name: impl Into<String>, version: impl Into<String>) -> Self {
+        self.ta_name = name.into();
+        self.ta_version = version.into();
+        self
+    }
+
+    /// Sets the file to write the timing of every request to.
+    pub fn with_trace_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.trace_file = Some(path.into());
+        self
+    }
+
+    /// Reports commands executing for longer than `threshold` as stuck, and
+    /// closes their sessions if `close_stuck` is set.
+    pub fn with_watchdog(mut self, threshold: Duration, close_stuck: bool) -> Self {
+        self.stuck_threshold = Some(threshold);
+        self.close_stuck_sessions = close_stuck;
+        self
+    }
+
+    /// Sets the file to save the sessions to, and to restore them from when
+    /// the manager starts.
+    pub fn with_session_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.session_state_file = Some(path.into());
+        self
+    }
+
+    /// Sets the control socket, e.g.
+    /// [`ControlClient::default_socket`](crate::ControlClient::default_socket)
+    /// for `ta_managerctl` to find it without options.
+    pub fn with_control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Adds a plugin servicing normal-world requests of the TA, asked after
+    /// the plugins added before it.
+    pub fn with_supplicant_plugin(mut self, plugin: impl SupplicantPlugin) -> Self {
+        self.supplicant_plugins.push(Arc::new(plugin));
+        self
+    }
+
+    /// Sets where the persistent objects of the TA are kept.
+    #[cfg(feature = "secure_storage")]
+    pub fn with_secure_storage(mut self, storage: SecureStorage) -> Self {
+        self.secure_storage = Some(storage);
+        self
+    }
+
+    /// Confines the persistent objects of each session to the tenant of its
+    /// client, so that one TA may serve several applications without them
+    /// reaching the objects of each other.
+    #[cfg(feature = "secure_storage")]
+    pub fn with_tenant_isolation(mut self) -> Self {
+        self.tenant_isolation = true;
+        self
+    }
+
+    /// Seals the connections of CAs with keys derived from `key`, which the
+    /// CAs must share, e.g. when they reach the manager across a trust
+    /// boundary. Connections whose CA does not negotiate encryption in its
+    /// `Hello` are refused with `AccessDenied`.
+    #[cfg(feature = "encrypted_transport")]
+    pub fn with_transport_key(mut self, key: TransportKey) -> Self {
+        self.transport_key = Some(key);
+        self
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use optee_utee::{Identity, Result};
+
+use crate::notify::NotificationSender;
+use crate::peer::{PeerCredentials, SecurityLabel};
+use crate::protocol::{Parameters, ReeService};
+use crate::supplicant::Supplicant;
+
+// Time a cancel that arrived before its command, or a command that
+// completed, is remembered.
+const RECENT_TTL: Duration = Duration::from_secs(5);
+
+// Cancels and completed commands remembered at most, past which the oldest
+// are forgotten first.
+const MAX_RECENT: usize = 1024;
+
+/// What a CA opening a session hands to
+/// [`TrustedApplication::open_session`](crate::TrustedApplication::open_session).
+pub struct OpenSessionContext {
+    params: Parameters,
+    identity: Identity,
+    peer: PeerCredentials,
+    notifications: NotificationSender,
+}
+
+impl OpenSessionContext {
+    pub(crate) fn new(
+        params: Parameters,
+        identity: Identity,
+        peer: PeerCredentials,
+        notifications: NotificationSender,
+    ) -> Self {
+        Self {
+            params,
+            identity,
+            peer,
+            notifications,
+        }
+    }
+
+    /// Returns the parameters the CA passed.
+    pub fn params(&self) -> &Parameters {
+        &self.params
+    }
+
+    /// Returns the identity of the client, e.g. to restrict sessions to some
+    /// login types.
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    /// Returns the credentials of the process running the CA.
+    pub fn peer(&self) -> &PeerCredentials {
+        &self.peer
+    }
+
+    /// Returns the security label of the process running the CA, if a Linux
+    /// Security Module provides one.
+    pub fn label(&self) -> Option<&SecurityLabel> {
+        self.peer.label.as_ref()
+    }
+
+    /// Returns the handle through which the TA pushes events to the CAs
+    /// subscribed to it, e.g. to keep it for a thread watching for
+    /// tampering.
+    pub fn notifications(&self) -> &NotificationSender {
+        &self.notifications
+    }
+}
+
+/// Per-command context handed to
+/// [`TrustedApplication::invoke_command_with_context`](crate::TrustedApplication::invoke_command_with_context).
+#[derive(Clone, Default)]
+pub struct CommandContext {
+    state: Arc<CommandState>,
+    supplicant: Arc<Supplicant>,
+    notifications: NotificationSender,
+    deadline: Option<Instant>,
+    trace_id: u64,
+}
+
+// State of a command shared by the dispatcher and the session thread.
+#[derive(Default)]
+struct CommandState {
+    cancelled: AtomicBool,
+    // When the session thread took the command from its queue.
+    started: OnceLock<Instant>,
+}
+
+impl CommandContext {
+    pub(crate) fn new(supplicant: Arc<Supplicant>) -> Self {
+        Self {
+            state: Arc::default(),
+            supplicant,
+            notifications: NotificationSender::default(),
+            deadline: None,
+            trace_id: 0,
+        }
+    }
+
+    pub(crate) fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub(crate) fn with_notifications(mut self, notifications: NotificationSender) -> Self {
+        self.notifications = notifications;
+        self
+    }
+
+    pub(crate) fn with_trace_id(mut self, trace_id: u64) -> Self {
+        self.trace_id = trace_id;
+        self
+    }
+
+    /// Returns whether the CA requested the cancellation of this command.
+    ///
+    /// Long running commands should poll it and return `ErrorKind::Cancel`
+    /// once it is set, like `TEE_GetCancellationFlag` under OP-TEE.
+    ///
+    /// It is also set once the [`deadline`](Self::deadline) passed, as the CA
+    /// no longer waits for the result.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Returns when the CA stops waiting for this command, if it set a
+    /// timeout.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns the time left before the [`deadline`](Self::deadline), zero
+    /// once it passed.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns the correlation id of the request that invoked this command,
+    /// chosen by the CA with a
+    /// [`TeeRequest::Traced`](crate::protocol::TeeRequest::Traced) or
+    /// generated by the manager, e.g. to log it along the events of the TA
+    /// or to pass it on to the services the TA calls. The manager logs the
+    /// command in spans carrying the same id, written in hexadecimal.
+    pub fn trace_id(&self) -> u64 {
+        self.trace_id
+    }
+
+    /// Calls a normal-world service, e.g. to load a file from the REE
+    /// filesystem, and returns its output.
+    ///
+    /// The service goes to the supplicant plugins of the
+    /// [`TAManagerConfig`](crate::TAManagerConfig), then to the TA Manager
+    /// server if none of them handles it.
+    ///
+    /// # Errors
+    ///
+    /// 1) `Communication`: If the TA Manager server could not be reached.
+    /// 2) Errors returned by the plugin or server servicing the request.
+    pub fn ree_service(&self, service: ReeService) -> Result<Vec<u8>> {
+        self.supplicant.call(service)
+    }
+
+    /// Returns the handle through which the TA pushes events to the CAs
+    /// subscribed to it.
+    pub fn notifications(&self) -> &NotificationSender {
+        &self.notifications
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn mark_started(&self) {
+        let _ = self.state.started.set(Instant::now());
+    }
+
+    pub(crate) fn started(&self) -> Option<Instant> {
+        self.state.started.get().copied()
+    }
+}
+
+// Commands executing on the sessions of a manager, keyed by session and
+// operation id. The CA chooses the operation ids, so a cancel may reach the
+// manager before the command it names, or after it completed: both are
+// remembered for `RECENT_TTL`.
+#[derive(Default)]
+pub(crate) struct PendingCommands {
+    running: HashMap<(u32, u32), CommandContext>,
+    recent: HashMap<(u32, u32), (Instant, Recent)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Recent {
+    Cancelled,
+    Completed,
+}
+
+impl PendingCommands {
+    // Registers the command `key`, cancelled at once if a cancel for it
+    // arrived first. Returns false if a command with the same id is still
+    // executing on the session.
+    pub(crate) fn register(&mut self, key: (u32, u32), context: &CommandContext) -> bool {
+        if self.running.contains_key(&key) {
+            return false;
+        }
+        self.expire();
+        if let Some((_, Recent::Cancelled)) = self.recent.remove(&key) {
+            context.cancel();
+        }
+        self.running.insert(key, context.clone());
+        true
+    }
+
+    // Unregisters the command `key` once it completed.
+    pub(crate) fn complete(&mut self, key: (u32, u32)) -> Option<CommandContext> {
+        let context = self.running.remove(&key)?;
+        self.remember(key, Recent::Completed);
+        Some(context)
+    }
+
+    // Cancels the command `key`, or remembers the cancel for a command not
+    // registered yet. Returns false if the command already completed.
+    pub(crate) fn cancel(&mut self, key: (u32, u32)) -> bool {
+        if let Some(context) = self.running.get(&key) {
+            context.cancel();
+            return true;
+        }
+        self.expire();
+        if let Some((_, Recent::Completed)) = self.recent.get(&key) {
+            return false;
+        }
+        self.remember(key, Recent::Cancelled);
+        true
+    }
+
+    fn remember(&mut self, key: (u32, u32), recent: Recent) {
+        self.expire();
+        if self.recent.len() >= MAX_RECENT && !self.recent.contains_key(&key) {
+            let oldest = self.recent.iter().min_by_key(|(_, (at, _))| *at);
+            if let Some((&oldest, _)) = oldest {
+                self.recent.remove(&oldest);
+            }
+        }
+        self.recent.insert(key, (Instant::now(), recent));
+    }
+
+    fn expire(&mut self) {
+        self.recent.retain(|_, (at, _)| at.elapsed() < RECENT_TTL);
+    }
+}
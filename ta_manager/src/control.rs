@@ -0,0 +1,344 @@
+//! Control socket of a manager, serving the requests of
+//! [`protocol::control`](crate::protocol::control) for the TAs it hosts, and
+//! the client speaking to it.
+
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Instant,
+};
+#[cfg(target_os = "linux")]
+use std::{fs, os::unix::fs::MetadataExt};
+
+use bincode::{Decode, Encode};
+use optee_utee::{Error, ErrorKind};
+use tracing::{Span, debug, info, warn};
+
+use crate::TrustedApplication;
+use crate::codec::{read_frame, write_frame};
+use crate::config::default_socket_dir;
+use crate::dispatch::Dispatcher;
+use crate::error::ManagerError;
+use crate::ipc::{self, IpcListener, IpcStream, bind};
+use crate::peer::PeerCredentials;
+use crate::protocol::control::{
+    CONTROL_PROTOCOL_VERSION, ControlRequest, ControlResponse, ManagerStatus, SessionStatus,
+    TaStatus,
+};
+
+// Socket `ta_managerctl` connects to by default: `TA_MANAGER_CONTROL_SOCKET`,
+// else `control.sock` in the socket directory.
+pub(crate) fn default_control_socket() -> PathBuf {
+    env::var_os("TA_MANAGER_CONTROL_SOCKET")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| default_socket_dir().join("control.sock"))
+}
+
+// A TA as seen from a control socket.
+pub(crate) trait Controlled: Send + Sync + 'static {
+    fn uuid(&self) -> &str;
+    fn status(&self) -> TaStatus;
+    fn sessions(&self) -> Vec<SessionStatus>;
+    fn close_session(&self, session_id: u32) -> Result<(), ManagerError>;
+}
+
+// TA served by a `TAManager`.
+pub(crate) struct ManagedTa<T: TrustedApplication> {
+    pub(crate) uuid: String,
+    pub(crate) dispatcher: Arc<Dispatcher<T>>,
+}
+
+impl<T: TrustedApplication> Controlled for ManagedTa<T> {
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn status(&self) -> TaStatus {
+        let dispatcher = &self.dispatcher;
+        let metrics = dispatcher.metrics.snapshot(dispatcher.sessions.len());
+        TaStatus {
+            uuid: self.uuid.clone(),
+            name: dispatcher.config.ta_name.clone(),
+            version: dispatcher.config.ta_version.clone(),
+            state: format!("{:?}", dispatcher.lifecycle.state()),
+            open_sessions: metrics.open_sessions as u32,
+            sessions_opened: metrics.sessions_opened,
+            commands_invoked: metrics.commands_invoked,
+            errors: metrics.errors,
+        }
+    }
+
+    fn sessions(&self) -> Vec<SessionStatus> {
+        self.dispatcher
+            .sessions
+            .list()
+            .into_iter()
+            .map(|info| SessionStatus {
+                session_id: info.session_id,
+                uid: info.peer.uid,
+                gid: info.peer.gid,
+                pid: info.peer.pid,
+                idle_ms: info.idle_for.as_millis() as u64,
+                busy_ms: info.busy_for.map(|busy_for| busy_for.as_millis() as u64),
+                stuck: info.stuck,
+            })
+            .collect()
+    }
+
+    fn close_session(&self, session_id: u32) -> Result<(), ManagerError> {
+        self.dispatcher.evict_session(session_id)
+    }
+}
+
+// Control socket being served, closed when dropped.
+pub(crate) struct ControlSocket {
+    listener: IpcListener,
+    #[cfg(target_os = "linux")]
+    path: PathBuf,
+    // Inode of the socket, to leave alone a socket that replaced it, e.g.
+    // that of a manager taking over the TA.
+    #[cfg(target_os = "linux")]
+    ino: u64,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Release);
+        // Wakes the thread blocked accepting on the listener up, so that it
+        // notices.
+        ipc::shutdown_listener(&self.listener);
+        #[cfg(target_os = "linux")]
+        if fs::metadata(&self.path).is_ok_and(|metadata| metadata.ino() == self.ino) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Serves the control socket at `path` for `tas` on a thread of its own. The
+// socket gets mode 0600, so that only processes running as the same user as
+// the manager, or as root, may connect. Pipes on Windows are as restricted.
+pub(crate) fn listen(path: &Path, tas: Vec<Arc<dyn Controlled>>) -> io::Result<ControlSocket> {
+    let listener = bind(path, 0o600, false)?;
+    #[cfg(target_os = "linux")]
+    let ino = fs::metadata(path)?.ino();
+    info!(?path, "Control socket listening");
+    let stopped = Arc::new(AtomicBool::new(false));
+    let server = Arc::new(ControlServer {
+        tas,
+        started: Instant::now(),
+    });
+    let (incoming, span, stop) = (listener.try_clone()?, Span::current(), stopped.clone());
+    thread::spawn(move || {
+        let _entered = span.enter();
+        accept(&incoming, &server, &stop)
+    });
+    Ok(ControlSocket {
+        listener,
+        #[cfg(target_os = "linux")]
+        path: path.into(),
+        #[cfg(target_os = "linux")]
+        ino,
+        stopped,
+    })
+}
+
+struct ControlServer {
+    tas: Vec<Arc<dyn Controlled>>,
+    started: Instant,
+}
+
+fn accept(listener: &IpcListener, server: &Arc<ControlServer>, stopped: &AtomicBool) {
+    loop {
+        let accepted = listener.accept();
+        if stopped.load(Ordering::Acquire) {
+            return;
+        }
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!(error = ?e, "Failed to accept a control connection");
+                continue;
+            }
+        };
+        let server = server.clone();
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            if let Err(e) = server.serve(stream) {
+                debug!(error = ?e, "Control connection failed");
+            }
+        });
+    }
+}
+
+impl ControlServer {
+    fn serve(&self, mut stream: IpcStream) -> io::Result<()> {
+        let peer = PeerCredentials::from_stream(&stream)?;
+        while let Some(frame) = read_frame(&mut stream)? {
+            let req: ControlRequest = decode(&frame)?;
+            debug!(uid = peer.uid, pid = peer.pid, ?req, "Control request");
+            let resp = self.handle(req, &peer);
+            write_frame(&mut stream, &encode(&resp)?)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, req: ControlRequest, peer: &PeerCredentials) -> ControlResponse {
+        match req {
+            ControlRequest::Status => ControlResponse::Status(ManagerStatus {
+                version: CONTROL_PROTOCOL_VERSION,
+                pid: std::process::id(),
+                uptime_ms: self.started.elapsed().as_millis() as u64,
+                tas: self.tas.len() as u32,
+            }),
+            ControlRequest::ListTas => {
+                ControlResponse::Tas(self.tas.iter().map(|ta| ta.status()).collect())
+            }
+            ControlRequest::ListSessions { uuid } => match self.ta(&uuid) {
+                Some(ta) => ControlResponse::Sessions(ta.sessions()),
+                None => unknown_ta(&uuid),
+            },
+            ControlRequest::CloseSession { uuid, session_id } => {
+                let Some(ta) = self.ta(&uuid) else {
+                    return unknown_ta(&uuid);
+                };
+                match ta.close_session(session_id) {
+                    Ok(()) => {
+                        info!(%uuid, session_id, uid = peer.uid, pid = peer.pid, "Session closed through the control socket");
+                        ControlResponse::Closed
+                    }
+                    Err(e) => ControlResponse::Error {
+                        result: match &e {
+                            ManagerError::SessionNotFound(_) => ErrorKind::ItemNotFound.into(),
+                            ManagerError::TaError(kind) => (*kind).into(),
+                            _ => ErrorKind::Generic.into(),
+                        },
+                        message: e.to_string(),
+                    },
+                }
+            }
+        }
+    }
+
+    fn ta(&self, uuid: &str) -> Option<&Arc<dyn Controlled>> {
+        self.tas.iter().find(|ta| ta.uuid() == uuid)
+    }
+}
+
+fn unknown_ta(uuid: &str) -> ControlResponse {
+    ControlResponse::Error {
+        result: ErrorKind::ItemNotFound.into(),
+        message: format!("no TA {} on this manager", uuid),
+    }
+}
+
+fn encode(value: &impl Encode) -> io::Result<Vec<u8>> {
+    bincode::encode_to_vec(value, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn decode<T: Decode<()>>(frame: &[u8]) -> io::Result<T> {
+    bincode::decode_from_slice(frame, bincode::config::standard())
+        .map(|(value, _)| value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Client of the control socket of a manager, as used by `ta_managerctl`.
+///
+/// ```no_run
+/// use ta_manager::ControlClient;
+///
+/// let mut client = ControlClient::connect(ControlClient::default_socket())?;
+/// for ta in client.list_tas()? {
+///     println!("{} {} sessions", ta.uuid, ta.open_sessions);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+///
+/// Requests the manager answers with [`ControlResponse::Error`] fail with
+/// an error of kind `Other` whose message gives the GP error code.
+pub struct ControlClient {
+    stream: IpcStream,
+}
+
+impl ControlClient {
+    /// Returns the socket taken from `TA_MANAGER_CONTROL_SOCKET`, else
+    /// `control.sock` in the default socket directory.
+    pub fn default_socket() -> PathBuf {
+        default_control_socket()
+    }
+
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            stream: ipc::connect(path.as_ref(), false)?,
+        })
+    }
+
+    /// Sends `req` and returns the response of the manager as is.
+    pub fn request(&mut self, req: &ControlRequest) -> io::Result<ControlResponse> {
+        write_frame(&mut self.stream, &encode(req)?)?;
+        let frame = read_frame(&mut self.stream)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "manager closed the control connection",
+            )
+        })?;
+        match decode(&frame)? {
+            ControlResponse::Error { result, message } => Err(io::Error::other(format!(
+                "{} ({})",
+                message,
+                Error::from_raw_error(result).kind()
+            ))),
+            resp => Ok(resp),
+        }
+    }
+
+    pub fn status(&mut self) -> io::Result<ManagerStatus> {
+        match self.request(&ControlRequest::Status)? {
+            ControlResponse::Status(status) => Ok(status),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    pub fn list_tas(&mut self) -> io::Result<Vec<TaStatus>> {
+        match self.request(&ControlRequest::ListTas)? {
+            ControlResponse::Tas(tas) => Ok(tas),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    pub fn list_sessions(&mut self, uuid: &str) -> io::Result<Vec<SessionStatus>> {
+        let req = ControlRequest::ListSessions {
+            uuid: uuid.to_string(),
+        };
+        match self.request(&req)? {
+            ControlResponse::Sessions(sessions) => Ok(sessions),
+            resp => Err(unexpected(resp)),
+        }
+    }
+
+    /// Closes a session on the TA `uuid` as if its CA had closed it.
+    pub fn close_session(&mut self, uuid: &str, session_id: u32) -> io::Result<()> {
+        let req = ControlRequest::CloseSession {
+            uuid: uuid.to_string(),
+            session_id,
+        };
+        match self.request(&req)? {
+            ControlResponse::Closed => Ok(()),
+            resp => Err(unexpected(resp)),
+        }
+    }
+}
+
+fn unexpected(resp: ControlResponse) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected control response {:?}", resp),
+    )
+}
@@ -0,0 +1,2946 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::Shutdown,
+    sync::{
+        Arc, Mutex, OnceLock, RwLock,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{RecvTimeoutError, unbounded};
+use optee_utee::{ErrorKind, Identity, LoginType};
+use tracing::{debug, error, info, info_span, warn};
+
+use crate::TrustedApplication;
+use crate::audit::AuditEvent;
+use crate::authz::{Action, Authorizer, Subject};
+use crate::cache::{CacheKey, ResponseCache};
+use crate::config::{ExecutionModel, TAManagerConfig};
+use crate::context::{CommandContext, OpenSessionContext, PendingCommands};
+use crate::error::ManagerError;
+use crate::handover::Predecessor;
+use crate::ipc::{self, IpcStream};
+use crate::lifecycle::{Lifecycle, LifecycleState};
+use crate::metrics::Metrics;
+use crate::notify::NotificationSender;
+use crate::peer::{PeerCredentials, SecurityLabel};
+use crate::protocol::{
+    CANCEL_COMMAND_VERSION, CAP_ENCRYPTION, CAPABILITIES, ClientIdentity, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION, Parameters, ReturnOrigin, TeeRequest, TeeResponse,
+};
+use crate::rate_limit::{RateLimiter, TokenBucket};
+use crate::session::{SessionEnv, SessionMessage, SessionRunner, SessionTable, session_queue};
+use crate::snapshot::{PersistedSession, SessionSnapshot, SessionStore};
+use crate::stream::{PendingInvoke, PendingInvokes};
+use crate::supplicant::Supplicant;
+use crate::template;
+use crate::trace::{self, Tracer};
+use crate::transport::{Connection, KEY_EXCHANGE_NONCE_LEN};
+#[cfg(feature = "encrypted_transport")]
+use crate::transport::{SecureChannel, Side, key_exchange_nonce};
+use crate::worker::WorkerPool;
+
+// How often a subscription checks that its session is still open.
+const SUBSCRIPTION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// How long interrupted sessions are reported as such, after which requests
+// on them fail as on any unknown session.
+const INTERRUPTED_TTL: Duration = Duration::from_secs(600);
+// Most interrupted sessions remembered, the oldest being forgotten first.
+const MAX_INTERRUPTED: usize = 4096;
+
+// State shared by the threads serving CA connections.
+pub(crate) struct Dispatcher<T: TrustedApplication> {
+    // The TA instance serving new sessions.
+    ta: RwLock<Arc<T>>,
+    // Instance created ahead of time to replace `ta` if it dies.
+    standby: Mutex<Option<Arc<T>>>,
+    // Login types the TA accepts sessions from, all of them if empty.
+    login_types: RwLock<Vec<LoginType>>,
+    // Sessions whose thread or instance died, e.g. because the TA panicked,
+    // for `INTERRUPTED_TTL`.
+    interrupted: Mutex<HashMap<u32, Interrupted>>,
+    pub(crate) config: TAManagerConfig,
+    pub(crate) sessions: SessionTable,
+    session_id: AtomicU32,
+    // Commands currently executing, keyed by (session_id, operation_id).
+    pending: Mutex<PendingCommands>,
+    // Number of executions of each command, by id.
+    running: Mutex<HashMap<u32, usize>>,
+    // Number of commands queued or executing on each session, by id.
+    in_flight: Mutex<HashMap<u32, usize>>,
+    // Whether the TA instance exists, i.e. `create` was called without a
+    // matching `destroy`. Held while opening sessions so that the instance
+    // is not destroyed under a session being opened.
+    instance: Mutex<bool>,
+    pub(crate) lifecycle: Lifecycle,
+    pub(crate) metrics: Metrics,
+    supplicant: Arc<Supplicant>,
+    // Reaches the CAs subscribed to the events of the TA.
+    notifications: NotificationSender,
+    // Manager the TA was taken over from, still serving the sessions it
+    // opened.
+    predecessor: OnceLock<Predecessor>,
+    // Manager this one handed over to, which forwards the requests for the
+    // sessions of this manager on behalf of their CAs.
+    successor: OnceLock<PeerCredentials>,
+    pub(crate) tracer: OnceLock<Tracer>,
+    rate_limiter: Option<RateLimiter>,
+    // Responses of idempotent commands, if they are replayed.
+    cache: Option<ResponseCache>,
+    // Where the sessions are saved, if they are.
+    store: Option<Arc<SessionStore>>,
+    // Workers serving the sessions under `ExecutionModel::WorkerPool`,
+    // started with the first session.
+    workers: OnceLock<WorkerPool<SessionRunner<T>>>,
+}
+
+impl<T: TrustedApplication> Dispatcher<T> {
+    pub(crate) fn new(ta: T, config: TAManagerConfig, supplicant: Arc<Supplicant>) -> Self {
+        let rate_limiter = config.rate_limit.map(RateLimiter::new);
+        let cache = config.response_cache_ttl.map(ResponseCache::new);
+        let store = config
+            .session_state_file
+            .clone()
+            .map(|path| Arc::new(SessionStore::new(path)));
+        Self {
+            ta: RwLock::new(Arc::new(ta)),
+            standby: Mutex::new(None),
+            login_types: RwLock::new(Vec::new()),
+            interrupted: Mutex::new(HashMap::new()),
+            config,
+            sessions: SessionTable::default(),
+            session_id: AtomicU32::new(1),
+            pending: Mutex::default(),
+            running: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            instance: Mutex::new(false),
+            lifecycle: Lifecycle::default(),
+            metrics: Metrics::default(),
+            supplicant,
+            notifications: NotificationSender::default(),
+            predecessor: OnceLock::new(),
+            successor: OnceLock::new(),
+            tracer: OnceLock::new(),
+            rate_limiter,
+            cache,
+            store,
+            workers: OnceLock::new(),
+        }
+    }
+
+    fn ta(&self) -> Arc<T> {
+        self.ta.read().unwrap().clone()
+    }
+
+    pub(crate) fn set_standby(&self, standby: T) {
+        *self.standby.lock().unwrap() = Some(Arc::new(standby));
+    }
+
+    pub(crate) fn set_login_types(&self, login_types: Vec<LoginType>) {
+        *self.login_types.write().unwrap() = login_types;
+    }
+
+    // Whether the TA accepts sessions from the login type of `identity`.
+    fn accepts_login(&self, identity: &Identity) -> bool {
+        let login_types = self.login_types.read().unwrap();
+        login_types.is_empty() || login_types.contains(&identity.login_type())
+    }
+
+    // Records `event` to the audit sink, if there is one.
+    pub(crate) fn audit(&self, event: impl FnOnce() -> AuditEvent) {
+        if let Some(sink) = &self.config.audit_sink {
+            sink.record(&event());
+        }
+    }
+
+    // Records that `req` of `peer` was refused with `result` for lack of
+    // authorization.
+    fn audit_denied(&self, peer: &PeerCredentials, req: &TeeRequest, result: u32) {
+        self.audit(|| {
+            let (session_id, action) = match req.untraced() {
+                TeeRequest::OpenSession { .. } => (None, Some(Action::OpenSession)),
+                TeeRequest::InvokeCommand {
+                    session_id, cmd_id, ..
+                }
+                | TeeRequest::InvokeStreamBegin {
+                    session_id, cmd_id, ..
+                }
+                | TeeRequest::InvokeTemplate {
+                    session_id, cmd_id, ..
+                } => (Some(*session_id), Some(Action::InvokeCommand(*cmd_id))),
+                TeeRequest::CloseSession { session_id }
+                | TeeRequest::CancelCommand { session_id, .. }
+                | TeeRequest::InvokeStreamChunk { session_id, .. }
+                | TeeRequest::InvokeStreamEnd { session_id, .. }
+                | TeeRequest::InvokeBatch { session_id, .. }
+                | TeeRequest::RegisterTemplate { session_id, .. }
+                | TeeRequest::Subscribe { session_id } => (Some(*session_id), None),
+                TeeRequest::Hello { .. }
+                | TeeRequest::KeyExchange { .. }
+                | TeeRequest::Traced { .. } => (None, None),
+            };
+            AuditEvent::AccessDenied {
+                peer: peer.clone(),
+                session_id,
+                action,
+                result,
+            }
+        });
+    }
+
+    pub(crate) fn create_instance(&self) -> optee_utee::Result<()> {
+        let mut created = self.instance.lock().unwrap();
+        if !*created {
+            self.ta().create()?;
+            *created = true;
+        }
+        Ok(())
+    }
+
+    // Create the standby instance so that it is ready to take over. A standby
+    // that fails to create is dropped rather than failing the manager.
+    pub(crate) fn create_standby(&self) {
+        let mut standby = self.standby.lock().unwrap();
+        if let Some(ta) = standby.as_ref()
+            && let Err(e) = ta.create()
+        {
+            warn!(error = ?e, "Failed to create standby TA instance");
+            *standby = None;
+        }
+    }
+
+    // Forward the requests for the sessions of `predecessor` to it, and open
+    // the sessions of this manager after them.
+    pub(crate) fn take_over(&self, predecessor: Predecessor, next_session_id: u32) {
+        self.session_id.fetch_max(next_session_id, Ordering::SeqCst);
+        if let Some(store) = &self.store {
+            store.reserve(next_session_id);
+        }
+        let _ = self.predecessor.set(predecessor);
+    }
+
+    // Hand the TA over to the manager `successor` through `hand_over`, given
+    // the id of the next session and the owners of the open sessions, then
+    // drain. Sessions are not opened meanwhile, so that the other manager
+    // may open its sessions from that id. Returns the id.
+    pub(crate) fn hand_over(
+        &self,
+        successor: PeerCredentials,
+        hand_over: impl FnOnce(u32, &[(u32, u32, i32)]) -> io::Result<()>,
+    ) -> io::Result<u32> {
+        let _created = self.instance.lock().unwrap();
+        let next_session_id = self.session_id.load(Ordering::SeqCst);
+        // The other manager saves its sessions in place of ours, which end
+        // with this manager.
+        if let Some(store) = &self.store {
+            store.set_detached(true);
+        }
+        if let Err(e) = hand_over(next_session_id, &self.sessions.owners()) {
+            if let Some(store) = &self.store {
+                store.set_detached(false);
+            }
+            return Err(e);
+        }
+        let _ = self.successor.set(successor);
+        self.lifecycle.transition(LifecycleState::Draining);
+        Ok(next_session_id)
+    }
+
+    // Checks that `peer` may act on `session_id`: only the CA that opened
+    // the session may, and the manager this one handed over to, which
+    // checked the CA itself. Sessions that are not open are left for the
+    // request to report.
+    fn check_owner(&self, peer: &PeerCredentials, session_id: u32) -> optee_utee::Result<()> {
+        let owner = match self.predecessor(session_id) {
+            Some(predecessor) => predecessor.owner(session_id),
+            None => self
+                .sessions
+                .owner(session_id)
+                .map(|(owner, _)| (owner.uid, owner.pid))
+                .or_else(|| self.interrupted_entry(session_id).map(|entry| entry.owner)),
+        };
+        let Some((uid, pid)) = owner else {
+            return Ok(());
+        };
+        if peer.is_process(uid, pid) || self.is_successor(peer) {
+            return Ok(());
+        }
+        warn!(
+            session_id,
+            uid = peer.uid,
+            pid = peer.pid,
+            owner_uid = uid,
+            owner_pid = pid,
+            "Refusing a request on the session of another CA"
+        );
+        Err(ErrorKind::AccessDenied.into())
+    }
+
+    // Whether `peer` is the manager this one handed over to.
+    fn is_successor(&self, peer: &PeerCredentials) -> bool {
+        self.successor
+            .get()
+            .is_some_and(|successor| peer.is_process(successor.uid, successor.pid))
+    }
+
+    // Returns the predecessor if it opened `session_id`.
+    fn predecessor(&self, session_id: u32) -> Option<&Predecessor> {
+        self.predecessor
+            .get()
+            .filter(|predecessor| predecessor.owns(session_id))
+    }
+
+    pub(crate) fn destroy_instance(&self) {
+        let mut created = self.instance.lock().unwrap();
+        if *created {
+            if let Err(e) = self.ta().destroy() {
+                error!(error = ?e, "Failed to destroy TA instance");
+            }
+            *created = false;
+        }
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        if let Some(standby) = self.standby.lock().unwrap().take()
+            && let Err(e) = standby.destroy()
+        {
+            error!(error = ?e, "Failed to destroy standby TA instance");
+        }
+    }
+
+    // Replaces a TA instance that died by the standby. The sessions of the
+    // dead instance are dropped without being closed and reported as
+    // interrupted from then on. Returns whether a standby took over.
+    fn fail_over(&self) -> bool {
+        let mut created = self.instance.lock().unwrap();
+        self.promote_standby(&mut created)
+    }
+
+    // Makes the standby, if there is one, the TA instance, `created` being
+    // the guard of `instance`. Returns whether there was a standby.
+    fn promote_standby(&self, created: &mut bool) -> bool {
+        let Some(standby) = self.standby.lock().unwrap().take() else {
+            return false;
+        };
+        warn!("TA instance died, failing over to the standby instance");
+        *self.ta.write().unwrap() = standby;
+        *created = true;
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        let owners = self.sessions.owners();
+        self.sessions.drain();
+        self.record_interrupted(
+            owners
+                .into_iter()
+                .map(|(session_id, uid, pid)| (session_id, (uid, pid))),
+            true,
+        );
+        true
+    }
+
+    // Records the sessions of `sessions`, with their owners, as interrupted,
+    // with whether their CAs may retry on a new session.
+    fn record_interrupted(
+        &self,
+        sessions: impl IntoIterator<Item = (u32, (u32, i32))>,
+        retry: bool,
+    ) {
+        let mut interrupted = self.interrupted.lock().unwrap();
+        let now = Instant::now();
+        interrupted.retain(|_, entry| now.duration_since(entry.at) < INTERRUPTED_TTL);
+        for (session_id, owner) in sessions {
+            let entry = Interrupted {
+                retry,
+                owner,
+                at: now,
+            };
+            interrupted.insert(session_id, entry);
+        }
+        while interrupted.len() > MAX_INTERRUPTED {
+            let oldest = interrupted
+                .iter()
+                .min_by_key(|(_, entry)| entry.at)
+                .map(|(session_id, _)| *session_id);
+            interrupted.remove(&oldest.unwrap());
+        }
+    }
+
+    // Returns whether `session_id` was interrupted and, if so, whether the
+    // CA may retry on a new session.
+    fn interrupted(&self, session_id: u32) -> Option<bool> {
+        self.interrupted_entry(session_id).map(|entry| entry.retry)
+    }
+
+    fn interrupted_entry(&self, session_id: u32) -> Option<Interrupted> {
+        let interrupted = self.interrupted.lock().unwrap();
+        let entry = interrupted.get(&session_id)?;
+        (entry.at.elapsed() < INTERRUPTED_TTL).then_some(*entry)
+    }
+
+    // Handles the death of the thread of `session_id`, e.g. because the TA
+    // panicked in one of its commands. Only the broken session is evicted,
+    // so that later requests on it fail fast: the instance and its other
+    // sessions are left alone. Returns whether the CA may retry.
+    fn session_died(&self, session_id: u32) -> bool {
+        if let Some(retry) = self.interrupted(session_id) {
+            return retry;
+        }
+        if let Some((owner, _)) = self.sessions.owner(session_id) {
+            self.record_interrupted([(session_id, (owner.uid, owner.pid))], false);
+        }
+        self.sessions.remove(session_id);
+        self.release_instance_if_unused();
+        false
+    }
+
+    // Fails over if the TA reported the death of its instance, answering a
+    // command with `TargetDead`. Returns whether a standby took over, for the
+    // CA to retry on a new session.
+    fn instance_died(&self, session_id: u32, result: u32, origin: ReturnOrigin) -> bool {
+        if result != u32::from(ErrorKind::TargetDead) || origin != ReturnOrigin::TrustedApp {
+            return false;
+        }
+        error!(session_id, "TA instance died");
+        self.fail_over()
+    }
+
+    // Report the commands executing for `threshold` or longer, and abandon
+    // their sessions if configured to.
+    pub(crate) fn watch_commands(&self, threshold: Duration) {
+        let stuck = self.sessions.flag_stuck(threshold);
+        if stuck.is_empty() {
+            return;
+        }
+        for (session_id, cmd_id, busy_for) in stuck {
+            warn!(session_id, cmd_id, ?busy_for, "Command is stuck");
+            if self.config.close_stuck_sessions {
+                // Recorded first, so that the waiting commands report the
+                // session as interrupted.
+                if let Some((owner, _)) = self.sessions.owner(session_id) {
+                    self.record_interrupted([(session_id, (owner.uid, owner.pid))], false);
+                }
+                if self.sessions.abandon(session_id) {
+                    error!(session_id, cmd_id, "Closed the session of a stuck command");
+                    self.audit(|| AuditEvent::SessionClosed { session_id });
+                }
+                if let Some(store) = &self.store {
+                    store.remove(session_id);
+                }
+            }
+        }
+        self.release_instance_if_unused();
+    }
+
+    // Closes a session on the TA as if the CA had closed it.
+    pub(crate) fn evict_session(&self, session_id: u32) -> Result<(), ManagerError> {
+        let result = self.sessions.evict(session_id);
+        if !result
+            .as_ref()
+            .is_err_and(|e| e.kind() == ErrorKind::ItemNotFound)
+        {
+            self.audit(|| AuditEvent::SessionClosed { session_id });
+        }
+        self.release_instance_if_unused();
+        result.map_err(|e| match e.kind() {
+            ErrorKind::ItemNotFound => ManagerError::SessionNotFound(session_id),
+            kind => ManagerError::TaError(kind),
+        })
+    }
+
+    // Destroy the TA instance once its last session is closed, unless the TA
+    // asked to be kept alive, and let a draining manager stop.
+    pub(crate) fn release_instance_if_unused(&self) {
+        if self.lifecycle.is_draining() && self.sessions.is_empty() {
+            self.lifecycle.wake();
+        }
+        if self.config.ta_flags.keeps_instance_alive() {
+            return;
+        }
+        let mut created = self.instance.lock().unwrap();
+        if *created && self.sessions.is_empty() {
+            info!("Destroying TA instance after its last session");
+            if let Err(e) = self.ta().destroy() {
+                error!(error = ?e, "Failed to destroy TA instance");
+            }
+            *created = false;
+        }
+    }
+
+    // Answer the requests sent on a CA connection until the CA closes it,
+    // after negotiating the protocol version if the CA starts with a `Hello`
+    // and sealing the connection if it follows with a `KeyExchange`.
+    pub(crate) fn handle_connection(&self, stream: IpcStream) -> Result<(), ManagerError> {
+        let peer = PeerCredentials::from_stream(&stream)?;
+        self.serve_connection(stream, peer)
+    }
+
+    // Serves a CA connection made by `peer`.
+    fn serve_connection(
+        &self,
+        stream: IpcStream,
+        peer: PeerCredentials,
+    ) -> Result<(), ManagerError> {
+        let mut stream = Connection::new(stream);
+        let Some(mut req) = self.read_request(&mut stream)? else {
+            return Ok(());
+        };
+        let mut agreed = None;
+        if let TeeRequest::Hello {
+            version,
+            capabilities,
+        } = req
+        {
+            agreed = self.handle_hello(&mut stream, version, capabilities)?;
+            if agreed.is_none() {
+                return Ok(());
+            }
+            match self.read_request(&mut stream)? {
+                Some(next) => req = next,
+                None => return Ok(()),
+            }
+        }
+        if let TeeRequest::KeyExchange { nonce } = req {
+            if !self.handle_key_exchange(&mut stream, agreed, nonce)? {
+                return Ok(());
+            }
+            match self.read_request(&mut stream)? {
+                Some(next) => req = next,
+                None => return Ok(()),
+            }
+        }
+        if self.requires_encryption() && !stream.is_sealed() {
+            warn!(
+                uid = peer.uid,
+                pid = peer.pid,
+                "Refusing unencrypted connection"
+            );
+            self.audit_denied(&peer, &req, ErrorKind::AccessDenied.into());
+            let resp = TeeResponse::error(req, ErrorKind::AccessDenied.into(), ReturnOrigin::Tee);
+            return self.write_response(&mut stream, resp);
+        }
+
+        // Connections without a `Hello` speak the oldest version.
+        let version = agreed.map_or(MIN_PROTOCOL_VERSION, |(version, _)| version);
+        let mut streams = PendingInvokes::new();
+        let mut bucket = self
+            .rate_limiter
+            .as_ref()
+            .map(RateLimiter::connection_bucket);
+        loop {
+            let (trace_id, request) = match req {
+                TeeRequest::Traced { trace_id, request } => (trace_id, *request),
+                req => (trace::new_trace_id(), req),
+            };
+            trace::set_trace_id(trace_id);
+            let span = info_span!("request", trace_id = %format_args!("{:016x}", trace_id));
+            span.in_scope(|| {
+                if self.rate_limited(&peer, bucket.as_mut(), &request) {
+                    let resp =
+                        TeeResponse::error(request, ErrorKind::Busy.into(), ReturnOrigin::Tee);
+                    self.write_response(&mut stream, resp)
+                } else {
+                    self.handle_request(
+                        &mut stream,
+                        &peer,
+                        &mut streams,
+                        request,
+                        version,
+                        trace_id,
+                    )
+                }
+            })?;
+            match self.read_request(&mut stream)? {
+                Some(next) => req = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Returns whether `req` exceeds the rate allowed to `peer`, whose
+    // connection owns `bucket`. Only requests starting work on the TA count.
+    // The chunks and end of a streamed command belong to its
+    // `InvokeStreamBegin`, which was counted, and refusing one would leave
+    // the command half sent. `RegisterTemplate` only stores parameters, the
+    // `InvokeTemplate` requests running them count.
+    fn rate_limited(
+        &self,
+        peer: &PeerCredentials,
+        bucket: Option<&mut TokenBucket>,
+        req: &TeeRequest,
+    ) -> bool {
+        let (Some(limiter), Some(bucket)) = (&self.rate_limiter, bucket) else {
+            return false;
+        };
+        let limited = matches!(
+            req,
+            TeeRequest::OpenSession { .. }
+                | TeeRequest::InvokeCommand { .. }
+                | TeeRequest::InvokeStreamBegin { .. }
+                | TeeRequest::InvokeBatch { .. }
+                | TeeRequest::InvokeTemplate { .. }
+        ) && !limiter.allow(peer, bucket);
+        if limited {
+            debug!(
+                uid = peer.uid,
+                pid = peer.pid,
+                "Request rate limit exceeded"
+            );
+            self.metrics.request_rate_limited();
+        }
+        limited
+    }
+
+    fn handle_request(
+        &self,
+        stream: &mut Connection,
+        peer: &PeerCredentials,
+        streams: &mut PendingInvokes,
+        req: TeeRequest,
+        version: u32,
+        trace_id: u64,
+    ) -> Result<(), ManagerError> {
+        if let Err(e) = self.ta().authorize(peer) {
+            warn!(
+                uid = peer.uid,
+                gid = peer.gid,
+                pid = peer.pid,
+                error = ?e,
+                "Refusing request"
+            );
+            self.audit_denied(peer, &req, e.raw_code());
+            let resp = TeeResponse::error(req, e.raw_code(), ReturnOrigin::TrustedApp);
+            return self.write_response(stream, resp);
+        }
+        if let Some(session_id) = req.session_id()
+            && let Err(e) = self.check_owner(peer, session_id)
+        {
+            self.audit_denied(peer, &req, e.raw_code());
+            let resp = TeeResponse::error(req, e.raw_code(), ReturnOrigin::Tee);
+            return self.write_response(stream, resp);
+        }
+
+        match req {
+            TeeRequest::OpenSession {
+                uuid: _,
+                connection_method: _,
+                params,
+                identity,
+            } => self.handle_open_session(stream, peer, params, identity),
+            TeeRequest::CloseSession { session_id } => {
+                self.handle_close_session(stream, session_id)
+            }
+            TeeRequest::InvokeCommand {
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+            } => {
+                let resp = match self.authorize_command(peer, session_id, cmd_id) {
+                    Ok(()) => {
+                        let resp = self.invoke_command(
+                            session_id,
+                            cmd_id,
+                            operation_id,
+                            params,
+                            timeout_ms,
+                            trace_id,
+                        );
+                        self.audit_command(peer, session_id, cmd_id, resp.result());
+                        resp
+                    }
+                    Err(e) => TeeResponse::InvokeCommand {
+                        params,
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::Tee,
+                        retry: false,
+                    },
+                };
+                self.write_response(stream, resp)
+            }
+            TeeRequest::InvokeBatch {
+                session_id,
+                operation_id,
+                commands,
+                timeout_ms,
+            } => {
+                let authorized = commands
+                    .iter()
+                    .try_for_each(|(cmd_id, _)| self.authorize_command(peer, session_id, *cmd_id));
+                let resp = match authorized {
+                    Ok(()) => {
+                        let cmd_ids: Vec<u32> =
+                            commands.iter().map(|(cmd_id, _)| *cmd_id).collect();
+                        let resp = self.invoke_batch(
+                            session_id,
+                            operation_id,
+                            commands,
+                            timeout_ms,
+                            trace_id,
+                        );
+                        if let TeeResponse::InvokeBatch { results, .. } = &resp {
+                            for (cmd_id, result) in cmd_ids.into_iter().zip(results) {
+                                self.audit_command(peer, session_id, cmd_id, result.result);
+                            }
+                        }
+                        resp
+                    }
+                    Err(e) => TeeResponse::InvokeBatch {
+                        results: Vec::new(),
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::Tee,
+                        retry: false,
+                    },
+                };
+                self.write_response(stream, resp)
+            }
+            TeeRequest::RegisterTemplate { session_id, params } => {
+                let resp = self.register_template(session_id, params);
+                self.write_response(stream, resp)
+            }
+            TeeRequest::InvokeTemplate {
+                session_id,
+                cmd_id,
+                operation_id,
+                template_id,
+                deltas,
+                timeout_ms,
+            } => {
+                let resp = match self.authorize_command(peer, session_id, cmd_id) {
+                    Ok(()) => {
+                        let resp = match self.predecessor(session_id) {
+                            Some(predecessor) => predecessor.invoke_template(
+                                session_id,
+                                cmd_id,
+                                operation_id,
+                                template_id,
+                                deltas,
+                                timeout_ms,
+                            ),
+                            None => match self
+                                .sessions
+                                .template(session_id, template_id)
+                                .and_then(|template| template::expand(&template, deltas))
+                            {
+                                Ok(params) => {
+                                    let mut resp = self.invoke_command(
+                                        session_id,
+                                        cmd_id,
+                                        operation_id,
+                                        params,
+                                        timeout_ms,
+                                        trace_id,
+                                    );
+                                    if let TeeResponse::InvokeCommand { params, .. } = &mut resp {
+                                        template::strip_inputs(params);
+                                    }
+                                    resp
+                                }
+                                Err(kind) => self.template_error(session_id, template_id, kind),
+                            },
+                        };
+                        self.audit_command(peer, session_id, cmd_id, resp.result());
+                        resp
+                    }
+                    Err(e) => TeeResponse::InvokeCommand {
+                        params: Parameters::default(),
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::Tee,
+                        retry: false,
+                    },
+                };
+                self.write_response(stream, resp)
+            }
+            req @ TeeRequest::CancelCommand { .. } if version < CANCEL_COMMAND_VERSION => {
+                warn!(
+                    version,
+                    "Refusing CancelCommand on an older protocol version"
+                );
+                let resp =
+                    TeeResponse::error(req, ErrorKind::NotSupported.into(), ReturnOrigin::Tee);
+                self.write_response(stream, resp)
+            }
+            TeeRequest::CancelCommand {
+                session_id,
+                operation_id,
+            } => self.handle_cancel_command(stream, session_id, operation_id),
+            TeeRequest::Subscribe { session_id } => self.handle_subscribe(stream, session_id),
+            TeeRequest::Hello { .. } => Err(ManagerError::Protocol(
+                "unexpected Hello after handshake".to_string(),
+            )),
+            TeeRequest::KeyExchange { .. } => Err(ManagerError::Protocol(
+                "unexpected KeyExchange after handshake".to_string(),
+            )),
+            req @ TeeRequest::Traced { .. } => {
+                warn!("Refusing nested Traced request");
+                let resp =
+                    TeeResponse::error(req, ErrorKind::BadParameters.into(), ReturnOrigin::Tee);
+                self.write_response(stream, resp)
+            }
+            TeeRequest::InvokeStreamBegin {
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+                lengths,
+            } => {
+                debug!(
+                    session_id,
+                    cmd_id,
+                    operation_id,
+                    ?lengths,
+                    "Starting streamed command"
+                );
+                let available = self
+                    .config
+                    .max_stream_size
+                    .saturating_sub(streams.values().map(PendingInvoke::size).sum());
+                let result = self
+                    .authorize_command(peer, session_id, cmd_id)
+                    .map_err(|e| e.kind())
+                    .and_then(|()| {
+                        PendingInvoke::new(cmd_id, params, timeout_ms, lengths, available)
+                    })
+                    .map(|invoke| {
+                        streams.insert((session_id, operation_id), invoke);
+                    });
+                self.write_stream_response(stream, result)
+            }
+            TeeRequest::InvokeStreamChunk {
+                session_id,
+                operation_id,
+                index,
+                data,
+            } => {
+                let key = (session_id, operation_id);
+                let result = match streams.get_mut(&key) {
+                    Some(invoke) => invoke.append(index, &data),
+                    None => Err(ErrorKind::ItemNotFound),
+                };
+                if result.is_err() {
+                    streams.remove(&key);
+                }
+                self.write_stream_response(stream, result)
+            }
+            TeeRequest::InvokeStreamEnd {
+                session_id,
+                operation_id,
+            } => {
+                let invoke = match streams.remove(&(session_id, operation_id)) {
+                    Some(mut invoke) => invoke.finish().map(|params| (invoke, params)),
+                    None => Err(ErrorKind::ItemNotFound),
+                };
+                let resp = match invoke {
+                    Ok((invoke, params)) => {
+                        let mut resp = self.invoke_command(
+                            session_id,
+                            invoke.cmd_id,
+                            operation_id,
+                            params,
+                            invoke.timeout_ms,
+                            trace_id,
+                        );
+                        self.audit_command(peer, session_id, invoke.cmd_id, resp.result());
+                        if let TeeResponse::InvokeCommand { params, .. } = &mut resp {
+                            invoke.strip_inputs(params);
+                        }
+                        resp
+                    }
+                    Err(kind) => {
+                        warn!(
+                            session_id,
+                            operation_id,
+                            ?kind,
+                            "Cannot run streamed command"
+                        );
+                        TeeResponse::InvokeCommand {
+                            params: Parameters::default(),
+                            result: kind.into(),
+                            origin: ReturnOrigin::Api,
+                            retry: false,
+                        }
+                    }
+                };
+                self.write_response(stream, resp)
+            }
+        }
+    }
+
+    // Asks the access policy, then the authorizer, whether `subject` may
+    // perform `action`.
+    fn authorize(&self, subject: &Subject<'_>, action: Action) -> optee_utee::Result<()> {
+        self.config.policy.authorize(subject, action)?;
+        match &self.config.authorizer {
+            Some(authorizer) => authorizer.authorize(subject, action),
+            None => Ok(()),
+        }
+    }
+
+    // Asks the authorizer whether `peer`, which opened the session, may run
+    // `cmd_id` on it. The access policy lets every command through. A
+    // request the successor forwards is checked against the CA that opened
+    // the session. Sessions missing from the table, such as those of a
+    // predecessor, are left for the invocation to report.
+    fn authorize_command(
+        &self,
+        peer: &PeerCredentials,
+        session_id: u32,
+        cmd_id: u32,
+    ) -> optee_utee::Result<()> {
+        if self.config.authorizer.is_none() {
+            return Ok(());
+        }
+        let Some((owner, identity)) = self.sessions.owner(session_id) else {
+            return Ok(());
+        };
+        let peer = if self.is_successor(peer) {
+            &owner
+        } else {
+            peer
+        };
+        let subject = Subject {
+            peer,
+            identity: &identity,
+        };
+        self.authorize(&subject, Action::InvokeCommand(cmd_id))
+            .inspect_err(|e| {
+                warn!(
+                    session_id,
+                    cmd_id,
+                    uid = peer.uid,
+                    error = ?e,
+                    "Authorizer refused a command"
+                );
+                self.audit(|| AuditEvent::AccessDenied {
+                    peer: peer.clone(),
+                    session_id: Some(session_id),
+                    action: Some(Action::InvokeCommand(cmd_id)),
+                    result: e.raw_code(),
+                });
+            })
+    }
+
+    // Records that `cmd_id` ran on a session for `peer` and returned `result`.
+    fn audit_command(&self, peer: &PeerCredentials, session_id: u32, cmd_id: u32, result: u32) {
+        self.audit(|| AuditEvent::CommandInvoked {
+            session_id,
+            cmd_id,
+            peer: peer.clone(),
+            result,
+        });
+    }
+
+    // Acknowledges a chunk of a streamed command, or reports why the command
+    // was dropped.
+    fn write_stream_response(
+        &self,
+        stream: &mut Connection,
+        result: Result<(), ErrorKind>,
+    ) -> Result<(), ManagerError> {
+        let resp = match result {
+            Ok(()) => TeeResponse::InvokeStream {
+                result: 0,
+                origin: ReturnOrigin::Tee,
+            },
+            Err(kind) => {
+                warn!(?kind, "Dropping streamed command");
+                TeeResponse::InvokeStream {
+                    result: kind.into(),
+                    origin: ReturnOrigin::Api,
+                }
+            }
+        };
+        self.write_response(stream, resp)
+    }
+
+    // Answer a `Hello` with the highest version both sides speak. Returns
+    // the version and capabilities agreed on if the connection may go on.
+    fn handle_hello(
+        &self,
+        stream: &mut Connection,
+        version: u32,
+        capabilities: u32,
+    ) -> Result<Option<(u32, u32)>, ManagerError> {
+        let negotiated = version.min(PROTOCOL_VERSION);
+        let accepted = negotiated >= MIN_PROTOCOL_VERSION;
+        if accepted {
+            if negotiated < version {
+                info!(version, negotiated, "Downgrading CA protocol version");
+            }
+        } else {
+            warn!(
+                version,
+                min_version = MIN_PROTOCOL_VERSION,
+                "Rejecting CA protocol version"
+            );
+        }
+
+        let mut offered = CAPABILITIES;
+        if self.requires_encryption() {
+            offered |= CAP_ENCRYPTION;
+        }
+        let capabilities = capabilities & offered;
+        self.write_response(
+            stream,
+            TeeResponse::Hello {
+                version: negotiated,
+                capabilities,
+                result: if accepted {
+                    0
+                } else {
+                    ErrorKind::UnsupportedVersion.into()
+                },
+                origin: ReturnOrigin::Tee,
+            },
+        )?;
+        Ok(accepted.then_some((negotiated, capabilities)))
+    }
+
+    // Whether CA connections must be sealed.
+    fn requires_encryption(&self) -> bool {
+        #[cfg(feature = "encrypted_transport")]
+        return self.config.transport_key.is_some();
+        #[cfg(not(feature = "encrypted_transport"))]
+        false
+    }
+
+    // Answer a `KeyExchange` following a `Hello` that agreed on `agreed`,
+    // and seal the connection. Returns whether the connection may go on.
+    #[cfg_attr(not(feature = "encrypted_transport"), allow(unused_variables))]
+    fn handle_key_exchange(
+        &self,
+        stream: &mut Connection,
+        agreed: Option<(u32, u32)>,
+        nonce: Vec<u8>,
+    ) -> Result<bool, ManagerError> {
+        let kind = if nonce.len() != KEY_EXCHANGE_NONCE_LEN {
+            ErrorKind::BadParameters
+        } else {
+            ErrorKind::NotSupported
+        };
+        #[cfg(feature = "encrypted_transport")]
+        if let (Some(key), Some((version, capabilities))) = (&self.config.transport_key, agreed)
+            && capabilities & CAP_ENCRYPTION != 0
+            && nonce.len() == KEY_EXCHANGE_NONCE_LEN
+        {
+            let manager_nonce = key_exchange_nonce();
+            let channel = SecureChannel::new(
+                key,
+                Side::Manager,
+                &nonce,
+                &manager_nonce,
+                version,
+                capabilities,
+            )?;
+            self.write_response(
+                stream,
+                TeeResponse::KeyExchange {
+                    nonce: manager_nonce,
+                    result: 0,
+                    origin: ReturnOrigin::Tee,
+                },
+            )?;
+            stream.seal(channel);
+            return Ok(true);
+        }
+
+        warn!(?kind, "Refusing key exchange");
+        self.write_response(
+            stream,
+            TeeResponse::KeyExchange {
+                nonce: Vec::new(),
+                result: kind.into(),
+                origin: ReturnOrigin::Tee,
+            },
+        )?;
+        Ok(false)
+    }
+
+    fn handle_open_session(
+        &self,
+        stream: &mut Connection,
+        peer: &PeerCredentials,
+        params: Parameters,
+        identity: ClientIdentity,
+    ) -> Result<(), ManagerError> {
+        // The client UUID the CA sent is not trusted, the one derived from
+        // its credentials replaces it.
+        let identity = match peer.identity(identity) {
+            Ok(identity) => identity,
+            Err(e) if e.kind() == ErrorKind::BadParameters => {
+                warn!(login = identity.login, "Invalid client login type");
+                return self.write_response(
+                    stream,
+                    TeeResponse::OpenSession {
+                        session_id: 0,
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::Api,
+                    },
+                );
+            }
+            Err(e) => {
+                warn!(
+                    login = identity.login,
+                    uid = peer.uid,
+                    pid = peer.pid,
+                    "Credentials do not support the login type, refusing a session"
+                );
+                self.audit_open_denied(peer, e.raw_code());
+                return self.write_response(
+                    stream,
+                    TeeResponse::OpenSession {
+                        session_id: 0,
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::Tee,
+                    },
+                );
+            }
+        };
+        let client = ClientIdentity {
+            login: identity.login_type() as u32,
+            uuid: identity.uuid().to_bytes(),
+        };
+
+        if !self.accepts_login(&identity) {
+            warn!(
+                login = client.login,
+                uid = peer.uid,
+                "Login type not accepted by the TA, refusing a session"
+            );
+            self.audit_open_denied(peer, ErrorKind::AccessDenied.into());
+            return self.write_response(
+                stream,
+                TeeResponse::OpenSession {
+                    session_id: 0,
+                    result: ErrorKind::AccessDenied.into(),
+                    origin: ReturnOrigin::Tee,
+                },
+            );
+        }
+
+        let subject = Subject {
+            peer,
+            identity: &identity,
+        };
+        if let Err(e) = self.authorize(&subject, Action::OpenSession) {
+            warn!(
+                uid = peer.uid,
+                gid = peer.gid,
+                pid = peer.pid,
+                label = peer.label.as_ref().map(SecurityLabel::as_str),
+                error = ?e,
+                "Authorization refused a session"
+            );
+            self.audit_open_denied(peer, e.raw_code());
+            return self.write_response(
+                stream,
+                TeeResponse::OpenSession {
+                    session_id: 0,
+                    result: e.raw_code(),
+                    origin: ReturnOrigin::Tee,
+                },
+            );
+        }
+
+        let mut created = self.instance.lock().unwrap();
+        if self.lifecycle.is_draining() {
+            info!("Refusing a new session while draining");
+            self.write_response(
+                stream,
+                TeeResponse::OpenSession {
+                    session_id: 0,
+                    result: ErrorKind::Busy.into(),
+                    origin: ReturnOrigin::Tee,
+                },
+            )?;
+            // Make the CA connect again for its next session, which reaches
+            // the manager that took over the socket if there is one.
+            let _ = stream.stream.shutdown(Shutdown::Both);
+            return Ok(());
+        }
+        if self
+            .config
+            .max_sessions
+            .is_some_and(|max| self.sessions.len() >= max)
+            || self
+                .config
+                .max_sessions_per_client
+                .is_some_and(|max| self.sessions.count_for_uid(peer.uid) >= max)
+        {
+            warn!(uid = peer.uid, "Session limit reached, refusing a session");
+            self.metrics.session_refused();
+            self.audit_open_denied(peer, ErrorKind::Busy.into());
+            return self.write_response(
+                stream,
+                TeeResponse::OpenSession {
+                    session_id: 0,
+                    result: ErrorKind::Busy.into(),
+                    origin: ReturnOrigin::Tee,
+                },
+            );
+        }
+        if !self.config.ta_flags.multi_session && !self.sessions.is_empty() {
+            info!("Single-session TA already has a session open");
+            return self.write_response(
+                stream,
+                TeeResponse::OpenSession {
+                    session_id: 0,
+                    result: ErrorKind::Busy.into(),
+                    origin: ReturnOrigin::Tee,
+                },
+            );
+        }
+        let mut ta = self.ta();
+        if !*created {
+            match ta.create() {
+                Ok(()) => *created = true,
+                // The standby was created ahead of time.
+                Err(e) if self.promote_standby(&mut created) => {
+                    error!(error = ?e, "Failed to create TA instance");
+                    ta = self.ta();
+                }
+                Err(e) => {
+                    error!(error = ?e, "Failed to create TA instance");
+                    return self.write_response(
+                        stream,
+                        TeeResponse::OpenSession {
+                            session_id: 0,
+                            result: e.raw_code(),
+                            origin: ReturnOrigin::TrustedApp,
+                        },
+                    );
+                }
+            }
+        }
+
+        let session_id = self.next_session_id();
+        debug!(session_id, "Opening session");
+
+        #[cfg(feature = "secure_storage")]
+        let _tenant = self.tenant(&identity).map(crate::storage::enter_tenant);
+        #[cfg(feature = "client_properties")]
+        let _client = crate::properties::enter(crate::properties::ClientProperties {
+            session_id,
+            identity,
+            peer: peer.clone(),
+        });
+        let ctx =
+            OpenSessionContext::new(params, identity, peer.clone(), self.notifications.clone());
+        let resp = match ta.open_session(&ctx) {
+            Ok(ctx) => {
+                info!(session_id, "Session opened");
+                self.metrics.session_opened();
+                self.spawn_session(ta, session_id, ctx, client, identity, peer.clone());
+                TeeResponse::OpenSession {
+                    session_id,
+                    result: 0,
+                    origin: ReturnOrigin::TrustedApp,
+                }
+            }
+            Err(e) => {
+                warn!(session_id, error = ?e, "Failed to open session");
+                TeeResponse::OpenSession {
+                    session_id,
+                    result: e.raw_code(),
+                    origin: ReturnOrigin::TrustedApp,
+                }
+            }
+        };
+        drop(created);
+        self.release_instance_if_unused();
+        self.audit(|| AuditEvent::SessionOpened {
+            session_id,
+            peer: peer.clone(),
+            login: client.login,
+            result: resp.result(),
+        });
+
+        self.write_response(stream, resp)
+    }
+
+    // Records that `peer` was refused a session with `result`.
+    fn audit_open_denied(&self, peer: &PeerCredentials, result: u32) {
+        self.audit(|| AuditEvent::AccessDenied {
+            peer: peer.clone(),
+            session_id: None,
+            action: Some(Action::OpenSession),
+            result,
+        });
+    }
+
+    // Serves a session opened on `ta` as the execution model says, saving
+    // its context if the manager saves sessions.
+    fn spawn_session(
+        &self,
+        ta: Arc<T>,
+        session_id: u32,
+        ctx: T::SessionContext,
+        client: ClientIdentity,
+        identity: Identity,
+        peer: PeerCredentials,
+    ) {
+        let (queue, rx) = session_queue(self.config.session_queue_depth, self.config.queue_policy);
+        let persisted = self.store.as_ref().map(|store| {
+            let snapshot = SessionSnapshot::new(session_id, client, &peer, Vec::new());
+            let mut persisted = PersistedSession::new(store.clone(), snapshot);
+            persisted.update(ta.serialize_session(&ctx));
+            persisted
+        });
+        let env = SessionEnv {
+            span: info_span!("session", session_id),
+            #[cfg(feature = "secure_storage")]
+            storage: crate::storage::current(),
+            #[cfg(feature = "secure_storage")]
+            tenant: self.tenant(&identity),
+            #[cfg(feature = "ta_sessions")]
+            router: crate::ta_sessions::current(),
+            #[cfg(feature = "client_properties")]
+            client: Some(crate::properties::ClientProperties {
+                session_id,
+                identity,
+                peer: peer.clone(),
+            }),
+        };
+        let runner = SessionRunner::new(ta, ctx, rx, persisted, env);
+        let (queue, thread) = match self.config.execution_model {
+            ExecutionModel::ThreadPerSession => (queue, Some(thread::spawn(move || runner.run()))),
+            ExecutionModel::WorkerPool { workers } => {
+                let pool = self.workers.get_or_init(|| WorkerPool::new(workers));
+                let weight = self.config.session_weights.get(&peer.uid);
+                let weight = weight.copied().unwrap_or(1);
+                (pool.add(session_id, weight, queue, runner), None)
+            }
+        };
+        self.sessions
+            .insert(session_id, queue, thread, peer, identity);
+    }
+
+    // Returns the namespace of the persistent objects of the sessions of
+    // `identity`, if they are confined to their tenant.
+    #[cfg(feature = "secure_storage")]
+    fn tenant(&self, identity: &Identity) -> Option<Vec<u8>> {
+        self.config
+            .tenant_isolation
+            .then(|| optee_utee::tenant::TenantContext::new(identity).namespace())
+    }
+
+    // Serves again the sessions saved by the manager that ran before, under
+    // the ids they had. Sessions the TA fails to restore are dropped.
+    pub(crate) fn restore_sessions(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let (next_session_id, snapshots) = match store.load() {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!(error = ?e, "Failed to read the saved sessions");
+                return;
+            }
+        };
+        self.session_id.fetch_max(next_session_id, Ordering::SeqCst);
+        store.reserve(self.session_id.load(Ordering::SeqCst));
+        if snapshots.is_empty() {
+            return;
+        }
+
+        let _created = self.instance.lock().unwrap();
+        let ta = self.ta();
+        for snapshot in snapshots {
+            let session_id = snapshot.session_id;
+            let identity = match Identity::try_from(snapshot.identity) {
+                Ok(identity) => identity,
+                Err(e) => {
+                    warn!(session_id, error = ?e, "Dropping a saved session");
+                    continue;
+                }
+            };
+            if !self.accepts_login(&identity) {
+                warn!(
+                    session_id,
+                    "Login type no longer accepted, dropping a saved session"
+                );
+                continue;
+            }
+            #[cfg(feature = "secure_storage")]
+            let _tenant = self.tenant(&identity).map(crate::storage::enter_tenant);
+            match ta.deserialize_session(&snapshot.state, &identity) {
+                Ok(ctx) => {
+                    info!(session_id, "Session restored");
+                    self.metrics.session_opened();
+                    self.spawn_session(
+                        ta.clone(),
+                        session_id,
+                        ctx,
+                        snapshot.identity,
+                        identity,
+                        snapshot.peer(),
+                    );
+                }
+                Err(e) => warn!(session_id, error = ?e, "Failed to restore a saved session"),
+            }
+        }
+    }
+
+    fn handle_close_session(
+        &self,
+        stream: &mut Connection,
+        session_id: u32,
+    ) -> Result<(), ManagerError> {
+        debug!(session_id, "Closing session");
+
+        if let Some(predecessor) = self.predecessor(session_id) {
+            return self.write_response(stream, predecessor.close_session(session_id));
+        }
+
+        let resp = match self.sessions.close(session_id) {
+            Some(resp) => {
+                self.release_instance_if_unused();
+                self.audit(|| AuditEvent::SessionClosed { session_id });
+                resp
+            }
+            None if self
+                .interrupted
+                .lock()
+                .unwrap()
+                .remove(&session_id)
+                .is_some() =>
+            {
+                warn!(session_id, "Session was interrupted");
+                TeeResponse::CloseSession {
+                    result: ErrorKind::TargetDead.into(),
+                    origin: ReturnOrigin::Comms,
+                }
+            }
+            None => {
+                warn!(session_id, "Session not found");
+                TeeResponse::CloseSession {
+                    result: ErrorKind::ItemNotFound.into(),
+                    origin: ReturnOrigin::Tee,
+                }
+            }
+        };
+
+        self.write_response(stream, resp)
+    }
+
+    // Runs a command on a session and returns the response to send to the CA.
+    fn invoke_command(
+        &self,
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
+        params: Parameters,
+        timeout_ms: Option<u32>,
+        trace_id: u64,
+    ) -> TeeResponse {
+        debug!(session_id, cmd_id, timeout_ms, "Invoking command");
+
+        if let Some(predecessor) = self.predecessor(session_id) {
+            return predecessor.invoke_command(
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+            );
+        }
+
+        if let Some(index) = params.malformed() {
+            warn!(
+                session_id,
+                cmd_id, index, "Parameter does not match its type"
+            );
+            return TeeResponse::InvokeCommand {
+                params,
+                result: ErrorKind::BadParameters.into(),
+                origin: ReturnOrigin::Api,
+                retry: false,
+            };
+        }
+
+        let cache_key = self
+            .cache
+            .as_ref()
+            .filter(|_| self.ta().is_idempotent(cmd_id))
+            .and_then(|_| CacheKey::new(cmd_id, &params));
+        if let Some(key) = &cache_key
+            && let Some(resp) = self.cached_response(session_id, cmd_id, key)
+        {
+            return resp;
+        }
+
+        let limit = self.config.max_in_flight_per_session;
+        let Some(_in_flight) = acquire_slot(&self.in_flight, session_id, limit) else {
+            warn!(session_id, cmd_id, "Session in-flight limit reached");
+            return TeeResponse::InvokeCommand {
+                params,
+                result: ErrorKind::Busy.into(),
+                origin: ReturnOrigin::Tee,
+                retry: false,
+            };
+        };
+        let limit = self.config.command_limits.get(&cmd_id).copied();
+        let Some(_running) = acquire_slot(&self.running, cmd_id, limit) else {
+            warn!(session_id, cmd_id, "Command concurrency limit reached");
+            return TeeResponse::InvokeCommand {
+                params,
+                result: ErrorKind::Busy.into(),
+                origin: ReturnOrigin::Tee,
+                retry: false,
+            };
+        };
+
+        match self.sessions.sender(session_id) {
+            Some(queue) => {
+                let started = Instant::now();
+                let deadline = timeout_ms.map(|ms| started + Duration::from_millis(u64::from(ms)));
+                let context = CommandContext::new(self.supplicant.clone())
+                    .with_notifications(self.notifications.clone())
+                    .with_deadline(deadline)
+                    .with_trace_id(trace_id);
+                let key = (session_id, operation_id);
+                if !self.pending.lock().unwrap().register(key, &context) {
+                    warn!(session_id, operation_id, "Operation id already in flight");
+                    return TeeResponse::InvokeCommand {
+                        params,
+                        result: ErrorKind::BadParameters.into(),
+                        origin: ReturnOrigin::Api,
+                        retry: false,
+                    };
+                }
+
+                let (resp_tx, resp_rx) = unbounded();
+                let queued = Instant::now();
+                let resp = match queue.send(SessionMessage::Invoke {
+                    cmd_id,
+                    params,
+                    context,
+                    resp_tx,
+                }) {
+                    Ok(_) => queue.wait(&resp_rx, deadline),
+                    Err(_) => Err(RecvTimeoutError::Disconnected),
+                };
+
+                let context = self.pending.lock().unwrap().complete(key);
+                if let Some(started) = context.as_ref().and_then(CommandContext::started) {
+                    trace::span("queue wait", queued, started);
+                    trace::span("TA execution", started, Instant::now());
+                }
+                self.sessions.touch(session_id);
+                let resp = match resp {
+                    Ok(TeeResponse::InvokeCommand {
+                        params,
+                        result,
+                        origin,
+                        retry: _,
+                    }) => TeeResponse::InvokeCommand {
+                        params,
+                        result,
+                        origin,
+                        retry: self.instance_died(session_id, result, origin),
+                    },
+                    Ok(resp) => resp,
+                    Err(RecvTimeoutError::Timeout) => {
+                        // The TA keeps the session busy until it notices the
+                        // cancellation, later commands queue behind it.
+                        warn!(session_id, cmd_id, ?timeout_ms, "Command timed out");
+                        if let Some(context) = context {
+                            context.cancel();
+                        }
+                        TeeResponse::InvokeCommand {
+                            params: Parameters::default(),
+                            result: ErrorKind::Timeout.into(),
+                            origin: ReturnOrigin::Tee,
+                            retry: false,
+                        }
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        error!(session_id, cmd_id, "Session terminated");
+                        TeeResponse::InvokeCommand {
+                            params: Parameters::default(),
+                            result: ErrorKind::TargetDead.into(),
+                            origin: ReturnOrigin::Comms,
+                            retry: self.session_died(session_id),
+                        }
+                    }
+                };
+                self.metrics
+                    .command_invoked(cmd_id, started.elapsed(), resp.result());
+                if let (Some(cache), Some(key)) = (&self.cache, cache_key)
+                    && let TeeResponse::InvokeCommand {
+                        params, result: 0, ..
+                    } = &resp
+                {
+                    cache.insert(key, params.clone());
+                }
+                resp
+            }
+            None if let Some(retry) = self.interrupted(session_id) => {
+                warn!(session_id, "Session was interrupted");
+                TeeResponse::InvokeCommand {
+                    params,
+                    result: ErrorKind::TargetDead.into(),
+                    origin: ReturnOrigin::Comms,
+                    retry,
+                }
+            }
+            None => {
+                warn!(session_id, "Session not found");
+                TeeResponse::InvokeCommand {
+                    params,
+                    result: ErrorKind::ItemNotFound.into(),
+                    origin: ReturnOrigin::Tee,
+                    retry: false,
+                }
+            }
+        }
+    }
+
+    // Returns the cached response of `cmd_id` invoked on `session_id` with
+    // the parameters of `key`, if the session exists and the response did
+    // not expire.
+    fn cached_response(&self, session_id: u32, cmd_id: u32, key: &CacheKey) -> Option<TeeResponse> {
+        self.sessions.sender(session_id)?;
+        let params = self.cache.as_ref()?.get(key)?;
+        debug!(session_id, cmd_id, "Replaying cached response");
+        self.sessions.touch(session_id);
+        self.metrics.command_invoked(cmd_id, Duration::ZERO, 0);
+        Some(TeeResponse::InvokeCommand {
+            params,
+            result: 0,
+            origin: ReturnOrigin::TrustedApp,
+            retry: false,
+        })
+    }
+
+    // Keeps `params` as a template of a session and returns the response to
+    // send to the CA.
+    fn register_template(&self, session_id: u32, params: Parameters) -> TeeResponse {
+        if let Some(predecessor) = self.predecessor(session_id) {
+            return predecessor.register_template(session_id, params);
+        }
+
+        let registered = match params.malformed() {
+            Some(index) => {
+                warn!(
+                    session_id,
+                    index, "Template parameter does not match its type"
+                );
+                Err(ErrorKind::BadParameters)
+            }
+            None => self.sessions.add_template(session_id, params),
+        };
+        match registered {
+            Ok(template_id) => {
+                debug!(session_id, template_id, "Template registered");
+                TeeResponse::RegisterTemplate {
+                    template_id,
+                    result: 0,
+                    origin: ReturnOrigin::Tee,
+                }
+            }
+            Err(kind) => {
+                warn!(session_id, ?kind, "Cannot register template");
+                TeeResponse::RegisterTemplate {
+                    template_id: 0,
+                    result: kind.into(),
+                    origin: ReturnOrigin::Tee,
+                }
+            }
+        }
+    }
+
+    // Returns the response to an `InvokeTemplate` whose parameters could not
+    // be built from the template `template_id` of a session.
+    fn template_error(&self, session_id: u32, template_id: u32, kind: ErrorKind) -> TeeResponse {
+        match kind {
+            ErrorKind::ItemNotFound if let Some(retry) = self.interrupted(session_id) => {
+                warn!(session_id, "Session was interrupted");
+                TeeResponse::InvokeCommand {
+                    params: Parameters::default(),
+                    result: ErrorKind::TargetDead.into(),
+                    origin: ReturnOrigin::Comms,
+                    retry,
+                }
+            }
+            ErrorKind::ItemNotFound => {
+                warn!(session_id, "Session not found");
+                TeeResponse::InvokeCommand {
+                    params: Parameters::default(),
+                    result: kind.into(),
+                    origin: ReturnOrigin::Tee,
+                    retry: false,
+                }
+            }
+            _ => {
+                warn!(session_id, template_id, ?kind, "Cannot apply template");
+                TeeResponse::InvokeCommand {
+                    params: Parameters::default(),
+                    result: kind.into(),
+                    origin: ReturnOrigin::Api,
+                    retry: false,
+                }
+            }
+        }
+    }
+
+    // Runs a batch of commands on a session and returns the response to send
+    // to the CA. The batch counts as one in-flight command of the session,
+    // and once against the limit of each of its command ids.
+    fn invoke_batch(
+        &self,
+        session_id: u32,
+        operation_id: u32,
+        commands: Vec<(u32, Parameters)>,
+        timeout_ms: Option<u32>,
+        trace_id: u64,
+    ) -> TeeResponse {
+        debug!(
+            session_id,
+            commands = commands.len(),
+            timeout_ms,
+            "Invoking batch"
+        );
+        let failed = |result: ErrorKind, origin, retry| TeeResponse::InvokeBatch {
+            results: Vec::new(),
+            result: result.into(),
+            origin,
+            retry,
+        };
+
+        if let Some(predecessor) = self.predecessor(session_id) {
+            return predecessor.invoke_batch(session_id, operation_id, commands, timeout_ms);
+        }
+
+        if let Some((cmd_id, index)) = commands
+            .iter()
+            .find_map(|(cmd_id, params)| Some((*cmd_id, params.malformed()?)))
+        {
+            warn!(
+                session_id,
+                cmd_id, index, "Parameter does not match its type"
+            );
+            return failed(ErrorKind::BadParameters, ReturnOrigin::Api, false);
+        }
+
+        let limit = self.config.max_in_flight_per_session;
+        let Some(_in_flight) = acquire_slot(&self.in_flight, session_id, limit) else {
+            warn!(session_id, "Session in-flight limit reached");
+            return failed(ErrorKind::Busy, ReturnOrigin::Tee, false);
+        };
+        let mut cmd_ids: Vec<u32> = commands.iter().map(|(cmd_id, _)| *cmd_id).collect();
+        cmd_ids.sort_unstable();
+        cmd_ids.dedup();
+        let mut slots = Vec::with_capacity(cmd_ids.len());
+        for cmd_id in cmd_ids {
+            let limit = self.config.command_limits.get(&cmd_id).copied();
+            match acquire_slot(&self.running, cmd_id, limit) {
+                Some(slot) => slots.push(slot),
+                None => {
+                    warn!(session_id, cmd_id, "Command concurrency limit reached");
+                    return failed(ErrorKind::Busy, ReturnOrigin::Tee, false);
+                }
+            }
+        }
+
+        let Some(queue) = self.sessions.sender(session_id) else {
+            return match self.interrupted(session_id) {
+                Some(retry) => {
+                    warn!(session_id, "Session was interrupted");
+                    failed(ErrorKind::TargetDead, ReturnOrigin::Comms, retry)
+                }
+                None => {
+                    warn!(session_id, "Session not found");
+                    failed(ErrorKind::ItemNotFound, ReturnOrigin::Tee, false)
+                }
+            };
+        };
+
+        let started = Instant::now();
+        let deadline = timeout_ms.map(|ms| started + Duration::from_millis(u64::from(ms)));
+        let context = CommandContext::new(self.supplicant.clone())
+            .with_notifications(self.notifications.clone())
+            .with_deadline(deadline)
+            .with_trace_id(trace_id);
+        let key = (session_id, operation_id);
+        if !self.pending.lock().unwrap().register(key, &context) {
+            warn!(session_id, operation_id, "Operation id already in flight");
+            return failed(ErrorKind::BadParameters, ReturnOrigin::Api, false);
+        }
+
+        let cmd_ids: Vec<u32> = commands.iter().map(|(cmd_id, _)| *cmd_id).collect();
+        let (resp_tx, resp_rx) = unbounded();
+        let queued = Instant::now();
+        let results = match queue.send(SessionMessage::InvokeBatch {
+            commands,
+            context,
+            resp_tx,
+        }) {
+            Ok(_) => queue.wait(&resp_rx, deadline),
+            Err(_) => Err(RecvTimeoutError::Disconnected),
+        };
+
+        let context = self.pending.lock().unwrap().complete(key);
+        if let Some(started) = context.as_ref().and_then(CommandContext::started) {
+            trace::span("queue wait", queued, started);
+            trace::span("TA execution", started, Instant::now());
+        }
+        self.sessions.touch(session_id);
+        match results {
+            Ok(results) => {
+                for (cmd_id, (result, latency)) in cmd_ids.into_iter().zip(&results) {
+                    self.metrics
+                        .command_invoked(cmd_id, *latency, result.result);
+                }
+                let retry = results.iter().any(|(result, _)| {
+                    self.instance_died(session_id, result.result, result.origin)
+                });
+                TeeResponse::InvokeBatch {
+                    results: results.into_iter().map(|(result, _)| result).collect(),
+                    result: 0,
+                    origin: ReturnOrigin::TrustedApp,
+                    retry,
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // The commands not started yet are skipped.
+                warn!(session_id, ?timeout_ms, "Batch timed out");
+                if let Some(context) = context {
+                    context.cancel();
+                }
+                failed(ErrorKind::Timeout, ReturnOrigin::Tee, false)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!(session_id, "Session terminated");
+                let retry = self.session_died(session_id);
+                failed(ErrorKind::TargetDead, ReturnOrigin::Comms, retry)
+            }
+        }
+    }
+
+    fn handle_cancel_command(
+        &self,
+        stream: &mut Connection,
+        session_id: u32,
+        operation_id: u32,
+    ) -> Result<(), ManagerError> {
+        debug!(session_id, operation_id, "Cancelling operation");
+
+        if let Some(predecessor) = self.predecessor(session_id) {
+            let resp = predecessor.cancel_command(session_id, operation_id);
+            return self.write_response(stream, resp);
+        }
+
+        let key = (session_id, operation_id);
+        let result = if self.sessions.owner(session_id).is_none() {
+            warn!(session_id, operation_id, "Session not found");
+            ErrorKind::ItemNotFound.into()
+        } else if self.pending.lock().unwrap().cancel(key) {
+            0
+        } else {
+            warn!(session_id, operation_id, "Operation already completed");
+            ErrorKind::ItemNotFound.into()
+        };
+
+        self.write_response(
+            stream,
+            TeeResponse::CancelCommand {
+                result,
+                origin: ReturnOrigin::Tee,
+            },
+        )
+    }
+
+    // Write the events of the TA to the connection of a CA subscribed on
+    // `session_id`, until the session is closed, the CA disconnects or it
+    // falls behind. Sessions still served by the predecessor are not found:
+    // their CAs subscribe on the connections they have to it.
+    fn handle_subscribe(
+        &self,
+        stream: &mut Connection,
+        session_id: u32,
+    ) -> Result<(), ManagerError> {
+        if self.sessions.sender(session_id).is_none() {
+            warn!(session_id, "Session not found");
+            return self.write_response(
+                stream,
+                TeeResponse::Subscribe {
+                    result: ErrorKind::ItemNotFound.into(),
+                    origin: ReturnOrigin::Tee,
+                },
+            );
+        }
+        let notifications = self.notifications.subscribe();
+        self.write_response(
+            stream,
+            TeeResponse::Subscribe {
+                result: 0,
+                origin: ReturnOrigin::Tee,
+            },
+        )?;
+        debug!(session_id, "CA subscribed to notifications");
+        loop {
+            match notifications.recv_timeout(SUBSCRIPTION_CHECK_INTERVAL) {
+                Ok((event, data)) => {
+                    self.write_response(stream, TeeResponse::Notification { event, data })?
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if self.sessions.sender(session_id).is_none() || ipc::hung_up(&stream.stream) {
+                break;
+            }
+        }
+        debug!(session_id, "Subscription ended");
+        // Nothing more is read on the connection.
+        let _ = stream.stream.shutdown(Shutdown::Both);
+        Ok(())
+    }
+
+    fn next_session_id(&self) -> u32 {
+        let session_id = self.session_id.fetch_add(1, Ordering::SeqCst);
+        if let Some(store) = &self.store {
+            store.reserve(session_id + 1);
+        }
+        session_id
+    }
+
+    // Read the next request from the CA, or `None` once it closed the
+    // connection.
+    fn read_request(&self, stream: &mut Connection) -> Result<Option<TeeRequest>, ManagerError> {
+        match stream.read_frame()? {
+            Some(buf) => {
+                let decoding = Instant::now();
+                let req = self
+                    .config
+                    .codec
+                    .decode_request(&buf)
+                    .map_err(ManagerError::codec)?;
+                if self.tracer.get().is_some() {
+                    trace::start(&req, decoding);
+                }
+                Ok(Some(req))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_response(
+        &self,
+        stream: &mut Connection,
+        resp: TeeResponse,
+    ) -> Result<(), ManagerError> {
+        self.metrics.response_sent(resp.result());
+        let encoding = Instant::now();
+        let resp_data = self
+            .config
+            .codec
+            .encode_response(&resp)
+            .map_err(ManagerError::codec)?;
+        let writing = Instant::now();
+        stream.write_frame(&resp_data)?;
+        if let Some(tracer) = self.tracer.get() {
+            trace::span("encode", encoding, writing);
+            trace::span("write", writing, Instant::now());
+            if let Some(trace) = trace::finish()
+                && let Err(e) = tracer.record(trace)
+            {
+                warn!(error = ?e, "Failed to write the request trace");
+            }
+        }
+        Ok(())
+    }
+}
+
+// Session whose thread or instance died.
+#[derive(Clone, Copy)]
+struct Interrupted {
+    // Whether a standby instance took over, so that the CA may retry.
+    retry: bool,
+    // Uid and pid of the CA that opened the session, the only one told.
+    owner: (u32, i32),
+    at: Instant,
+}
+
+fn acquire_slot(
+    counts: &Mutex<HashMap<u32, usize>>,
+    key: u32,
+    max: Option<usize>,
+) -> Option<Slot<'_>> {
+    let mut counts_guard = counts.lock().unwrap();
+    let count = counts_guard.entry(key).or_insert(0);
+    if max.is_some_and(|max| *count >= max) {
+        return None;
+    }
+    *count += 1;
+    Some(Slot { counts, key })
+}
+
+// One use of a key counted against a limit.
+struct Slot<'a> {
+    counts: &'a Mutex<HashMap<u32, usize>>,
+    key: u32,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.key);
+            }
+        }
+    }
+}
+
+// The CAs of the tests connect through socket pairs.
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicBool;
+
+    use optee_utee::Result;
+
+    use super::*;
+    use crate::codec::{read_frame, write_frame};
+    use crate::config::{RateLimitKey, TaFlags};
+    use crate::protocol::{ParamType, Parameter, TeeParam};
+    use crate::{AuditSink, TAManager};
+
+    // TA accepting every session and command.
+    struct AcceptAll;
+
+    impl TrustedApplication for AcceptAll {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            _cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // TA panicking in command 1 and reporting its instance dead in command
+    // 2, the other commands succeeding.
+    struct Fragile;
+
+    impl TrustedApplication for Fragile {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            match cmd_id {
+                1 => panic!("command 1"),
+                2 => Err(ErrorKind::TargetDead.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    // TA failing command 0 with `Cancel` if it was cancelled and waiting in
+    // command 1 until it is, the other commands succeeding.
+    #[derive(Default)]
+    struct Cancellable {
+        waiting: AtomicBool,
+    }
+
+    impl TrustedApplication for Cancellable {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            _cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command_with_context(
+            &self,
+            cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+            context: &CommandContext,
+        ) -> Result<()> {
+            if cmd_id == 1 {
+                self.waiting.store(true, Ordering::Release);
+                while !context.is_cancelled() {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            match cmd_id {
+                0 | 1 if context.is_cancelled() => Err(ErrorKind::Cancel.into()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    // TA keeping the first parameter of the last command invoked.
+    #[derive(Default)]
+    struct Recorder {
+        first: Mutex<Option<Parameter>>,
+    }
+
+    impl TrustedApplication for Recorder {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            _cmd_id: u32,
+            params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            *self.first.lock().unwrap() = Some(params.0.clone());
+            Ok(())
+        }
+    }
+
+    // TA blocking in command 1 until released.
+    #[derive(Default)]
+    struct Blocking {
+        released: AtomicBool,
+    }
+
+    impl TrustedApplication for Blocking {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            while cmd_id == 1 && !self.released.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(())
+        }
+    }
+
+    // TA counting the instances created and destroyed.
+    #[derive(Default)]
+    struct Instances {
+        created: AtomicU32,
+        destroyed: AtomicU32,
+    }
+
+    impl TrustedApplication for Instances {
+        type SessionContext = ();
+
+        fn create(&self) -> Result<()> {
+            self.created.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+            Ok(())
+        }
+
+        fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn destroy(&self) -> Result<()> {
+            self.destroyed.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn invoke_command(
+            &self,
+            _cmd_id: u32,
+            _params: &mut Parameters,
+            _ctx: &mut (),
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Instances {
+        // The instances created and destroyed so far.
+        fn counts(&self) -> (u32, u32) {
+            (
+                self.created.load(Ordering::Relaxed),
+                self.destroyed.load(Ordering::Relaxed),
+            )
+        }
+    }
+
+    // A CA connected to `dispatcher` as the process `pid` of `uid`.
+    struct Client<T: TrustedApplication> {
+        dispatcher: Arc<Dispatcher<T>>,
+        stream: UnixStream,
+    }
+
+    impl<T: TrustedApplication> Client<T> {
+        fn connect(dispatcher: &Arc<Dispatcher<T>>, uid: u32, pid: i32) -> Self {
+            let (stream, manager) = UnixStream::pair().unwrap();
+            let peer = PeerCredentials {
+                uid,
+                gid: uid,
+                pid,
+                groups: Vec::new(),
+                label: None,
+            };
+            let served = dispatcher.clone();
+            thread::spawn(move || served.serve_connection(manager, peer));
+            Self {
+                dispatcher: dispatcher.clone(),
+                stream,
+            }
+        }
+
+        fn request(&mut self, req: TeeRequest) -> TeeResponse {
+            let codec = &self.dispatcher.config.codec;
+            write_frame(&mut self.stream, &codec.encode_request(&req).unwrap()).unwrap();
+            let frame = read_frame(&mut self.stream).unwrap().unwrap();
+            codec.decode_response(&frame).unwrap()
+        }
+
+        fn hello(&mut self, version: u32) {
+            let resp = self.request(TeeRequest::Hello {
+                version,
+                capabilities: u32::MAX,
+            });
+            assert_eq!(resp.result(), 0, "Hello failed");
+        }
+
+        fn open_session(&mut self) -> u32 {
+            let resp = self.request(TeeRequest::OpenSession {
+                uuid: String::new(),
+                connection_method: 0,
+                params: Parameters::default(),
+                identity: ClientIdentity::default(),
+            });
+            match resp {
+                TeeResponse::OpenSession {
+                    session_id,
+                    result: 0,
+                    ..
+                } => session_id,
+                resp => panic!("failed to open a session: {:#x}", resp.result()),
+            }
+        }
+
+        fn close_session(&mut self, session_id: u32) {
+            let resp = self.request(TeeRequest::CloseSession { session_id });
+            assert_eq!(resp.result(), 0, "failed to close session {}", session_id);
+        }
+
+        fn cancel(&mut self, session_id: u32, operation_id: u32) -> u32 {
+            self.request(TeeRequest::CancelCommand {
+                session_id,
+                operation_id,
+            })
+            .result()
+        }
+
+        fn invoke(&mut self, session_id: u32, operation_id: u32) -> u32 {
+            self.invoke_command(session_id, 0, operation_id).result()
+        }
+
+        fn invoke_command(
+            &mut self,
+            session_id: u32,
+            cmd_id: u32,
+            operation_id: u32,
+        ) -> TeeResponse {
+            self.request(TeeRequest::InvokeCommand {
+                session_id,
+                cmd_id,
+                operation_id,
+                params: Parameters::default(),
+                timeout_ms: None,
+            })
+        }
+    }
+
+    fn dispatcher<T: TrustedApplication>(ta: T) -> Arc<Dispatcher<T>> {
+        Arc::new(Dispatcher::new(
+            ta,
+            TAManagerConfig::default(),
+            Arc::default(),
+        ))
+    }
+
+    // Manager serving through `dispatcher`, to run its background threads.
+    fn manager<T: TrustedApplication>(dispatcher: &Arc<Dispatcher<T>>) -> TAManager<T> {
+        TAManager {
+            uuid: String::new(),
+            dispatcher: dispatcher.clone(),
+        }
+    }
+
+    // Audit sink keeping the events recorded.
+    #[derive(Clone, Debug, Default)]
+    struct AuditTrail(Arc<Mutex<Vec<AuditEvent>>>);
+
+    impl AuditSink for AuditTrail {
+        fn record(&self, event: &AuditEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    impl AuditTrail {
+        // Whether the close of the session `id` was recorded.
+        fn closed(&self, id: u32) -> bool {
+            self.0.lock().unwrap().iter().any(
+                |event| matches!(event, AuditEvent::SessionClosed { session_id } if *session_id == id),
+            )
+        }
+    }
+
+    // Waits up to 5 seconds for `condition` to hold.
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn sessions_belong_to_their_ca() {
+        let dispatcher = dispatcher(AcceptAll);
+        let mut owner = Client::connect(&dispatcher, 1000, 10);
+        let session_id = owner.open_session();
+        let denied = u32::from(ErrorKind::AccessDenied);
+
+        let mut other = Client::connect(&dispatcher, 1000, 11);
+        assert_eq!(other.invoke(session_id, 1), denied);
+        let requests = [
+            TeeRequest::InvokeBatch {
+                session_id,
+                operation_id: 2,
+                commands: vec![(0, Parameters::default())],
+                timeout_ms: None,
+            },
+            TeeRequest::RegisterTemplate {
+                session_id,
+                params: Parameters::default(),
+            },
+            TeeRequest::InvokeTemplate {
+                session_id,
+                cmd_id: 0,
+                operation_id: 3,
+                template_id: 0,
+                deltas: Vec::new(),
+                timeout_ms: None,
+            },
+            TeeRequest::InvokeStreamBegin {
+                session_id,
+                cmd_id: 0,
+                operation_id: 4,
+                params: Parameters::default(),
+                timeout_ms: None,
+                lengths: [0; 4],
+            },
+            TeeRequest::CancelCommand {
+                session_id,
+                operation_id: 1,
+            },
+            TeeRequest::Subscribe { session_id },
+            TeeRequest::CloseSession { session_id },
+        ];
+        for req in requests {
+            assert_eq!(other.request(req).result(), denied);
+        }
+
+        // The session is left untouched, also for another connection of the
+        // same CA.
+        let mut again = Client::connect(&dispatcher, 1000, 10);
+        assert_eq!(again.invoke(session_id, 5), 0);
+        let resp = owner.request(TeeRequest::CloseSession { session_id });
+        assert_eq!(resp.result(), 0);
+    }
+
+    fn with_standby() -> Arc<Dispatcher<Fragile>> {
+        let dispatcher = dispatcher(Fragile);
+        dispatcher.set_standby(Fragile);
+        dispatcher.create_standby();
+        dispatcher
+    }
+
+    fn retry(resp: &TeeResponse) -> bool {
+        matches!(resp, TeeResponse::InvokeCommand { retry: true, .. })
+    }
+
+    #[test]
+    fn panics_only_end_their_session() {
+        let dispatcher = with_standby();
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let broken = ca.open_session();
+        let other = ca.open_session();
+
+        let resp = ca.invoke_command(broken, 1, 1);
+        assert_eq!(resp.result(), u32::from(ErrorKind::TargetDead));
+        assert!(!retry(&resp));
+        assert_eq!(ca.invoke(broken, 2), u32::from(ErrorKind::TargetDead));
+        // Neither the instance nor the other sessions are affected, and the
+        // standby is kept.
+        assert_eq!(ca.invoke(other, 3), 0);
+        assert!(dispatcher.standby.lock().unwrap().is_some());
+
+        // Only the owner of the session is told it was interrupted.
+        let mut stranger = Client::connect(&dispatcher, 1000, 11);
+        let denied = u32::from(ErrorKind::AccessDenied);
+        assert_eq!(stranger.invoke(broken, 4), denied);
+    }
+
+    #[test]
+    fn panics_evict_their_session() {
+        let dispatcher = dispatcher(Fragile);
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let broken = ca.open_session();
+        let other = ca.open_session();
+
+        // Without a standby there is nothing to retry on.
+        let dead = u32::from(ErrorKind::TargetDead);
+        let resp = ca.invoke_command(broken, 1, 1);
+        assert_eq!(resp.result(), dead);
+        assert!(!retry(&resp));
+        assert_eq!(dispatcher.sessions.len(), 1);
+
+        // Later requests on the session fail fast instead of hanging.
+        let resp = ca.invoke_command(broken, 0, 2);
+        assert_eq!(resp.result(), dead);
+        assert!(!retry(&resp));
+        assert_eq!(ca.invoke(other, 3), 0);
+
+        let resp = ca.request(TeeRequest::CloseSession { session_id: broken });
+        assert_eq!(resp.result(), dead);
+    }
+
+    #[test]
+    fn dead_instances_fail_over() {
+        let dispatcher = with_standby();
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let reporting = ca.open_session();
+        let other = ca.open_session();
+
+        let resp = ca.invoke_command(reporting, 2, 1);
+        assert_eq!(resp.result(), u32::from(ErrorKind::TargetDead));
+        assert!(retry(&resp));
+        // All sessions of the dead instance are interrupted, and new ones
+        // are opened on the standby.
+        assert!(retry(&ca.invoke_command(other, 0, 2)));
+        let session_id = ca.open_session();
+        assert_eq!(ca.invoke(session_id, 3), 0);
+        assert!(dispatcher.standby.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn interrupted_sessions_are_bounded() {
+        let dispatcher = dispatcher(AcceptAll);
+        let sessions = (0..MAX_INTERRUPTED as u32 + 10).map(|session_id| (session_id, (0, 0)));
+        dispatcher.record_interrupted(sessions, false);
+        assert_eq!(
+            dispatcher.interrupted.lock().unwrap().len(),
+            MAX_INTERRUPTED
+        );
+
+        let expired = Instant::now().checked_sub(INTERRUPTED_TTL).unwrap();
+        for entry in dispatcher.interrupted.lock().unwrap().values_mut() {
+            entry.at = expired;
+        }
+        assert_eq!(dispatcher.interrupted(1), None);
+        dispatcher.record_interrupted([(1, (0, 0))], true);
+        assert_eq!(dispatcher.interrupted.lock().unwrap().len(), 1);
+        assert_eq!(dispatcher.interrupted(1), Some(true));
+    }
+
+    #[test]
+    fn empty_input_memrefs_reach_the_ta() {
+        let dispatcher = dispatcher(Recorder::default());
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+        let memref = Parameter {
+            param: TeeParam::default(),
+            param_type: ParamType::MemrefInput,
+        };
+        let resp = ca.request(TeeRequest::InvokeCommand {
+            session_id,
+            cmd_id: 0,
+            operation_id: 1,
+            params: Parameters(
+                memref.clone(),
+                Parameter::default(),
+                Parameter::default(),
+                Parameter::default(),
+            ),
+            timeout_ms: None,
+        });
+        assert_eq!(resp.result(), 0);
+        assert_eq!(*dispatcher.ta().first.lock().unwrap(), Some(memref));
+    }
+
+    #[test]
+    fn idle_sessions_are_evicted() {
+        let audit = AuditTrail::default();
+        let config = TAManagerConfig::default().with_audit_sink(audit.clone());
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+
+        let reaper = manager(&dispatcher).spawn_idle_reaper(Duration::from_millis(10));
+        wait_for(|| audit.closed(session_id));
+        assert_eq!(dispatcher.sessions.len(), 0);
+        // The reaper stops along with the manager.
+        dispatcher.lifecycle.transition(LifecycleState::Destroyed);
+        reaper.join().unwrap();
+    }
+
+    #[test]
+    fn stuck_commands_are_flagged() {
+        let threshold = Duration::from_millis(20);
+        for close_stuck in [false, true] {
+            let audit = AuditTrail::default();
+            let config = TAManagerConfig::default()
+                .with_watchdog(threshold, close_stuck)
+                .with_audit_sink(audit.clone());
+            let dispatcher = Arc::new(Dispatcher::new(Blocking::default(), config, Arc::default()));
+            let mut ca = Client::connect(&dispatcher, 1000, 10);
+            let session_id = ca.open_session();
+            let watchdog = manager(&dispatcher).spawn_watchdog(threshold);
+            let invoke = thread::spawn(move || ca.invoke_command(session_id, 1, 1).result());
+
+            let expected = if close_stuck {
+                // The session is closed, and the CA learns that it died.
+                wait_for(|| audit.closed(session_id));
+                assert_eq!(dispatcher.sessions.len(), 0);
+                u32::from(ErrorKind::TargetDead)
+            } else {
+                wait_for(|| dispatcher.sessions.list().iter().any(|info| info.stuck));
+                assert!(!audit.closed(session_id));
+                0
+            };
+            dispatcher.ta().released.store(true, Ordering::Release);
+            assert_eq!(invoke.join().unwrap(), expected);
+            dispatcher.lifecycle.transition(LifecycleState::Destroyed);
+            watchdog.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn hello_negotiates_the_version() {
+        let dispatcher = dispatcher(AcceptAll);
+        for (offered, agreed) in [
+            (PROTOCOL_VERSION + 1, PROTOCOL_VERSION),
+            (PROTOCOL_VERSION, PROTOCOL_VERSION),
+            (MIN_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION),
+        ] {
+            let mut ca = Client::connect(&dispatcher, 1000, 10);
+            let resp = ca.request(TeeRequest::Hello {
+                version: offered,
+                capabilities: u32::MAX,
+            });
+            match resp {
+                TeeResponse::Hello {
+                    version,
+                    capabilities,
+                    result: 0,
+                    ..
+                } => {
+                    assert_eq!(version, agreed);
+                    // Only the capabilities the manager has are agreed on.
+                    assert_eq!(capabilities, CAPABILITIES);
+                }
+                resp => panic!("Hello failed: {:#x}", resp.result()),
+            }
+            ca.open_session();
+
+            // The version is only negotiated once: a second `Hello` ends the
+            // connection.
+            let hello = TeeRequest::Hello {
+                version: agreed,
+                capabilities: 0,
+            };
+            let frame = dispatcher.config.codec.encode_request(&hello).unwrap();
+            write_frame(&mut ca.stream, &frame).unwrap();
+            assert!(read_frame(&mut ca.stream).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn old_versions_are_refused() {
+        let dispatcher = dispatcher(AcceptAll);
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let resp = ca.request(TeeRequest::Hello {
+            version: MIN_PROTOCOL_VERSION - 1,
+            capabilities: 0,
+        });
+        match resp {
+            TeeResponse::Hello {
+                version, result, ..
+            } => {
+                assert_eq!(version, MIN_PROTOCOL_VERSION - 1);
+                assert_eq!(result, u32::from(ErrorKind::UnsupportedVersion));
+            }
+            resp => panic!("unexpected response: {:#x}", resp.result()),
+        }
+        // The manager hangs up rather than serve a version it does not speak.
+        assert!(read_frame(&mut ca.stream).unwrap().is_none());
+    }
+
+    #[cfg(feature = "encrypted_transport")]
+    #[test]
+    fn key_exchanges_seal_connections() {
+        use crate::transport::TransportKey;
+
+        let key = TransportKey::new([7; 32]);
+        let config = TAManagerConfig::default().with_transport_key(key.clone());
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let hello = || TeeRequest::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: CAP_ENCRYPTION,
+        };
+
+        // Nonces of the wrong size are refused.
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        assert_eq!(ca.request(hello()).result(), 0);
+        let resp = ca.request(TeeRequest::KeyExchange { nonce: vec![0; 16] });
+        assert_eq!(resp.result(), u32::from(ErrorKind::BadParameters));
+
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let capabilities = match ca.request(hello()) {
+            TeeResponse::Hello {
+                capabilities,
+                result: 0,
+                ..
+            } => capabilities,
+            resp => panic!("Hello failed: {:#x}", resp.result()),
+        };
+        let nonce = key_exchange_nonce();
+        let manager_nonce = match ca.request(TeeRequest::KeyExchange {
+            nonce: nonce.clone(),
+        }) {
+            TeeResponse::KeyExchange {
+                nonce, result: 0, ..
+            } => nonce,
+            resp => panic!("KeyExchange failed: {:#x}", resp.result()),
+        };
+        let codec = &dispatcher.config.codec;
+        let mut stream = Connection::new(ca.stream);
+        stream.seal(
+            SecureChannel::new(
+                &key,
+                Side::Client,
+                &nonce,
+                &manager_nonce,
+                PROTOCOL_VERSION,
+                capabilities,
+            )
+            .unwrap(),
+        );
+        let req = TeeRequest::OpenSession {
+            uuid: String::new(),
+            connection_method: 0,
+            params: Parameters::default(),
+            identity: ClientIdentity::default(),
+        };
+        stream
+            .write_frame(&codec.encode_request(&req).unwrap())
+            .unwrap();
+        let frame = stream.read_frame().unwrap().unwrap();
+        assert_eq!(codec.decode_response(&frame).unwrap().result(), 0);
+    }
+
+    #[test]
+    fn cancels_reach_their_command() {
+        let dispatcher = dispatcher(Cancellable::default());
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        ca.hello(PROTOCOL_VERSION);
+        let session_id = ca.open_session();
+        let cancelled = u32::from(ErrorKind::Cancel);
+
+        // Before the command: the cancel is kept until it arrives.
+        assert_eq!(ca.cancel(session_id, 1), 0);
+        assert_eq!(ca.invoke_command(session_id, 0, 1).result(), cancelled);
+
+        // During the command.
+        let mut waiting = Client::connect(&dispatcher, 1000, 10);
+        let invoke = thread::spawn(move || waiting.invoke_command(session_id, 1, 2).result());
+        while !dispatcher.ta().waiting.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(ca.cancel(session_id, 2), 0);
+        assert_eq!(invoke.join().unwrap(), cancelled);
+
+        // After the command: the cancel fails, and does not reach a later
+        // command reusing the id.
+        assert_eq!(ca.invoke_command(session_id, 0, 3).result(), 0);
+        let not_found = u32::from(ErrorKind::ItemNotFound);
+        assert_eq!(ca.cancel(session_id, 3), not_found);
+        assert_eq!(ca.invoke_command(session_id, 0, 3).result(), 0);
+
+        // Nor does one for a session that does not exist.
+        assert_eq!(ca.cancel(session_id + 1, 4), not_found);
+    }
+
+    #[test]
+    fn operation_ids_in_flight_are_refused() {
+        let dispatcher = dispatcher(Cancellable::default());
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        ca.hello(PROTOCOL_VERSION);
+        let session_id = ca.open_session();
+
+        let mut waiting = Client::connect(&dispatcher, 1000, 10);
+        let invoke = thread::spawn(move || waiting.invoke_command(session_id, 1, 1).result());
+        while !dispatcher.ta().waiting.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let bad_parameters = u32::from(ErrorKind::BadParameters);
+        assert_eq!(ca.invoke_command(session_id, 0, 1).result(), bad_parameters);
+        let resp = ca.request(TeeRequest::InvokeBatch {
+            session_id,
+            operation_id: 1,
+            commands: vec![(0, Parameters::default())],
+            timeout_ms: None,
+        });
+        assert_eq!(resp.result(), bad_parameters);
+
+        // The command in flight still gets its cancel.
+        assert_eq!(ca.cancel(session_id, 1), 0);
+        assert_eq!(invoke.join().unwrap(), u32::from(ErrorKind::Cancel));
+    }
+
+    #[test]
+    fn cancels_need_version_12() {
+        let dispatcher = dispatcher(Cancellable::default());
+        let not_supported = u32::from(ErrorKind::NotSupported);
+
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        ca.hello(CANCEL_COMMAND_VERSION - 1);
+        let session_id = ca.open_session();
+        assert_eq!(ca.cancel(session_id, 1), not_supported);
+
+        // Without a `Hello` the connection speaks the oldest version.
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        assert_eq!(ca.cancel(session_id, 1), not_supported);
+    }
+
+    #[test]
+    fn requests_over_the_rate_limit_are_busy() {
+        let config = TAManagerConfig::default().with_rate_limit(1, 2, RateLimitKey::Connection);
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+        assert_eq!(ca.invoke(session_id, 1), 0);
+        let busy = u32::from(ErrorKind::Busy);
+        assert_eq!(ca.invoke(session_id, 2), busy);
+        assert_eq!(dispatcher.metrics.snapshot(1).rate_limited, 1);
+
+        // Requests not starting work on the TA are not counted.
+        ca.close_session(session_id);
+
+        // Each connection has its own bucket.
+        let mut other = Client::connect(&dispatcher, 1000, 10);
+        other.open_session();
+    }
+
+    #[test]
+    fn sessions_per_client_are_limited() {
+        let config = TAManagerConfig::default().with_max_sessions_per_client(1);
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        ca.open_session();
+        let resp = ca.request(TeeRequest::OpenSession {
+            uuid: String::new(),
+            connection_method: 0,
+            params: Parameters::default(),
+            identity: ClientIdentity::default(),
+        });
+        assert_eq!(resp.result(), u32::from(ErrorKind::Busy));
+
+        // Other uids are counted apart.
+        let mut other = Client::connect(&dispatcher, 1001, 11);
+        other.open_session();
+    }
+
+    #[test]
+    fn sessions_are_limited() {
+        let audit = AuditTrail::default();
+        let config = TAManagerConfig::default()
+            .with_max_sessions(1)
+            .with_audit_sink(audit.clone());
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+
+        // The limit holds across uids.
+        let busy = u32::from(ErrorKind::Busy);
+        let mut other = Client::connect(&dispatcher, 1001, 11);
+        let resp = other.request(TeeRequest::OpenSession {
+            uuid: String::new(),
+            connection_method: 0,
+            params: Parameters::default(),
+            identity: ClientIdentity::default(),
+        });
+        assert_eq!(resp.result(), busy);
+        assert_eq!(dispatcher.metrics.snapshot(1).sessions_refused, 1);
+        assert!(audit.0.lock().unwrap().iter().any(|event| matches!(
+            event,
+            AuditEvent::AccessDenied { peer, action: Some(Action::OpenSession), result, .. }
+                if peer.uid == 1001 && *result == busy
+        )));
+
+        // Closing the session frees its slot.
+        let resp = ca.request(TeeRequest::CloseSession { session_id });
+        assert_eq!(resp.result(), 0);
+        other.open_session();
+    }
+
+    #[test]
+    fn single_session_tas_refuse_a_second_session() {
+        let flags = TaFlags {
+            multi_session: false,
+            ..TaFlags::default()
+        };
+        let config = TAManagerConfig::default().with_ta_flags(flags);
+        let dispatcher = Arc::new(Dispatcher::new(AcceptAll, config, Arc::default()));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+
+        let mut other = Client::connect(&dispatcher, 1001, 11);
+        let resp = other.request(TeeRequest::OpenSession {
+            uuid: String::new(),
+            connection_method: 0,
+            params: Parameters::default(),
+            identity: ClientIdentity::default(),
+        });
+        assert_eq!(resp.result(), u32::from(ErrorKind::Busy));
+
+        ca.close_session(session_id);
+        other.open_session();
+    }
+
+    #[test]
+    fn instances_end_with_their_last_session() {
+        let flags = TaFlags {
+            instance_keep_alive: false,
+            ..TaFlags::default()
+        };
+        let config = TAManagerConfig::default().with_ta_flags(flags);
+        let dispatcher = Arc::new(Dispatcher::new(
+            Instances::default(),
+            config,
+            Arc::default(),
+        ));
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let first = ca.open_session();
+        let second = ca.open_session();
+        assert_eq!(dispatcher.ta().counts(), (1, 0));
+
+        ca.close_session(first);
+        assert_eq!(dispatcher.ta().counts(), (1, 0));
+        ca.close_session(second);
+        assert_eq!(dispatcher.ta().counts(), (1, 1));
+
+        // The next session gets a new instance.
+        ca.open_session();
+        assert_eq!(dispatcher.ta().counts(), (2, 1));
+    }
+
+    #[test]
+    fn instances_are_kept_alive() {
+        let dispatcher = dispatcher(Instances::default());
+        let mut ca = Client::connect(&dispatcher, 1000, 10);
+        let session_id = ca.open_session();
+        ca.close_session(session_id);
+        ca.open_session();
+        assert_eq!(dispatcher.ta().counts(), (1, 0));
+    }
+}
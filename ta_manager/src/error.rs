@@ -0,0 +1,75 @@
+use std::{error, fmt, io};
+
+use optee_utee::ErrorKind;
+
+/// Failure of a [`TAManager`](crate::TAManager), or of the handling of a CA
+/// connection.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ManagerError {
+    /// A socket or a file could not be used.
+    Io(io::Error),
+    /// A frame could not be encoded or decoded by the codec of the manager.
+    Codec(Box<dyn error::Error + Send + Sync>),
+    /// A CA broke the protocol, e.g. by sending a request out of order.
+    Protocol(String),
+    /// The TA could not be registered with the TA Manager server.
+    Registration(io::Error),
+    /// No session with this id is open.
+    SessionNotFound(u32),
+    /// The TA failed, e.g. to create its instance.
+    TaError(ErrorKind),
+    /// A TA is already hosted under this uuid.
+    AlreadyHosted(String),
+    /// The thread serving a TA panicked.
+    Panicked,
+    /// The secure storage of the TA failed its startup check, under
+    /// [`StorageFailurePolicy::Fatal`](crate::StorageFailurePolicy::Fatal).
+    Storage(io::Error),
+}
+
+impl ManagerError {
+    pub(crate) fn codec(e: anyhow::Error) -> Self {
+        ManagerError::Codec(e.into())
+    }
+}
+
+impl fmt::Display for ManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManagerError::Io(e) => write!(f, "I/O error: {}", e),
+            ManagerError::Codec(e) => write!(f, "codec error: {}", e),
+            ManagerError::Protocol(msg) => write!(f, "protocol violation: {}", msg),
+            ManagerError::Registration(e) => write!(f, "failed to register the TA: {}", e),
+            ManagerError::SessionNotFound(id) => write!(f, "session {} not found", id),
+            ManagerError::TaError(kind) => write!(f, "TA error: {}", kind),
+            ManagerError::AlreadyHosted(uuid) => write!(f, "TA {} is already hosted", uuid),
+            ManagerError::Panicked => write!(f, "TA thread panicked"),
+            ManagerError::Storage(e) => write!(f, "secure storage unavailable: {}", e),
+        }
+    }
+}
+
+impl error::Error for ManagerError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ManagerError::Io(e) | ManagerError::Registration(e) | ManagerError::Storage(e) => {
+                Some(e)
+            }
+            ManagerError::Codec(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ManagerError {
+    fn from(e: io::Error) -> Self {
+        ManagerError::Io(e)
+    }
+}
+
+impl From<optee_utee::Error> for ManagerError {
+    fn from(e: optee_utee::Error) -> Self {
+        ManagerError::TaError(e.kind())
+    }
+}
@@ -0,0 +1,91 @@
+//! Entry points of the fuzz targets under `fuzz/`, built with the `fuzzing`
+//! feature. Not part of the stable API.
+
+use std::{
+    io::{self, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    sync::Arc,
+    thread,
+};
+
+use optee_utee::Result;
+
+use crate::TrustedApplication;
+use crate::codec::{BincodeCodec, CanonicalCodec, Codec};
+use crate::config::TAManagerConfig;
+use crate::context::OpenSessionContext;
+use crate::dispatch::Dispatcher;
+use crate::protocol::Parameters;
+
+// Limit on the memrefs of streamed invocations, well below the default so
+// that an input announcing large ones does not exhaust the fuzzer.
+const MAX_STREAM_SIZE: usize = 1 << 20;
+
+// TA accepting every session and command.
+struct AcceptAll;
+
+impl TrustedApplication for AcceptAll {
+    type SessionContext = ();
+
+    fn create(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn open_session(&self, _ctx: &OpenSessionContext) -> Result<()> {
+        Ok(())
+    }
+
+    fn close_session(&self, _ctx: &mut ()) -> Result<()> {
+        Ok(())
+    }
+
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn invoke_command(&self, _cmd_id: u32, _params: &mut Parameters, _ctx: &mut ()) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Decodes `input` as a request and as a response with every codec built
+/// in.
+pub fn decode(input: &[u8]) {
+    let mut codecs: Vec<Box<dyn Codec>> = vec![Box::new(BincodeCodec), Box::new(CanonicalCodec)];
+    #[cfg(feature = "cbor")]
+    codecs.push(Box::new(crate::codec::CborCodec));
+    #[cfg(feature = "json")]
+    codecs.push(Box::new(crate::codec::JsonCodec));
+    for codec in codecs {
+        let _ = codec.decode_request(input);
+        let _ = codec.decode_response(input);
+    }
+}
+
+/// Serves `input` as the bytes a CA sent on a connection, frames and all,
+/// as a [`TAManager`](crate::TAManager) would, with the default codec and
+/// a TA accepting every session and command. The sessions opened are closed
+/// afterwards.
+pub fn serve_connection(input: &[u8]) {
+    let config = TAManagerConfig::default().with_max_stream_size(MAX_STREAM_SIZE);
+    let dispatcher = Dispatcher::new(AcceptAll, config, Arc::default());
+    let (mut ca, manager) = UnixStream::pair().expect("failed to create a socket pair");
+    let mut responses = ca.try_clone().expect("failed to clone a socket");
+    // Read the responses while the manager writes them, so that it does not
+    // block on a full socket.
+    let reader = thread::spawn(move || io::copy(&mut responses, &mut io::sink()));
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        let _ = ca.write_all(&input);
+        let _ = ca.shutdown(Shutdown::Write);
+    });
+
+    let _ = dispatcher.handle_connection(manager);
+    let _ = writer.join();
+    let _ = reader.join();
+    for info in dispatcher.sessions.list() {
+        dispatcher.sessions.close(info.session_id);
+    }
+    dispatcher.destroy_instance();
+}
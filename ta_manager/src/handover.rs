@@ -0,0 +1,491 @@
+//! Handover of the sockets of a manager to a manager taking over the TA.
+//!
+//! The sockets are passed as `SCM_RIGHTS` over a Unix socket, which Windows
+//! has no equivalent of for named pipes: there, a manager never finds one to
+//! take over from, and no manager can take over from it.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    io::{Read, Write},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr, thread,
+    time::Duration,
+};
+
+use crossbeam_channel::Receiver;
+#[cfg(target_os = "linux")]
+use crossbeam_channel::unbounded;
+use optee_utee::ErrorKind;
+use tracing::warn;
+#[cfg(target_os = "linux")]
+use tracing::{Span, debug, info};
+
+use crate::client::ClientPool;
+use crate::config::TAManagerConfig;
+use crate::ipc::{IpcListener, IpcStream};
+#[cfg(target_os = "linux")]
+use crate::ipc::{connect, current_uid, remove_socket, socket_addr};
+use crate::lifecycle::Lifecycle;
+#[cfg(target_os = "linux")]
+use crate::peer::PeerCredentials;
+use crate::protocol::{ParamDelta, Parameters, ReturnOrigin, TeeRequest, TeeResponse};
+
+// How long a manager handing over waits for its successor to confirm it got
+// the sockets.
+#[cfg(target_os = "linux")]
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Connections kept to the predecessor, each carrying one forwarded request
+// at a time.
+const PREDECESSOR_CONNECTIONS: usize = 8;
+
+// Most sessions whose owners a handover may carry.
+#[cfg(target_os = "linux")]
+const MAX_HANDED_OVER_SESSIONS: usize = 1 << 16;
+
+// Size of the owner of a session in a handover: its id, then the uid and
+// pid of the CA that opened it.
+#[cfg(target_os = "linux")]
+const OWNER_SIZE: usize = 12;
+
+/// What a manager inherits from the manager it takes over from.
+pub(crate) struct Inherited {
+    /// Socket the CAs connect to.
+    pub listener: IpcListener,
+    /// Connection on which the TA is registered with the TA Manager server.
+    pub registration: IpcStream,
+    /// Id of the first session the predecessor did not open.
+    pub next_session_id: u32,
+    /// Uid and pid of the CA that opened each session of the predecessor.
+    pub owners: HashMap<u32, (u32, i32)>,
+}
+
+/// Manager a TA was taken over from, which keeps serving the sessions it
+/// opened on a socket of its own until they are closed. The requests for
+/// these sessions are forwarded to it.
+pub(crate) struct Predecessor {
+    // Name of the socket the predecessor drains on, as a uuid for `pool`.
+    name: String,
+    next_session_id: u32,
+    // Uid and pid of the CA that opened each session, only these CAs may
+    // act on them.
+    owners: HashMap<u32, (u32, i32)>,
+    pool: ClientPool,
+}
+
+impl Predecessor {
+    // Talks to the predecessor with the codec and transport key of
+    // `config`, as CAs do.
+    pub(crate) fn new(
+        config: &TAManagerConfig,
+        uuid: &str,
+        next_session_id: u32,
+        owners: HashMap<u32, (u32, i32)>,
+    ) -> Self {
+        let pool = ClientPool::new(PREDECESSOR_CONNECTIONS)
+            .with_shared_codec(config.codec.clone())
+            .with_socket_dir(&config.socket_dir)
+            .with_abstract_namespace(config.abstract_sockets);
+        #[cfg(feature = "encrypted_transport")]
+        let pool = pool.with_optional_transport_key(config.transport_key.clone());
+        Self {
+            name: drain_name(uuid, next_session_id),
+            next_session_id,
+            owners,
+            pool,
+        }
+    }
+
+    // Returns whether `session_id` was opened by the predecessor.
+    pub(crate) fn owns(&self, session_id: u32) -> bool {
+        session_id < self.next_session_id
+    }
+
+    // Returns the uid and pid of the CA that opened `session_id`, if it was
+    // open when the predecessor handed over.
+    pub(crate) fn owner(&self, session_id: u32) -> Option<(u32, i32)> {
+        self.owners.get(&session_id).copied()
+    }
+
+    pub(crate) fn invoke_command(
+        &self,
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
+        params: Parameters,
+        timeout_ms: Option<u32>,
+    ) -> TeeResponse {
+        self.pool
+            .send_invoke(
+                &self.name,
+                session_id,
+                cmd_id,
+                operation_id,
+                params,
+                timeout_ms,
+            )
+            .unwrap_or_else(|e| {
+                warn!(session_id, error = %e, "Failed to forward a command to the previous TA manager");
+                TeeResponse::InvokeCommand {
+                    params: Parameters::default(),
+                    result: ErrorKind::TargetDead.into(),
+                    origin: ReturnOrigin::Comms,
+                    retry: true,
+                }
+            })
+    }
+
+    pub(crate) fn invoke_batch(
+        &self,
+        session_id: u32,
+        operation_id: u32,
+        commands: Vec<(u32, Parameters)>,
+        timeout_ms: Option<u32>,
+    ) -> TeeResponse {
+        let req = TeeRequest::InvokeBatch {
+            session_id,
+            operation_id,
+            commands,
+            timeout_ms,
+        };
+        self.pool.request(&self.name, req).unwrap_or_else(|e| {
+            warn!(session_id, error = ?e, "Failed to forward a batch to the previous TA manager");
+            TeeResponse::InvokeBatch {
+                results: Vec::new(),
+                result: ErrorKind::TargetDead.into(),
+                origin: ReturnOrigin::Comms,
+                retry: true,
+            }
+        })
+    }
+
+    pub(crate) fn register_template(&self, session_id: u32, params: Parameters) -> TeeResponse {
+        let req = TeeRequest::RegisterTemplate { session_id, params };
+        self.pool.request(&self.name, req).unwrap_or_else(|e| {
+            warn!(session_id, error = ?e, "Failed to forward a template to the previous TA manager");
+            TeeResponse::RegisterTemplate {
+                template_id: 0,
+                result: ErrorKind::TargetDead.into(),
+                origin: ReturnOrigin::Comms,
+            }
+        })
+    }
+
+    pub(crate) fn invoke_template(
+        &self,
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
+        template_id: u32,
+        deltas: Vec<ParamDelta>,
+        timeout_ms: Option<u32>,
+    ) -> TeeResponse {
+        let req = TeeRequest::InvokeTemplate {
+            session_id,
+            cmd_id,
+            operation_id,
+            template_id,
+            deltas,
+            timeout_ms,
+        };
+        self.pool.request(&self.name, req).unwrap_or_else(|e| {
+            warn!(session_id, error = ?e, "Failed to forward a command to the previous TA manager");
+            TeeResponse::InvokeCommand {
+                params: Parameters::default(),
+                result: ErrorKind::TargetDead.into(),
+                origin: ReturnOrigin::Comms,
+                retry: true,
+            }
+        })
+    }
+
+    pub(crate) fn close_session(&self, session_id: u32) -> TeeResponse {
+        let req = TeeRequest::CloseSession { session_id };
+        self.pool.request(&self.name, req).unwrap_or_else(|e| {
+            warn!(session_id, error = ?e, "Failed to forward a close to the previous TA manager");
+            TeeResponse::CloseSession {
+                result: ErrorKind::TargetDead.into(),
+                origin: ReturnOrigin::Comms,
+            }
+        })
+    }
+
+    pub(crate) fn cancel_command(&self, session_id: u32, operation_id: u32) -> TeeResponse {
+        let req = TeeRequest::CancelCommand {
+            session_id,
+            operation_id,
+        };
+        self.pool.request(&self.name, req).unwrap_or_else(|e| {
+            warn!(session_id, error = ?e, "Failed to forward a cancellation to the previous TA manager");
+            TeeResponse::CancelCommand {
+                result: ErrorKind::TargetDead.into(),
+                origin: ReturnOrigin::Comms,
+            }
+        })
+    }
+}
+
+// Socket on which the manager serving the TA identified by `uuid` waits for
+// a manager to take over from it.
+pub(crate) fn handover_socket_path(socket_dir: &Path, uuid: &str) -> PathBuf {
+    socket_dir.join(format!("{}.handover.sock", uuid))
+}
+
+// Name of the socket a manager that handed over serves its sessions on,
+// unique to it since the sessions of each manager start after those of the
+// previous one.
+pub(crate) fn drain_name(uuid: &str, next_session_id: u32) -> String {
+    format!("{}.drain-{}", uuid, next_session_id)
+}
+
+// Asks the manager serving on `path`, if any, to hand over its sockets.
+// Returns `None` if no manager is listening there.
+#[cfg(target_os = "linux")]
+pub(crate) fn take_over(path: &Path, abstract_namespace: bool) -> io::Result<Option<Inherited>> {
+    let mut stream = match connect(path, abstract_namespace) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    let mut header = [0u8; 8];
+    let [listener, registration] = recv_fds(&stream, &mut header)?;
+    let next_session_id = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let sessions = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if sessions > MAX_HANDED_OVER_SESSIONS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "handover carries too many sessions",
+        ));
+    }
+    let mut owners = vec![0u8; sessions * OWNER_SIZE];
+    stream.read_exact(&mut owners)?;
+    stream.write_all(&[0])?;
+    let owners = owners
+        .chunks_exact(OWNER_SIZE)
+        .map(|owner| {
+            let field = |i: usize| owner[i * 4..i * 4 + 4].try_into().unwrap();
+            let uid = u32::from_le_bytes(field(1));
+            let pid = i32::from_le_bytes(field(2));
+            (u32::from_le_bytes(field(0)), (uid, pid))
+        })
+        .collect();
+    info!(
+        next_session_id,
+        sessions, "Took over from the running TA manager"
+    );
+    Ok(Some(Inherited {
+        listener: IpcListener::from(listener),
+        registration: IpcStream::from(registration),
+        next_session_id,
+        owners,
+    }))
+}
+
+// Listens on `path` for managers taking over, which must run as the same
+// user. Each of them is sent to the returned channel, and the accept loop is
+// woken up through `lifecycle` to hand over to it.
+//
+// A socket in the abstract namespace cannot be replaced like a file, so it
+// is closed as soon as a manager asks to take over, for that manager to
+// listen under the same name. The manager can then no longer be taken over,
+// even if that handover fails.
+#[cfg(target_os = "linux")]
+pub(crate) fn listen(
+    path: &Path,
+    abstract_namespace: bool,
+    lifecycle: Lifecycle,
+) -> io::Result<Receiver<IpcStream>> {
+    remove_socket(path, abstract_namespace);
+    let listener = IpcListener::bind_addr(&socket_addr(path, abstract_namespace)?)?;
+    let (tx, rx) = unbounded();
+    let span = Span::current();
+    thread::spawn(move || {
+        let _entered = span.enter();
+        let uid = current_uid();
+        loop {
+            let Ok((stream, _)) = listener.accept() else {
+                continue;
+            };
+            match PeerCredentials::from_stream(&stream) {
+                Ok(peer) if peer.uid == uid => {}
+                peer => {
+                    warn!(?peer, "Refusing a handover to another user");
+                    continue;
+                }
+            }
+            debug!("A TA manager asked to take over");
+            if abstract_namespace {
+                drop(listener);
+                if tx.send(stream).is_ok() {
+                    lifecycle.wake();
+                }
+                return;
+            }
+            if tx.send(stream).is_err() {
+                return;
+            }
+            lifecycle.wake();
+        }
+    });
+    Ok(rx)
+}
+
+// Sends the sockets of the manager, the id of its next session and the
+// owners of its sessions, given as session id, uid and pid, to `successor`,
+// and waits for it to confirm it got them.
+#[cfg(target_os = "linux")]
+pub(crate) fn hand_over(
+    mut successor: IpcStream,
+    next_session_id: u32,
+    owners: &[(u32, u32, i32)],
+    listener: &IpcListener,
+    registration: &IpcStream,
+) -> io::Result<()> {
+    if owners.len() > MAX_HANDED_OVER_SESSIONS {
+        return Err(io::Error::other("too many sessions to hand over"));
+    }
+    let mut header = next_session_id.to_le_bytes().to_vec();
+    header.extend_from_slice(&(owners.len() as u32).to_le_bytes());
+    send_fds(
+        &successor,
+        &header,
+        &[listener.as_raw_fd(), registration.as_raw_fd()],
+    )?;
+    let mut sessions = Vec::with_capacity(owners.len() * OWNER_SIZE);
+    for (session_id, uid, pid) in owners {
+        sessions.extend_from_slice(&session_id.to_le_bytes());
+        sessions.extend_from_slice(&uid.to_le_bytes());
+        sessions.extend_from_slice(&pid.to_le_bytes());
+    }
+    successor.write_all(&sessions)?;
+    successor.set_read_timeout(Some(ACK_TIMEOUT))?;
+    successor.read_exact(&mut [0])?;
+    Ok(())
+}
+
+// Sends `fds` as `SCM_RIGHTS` ancillary data along `payload`.
+#[cfg(target_os = "linux")]
+pub(crate) fn send_fds(socket: &impl AsRawFd, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let mut iov = libc::iovec {
+        iov_base: payload.as_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let data_len = mem::size_of_val(fds) as u32;
+    // SAFETY: CMSG_SPACE only computes a size.
+    let space = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+    // u64s keep the buffer aligned for `cmsghdr`.
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    // SAFETY: `control` holds room for a header and `fds`, and `msg` points
+    // to buffers that outlive the call.
+    let ret = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Receives the `N` fds sent by `send_fds` along `payload`, which must be
+// received whole.
+#[cfg(target_os = "linux")]
+fn recv_fds<const N: usize>(stream: &IpcStream, payload: &mut [u8]) -> io::Result<[OwnedFd; N]> {
+    let mut iov = libc::iovec {
+        iov_base: payload.as_mut_ptr() as *mut libc::c_void,
+        iov_len: payload.len(),
+    };
+    let data_len = (N * mem::size_of::<RawFd>()) as u32;
+    // SAFETY: CMSG_SPACE only computes a size.
+    let space = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = space as _;
+    // SAFETY: `msg` points to buffers that outlive the call.
+    let ret = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if ret == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let complete = ret as usize == payload.len();
+
+    let mut fds = [-1; N];
+    // SAFETY: the kernel filled `control` with `msg_controllen` bytes of
+    // well-formed headers.
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if !cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+            && (*cmsg).cmsg_len as usize == libc::CMSG_LEN(data_len) as usize
+        {
+            ptr::copy_nonoverlapping(libc::CMSG_DATA(cmsg) as *const RawFd, fds.as_mut_ptr(), N);
+        }
+    }
+    if !complete || msg.msg_flags & libc::MSG_CTRUNC != 0 || fds.contains(&-1) {
+        // SAFETY: the fds the kernel installed belong to this process.
+        fds.iter()
+            .filter(|&&fd| fd >= 0)
+            .for_each(|&fd| drop(unsafe { OwnedFd::from_raw_fd(fd) }));
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "handover did not carry the expected sockets",
+        ));
+    }
+    // SAFETY: the kernel installed the fds in this process for us to own.
+    Ok(fds.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+// No manager serves the TA on Windows that could hand over its pipes.
+#[cfg(windows)]
+pub(crate) fn take_over(_path: &Path, _abstract_namespace: bool) -> io::Result<Option<Inherited>> {
+    Ok(None)
+}
+
+// No manager can take over on Windows, so none ever asks to.
+#[cfg(windows)]
+pub(crate) fn listen(
+    _path: &Path,
+    _abstract_namespace: bool,
+    _lifecycle: Lifecycle,
+) -> io::Result<Receiver<IpcStream>> {
+    Ok(crossbeam_channel::never())
+}
+
+#[cfg(windows)]
+pub(crate) fn hand_over(
+    _successor: IpcStream,
+    _next_session_id: u32,
+    _owners: &[(u32, u32, i32)],
+    _listener: &IpcListener,
+    _registration: &IpcStream,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
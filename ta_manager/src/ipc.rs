@@ -0,0 +1,206 @@
+//! Local connections between CAs, TA managers and the TA Manager server:
+//! Unix sockets on Linux, named pipes on Windows.
+//!
+//! Sockets are named by paths, e.g. those of
+//! [`TAManagerConfig::socket_dir`](crate::TAManagerConfig::socket_dir). On
+//! Windows the path names the pipe `\\.\pipe\<path>` instead, which leaves
+//! no file behind, and the features passing sockets between processes,
+//! handovers and socket activation by systemd, are not available.
+
+use std::{io, path::Path, time::Duration};
+
+#[cfg(target_os = "linux")]
+use std::{
+    fs::{self, DirBuilder, Permissions},
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            fs::{DirBuilderExt, PermissionsExt},
+            io::AsRawFd,
+        },
+    },
+};
+
+#[cfg(target_os = "linux")]
+pub(crate) use std::os::unix::net::{
+    SocketAddr as IpcAddr, UnixListener as IpcListener, UnixStream as IpcStream,
+};
+
+#[cfg(windows)]
+pub(crate) use crate::pipe::{
+    PipeAddr as IpcAddr, PipeListener as IpcListener, PipeStream as IpcStream,
+};
+
+// Address of the socket `path`, or with `abstract_namespace` that of the
+// socket named after it in the Linux abstract namespace, which leaves no file
+// behind.
+#[cfg(target_os = "linux")]
+pub(crate) fn socket_addr(path: &Path, abstract_namespace: bool) -> io::Result<IpcAddr> {
+    if abstract_namespace {
+        IpcAddr::from_abstract_name(path.as_os_str().as_bytes())
+    } else {
+        IpcAddr::from_pathname(path)
+    }
+}
+
+// Name of the pipe standing for the socket `path`. Pipes leave no file
+// behind, as sockets in the abstract namespace do.
+#[cfg(windows)]
+pub(crate) fn socket_addr(path: &Path, _abstract_namespace: bool) -> io::Result<IpcAddr> {
+    IpcAddr::new(path)
+}
+
+pub(crate) fn connect(path: &Path, abstract_namespace: bool) -> io::Result<IpcStream> {
+    connect_addr(&socket_addr(path, abstract_namespace)?)
+}
+
+pub(crate) fn connect_addr(addr: &IpcAddr) -> io::Result<IpcStream> {
+    #[cfg(target_os = "linux")]
+    return IpcStream::connect_addr(addr);
+    #[cfg(windows)]
+    return IpcStream::connect(addr);
+}
+
+// Bind a socket for CAs at `path`, replacing any stale one. A socket in the
+// abstract namespace has no file to replace, nor permissions to set.
+#[cfg(target_os = "linux")]
+pub(crate) fn bind(path: &Path, mode: u32, abstract_namespace: bool) -> io::Result<IpcListener> {
+    if abstract_namespace {
+        return IpcListener::bind_addr(&socket_addr(path, true)?);
+    }
+    let _ = fs::remove_file(path);
+    let listener = IpcListener::bind(path)?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+// Create the pipe standing for the socket `path`. Pipes have no mode: only
+// the user running the manager, administrators and LocalSystem may connect.
+#[cfg(windows)]
+pub(crate) fn bind(path: &Path, _mode: u32, _abstract_namespace: bool) -> io::Result<IpcListener> {
+    IpcListener::bind(&socket_addr(path, true)?)
+}
+
+// Removes the file of the socket `path`, if it has one.
+pub(crate) fn remove_socket(path: &Path, abstract_namespace: bool) {
+    #[cfg(target_os = "linux")]
+    if !abstract_namespace {
+        let _ = fs::remove_file(path);
+    }
+    #[cfg(windows)]
+    let _ = (path, abstract_namespace);
+}
+
+// Creates the directory of the sockets, only accessible to the user
+// running the manager. Pipes need none.
+pub(crate) fn create_socket_dir(path: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return DirBuilder::new().recursive(true).mode(0o700).create(path);
+    #[cfg(windows)]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+// Waits up to `timeout` for a connection on `listener`. Returns `None` if
+// none came, for the caller to check whether it should go on accepting.
+pub(crate) fn accept_timeout(
+    listener: &IpcListener,
+    timeout: Duration,
+) -> io::Result<Option<IpcStream>> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut fd = libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        // SAFETY: `fd` is valid for the whole call.
+        match unsafe { libc::poll(&mut fd, 1, millis) } {
+            0 => Ok(None),
+            -1 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => Ok(None),
+                e => Err(e),
+            },
+            _ => listener.accept().map(|(stream, _)| Some(stream)),
+        }
+    }
+    #[cfg(windows)]
+    return listener
+        .accept_timeout(timeout)
+        .map(|accepted| accepted.map(|(stream, _)| stream));
+}
+
+// Makes `listener` fail to accept connections from now on, waking the
+// thread blocked accepting on it up.
+pub(crate) fn shutdown_listener(listener: &IpcListener) {
+    // SAFETY: the listener is open for the whole call.
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::shutdown(listener.as_raw_fd(), libc::SHUT_RDWR)
+    };
+    #[cfg(windows)]
+    listener.shutdown();
+}
+
+// Whether the peer of `stream` closed its end, leaving what it sent unread.
+pub(crate) fn hung_up(stream: &IpcStream) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let mut byte = [0u8; 1];
+        // SAFETY: `byte` is valid for writes of its length for the whole
+        // call.
+        let received = unsafe {
+            libc::recv(
+                stream.as_raw_fd(),
+                byte.as_mut_ptr().cast(),
+                byte.len(),
+                libc::MSG_PEEK | libc::MSG_DONTWAIT,
+            )
+        };
+        received == 0
+    }
+    #[cfg(windows)]
+    stream.hung_up()
+}
+
+// Returns the uid the manager runs as, against which the credentials of
+// other managers are checked. On Windows, the relative id of the user.
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: geteuid cannot fail.
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::geteuid() };
+    #[cfg(windows)]
+    return crate::pipe::current_ids().map_or(u32::MAX, |(uid, _)| uid);
+}
+
+// Returns the gid the manager runs as. On Windows, the relative id of the
+// primary group of the user.
+pub(crate) fn current_gid() -> u32 {
+    // SAFETY: getegid cannot fail.
+    #[cfg(target_os = "linux")]
+    return unsafe { libc::getegid() };
+    #[cfg(windows)]
+    return crate::pipe::current_ids().map_or(u32::MAX, |(_, gid)| gid);
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_gives_up_after_its_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ta.sock");
+        let listener = bind(&path, 0o600, false).unwrap();
+        let accepted = accept_timeout(&listener, Duration::from_millis(10)).unwrap();
+        assert!(accepted.is_none());
+
+        let _client = connect(&path, false).unwrap();
+        let accepted = accept_timeout(&listener, Duration::from_secs(5)).unwrap();
+        assert!(accepted.is_some());
+    }
+}
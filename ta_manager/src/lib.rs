@@ -1,24 +1,147 @@
 use std::{
-    collections::HashMap,
-    io::{Read, Write},
-    os::unix::net::{UnixListener, UnixStream},
-    path::PathBuf,
-    sync::{
-        Arc,
-        atomic::{AtomicU32, Ordering},
-    },
-    thread,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
-use bincode::config;
-use crossbeam_channel::{Receiver, Sender, unbounded};
-use optee_utee::{ErrorKind, Result};
+use crossbeam_channel::Receiver;
+use optee_utee::platform::{self, Environment, Platform, Support};
+use optee_utee::{ErrorKind, Identity, LoginType, Result};
+use tracing::{Span, debug, error, info, info_span, warn};
 
-use crate::protocol::{Parameters, TARequest, TeeRequest, TeeResponse};
+use crate::control::{Controlled, ManagedTa};
+use crate::dispatch::Dispatcher;
+use crate::handover::{Inherited, Predecessor, handover_socket_path};
+use crate::ipc::{
+    IpcListener, IpcStream, accept_timeout, bind, connect, remove_socket, socket_addr,
+};
+use crate::protocol::{Parameters, TARequest};
+use crate::supplicant::Supplicant;
+
+#[cfg(feature = "audit_log")]
+pub use crate::audit::{AuditCheckpoint, HashChainAuditLog};
+pub use crate::audit::{AuditEvent, AuditSink};
+pub use crate::authz::{Action, Authorizer, PolicyAuthorizer, Subject};
+pub use crate::client::{ClientError, ClientPool, Subscription};
+#[cfg(feature = "cbor")]
+pub use crate::codec::CborCodec;
+#[cfg(feature = "json")]
+pub use crate::codec::JsonCodec;
+pub use crate::codec::{BincodeCodec, CanonicalCodec, Codec};
+pub use crate::config::{
+    ExecutionModel, QueuePolicy, RateLimit, RateLimitKey, TAManagerConfig, TaFlags,
+};
+pub use crate::context::{CommandContext, OpenSessionContext};
+pub use crate::control::ControlClient;
+pub use crate::error::ManagerError;
+pub use crate::lifecycle::{Lifecycle, LifecycleEvent, LifecycleState};
+pub use crate::metrics::{CommandMetrics, LATENCY_BUCKETS, LatencyHistogram, MetricsSnapshot};
+pub use crate::mock::MockCaClient;
+pub use crate::multi::MultiTAManager;
+pub use crate::notify::NotificationSender;
+pub use crate::peer::{PeerCredentials, SecurityLabel};
+pub use crate::policy::AccessPolicy;
+pub use crate::session::{SessionInfo, SessionTable};
+#[cfg(feature = "secure_storage")]
+pub use crate::storage::{SecureStorage, StorageFailurePolicy, StorageMode, storage_mode};
+pub use crate::supplicant::{ReeFsPlugin, ReeNetworkPlugin, SupplicantPlugin};
+#[cfg(feature = "encrypted_transport")]
+pub use crate::transport::TransportKey;
+#[cfg(feature = "wasm_ta")]
+pub use crate::wasm::WasmTa;
+
+// Time the accept loop waits for a connection before checking again whether
+// the manager drained or is being taken over.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Socket on which the TA identified by `uuid` serves CAs.
+pub(crate) fn ca_socket_path(socket_dir: &Path, uuid: &str) -> PathBuf {
+    socket_dir.join(format!("{}.sock", uuid))
+}
 
-const SERVER_SOCKET_PATH: &str = "/tmp/server.sock";
+// Returns the profile of the capabilities a TA hosted with `config` gets,
+// for TAs to query through `optee_utee::platform::current`.
+fn hosted_platform(config: &TAManagerConfig) -> Platform {
+    #[cfg(feature = "secure_storage")]
+    let secure_storage = match config.secure_storage {
+        Some(_) => Support::Emulated,
+        None => Support::Unavailable,
+    };
+    #[cfg(not(feature = "secure_storage"))]
+    let secure_storage = {
+        let _ = config;
+        Support::Unavailable
+    };
+    Platform {
+        environment: Environment::Hosted,
+        secure_storage,
+        rpmb: Support::Unavailable,
+        // Cryptographic operations are not emulated.
+        ecc_curves: &[],
+        supplicant_plugins: Support::Emulated,
+        // Through `CommandContext::is_cancelled`, not `TEE_GetCancellationFlag`.
+        cancellation: Support::Emulated,
+        ta_sessions: if cfg!(feature = "ta_sessions") {
+            Support::Emulated
+        } else {
+            Support::Unavailable
+        },
+    }
+}
 
+// The manager takes the credentials of CAs from the kernel: `SO_PEERCRED` on
+// Linux, the token of the client of a named pipe on Windows, see `ipc`.
+#[cfg(not(any(target_os = "linux", windows)))]
+compile_error!("ta_manager runs on Linux and Windows hosts");
+
+mod audit;
+mod authz;
+pub mod buffer;
+pub mod ca_client;
+mod cache;
+mod client;
+mod codec;
+mod config;
+mod context;
+mod control;
+mod dispatch;
+mod error;
+// The fuzz targets feed the dispatcher through socket pairs.
+#[cfg(all(feature = "fuzzing", target_os = "linux"))]
+#[doc(hidden)]
+pub mod fuzz;
+mod handover;
+mod ipc;
+mod lifecycle;
+mod metrics;
+mod mock;
+mod multi;
+mod notify;
+mod peer;
+#[cfg(windows)]
+mod pipe;
+mod policy;
+#[cfg(feature = "client_properties")]
+mod properties;
 pub mod protocol;
+mod rate_limit;
+mod session;
+mod snapshot;
+#[cfg(feature = "secure_storage")]
+mod storage;
+mod stream;
+mod supplicant;
+mod systemd;
+#[cfg(feature = "ta_sessions")]
+mod ta_sessions;
+mod template;
+mod trace;
+mod transport;
+#[cfg(feature = "wasm_ta")]
+mod wasm;
+mod worker;
 
 /// Trait representing a Trusted Application (TA).
 pub trait TrustedApplication: Send + Sync + 'static {
@@ -28,8 +151,45 @@ pub trait TrustedApplication: Send + Sync + 'static {
     /// Create a new TA instance.
     fn create(&self) -> Result<()>;
 
-    /// Open a session with the TA.
-    fn open_session(&self, params: &mut Parameters) -> Result<Self::SessionContext>;
+    /// Decide whether the process behind a CA connection may talk to the TA.
+    ///
+    /// Called for every request before it is dispatched. Returning an error
+    /// answers the request with that error instead. The default
+    /// implementation accepts every caller.
+    fn authorize(&self, _peer: &PeerCredentials) -> Result<()> {
+        Ok(())
+    }
+
+    /// Open a session with the TA on behalf of the CA described by `ctx`,
+    /// which carries the parameters, the identity of the client and the
+    /// credentials of its process, e.g. to restrict sessions to some login
+    /// types or to key them on the [`SecurityLabel`] of the CA.
+    fn open_session(&self, ctx: &OpenSessionContext) -> Result<Self::SessionContext>;
+
+    /// Serialize the context of a session, so that the session survives a
+    /// restart of the manager, see
+    /// [`TAManagerConfig::with_session_state_file`].
+    ///
+    /// Called after the session is opened and after every command invoked on
+    /// it. Returning `None` leaves the session out of the saved sessions,
+    /// which is what the default implementation does for every session.
+    fn serialize_session(&self, _ctx: &Self::SessionContext) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Rebuild the context of a session of the client `identity` from the
+    /// `state` returned by [`serialize_session`](Self::serialize_session)
+    /// before the manager restarted. The session is then served under its
+    /// former id. Returning an error drops the session.
+    ///
+    /// The default implementation fails with `NotSupported`.
+    fn deserialize_session(
+        &self,
+        _state: &[u8],
+        _identity: &Identity,
+    ) -> Result<Self::SessionContext> {
+        Err(ErrorKind::NotSupported.into())
+    }
 
     /// Close the session with the TA.
     fn close_session(&self, ctx: &mut Self::SessionContext) -> Result<()>;
@@ -44,242 +204,483 @@ pub trait TrustedApplication: Send + Sync + 'static {
         params: &mut Parameters,
         ctx: &mut Self::SessionContext,
     ) -> Result<()>;
+
+    /// Whether the command `cmd_id` only reads state that does not change
+    /// while the TA instance lives, e.g. returning a public key or a
+    /// version, so that its response depends on nothing but its parameters:
+    /// not on the session, its client or its tenant.
+    ///
+    /// With [`TAManagerConfig::with_response_cache`], the manager answers
+    /// such commands invoked again with the same parameters from the
+    /// response of an earlier invocation, without queuing them on the
+    /// session. The default implementation marks no command idempotent.
+    fn is_idempotent(&self, _cmd_id: u32) -> bool {
+        false
+    }
+
+    /// Invoke a command on the TA with access to its [`CommandContext`], e.g.
+    /// to notice cancellation requests from the CA.
+    ///
+    /// The default implementation ignores the context and calls
+    /// [`invoke_command`](Self::invoke_command).
+    fn invoke_command_with_context(
+        &self,
+        cmd_id: u32,
+        params: &mut Parameters,
+        ctx: &mut Self::SessionContext,
+        _context: &CommandContext,
+    ) -> Result<()> {
+        self.invoke_command(cmd_id, params, ctx)
+    }
 }
 
+/// Serves a TA to the CAs connecting to its socket.
+///
+/// The manager logs through [`tracing`], with its events recorded in a `ta`
+/// span carrying the TA uuid and those of session threads in a nested
+/// `session` span carrying the session id. Install a subscriber, such as one
+/// from `tracing-subscriber`, to collect them.
 pub struct TAManager<T: TrustedApplication> {
-    ta: Arc<T>,
     uuid: String,
-    sessions: HashMap<u32, Sender<SessionMessage>>,
-    session_id: AtomicU32,
+    dispatcher: Arc<Dispatcher<T>>,
 }
 
 impl<T: TrustedApplication> TAManager<T> {
     pub fn new(ta: T, uuid: &str) -> Self {
+        Self::with_config(ta, uuid, TAManagerConfig::default())
+    }
+
+    pub fn with_config(ta: T, uuid: &str, config: TAManagerConfig) -> Self {
+        let supplicant = Arc::new(Supplicant::new(
+            uuid,
+            config.supplicant_plugins.clone(),
+            config.server_socket.clone(),
+            config.abstract_sockets,
+        ));
         Self {
-            ta: Arc::new(ta),
             uuid: uuid.to_string(),
-            sessions: HashMap::new(),
-            session_id: AtomicU32::new(1),
+            dispatcher: Arc::new(Dispatcher::new(ta, config, supplicant)),
         }
     }
 
-    pub fn run_ta(&mut self) -> anyhow::Result<()> {
-        self.ta.create()?;
-        let _stream = self.register_ta()?;
-        self.handle_ca_request(self.ta.clone())?;
-        Ok(())
+    /// Keeps `standby` created next to the TA instance, to take over new
+    /// sessions if the TA instance dies: if creating it fails, or if the TA
+    /// answers a command with `TargetDead`. A session whose command panics
+    /// only ends that session, leaving the instance serving the others.
+    ///
+    /// Sessions of the dead instance are not closed on it. Commands invoked
+    /// on them fail with `TargetDead` and the `retry` hint set, telling the CA
+    /// to open a new session, which the standby serves.
+    pub fn with_standby(self, standby: T) -> Self {
+        self.dispatcher.set_standby(standby);
+        self
     }
 
-    // Register the TA with the TA Manager.
-    fn register_ta(&self) -> anyhow::Result<UnixStream> {
-        let mut stream = UnixStream::connect(SERVER_SOCKET_PATH)?;
+    /// Declares the login types the TA accepts sessions from, e.g. only
+    /// [`LoginType::Application`] for a TA serving a single CA. Opening a
+    /// session with another login type fails with `AccessDenied` without
+    /// reaching the TA, on top of the
+    /// [`allowed_login_types`](AccessPolicy::allowed_login_types) of the
+    /// access policy. All login types are accepted by default.
+    pub fn with_login_types(self, login_types: &[LoginType]) -> Self {
+        self.dispatcher.set_login_types(login_types.to_vec());
+        self
+    }
 
-        let req = TARequest::Register {
-            uuid: self.uuid.clone(),
+    /// Serves the TA until it is drained through its
+    /// [`lifecycle`](Self::lifecycle), then destroys the TA instance.
+    ///
+    /// On Linux, if another manager already serves the TA, e.g. an older
+    /// version of it, this manager takes over its socket and its
+    /// registration with the TA Manager server once its TA instance is
+    /// created, so that CAs keep reaching the TA on the same path. The other manager then drains: it
+    /// serves the sessions it opened, whose requests this manager forwards
+    /// to it, until they are closed, then stops like any drained manager.
+    /// Both managers must run as the same user.
+    ///
+    /// Otherwise, with a
+    /// [`session_state_file`](TAManagerConfig::session_state_file), the
+    /// sessions saved by the manager that served the TA before are restored
+    /// before serving CAs, on the socket systemd passed for the TA if the
+    /// manager was started through socket activation, see
+    /// [`fd_store`](TAManagerConfig::fd_store).
+    pub fn run_ta(&mut self) -> std::result::Result<(), ManagerError> {
+        let span = info_span!("ta", uuid = %self.uuid);
+        let _entered = span.enter();
+        platform::install(hosted_platform(&self.dispatcher.config));
+        #[cfg(feature = "secure_storage")]
+        let _storage = storage::enter(
+            self.dispatcher
+                .config
+                .secure_storage
+                .as_ref()
+                .map(|storage| storage.for_ta(&self.uuid))
+                .transpose()
+                .map_err(ManagerError::Storage)?,
+        );
+        #[cfg(feature = "ta_sessions")]
+        let _router = ta_sessions::enter(Some(Arc::new(ta_sessions::Router::new(
+            &self.uuid,
+            &self.dispatcher.config,
+        ))));
+        let config = &self.dispatcher.config;
+        if let Some(path) = &config.trace_file {
+            let _ = self
+                .dispatcher
+                .tracer
+                .set(trace::Tracer::create(path, &self.uuid)?);
+        }
+        self.dispatcher.create_instance()?;
+        self.dispatcher.create_standby();
+        let _control = config
+            .control_socket
+            .as_ref()
+            .map(|path| control::listen(path, vec![self.controlled()]))
+            .transpose()?;
+        let inherited = handover::take_over(
+            &handover_socket_path(&config.socket_dir, &self.uuid),
+            config.abstract_sockets,
+        )?;
+        let (listener, stream) = match inherited {
+            Some(Inherited {
+                listener,
+                mut registration,
+                next_session_id,
+                owners,
+            }) => {
+                let predecessor = Predecessor::new(config, &self.uuid, next_session_id, owners);
+                self.dispatcher.take_over(predecessor, next_session_id);
+                match register(&mut registration, &self.uuid, config) {
+                    Ok(()) => (Some(listener), registration),
+                    Err(e) => {
+                        warn!(error = ?e, "Inherited a broken registration, registering again");
+                        (Some(listener), register_ta(&self.uuid, config)?)
+                    }
+                }
+            }
+            None => {
+                self.dispatcher.restore_sessions();
+                (None, register_ta(&self.uuid, config)?)
+            }
         };
-        let data = bincode::encode_to_vec(req, config::standard())?;
-        stream.write_all(&data)?;
-        println!("TA registered with UUID: {}", self.uuid);
+        self.dispatcher
+            .lifecycle
+            .transition(LifecycleState::Registered);
+        let registration = Arc::new(Mutex::new(Some(stream)));
+        self.spawn_heartbeat(registration.clone());
+        if let Some(timeout) = self.dispatcher.config.idle_timeout {
+            self.spawn_idle_reaper(timeout);
+        }
+        if let Some(threshold) = self.dispatcher.config.stuck_threshold {
+            self.spawn_watchdog(threshold);
+        }
+        // Stopped even if serving failed, along with the threads above.
+        let served = self.handle_ca_request(listener, &registration);
+
+        self.dispatcher.destroy_instance();
+        if let Some(tracer) = self.dispatcher.tracer.get() {
+            tracer.close();
+        }
+        self.dispatcher
+            .lifecycle
+            .transition(LifecycleState::Destroyed);
+        served
+    }
+
+    /// Returns a handle to follow the lifecycle of the TA and to drain it.
+    pub fn lifecycle(&self) -> Lifecycle {
+        self.dispatcher.lifecycle.clone()
+    }
+
+    /// Returns the activity of the TA since the manager was created.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.dispatcher
+            .metrics
+            .snapshot(self.dispatcher.sessions.len())
+    }
+
+    /// Lists the sessions currently open on the TA.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.dispatcher.sessions.list()
+    }
+
+    /// Closes a session on the TA and removes it, as if the CA had closed it.
+    pub fn evict_session(&self, session_id: u32) -> std::result::Result<(), ManagerError> {
+        self.dispatcher.evict_session(session_id)
+    }
+
+    /// Returns a handle to the session table that stays usable from other
+    /// threads while [`run_ta`](Self::run_ta) is serving requests.
+    pub fn session_table(&self) -> SessionTable {
+        self.dispatcher.sessions.clone()
+    }
 
-        Ok(stream)
+    // Returns the TA as served through a control socket.
+    pub(crate) fn controlled(&self) -> Arc<dyn Controlled> {
+        Arc::new(ManagedTa {
+            uuid: self.uuid.clone(),
+            dispatcher: self.dispatcher.clone(),
+        })
     }
 
-    // Handle requests from the Client Application (CA).
-    fn handle_ca_request(&mut self, ta: Arc<T>) -> anyhow::Result<()> {
-        let path = PathBuf::from(format!("/tmp/{}.sock", self.uuid));
-        let _ = std::fs::remove_file(path.clone());
-
-        let listener = UnixListener::bind(path.clone())?;
-        println!("TA listening on socket: {:?}", path);
-
-        for stream in listener.incoming() {
-            println!("Received connection from CA");
-            let mut stream = stream?;
-
-            let mut len_buf = [0u8; 4];
-            stream.read_exact(&mut len_buf)?;
-            let len = u32::from_ne_bytes(len_buf) as usize;
-            let mut buf = vec![0u8; len];
-            stream.read_exact(&mut buf)?;
-
-            let (req, _): (TeeRequest, _) = bincode::decode_from_slice(&buf, config::standard())?;
-            match req {
-                TeeRequest::OpenSession {
-                    uuid: _,
-                    connection_method: _,
-                    params,
-                } => self.handle_open_session(stream, ta.clone(), params)?,
-                TeeRequest::CloseSession { session_id } => {
-                    self.handle_close_session(stream, session_id)? 
+    // Periodically evict sessions whose CA stopped talking to them, until
+    // the manager stops.
+    fn spawn_idle_reaper(&self, timeout: Duration) -> JoinHandle<()> {
+        let dispatcher = self.dispatcher.clone();
+        let events = dispatcher.lifecycle.subscribe();
+        let interval = (timeout / 2).clamp(Duration::from_millis(100), Duration::from_secs(1));
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            while !lifecycle::stopped(&events, interval) {
+                for session_id in dispatcher.sessions.evict_idle(timeout) {
+                    info!(session_id, ?timeout, "Session evicted after being idle");
+                    dispatcher.audit(|| AuditEvent::SessionClosed { session_id });
                 }
-                TeeRequest::InvokeCommand {
-                    session_id,
-                    cmd_id,
-                    params,
-                } => self.handle_invoke_command(stream, session_id, cmd_id, params)?,
-                TeeRequest::RequestCancellation { session_id: _ } => todo!(),
+                dispatcher.release_instance_if_unused();
             }
-        }
+        })
+    }
 
-        Ok(())
+    // Periodically report the commands stuck for `threshold`, until the
+    // manager stops.
+    fn spawn_watchdog(&self, threshold: Duration) -> JoinHandle<()> {
+        let dispatcher = self.dispatcher.clone();
+        let events = dispatcher.lifecycle.subscribe();
+        let interval = (threshold / 4).clamp(Duration::from_millis(10), Duration::from_secs(1));
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            while !lifecycle::stopped(&events, interval) {
+                dispatcher.watch_commands(threshold);
+            }
+        })
     }
 
-    fn handle_open_session(
-        &mut self,
-        mut stream: UnixStream,
-        ta: Arc<T>,
-        mut params: Parameters,
-    ) -> anyhow::Result<()> {
-        let session_id = self.next_session_id();
-        println!("Opening session with ID: {}", session_id);
-
-        let resp = match ta.open_session(&mut params) {
-            Ok(ctx) => {
-                println!("Session {} opened successfully", session_id);
-                let (tx, rx) = unbounded();
-                self.sessions.insert(session_id, tx);
-                thread::spawn(move || {
-                    session_thread(ta.clone(), ctx, rx);
-                });
-                TeeResponse::OpenSession {
-                    session_id,
-                    result: 0,
+    // Keep the TA registered until the manager stops: send heartbeats on
+    // the registration connection, and register again once it broke, e.g.
+    // because the TA Manager server restarted. Dropping the connection when
+    // the manager stops unregisters the TA.
+    fn spawn_heartbeat(&self, registration: Registration) {
+        let dispatcher = self.dispatcher.clone();
+        let uuid = self.uuid.clone();
+        let events = dispatcher.lifecycle.subscribe();
+        let span = Span::current();
+        thread::spawn(move || {
+            let _entered = span.enter();
+            let config = &dispatcher.config;
+            let stopped = |timeout| lifecycle::stopped(&events, timeout);
+            while !stopped(config.heartbeat_interval) {
+                let mut slot = registration.lock().unwrap();
+                // The registration went to a manager that took over.
+                let Some(stream) = slot.as_mut() else {
+                    return;
+                };
+                if send(stream, &TARequest::Heartbeat).is_ok() {
+                    continue;
                 }
-            }
-            Err(e) => {
-                println!("Failed to open session {}: {:?}", session_id, e);
-                TeeResponse::OpenSession {
-                    session_id,
-                    result: e.raw_code(),
+                drop(slot);
+                warn!("Lost the registration with the TA Manager server");
+                let mut delay = Duration::from_millis(100);
+                let stream = loop {
+                    match register_ta(&uuid, config) {
+                        Ok(stream) => break stream,
+                        Err(e) => {
+                            warn!(error = ?e, ?delay, "Failed to register again, retrying");
+                            if stopped(delay) {
+                                return;
+                            }
+                            delay = (delay * 2).min(config.max_reregister_delay);
+                        }
+                    }
+                };
+                match registration.lock().unwrap().as_mut() {
+                    Some(slot) => *slot = stream,
+                    None => return,
                 }
             }
-        };
-
-        let resp_data = bincode::encode_to_vec(resp, config::standard())?;
-        let mut message = Vec::with_capacity(4 + resp_data.len());
-        message.extend_from_slice(&(resp_data.len() as u32).to_ne_bytes());
-        message.extend_from_slice(&resp_data);
-        stream.write_all(&message)?;
-
-        Ok(())
+        });
     }
 
-    fn handle_close_session(
+    // Handle requests from the Client Application (CA) on `listener`, or on a
+    // newly bound socket, until the manager drained or handed over to
+    // another manager. In the latter case, the open sessions are then served
+    // on a socket of their own until they are closed.
+    fn handle_ca_request(
         &mut self,
-        mut stream: UnixStream,
-        session_id: u32,
-    ) -> anyhow::Result<()> {
-        println!("Closing session with ID: {}", session_id);
-
-        let resp = match self.sessions.get(&session_id) {
-            Some(tx) => {
-                let (resp_tx, resp_rx) = unbounded();
-                tx.send(SessionMessage::Close { resp_tx })?;
-                resp_rx.recv()?
-            }
-            None => {
-                println!("Session {} not found", session_id);
-                TeeResponse::CloseSession {
-                    result: ErrorKind::ItemNotFound as u32,
-                }
+        listener: Option<IpcListener>,
+        registration: &Registration,
+    ) -> std::result::Result<(), ManagerError> {
+        let config = &self.dispatcher.config;
+        let abstract_sockets = config.abstract_sockets;
+        if !abstract_sockets {
+            ipc::create_socket_dir(&config.socket_dir)?;
+        }
+        let path = ca_socket_path(&config.socket_dir, &self.uuid);
+        // A socket passed by systemd belongs to it, and stays in place.
+        let (listener, activated) = match listener {
+            Some(listener) => (listener, false),
+            None => match systemd::listener(&self.uuid)? {
+                Some(listener) => (listener, true),
+                None => (bind(&path, config.socket_mode, abstract_sockets)?, false),
+            },
+        };
+        let addr = listener.local_addr()?;
+        info!(?addr, "TA listening on socket");
+        let lifecycle = &self.dispatcher.lifecycle;
+        lifecycle.set_socket(addr);
+        let handover_path = handover_socket_path(&config.socket_dir, &self.uuid);
+        let successors = handover::listen(&handover_path, abstract_sockets, lifecycle.clone())?;
+        lifecycle.transition(LifecycleState::Serving);
+
+        let Some(next_session_id) = self.accept(&listener, Some((&successors, registration)))?
+        else {
+            let stored = config.fd_store
+                && systemd::store(&listener, &self.uuid).unwrap_or_else(|e| {
+                    warn!(error = ?e, "Failed to hand the socket to the systemd fd store");
+                    false
+                });
+            if !activated && !stored {
+                remove_socket(&path, abstract_sockets);
             }
+            remove_socket(&handover_path, abstract_sockets);
+            return Ok(());
         };
 
-        let resp_data = bincode::encode_to_vec(resp, config::standard())?;
-        let mut message = Vec::with_capacity(4 + resp_data.len());
-        message.extend_from_slice(&(resp_data.len() as u32).to_ne_bytes());
-        message.extend_from_slice(&resp_data);
-        stream.write_all(&message)?;
-
+        // The sockets now belong to the manager that took over, which
+        // forwards the requests for the open sessions to the drain socket.
+        let name = handover::drain_name(&self.uuid, next_session_id);
+        let drain_path = ca_socket_path(&config.socket_dir, &name);
+        let drain = bind(&drain_path, config.socket_mode, abstract_sockets)?;
+        info!(path = ?drain_path, "Handed over to a new TA manager, draining");
+        lifecycle.set_socket(socket_addr(&drain_path, abstract_sockets)?);
+        self.accept(&drain, None)?;
+        remove_socket(&drain_path, abstract_sockets);
         Ok(())
     }
 
-    fn handle_invoke_command(
-        &mut self,
-        mut stream: UnixStream,
-        session_id: u32,
-        cmd_id: u32,
-        params: Parameters,
-    ) -> anyhow::Result<()> {
-        println!("Invoking command {} on session {}", cmd_id, session_id);
-
-        let resp = match self.sessions.get(&session_id) {
-            Some(tx) => {
-                let (resp_tx, resp_rx) = unbounded();
-                tx.send(SessionMessage::Invoke {
-                    cmd_id,
-                    params,
-                    resp_tx,
-                })?;
-                resp_rx.recv()?
+    // Serve every connection accepted on `listener` on its own thread, so
+    // that a cancellation can reach a command that is still executing, until
+    // the manager drained. With `handover`, the manager may also hand over to
+    // a manager taking over, returning the id of its first session.
+    //
+    // The manager is woken up by a connection when it should stop or hand
+    // over, and checks anyway every `ACCEPT_POLL_INTERVAL`, e.g. in case the
+    // last session closed while the wake up went to another socket.
+    fn accept(
+        &self,
+        listener: &IpcListener,
+        handover: Option<(&Receiver<IpcStream>, &Registration)>,
+    ) -> io::Result<Option<u32>> {
+        loop {
+            if self.dispatcher.lifecycle.is_draining() && self.dispatcher.sessions.is_empty() {
+                return Ok(None);
             }
-            None => {
-                println!("Session {} not found", session_id);
-                TeeResponse::InvokeCommand {
-                    params,
-                    result: ErrorKind::ItemNotFound as u32,
-                }
+            if let Some((successors, registration)) = handover
+                && let Ok(successor) = successors.try_recv()
+                && let Some(next_session_id) = self.hand_over(successor, listener, registration)
+            {
+                return Ok(Some(next_session_id));
             }
-        };
-
-        let resp_data = bincode::encode_to_vec(resp, config::standard())?;
-        let mut message = Vec::with_capacity(4 + resp_data.len());
-        message.extend_from_slice(&(resp_data.len() as u32).to_ne_bytes());
-        message.extend_from_slice(&resp_data);
-        stream.write_all(&message)?;
-
-        Ok(())
+            let Some(stream) = accept_timeout(listener, ACCEPT_POLL_INTERVAL)? else {
+                continue;
+            };
+            debug!("Received connection from CA");
+
+            let dispatcher = self.dispatcher.clone();
+            let span = Span::current();
+            #[cfg(feature = "secure_storage")]
+            let storage = storage::current();
+            #[cfg(feature = "ta_sessions")]
+            let router = ta_sessions::current();
+            thread::spawn(move || {
+                let _entered = span.enter();
+                #[cfg(feature = "secure_storage")]
+                let _storage = storage::enter(storage);
+                #[cfg(feature = "ta_sessions")]
+                let _router = ta_sessions::enter(router);
+                if let Err(e) = dispatcher.handle_connection(stream) {
+                    error!(error = ?e, "Failed to handle CA request");
+                }
+            });
+        }
     }
 
-    fn next_session_id(&self) -> u32 {
-        self.session_id.fetch_add(1, Ordering::SeqCst)
+    // Hand the sockets of the manager over to `successor`. Returns the id of
+    // the first session of `successor` if it took them.
+    fn hand_over(
+        &self,
+        successor: IpcStream,
+        listener: &IpcListener,
+        registration: &Registration,
+    ) -> Option<u32> {
+        let mut registration = registration.lock().unwrap();
+        let stream = registration.as_ref()?;
+        let peer = match PeerCredentials::from_stream(&successor) {
+            Ok(peer) => peer,
+            Err(e) => {
+                warn!(error = ?e, "Failed to identify the new TA manager");
+                return None;
+            }
+        };
+        let handed_over = self.dispatcher.hand_over(peer, |next_session_id, owners| {
+            handover::hand_over(successor, next_session_id, owners, listener, stream)
+        });
+        match handed_over {
+            Ok(next_session_id) => {
+                *registration = None;
+                Some(next_session_id)
+            }
+            Err(e) => {
+                warn!(error = ?e, "Failed to hand over to the new TA manager");
+                None
+            }
+        }
     }
 }
 
-// Messages sent to session threads.
-enum SessionMessage {
-    Invoke {
-        cmd_id: u32,
-        params: Parameters,
-        resp_tx: Sender<TeeResponse>,
-    },
-    Close {
-        resp_tx: Sender<TeeResponse>,
-    },
+// Connection on which the TA is registered with the TA Manager server, taken
+// by the manager the TA is handed over to.
+type Registration = Arc<Mutex<Option<IpcStream>>>;
+
+// Register the TA with the TA Manager server.
+fn register_ta(
+    uuid: &str,
+    config: &TAManagerConfig,
+) -> std::result::Result<IpcStream, ManagerError> {
+    let mut stream = connect(&config.server_socket, config.abstract_sockets)
+        .map_err(ManagerError::Registration)?;
+    register(&mut stream, uuid, config)?;
+    Ok(stream)
 }
 
-// Thread function to handle a TA session.
-fn session_thread<T: TrustedApplication>(
-    ta: Arc<T>,
-    mut ctx: T::SessionContext,
-    rx: Receiver<SessionMessage>,
-) {
-    for msg in rx.iter() {
-        match msg {
-            SessionMessage::Invoke {
-                cmd_id,
-                mut params,
-                resp_tx,
-            } => {
-                let resp = match ta.invoke_command(cmd_id, &mut params, &mut ctx) {
-                    Ok(_) => TeeResponse::InvokeCommand { params, result: 0 },
-                    Err(e) => TeeResponse::InvokeCommand {
-                        params,
-                        result: e.raw_code(),
-                    },
-                };
-                let _ = resp_tx.send(resp);
-            }
-            SessionMessage::Close { resp_tx } => {
-                let resp = match ta.close_session(&mut ctx) {
-                    Ok(_) => TeeResponse::CloseSession { result: 0 },
-                    Err(e) => TeeResponse::CloseSession {
-                        result: e.raw_code(),
-                    },
-                };
-                let _ = resp_tx.send(resp);
-                break;
-            }
-        }
+// Register the TA on `stream`, or update its registration on a connection
+// inherited from another manager.
+fn register(
+    stream: &mut IpcStream,
+    uuid: &str,
+    config: &TAManagerConfig,
+) -> std::result::Result<(), ManagerError> {
+    send(
+        stream,
+        &TARequest::Register {
+            uuid: uuid.to_string(),
+            name: config.ta_name.clone(),
+            version: config.ta_version.clone(),
+        },
+    )
+    .map_err(ManagerError::Registration)?;
+    info!("TA registered");
+    if let Some(sink) = &config.audit_sink {
+        sink.record(&AuditEvent::Registered {
+            uuid: uuid.to_string(),
+        });
     }
+    Ok(())
+}
+
+fn send(stream: &mut IpcStream, req: &TARequest) -> io::Result<()> {
+    let data = bincode::encode_to_vec(req, bincode::config::standard())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    stream.write_all(&data)
 }
@@ -0,0 +1,115 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use tracing::info;
+
+use crate::ipc::{self, IpcAddr};
+
+/// States a hosted TA goes through, in this order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LifecycleState {
+    /// The manager has been created but does not serve the TA yet.
+    #[default]
+    Created,
+    /// The TA is registered with the TA Manager server.
+    Registered,
+    /// The TA accepts requests from CAs.
+    Serving,
+    /// The TA serves its open sessions but refuses new ones.
+    Draining,
+    /// The TA instance has been destroyed and the manager stopped.
+    Destroyed,
+}
+
+/// A transition between two [`LifecycleState`]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LifecycleEvent {
+    pub from: LifecycleState,
+    pub to: LifecycleState,
+}
+
+#[derive(Default)]
+struct LifecycleInner {
+    state: Mutex<LifecycleState>,
+    subscribers: Mutex<Vec<Sender<LifecycleEvent>>>,
+    // Socket the manager listens on, used to wake it up while draining.
+    socket: Mutex<Option<IpcAddr>>,
+}
+
+/// Handle on the lifecycle of a [`TAManager`](crate::TAManager), to follow
+/// its transitions from other threads and to ask it to stop.
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    inner: Arc<LifecycleInner>,
+}
+
+impl Lifecycle {
+    /// Returns the current state.
+    pub fn state(&self) -> LifecycleState {
+        *self.inner.state.lock().unwrap()
+    }
+
+    /// Returns a channel receiving every later transition.
+    pub fn subscribe(&self) -> Receiver<LifecycleEvent> {
+        let (tx, rx) = unbounded();
+        self.inner.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Asks a serving manager to drain: new sessions are refused and the
+    /// manager stops once the open sessions are closed.
+    pub fn drain(&self) {
+        if self.transition(LifecycleState::Draining) {
+            self.wake();
+        }
+    }
+
+    // Moves to `to` if it comes after the current state, and notifies the
+    // subscribers. Returns whether the state changed.
+    pub(crate) fn transition(&self, to: LifecycleState) -> bool {
+        let from = {
+            let mut state = self.inner.state.lock().unwrap();
+            if *state >= to {
+                return false;
+            }
+            std::mem::replace(&mut *state, to)
+        };
+
+        info!(?from, ?to, "TA lifecycle transition");
+        let event = LifecycleEvent { from, to };
+        self.inner
+            .subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event).is_ok());
+        true
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.state() == LifecycleState::Draining
+    }
+
+    pub(crate) fn set_socket(&self, addr: IpcAddr) {
+        *self.inner.socket.lock().unwrap() = Some(addr);
+    }
+
+    // Unblock the accept loop so that it notices the manager is draining.
+    pub(crate) fn wake(&self) {
+        if let Some(addr) = self.inner.socket.lock().unwrap().as_ref() {
+            let _ = ipc::connect_addr(addr);
+        }
+    }
+}
+
+// Waits up to `timeout` on `events`, a subscription to the lifecycle of a
+// manager, returning whether the manager stopped.
+pub(crate) fn stopped(events: &Receiver<LifecycleEvent>, timeout: Duration) -> bool {
+    match events.recv_timeout(timeout) {
+        Ok(event) => event.to == LifecycleState::Destroyed,
+        Err(RecvTimeoutError::Timeout) => false,
+        Err(RecvTimeoutError::Disconnected) => true,
+    }
+}
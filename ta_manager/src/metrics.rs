@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+/// Upper bounds of the buckets of a [`LatencyHistogram`].
+pub const LATENCY_BUCKETS: [Duration; 6] = [
+    Duration::from_micros(100),
+    Duration::from_millis(1),
+    Duration::from_millis(10),
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+/// Distribution of command latencies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// `counts[i]` counts the commands that took at most
+    /// `LATENCY_BUCKETS[i]` and longer than the previous bound. The last
+    /// entry counts the commands slower than every bound.
+    pub counts: [u64; LATENCY_BUCKETS.len() + 1],
+    /// Sum of the latencies of all commands.
+    pub total: Duration,
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| latency <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.total += latency;
+    }
+
+    /// Returns the number of commands recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// Statistics of one command id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommandMetrics {
+    pub invoked: u64,
+    /// Invocations answered with a non-zero result.
+    pub errors: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// Snapshot of the activity of a [`TAManager`](crate::TAManager), returned
+/// by [`TAManager::metrics`](crate::TAManager::metrics).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub open_sessions: usize,
+    pub sessions_opened: u64,
+    pub commands_invoked: u64,
+    /// Responses of any kind sent with a non-zero result.
+    pub errors: u64,
+    /// Requests refused for exceeding the rate limit.
+    pub rate_limited: u64,
+    /// Sessions refused for reaching `max_sessions` or
+    /// `max_sessions_per_client`.
+    pub sessions_refused: u64,
+    pub commands: BTreeMap<u32, CommandMetrics>,
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl Metrics {
+    pub(crate) fn session_opened(&self) {
+        self.inner.lock().unwrap().sessions_opened += 1;
+    }
+
+    pub(crate) fn session_refused(&self) {
+        self.inner.lock().unwrap().sessions_refused += 1;
+    }
+
+    pub(crate) fn command_invoked(&self, cmd_id: u32, latency: Duration, result: u32) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.commands_invoked += 1;
+        let command = inner.commands.entry(cmd_id).or_default();
+        command.invoked += 1;
+        if result != 0 {
+            command.errors += 1;
+        }
+        command.latency.record(latency);
+    }
+
+    pub(crate) fn request_rate_limited(&self) {
+        self.inner.lock().unwrap().rate_limited += 1;
+    }
+
+    pub(crate) fn response_sent(&self, result: u32) {
+        if result != 0 {
+            self.inner.lock().unwrap().errors += 1;
+        }
+    }
+
+    pub(crate) fn snapshot(&self, open_sessions: usize) -> MetricsSnapshot {
+        MetricsSnapshot {
+            open_sessions,
+            ..self.inner.lock().unwrap().clone()
+        }
+    }
+}
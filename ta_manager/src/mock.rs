@@ -0,0 +1,232 @@
+use std::{path::PathBuf, sync::Arc, thread};
+
+use crossbeam_channel::unbounded;
+use optee_utee::{Error, ErrorKind, ErrorOrigin, Result};
+use tracing::Span;
+
+use crate::TrustedApplication;
+use crate::client::response_error;
+use crate::config::{DEFAULT_SESSION_QUEUE_DEPTH, QueuePolicy};
+use crate::context::{CommandContext, OpenSessionContext};
+use crate::ipc::{current_gid, current_uid};
+use crate::notify::NotificationSender;
+use crate::peer::PeerCredentials;
+use crate::protocol::{ClientIdentity, Parameters, ReturnOrigin, TeeResponse};
+use crate::session::{SessionEnv, SessionMessage, SessionRunner, SessionTable, session_queue};
+use crate::supplicant::{Supplicant, SupplicantPlugin};
+
+/// In-process CA driving a [`TrustedApplication`] without sockets or a TA
+/// Manager server, e.g. to test its command handlers with `cargo test`.
+///
+/// ```
+/// # use ta_manager::{MockCaClient, OpenSessionContext, TrustedApplication, protocol::*};
+/// # struct Counter;
+/// # impl TrustedApplication for Counter {
+/// #     type SessionContext = u32;
+/// #     fn create(&self) -> optee_utee::Result<()> { Ok(()) }
+/// #     fn open_session(&self, _: &OpenSessionContext) -> optee_utee::Result<u32> { Ok(0) }
+/// #     fn close_session(&self, _: &mut u32) -> optee_utee::Result<()> { Ok(()) }
+/// #     fn destroy(&self) -> optee_utee::Result<()> { Ok(()) }
+/// #     fn invoke_command(&self, _: u32, p: &mut Parameters, c: &mut u32) -> optee_utee::Result<()> {
+/// #         *c += 1;
+/// #         p.0.param.values.a = *c;
+/// #         Ok(())
+/// #     }
+/// # }
+/// let mut client = MockCaClient::new(Counter);
+/// let session = client.open_session(Parameters::default(), ClientIdentity::default())?;
+/// client.invoke_command(session, 0, Parameters::default())?;
+/// let params = client.invoke_command(session, 0, Parameters::default())?;
+/// assert_eq!(params.0.param.values.a, 2);
+/// client.close_session(session)?;
+/// # Ok::<(), optee_utee::Error>(())
+/// ```
+///
+/// Sessions run on their own threads and receive their commands over
+/// channels, as under a [`TAManager`](crate::TAManager), so a TA that panics
+/// answers `TargetDead`. The instance is created when the first session
+/// opens and destroyed after the last one closes, or when the client is
+/// dropped.
+pub struct MockCaClient<T: TrustedApplication> {
+    ta: Arc<T>,
+    sessions: SessionTable,
+    supplicant: Arc<Supplicant>,
+    plugins: Vec<Arc<dyn SupplicantPlugin>>,
+    peer: PeerCredentials,
+    created: bool,
+    next_session_id: u32,
+}
+
+impl<T: TrustedApplication> MockCaClient<T> {
+    pub fn new(ta: T) -> Self {
+        let peer = PeerCredentials {
+            uid: current_uid(),
+            gid: current_gid(),
+            pid: std::process::id() as i32,
+            groups: Vec::new(),
+            label: None,
+        };
+        Self {
+            ta: Arc::new(ta),
+            sessions: SessionTable::default(),
+            supplicant: Arc::default(),
+            plugins: Vec::new(),
+            peer,
+            created: false,
+            next_session_id: 1,
+        }
+    }
+
+    /// Services the REE requests of the TA with `plugin`, after those added
+    /// before it. Requests no plugin services fail with `Communication`.
+    pub fn with_supplicant_plugin(mut self, plugin: impl SupplicantPlugin) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self.supplicant = Arc::new(Supplicant::new(
+            "mock",
+            self.plugins.clone(),
+            PathBuf::new(),
+            false,
+        ));
+        self
+    }
+
+    /// Sets the credentials passed to [`TrustedApplication::authorize`], by
+    /// default those of the current process.
+    pub fn with_peer(mut self, peer: PeerCredentials) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    /// Returns the TA driven by the client, e.g. to inspect its state.
+    pub fn ta(&self) -> &T {
+        &self.ta
+    }
+
+    /// Opens a session on the TA, creating its instance first if needed, and
+    /// returns its id. The client UUID is derived from the credentials of the
+    /// client, as a [`TAManager`](crate::TAManager) derives it.
+    pub fn open_session(&mut self, params: Parameters, identity: ClientIdentity) -> Result<u32> {
+        let identity = self.peer.identity(identity).map_err(|e| match e.kind() {
+            ErrorKind::BadParameters => e.with_origin(ErrorOrigin::Api),
+            _ => e.with_origin(ErrorOrigin::Tee),
+        })?;
+        self.ta.authorize(&self.peer)?;
+        if !self.created {
+            self.ta.create().map_err(ta_error)?;
+            self.created = true;
+        }
+
+        let ctx = OpenSessionContext::new(
+            params,
+            identity,
+            self.peer.clone(),
+            NotificationSender::default(),
+        );
+        let ctx = match self.ta.open_session(&ctx) {
+            Ok(ctx) => ctx,
+            Err(e) => {
+                self.release_instance_if_unused();
+                return Err(ta_error(e));
+            }
+        };
+        let session_id = self.next_session_id;
+        self.next_session_id = self.next_session_id.wrapping_add(1).max(1);
+        let (queue, rx) = session_queue(DEFAULT_SESSION_QUEUE_DEPTH, QueuePolicy::Block);
+        let ta = self.ta.clone();
+        let runner = SessionRunner::new(ta, ctx, rx, None, SessionEnv::new(Span::none()));
+        let thread = thread::spawn(move || runner.run());
+        self.sessions
+            .insert(session_id, queue, Some(thread), self.peer.clone(), identity);
+        Ok(session_id)
+    }
+
+    /// Invokes a command on a session and returns the parameters updated by
+    /// the TA.
+    ///
+    /// # Errors
+    ///
+    /// 1) `ItemNotFound`: If the session does not exist.
+    /// 2) `BadParameters`: If a parameter does not match its type, e.g. a
+    ///    value parameter carrying a buffer.
+    /// 3) `TargetDead`: If the TA panicked, which also ends the session.
+    /// 4) Errors returned by the TA.
+    pub fn invoke_command(
+        &mut self,
+        session_id: u32,
+        cmd_id: u32,
+        params: Parameters,
+    ) -> Result<Parameters> {
+        self.ta.authorize(&self.peer)?;
+        if params.malformed().is_some() {
+            return Err(Error::new(ErrorKind::BadParameters).with_origin(ErrorOrigin::Api));
+        }
+        let queue = self
+            .sessions
+            .sender(session_id)
+            .ok_or_else(|| Error::new(ErrorKind::ItemNotFound).with_origin(ErrorOrigin::Tee))?;
+
+        let (resp_tx, resp_rx) = unbounded();
+        let resp = match queue.send(SessionMessage::Invoke {
+            cmd_id,
+            params,
+            context: CommandContext::new(self.supplicant.clone()),
+            resp_tx,
+        }) {
+            Ok(_) => resp_rx.recv().ok(),
+            Err(_) => None,
+        };
+        match resp {
+            Some(TeeResponse::InvokeCommand {
+                params, result: 0, ..
+            }) => Ok(params),
+            Some(resp) => Err(response_error(resp.result(), ReturnOrigin::TrustedApp)),
+            None => {
+                self.sessions.remove(session_id);
+                self.release_instance_if_unused();
+                Err(Error::new(ErrorKind::TargetDead).with_origin(ErrorOrigin::Comms))
+            }
+        }
+    }
+
+    /// Closes a session, and destroys the TA instance if it was the last one.
+    pub fn close_session(&mut self, session_id: u32) -> Result<()> {
+        self.ta.authorize(&self.peer)?;
+        let resp = self
+            .sessions
+            .close(session_id)
+            .ok_or_else(|| Error::new(ErrorKind::ItemNotFound).with_origin(ErrorOrigin::Tee))?;
+        self.release_instance_if_unused();
+        match resp {
+            TeeResponse::CloseSession { result: 0, .. } => Ok(()),
+            TeeResponse::CloseSession { result, origin } => Err(response_error(result, origin)),
+            _ => Err(Error::new(ErrorKind::BadFormat).with_origin(ErrorOrigin::Comms)),
+        }
+    }
+
+    /// Returns the sessions currently open, e.g. to evict them as a manager
+    /// would.
+    pub fn session_table(&self) -> SessionTable {
+        self.sessions.clone()
+    }
+
+    fn release_instance_if_unused(&mut self) {
+        if self.created && self.sessions.is_empty() {
+            // Like the manager, a failure to destroy only ends the instance.
+            let _ = self.ta.destroy();
+            self.created = false;
+        }
+    }
+}
+
+impl<T: TrustedApplication> Drop for MockCaClient<T> {
+    fn drop(&mut self) {
+        for info in self.sessions.list() {
+            self.sessions.close(info.session_id);
+        }
+        self.release_instance_if_unused();
+    }
+}
+
+fn ta_error(e: Error) -> Error {
+    e.with_origin(ErrorOrigin::Ta)
+}
@@ -0,0 +1,113 @@
+use std::{path::PathBuf, sync::Arc, thread};
+
+use tracing::error;
+
+use crate::control::{self, Controlled};
+use crate::{ManagerError, SessionTable, TAManager, TAManagerConfig, TrustedApplication};
+
+struct HostedTA {
+    uuid: String,
+    sessions: SessionTable,
+    controlled: Arc<dyn Controlled>,
+    run: Box<dyn FnOnce() -> Result<(), ManagerError> + Send>,
+}
+
+/// Hosts several TAs in one process.
+///
+/// Every TA is served by its own [`TAManager`]: it is registered under its
+/// UUID and listens on its own socket, so CAs reach it exactly as if it ran
+/// alone.
+#[derive(Default)]
+pub struct MultiTAManager {
+    tas: Vec<HostedTA>,
+    control_socket: Option<PathBuf>,
+}
+
+impl MultiTAManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a TA served under `uuid` with the default configuration.
+    pub fn add<T: TrustedApplication>(&mut self, ta: T, uuid: &str) -> Result<(), ManagerError> {
+        self.add_with_config(ta, uuid, TAManagerConfig::default())
+    }
+
+    /// Adds a TA served under `uuid`.
+    ///
+    /// Fails if a TA was already added under `uuid`.
+    pub fn add_with_config<T: TrustedApplication>(
+        &mut self,
+        ta: T,
+        uuid: &str,
+        config: TAManagerConfig,
+    ) -> Result<(), ManagerError> {
+        if self.tas.iter().any(|hosted| hosted.uuid == uuid) {
+            return Err(ManagerError::AlreadyHosted(uuid.to_string()));
+        }
+
+        let mut manager = TAManager::with_config(ta, uuid, config);
+        self.tas.push(HostedTA {
+            uuid: uuid.to_string(),
+            sessions: manager.session_table(),
+            controlled: manager.controlled(),
+            run: Box::new(move || manager.run_ta()),
+        });
+        Ok(())
+    }
+
+    /// Serves every TA on the control socket at `path` while
+    /// [`run`](Self::run) runs, like
+    /// [`TAManagerConfig::with_control_socket`] does for a single TA.
+    pub fn with_control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Returns the session table of the TA hosted under `uuid`.
+    pub fn session_table(&self, uuid: &str) -> Option<SessionTable> {
+        self.tas
+            .iter()
+            .find(|hosted| hosted.uuid == uuid)
+            .map(|hosted| hosted.sessions.clone())
+    }
+
+    /// Runs every TA on its own thread until all of them stop, and returns
+    /// the first error one of them stopped with.
+    pub fn run(self) -> Result<(), ManagerError> {
+        let _control = self
+            .control_socket
+            .as_ref()
+            .map(|path| {
+                let tas = self.tas.iter().map(|hosted| hosted.controlled.clone());
+                control::listen(path, tas.collect())
+            })
+            .transpose()?;
+        let handles: Vec<_> = self
+            .tas
+            .into_iter()
+            .map(|hosted| {
+                let uuid = hosted.uuid;
+                let handle = thread::Builder::new().name(uuid.clone()).spawn(hosted.run);
+                (uuid, handle)
+            })
+            .collect();
+
+        let mut result = Ok(());
+        for (uuid, handle) in handles {
+            let outcome = match handle {
+                Ok(handle) => handle
+                    .join()
+                    .unwrap_or_else(|_| Err(ManagerError::Panicked)),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = outcome {
+                error!(uuid = %uuid, error = ?e, "TA stopped");
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError, bounded};
+use tracing::warn;
+
+// Notifications queued for a subscriber that does not keep up, past which
+// it is dropped.
+const SUBSCRIBER_QUEUE_DEPTH: usize = 64;
+
+// Event id and data of a notification.
+type Notification = (u32, Vec<u8>);
+
+/// Handle through which a TA pushes events to the CAs subscribed to it with
+/// [`TeeRequest::Subscribe`](crate::protocol::TeeRequest::Subscribe), e.g.
+/// to tell them a key was rotated rather than have them poll for it.
+///
+/// Handed to
+/// [`TrustedApplication::open_session`](crate::TrustedApplication::open_session)
+/// through [`OpenSessionContext::notifications`](crate::OpenSessionContext::notifications)
+/// and available from
+/// [`CommandContext::notifications`](crate::CommandContext::notifications).
+/// Every handle of a manager reaches the same subscribers, so a TA may keep
+/// one, e.g. in a background thread of its own.
+#[derive(Clone, Default)]
+pub struct NotificationSender {
+    subscribers: Arc<Mutex<Vec<Sender<Notification>>>>,
+}
+
+impl NotificationSender {
+    /// Sends the event `event`, carrying `data`, to every subscribed CA and
+    /// returns how many it was queued for.
+    ///
+    /// A subscriber that has 64 notifications waiting to be written is
+    /// dropped instead, ending its subscription, so that a CA that stopped
+    /// reading cannot hold memory on the manager.
+    pub fn notify(&self, event: u32, data: &[u8]) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(
+            |subscriber| match subscriber.try_send((event, data.to_vec())) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!(event, "Dropping a subscriber that does not keep up");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        );
+        subscribers.len()
+    }
+
+    // Returns the notifications sent from now on, until the receiver is
+    // dropped or falls behind.
+    pub(crate) fn subscribe(&self) -> Receiver<Notification> {
+        let (tx, rx) = bounded(SUBSCRIBER_QUEUE_DEPTH);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
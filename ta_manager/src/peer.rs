@@ -0,0 +1,337 @@
+use std::{fmt, io, sync::Arc};
+#[cfg(target_os = "linux")]
+use std::{mem, os::unix::io::AsRawFd};
+
+use optee_utee::{Error, ErrorKind, Identity, LoginType, Uuid};
+use sha1::{Digest, Sha1};
+
+use crate::ipc::{IpcStream, current_uid};
+use crate::protocol::ClientIdentity;
+
+// Size first tried for a security label, enough for most policies.
+#[cfg(target_os = "linux")]
+const LABEL_SIZE: usize = 256;
+
+// Number of supplementary groups first tried, enough for most users.
+#[cfg(target_os = "linux")]
+const GROUPS_SIZE: usize = 32;
+
+// Namespace of the client UUIDs derived from credentials, the one the Linux
+// TEE subsystem derives them in.
+const CLIENT_UUID_NAMESPACE: [u8; 16] = [
+    0x58, 0xac, 0x9c, 0xa0, 0x20, 0x86, 0x46, 0x83, 0xa1, 0xb8, 0xec, 0x4b, 0xc0, 0x8e, 0x01, 0xb6,
+];
+
+/// Credentials of the process at the other end of a CA connection, as
+/// reported by the kernel through `SO_PEERCRED` when it connected.
+///
+/// On Windows, `uid`, `gid` and `groups` are the relative ids of the user,
+/// of the primary group and of the enabled groups of the client of the pipe,
+/// the last sub-authority of their SIDs, and the process has no label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32,
+    /// Supplementary groups of the process when it connected, as reported
+    /// through `SO_PEERGROUPS`. Empty on kernels older than 4.13.
+    pub groups: Vec<u32>,
+    /// Security label of the process, as reported through `SO_PEERSEC`.
+    /// `None` when no Linux Security Module labels sockets, or when the
+    /// connection was not made through a socket.
+    pub label: Option<SecurityLabel>,
+}
+
+impl PeerCredentials {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn from_stream(stream: &IpcStream) -> io::Result<Self> {
+        let mut cred: libc::ucred = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: `cred` and `len` describe a buffer of the size expected
+        // for SO_PEERCRED, and the fd stays open for the whole call.
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            uid: cred.uid,
+            gid: cred.gid,
+            pid: cred.pid,
+            groups: peer_groups(stream)?,
+            label: SecurityLabel::from_stream(stream)?,
+        })
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn from_stream(stream: &IpcStream) -> io::Result<Self> {
+        let (uid, gid, groups) = stream.client_ids()?;
+        Ok(Self {
+            uid,
+            gid,
+            pid: stream.client_process_id()? as i32,
+            groups,
+            label: None,
+        })
+    }
+
+    // Returns whether the peer is the process `pid`, running as `uid`.
+    pub(crate) fn is_process(&self, uid: u32, pid: i32) -> bool {
+        self.uid == uid && self.pid == pid
+    }
+
+    // Returns the identity of the peer logging in as `client`, whose client
+    // UUID is derived from the credentials rather than taken from the CA:
+    // from the uid for `User` and the gid for `Group`, as the Linux TEE
+    // subsystem derives them, and from the security label for the
+    // `Application` logins. Only TA managers, running as the same user as
+    // this one, may log in as `TrustedApp` with the UUID of their TA.
+    //
+    // Fails with `BadParameters` if the login type is not valid, and with
+    // `AccessDenied` if the credentials do not support it.
+    pub(crate) fn identity(&self, client: ClientIdentity) -> optee_utee::Result<Identity> {
+        let login = LoginType::try_from(client.login)?;
+        let name = match (login, &self.label) {
+            (LoginType::Public, _) => return Ok(Identity::new(login, Uuid::from_bytes([0; 16]))),
+            (LoginType::TrustedApp, _) if self.uid == current_uid() => {
+                return Ok(Identity::new(login, Uuid::from_bytes(client.uuid)));
+            }
+            (LoginType::User, _) => format!("uid={:x}", self.uid),
+            (LoginType::Group, _) => format!("gid={:x}", self.gid),
+            (LoginType::Application, Some(label)) => format!("app={}", label),
+            (LoginType::ApplicationUser, Some(label)) => {
+                format!("app={}:uid={:x}", label, self.uid)
+            }
+            (LoginType::ApplicationGroup, Some(label)) => {
+                format!("app={}:gid={:x}", label, self.gid)
+            }
+            _ => return Err(Error::new(ErrorKind::AccessDenied)),
+        };
+        Ok(Identity::new(login, Uuid::from_bytes(client_uuid(&name))))
+    }
+}
+
+// Reads the supplementary groups the peer of `stream` had when it
+// connected, which unlike those in procfs cannot change or be those of
+// another process reusing its pid.
+#[cfg(target_os = "linux")]
+fn peer_groups(stream: &IpcStream) -> io::Result<Vec<u32>> {
+    const GID_SIZE: usize = mem::size_of::<libc::gid_t>();
+    let mut groups: Vec<libc::gid_t> = vec![0; GROUPS_SIZE];
+    loop {
+        let mut len = (groups.len() * GID_SIZE) as libc::socklen_t;
+        // SAFETY: `groups` and `len` describe a writable buffer, and the fd
+        // stays open for the whole call.
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERGROUPS,
+                groups.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            groups.truncate(len as usize / GID_SIZE);
+            return Ok(groups);
+        }
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            // The kernel reports the size it needs in `len`.
+            Some(libc::ERANGE) if len as usize > groups.len() * GID_SIZE => {
+                groups.resize(len as usize / GID_SIZE, 0)
+            }
+            Some(libc::ENOPROTOOPT) => return Ok(Vec::new()),
+            _ => return Err(e),
+        }
+    }
+}
+
+// Returns the name-based (version 5) UUID of `name` in the namespace of
+// client UUIDs.
+fn client_uuid(name: &str) -> [u8; 16] {
+    let hash = Sha1::new()
+        .chain_update(CLIENT_UUID_NAMESPACE)
+        .chain_update(name)
+        .finalize();
+    let mut uuid = [0u8; 16];
+    uuid.copy_from_slice(&hash[..16]);
+    uuid[6] = (uuid[6] & 0x0f) | 0x50;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid
+}
+
+/// Security context of a process under a Linux Security Module, e.g.
+/// `system_u:system_r:httpd_t:s0` under SELinux or `/usr/bin/app (enforce)`
+/// under AppArmor.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SecurityLabel(Arc<str>);
+
+impl SecurityLabel {
+    pub fn new(label: impl Into<Arc<str>>) -> Self {
+        Self(label.into())
+    }
+
+    // Reads the label of the peer of `stream`. Returns `None` if no module
+    // provides one.
+    #[cfg(target_os = "linux")]
+    fn from_stream(stream: &IpcStream) -> io::Result<Option<Self>> {
+        let mut buf = vec![0u8; LABEL_SIZE];
+        loop {
+            let mut len = buf.len() as libc::socklen_t;
+            // SAFETY: `buf` and `len` describe a writable buffer, and the fd
+            // stays open for the whole call.
+            let ret = unsafe {
+                libc::getsockopt(
+                    stream.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_PEERSEC,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            if ret == 0 {
+                buf.truncate(len as usize);
+                break;
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                // The kernel reports the size it needs in `len`.
+                Some(libc::ERANGE) if len as usize > buf.len() => buf.resize(len as usize, 0),
+                Some(libc::ENOPROTOOPT) => return Ok(None),
+                _ => return Err(e),
+            }
+        }
+        // Labels are usually NUL-terminated.
+        while buf.last() == Some(&0) {
+            buf.pop();
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(String::from_utf8_lossy(&buf))))
+    }
+
+    /// Returns the whole label.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the domain the process runs in: the type of an SELinux
+    /// context, the profile of an AppArmor label, or the whole label for
+    /// other modules.
+    pub fn domain(&self) -> &str {
+        if let Some(profile) = self.0.strip_suffix(')').and_then(|s| s.rsplit_once(" (")) {
+            return profile.0;
+        }
+        let mut fields = self.0.splitn(4, ':');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(_), Some(_), Some(domain)) => domain,
+            _ => &self.0,
+        }
+    }
+}
+
+impl fmt::Display for SecurityLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    fn peer(label: Option<&str>) -> PeerCredentials {
+        PeerCredentials {
+            uid: 1000,
+            gid: 100,
+            pid: 42,
+            groups: Vec::new(),
+            label: label.map(SecurityLabel::new),
+        }
+    }
+
+    // Logs in as `login`, claiming an arbitrary client UUID.
+    fn login(login: LoginType) -> ClientIdentity {
+        ClientIdentity {
+            login: login as u32,
+            uuid: [0xAA; 16],
+        }
+    }
+
+    #[test]
+    fn identity_comes_from_credentials() {
+        let invalid = ClientIdentity {
+            login: 3,
+            uuid: [0; 16],
+        };
+        let refused = peer(None).identity(invalid).err().unwrap();
+        assert_eq!(refused.kind(), ErrorKind::BadParameters);
+        let uuid = |type_| peer(None).identity(login(type_)).unwrap().uuid().to_bytes();
+        assert_eq!(uuid(LoginType::Public), [0; 16]);
+        // As the Linux TEE subsystem derives them.
+        assert_eq!(
+            Uuid::from_bytes(uuid(LoginType::User)).to_string(),
+            "fc34275c-d8dd-5ba7-968b-504718363b87"
+        );
+        assert_eq!(
+            Uuid::from_bytes(uuid(LoginType::Group)).to_string(),
+            "d32f761e-b364-519b-be12-ed54ba663b0a"
+        );
+    }
+
+    #[test]
+    fn application_logins_need_a_label() {
+        for type_ in [
+            LoginType::Application,
+            LoginType::ApplicationUser,
+            LoginType::ApplicationGroup,
+        ] {
+            let refused = peer(None).identity(login(type_)).err().unwrap();
+            assert_eq!(refused.kind(), ErrorKind::AccessDenied);
+            let labelled = peer(Some("/usr/bin/app (enforce)")).identity(login(type_));
+            let labelled = labelled.unwrap();
+            assert_eq!(labelled.login_type(), type_);
+            assert_ne!(labelled.uuid().to_bytes(), [0xAA; 16]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn groups_come_from_the_socket() {
+        let (ca, _manager) = std::os::unix::net::UnixStream::pair().unwrap();
+        // SAFETY: only asks for the number of groups, then fills a buffer
+        // that large.
+        let mut groups = vec![0; unsafe { libc::getgroups(0, ptr::null_mut()) } as usize];
+        let len = unsafe { libc::getgroups(groups.len() as i32, groups.as_mut_ptr()) };
+        groups.truncate(len as usize);
+        let peer = PeerCredentials::from_stream(&ca).unwrap();
+        assert_eq!(peer.pid, std::process::id() as i32);
+        assert_eq!(peer.groups, groups);
+    }
+
+    #[test]
+    fn trusted_app_logins_come_from_managers() {
+        let mut manager = peer(None);
+        manager.uid = current_uid();
+        let identity = manager.identity(login(LoginType::TrustedApp)).unwrap();
+        assert_eq!(identity.uuid().to_bytes(), [0xAA; 16]);
+        manager.uid += 1;
+        let refused = manager
+            .identity(login(LoginType::TrustedApp))
+            .err()
+            .unwrap();
+        assert_eq!(refused.kind(), ErrorKind::AccessDenied);
+    }
+}
@@ -0,0 +1,533 @@
+//! Named pipes, standing in for Unix sockets on Windows hosts, see
+//! [`ipc`](crate::ipc).
+//!
+//! A listener keeps one instance of its pipe waiting for a client, and
+//! creates the next one as soon as a client connects to it. Pipes refuse
+//! remote clients and keep the default security of named pipes, under which
+//! only the user running the manager, administrators and LocalSystem may
+//! open them for writing. Handles are opened for overlapped I/O, so that a
+//! listener shut down from another thread stops waiting for clients.
+
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::Shutdown,
+    path::Path,
+    ptr, slice,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use windows_sys::Win32::{
+    Foundation::{
+        BOOL, CloseHandle, ERROR_BROKEN_PIPE, ERROR_IO_PENDING, ERROR_PIPE_BUSY,
+        ERROR_PIPE_CONNECTED, FALSE, GENERIC_READ, GENERIC_WRITE, HANDLE, INVALID_HANDLE_VALUE,
+        TRUE, WAIT_OBJECT_0, WAIT_TIMEOUT,
+    },
+    Security::{
+        GetSidSubAuthority, GetSidSubAuthorityCount, GetTokenInformation, PSID, RevertToSelf,
+        SID_AND_ATTRIBUTES, TOKEN_GROUPS, TOKEN_INFORMATION_CLASS, TOKEN_PRIMARY_GROUP,
+        TOKEN_QUERY, TOKEN_USER, TokenGroups, TokenPrimaryGroup, TokenUser,
+    },
+    Storage::FileSystem::{
+        CreateFileW, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, OPEN_EXISTING,
+        PIPE_ACCESS_DUPLEX, ReadFile, SECURITY_IDENTIFICATION, SECURITY_SQOS_PRESENT, WriteFile,
+    },
+    System::{
+        IO::{CancelIoEx, GetOverlappedResult, OVERLAPPED},
+        Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, GetNamedPipeClientProcessId,
+            ImpersonateNamedPipeClient, NMPWAIT_USE_DEFAULT_WAIT, PIPE_READMODE_BYTE,
+            PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            PeekNamedPipe, WaitNamedPipeW,
+        },
+        Threading::{
+            CreateEventW, GetCurrentProcess, GetCurrentThread, INFINITE, OpenProcessToken,
+            OpenThreadToken, SetEvent, WaitForMultipleObjects,
+        },
+    },
+};
+
+// Namespace of the pipes of the local machine.
+const PIPE_NAMESPACE: &str = r"\\.\pipe\";
+
+// Size of the buffers of a pipe instance, in each direction.
+const BUFFER_SIZE: u32 = 64 * 1024;
+
+// Attribute of the groups of a token that are enabled, from winnt.h.
+const SE_GROUP_ENABLED: u32 = 0x4;
+
+/// Name of a pipe, e.g. `\\.\pipe\C:/Users/app/ta_manager/<uuid>.sock`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct PipeAddr(String);
+
+impl PipeAddr {
+    // Names the pipe standing for the socket `path`. Backslashes are not
+    // allowed in pipe names, and become slashes.
+    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+        let path = path.to_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "pipe names must be Unicode")
+        })?;
+        Ok(Self(format!(
+            "{}{}",
+            PIPE_NAMESPACE,
+            path.replace('\\', "/")
+        )))
+    }
+
+    // Returns the name NUL-terminated, as Windows takes it.
+    fn to_wide(&self) -> Vec<u16> {
+        self.0.encode_utf16().chain(Some(0)).collect()
+    }
+}
+
+// Handle closed when dropped.
+struct Handle(HANDLE);
+
+// SAFETY: kernel handles may be used and closed from any thread.
+unsafe impl Send for Handle {}
+unsafe impl Sync for Handle {}
+
+impl Handle {
+    fn new(handle: HANDLE) -> io::Result<Self> {
+        if handle.is_null() || handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: `self` owns the handle, which is open.
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+// Returns a new manual-reset event, not signalled.
+fn event() -> io::Result<Handle> {
+    // SAFETY: an unnamed event with default security.
+    Handle::new(unsafe { CreateEventW(ptr::null(), TRUE, FALSE, ptr::null()) })
+}
+
+// Returns a new `OVERLAPPED` signalling `event` once its operation
+// completes.
+fn overlapped(event: &Handle) -> OVERLAPPED {
+    // SAFETY: `OVERLAPPED` is plain data, and all zeroes before an
+    // operation starts.
+    let mut overlapped: OVERLAPPED = unsafe { mem::zeroed() };
+    overlapped.hEvent = event.0;
+    overlapped
+}
+
+// Starts an operation on `handle` with `start` and waits for it to complete.
+// Returns the number of bytes transferred.
+fn wait_for(handle: HANDLE, start: impl FnOnce(*mut OVERLAPPED) -> BOOL) -> io::Result<u32> {
+    let event = event()?;
+    let mut overlapped = overlapped(&event);
+    if start(&mut overlapped) == FALSE {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+            return Err(e);
+        }
+    }
+    let mut transferred = 0;
+    // SAFETY: `overlapped` outlives the operation, which is waited for.
+    if unsafe { GetOverlappedResult(handle, &overlapped, &mut transferred, TRUE) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(transferred)
+}
+
+/// Server end of a pipe a client connected to, or client end of a pipe.
+pub(crate) struct PipeStream {
+    handle: Handle,
+    read_shut: AtomicBool,
+    write_shut: AtomicBool,
+}
+
+impl PipeStream {
+    fn new(handle: Handle) -> Self {
+        Self {
+            handle,
+            read_shut: AtomicBool::new(false),
+            write_shut: AtomicBool::new(false),
+        }
+    }
+
+    /// Connects to the pipe `addr`, waiting for the listener to create an
+    /// instance if all of them are taken.
+    pub(crate) fn connect(addr: &PipeAddr) -> io::Result<Self> {
+        let name = addr.to_wide();
+        loop {
+            // SAFETY: `name` is NUL-terminated. The server may only identify
+            // the client, not act on its behalf.
+            let handle = unsafe {
+                CreateFileW(
+                    name.as_ptr(),
+                    GENERIC_READ | GENERIC_WRITE,
+                    0,
+                    ptr::null(),
+                    OPEN_EXISTING,
+                    FILE_FLAG_OVERLAPPED | SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION,
+                    ptr::null_mut(),
+                )
+            };
+            match Handle::new(handle) {
+                Ok(handle) => return Ok(Self::new(handle)),
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {}
+                Err(e) => return Err(e),
+            }
+            // SAFETY: `name` is NUL-terminated.
+            if unsafe { WaitNamedPipeW(name.as_ptr(), NMPWAIT_USE_DEFAULT_WAIT) } == FALSE {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+
+    /// Stops reading and/or writing on the stream: reads then find the end
+    /// of the stream, and writes fail. The other end sees the pipe closed
+    /// once the stream is dropped.
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.read_shut.store(true, Ordering::Release);
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.write_shut.store(true, Ordering::Release);
+        }
+        // SAFETY: the handle is open. Cancels the operations in flight.
+        unsafe { CancelIoEx(self.handle.0, ptr::null()) };
+        Ok(())
+    }
+
+    /// Returns whether the other end closed the pipe, leaving what it sent
+    /// unread.
+    pub(crate) fn hung_up(&self) -> bool {
+        let mut available = 0;
+        // SAFETY: only counts the bytes available, without reading them.
+        let peeked = unsafe {
+            PeekNamedPipe(
+                self.handle.0,
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut available,
+                ptr::null_mut(),
+            )
+        };
+        peeked == FALSE
+            && io::Error::last_os_error().raw_os_error() == Some(ERROR_BROKEN_PIPE as i32)
+    }
+
+    /// Returns the id of the client process of a server end.
+    pub(crate) fn client_process_id(&self) -> io::Result<u32> {
+        let mut pid = 0;
+        // SAFETY: the handle is open and `pid` is writable.
+        if unsafe { GetNamedPipeClientProcessId(self.handle.0, &mut pid) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(pid)
+    }
+
+    /// Returns the relative ids of the user, of the primary group and of
+    /// the enabled groups of the client of a server end, from the token of
+    /// the client rather than that of its process, whose id may have been
+    /// reused.
+    pub(crate) fn client_ids(&self) -> io::Result<(u32, u32, Vec<u32>)> {
+        // SAFETY: the handle is open. The thread identifies as the client
+        // only until `RevertToSelf`.
+        if unsafe { ImpersonateNamedPipeClient(self.handle.0) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        let mut token = ptr::null_mut();
+        // SAFETY: `token` is writable. The token is opened with the rights
+        // of the manager, not those of the client.
+        let opened = unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, TRUE, &mut token) };
+        let e = io::Error::last_os_error();
+        // SAFETY: ends the impersonation. A thread that cannot act as the
+        // manager again must not run anything else.
+        if unsafe { RevertToSelf() } == FALSE {
+            std::process::abort();
+        }
+        if opened == FALSE {
+            return Err(e);
+        }
+        let token = Handle::new(token)?;
+        let (uid, gid) = token_ids(&token)?;
+        Ok((uid, gid, token_groups(&token)?))
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_shut.load(Ordering::Acquire) {
+            return Ok(0);
+        }
+        let handle = self.handle.0;
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        // SAFETY: `buf` is writable for `len` bytes until the read completes.
+        let read = wait_for(handle, |overlapped| unsafe {
+            ReadFile(handle, buf.as_mut_ptr(), len, ptr::null_mut(), overlapped)
+        });
+        match read {
+            Ok(read) => Ok(read as usize),
+            // The other end closed the pipe.
+            Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_shut.load(Ordering::Acquire) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let handle = self.handle.0;
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        // SAFETY: `buf` is readable for `len` bytes until the write completes.
+        let written = wait_for(handle, |overlapped| unsafe {
+            WriteFile(handle, buf.as_ptr(), len, ptr::null_mut(), overlapped)
+        })?;
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Pipe accepting clients, the instances of which are created by the
+/// listener and its clones.
+pub(crate) struct PipeListener {
+    inner: Arc<ListenerInner>,
+}
+
+struct ListenerInner {
+    addr: PipeAddr,
+    name: Vec<u16>,
+    // Instance waiting for the next client. Locked while a client is
+    // awaited, so that a single thread accepts at a time.
+    instance: Mutex<Option<Handle>>,
+    // Signalled once the listener is shut down.
+    stop: Handle,
+}
+
+impl PipeListener {
+    /// Creates the pipe `addr`. Fails if another listener serves the same
+    /// name, as binding a socket in use does.
+    pub(crate) fn bind(addr: &PipeAddr) -> io::Result<Self> {
+        let name = addr.to_wide();
+        let instance = create_instance(&name, true)?;
+        Ok(Self {
+            inner: Arc::new(ListenerInner {
+                addr: addr.clone(),
+                name,
+                instance: Mutex::new(Some(instance)),
+                stop: event()?,
+            }),
+        })
+    }
+
+    /// Waits for a client to connect, and returns the server end of its
+    /// pipe.
+    pub(crate) fn accept(&self) -> io::Result<(PipeStream, PipeAddr)> {
+        self.accept_within(None)
+            .map(|accepted| accepted.expect("accepts without timeout"))
+    }
+
+    /// Waits up to `timeout` for a client to connect, and returns the server
+    /// end of its pipe, or `None` if none came.
+    pub(crate) fn accept_timeout(
+        &self,
+        timeout: Duration,
+    ) -> io::Result<Option<(PipeStream, PipeAddr)>> {
+        self.accept_within(Some(timeout))
+    }
+
+    fn accept_within(
+        &self,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<(PipeStream, PipeAddr)>> {
+        let inner = &self.inner;
+        let mut instance = inner.instance.lock().unwrap();
+        let pipe = match instance.take() {
+            Some(pipe) => pipe,
+            None => create_instance(&inner.name, false)?,
+        };
+        if !inner.connect(&pipe, timeout)? {
+            // The instance keeps waiting for the next accept.
+            *instance = Some(pipe);
+            return Ok(None);
+        }
+        // Clients find the pipe while the stream is served. Failing to
+        // create the instance now leaves it to the next accept.
+        *instance = create_instance(&inner.name, false).ok();
+        Ok(Some((PipeStream::new(pipe), inner.addr.clone())))
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<PipeAddr> {
+        Ok(self.inner.addr.clone())
+    }
+
+    /// Returns a listener accepting clients of the same pipe.
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::clone(&self.inner),
+        })
+    }
+
+    /// Makes the listener and its clones fail to accept clients from now
+    /// on, waking the thread waiting for one up.
+    pub(crate) fn shutdown(&self) {
+        // SAFETY: the event is open.
+        unsafe { SetEvent(self.inner.stop.0) };
+    }
+}
+
+impl ListenerInner {
+    // Waits up to `timeout`, if any, for a client to connect to `pipe`,
+    // unless the listener is shut down first. Returns whether a client
+    // connected.
+    fn connect(&self, pipe: &Handle, timeout: Option<Duration>) -> io::Result<bool> {
+        let event = event()?;
+        let mut overlapped = overlapped(&event);
+        // SAFETY: `overlapped` outlives the operation, which is waited for
+        // or cancelled and waited for.
+        if unsafe { ConnectNamedPipe(pipe.0, &mut overlapped) } == FALSE {
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(code) if code == ERROR_IO_PENDING as i32 => {}
+                // The client connected before the call.
+                Some(code) if code == ERROR_PIPE_CONNECTED as i32 => return Ok(true),
+                _ => return Err(e),
+            }
+        }
+        let events = [event.0, self.stop.0];
+        let millis = timeout.map_or(INFINITE, |timeout| {
+            timeout.as_millis().min(u128::from(INFINITE - 1)) as u32
+        });
+        // SAFETY: both events are open.
+        let signalled = unsafe { WaitForMultipleObjects(2, events.as_ptr(), FALSE, millis) };
+        let mut transferred = 0;
+        if signalled != WAIT_OBJECT_0 {
+            // SAFETY: cancels the operation started above, which completes
+            // before `overlapped` is dropped.
+            let connected = unsafe {
+                CancelIoEx(pipe.0, &overlapped);
+                GetOverlappedResult(pipe.0, &overlapped, &mut transferred, TRUE)
+            } != FALSE;
+            // A client may have connected before the operation was
+            // cancelled.
+            if signalled == WAIT_TIMEOUT {
+                return Ok(connected);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "the listener was shut down",
+            ));
+        }
+        // SAFETY: the operation completed.
+        if unsafe { GetOverlappedResult(pipe.0, &overlapped, &mut transferred, FALSE) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(true)
+    }
+}
+
+// Creates an instance of the pipe `name`, the first one with `first`.
+fn create_instance(name: &[u16], first: bool) -> io::Result<Handle> {
+    let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+    if first {
+        open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+    }
+    // SAFETY: `name` is NUL-terminated, and the pipe gets default security.
+    Handle::new(unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            ptr::null(),
+        )
+    })
+}
+
+/// Returns the relative ids of the user and of the primary group the
+/// manager runs as.
+pub(crate) fn current_ids() -> io::Result<(u32, u32)> {
+    let mut token = ptr::null_mut();
+    // SAFETY: `token` is writable, and the process handle needs no closing.
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    token_ids(&Handle::new(token)?)
+}
+
+// Returns the relative ids of the user and of the primary group of `token`.
+fn token_ids(token: &Handle) -> io::Result<(u32, u32)> {
+    let user = token_information(token, TokenUser)?;
+    let group = token_information(token, TokenPrimaryGroup)?;
+    // SAFETY: the buffers hold the structures of their information class,
+    // whose SIDs point within the buffers.
+    unsafe {
+        let user = (*(user.as_ptr() as *const TOKEN_USER)).User.Sid;
+        let group = (*(group.as_ptr() as *const TOKEN_PRIMARY_GROUP)).PrimaryGroup;
+        Ok((relative_id(user), relative_id(group)))
+    }
+}
+
+// Returns the relative ids of the enabled groups of `token`.
+fn token_groups(token: &Handle) -> io::Result<Vec<u32>> {
+    let groups = token_information(token, TokenGroups)?;
+    // SAFETY: the buffer holds a `TOKEN_GROUPS` followed by the rest of its
+    // `GroupCount` entries, whose SIDs point within the buffer.
+    unsafe {
+        let groups = groups.as_ptr() as *const TOKEN_GROUPS;
+        let entries: &[SID_AND_ATTRIBUTES] = slice::from_raw_parts(
+            ptr::addr_of!((*groups).Groups).cast(),
+            (*groups).GroupCount as usize,
+        );
+        Ok(entries
+            .iter()
+            .filter(|entry| entry.Attributes & SE_GROUP_ENABLED != 0)
+            .map(|entry| relative_id(entry.Sid))
+            .collect())
+    }
+}
+
+// Reads the information `class` of `token`, in a buffer aligned for the
+// structure holding it.
+fn token_information(token: &Handle, class: TOKEN_INFORMATION_CLASS) -> io::Result<Vec<u64>> {
+    let mut len = 0;
+    // SAFETY: only asks for the size of the information.
+    unsafe { GetTokenInformation(token.0, class, ptr::null_mut(), 0, &mut len) };
+    let mut buf = vec![0u64; (len as usize).div_ceil(mem::size_of::<u64>())];
+    // SAFETY: `buf` is writable for at least `len` bytes.
+    let ret =
+        unsafe { GetTokenInformation(token.0, class, buf.as_mut_ptr().cast(), len, &mut len) };
+    if ret == FALSE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(buf)
+}
+
+// Returns the last sub-authority of `sid`, which tells apart the accounts
+// of a machine or domain.
+//
+// SAFETY: `sid` must point to a valid SID.
+unsafe fn relative_id(sid: PSID) -> u32 {
+    unsafe {
+        let count = *GetSidSubAuthorityCount(sid);
+        match count {
+            0 => 0,
+            count => *GetSidSubAuthority(sid, count as u32 - 1),
+        }
+    }
+}
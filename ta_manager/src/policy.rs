@@ -0,0 +1,61 @@
+use optee_utee::{LoginType, Result};
+
+use crate::authz::{self, Action, Authorizer, Subject};
+
+/// Access-control policy checked by a [`TAManager`](crate::TAManager) before
+/// asking the TA to open a session.
+///
+/// The default policy lets everyone in. Once a uid or a gid is allowed, only
+/// callers running as an allowed uid or belonging to an allowed group, as
+/// primary or supplementary group, may open sessions.
+///
+/// The policy is also an [`Authorizer`](crate::Authorizer) checking the
+/// sessions opened, e.g. to combine it with other authorizers, decided by
+/// the rules of a [`PolicyAuthorizer`](crate::PolicyAuthorizer) it stands
+/// for.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    pub allowed_uids: Vec<u32>,
+    pub allowed_gids: Vec<u32>,
+    /// Login types CAs may open sessions with. Empty allows all of them.
+    pub allowed_login_types: Vec<LoginType>,
+    /// Security labels CAs may open sessions with, each matching a whole
+    /// label or its [domain](crate::SecurityLabel::domain). Empty allows
+    /// any label, including none, while otherwise CAs without a label are
+    /// refused.
+    pub allowed_labels: Vec<String>,
+}
+
+impl AccessPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.allowed_uids.push(uid);
+        self
+    }
+
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.allowed_gids.push(gid);
+        self
+    }
+
+    pub fn allow_login_type(mut self, login_type: LoginType) -> Self {
+        self.allowed_login_types.push(login_type);
+        self
+    }
+
+    pub fn allow_label(mut self, label: impl Into<String>) -> Self {
+        self.allowed_labels.push(label.into());
+        self
+    }
+}
+
+// Checks the uids, gids, login types and labels of the policy before
+// opening sessions, letting every command through.
+impl Authorizer for AccessPolicy {
+    fn authorize(&self, subject: &Subject<'_>, action: Action) -> Result<()> {
+        authz::authorize_access(self, subject, action)
+    }
+}
@@ -0,0 +1,222 @@
+//! Emulation of the GP client properties for TAs hosted by a
+//! [`TAManager`](crate::TAManager).
+//!
+//! The `TEE_GetPropertyAs*` functions called by `optee_utee::property` are
+//! implemented here for the `TEE_PROPSET_CURRENT_CLIENT` set, from what the
+//! manager knows of the session the calling thread serves, be it opening
+//! it, running one of its commands or closing it:
+//!
+//! | Property                   | Type     | Value                                        |
+//! |----------------------------|----------|----------------------------------------------|
+//! | `gpd.client.identity`      | identity | login type and uuid the session was opened with |
+//! | `gpd.client.endian`        | u32      | 0, little endian                             |
+//! | `ta_manager.client.uid`    | u32      | user id of the CA process                    |
+//! | `ta_manager.client.gid`    | u32      | group id of the CA process                   |
+//! | `ta_manager.client.pid`    | u32      | process id of the CA                         |
+//! | `ta_manager.client.label`  | string   | security label of the CA, if the LSM sets one |
+//! | `ta_manager.session.id`    | u32      | id the manager gave the session              |
+//!
+//! so that `optee_utee::property::ClientIdentity.get()` works the same under OP-TEE
+//! and under the manager. As in GP, every property may also be read as a
+//! string, and the u32 ones as u64.
+//!
+//! Other property sets, property enumerators and calls from threads serving
+//! no session find no property and fail with `TEE_ERROR_ITEM_NOT_FOUND`.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, c_char, c_void},
+    ptr,
+};
+
+use optee_utee::{ErrorKind, Identity};
+use optee_utee_sys as raw;
+
+use crate::peer::PeerCredentials;
+
+type TeeResult<T> = Result<T, ErrorKind>;
+
+// What the manager knows of the client of a session.
+#[derive(Clone)]
+pub(crate) struct ClientProperties {
+    pub(crate) session_id: u32,
+    pub(crate) identity: Identity,
+    pub(crate) peer: PeerCredentials,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<ClientProperties>> = const { RefCell::new(None) };
+}
+
+/// Makes `client` that of the current thread until the guard is dropped.
+pub(crate) fn enter(client: ClientProperties) -> Entered {
+    Entered(CURRENT.with(|current| current.replace(Some(client))))
+}
+
+pub(crate) struct Entered(Option<ClientProperties>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+enum Property {
+    U32(u32),
+    String(String),
+    Identity(Identity),
+}
+
+impl Property {
+    fn as_string(&self) -> String {
+        match self {
+            Property::U32(value) => value.to_string(),
+            Property::String(value) => value.clone(),
+            Property::Identity(identity) => {
+                format!("{}:{}", identity.login_type() as u32, identity.uuid())
+            }
+        }
+    }
+}
+
+// Looks `name` up in the property set `set` for the current thread.
+unsafe fn lookup(set: raw::TEE_PropSetHandle, name: *const c_char) -> TeeResult<Property> {
+    if set != raw::TEE_PROPSET_CURRENT_CLIENT || name.is_null() {
+        return Err(ErrorKind::ItemNotFound);
+    }
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_str()
+        .map_err(|_| ErrorKind::ItemNotFound)?;
+    CURRENT.with(|current| {
+        let current = current.borrow();
+        let client = current.as_ref().ok_or(ErrorKind::ItemNotFound)?;
+        let property = match name {
+            "gpd.client.identity" => Property::Identity(client.identity),
+            "gpd.client.endian" => Property::U32(0),
+            "ta_manager.client.uid" => Property::U32(client.peer.uid),
+            "ta_manager.client.gid" => Property::U32(client.peer.gid),
+            "ta_manager.client.pid" => Property::U32(client.peer.pid as u32),
+            "ta_manager.client.label" => {
+                let label = client.peer.label.as_ref().ok_or(ErrorKind::ItemNotFound)?;
+                Property::String(label.as_str().to_string())
+            }
+            "ta_manager.session.id" => Property::U32(client.session_id),
+            _ => return Err(ErrorKind::ItemNotFound),
+        };
+        Ok(property)
+    })
+}
+
+fn result(result: TeeResult<()>) -> raw::TEE_Result {
+    match result {
+        Ok(()) => raw::TEE_SUCCESS,
+        Err(kind) => kind.into(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsString(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    buffer: *mut c_char,
+    buffer_len: *mut usize,
+) -> raw::TEE_Result {
+    result((|| {
+        let value = unsafe { lookup(set, name)? }.as_string();
+        // The string is written with its terminating NUL.
+        let len = value.len() + 1;
+        let available = unsafe { *buffer_len };
+        unsafe { *buffer_len = len };
+        if buffer.is_null() || available < len {
+            return Err(ErrorKind::ShortBuffer);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), buffer as *mut u8, value.len());
+            *buffer.add(value.len()) = 0;
+        }
+        Ok(())
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsBool(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    _value: *mut bool,
+) -> raw::TEE_Result {
+    // None of the emulated properties is a boolean.
+    result(unsafe { lookup(set, name) }.and(Err(ErrorKind::BadFormat)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsU32(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    value: *mut u32,
+) -> raw::TEE_Result {
+    result((|| match unsafe { lookup(set, name)? } {
+        Property::U32(property) => {
+            unsafe { *value = property };
+            Ok(())
+        }
+        _ => Err(ErrorKind::BadFormat),
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsU64(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    value: *mut u64,
+) -> raw::TEE_Result {
+    result((|| match unsafe { lookup(set, name)? } {
+        Property::U32(property) => {
+            unsafe { *value = u64::from(property) };
+            Ok(())
+        }
+        _ => Err(ErrorKind::BadFormat),
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsBinaryBlock(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    _buffer: *mut c_void,
+    _buffer_len: *mut usize,
+) -> raw::TEE_Result {
+    // None of the emulated properties is a binary block.
+    result(unsafe { lookup(set, name) }.and(Err(ErrorKind::BadFormat)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsUUID(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    _value: *mut raw::TEE_UUID,
+) -> raw::TEE_Result {
+    // None of the emulated properties is a uuid.
+    result(unsafe { lookup(set, name) }.and(Err(ErrorKind::BadFormat)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetPropertyAsIdentity(
+    set: raw::TEE_PropSetHandle,
+    name: *const c_char,
+    value: *mut raw::TEE_Identity,
+) -> raw::TEE_Result {
+    result((|| match unsafe { lookup(set, name)? } {
+        Property::Identity(identity) => {
+            let uuid = identity.uuid();
+            unsafe {
+                *value = raw::TEE_Identity {
+                    login: identity.login_type() as u32,
+                    uuid: *uuid.as_raw_ptr(),
+                }
+            };
+            Ok(())
+        }
+        _ => Err(ErrorKind::BadFormat),
+    })())
+}
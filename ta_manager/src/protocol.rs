@@ -1,16 +1,115 @@
 use bincode::{Decode, Encode};
+use optee_utee::{ErrorKind, ErrorOrigin, Identity, LoginType, Uuid};
 
+use crate::buffer::{AlignedBuffer, HUGE_PAGE_SIZE, page_size};
+
+pub mod conformance;
+pub mod control;
+#[cfg(feature = "schema")]
+pub mod schema;
+
+/// Version of the CA protocol spoken by this manager.
+///
+/// Version 2 added the client identity to `OpenSession`, version 3 the retry
+/// hint to `InvokeCommand` responses, version 4 the timeout of
+/// `InvokeCommand` requests, version 5 the `InvokeStream*` requests, version
+/// 6 the alignment of memrefs, see [`Parameter::with_alignment`], version 7
+/// the `InvokeBatch` request, version 8 the `KeyExchange` request, version 9
+/// the `Traced` request, version 10 the `RegisterTemplate` and
+/// `InvokeTemplate` requests, version 11 the `Subscribe` request, version 12
+/// the `CancelCommand` request, which replaced the cancellation request of
+/// earlier versions and refuses operation ids already in flight. Connections
+/// on an older version get `NotSupported` for it.
+pub const PROTOCOL_VERSION: u32 = 12;
+/// First CA protocol version with [`TeeRequest::CancelCommand`].
+pub const CANCEL_COMMAND_VERSION: u32 = 12;
+/// Oldest CA protocol version the manager still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 4;
+
+/// Largest frame body, in bytes, accepted on a connection. A peer announcing a
+/// larger frame gets disconnected.
+pub const MAX_FRAME_SIZE: u32 = 16 << 20;
+
+/// The manager accepts [`TeeRequest::CancelCommand`].
+pub const CAP_CANCELLATION: u32 = 1 << 0;
+/// The connection is sealed after a [`TeeRequest::KeyExchange`]. Offered
+/// only by managers configured with a transport key, which then refuse
+/// connections that do not take it.
+pub const CAP_ENCRYPTION: u32 = 1 << 1;
+/// Capabilities offered by this manager.
+pub const CAPABILITIES: u32 = CAP_CANCELLATION;
+
+/// Requests sent to the TA Manager server.
 #[derive(Encode, Decode, Debug)]
 pub enum TARequest {
-    Register { uuid: String },
+    /// Sent by a TA manager when it starts serving a TA. A manager taking
+    /// over from another one sends it again on the connection it inherited,
+    /// with its own name and version.
+    Register {
+        uuid: String,
+        name: String,
+        version: String,
+    },
+    /// Sent by a CA to discover the TAs currently registered, answered with
+    /// [`TAResponse::List`].
+    List,
+    /// Sent by a TA manager on behalf of the TA `uuid` for a service no
+    /// supplicant plugin handled, answered with [`TAResponse::ReeService`].
+    ReeService { uuid: String, service: ReeService },
+    /// Sent by a TA manager on its registration connection every
+    /// [`TAManagerConfig::heartbeat_interval`](crate::TAManagerConfig::heartbeat_interval),
+    /// not answered. The manager registers again once sending it fails.
+    Heartbeat,
+}
+
+/// Responses of the TA Manager server.
+#[derive(Encode, Decode, Debug)]
+pub enum TAResponse {
+    List {
+        tas: Vec<TaInfo>,
+    },
+    /// `data` holds the output of the service when `result` is 0.
+    ReeService {
+        result: u32,
+        data: Vec<u8>,
+    },
+}
+
+/// Normal-world services a TA may call, like those tee-supplicant provides
+/// to TAs running under OP-TEE.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub enum ReeService {
+    /// Reads a file of the REE filesystem and returns its contents.
+    LoadFile { path: String },
+    /// Writes `data` to a file of the REE filesystem, replacing it.
+    StoreFile { path: String, data: Vec<u8> },
+    /// Sends `data` to a TCP endpoint and returns what it answers until it
+    /// closes the connection.
+    Network {
+        host: String,
+        port: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// A TA registered with the TA Manager server, as described by its
+/// [`TAManagerConfig`](crate::TAManagerConfig).
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaInfo {
+    pub uuid: String,
+    pub name: String,
+    pub version: String,
 }
 
 #[derive(Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum TeeRequest {
     OpenSession {
         uuid: String,
         connection_method: u32,
         params: Parameters,
+        identity: ClientIdentity,
     },
     CloseSession {
         session_id: u32,
@@ -18,43 +117,527 @@ pub enum TeeRequest {
     InvokeCommand {
         session_id: u32,
         cmd_id: u32,
+        // Chosen by the CA to identify the command in `CancelCommand`. An id
+        // still in flight on the session fails with `BadParameters`.
+        operation_id: u32,
+        params: Parameters,
+        /// Milliseconds the CA waits for the command, or `None` to wait until
+        /// it completes. Past it the manager answers `Timeout` and flags the
+        /// command as cancelled.
+        timeout_ms: Option<u32>,
+    },
+    /// Cancels the command `operation_id` of the session. A cancel reaching
+    /// the manager before its command is kept for a few seconds and
+    /// cancels the command once it arrives. One for a command that already
+    /// completed fails with `ItemNotFound`.
+    CancelCommand {
+        session_id: u32,
+        operation_id: u32,
+    },
+    /// Optional first frame of a connection, followed by the actual requests
+    /// once the manager accepted the version. Connections that start
+    /// directly with a request are served as [`MIN_PROTOCOL_VERSION`].
+    Hello {
+        version: u32,
+        capabilities: u32,
+    },
+    /// Starts an `InvokeCommand` whose memrefs are too large for one frame.
+    /// `lengths` gives the size of the input memrefs sent afterwards in
+    /// `InvokeStreamChunk` requests, whose data is left empty in `params`,
+    /// and 0 for the other parameters.
+    InvokeStreamBegin {
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
         params: Parameters,
+        timeout_ms: Option<u32>,
+        lengths: [u32; 4],
     },
-    RequestCancellation {
+    /// Appends `data` to the memref `index` of the invocation started with
+    /// the same `operation_id` on the connection.
+    InvokeStreamChunk {
+        session_id: u32,
+        operation_id: u32,
+        index: u8,
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        data: Vec<u8>,
+    },
+    /// Invokes the command once all its memrefs were received, answered with
+    /// [`TeeResponse::InvokeCommand`]. The streamed input memrefs are not
+    /// sent back.
+    InvokeStreamEnd {
+        session_id: u32,
+        operation_id: u32,
+    },
+    /// Invokes `commands`, given as command id and parameters, one after the
+    /// other on the session, answered with a single
+    /// [`TeeResponse::InvokeBatch`]. A command failing does not stop the
+    /// next ones. Cancelling `operation_id`, or the timeout passing, skips
+    /// the commands not started yet.
+    InvokeBatch {
+        session_id: u32,
+        operation_id: u32,
+        commands: Vec<(u32, Parameters)>,
+        timeout_ms: Option<u32>,
+    },
+    /// Sent right after a `Hello` that agreed on [`CAP_ENCRYPTION`], with 32
+    /// random bytes. Every frame after the response is sealed with keys
+    /// derived from the transport key and the nonces of both sides.
+    KeyExchange {
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        nonce: Vec<u8>,
+    },
+    /// Carries `request` with the correlation id the CA chose for it, and is
+    /// answered as `request` would be. The manager logs the handling of the
+    /// request in a `request` span carrying the id, and hands it to the TA
+    /// in [`CommandContext::trace_id`](crate::CommandContext::trace_id).
+    /// Requests sent without it get an id generated by the manager.
+    /// `request` may not be a `Traced` request itself.
+    Traced {
+        trace_id: u64,
+        request: Box<TeeRequest>,
+    },
+    /// Keeps `params` on the manager as a template of the parameters of the
+    /// commands invoked on the session, answered with
+    /// [`TeeResponse::RegisterTemplate`]. Templates live as long as the
+    /// session, up to [`MAX_TEMPLATES`] of them.
+    RegisterTemplate {
+        session_id: u32,
+        params: Parameters,
+    },
+    /// Invokes a command like `InvokeCommand`, with the parameters of the
+    /// template `template_id` of the session updated by `deltas`, which
+    /// leave the template itself unchanged. Answered with
+    /// [`TeeResponse::InvokeCommand`], whose input memrefs are not sent
+    /// back.
+    InvokeTemplate {
+        session_id: u32,
+        cmd_id: u32,
+        operation_id: u32,
+        template_id: u32,
+        deltas: Vec<ParamDelta>,
+        timeout_ms: Option<u32>,
+    },
+    /// Subscribes the connection to the events the TA emits through its
+    /// [`NotificationSender`](crate::NotificationSender), for as long as the
+    /// session stays open. Answered with [`TeeResponse::Subscribe`], then
+    /// with a [`TeeResponse::Notification`] for every event, until the
+    /// session is closed, the CA closes the connection or stops reading.
+    /// The manager reads no further request on the connection.
+    Subscribe {
         session_id: u32,
     },
 }
 
 #[derive(Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum TeeResponse {
-    OpenSession { session_id: u32, result: u32 },
-    CloseSession { result: u32 },
-    InvokeCommand { params: Parameters, result: u32 },
-    RequestCancellation { result: u32 },
+    OpenSession {
+        session_id: u32,
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    CloseSession {
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    InvokeCommand {
+        params: Parameters,
+        result: u32,
+        origin: ReturnOrigin,
+        /// Set along a `TargetDead` result when the session was interrupted
+        /// by its TA instance dying and a standby instance took over: the
+        /// command may be retried on a newly opened session.
+        retry: bool,
+    },
+    CancelCommand {
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// The version and capabilities both sides agreed on. On a non-zero
+    /// `result` the manager closes the connection.
+    Hello {
+        version: u32,
+        capabilities: u32,
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// Acknowledges an `InvokeStreamBegin` or `InvokeStreamChunk`. On a
+    /// non-zero `result` the manager dropped the invocation.
+    InvokeStream {
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// The outcome of every command of an `InvokeBatch`, in order. On a
+    /// non-zero `result` the batch did not complete, e.g. because the
+    /// session does not exist, and `results` is empty.
+    InvokeBatch {
+        results: Vec<BatchResult>,
+        result: u32,
+        origin: ReturnOrigin,
+        /// As in [`TeeResponse::InvokeCommand`].
+        retry: bool,
+    },
+    /// The 32 random bytes of the manager. On a non-zero `result` the
+    /// manager closes the connection.
+    KeyExchange {
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        nonce: Vec<u8>,
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// The id of the template registered by a `RegisterTemplate`, valid on
+    /// a zero `result` only.
+    RegisterTemplate {
+        template_id: u32,
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// Acknowledges a `Subscribe`. On a non-zero `result` the connection
+    /// takes requests again.
+    Subscribe {
+        result: u32,
+        origin: ReturnOrigin,
+    },
+    /// An event emitted by the TA, sent to the connections subscribed to it.
+    Notification {
+        event: u32,
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        data: Vec<u8>,
+    },
+}
+
+/// Most templates kept for a session, see [`TeeRequest::RegisterTemplate`].
+pub const MAX_TEMPLATES: usize = 16;
+
+/// Change to the parameter `index` of a template for one
+/// [`TeeRequest::InvokeTemplate`].
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum ParamDelta {
+    /// Replaces the values of a value parameter.
+    Values { index: u8, values: Value },
+    /// Replaces the data of a memref.
+    Data {
+        index: u8,
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        data: Vec<u8>,
+    },
+    /// Overwrites the data of a memref from `offset` on, without growing it.
+    Patch {
+        index: u8,
+        offset: u32,
+        #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
+        data: Vec<u8>,
+    },
+}
+
+/// Outcome of one command of a [`TeeRequest::InvokeBatch`], as it would be
+/// answered to an `InvokeCommand`.
+#[derive(Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BatchResult {
+    pub params: Parameters,
+    pub result: u32,
+    pub origin: ReturnOrigin,
+}
+
+impl TeeRequest {
+    // Returns the request carried by a `Traced` request, or the request
+    // itself.
+    pub(crate) fn untraced(&self) -> &TeeRequest {
+        match self {
+            TeeRequest::Traced { request, .. } => request.untraced(),
+            req => req,
+        }
+    }
+
+    // Returns the session the request acts on, if it acts on one.
+    pub(crate) fn session_id(&self) -> Option<u32> {
+        match self {
+            TeeRequest::CloseSession { session_id }
+            | TeeRequest::InvokeCommand { session_id, .. }
+            | TeeRequest::CancelCommand { session_id, .. }
+            | TeeRequest::InvokeStreamBegin { session_id, .. }
+            | TeeRequest::InvokeStreamChunk { session_id, .. }
+            | TeeRequest::InvokeStreamEnd { session_id, .. }
+            | TeeRequest::InvokeBatch { session_id, .. }
+            | TeeRequest::RegisterTemplate { session_id, .. }
+            | TeeRequest::InvokeTemplate { session_id, .. }
+            | TeeRequest::Subscribe { session_id } => Some(*session_id),
+            TeeRequest::Traced { request, .. } => request.session_id(),
+            TeeRequest::OpenSession { .. }
+            | TeeRequest::Hello { .. }
+            | TeeRequest::KeyExchange { .. } => None,
+        }
+    }
 }
 
-#[derive(Encode, Decode, Default)]
+impl TeeResponse {
+    pub(crate) fn result(&self) -> u32 {
+        match self {
+            TeeResponse::OpenSession { result, .. }
+            | TeeResponse::CloseSession { result, .. }
+            | TeeResponse::InvokeCommand { result, .. }
+            | TeeResponse::CancelCommand { result, .. }
+            | TeeResponse::Hello { result, .. }
+            | TeeResponse::InvokeStream { result, .. }
+            | TeeResponse::InvokeBatch { result, .. }
+            | TeeResponse::KeyExchange { result, .. }
+            | TeeResponse::RegisterTemplate { result, .. }
+            | TeeResponse::Subscribe { result, .. } => *result,
+            TeeResponse::Notification { .. } => 0,
+        }
+    }
+
+    // Builds the response to `req` reporting that it failed with `result`.
+    pub(crate) fn error(req: TeeRequest, result: u32, origin: ReturnOrigin) -> Self {
+        match req {
+            TeeRequest::OpenSession { .. } => TeeResponse::OpenSession {
+                session_id: 0,
+                result,
+                origin,
+            },
+            TeeRequest::CloseSession { .. } => TeeResponse::CloseSession { result, origin },
+            TeeRequest::InvokeCommand { params, .. } => TeeResponse::InvokeCommand {
+                params,
+                result,
+                origin,
+                retry: false,
+            },
+            TeeRequest::CancelCommand { .. } => TeeResponse::CancelCommand { result, origin },
+            TeeRequest::Hello { .. } => TeeResponse::Hello {
+                version: 0,
+                capabilities: 0,
+                result,
+                origin,
+            },
+            TeeRequest::InvokeStreamBegin { .. } | TeeRequest::InvokeStreamChunk { .. } => {
+                TeeResponse::InvokeStream { result, origin }
+            }
+            TeeRequest::InvokeStreamEnd { .. } => TeeResponse::InvokeCommand {
+                params: Parameters::default(),
+                result,
+                origin,
+                retry: false,
+            },
+            TeeRequest::InvokeBatch { .. } => TeeResponse::InvokeBatch {
+                results: Vec::new(),
+                result,
+                origin,
+                retry: false,
+            },
+            TeeRequest::KeyExchange { .. } => TeeResponse::KeyExchange {
+                nonce: Vec::new(),
+                result,
+                origin,
+            },
+            TeeRequest::Traced { request, .. } => Self::error(*request, result, origin),
+            TeeRequest::RegisterTemplate { .. } => TeeResponse::RegisterTemplate {
+                template_id: 0,
+                result,
+                origin,
+            },
+            TeeRequest::InvokeTemplate { .. } => TeeResponse::InvokeCommand {
+                params: Parameters::default(),
+                result,
+                origin,
+                retry: false,
+            },
+            TeeRequest::Subscribe { .. } => TeeResponse::Subscribe { result, origin },
+        }
+    }
+}
+
+/// Identity of the CA opening a session, as in the GP `TEE_Identity`.
+///
+/// The manager does not trust the UUID a CA sends: it derives the client
+/// UUID from the credentials of the CA, like the Linux TEE subsystem does,
+/// and refuses login types the credentials do not support. Only the UUID of
+/// a `TEE_LOGIN_TRUSTED_APP` login, sent by a TA manager on behalf of its TA,
+/// is kept.
+#[derive(Encode, Decode, Clone, Copy, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ClientIdentity {
+    /// One of the `TEE_LOGIN_*` values.
+    pub login: u32,
+    /// Big-endian bytes of the client UUID.
+    pub uuid: [u8; 16],
+}
+
+impl TryFrom<ClientIdentity> for Identity {
+    type Error = optee_utee::Error;
+
+    fn try_from(identity: ClientIdentity) -> optee_utee::Result<Identity> {
+        let login_type = LoginType::try_from(identity.login)?;
+        Ok(Identity::new(login_type, Uuid::from_bytes(identity.uuid)))
+    }
+}
+
+/// Where the `result` of a [`TeeResponse`] comes from, with the values of the
+/// GP `TEE_ORIGIN_*` constants.
+#[derive(Encode, Decode, Default, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum ReturnOrigin {
+    /// The request was rejected before reaching the TA, e.g. malformed.
+    Api = 1,
+    /// The transport between the CA, the manager and the TA failed.
+    Comms = 2,
+    /// The manager itself produced the result.
+    #[default]
+    Tee = 3,
+    /// The result was returned by the TA.
+    TrustedApp = 4,
+}
+
+impl From<ReturnOrigin> for ErrorOrigin {
+    fn from(origin: ReturnOrigin) -> ErrorOrigin {
+        match origin {
+            ReturnOrigin::Api => ErrorOrigin::Api,
+            ReturnOrigin::Comms => ErrorOrigin::Comms,
+            ReturnOrigin::Tee => ErrorOrigin::Tee,
+            ReturnOrigin::TrustedApp => ErrorOrigin::Ta,
+        }
+    }
+}
+
+/// The four parameters of a request, which [`validate`](Self::validate)
+/// checks against their types.
+#[derive(Encode, Decode, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Parameters(pub Parameter, pub Parameter, pub Parameter, pub Parameter);
 
-#[derive(Encode, Decode, Default)]
+#[derive(Encode, Decode, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Parameter {
     pub param: TeeParam,
     pub param_type: ParamType,
 }
 
-#[derive(Encode, Decode, Default)]
+impl Parameters {
+    /// Checks that the payload of every parameter matches its type, as the
+    /// manager does before handing parameters to a TA: unused parameters
+    /// carry nothing, value parameters no data, memrefs no values but their
+    /// alignment, and input memrefs some data.
+    ///
+    /// # Errors
+    ///
+    /// 1) `BadParameters`: If a parameter does not match its type.
+    pub fn validate(&self) -> optee_utee::Result<()> {
+        match self.malformed() {
+            Some(_) => Err(optee_utee::Error::new(ErrorKind::BadParameters)),
+            None => Ok(()),
+        }
+    }
+
+    // Returns the index of the first parameter whose payload does not match
+    // its type.
+    pub(crate) fn malformed(&self) -> Option<usize> {
+        [&self.0, &self.1, &self.2, &self.3]
+            .iter()
+            .position(|param| !param.is_well_formed())
+    }
+}
+
+impl Parameter {
+    /// Asks the TA to handle the memref in a buffer aligned to `align`
+    /// bytes, a power of two up to [`HUGE_PAGE_SIZE`], e.g. because it hands
+    /// the buffer to a device. Carried in `values.a`, which memrefs leave
+    /// unused, and only understood by managers speaking protocol version 6.
+    pub fn with_alignment(mut self, align: u32) -> Self {
+        self.param.values.a = align;
+        self
+    }
+
+    /// Returns the alignment the memref was marked with, if any.
+    pub fn alignment(&self) -> Option<usize> {
+        match self.param_type {
+            ParamType::MemrefInput | ParamType::MemrefOutput | ParamType::MemrefInout => {
+                Some(self.param.values.a as usize).filter(|align| *align != 0)
+            }
+            _ => None,
+        }
+    }
+
+    /// Copies the data of the memref into a buffer aligned as it was marked
+    /// with, or to a page otherwise.
+    ///
+    /// # Errors
+    ///
+    /// See [`AlignedBuffer::with_alignment`].
+    pub fn to_aligned(&self) -> optee_utee::Result<AlignedBuffer> {
+        let align = self.alignment().unwrap_or_else(page_size);
+        AlignedBuffer::from_slice(&self.param.data, align)
+    }
+
+    // Unused parameters carry nothing, value parameters no buffer, and memrefs
+    // no values but their alignment. Memrefs may be empty, as GP allows: an
+    // empty output memref asks the TA for the size it needs.
+    fn is_well_formed(&self) -> bool {
+        let TeeParam { data, values } = &self.param;
+        let no_values = values.a == 0 && values.b == 0;
+        let align = values.a as usize;
+        let memref_values =
+            values.b == 0 && (align == 0 || align.is_power_of_two() && align <= HUGE_PAGE_SIZE);
+        match self.param_type {
+            ParamType::None => data.is_empty() && no_values,
+            ParamType::ValueInput | ParamType::ValueOutput | ParamType::ValueInout => {
+                data.is_empty()
+            }
+            ParamType::MemrefInput | ParamType::MemrefOutput | ParamType::MemrefInout => {
+                memref_values
+            }
+        }
+    }
+}
+
+#[derive(Encode, Decode, Clone, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct TeeParam {
+    #[cfg_attr(feature = "serde", serde(with = "serde_bytes"))]
     pub data: Vec<u8>,
     pub values: Value,
 }
 
-#[derive(Encode, Decode, Clone, Copy, Default)]
+#[cfg(feature = "cbor")]
+impl TeeParam {
+    /// Creates a memref payload holding the deterministic CBOR encoding of `value`.
+    pub fn from_cbor<T: optee_utee::cbor::minicbor::Encode<()>>(
+        value: &T,
+    ) -> optee_utee::Result<Self> {
+        Ok(Self {
+            data: optee_utee::cbor::to_vec(value)?,
+            values: Value::default(),
+        })
+    }
+
+    /// Decodes the CBOR payload held by the memref.
+    pub fn decode_cbor<'b, T: optee_utee::cbor::minicbor::Decode<'b, ()>>(
+        &'b self,
+    ) -> optee_utee::Result<T> {
+        optee_utee::cbor::decode(&self.data)
+    }
+}
+
+#[derive(Encode, Decode, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Value {
     pub a: u32,
     pub b: u32,
 }
 
 #[derive(Encode, Decode, Default, Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ParamType {
     #[default]
     None = 0,
@@ -68,15 +651,41 @@ pub enum ParamType {
 
 impl From<u32> for ParamType {
     fn from(value: u32) -> Self {
-        match value {
-            0 => ParamType::None,
-            1 => ParamType::ValueInput,
-            2 => ParamType::ValueOutput,
-            3 => ParamType::ValueInout,
-            5 => ParamType::MemrefInput,
-            6 => ParamType::MemrefOutput,
-            7 => ParamType::MemrefInout,
-            _ => ParamType::None,
+        optee_utee::ParamType::from(value).into()
+    }
+}
+
+impl From<ParamType> for u32 {
+    fn from(param_type: ParamType) -> Self {
+        param_type as u32
+    }
+}
+
+// The values of both enums are the `TEE_PARAM_TYPE_*` ones.
+impl From<optee_utee::ParamType> for ParamType {
+    fn from(param_type: optee_utee::ParamType) -> Self {
+        match param_type {
+            optee_utee::ParamType::None => ParamType::None,
+            optee_utee::ParamType::ValueInput => ParamType::ValueInput,
+            optee_utee::ParamType::ValueOutput => ParamType::ValueOutput,
+            optee_utee::ParamType::ValueInout => ParamType::ValueInout,
+            optee_utee::ParamType::MemrefInput => ParamType::MemrefInput,
+            optee_utee::ParamType::MemrefOutput => ParamType::MemrefOutput,
+            optee_utee::ParamType::MemrefInout => ParamType::MemrefInout,
+        }
+    }
+}
+
+impl From<ParamType> for optee_utee::ParamType {
+    fn from(param_type: ParamType) -> Self {
+        match param_type {
+            ParamType::None => optee_utee::ParamType::None,
+            ParamType::ValueInput => optee_utee::ParamType::ValueInput,
+            ParamType::ValueOutput => optee_utee::ParamType::ValueOutput,
+            ParamType::ValueInout => optee_utee::ParamType::ValueInout,
+            ParamType::MemrefInput => optee_utee::ParamType::MemrefInput,
+            ParamType::MemrefOutput => optee_utee::ParamType::MemrefOutput,
+            ParamType::MemrefInout => optee_utee::ParamType::MemrefInout,
         }
     }
 }
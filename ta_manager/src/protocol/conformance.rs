@@ -0,0 +1,439 @@
+//! Conformance suite for servers speaking the CA protocol with the
+//! [`BincodeCodec`], so that other implementations of the manager or of its
+//! transport can check they are compatible with CAs written against this one.
+//!
+//! ```no_run
+//! # #[cfg(target_os = "linux")] {
+//! use ta_manager::protocol::conformance::{self, UnixTransport};
+//!
+//! let report = conformance::run(&mut UnixTransport::for_ta("my-ta-uuid"));
+//! print!("{}", report);
+//! assert!(report.passed());
+//! # }
+//! ```
+//!
+//! [`UnixTransport`] is only available on Linux: named pipes cannot be shut
+//! down for writing only, so on Windows servers are reached through a
+//! [`Transport`] of the caller.
+//!
+//! Every case opens its own connection. The suite only sends requests that
+//! must fail before reaching the TA, such as commands on sessions that do not
+//! exist, so it can run against a server hosting any TA.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+#[cfg(target_os = "linux")]
+use std::{os::unix::net::UnixStream, path::PathBuf, time::Duration};
+
+use anyhow::{bail, ensure};
+
+use crate::codec::{BincodeCodec, Codec, read_frame, write_frame};
+use crate::protocol::{
+    MAX_FRAME_SIZE, PROTOCOL_VERSION, ParamDelta, ParamType, Parameter, Parameters, TeeParam,
+    TeeRequest, TeeResponse, Value,
+};
+#[cfg(target_os = "linux")]
+use crate::{ca_socket_path, config::default_socket_dir};
+
+// Session id the suite assumes no server ever hands out.
+const UNKNOWN_SESSION: u32 = u32::MAX;
+
+/// Way of reaching the server under test.
+pub trait Transport {
+    type Stream: Read + Write;
+
+    /// Opens a new connection to the server. Reads on it should time out
+    /// rather than block forever, so that a server that does not answer makes
+    /// the case fail instead of hanging the suite.
+    fn connect(&mut self) -> io::Result<Self::Stream>;
+
+    /// Tells the server nothing more will be written on `stream`, keeping it
+    /// open for reading.
+    fn shutdown_write(&mut self, stream: &mut Self::Stream) -> io::Result<()>;
+}
+
+/// Connections over the Unix socket of a TA, as used by [`TAManager`](crate::TAManager).
+#[cfg(target_os = "linux")]
+pub struct UnixTransport {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+#[cfg(target_os = "linux")]
+impl UnixTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Connects to the socket on which the TA `uuid` is served, in the
+    /// default socket directory of [`TAManagerConfig`](crate::TAManagerConfig).
+    pub fn for_ta(uuid: &str) -> Self {
+        Self::new(ca_socket_path(&default_socket_dir(), uuid))
+    }
+
+    /// Sets how long to wait for an answer of the server, 5 seconds by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Transport for UnixTransport {
+    type Stream = UnixStream;
+
+    fn connect(&mut self) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        Ok(stream)
+    }
+
+    fn shutdown_write(&mut self, stream: &mut UnixStream) -> io::Result<()> {
+        stream.shutdown(std::net::Shutdown::Write)
+    }
+}
+
+/// Outcome of one case of the suite.
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    /// Why the case failed, if it did.
+    pub failure: Option<String>,
+}
+
+/// Outcome of [`run`].
+#[derive(Debug)]
+pub struct Report {
+    pub cases: Vec<CaseResult>,
+}
+
+impl Report {
+    /// Returns whether every case passed.
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|case| case.failure.is_none())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for case in &self.cases {
+            match &case.failure {
+                None => writeln!(f, "ok      {}", case.name)?,
+                Some(failure) => writeln!(f, "FAILED  {}: {}", case.name, failure)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+type Case<T> = fn(&mut T) -> anyhow::Result<()>;
+
+/// Runs the whole suite against the server reached through `transport`.
+pub fn run<T: Transport>(transport: &mut T) -> Report {
+    let cases: [(&'static str, Case<T>); 13] = [
+        ("hello", hello),
+        ("unsupported_version", unsupported_version),
+        ("request_without_hello", request_without_hello),
+        (
+            "several_requests_per_connection",
+            several_requests_per_connection,
+        ),
+        ("zero_length_memref", zero_length_memref),
+        ("max_size_payload", max_size_payload),
+        ("oversized_frame", oversized_frame),
+        ("unknown_message_type", unknown_message_type),
+        ("truncated_frame", truncated_frame),
+        ("cancel_unknown_operation", cancel_unknown_operation),
+        ("traced_request", traced_request),
+        ("template_on_unknown_session", template_on_unknown_session),
+        ("subscribe_to_unknown_session", subscribe_to_unknown_session),
+    ];
+
+    Report {
+        cases: cases
+            .iter()
+            .map(|(name, case)| CaseResult {
+                name,
+                failure: case(transport).err().map(|e| format!("{:#}", e)),
+            })
+            .collect(),
+    }
+}
+
+// The server accepts its own protocol version.
+fn hello<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    connect_with_hello(transport)?;
+    Ok(())
+}
+
+// The server refuses a version older than it supports, then disconnects.
+fn unsupported_version<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = transport.connect()?;
+    send(
+        &mut stream,
+        &TeeRequest::Hello {
+            version: 1,
+            capabilities: 0,
+        },
+    )?;
+    match receive(&mut stream)? {
+        TeeResponse::Hello { result, .. } => ensure!(result != 0, "version 1 was accepted"),
+        _ => bail!("expected a Hello response"),
+    }
+    expect_disconnected(&mut stream)
+}
+
+// A connection may start directly with a request.
+fn request_without_hello<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = transport.connect()?;
+    expect_close_failure(&mut stream)
+}
+
+// Requests are answered in order on a single connection.
+fn several_requests_per_connection<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    for _ in 0..3 {
+        expect_close_failure(&mut stream)?;
+    }
+    Ok(())
+}
+
+fn zero_length_memref<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    send(&mut stream, &invoke_with_memref(Vec::new()))?;
+    expect_invoke_failure(&mut stream)
+}
+
+// A memref filling a frame close to `MAX_FRAME_SIZE` goes through.
+fn max_size_payload<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let overhead = encode(&invoke_with_memref(Vec::new()))?.len();
+    // Leave room for the response, which echoes the parameters back.
+    let len = MAX_FRAME_SIZE as usize - overhead - 64;
+    let mut stream = connect_with_hello(transport)?;
+    send(&mut stream, &invoke_with_memref(vec![0xA5; len]))?;
+    expect_invoke_failure(&mut stream)
+}
+
+// A frame announcing more than `MAX_FRAME_SIZE` bytes gets the connection
+// closed without the server waiting for its body.
+fn oversized_frame<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    stream.write_all(&(MAX_FRAME_SIZE + 1).to_ne_bytes())?;
+    expect_disconnected(&mut stream)
+}
+
+fn unknown_message_type<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    // bincode encodes the variant index first, and `TeeRequest` has far
+    // fewer than 0x7F variants.
+    write_frame(&mut stream, &[0x7F, 0, 0, 0])?;
+    expect_disconnected(&mut stream)
+}
+
+fn truncated_frame<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    let body = encode(&TeeRequest::CloseSession {
+        session_id: UNKNOWN_SESSION,
+    })?;
+    stream.write_all(&(body.len() as u32 + 8).to_ne_bytes())?;
+    stream.write_all(&body)?;
+    transport.shutdown_write(&mut stream)?;
+    expect_disconnected(&mut stream)
+}
+
+fn cancel_unknown_operation<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    send(
+        &mut stream,
+        &TeeRequest::CancelCommand {
+            session_id: UNKNOWN_SESSION,
+            operation_id: 1,
+        },
+    )?;
+    match receive(&mut stream)? {
+        TeeResponse::CancelCommand { result, .. } => {
+            ensure!(result != 0, "cancelled an unknown operation");
+            Ok(())
+        }
+        _ => bail!("expected a CancelCommand response"),
+    }
+}
+
+// A `Traced` request is answered as the request it carries.
+fn traced_request<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    send(
+        &mut stream,
+        &TeeRequest::Traced {
+            trace_id: 0x0123_4567_89AB_CDEF,
+            request: Box::new(invoke_with_memref(vec![0xA5])),
+        },
+    )?;
+    expect_invoke_failure(&mut stream)
+}
+
+// Registering a template on an unknown session and invoking one fail with
+// the usual responses.
+fn template_on_unknown_session<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    send(
+        &mut stream,
+        &TeeRequest::RegisterTemplate {
+            session_id: UNKNOWN_SESSION,
+            params: Parameters::default(),
+        },
+    )?;
+    match receive(&mut stream)? {
+        TeeResponse::RegisterTemplate { result, .. } => {
+            ensure!(result != 0, "registered a template on an unknown session")
+        }
+        _ => bail!("expected a RegisterTemplate response"),
+    }
+    send(
+        &mut stream,
+        &TeeRequest::InvokeTemplate {
+            session_id: UNKNOWN_SESSION,
+            cmd_id: 0,
+            operation_id: 0,
+            template_id: 0,
+            deltas: vec![ParamDelta::Values {
+                index: 0,
+                values: Value { a: 1, b: 2 },
+            }],
+            timeout_ms: None,
+        },
+    )?;
+    expect_invoke_failure(&mut stream)
+}
+
+// Subscribing on an unknown session fails, leaving the connection usable.
+fn subscribe_to_unknown_session<T: Transport>(transport: &mut T) -> anyhow::Result<()> {
+    let mut stream = connect_with_hello(transport)?;
+    send(
+        &mut stream,
+        &TeeRequest::Subscribe {
+            session_id: UNKNOWN_SESSION,
+        },
+    )?;
+    match receive(&mut stream)? {
+        TeeResponse::Subscribe { result, .. } => {
+            ensure!(result != 0, "subscribed on an unknown session")
+        }
+        _ => bail!("expected a Subscribe response"),
+    }
+    send(&mut stream, &invoke_with_memref(vec![0xA5]))?;
+    expect_invoke_failure(&mut stream)
+}
+
+fn connect_with_hello<T: Transport>(transport: &mut T) -> anyhow::Result<T::Stream> {
+    let mut stream = transport.connect()?;
+    send(
+        &mut stream,
+        &TeeRequest::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: 0,
+        },
+    )?;
+    match receive(&mut stream)? {
+        TeeResponse::Hello {
+            version, result, ..
+        } => {
+            ensure!(result == 0, "Hello failed with {:#x}", result);
+            ensure!(
+                version == PROTOCOL_VERSION,
+                "negotiated version {} instead of {}",
+                version,
+                PROTOCOL_VERSION
+            );
+            Ok(stream)
+        }
+        _ => bail!("expected a Hello response"),
+    }
+}
+
+fn invoke_with_memref(data: Vec<u8>) -> TeeRequest {
+    TeeRequest::InvokeCommand {
+        session_id: UNKNOWN_SESSION,
+        cmd_id: 0,
+        operation_id: 0,
+        timeout_ms: None,
+        params: Parameters(
+            Parameter {
+                param: TeeParam {
+                    data,
+                    values: Value::default(),
+                },
+                param_type: ParamType::MemrefInput,
+            },
+            Parameter::default(),
+            Parameter::default(),
+            Parameter::default(),
+        ),
+    }
+}
+
+// Closing an unknown session fails, with a response of the right type.
+fn expect_close_failure(stream: &mut (impl Read + Write)) -> anyhow::Result<()> {
+    send(
+        stream,
+        &TeeRequest::CloseSession {
+            session_id: UNKNOWN_SESSION,
+        },
+    )?;
+    match receive(stream)? {
+        TeeResponse::CloseSession { result, .. } => {
+            ensure!(result != 0, "closed an unknown session");
+            Ok(())
+        }
+        _ => bail!("expected a CloseSession response"),
+    }
+}
+
+fn expect_invoke_failure(stream: &mut impl Read) -> anyhow::Result<()> {
+    match receive(stream)? {
+        TeeResponse::InvokeCommand { result, .. } => {
+            ensure!(result != 0, "invoked a command on an unknown session");
+            Ok(())
+        }
+        _ => bail!("expected an InvokeCommand response"),
+    }
+}
+
+// The server closed the connection, or reset it, without answering.
+fn expect_disconnected(stream: &mut impl Read) -> anyhow::Result<()> {
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf) {
+        Ok(0) => Ok(()),
+        Ok(_) => bail!("server answered instead of disconnecting"),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            bail!("server kept the connection open")
+        }
+        Err(_) => Ok(()),
+    }
+}
+
+fn encode(req: &TeeRequest) -> anyhow::Result<Vec<u8>> {
+    BincodeCodec.encode_request(req)
+}
+
+fn send(stream: &mut impl Write, req: &TeeRequest) -> anyhow::Result<()> {
+    write_frame(stream, &encode(req)?)?;
+    Ok(())
+}
+
+fn receive(stream: &mut impl Read) -> anyhow::Result<TeeResponse> {
+    match read_frame(stream)? {
+        Some(buf) => BincodeCodec.decode_response(&buf),
+        None => bail!("server closed the connection"),
+    }
+}
@@ -0,0 +1,92 @@
+//! Protocol of the control socket of a manager, through which operators
+//! inspect the TAs it hosts and close their sessions, see
+//! [`TAManagerConfig::with_control_socket`](crate::TAManagerConfig::with_control_socket)
+//! and [`ControlClient`](crate::ControlClient).
+//!
+//! Each [`ControlRequest`] is sent as a frame, a 4-byte length in native
+//! byte order followed by the body encoded with bincode's standard
+//! configuration, and answered with a [`ControlResponse`] framed the same
+//! way. A connection may carry any number of requests, one after the other.
+
+use bincode::{Decode, Encode};
+
+/// Version of the control protocol spoken by this manager, reported in
+/// [`ControlResponse::Status`].
+pub const CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+/// Requests sent on a control socket.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub enum ControlRequest {
+    /// Answered with [`ControlResponse::Status`].
+    Status,
+    /// Answered with [`ControlResponse::Tas`].
+    ListTas,
+    /// Lists the sessions open on the TA `uuid`, answered with
+    /// [`ControlResponse::Sessions`].
+    ListSessions { uuid: String },
+    /// Closes a session on the TA `uuid` as if its CA had, answered with
+    /// [`ControlResponse::Closed`].
+    CloseSession { uuid: String, session_id: u32 },
+}
+
+/// Responses sent on a control socket.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub enum ControlResponse {
+    Status(ManagerStatus),
+    Tas(Vec<TaStatus>),
+    Sessions(Vec<SessionStatus>),
+    Closed,
+    /// The request failed with the GP error code `result`, e.g.
+    /// `TEE_ERROR_ITEM_NOT_FOUND` for an unknown TA or session.
+    Error {
+        result: u32,
+        message: String,
+    },
+}
+
+/// State of a manager, as answered to [`ControlRequest::Status`].
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ManagerStatus {
+    /// [`CONTROL_PROTOCOL_VERSION`] of the manager.
+    pub version: u32,
+    /// Process id of the manager.
+    pub pid: u32,
+    /// Time elapsed since the control socket was opened, in milliseconds.
+    pub uptime_ms: u64,
+    /// Number of TAs served through the socket.
+    pub tas: u32,
+}
+
+/// A TA served through a control socket.
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaStatus {
+    pub uuid: String,
+    pub name: String,
+    pub version: String,
+    /// [`LifecycleState`](crate::LifecycleState) of the TA, e.g. `Serving`.
+    pub state: String,
+    pub open_sessions: u32,
+    pub sessions_opened: u64,
+    pub commands_invoked: u64,
+    /// Responses sent with a non-zero result.
+    pub errors: u64,
+}
+
+/// A session open on a TA, as described by its
+/// [`SessionInfo`](crate::SessionInfo).
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionStatus {
+    pub session_id: u32,
+    /// Credentials of the CA that opened the session.
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32,
+    /// Time elapsed since the session last received a request, in
+    /// milliseconds.
+    pub idle_ms: u64,
+    /// Time the command the TA is executing on the session has been running,
+    /// in milliseconds, if any.
+    pub busy_ms: Option<u64>,
+    /// Whether the watchdog reported that command as stuck.
+    pub stuck: bool,
+}
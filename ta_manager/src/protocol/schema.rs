@@ -0,0 +1,144 @@
+//! Machine-readable description of the CA protocol, for CAs written in
+//! languages other than Rust, built with the `schema` feature and printed as
+//! JSON by `ta_managerctl schema`.
+//!
+//! The [`Schema`] is traced from [`TeeRequest`] and [`TeeResponse`] with
+//! [serde-reflection](https://docs.rs/serde-reflection), whose formats map
+//! to the default [`BincodeCodec`](crate::BincodeCodec) as follows:
+//!
+//! - A frame is a 4-byte length in native byte order followed by the body.
+//! - Integers wider than a byte are varints: values below 251 take one byte,
+//!   larger ones a byte 251, 252, 253 or 254 followed by a little-endian
+//!   `u16`, `u32`, `u64` or `u128`.
+//! - `BOOL` is a byte 0 or 1, `OPTION` a byte 0 for `None` or 1 followed by
+//!   the value.
+//! - `STR`, `BYTES` and `SEQ` are a varint length followed by the bytes or
+//!   the elements, `TUPLEARRAY` only its elements.
+//! - `STRUCT` and `TUPLE` are their fields in order.
+//! - `ENUM` is the index of the variant as a varint, followed by its
+//!   fields. The index is the key of the variant in the schema, not the
+//!   value the enum gives it in Rust: `ParamType::MemrefInout` is sent as 6,
+//!   not as `TEE_PARAM_TYPE_MEMREF_INOUT`.
+//!
+//! The JSON and CBOR codecs follow the serde data model the schema describes
+//! as well, with variants and fields named rather than numbered.
+//!
+//! # Stability
+//!
+//! Messages only evolve in ways a [`Schema`] checks with
+//! [`check_compatible`](Schema::check_compatible): containers, variants and
+//! fields are never removed, renamed or reordered, and the fields of a
+//! variant never change. New requests and responses are appended as new
+//! variants, with a new [`PROTOCOL_VERSION`], so that a CA built against an
+//! older schema still encodes and decodes every message it knows.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_reflection::{ContainerFormat, Named, Registry, Tracer, TracerConfig};
+
+use crate::protocol::{
+    MAX_FRAME_SIZE, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, ParamDelta, ParamType, ReturnOrigin,
+    TeeRequest, TeeResponse,
+};
+
+/// The CA protocol spoken by this manager.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Schema {
+    /// See [`PROTOCOL_VERSION`].
+    pub version: u32,
+    /// See [`MIN_PROTOCOL_VERSION`].
+    pub min_version: u32,
+    /// See [`MAX_FRAME_SIZE`].
+    pub max_frame_size: u32,
+    /// Format of every type sent on a connection, by name, `TeeRequest` and
+    /// `TeeResponse` being the messages.
+    pub types: Registry,
+}
+
+impl Schema {
+    /// Traces the schema of the protocol types.
+    ///
+    /// # Errors
+    ///
+    /// 1) If a type cannot be traced, which would be a bug of the protocol
+    ///    types.
+    pub fn new() -> anyhow::Result<Self> {
+        // The errors of serde-reflection hold formats, which are not `Send`.
+        let error = |e: serde_reflection::Error| anyhow::anyhow!("{}", e);
+        let mut tracer = Tracer::new(TracerConfig::default());
+        // Every enum is traced on its own, for all its variants to be.
+        tracer.trace_simple_type::<ParamType>().map_err(error)?;
+        tracer.trace_simple_type::<ReturnOrigin>().map_err(error)?;
+        tracer.trace_simple_type::<ParamDelta>().map_err(error)?;
+        tracer.trace_simple_type::<TeeRequest>().map_err(error)?;
+        tracer.trace_simple_type::<TeeResponse>().map_err(error)?;
+        Ok(Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_frame_size: MAX_FRAME_SIZE,
+            types: tracer.registry().map_err(error)?,
+        })
+    }
+
+    /// Returns the schema as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a schema always serializes")
+    }
+
+    /// Parses a schema printed by [`to_json`](Self::to_json), e.g. the one a
+    /// CA was built against.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Checks that a CA built against `older` can speak this schema: every
+    /// container of `older` exists, structs have the same fields, and enums
+    /// have the same variants at the same indices, possibly followed by new
+    /// ones.
+    ///
+    /// # Errors
+    ///
+    /// 1) A description of the first incompatible change found.
+    pub fn check_compatible(&self, older: &Schema) -> Result<(), String> {
+        for (name, old) in &older.types {
+            let new = self
+                .types
+                .get(name)
+                .ok_or_else(|| format!("{} was removed", name))?;
+            match (old, new) {
+                (ContainerFormat::Enum(old), ContainerFormat::Enum(new)) => {
+                    check_variants(name, old, new)?
+                }
+                _ if old == new => {}
+                _ => return Err(format!("{} changed", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Checks that `new` keeps every variant of `old` at its index, and only adds
+// variants after them.
+fn check_variants<T: PartialEq>(
+    name: &str,
+    old: &BTreeMap<u32, Named<T>>,
+    new: &BTreeMap<u32, Named<T>>,
+) -> Result<(), String> {
+    for (index, old) in old {
+        match new.get(index) {
+            Some(new) if new.name != old.name => {
+                return Err(format!(
+                    "variant {} of {} was renamed from {} to {}",
+                    index, name, old.name, new.name
+                ));
+            }
+            Some(new) if new.value != old.value => {
+                return Err(format!("variant {}::{} changed", name, old.name));
+            }
+            Some(_) => {}
+            None => return Err(format!("variant {}::{} was removed", name, old.name)),
+        }
+    }
+    Ok(())
+}
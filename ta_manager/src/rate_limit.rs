@@ -0,0 +1,85 @@
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+use crate::config::{RateLimit, RateLimitKey};
+use crate::peer::PeerCredentials;
+
+// Number of clients tracked past which the buckets that refilled are
+// dropped, a full bucket being the same as no bucket.
+const PRUNE_THRESHOLD: usize = 256;
+
+// Tokens available to a client, one taken per request.
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            refilled: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * f64::from(limit.requests_per_sec)).min(f64::from(limit.burst));
+        self.refilled = now;
+    }
+
+    fn take(&mut self, limit: &RateLimit) -> bool {
+        self.refill(limit, Instant::now());
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens + elapsed * f64::from(limit.requests_per_sec) >= f64::from(limit.burst)
+    }
+}
+
+// Rate limiter of the requests of CAs, keyed as configured.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    // Buckets by uid, under `RateLimitKey::Client`.
+    clients: Mutex<HashMap<u32, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Creates the bucket of a new connection, used under
+    // `RateLimitKey::Connection`.
+    pub(crate) fn connection_bucket(&self) -> TokenBucket {
+        TokenBucket::new(&self.limit)
+    }
+
+    // Takes a token for a request of `peer` sent on the connection owning
+    // `connection`. Returns whether the request may proceed.
+    pub(crate) fn allow(&self, peer: &PeerCredentials, connection: &mut TokenBucket) -> bool {
+        match self.limit.key {
+            RateLimitKey::Connection => connection.take(&self.limit),
+            RateLimitKey::Client => {
+                let mut clients = self.clients.lock().unwrap();
+                if clients.len() >= PRUNE_THRESHOLD && !clients.contains_key(&peer.uid) {
+                    let now = Instant::now();
+                    clients.retain(|_, bucket| !bucket.is_full(&self.limit, now));
+                }
+                clients
+                    .entry(peer.uid)
+                    .or_insert_with(|| TokenBucket::new(&self.limit))
+                    .take(&self.limit)
+            }
+        }
+    }
+}
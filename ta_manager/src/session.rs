@@ -0,0 +1,743 @@
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{
+    Receiver, RecvTimeoutError, Sender, TryRecvError, TrySendError, bounded, select, unbounded,
+};
+use optee_utee::{Error, ErrorKind, Identity, Result};
+use tracing::{Span, error, info_span, span, warn};
+
+use crate::TrustedApplication;
+use crate::config::QueuePolicy;
+use crate::context::CommandContext;
+use crate::peer::PeerCredentials;
+use crate::protocol::{BatchResult, MAX_TEMPLATES, Parameters, ReturnOrigin, TeeResponse};
+use crate::snapshot::PersistedSession;
+
+// Messages sent to session threads.
+pub(crate) enum SessionMessage {
+    Invoke {
+        cmd_id: u32,
+        params: Parameters,
+        context: CommandContext,
+        resp_tx: Sender<TeeResponse>,
+    },
+    // Runs the commands one after the other with a shared context, and
+    // answers with the outcome of each and how long it ran.
+    InvokeBatch {
+        commands: Vec<(u32, Parameters)>,
+        context: CommandContext,
+        resp_tx: Sender<Vec<(BatchResult, Duration)>>,
+    },
+    Close {
+        resp_tx: Sender<TeeResponse>,
+    },
+}
+
+// Sending end of the bounded queue of a session thread.
+#[derive(Clone)]
+pub(crate) struct SessionQueue {
+    tx: Sender<SessionMessage>,
+    policy: QueuePolicy,
+    // Receiving end used to drop the oldest command under
+    // `QueuePolicy::DropOldest`, released when the session thread exits so
+    // that senders notice it.
+    oldest: Arc<Mutex<Option<Receiver<SessionMessage>>>>,
+    // Command the session thread is executing, with when it started.
+    busy: Arc<Mutex<Option<(u32, Instant)>>>,
+    // Sender of `abandoned`, released when the session is abandoned to its
+    // thread so that the commands waiting for it notice.
+    abandon: Arc<Mutex<Option<Sender<()>>>>,
+    abandoned: Receiver<()>,
+    // Wakes the worker serving the session up, see `on_send`. Declared last
+    // so that `tx` is gone by the time the last queue drops it.
+    notify: Option<Arc<Notify>>,
+}
+
+// Callback of a session queue, also called once the queue is dropped for
+// the session worker to notice.
+struct Notify(Box<dyn Fn() + Send + Sync>);
+
+impl Drop for Notify {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+// Receiving end of the queue, owned by the session thread.
+pub(crate) struct SessionReceiver {
+    rx: Receiver<SessionMessage>,
+    oldest: Arc<Mutex<Option<Receiver<SessionMessage>>>>,
+    busy: Arc<Mutex<Option<(u32, Instant)>>>,
+    abandoned: Receiver<()>,
+}
+
+impl SessionReceiver {
+    fn is_abandoned(&self) -> bool {
+        self.abandoned.try_recv() == Err(TryRecvError::Disconnected)
+    }
+}
+
+impl Drop for SessionReceiver {
+    fn drop(&mut self) {
+        self.oldest.lock().unwrap().take();
+    }
+}
+
+// Creates the queue of a session holding up to `depth` messages.
+pub(crate) fn session_queue(depth: usize, policy: QueuePolicy) -> (SessionQueue, SessionReceiver) {
+    let (tx, rx) = bounded(depth.max(1));
+    let oldest = Arc::new(Mutex::new(
+        (policy == QueuePolicy::DropOldest).then(|| rx.clone()),
+    ));
+    let busy = Arc::default();
+    let (abandon, abandoned) = bounded(0);
+    let queue = SessionQueue {
+        tx,
+        policy,
+        oldest: oldest.clone(),
+        busy: Arc::clone(&busy),
+        abandon: Arc::new(Mutex::new(Some(abandon))),
+        abandoned: abandoned.clone(),
+        notify: None,
+    };
+    let receiver = SessionReceiver {
+        rx,
+        oldest,
+        busy,
+        abandoned,
+    };
+    (queue, receiver)
+}
+
+impl SessionQueue {
+    // Calls `notify` whenever a message is sent to the queue, and once the
+    // queue is dropped, for a session served by a worker pool.
+    pub(crate) fn on_send(mut self, notify: impl Fn() + Send + Sync + 'static) -> Self {
+        self.notify = Some(Arc::new(Notify(Box::new(notify))));
+        self
+    }
+
+    // Queues `msg` for the session thread. When the queue is full, a command
+    // that does not get queued is answered `Busy` on its response channel.
+    // Closing always waits for room. Fails if the session thread exited.
+    pub(crate) fn send(&self, msg: SessionMessage) -> std::result::Result<(), ()> {
+        let sent = self.enqueue(msg);
+        if let Some(notify) = &self.notify {
+            (notify.0)();
+        }
+        sent
+    }
+
+    fn enqueue(&self, msg: SessionMessage) -> std::result::Result<(), ()> {
+        if self.policy == QueuePolicy::Block || matches!(msg, SessionMessage::Close { .. }) {
+            return self.tx.send(msg).map_err(drop);
+        }
+        let msg = match self.tx.try_send(msg) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(_)) => return Err(()),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+        if self.policy == QueuePolicy::DropOldest {
+            let oldest = self
+                .oldest
+                .lock()
+                .unwrap()
+                .as_ref()
+                .and_then(|rx| rx.try_recv().ok());
+            match oldest {
+                // The session is being closed, keep closing it.
+                Some(close @ SessionMessage::Close { .. }) => self.tx.send(close).map_err(drop)?,
+                Some(oldest) => {
+                    reject(oldest);
+                    return self.tx.send(msg).map_err(drop);
+                }
+                None => return self.tx.send(msg).map_err(drop),
+            }
+        }
+        reject(msg);
+        Ok(())
+    }
+
+    // Waits for the response to a command queued with `resp_tx`, until
+    // `deadline` if any. Fails with `Disconnected` if the session thread
+    // exited or the session was abandoned.
+    pub(crate) fn wait<R>(
+        &self,
+        resp_rx: &Receiver<R>,
+        deadline: Option<Instant>,
+    ) -> std::result::Result<R, RecvTimeoutError> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match timeout {
+            Some(timeout) => select! {
+                recv(resp_rx) -> resp => resp.map_err(|_| RecvTimeoutError::Disconnected),
+                recv(self.abandoned) -> _ => Err(RecvTimeoutError::Disconnected),
+                default(timeout) => Err(RecvTimeoutError::Timeout),
+            },
+            None => select! {
+                recv(resp_rx) -> resp => resp.map_err(|_| RecvTimeoutError::Disconnected),
+                recv(self.abandoned) -> _ => Err(RecvTimeoutError::Disconnected),
+            },
+        }
+    }
+}
+
+fn reject(msg: SessionMessage) {
+    match msg {
+        SessionMessage::Invoke {
+            cmd_id,
+            params,
+            resp_tx,
+            ..
+        } => {
+            warn!(cmd_id, "Session queue full, rejecting command");
+            let _ = resp_tx.send(TeeResponse::InvokeCommand {
+                params,
+                result: ErrorKind::Busy.into(),
+                origin: ReturnOrigin::Tee,
+                retry: false,
+            });
+        }
+        SessionMessage::InvokeBatch {
+            commands, resp_tx, ..
+        } => {
+            warn!(
+                commands = commands.len(),
+                "Session queue full, rejecting batch"
+            );
+            let results = commands
+                .into_iter()
+                .map(|(_, params)| {
+                    let result = BatchResult {
+                        params,
+                        result: ErrorKind::Busy.into(),
+                        origin: ReturnOrigin::Tee,
+                    };
+                    (result, Duration::ZERO)
+                })
+                .collect();
+            let _ = resp_tx.send(results);
+        }
+        SessionMessage::Close { .. } => {}
+    }
+}
+
+struct SessionEntry {
+    queue: SessionQueue,
+    // Thread serving the session, unless a worker pool does.
+    thread: Option<JoinHandle<()>>,
+    peer: PeerCredentials,
+    identity: Identity,
+    last_active: Instant,
+    // Start of the command the watchdog reported as stuck.
+    stuck_since: Option<Instant>,
+    // Parameter templates registered on the session, by id.
+    templates: Vec<Arc<Parameters>>,
+}
+
+impl SessionEntry {
+    // Returns the command the session thread is executing, with how long
+    // it has been running.
+    fn busy(&self) -> Option<(u32, Instant)> {
+        *self.queue.busy.lock().unwrap()
+    }
+}
+
+/// Snapshot of an open session.
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub session_id: u32,
+    /// Credentials of the CA that opened the session.
+    pub peer: PeerCredentials,
+    /// Time elapsed since the session last received a request.
+    pub idle_for: Duration,
+    /// Time elapsed since the TA started the command it is executing on the
+    /// session, if any.
+    pub busy_for: Option<Duration>,
+    /// Whether the watchdog reported that command as stuck, see
+    /// [`TAManagerConfig::with_watchdog`](crate::TAManagerConfig::with_watchdog).
+    pub stuck: bool,
+}
+
+/// Table of the sessions opened on a [`TAManager`](crate::TAManager).
+///
+/// The table is shared with the manager, so a clone obtained through
+/// [`TAManager::session_table`](crate::TAManager::session_table) can be used
+/// to inspect and evict sessions while the manager is serving requests.
+#[derive(Clone, Default)]
+pub struct SessionTable {
+    inner: Arc<Mutex<HashMap<u32, SessionEntry>>>,
+}
+
+impl SessionTable {
+    pub(crate) fn insert(
+        &self,
+        session_id: u32,
+        queue: SessionQueue,
+        thread: Option<JoinHandle<()>>,
+        peer: PeerCredentials,
+        identity: Identity,
+    ) {
+        let entry = SessionEntry {
+            queue,
+            thread,
+            peer,
+            identity,
+            last_active: Instant::now(),
+            stuck_since: None,
+            templates: Vec::new(),
+        };
+        self.inner.lock().unwrap().insert(session_id, entry);
+    }
+
+    // Returns the queue of a session and marks it as active.
+    pub(crate) fn sender(&self, session_id: u32) -> Option<SessionQueue> {
+        let mut sessions = self.inner.lock().unwrap();
+        let entry = sessions.get_mut(&session_id)?;
+        entry.last_active = Instant::now();
+        Some(entry.queue.clone())
+    }
+
+    pub(crate) fn touch(&self, session_id: u32) {
+        if let Some(entry) = self.inner.lock().unwrap().get_mut(&session_id) {
+            entry.last_active = Instant::now();
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    // Returns the credentials of the CA that opened a session, with the
+    // identity it opened the session with.
+    pub(crate) fn owner(&self, session_id: u32) -> Option<(PeerCredentials, Identity)> {
+        let sessions = self.inner.lock().unwrap();
+        let entry = sessions.get(&session_id)?;
+        Some((entry.peer.clone(), entry.identity))
+    }
+
+    // Returns the id of every session with the uid and pid of the CA that
+    // opened it.
+    pub(crate) fn owners(&self) -> Vec<(u32, u32, i32)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| (*id, entry.peer.uid, entry.peer.pid))
+            .collect()
+    }
+
+    // Returns the number of sessions opened by CAs running as `uid`.
+    pub(crate) fn count_for_uid(&self, uid: u32) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.peer.uid == uid)
+            .count()
+    }
+
+    // Keeps `params` as a template of the session and returns its id.
+    pub(crate) fn add_template(
+        &self,
+        session_id: u32,
+        params: Parameters,
+    ) -> std::result::Result<u32, ErrorKind> {
+        let mut sessions = self.inner.lock().unwrap();
+        let entry = sessions
+            .get_mut(&session_id)
+            .ok_or(ErrorKind::ItemNotFound)?;
+        if entry.templates.len() >= MAX_TEMPLATES {
+            return Err(ErrorKind::OutOfMemory);
+        }
+        entry.templates.push(Arc::new(params));
+        Ok(entry.templates.len() as u32 - 1)
+    }
+
+    // Returns the template `template_id` of a session.
+    pub(crate) fn template(
+        &self,
+        session_id: u32,
+        template_id: u32,
+    ) -> std::result::Result<Arc<Parameters>, ErrorKind> {
+        let sessions = self.inner.lock().unwrap();
+        let entry = sessions.get(&session_id).ok_or(ErrorKind::ItemNotFound)?;
+        entry
+            .templates
+            .get(template_id as usize)
+            .cloned()
+            .ok_or(ErrorKind::BadParameters)
+    }
+
+    // Removes a session from the table, closes it on the TA and joins its
+    // thread. Returns `None` if the session does not exist.
+    pub(crate) fn close(&self, session_id: u32) -> Option<TeeResponse> {
+        let entry = self.inner.lock().unwrap().remove(&session_id)?;
+        Some(close_entry(entry))
+    }
+
+    // Removes a session whose thread died from the table.
+    pub(crate) fn remove(&self, session_id: u32) {
+        let entry = self.inner.lock().unwrap().remove(&session_id);
+        if let Some(thread) = entry.and_then(|entry| entry.thread) {
+            let _ = thread.join();
+        }
+    }
+
+    // Removes every session from the table without closing them on the TA,
+    // e.g. because their TA instance died, and returns their ids. The session
+    // threads exit once they notice their channel was closed.
+    pub(crate) fn drain(&self) -> Vec<u32> {
+        self.inner
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Lists the open sessions.
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let now = Instant::now();
+        let mut list: Vec<SessionInfo> = self
+            .inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| {
+                let busy = entry.busy();
+                SessionInfo {
+                    session_id: *id,
+                    peer: entry.peer.clone(),
+                    idle_for: now.duration_since(entry.last_active),
+                    busy_for: busy.map(|(_, since)| now.duration_since(since)),
+                    stuck: busy.is_some_and(|(_, since)| entry.stuck_since == Some(since)),
+                }
+            })
+            .collect();
+        list.sort_by_key(|info| info.session_id);
+        list
+    }
+
+    // Flags the sessions executing a command for `threshold` or longer, and
+    // returns those newly flagged with their command and its duration.
+    pub(crate) fn flag_stuck(&self, threshold: Duration) -> Vec<(u32, u32, Duration)> {
+        let now = Instant::now();
+        let mut flagged = Vec::new();
+        for (id, entry) in self.inner.lock().unwrap().iter_mut() {
+            let Some((cmd_id, since)) = entry.busy() else {
+                continue;
+            };
+            let busy_for = now.duration_since(since);
+            if busy_for >= threshold && entry.stuck_since != Some(since) {
+                entry.stuck_since = Some(since);
+                flagged.push((*id, cmd_id, busy_for));
+            }
+        }
+        flagged
+    }
+
+    // Removes a session from the table without waiting for its thread, which
+    // keeps running until the TA returns. The commands waiting for the
+    // thread are answered as if it died. Returns whether the session existed.
+    pub(crate) fn abandon(&self, session_id: u32) -> bool {
+        let Some(entry) = self.inner.lock().unwrap().remove(&session_id) else {
+            return false;
+        };
+        entry.queue.abandon.lock().unwrap().take();
+        true
+    }
+
+    /// Closes a session on the TA and removes it from the table.
+    pub fn evict(&self, session_id: u32) -> Result<()> {
+        match self.close(session_id) {
+            Some(TeeResponse::CloseSession { result: 0, .. }) => Ok(()),
+            Some(TeeResponse::CloseSession { result, origin }) => {
+                Err(Error::from_raw_error(result).with_origin(origin.into()))
+            }
+            Some(_) => Err(Error::new(ErrorKind::Generic)),
+            None => Err(Error::new(ErrorKind::ItemNotFound)),
+        }
+    }
+
+    /// Evicts every session idle for longer than `timeout` and returns their ids.
+    pub fn evict_idle(&self, timeout: Duration) -> Vec<u32> {
+        let idle: Vec<(u32, SessionEntry)> = {
+            let mut sessions = self.inner.lock().unwrap();
+            let ids: Vec<u32> = sessions
+                .iter()
+                .filter(|(_, entry)| entry.last_active.elapsed() >= timeout)
+                .map(|(id, _)| *id)
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| sessions.remove(&id).map(|entry| (id, entry)))
+                .collect()
+        };
+
+        idle.into_iter()
+            .map(|(id, entry)| {
+                close_entry(entry);
+                id
+            })
+            .collect()
+    }
+}
+
+// Asks the session thread to close the session and waits for it to exit.
+fn close_entry(entry: SessionEntry) -> TeeResponse {
+    let (resp_tx, resp_rx) = unbounded();
+    let resp = match entry.queue.send(SessionMessage::Close { resp_tx }) {
+        Ok(_) => resp_rx.recv().ok(),
+        Err(_) => None,
+    };
+    if let Some(thread) = entry.thread {
+        let _ = thread.join();
+    }
+
+    resp.unwrap_or(TeeResponse::CloseSession {
+        result: ErrorKind::TargetDead.into(),
+        origin: ReturnOrigin::Comms,
+    })
+}
+
+// Thread-local state the TA runs in for a session, entered around all it
+// does for the session.
+pub(crate) struct SessionEnv {
+    pub(crate) span: Span,
+    #[cfg(feature = "secure_storage")]
+    pub(crate) storage: Option<Arc<crate::storage::TaStorage>>,
+    #[cfg(feature = "secure_storage")]
+    pub(crate) tenant: Option<Vec<u8>>,
+    #[cfg(feature = "ta_sessions")]
+    pub(crate) router: Option<Arc<crate::ta_sessions::Router>>,
+    #[cfg(feature = "client_properties")]
+    pub(crate) client: Option<crate::properties::ClientProperties>,
+}
+
+// Guards of an entered `SessionEnv`, left in the reverse order.
+struct EnteredEnv<'a> {
+    #[cfg(feature = "client_properties")]
+    _client: Option<crate::properties::Entered>,
+    #[cfg(feature = "ta_sessions")]
+    _router: crate::ta_sessions::Entered,
+    #[cfg(feature = "secure_storage")]
+    _tenant: Option<crate::storage::TenantEntered>,
+    #[cfg(feature = "secure_storage")]
+    _storage: crate::storage::Entered,
+    _span: span::Entered<'a>,
+}
+
+impl SessionEnv {
+    pub(crate) fn new(span: Span) -> Self {
+        Self {
+            span,
+            #[cfg(feature = "secure_storage")]
+            storage: None,
+            #[cfg(feature = "secure_storage")]
+            tenant: None,
+            #[cfg(feature = "ta_sessions")]
+            router: None,
+            #[cfg(feature = "client_properties")]
+            client: None,
+        }
+    }
+
+    fn enter(&self) -> EnteredEnv<'_> {
+        EnteredEnv {
+            _span: self.span.enter(),
+            #[cfg(feature = "secure_storage")]
+            _storage: crate::storage::enter(self.storage.clone()),
+            #[cfg(feature = "secure_storage")]
+            _tenant: self.tenant.clone().map(crate::storage::enter_tenant),
+            #[cfg(feature = "ta_sessions")]
+            _router: crate::ta_sessions::enter(self.router.clone()),
+            #[cfg(feature = "client_properties")]
+            _client: self.client.clone().map(crate::properties::enter),
+        }
+    }
+}
+
+// A session being served, on a thread of its own or by a worker pool. If the
+// TA panics, the session ends without answering, which the dispatcher
+// reports as `TargetDead`. With `persisted`, the context of the session is
+// saved after every command.
+pub(crate) struct SessionRunner<T: TrustedApplication> {
+    ta: Arc<T>,
+    ctx: T::SessionContext,
+    rx: SessionReceiver,
+    persisted: Option<PersistedSession>,
+    env: SessionEnv,
+}
+
+impl<T: TrustedApplication> SessionRunner<T> {
+    pub(crate) fn new(
+        ta: Arc<T>,
+        ctx: T::SessionContext,
+        rx: SessionReceiver,
+        persisted: Option<PersistedSession>,
+        env: SessionEnv,
+    ) -> Self {
+        Self {
+            ta,
+            ctx,
+            rx,
+            persisted,
+            env,
+        }
+    }
+
+    // Serves the session on the current thread until it ends.
+    pub(crate) fn run(mut self) {
+        while let Ok(msg) = self.rx.rx.recv() {
+            if !self.handle(msg) {
+                return;
+            }
+        }
+    }
+
+    // Handles the next message of the session, if there is one. Returns
+    // false once the session ended, including when its queue was dropped or
+    // the session abandoned.
+    pub(crate) fn poll(&mut self) -> bool {
+        match self.rx.rx.try_recv() {
+            Ok(msg) => self.handle(msg),
+            Err(TryRecvError::Empty) => !self.rx.is_abandoned(),
+            Err(TryRecvError::Disconnected) => false,
+        }
+    }
+
+    // Handles `msg`. Returns false once the session ended.
+    fn handle(&mut self, msg: SessionMessage) -> bool {
+        let _entered = self.env.enter();
+        let ta = &*self.ta;
+        match msg {
+            SessionMessage::Invoke {
+                cmd_id,
+                mut params,
+                context,
+                resp_tx,
+            } => {
+                context.mark_started();
+                let Some(result) =
+                    run_command(ta, &mut self.ctx, &self.rx, cmd_id, &mut params, &context)
+                else {
+                    return false;
+                };
+                if let Some(persisted) = self.persisted.as_mut() {
+                    persisted.update(ta.serialize_session(&self.ctx));
+                }
+                let _ = resp_tx.send(TeeResponse::InvokeCommand {
+                    params,
+                    result,
+                    origin: ReturnOrigin::TrustedApp,
+                    retry: false,
+                });
+                true
+            }
+            SessionMessage::InvokeBatch {
+                commands,
+                context,
+                resp_tx,
+            } => {
+                context.mark_started();
+                let mut results = Vec::with_capacity(commands.len());
+                for (cmd_id, mut params) in commands {
+                    if context.is_cancelled() {
+                        let result = BatchResult {
+                            params,
+                            result: ErrorKind::Cancel.into(),
+                            origin: ReturnOrigin::Tee,
+                        };
+                        results.push((result, Duration::ZERO));
+                        continue;
+                    }
+                    let started = Instant::now();
+                    let Some(result) =
+                        run_command(ta, &mut self.ctx, &self.rx, cmd_id, &mut params, &context)
+                    else {
+                        return false;
+                    };
+                    let result = BatchResult {
+                        params,
+                        result,
+                        origin: ReturnOrigin::TrustedApp,
+                    };
+                    results.push((result, started.elapsed()));
+                }
+                if let Some(persisted) = self.persisted.as_mut() {
+                    persisted.update(ta.serialize_session(&self.ctx));
+                }
+                let _ = resp_tx.send(results);
+                true
+            }
+            SessionMessage::Close { resp_tx } => {
+                let ctx = &mut self.ctx;
+                let result = panic::catch_unwind(AssertUnwindSafe(|| ta.close_session(ctx)));
+                let resp = match result {
+                    Ok(Ok(_)) => TeeResponse::CloseSession {
+                        result: 0,
+                        origin: ReturnOrigin::TrustedApp,
+                    },
+                    Ok(Err(e)) => TeeResponse::CloseSession {
+                        result: e.raw_code(),
+                        origin: ReturnOrigin::TrustedApp,
+                    },
+                    Err(_) => {
+                        error!("TA panicked while closing a session");
+                        return false;
+                    }
+                };
+                drop(self.persisted.take());
+                let _ = resp_tx.send(resp);
+                false
+            }
+        }
+    }
+}
+
+// Invokes a command on the TA, flagging the session busy meanwhile, and
+// returns its raw result. Returns `None` if the thread must exit instead of
+// answering: the TA panicked, or the session was abandoned meanwhile. The
+// TA runs in a `command` span carrying the trace id of the request.
+fn run_command<T: TrustedApplication>(
+    ta: &T,
+    ctx: &mut T::SessionContext,
+    rx: &SessionReceiver,
+    cmd_id: u32,
+    params: &mut Parameters,
+    context: &CommandContext,
+) -> Option<u32> {
+    let trace_id = context.trace_id();
+    let _entered =
+        info_span!("command", cmd_id, trace_id = %format_args!("{:016x}", trace_id)).entered();
+    *rx.busy.lock().unwrap() = Some((cmd_id, Instant::now()));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        ta.invoke_command_with_context(cmd_id, params, ctx, context)
+    }));
+    *rx.busy.lock().unwrap() = None;
+    if rx.is_abandoned() {
+        // The commands queued behind were already answered.
+        warn!(
+            cmd_id,
+            "Stuck command returned after its session was closed"
+        );
+        return None;
+    }
+    match result {
+        Ok(Ok(_)) => Some(0),
+        Ok(Err(e)) => Some(e.raw_code()),
+        Err(_) => {
+            error!(cmd_id, "TA panicked while invoking a command");
+            None
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use bincode::{Decode, Encode};
+use tracing::warn;
+
+use crate::peer::{PeerCredentials, SecurityLabel};
+use crate::protocol::ClientIdentity;
+
+// Saved state of a session, enough to serve it again after the manager
+// restarts.
+#[derive(Encode, Decode, Clone)]
+pub(crate) struct SessionSnapshot {
+    pub(crate) session_id: u32,
+    pub(crate) identity: ClientIdentity,
+    uid: u32,
+    gid: u32,
+    pid: i32,
+    groups: Vec<u32>,
+    label: Option<String>,
+    // Context of the session, as serialized by the TA.
+    pub(crate) state: Vec<u8>,
+}
+
+impl SessionSnapshot {
+    pub(crate) fn new(
+        session_id: u32,
+        identity: ClientIdentity,
+        peer: &PeerCredentials,
+        state: Vec<u8>,
+    ) -> Self {
+        Self {
+            session_id,
+            identity,
+            uid: peer.uid,
+            gid: peer.gid,
+            pid: peer.pid,
+            groups: peer.groups.clone(),
+            label: peer.label.as_ref().map(|label| label.as_str().to_string()),
+            state,
+        }
+    }
+
+    // Returns the credentials of the CA that opened the session.
+    pub(crate) fn peer(&self) -> PeerCredentials {
+        PeerCredentials {
+            uid: self.uid,
+            gid: self.gid,
+            pid: self.pid,
+            groups: self.groups.clone(),
+            label: self.label.clone().map(SecurityLabel::new),
+        }
+    }
+}
+
+// Contents of the session state file.
+#[derive(Encode, Decode, Default)]
+struct SessionFile {
+    // First session id never handed out, so that a restarted manager does
+    // not reuse the ids of sessions that were not saved.
+    next_session_id: u32,
+    sessions: BTreeMap<u32, SessionSnapshot>,
+}
+
+// Session table persisted in a file, rewritten whenever a session changes.
+pub(crate) struct SessionStore {
+    path: PathBuf,
+    file: Mutex<SessionFile>,
+    // Set once the sessions were handed over to another manager, which then
+    // owns the file.
+    detached: AtomicBool,
+}
+
+impl SessionStore {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Mutex::default(),
+            detached: AtomicBool::new(false),
+        }
+    }
+
+    // Reads the sessions saved by the previous manager, with the id of its
+    // next session. A missing file holds no session.
+    pub(crate) fn load(&self) -> io::Result<(u32, Vec<SessionSnapshot>)> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, Vec::new())),
+            Err(e) => return Err(e),
+        };
+        let (file, _): (SessionFile, _) =
+            bincode::decode_from_slice(&data, bincode::config::standard())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok((file.next_session_id, file.sessions.into_values().collect()))
+    }
+
+    // Records that session ids below `next_session_id` were handed out.
+    pub(crate) fn reserve(&self, next_session_id: u32) {
+        let mut file = self.file.lock().unwrap();
+        if next_session_id > file.next_session_id {
+            file.next_session_id = next_session_id;
+            self.write(&file);
+        }
+    }
+
+    pub(crate) fn save(&self, snapshot: SessionSnapshot) {
+        let mut file = self.file.lock().unwrap();
+        file.sessions.insert(snapshot.session_id, snapshot);
+        self.write(&file);
+    }
+
+    pub(crate) fn remove(&self, session_id: u32) {
+        let mut file = self.file.lock().unwrap();
+        if file.sessions.remove(&session_id).is_some() {
+            self.write(&file);
+        }
+    }
+
+    // Stops or resumes writing the file.
+    pub(crate) fn set_detached(&self, detached: bool) {
+        self.detached.store(detached, Ordering::SeqCst);
+    }
+
+    // Replaces the file, atomically. Failures are logged, the sessions
+    // being served anyway.
+    fn write(&self, file: &SessionFile) {
+        if self.detached.load(Ordering::SeqCst) {
+            return;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let result = bincode::encode_to_vec(file, bincode::config::standard())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|data| {
+                // Session contexts may hold secrets of the CA.
+                let mut options = OpenOptions::new();
+                options.write(true).create(true).truncate(true);
+                #[cfg(unix)]
+                std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+                options.open(&tmp)?.write_all(&data)
+            })
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if let Err(e) = result {
+            warn!(path = ?self.path, error = ?e, "Failed to save the sessions");
+        }
+    }
+}
+
+// Session kept in a `SessionStore` by its session thread while the TA
+// serializes its context, removed from it when the thread exits.
+pub(crate) struct PersistedSession {
+    store: Arc<SessionStore>,
+    snapshot: SessionSnapshot,
+    // Whether `snapshot` is in the store.
+    saved: bool,
+}
+
+impl PersistedSession {
+    pub(crate) fn new(store: Arc<SessionStore>, snapshot: SessionSnapshot) -> Self {
+        Self {
+            store,
+            snapshot,
+            saved: false,
+        }
+    }
+
+    // Saves the context of the session as serialized by the TA, or forgets
+    // the session if the TA no longer serializes it.
+    pub(crate) fn update(&mut self, state: Option<Vec<u8>>) {
+        match state {
+            Some(state) if self.saved && state == self.snapshot.state => {}
+            Some(state) => {
+                self.snapshot.state = state;
+                self.store.save(self.snapshot.clone());
+                self.saved = true;
+            }
+            None if self.saved => {
+                self.store.remove(self.snapshot.session_id);
+                self.saved = false;
+            }
+            None => {}
+        }
+    }
+}
+
+impl Drop for PersistedSession {
+    fn drop(&mut self) {
+        self.update(None);
+    }
+}
@@ -0,0 +1,1295 @@
+//! Emulation of the GP persistent objects for TAs hosted by a
+//! [`TAManager`](crate::TAManager).
+//!
+//! The `TEE_*` persistent object functions called by `optee_utee::object`
+//! are implemented here on a directory of the REE filesystem. Every TA gets
+//! a subdirectory named after its uuid holding one file per object, named
+//! after the hex encoding of the object id and encrypted with AES-256-GCM
+//! under a key derived from the storage key and the TA uuid.
+//!
+//! The storage used by a call is that of the TA whose manager runs the
+//! calling thread. Calls from other threads fail with
+//! `TEE_ERROR_STORAGE_NOT_AVAILABLE`.
+//!
+//! With [`TAManagerConfig::with_tenant_isolation`](crate::TAManagerConfig::with_tenant_isolation)
+//! the calls made on behalf of a session only reach the objects of the
+//! tenant of its client: object ids are prefixed with the
+//! [`TenantContext`](optee_utee::tenant::TenantContext) namespace before
+//! reaching the files, and enumerations only list the objects of the tenant,
+//! under the ids the TA gave them. Calls made outside of sessions, e.g. from
+//! `create`, see the whole storage of the TA.
+//!
+//! Data written to an object is kept in memory until a handle on the object
+//! is closed, and only then written to its file. Files are replaced
+//! atomically: the new file is written and synced under a temporary name,
+//! renamed over the old one, and the directory synced.
+//!
+//! A manifest next to the objects, encrypted under its own key derived from
+//! that of the TA, lists the objects with the SHA-256 of their files. An
+//! object whose file was replaced by an older one, deleted, or copied from
+//! another object fails to open with `TEE_ERROR_CORRUPT_OBJECT`. Rolling
+//! back the whole directory, manifest included, is not detected: that would
+//! take checking the version of the manifest against a monotonic counter the
+//! REE cannot reset, like an RPMB partition. A storage found without a
+//! manifest, e.g. one written before manifests were kept, adopts the objects
+//! it holds.
+//!
+//! When the manager starts, the storage of the TA is checked by writing and
+//! reading back a probe file, reading the manifest, and decrypting every
+//! object. If the check fails, the [`StorageFailurePolicy`] of the storage
+//! decides whether the manager fails to start or serves the TA with degraded
+//! storage, which the TA learns through [`storage_mode`].
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    os::raw::c_void,
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::{Arc, Mutex},
+};
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload},
+};
+use optee_utee::ErrorKind;
+use optee_utee_sys as raw;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+const NONCE_LEN: usize = 12;
+
+// File written and read back by the startup check. Not the hex encoding of
+// an object id, so never listed as an object.
+const PROBE_FILE: &str = ".probe";
+
+// File of the manifest, not listed as an object either.
+const MANIFEST_FILE: &str = ".manifest";
+
+// SHA-256 of the file of an object.
+type FileHash = [u8; 32];
+
+/// What a manager does when the storage of its TA fails the startup check,
+/// e.g. because its directory is read-only or an object is corrupt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageFailurePolicy {
+    /// The manager fails to start with [`ManagerError::Storage`](crate::ManagerError::Storage).
+    #[default]
+    Fatal,
+    /// The TA keeps reading the objects that can be read, and fails to
+    /// create, write, rename or delete objects with `StorageNotAvailable`.
+    ReadOnly,
+    /// The TA gets an empty storage kept in memory, lost when the manager
+    /// stops. The objects on disk are left alone.
+    InMemory,
+}
+
+/// How the objects of the TA are stored, returned by [`storage_mode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageMode {
+    /// Objects are read and written on disk.
+    Persistent,
+    /// The storage failed the startup check: objects are only read, see
+    /// [`StorageFailurePolicy::ReadOnly`].
+    ReadOnly,
+    /// The storage failed the startup check: objects only live in memory,
+    /// see [`StorageFailurePolicy::InMemory`].
+    InMemory,
+}
+
+/// Returns how the objects of the TA served by the current thread are
+/// stored, e.g. for a TA to refuse the commands that need to persist keys
+/// while still serving those that only use them. `None` on threads that do
+/// not serve a TA with secure storage.
+pub fn storage_mode() -> Option<StorageMode> {
+    current().map(|storage| storage.mode)
+}
+
+/// Location and key of the secure storage emulated for the hosted TAs, see
+/// [`TAManagerConfig::with_secure_storage`](crate::TAManagerConfig::with_secure_storage).
+#[derive(Clone)]
+pub struct SecureStorage {
+    dir: PathBuf,
+    key: [u8; 32],
+    failure_policy: StorageFailurePolicy,
+}
+
+impl SecureStorage {
+    /// Keeps the objects under `dir`, encrypted with keys derived from `key`.
+    pub fn new(dir: impl Into<PathBuf>, key: [u8; 32]) -> Self {
+        Self {
+            dir: dir.into(),
+            key,
+            failure_policy: StorageFailurePolicy::default(),
+        }
+    }
+
+    /// Sets what to do when the storage fails the startup check,
+    /// [`StorageFailurePolicy::Fatal`] by default.
+    pub fn with_failure_policy(mut self, policy: StorageFailurePolicy) -> Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    // Returns the storage of the TA `uuid`, degraded according to the
+    // failure policy if it fails the startup check.
+    pub(crate) fn for_ta(&self, uuid: &str) -> io::Result<Arc<TaStorage>> {
+        let key = Sha256::new()
+            .chain_update(self.key)
+            .chain_update(uuid.as_bytes())
+            .finalize();
+        // Under a key of its own, no object file decrypts as the manifest.
+        let manifest_key = Sha256::new()
+            .chain_update(key)
+            .chain_update(MANIFEST_FILE)
+            .finalize();
+        let mut storage = TaStorage {
+            dir: self.dir.join(uuid),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            manifest_cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&manifest_key)),
+            mode: StorageMode::Persistent,
+            memory: Mutex::new(HashMap::new()),
+            manifest: Mutex::new(Manifest::default()),
+            objects: Mutex::new(HashMap::new()),
+        };
+        if let Err(e) = storage.check() {
+            storage.mode = match self.failure_policy {
+                StorageFailurePolicy::Fatal => return Err(e),
+                StorageFailurePolicy::ReadOnly => StorageMode::ReadOnly,
+                StorageFailurePolicy::InMemory => StorageMode::InMemory,
+            };
+            error!(error = %e, mode = ?storage.mode, "Secure storage failed its check, degrading it");
+        }
+        Ok(Arc::new(storage))
+    }
+}
+
+impl fmt::Debug for SecureStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecureStorage")
+            .field("dir", &self.dir)
+            .field("failure_policy", &self.failure_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+// Storage of one TA.
+pub(crate) struct TaStorage {
+    dir: PathBuf,
+    cipher: Aes256Gcm,
+    manifest_cipher: Aes256Gcm,
+    mode: StorageMode,
+    // Data of the objects in `StorageMode::InMemory`, by id.
+    memory: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+    // Manifest of the objects on disk, locked while one is replaced.
+    manifest: Mutex<Manifest>,
+    // Objects with open handles, by id. Locked before `manifest`.
+    objects: Mutex<HashMap<Vec<u8>, OpenObject>>,
+}
+
+// Data of an object shared by its open handles, with their flags.
+struct OpenObject {
+    data: Vec<u8>,
+    handles: Vec<u32>,
+    // Whether `data` was changed since it was last stored.
+    dirty: bool,
+}
+
+// Objects stored on disk, with the states their files may be in: the hash
+// of the file, or `None` for no file. An object has a single state, or two
+// while its file is replaced or removed, or after a replacement that was
+// interrupted, until the startup check finds which one the file is in.
+// Objects missing from the manifest have no file.
+#[derive(Debug, Default, PartialEq)]
+struct Manifest {
+    // Incremented every time the manifest is written.
+    version: u64,
+    objects: BTreeMap<Vec<u8>, Vec<Option<FileHash>>>,
+}
+
+impl Manifest {
+    fn states(&self, id: &[u8]) -> Vec<Option<FileHash>> {
+        self.objects.get(id).cloned().unwrap_or_else(|| vec![None])
+    }
+
+    fn set(&mut self, id: &[u8], states: Vec<Option<FileHash>>) {
+        if states == [None] {
+            self.objects.remove(id);
+        } else {
+            self.objects.insert(id.to_vec(), states);
+        }
+    }
+
+    // Version, number of objects, then for each object its length-prefixed
+    // id and its states, each a 0 byte for no file or a 1 byte and the hash.
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.version.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(self.objects.len() as u32).to_le_bytes());
+        for (id, states) in &self.objects {
+            bytes.extend_from_slice(&(id.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(id);
+            bytes.push(states.len() as u8);
+            for state in states {
+                match state {
+                    Some(hash) => {
+                        bytes.push(1);
+                        bytes.extend_from_slice(hash);
+                    }
+                    None => bytes.push(0),
+                }
+            }
+        }
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Option<Self> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            let (head, tail) = bytes.split_at_checked(len)?;
+            *bytes = tail;
+            Some(head)
+        }
+        fn take_u32(bytes: &mut &[u8]) -> Option<usize> {
+            Some(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?) as usize)
+        }
+        let version = u64::from_le_bytes(take(&mut bytes, 8)?.try_into().ok()?);
+        let mut objects = BTreeMap::new();
+        for _ in 0..take_u32(&mut bytes)? {
+            let len = take_u32(&mut bytes)?;
+            let id = take(&mut bytes, len)?.to_vec();
+            let count = take(&mut bytes, 1)?[0];
+            let states = (0..count)
+                .map(|_| match take(&mut bytes, 1)?[0] {
+                    0 => Some(None),
+                    1 => Some(Some(take(&mut bytes, 32)?.try_into().ok()?)),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            objects.insert(id, states);
+        }
+        bytes.is_empty().then_some(Self { version, objects })
+    }
+}
+
+// What a `TEE_ObjectHandle` returned by this module points to.
+struct Handle {
+    storage: Arc<TaStorage>,
+    id: Vec<u8>,
+    flags: u32,
+    position: usize,
+}
+
+// What a `TEE_ObjectEnumHandle` returned by this module points to.
+#[derive(Default)]
+struct Enumerator {
+    storage: Option<Arc<TaStorage>>,
+    ids: Vec<Vec<u8>>,
+    // Length of the tenant namespace the ids start with.
+    prefix_len: usize,
+    next: usize,
+}
+
+type TeeResult<T> = Result<T, ErrorKind>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<TaStorage>>> = const { RefCell::new(None) };
+    // Namespace of the tenant served by the current thread, empty without
+    // tenant isolation.
+    static TENANT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the storage of the TA served by the current thread.
+pub(crate) fn current() -> Option<Arc<TaStorage>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Makes `storage` that of the current thread until the guard is dropped.
+pub(crate) fn enter(storage: Option<Arc<TaStorage>>) -> Entered {
+    Entered(CURRENT.with(|current| current.replace(storage)))
+}
+
+pub(crate) struct Entered(Option<Arc<TaStorage>>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Confines the calls of the current thread to the objects under
+/// `namespace` until the guard is dropped.
+pub(crate) fn enter_tenant(namespace: Vec<u8>) -> TenantEntered {
+    TenantEntered(TENANT.with(|tenant| tenant.replace(namespace)))
+}
+
+pub(crate) struct TenantEntered(Vec<u8>);
+
+impl Drop for TenantEntered {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.0);
+        TENANT.with(|tenant| *tenant.borrow_mut() = previous);
+    }
+}
+
+// Maps an object id of the TA into the namespace of the current tenant.
+fn namespaced(id: &[u8]) -> Vec<u8> {
+    TENANT.with(|tenant| [tenant.borrow().as_slice(), id].concat())
+}
+
+impl TaStorage {
+    // Checks that files can be written and read back in the directory of the
+    // TA, that the manifest can be read, and that every object decrypts and
+    // is in a state the manifest lists.
+    fn check(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Read first, so that a storage degraded for failing the rest of the
+        // check still checks the objects it reads.
+        self.read_manifest()?;
+        let probe = self.dir.join(PROBE_FILE);
+        fs::write(&probe, PROBE_FILE)?;
+        let read = fs::read(&probe);
+        fs::remove_file(&probe)?;
+        if read? != PROBE_FILE.as_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "probe file read back differs",
+            ));
+        }
+        let mut ids = self.file_ids()?;
+        ids.extend(self.manifest.lock().unwrap().objects.keys().cloned());
+        ids.sort();
+        ids.dedup();
+        for id in &ids {
+            if let Err(kind) = self.load(id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("object {:?} cannot be read: {}", self.path(id), kind),
+                ));
+            }
+        }
+        self.settle()
+    }
+
+    // Reads the manifest, or without one builds it from the objects found.
+    fn read_manifest(&self) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "manifest cannot be read");
+        let manifest = match fs::read(self.dir.join(MANIFEST_FILE)) {
+            Ok(file) => {
+                let bytes = decrypt(&self.manifest_cipher, &file, MANIFEST_FILE.as_bytes())
+                    .map_err(|_| invalid())?;
+                Manifest::decode(&bytes).ok_or_else(invalid)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut manifest = Manifest::default();
+                for id in self.file_ids()? {
+                    let hash = file_hash(&fs::read(self.path(&id))?);
+                    manifest.set(&id, vec![Some(hash)]);
+                }
+                if !manifest.objects.is_empty() {
+                    warn!(
+                        objects = manifest.objects.len(),
+                        "Secure storage has no manifest, adopting the objects found"
+                    );
+                }
+                let mut current = self.manifest.lock().unwrap();
+                *current = manifest;
+                return self.write_manifest(&mut current);
+            }
+            Err(e) => return Err(e),
+        };
+        info!(version = manifest.version, "Secure storage manifest read");
+        *self.manifest.lock().unwrap() = manifest;
+        Ok(())
+    }
+
+    // Resolves the objects left with several states by interrupted
+    // replacements into the state their file is in.
+    fn settle(&self) -> io::Result<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        let pending: Vec<Vec<u8>> = manifest
+            .objects
+            .iter()
+            .filter(|(_, states)| states.len() > 1)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        for id in pending {
+            let state = match fs::read(self.path(&id)) {
+                Ok(file) => Some(file_hash(&file)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            manifest.set(&id, vec![state]);
+        }
+        self.write_manifest(&mut manifest)
+    }
+
+    fn write_manifest(&self, manifest: &mut Manifest) -> io::Result<()> {
+        manifest.version += 1;
+        let file = encrypt(
+            &self.manifest_cipher,
+            &manifest.encode(),
+            MANIFEST_FILE.as_bytes(),
+        )
+        .map_err(|_| io::Error::other("manifest cannot be encrypted"))?;
+        self.replace(&self.dir.join(MANIFEST_FILE), &file)
+    }
+
+    // Replaces the file at `path` with `contents`, atomically and durably.
+    fn replace(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        self.sync_dir()
+    }
+
+    // Makes the renames and removals in the directory of the TA durable.
+    // Windows cannot open directories to sync them, and NTFS journals them.
+    fn sync_dir(&self) -> io::Result<()> {
+        #[cfg(unix)]
+        return File::open(&self.dir)?.sync_all();
+        #[cfg(windows)]
+        return Ok(());
+    }
+
+    fn path(&self, id: &[u8]) -> PathBuf {
+        let name: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(name)
+    }
+
+    fn load(&self, id: &[u8]) -> TeeResult<Option<Vec<u8>>> {
+        if self.mode == StorageMode::InMemory {
+            return Ok(self.memory.lock().unwrap().get(id).cloned());
+        }
+        let file = match fs::read(self.path(id)) {
+            Ok(file) => Some(file),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(io_error(e)),
+        };
+        let state = file.as_deref().map(file_hash);
+        if !self.manifest.lock().unwrap().states(id).contains(&state) {
+            warn!(path = ?self.path(id), "Object file differs from the manifest");
+            return Err(ErrorKind::CorruptObject);
+        }
+        file.map(|file| decrypt(&self.cipher, &file, id))
+            .transpose()
+    }
+
+    // Replaces the file of an object, atomically.
+    fn store(&self, id: &[u8], data: &[u8]) -> TeeResult<()> {
+        match self.mode {
+            StorageMode::Persistent => {}
+            StorageMode::ReadOnly => return Err(ErrorKind::StorageNotAvailable),
+            StorageMode::InMemory => {
+                self.memory
+                    .lock()
+                    .unwrap()
+                    .insert(id.to_vec(), data.to_vec());
+                return Ok(());
+            }
+        }
+        let file = encrypt(&self.cipher, data, id)?;
+        fs::create_dir_all(&self.dir).map_err(io_error)?;
+        self.change(id, Some(file_hash(&file)), || {
+            self.replace(&self.path(id), &file)
+        })
+    }
+
+    fn remove(&self, id: &[u8]) -> TeeResult<()> {
+        match self.mode {
+            StorageMode::Persistent => self.change(id, None, || {
+                fs::remove_file(self.path(id))?;
+                self.sync_dir()
+            }),
+            StorageMode::ReadOnly => Err(ErrorKind::StorageNotAvailable),
+            StorageMode::InMemory => {
+                self.memory.lock().unwrap().remove(id);
+                Ok(())
+            }
+        }
+    }
+
+    // Brings the file of the object `id` into `state` with `apply`, listing
+    // the new state in the manifest before and the new state alone after, so
+    // that the manifest lists the state of the file whenever it stops.
+    fn change(
+        &self,
+        id: &[u8],
+        state: Option<FileHash>,
+        apply: impl FnOnce() -> io::Result<()>,
+    ) -> TeeResult<()> {
+        let mut manifest = self.manifest.lock().unwrap();
+        let mut states = manifest.states(id);
+        if !states.contains(&state) {
+            states.push(state);
+        }
+        manifest.set(id, states);
+        self.write_manifest(&mut manifest).map_err(io_error)?;
+        apply().map_err(io_error)?;
+        manifest.set(id, vec![state]);
+        self.write_manifest(&mut manifest).map_err(io_error)
+    }
+
+    fn exists(&self, id: &[u8]) -> bool {
+        match self.mode {
+            StorageMode::InMemory => self.memory.lock().unwrap().contains_key(id),
+            _ => self.path(id).exists(),
+        }
+    }
+
+    // Registers a new handle with `flags` on the object `id`, whose data is
+    // `data` if it is not open yet.
+    fn attach(
+        &self,
+        id: &[u8],
+        flags: u32,
+        data: impl FnOnce() -> TeeResult<Vec<u8>>,
+    ) -> TeeResult<()> {
+        let mut objects = self.objects.lock().unwrap();
+        match objects.get_mut(id) {
+            Some(object) => {
+                if object.handles.iter().any(|other| conflicts(*other, flags)) {
+                    return Err(ErrorKind::AccessConflict);
+                }
+                object.handles.push(flags);
+            }
+            None => {
+                let data = data()?;
+                objects.insert(
+                    id.to_vec(),
+                    OpenObject {
+                        data,
+                        handles: vec![flags],
+                        dirty: false,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // Unregisters a handle with `flags` on the object `id`, storing the data
+    // written to the object since it was last stored.
+    fn detach(&self, id: &[u8], flags: u32) -> TeeResult<()> {
+        let mut objects = self.objects.lock().unwrap();
+        let Some(object) = objects.get_mut(id) else {
+            return Ok(());
+        };
+        if let Some(index) = object.handles.iter().position(|other| *other == flags) {
+            object.handles.swap_remove(index);
+        }
+        let stored = match object.dirty {
+            true => self.store(id, &object.data),
+            false => Ok(()),
+        };
+        object.dirty &= stored.is_err();
+        if object.handles.is_empty() {
+            objects.remove(id);
+        }
+        stored
+    }
+
+    fn ids(&self) -> TeeResult<Vec<Vec<u8>>> {
+        if self.mode == StorageMode::InMemory {
+            return Ok(self.memory.lock().unwrap().keys().cloned().collect());
+        }
+        self.file_ids().map_err(io_error)
+    }
+
+    // Ids of the objects with a file.
+    fn file_ids(&self) -> io::Result<Vec<Vec<u8>>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| decode_hex(entry.file_name().to_str()?))
+            .collect())
+    }
+
+    // Size of the data of the object `id`, including the data not stored yet.
+    fn data_size(&self, id: &[u8]) -> usize {
+        if let Some(object) = self.objects.lock().unwrap().get(id) {
+            return object.data.len();
+        }
+        self.load(id).ok().flatten().map_or(0, |data| data.len())
+    }
+}
+
+// Encrypts `data` bound to `aad` under a fresh nonce, prepended.
+fn encrypt(cipher: &Aes256Gcm, data: &[u8], aad: &[u8]) -> TeeResult<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|_| ErrorKind::Generic)?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(cipher: &Aes256Gcm, file: &[u8], aad: &[u8]) -> TeeResult<Vec<u8>> {
+    if file.len() < NONCE_LEN {
+        return Err(ErrorKind::CorruptObject);
+    }
+    let (nonce, ciphertext) = file.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| ErrorKind::CorruptObject)
+}
+
+fn file_hash(file: &[u8]) -> FileHash {
+    Sha256::digest(file).into()
+}
+
+// Whether handles opened with `a` and `b` may not coexist: handles on the
+// same object must share it in the same way, allow each other's access, and
+// none may be opened to write its metadata.
+fn conflicts(a: u32, b: u32) -> bool {
+    let share = raw::TEE_DATA_FLAG_SHARE_READ | raw::TEE_DATA_FLAG_SHARE_WRITE;
+    let shared = |access: u32, share: u32| (a | b) & access == 0 || a & share != 0;
+    (a | b) & raw::TEE_DATA_FLAG_ACCESS_WRITE_META != 0
+        || a & share != b & share
+        || !shared(
+            raw::TEE_DATA_FLAG_ACCESS_READ,
+            raw::TEE_DATA_FLAG_SHARE_READ,
+        )
+        || !shared(
+            raw::TEE_DATA_FLAG_ACCESS_WRITE,
+            raw::TEE_DATA_FLAG_SHARE_WRITE,
+        )
+}
+
+fn io_error(e: std::io::Error) -> ErrorKind {
+    warn!(error = %e, "Secure storage I/O failed");
+    match e.kind() {
+        std::io::ErrorKind::NotFound => ErrorKind::ItemNotFound,
+        std::io::ErrorKind::StorageFull => ErrorKind::StorageNoSpace,
+        _ => ErrorKind::StorageNotAvailable,
+    }
+}
+
+fn decode_hex(name: &str) -> Option<Vec<u8>> {
+    if !name.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn result(result: TeeResult<()>) -> raw::TEE_Result {
+    match result {
+        Ok(()) => raw::TEE_SUCCESS,
+        Err(kind) => kind.into(),
+    }
+}
+
+// Reads an object id passed by the TA.
+unsafe fn object_id<'a>(id: *const c_void, len: usize) -> TeeResult<&'a [u8]> {
+    if len > raw::TEE_OBJECT_ID_MAX_LEN as usize || (id.is_null() && len != 0) {
+        return Err(ErrorKind::BadParameters);
+    }
+    if len == 0 {
+        return Ok(&[]);
+    }
+    Ok(unsafe { slice::from_raw_parts(id as *const u8, len) })
+}
+
+fn storage_for(storage_id: u32) -> TeeResult<Arc<TaStorage>> {
+    if storage_id != raw::TEE_STORAGE_PRIVATE {
+        return Err(ErrorKind::ItemNotFound);
+    }
+    current().ok_or(ErrorKind::StorageNotAvailable)
+}
+
+unsafe fn handle<'a>(object: raw::TEE_ObjectHandle) -> &'a mut Handle {
+    unsafe { &mut *(object as *mut Handle) }
+}
+
+fn new_handle(storage: Arc<TaStorage>, id: &[u8], flags: u32) -> raw::TEE_ObjectHandle {
+    Box::into_raw(Box::new(Handle {
+        storage,
+        id: id.to_vec(),
+        flags,
+        position: 0,
+    })) as raw::TEE_ObjectHandle
+}
+
+// Runs `f` on the data of the object behind `handle`.
+fn with_data<R>(handle: &Handle, f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+    let mut objects = handle.storage.objects.lock().unwrap();
+    let object = objects
+        .get_mut(&handle.id)
+        .expect("open objects are registered");
+    f(&mut object.data)
+}
+
+// Changes the data of the object behind `handle`, to be stored when a
+// handle on the object is closed.
+fn update(handle: &Handle, f: impl FnOnce(&mut Vec<u8>)) -> TeeResult<()> {
+    if handle.flags & raw::TEE_DATA_FLAG_ACCESS_WRITE == 0 {
+        return Err(ErrorKind::AccessDenied);
+    }
+    if handle.storage.mode == StorageMode::ReadOnly {
+        return Err(ErrorKind::StorageNotAvailable);
+    }
+    let mut objects = handle.storage.objects.lock().unwrap();
+    let object = objects
+        .get_mut(&handle.id)
+        .expect("open objects are registered");
+    f(&mut object.data);
+    object.dirty = true;
+    Ok(())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_OpenPersistentObject(
+    storage_id: u32,
+    object_id: *const c_void,
+    object_id_len: usize,
+    flags: u32,
+    object: *mut raw::TEE_ObjectHandle,
+) -> raw::TEE_Result {
+    unsafe { *object = ptr::null_mut() };
+    result((|| {
+        let id = namespaced(unsafe { self::object_id(object_id, object_id_len)? });
+        let storage = storage_for(storage_id)?;
+        storage.attach(&id, flags, || {
+            storage.load(&id)?.ok_or(ErrorKind::ItemNotFound)
+        })?;
+        unsafe { *object = new_handle(storage, &id, flags) };
+        Ok(())
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_CreatePersistentObject(
+    storage_id: u32,
+    object_id: *const c_void,
+    object_id_len: usize,
+    flags: u32,
+    attributes: raw::TEE_ObjectHandle,
+    initial_data: *const c_void,
+    initial_data_len: usize,
+    object: *mut raw::TEE_ObjectHandle,
+) -> raw::TEE_Result {
+    if !object.is_null() {
+        unsafe { *object = ptr::null_mut() };
+    }
+    result((|| {
+        // Objects carrying key attributes are not emulated.
+        if !attributes.is_null() {
+            return Err(ErrorKind::NotSupported);
+        }
+        let id = namespaced(unsafe { self::object_id(object_id, object_id_len)? });
+        let storage = storage_for(storage_id)?;
+        let data = match initial_data_len {
+            0 => Vec::new(),
+            len => unsafe { slice::from_raw_parts(initial_data as *const u8, len) }.to_vec(),
+        };
+
+        if storage.objects.lock().unwrap().contains_key(&id) {
+            return Err(ErrorKind::AccessConflict);
+        }
+        if flags & raw::TEE_DATA_FLAG_OVERWRITE == 0 && storage.exists(&id) {
+            return Err(ErrorKind::AccessConflict);
+        }
+        storage.store(&id, &data)?;
+        if object.is_null() {
+            return Ok(());
+        }
+        let flags = flags & !raw::TEE_DATA_FLAG_OVERWRITE;
+        storage.attach(&id, flags, || Ok(data))?;
+        unsafe { *object = new_handle(storage, &id, flags) };
+        Ok(())
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_CloseObject(object: raw::TEE_ObjectHandle) {
+    if object.is_null() {
+        return;
+    }
+    let handle = unsafe { Box::from_raw(object as *mut Handle) };
+    // The TA cannot learn of the failure: the data written since the object
+    // was last stored is lost.
+    if let Err(kind) = handle.storage.detach(&handle.id, handle.flags) {
+        error!(%kind, "Failed to store a persistent object on close");
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_CloseAndDeletePersistentObject1(
+    object: raw::TEE_ObjectHandle,
+) -> raw::TEE_Result {
+    if object.is_null() {
+        return raw::TEE_SUCCESS;
+    }
+    // The handle is closed whatever the outcome.
+    let handle = unsafe { handle(object) };
+    let removed = if handle.flags & raw::TEE_DATA_FLAG_ACCESS_WRITE_META == 0 {
+        Err(ErrorKind::AccessDenied)
+    } else {
+        handle.storage.remove(&handle.id)
+    };
+    // A handle opened to write the metadata is the only one on the object,
+    // whose data written since it was last stored is gone with it.
+    if removed.is_ok() {
+        handle.storage.objects.lock().unwrap().remove(&handle.id);
+    }
+    unsafe { TEE_CloseObject(object) };
+    result(removed)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_RenamePersistentObject(
+    object: raw::TEE_ObjectHandle,
+    new_object_id: *const c_void,
+    new_object_id_len: usize,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    result((|| {
+        if handle.flags & raw::TEE_DATA_FLAG_ACCESS_WRITE_META == 0 {
+            return Err(ErrorKind::AccessDenied);
+        }
+        let new_id = namespaced(unsafe { self::object_id(new_object_id, new_object_id_len)? });
+        let storage = &handle.storage;
+        let mut objects = storage.objects.lock().unwrap();
+        if storage.exists(&new_id) || objects.contains_key(&new_id) {
+            return Err(ErrorKind::AccessConflict);
+        }
+        let mut object = objects
+            .remove(&handle.id)
+            .expect("open objects are registered");
+        // The encryption binds the data to the object id.
+        if let Err(e) = storage.store(&new_id, &object.data) {
+            objects.insert(handle.id.clone(), object);
+            return Err(e);
+        }
+        object.dirty = false;
+        let _ = storage.remove(&handle.id);
+        objects.insert(new_id.clone(), object);
+        handle.id = new_id;
+        Ok(())
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetObjectInfo1(
+    object: raw::TEE_ObjectHandle,
+    object_info: *mut raw::TEE_ObjectInfo,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    let data_size = with_data(handle, |data| data.len());
+    unsafe {
+        *object_info = raw::TEE_ObjectInfo {
+            objectType: raw::TEE_TYPE_DATA,
+            objectSize: 0,
+            maxObjectSize: 0,
+            objectUsage: 0,
+            dataSize: data_size,
+            dataPosition: handle.position,
+            handleFlags: handle.flags
+                | raw::TEE_HANDLE_FLAG_PERSISTENT
+                | raw::TEE_HANDLE_FLAG_INITIALIZED,
+        }
+    };
+    raw::TEE_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_ReadObjectData(
+    object: raw::TEE_ObjectHandle,
+    buffer: *mut c_void,
+    size: usize,
+    count: *mut usize,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    if handle.flags & raw::TEE_DATA_FLAG_ACCESS_READ == 0 {
+        return ErrorKind::AccessDenied.into();
+    }
+    let read = with_data(handle, |data| {
+        let start = handle.position.min(data.len());
+        let read = size.min(data.len() - start);
+        if read > 0 {
+            unsafe { ptr::copy_nonoverlapping(data[start..].as_ptr(), buffer as *mut u8, read) };
+        }
+        read
+    });
+    handle.position += read;
+    unsafe { *count = read };
+    raw::TEE_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_WriteObjectData(
+    object: raw::TEE_ObjectHandle,
+    buffer: *const c_void,
+    size: usize,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    let Some(end) = handle
+        .position
+        .checked_add(size)
+        .filter(|end| *end <= raw::TEE_DATA_MAX_POSITION as usize)
+    else {
+        return ErrorKind::Overflow.into();
+    };
+    let bytes = match size {
+        0 => &[][..],
+        _ => unsafe { slice::from_raw_parts(buffer as *const u8, size) },
+    };
+    let position = handle.position;
+    let written = update(handle, |data| {
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[position..end].copy_from_slice(bytes);
+    });
+    if written.is_ok() {
+        handle.position = end;
+    }
+    result(written)
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_TruncateObjectData(
+    object: raw::TEE_ObjectHandle,
+    size: usize,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    result(update(handle, |data| data.resize(size, 0)))
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_SeekObjectData(
+    object: raw::TEE_ObjectHandle,
+    offset: i64,
+    whence: raw::TEE_Whence,
+) -> raw::TEE_Result {
+    let handle = unsafe { handle(object) };
+    let base = match whence {
+        raw::TEE_Whence::TEE_DATA_SEEK_SET => 0,
+        raw::TEE_Whence::TEE_DATA_SEEK_CUR => handle.position as i64,
+        raw::TEE_Whence::TEE_DATA_SEEK_END => with_data(handle, |data| data.len()) as i64,
+    };
+    match base.checked_add(offset) {
+        Some(position) if position > raw::TEE_DATA_MAX_POSITION as i64 => {
+            ErrorKind::Overflow.into()
+        }
+        // Seeking before the start moves to the start.
+        Some(position) => {
+            handle.position = position.max(0) as usize;
+            raw::TEE_SUCCESS
+        }
+        None => ErrorKind::Overflow.into(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_AllocatePersistentObjectEnumerator(
+    object_enumerator: *mut raw::TEE_ObjectEnumHandle,
+) -> raw::TEE_Result {
+    let enumerator = Box::into_raw(Box::<Enumerator>::default());
+    unsafe { *object_enumerator = enumerator as raw::TEE_ObjectEnumHandle };
+    raw::TEE_SUCCESS
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_FreePersistentObjectEnumerator(
+    object_enumerator: raw::TEE_ObjectEnumHandle,
+) {
+    if !object_enumerator.is_null() {
+        drop(unsafe { Box::from_raw(object_enumerator as *mut Enumerator) });
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_ResetPersistentObjectEnumerator(
+    object_enumerator: raw::TEE_ObjectEnumHandle,
+) {
+    let enumerator = unsafe { &mut *(object_enumerator as *mut Enumerator) };
+    *enumerator = Enumerator::default();
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_StartPersistentObjectEnumerator(
+    object_enumerator: raw::TEE_ObjectEnumHandle,
+    storage_id: u32,
+) -> raw::TEE_Result {
+    let enumerator = unsafe { &mut *(object_enumerator as *mut Enumerator) };
+    result((|| {
+        let storage = storage_for(storage_id)?;
+        let namespace = namespaced(&[]);
+        let mut ids = storage.ids()?;
+        ids.retain(|id| id.starts_with(&namespace));
+        ids.sort();
+        if ids.is_empty() {
+            return Err(ErrorKind::ItemNotFound);
+        }
+        *enumerator = Enumerator {
+            storage: Some(storage),
+            ids,
+            prefix_len: namespace.len(),
+            next: 0,
+        };
+        Ok(())
+    })())
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_GetNextPersistentObject(
+    object_enumerator: raw::TEE_ObjectEnumHandle,
+    object_info: *mut raw::TEE_ObjectInfo,
+    object_id: *mut c_void,
+    object_id_len: *mut usize,
+) -> raw::TEE_Result {
+    let enumerator = unsafe { &mut *(object_enumerator as *mut Enumerator) };
+    let Some(id) = enumerator.ids.get(enumerator.next) else {
+        return ErrorKind::ItemNotFound.into();
+    };
+    enumerator.next += 1;
+    let data_size = enumerator
+        .storage
+        .as_ref()
+        .map_or(0, |storage| storage.data_size(id));
+    let ta_id = &id[enumerator.prefix_len..];
+    unsafe {
+        ptr::copy_nonoverlapping(ta_id.as_ptr(), object_id as *mut u8, ta_id.len());
+        *object_id_len = ta_id.len();
+        if !object_info.is_null() {
+            *object_info = raw::TEE_ObjectInfo {
+                objectType: raw::TEE_TYPE_DATA,
+                objectSize: 0,
+                maxObjectSize: 0,
+                objectUsage: 0,
+                dataSize: data_size,
+                dataPosition: 0,
+                handleFlags: raw::TEE_HANDLE_FLAG_PERSISTENT | raw::TEE_HANDLE_FLAG_INITIALIZED,
+            };
+        }
+    }
+    raw::TEE_SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "8aaaf200-2450-11e4-abe2-0002a5d5c51b";
+    const ID: &[u8] = b"object";
+
+    fn storage(dir: &Path) -> io::Result<Arc<TaStorage>> {
+        SecureStorage::new(dir, [7; 32]).for_ta(UUID)
+    }
+
+    fn create(id: &[u8], data: &[u8]) -> raw::TEE_ObjectHandle {
+        let mut object = ptr::null_mut();
+        let res = unsafe {
+            TEE_CreatePersistentObject(
+                raw::TEE_STORAGE_PRIVATE,
+                id.as_ptr().cast(),
+                id.len(),
+                raw::TEE_DATA_FLAG_ACCESS_WRITE | raw::TEE_DATA_FLAG_ACCESS_WRITE_META,
+                ptr::null_mut(),
+                data.as_ptr().cast(),
+                data.len(),
+                &mut object,
+            )
+        };
+        assert_eq!(res, raw::TEE_SUCCESS);
+        object
+    }
+
+    fn open(id: &[u8], flags: u32) -> Result<raw::TEE_ObjectHandle, raw::TEE_Result> {
+        let mut object = ptr::null_mut();
+        match unsafe {
+            TEE_OpenPersistentObject(
+                raw::TEE_STORAGE_PRIVATE,
+                id.as_ptr().cast(),
+                id.len(),
+                flags,
+                &mut object,
+            )
+        } {
+            raw::TEE_SUCCESS => Ok(object),
+            res => Err(res),
+        }
+    }
+
+    fn read(id: &[u8]) -> Result<Vec<u8>, raw::TEE_Result> {
+        let object = open(id, raw::TEE_DATA_FLAG_ACCESS_READ)?;
+        let mut data = vec![0; 64];
+        let mut count = 0;
+        let res =
+            unsafe { TEE_ReadObjectData(object, data.as_mut_ptr().cast(), data.len(), &mut count) };
+        unsafe { TEE_CloseObject(object) };
+        assert_eq!(res, raw::TEE_SUCCESS);
+        data.truncate(count);
+        Ok(data)
+    }
+
+    fn write(object: raw::TEE_ObjectHandle, data: &[u8]) {
+        let res = unsafe { TEE_WriteObjectData(object, data.as_ptr().cast(), data.len()) };
+        assert_eq!(res, raw::TEE_SUCCESS);
+    }
+
+    fn corrupt() -> raw::TEE_Result {
+        ErrorKind::CorruptObject.into()
+    }
+
+    #[test]
+    fn objects_round_trip_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let _storage = enter(Some(storage(dir.path()).unwrap()));
+            let object = create(ID, b"hello");
+            unsafe { TEE_SeekObjectData(object, 0, raw::TEE_Whence::TEE_DATA_SEEK_END) };
+            write(object, b" world");
+            unsafe { TEE_CloseObject(object) };
+        }
+        let _storage = enter(Some(storage(dir.path()).unwrap()));
+        assert_eq!(read(ID).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn writes_are_stored_on_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        let object = create(ID, b"before");
+        let file = fs::read(storage.path(ID)).unwrap();
+
+        write(object, b"after!");
+        assert_eq!(fs::read(storage.path(ID)).unwrap(), file);
+        unsafe { TEE_CloseObject(object) };
+        assert_ne!(fs::read(storage.path(ID)).unwrap(), file);
+        assert_eq!(read(ID).unwrap(), b"after!");
+    }
+
+    #[test]
+    fn tampered_objects_are_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        unsafe { TEE_CloseObject(create(ID, b"secret")) };
+
+        let mut file = fs::read(storage.path(ID)).unwrap();
+        *file.last_mut().unwrap() ^= 1;
+        fs::write(storage.path(ID), file).unwrap();
+        assert_eq!(read(ID), Err(corrupt()));
+        assert!(self::storage(dir.path()).is_err());
+    }
+
+    #[test]
+    fn rolled_back_objects_are_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        let object = create(ID, b"v1");
+        let old = fs::read(storage.path(ID)).unwrap();
+        write(object, b"v2");
+        unsafe { TEE_CloseObject(object) };
+
+        fs::write(storage.path(ID), old).unwrap();
+        assert_eq!(read(ID), Err(corrupt()));
+        assert!(self::storage(dir.path()).is_err());
+    }
+
+    #[test]
+    fn deleted_and_copied_objects_are_corrupt() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        unsafe { TEE_CloseObject(create(ID, b"data")) };
+
+        fs::copy(storage.path(ID), storage.path(b"copy")).unwrap();
+        assert_eq!(read(b"copy"), Err(corrupt()));
+        fs::remove_file(storage.path(b"copy")).unwrap();
+        fs::remove_file(storage.path(ID)).unwrap();
+        assert_eq!(read(ID), Err(corrupt()));
+        assert!(self::storage(dir.path()).is_err());
+    }
+
+    #[test]
+    fn renamed_objects_move_with_their_data() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let _storage = enter(Some(storage(dir.path()).unwrap()));
+            let object = create(ID, b"data");
+            write(object, b"DA");
+            let new_id = b"renamed";
+            let res =
+                unsafe { TEE_RenamePersistentObject(object, new_id.as_ptr().cast(), new_id.len()) };
+            assert_eq!(res, raw::TEE_SUCCESS);
+            unsafe { TEE_CloseObject(object) };
+        }
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        assert_eq!(read(b"renamed").unwrap(), b"DAta");
+        assert_eq!(read(ID), Err(ErrorKind::ItemNotFound.into()));
+        assert!(!storage.path(ID).exists());
+    }
+
+    #[test]
+    fn interrupted_replacements_settle_on_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        unsafe { TEE_CloseObject(create(ID, b"stored")) };
+        // As if the manager stopped before replacing the file.
+        {
+            let mut manifest = storage.manifest.lock().unwrap();
+            let mut states = manifest.states(ID);
+            states.push(Some([0; 32]));
+            manifest.set(ID, states);
+            storage.write_manifest(&mut manifest).unwrap();
+        }
+
+        let storage = self::storage(dir.path()).unwrap();
+        let hash = file_hash(&fs::read(storage.path(ID)).unwrap());
+        assert_eq!(storage.manifest.lock().unwrap().states(ID), [Some(hash)]);
+        let _storage = enter(Some(storage));
+        assert_eq!(read(ID).unwrap(), b"stored");
+    }
+
+    #[test]
+    fn storages_without_manifest_adopt_their_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage(dir.path()).unwrap();
+        let _storage = enter(Some(storage.clone()));
+        unsafe { TEE_CloseObject(create(ID, b"legacy")) };
+        fs::remove_file(dir.path().join(UUID).join(MANIFEST_FILE)).unwrap();
+
+        let _storage = enter(Some(self::storage(dir.path()).unwrap()));
+        assert_eq!(read(ID).unwrap(), b"legacy");
+    }
+
+    #[test]
+    fn manifests_round_trip() {
+        let mut manifest = Manifest {
+            version: 3,
+            ..Manifest::default()
+        };
+        manifest.set(b"a", vec![Some([1; 32])]);
+        manifest.set(b"bc", vec![Some([2; 32]), None]);
+        let bytes = manifest.encode();
+        assert_eq!(Manifest::decode(&bytes), Some(manifest));
+        assert_eq!(Manifest::decode(&bytes[..bytes.len() - 1]), None);
+    }
+}
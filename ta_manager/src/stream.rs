@@ -0,0 +1,164 @@
+//! Memref parameters too large for a single frame, sent in chunks with the
+//! `InvokeStream*` requests.
+//!
+//! [`ClientPool`](crate::ClientPool) streams the input memrefs larger than
+//! its chunk size, all on one connection. The manager reassembles them as
+//! their chunks arrive and invokes the TA once they are complete.
+//! Invocations are tracked per connection, and dropped along with it.
+
+use std::{collections::HashMap, iter, mem};
+
+use optee_utee::ErrorKind;
+
+use crate::protocol::{ParamType, Parameter, Parameters, TeeRequest};
+
+// An invocation waiting for the chunks of its memrefs.
+pub(crate) struct PendingInvoke {
+    pub(crate) cmd_id: u32,
+    pub(crate) timeout_ms: Option<u32>,
+    params: Parameters,
+    lengths: [u32; 4],
+}
+
+/// Invocations started on a connection, by session and operation id.
+pub(crate) type PendingInvokes = HashMap<(u32, u32), PendingInvoke>;
+
+impl PendingInvoke {
+    // Checks that only input memrefs are streamed, with no data sent along
+    // `params`, and that their total size does not exceed `max_size`.
+    pub(crate) fn new(
+        cmd_id: u32,
+        mut params: Parameters,
+        timeout_ms: Option<u32>,
+        lengths: [u32; 4],
+        max_size: usize,
+    ) -> Result<Self, ErrorKind> {
+        let total: u64 = lengths.iter().map(|len| u64::from(*len)).sum();
+        if total > max_size as u64 {
+            return Err(ErrorKind::OutOfMemory);
+        }
+        for (param, len) in params_mut(&mut params).into_iter().zip(lengths) {
+            if len == 0 {
+                continue;
+            }
+            if !is_memref_input(param) || !param.param.data.is_empty() {
+                return Err(ErrorKind::BadParameters);
+            }
+            param.param.data.reserve_exact(len as usize);
+        }
+        Ok(Self {
+            cmd_id,
+            timeout_ms,
+            params,
+            lengths,
+        })
+    }
+
+    // Appends a chunk to the memref `index`.
+    pub(crate) fn append(&mut self, index: u8, chunk: &[u8]) -> Result<(), ErrorKind> {
+        let index = usize::from(index);
+        let Some(len) = self.lengths.get(index) else {
+            return Err(ErrorKind::BadParameters);
+        };
+        let data = &mut params_mut(&mut self.params)[index].param.data;
+        if data.len() + chunk.len() > *len as usize {
+            return Err(ErrorKind::BadParameters);
+        }
+        data.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    // Number of bytes reserved for the streamed memrefs.
+    pub(crate) fn size(&self) -> usize {
+        self.lengths.iter().map(|len| *len as usize).sum()
+    }
+
+    // Takes the parameters of the invocation, once all memrefs arrived.
+    pub(crate) fn finish(&mut self) -> Result<Parameters, ErrorKind> {
+        let complete = params_mut(&mut self.params)
+            .into_iter()
+            .zip(self.lengths)
+            .all(|(param, len)| len == 0 || param.param.data.len() == len as usize);
+        if !complete {
+            return Err(ErrorKind::BadParameters);
+        }
+        Ok(std::mem::take(&mut self.params))
+    }
+
+    // Empties the streamed input memrefs of the parameters returned by the
+    // TA, which the CA already has.
+    pub(crate) fn strip_inputs(&self, params: &mut Parameters) {
+        for (param, len) in params_mut(params).into_iter().zip(self.lengths) {
+            if len != 0 && param.param_type == ParamType::MemrefInput {
+                param.param.data = Vec::new();
+            }
+        }
+    }
+}
+
+fn is_memref_input(param: &Parameter) -> bool {
+    matches!(
+        param.param_type,
+        ParamType::MemrefInput | ParamType::MemrefInout
+    )
+}
+
+fn params_mut(params: &mut Parameters) -> [&mut Parameter; 4] {
+    [&mut params.0, &mut params.1, &mut params.2, &mut params.3]
+}
+
+// Whether `params` holds an input memref larger than `chunk_size`.
+pub(crate) fn oversized(params: &Parameters, chunk_size: usize) -> bool {
+    [&params.0, &params.1, &params.2, &params.3]
+        .into_iter()
+        .any(|param| is_memref_input(param) && param.param.data.len() > chunk_size)
+}
+
+// Splits an invocation into the `InvokeStream*` requests sending its input
+// memrefs larger than `chunk_size` in chunks of at most `chunk_size` bytes.
+pub(crate) fn requests(
+    session_id: u32,
+    cmd_id: u32,
+    operation_id: u32,
+    mut params: Parameters,
+    timeout_ms: Option<u32>,
+    chunk_size: usize,
+) -> impl Iterator<Item = TeeRequest> {
+    let mut lengths = [0; 4];
+    let mut streamed = Vec::new();
+    for (index, param) in params_mut(&mut params).into_iter().enumerate() {
+        if !is_memref_input(param) || param.param.data.len() <= chunk_size {
+            continue;
+        }
+        let Ok(len) = u32::try_from(param.param.data.len()) else {
+            continue;
+        };
+        lengths[index] = len;
+        streamed.push((index as u8, mem::take(&mut param.param.data)));
+    }
+
+    let begin = TeeRequest::InvokeStreamBegin {
+        session_id,
+        cmd_id,
+        operation_id,
+        params,
+        timeout_ms,
+        lengths,
+    };
+    let chunks = streamed.into_iter().flat_map(move |(index, data)| {
+        let len = data.len();
+        (0..len)
+            .step_by(chunk_size)
+            .map(move |start| TeeRequest::InvokeStreamChunk {
+                session_id,
+                operation_id,
+                index,
+                data: data[start..len.min(start + chunk_size)].to_vec(),
+            })
+    });
+    let end = TeeRequest::InvokeStreamEnd {
+        session_id,
+        operation_id,
+    };
+    iter::once(begin).chain(chunks).chain(iter::once(end))
+}
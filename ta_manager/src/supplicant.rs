@@ -0,0 +1,177 @@
+use std::{
+    fmt::Debug,
+    fs,
+    io::{Read, Write},
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use optee_utee::{Error, ErrorKind, ErrorOrigin, Result};
+use tracing::warn;
+
+use crate::ipc::connect;
+use crate::protocol::{ReeService, TARequest, TAResponse};
+
+/// Services normal-world requests of hosted TAs, like tee-supplicant does
+/// for TAs running under OP-TEE.
+///
+/// Plugins are registered with
+/// [`TAManagerConfig::with_supplicant_plugin`](crate::TAManagerConfig::with_supplicant_plugin)
+/// and asked in registration order.
+pub trait SupplicantPlugin: Debug + Send + Sync + 'static {
+    /// Services `service` for the TA `uuid`, or returns `None` to leave it
+    /// to the next plugin.
+    fn handle(&self, uuid: &str, service: &ReeService) -> Option<Result<Vec<u8>>>;
+}
+
+/// Reverse RPC channel from a TA to the normal world, reached through
+/// [`CommandContext::ree_service`](crate::CommandContext::ree_service).
+///
+/// A request no plugin services is forwarded to the TA Manager server.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Supplicant {
+    uuid: String,
+    plugins: Vec<Arc<dyn SupplicantPlugin>>,
+    server_socket: PathBuf,
+    abstract_namespace: bool,
+}
+
+impl Supplicant {
+    pub(crate) fn new(
+        uuid: &str,
+        plugins: Vec<Arc<dyn SupplicantPlugin>>,
+        server_socket: PathBuf,
+        abstract_namespace: bool,
+    ) -> Self {
+        Self {
+            uuid: uuid.to_string(),
+            plugins,
+            server_socket,
+            abstract_namespace,
+        }
+    }
+
+    pub(crate) fn call(&self, service: ReeService) -> Result<Vec<u8>> {
+        for plugin in &self.plugins {
+            if let Some(result) = plugin.handle(&self.uuid, &service) {
+                return result;
+            }
+        }
+        let resp = self.forward(service).map_err(|e| {
+            warn!(error = ?e, "Failed to forward REE service request");
+            Error::new(ErrorKind::Communication).with_origin(ErrorOrigin::Comms)
+        })?;
+        match resp {
+            TAResponse::ReeService { result: 0, data } => Ok(data),
+            TAResponse::ReeService { result, .. } => Err(Error::from_raw_error(result)),
+            _ => Err(Error::new(ErrorKind::BadFormat).with_origin(ErrorOrigin::Comms)),
+        }
+    }
+
+    fn forward(&self, service: ReeService) -> anyhow::Result<TAResponse> {
+        let config = bincode::config::standard();
+        let mut stream = connect(&self.server_socket, self.abstract_namespace)?;
+        let req = TARequest::ReeService {
+            uuid: self.uuid.clone(),
+            service,
+        };
+        bincode::encode_into_std_write(req, &mut stream, config)?;
+        Ok(bincode::decode_from_std_read(&mut stream, config)?)
+    }
+}
+
+/// Serves [`ReeService::LoadFile`] and [`ReeService::StoreFile`] from a
+/// directory of the REE filesystem, with a subdirectory per TA uuid.
+///
+/// Paths are relative to the directory of the TA. Absolute paths and paths
+/// leaving it fail with `AccessDenied`.
+#[derive(Clone, Debug)]
+pub struct ReeFsPlugin {
+    root: PathBuf,
+}
+
+impl ReeFsPlugin {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn resolve(&self, uuid: &str, path: &str) -> Result<PathBuf> {
+        let path = Path::new(path);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(Error::new(ErrorKind::AccessDenied));
+        }
+        Ok(self.root.join(uuid).join(path))
+    }
+}
+
+impl SupplicantPlugin for ReeFsPlugin {
+    fn handle(&self, uuid: &str, service: &ReeService) -> Option<Result<Vec<u8>>> {
+        let result = match service {
+            ReeService::LoadFile { path } => self
+                .resolve(uuid, path)
+                .and_then(|path| fs::read(path).map_err(io_error)),
+            ReeService::StoreFile { path, data } => self.resolve(uuid, path).and_then(|path| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(io_error)?;
+                }
+                fs::write(path, data).map(|_| Vec::new()).map_err(io_error)
+            }),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+/// Serves [`ReeService::Network`] over TCP.
+#[derive(Clone, Debug)]
+pub struct ReeNetworkPlugin {
+    timeout: Duration,
+}
+
+impl ReeNetworkPlugin {
+    /// Creates a plugin giving up on connections idle for `timeout`.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+
+    fn exchange(&self, host: &str, port: u16, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or(std::io::ErrorKind::NotFound)?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.write_all(data)?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer)?;
+        Ok(answer)
+    }
+}
+
+impl SupplicantPlugin for ReeNetworkPlugin {
+    fn handle(&self, _uuid: &str, service: &ReeService) -> Option<Result<Vec<u8>>> {
+        match service {
+            ReeService::Network { host, port, data } => {
+                Some(self.exchange(host, *port, data).map_err(io_error))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> Error {
+    let kind = match e.kind() {
+        std::io::ErrorKind::NotFound => ErrorKind::ItemNotFound,
+        std::io::ErrorKind::PermissionDenied => ErrorKind::AccessDenied,
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+        std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset => {
+            ErrorKind::Communication
+        }
+        _ => ErrorKind::Generic,
+    };
+    Error::new(kind).with_origin(ErrorOrigin::Comms)
+}
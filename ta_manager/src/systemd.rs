@@ -0,0 +1,111 @@
+//! Socket activation by systemd, and the systemd fd store.
+//!
+//! A manager started with `LISTEN_FDS`, by a socket unit or by systemd
+//! handing back the fds a previous run stored, serves the TA on the socket
+//! named after the TA uuid in `LISTEN_FDNAMES`, e.g. through
+//! `FileDescriptorName=<uuid>` in the socket unit. Without names, a single
+//! socket passed is taken as that of the TA. With
+//! [`TAManagerConfig::fd_store`](crate::TAManagerConfig::fd_store), a
+//! drained manager hands its socket to the fd store under the same name, so
+//! that the service restarted by systemd gets it back: CAs connecting in
+//! between wait in the backlog of the socket rather than finding it closed.
+//!
+//! systemd only runs on Linux: elsewhere, no socket is passed and none is
+//! stored.
+
+use std::io;
+#[cfg(target_os = "linux")]
+use std::{
+    env,
+    ffi::OsStr,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        net::UnixDatagram,
+    },
+    path::Path,
+    str::FromStr,
+};
+
+#[cfg(target_os = "linux")]
+use tracing::info;
+
+#[cfg(target_os = "linux")]
+use crate::handover::send_fds;
+use crate::ipc::IpcListener;
+#[cfg(target_os = "linux")]
+use crate::ipc::socket_addr;
+
+// First fd passed by systemd, `SD_LISTEN_FDS_START`.
+#[cfg(target_os = "linux")]
+const LISTEN_FDS_START: RawFd = 3;
+
+// Returns the socket systemd passed for the TA `uuid`, if any.
+#[cfg(target_os = "linux")]
+pub(crate) fn listener(uuid: &str) -> io::Result<Option<IpcListener>> {
+    // The fds are meant for the process systemd started, not its children.
+    // SAFETY: getpid cannot fail.
+    let pid = unsafe { libc::getpid() };
+    if env_number("LISTEN_PID") != Some(pid) {
+        return Ok(None);
+    }
+    let count: RawFd = match env_number("LISTEN_FDS") {
+        Some(count) if count > 0 => count,
+        _ => return Ok(None),
+    };
+    let index = match env::var("LISTEN_FDNAMES") {
+        Ok(names) => names.split(':').position(|name| name == uuid),
+        Err(_) if count == 1 => Some(0),
+        Err(_) => None,
+    };
+    let Some(index) = index.filter(|&index| (index as RawFd) < count) else {
+        return Ok(None);
+    };
+    let fd = LISTEN_FDS_START + index as RawFd;
+    // SAFETY: systemd passed `fd` open, and no one else takes the fd named
+    // after the TA.
+    let listener = unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        IpcListener::from_raw_fd(fd)
+    };
+    // Fails unless `fd` is a Unix socket.
+    let addr = listener.local_addr()?;
+    listener.set_nonblocking(false)?;
+    info!(?addr, fd, "Socket passed by systemd");
+    Ok(Some(listener))
+}
+
+#[cfg(target_os = "linux")]
+fn env_number<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.parse().ok()
+}
+
+// Hands `listener` to the fd store of systemd under the name `uuid`.
+// Returns whether the manager runs under systemd.
+#[cfg(target_os = "linux")]
+pub(crate) fn store(listener: &IpcListener, uuid: &str) -> io::Result<bool> {
+    let Some(notify_socket) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    // A leading @ names a socket in the abstract namespace.
+    let addr = match notify_socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => socket_addr(Path::new(OsStr::from_bytes(name)), true)?,
+        None => socket_addr(Path::new(&notify_socket), false)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.connect_addr(&addr)?;
+    let state = format!("FDSTORE=1\nFDNAME={}\n", uuid);
+    send_fds(&socket, state.as_bytes(), &[listener.as_raw_fd()])?;
+    info!("Socket handed to the systemd fd store");
+    Ok(true)
+}
+
+#[cfg(windows)]
+pub(crate) fn listener(_uuid: &str) -> io::Result<Option<IpcListener>> {
+    Ok(None)
+}
+
+#[cfg(windows)]
+pub(crate) fn store(_listener: &IpcListener, _uuid: &str) -> io::Result<bool> {
+    Ok(false)
+}
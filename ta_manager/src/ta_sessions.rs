@@ -0,0 +1,331 @@
+//! Sessions between TAs hosted by [`TAManager`](crate::TAManager)s.
+//!
+//! The `TEE_*` internal client functions called by
+//! `optee_utee::TaSessionBuilder` and `optee_utee::TaSession` are
+//! implemented here on top of the CA protocol. The target UUID is resolved
+//! among the TAs registered with the TA Manager server, then the requests
+//! are sent to the socket of the target TA like those of a CA, with the
+//! `TEE_LOGIN_TRUSTED_APP` login and the UUID of the calling TA.
+//!
+//! The manager running the calling thread provides the socket directory,
+//! server socket and codec. Calls from other threads fail with
+//! `TEE_ERROR_COMMUNICATION`. The cancellation timeout of a command is sent
+//! as its timeout, those of opening and closing sessions are ignored.
+
+use std::{
+    cell::RefCell,
+    ptr, slice,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use optee_utee::{ErrorKind, Uuid};
+use optee_utee_sys as raw;
+use tracing::warn;
+
+use crate::client::ClientPool;
+use crate::config::TAManagerConfig;
+use crate::protocol::{
+    ClientIdentity, ParamType, Parameter, Parameters, ReturnOrigin, TeeParam, TeeRequest,
+    TeeResponse, Value,
+};
+
+/// Route from the TA served by the current thread to the other hosted TAs.
+pub(crate) struct Router {
+    pool: ClientPool,
+    caller: [u8; 16],
+    operation_id: AtomicU32,
+}
+
+impl Router {
+    pub(crate) fn new(uuid: &str, config: &TAManagerConfig) -> Self {
+        // A TA registered under a name rather than a UUID calls with the nil
+        // UUID.
+        let caller = Uuid::parse_str(uuid)
+            // SAFETY: the pointer refers to the `TEE_UUID` inside `uuid`.
+            .map(|uuid| uuid_bytes(unsafe { &*uuid.as_raw_ptr() }))
+            .unwrap_or_default();
+        Self {
+            pool: ClientPool::new(1)
+                .with_shared_codec(config.codec.clone())
+                .with_socket_dir(&config.socket_dir)
+                .with_server_socket(&config.server_socket)
+                .with_abstract_namespace(config.abstract_sockets),
+            caller,
+            operation_id: AtomicU32::new(1),
+        }
+    }
+
+    // Returns the uuid under which the TA `target` registered.
+    fn resolve(&self, target: &Uuid) -> Result<String, (ErrorKind, ReturnOrigin)> {
+        let tas = self
+            .pool
+            .discover_tas()
+            .map_err(|e| (e.kind(), ReturnOrigin::Comms))?;
+        tas.into_iter()
+            .map(|info| info.uuid)
+            .find(|uuid| Uuid::parse_str(uuid).is_ok_and(|uuid| uuid == *target))
+            .ok_or((ErrorKind::ItemNotFound, ReturnOrigin::Tee))
+    }
+
+    fn request(
+        &self,
+        uuid: &str,
+        req: TeeRequest,
+    ) -> Result<TeeResponse, (ErrorKind, ReturnOrigin)> {
+        self.pool.request(uuid, req).map_err(|e| {
+            warn!(uuid, error = ?e, "Failed to reach target TA");
+            (ErrorKind::Communication, ReturnOrigin::Comms)
+        })
+    }
+}
+
+// What a `TEE_TASessionHandle` returned by this module points to.
+struct Session {
+    router: Arc<Router>,
+    uuid: String,
+    session_id: u32,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Arc<Router>>> = const { RefCell::new(None) };
+}
+
+/// Returns the router of the TA served by the current thread.
+pub(crate) fn current() -> Option<Arc<Router>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Makes `router` that of the current thread until the guard is dropped.
+pub(crate) fn enter(router: Option<Arc<Router>>) -> Entered {
+    Entered(CURRENT.with(|current| current.replace(router)))
+}
+
+pub(crate) struct Entered(Option<Arc<Router>>);
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+fn uuid_bytes(uuid: &raw::TEE_UUID) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes[..4].copy_from_slice(&uuid.timeLow.to_be_bytes());
+    bytes[4..6].copy_from_slice(&uuid.timeMid.to_be_bytes());
+    bytes[6..8].copy_from_slice(&uuid.timeHiAndVersion.to_be_bytes());
+    bytes[8..].copy_from_slice(&uuid.clockSeqAndNode);
+    bytes
+}
+
+fn param_type(param_types: u32, index: usize) -> ParamType {
+    ParamType::from((param_types >> (4 * index)) & 0xf)
+}
+
+// Copies the parameters passed by the calling TA into a request. Output
+// memrefs are sent zeroed, with the size of the buffer of the caller.
+unsafe fn read_params(param_types: u32, params: *const raw::TEE_Param) -> Parameters {
+    if params.is_null() {
+        return Parameters::default();
+    }
+    let mut out: [Parameter; 4] = Default::default();
+    for (i, param) in out.iter_mut().enumerate() {
+        let param_type = param_type(param_types, i);
+        let tee_param = match param_type {
+            ParamType::None => TeeParam::default(),
+            ParamType::ValueInput | ParamType::ValueOutput | ParamType::ValueInout => {
+                let value = unsafe { (*params.add(i)).value };
+                TeeParam {
+                    data: Vec::new(),
+                    values: Value {
+                        a: value.a,
+                        b: value.b,
+                    },
+                }
+            }
+            ParamType::MemrefInput | ParamType::MemrefInout => {
+                let memref = unsafe { (*params.add(i)).memref };
+                let data = match memref.size {
+                    0 => Vec::new(),
+                    size => {
+                        unsafe { slice::from_raw_parts(memref.buffer as *const u8, size) }.to_vec()
+                    }
+                };
+                TeeParam {
+                    data,
+                    values: Value::default(),
+                }
+            }
+            ParamType::MemrefOutput => TeeParam {
+                data: vec![0; unsafe { (*params.add(i)).memref.size }],
+                values: Value::default(),
+            },
+        };
+        *param = Parameter {
+            param: tee_param,
+            param_type,
+        };
+    }
+    let [p0, p1, p2, p3] = out;
+    Parameters(p0, p1, p2, p3)
+}
+
+// Copies the outputs of the target TA back to the calling TA. A memref
+// output larger than the buffer of the caller only updates its size, as
+// with `TEE_ERROR_SHORT_BUFFER`.
+unsafe fn write_params(param_types: u32, params: *mut raw::TEE_Param, returned: &Parameters) {
+    if params.is_null() {
+        return;
+    }
+    let returned = [&returned.0, &returned.1, &returned.2, &returned.3];
+    for (i, param) in returned.iter().enumerate() {
+        match param_type(param_types, i) {
+            ParamType::ValueOutput | ParamType::ValueInout => unsafe {
+                (*params.add(i)).value = raw::Value {
+                    a: param.param.values.a,
+                    b: param.param.values.b,
+                };
+            },
+            ParamType::MemrefOutput | ParamType::MemrefInout => {
+                let memref = unsafe { &mut (*params.add(i)).memref };
+                let data = &param.param.data;
+                if data.len() <= memref.size && !data.is_empty() {
+                    unsafe {
+                        ptr::copy_nonoverlapping(
+                            data.as_ptr(),
+                            memref.buffer as *mut u8,
+                            data.len(),
+                        )
+                    };
+                }
+                memref.size = data.len();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn finish(
+    result: Result<(), (ErrorKind, ReturnOrigin)>,
+    return_origin: *mut u32,
+) -> raw::TEE_Result {
+    let (code, origin) = match result {
+        Ok(()) => (raw::TEE_SUCCESS, ReturnOrigin::TrustedApp),
+        Err((kind, origin)) => (kind.into(), origin),
+    };
+    if !return_origin.is_null() {
+        unsafe { *return_origin = origin as u32 };
+    }
+    code
+}
+
+fn response_result(result: u32, origin: ReturnOrigin) -> Result<(), (ErrorKind, ReturnOrigin)> {
+    match result {
+        raw::TEE_SUCCESS => Ok(()),
+        code => Err((optee_utee::Error::from_raw_error(code).kind(), origin)),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_OpenTASession(
+    destination: *const raw::TEE_UUID,
+    _cancellation_request_timeout: u32,
+    param_types: u32,
+    params: *mut raw::TEE_Param,
+    session: *mut raw::TEE_TASessionHandle,
+    return_origin: *mut u32,
+) -> raw::TEE_Result {
+    unsafe { *session = ptr::null_mut() };
+    finish(
+        (|| {
+            let router = current().ok_or((ErrorKind::Communication, ReturnOrigin::Comms))?;
+            let uuid = router.resolve(&Uuid::from(unsafe { *destination }))?;
+            let req = TeeRequest::OpenSession {
+                uuid: uuid.clone(),
+                connection_method: raw::TEE_LOGIN_TRUSTED_APP,
+                params: unsafe { read_params(param_types, params) },
+                identity: ClientIdentity {
+                    login: raw::TEE_LOGIN_TRUSTED_APP,
+                    uuid: router.caller,
+                },
+            };
+            match router.request(&uuid, req)? {
+                TeeResponse::OpenSession {
+                    session_id,
+                    result,
+                    origin,
+                } => {
+                    response_result(result, origin)?;
+                    let handle = Box::new(Session {
+                        router,
+                        uuid,
+                        session_id,
+                    });
+                    unsafe { *session = Box::into_raw(handle) as raw::TEE_TASessionHandle };
+                    Ok(())
+                }
+                _ => Err((ErrorKind::BadFormat, ReturnOrigin::Comms)),
+            }
+        })(),
+        return_origin,
+    )
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_InvokeTACommand(
+    session: raw::TEE_TASessionHandle,
+    cancellation_request_timeout: u32,
+    command_id: u32,
+    param_types: u32,
+    params: *mut raw::TEE_Param,
+    return_origin: *mut u32,
+) -> raw::TEE_Result {
+    finish(
+        (|| {
+            let session = unsafe { (session as *const Session).as_ref() }
+                .ok_or((ErrorKind::BadParameters, ReturnOrigin::Api))?;
+            let req = TeeRequest::InvokeCommand {
+                session_id: session.session_id,
+                cmd_id: command_id,
+                operation_id: session.router.operation_id.fetch_add(1, Ordering::Relaxed),
+                params: unsafe { read_params(param_types, params) },
+                timeout_ms: (cancellation_request_timeout != raw::TEE_TIMEOUT_INFINITE)
+                    .then_some(cancellation_request_timeout),
+            };
+            match session.router.request(&session.uuid, req)? {
+                TeeResponse::InvokeCommand {
+                    params: returned,
+                    result,
+                    origin,
+                    ..
+                } => {
+                    if origin == ReturnOrigin::TrustedApp {
+                        unsafe { write_params(param_types, params, &returned) };
+                    }
+                    response_result(result, origin)
+                }
+                _ => Err((ErrorKind::BadFormat, ReturnOrigin::Comms)),
+            }
+        })(),
+        return_origin,
+    )
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn TEE_CloseTASession(session: raw::TEE_TASessionHandle) {
+    if session.is_null() {
+        return;
+    }
+    let session = unsafe { Box::from_raw(session as *mut Session) };
+    let req = TeeRequest::CloseSession {
+        session_id: session.session_id,
+    };
+    if let Ok(TeeResponse::CloseSession { result, .. }) = session.router.request(&session.uuid, req)
+        && result != raw::TEE_SUCCESS
+    {
+        warn!(uuid = %session.uuid, result, "Target TA failed to close the session");
+    }
+}
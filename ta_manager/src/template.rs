@@ -0,0 +1,90 @@
+//! Parameter templates registered on a session with `RegisterTemplate`, and
+//! invoked with `InvokeTemplate` requests carrying only what changed.
+//!
+//! A CA invoking the same command at a high rate, e.g. to sign telemetry,
+//! registers the parameters that stay the same once, such as a key handle
+//! in a value or a large memref, and then sends the few fields that differ
+//! as [`ParamDelta`]s. The manager applies them to a copy of the template
+//! and invokes the TA as for an `InvokeCommand`.
+
+use optee_utee::ErrorKind;
+
+use crate::protocol::{ParamDelta, ParamType, Parameter, Parameters};
+
+// Returns the parameters of an invocation of `template` with `deltas`
+// applied in order.
+pub(crate) fn expand(
+    template: &Parameters,
+    deltas: Vec<ParamDelta>,
+) -> Result<Parameters, ErrorKind> {
+    let mut params = template.clone();
+    for delta in deltas {
+        match delta {
+            ParamDelta::Values { index, values } => {
+                let param = param_mut(&mut params, index)?;
+                if !is_value(param) {
+                    return Err(ErrorKind::BadParameters);
+                }
+                param.param.values = values;
+            }
+            ParamDelta::Data { index, data } => {
+                let param = param_mut(&mut params, index)?;
+                if !is_memref(param) {
+                    return Err(ErrorKind::BadParameters);
+                }
+                param.param.data = data;
+            }
+            ParamDelta::Patch {
+                index,
+                offset,
+                data,
+            } => {
+                let param = param_mut(&mut params, index)?;
+                if !is_memref(param) {
+                    return Err(ErrorKind::BadParameters);
+                }
+                let offset = offset as usize;
+                let range = offset..offset.saturating_add(data.len());
+                match param.param.data.get_mut(range) {
+                    Some(target) => target.copy_from_slice(&data),
+                    None => return Err(ErrorKind::BadParameters),
+                }
+            }
+        }
+    }
+    Ok(params)
+}
+
+// Empties the input memrefs of the parameters returned by the TA, which the
+// CA already has.
+pub(crate) fn strip_inputs(params: &mut Parameters) {
+    for param in [&mut params.0, &mut params.1, &mut params.2, &mut params.3] {
+        if param.param_type == ParamType::MemrefInput {
+            param.param.data = Vec::new();
+        }
+    }
+}
+
+fn param_mut(params: &mut Parameters, index: u8) -> Result<&mut Parameter, ErrorKind> {
+    match index {
+        0 => Ok(&mut params.0),
+        1 => Ok(&mut params.1),
+        2 => Ok(&mut params.2),
+        3 => Ok(&mut params.3),
+        _ => Err(ErrorKind::BadParameters),
+    }
+}
+
+fn is_value(param: &Parameter) -> bool {
+    matches!(
+        param.param_type,
+        ParamType::ValueInput | ParamType::ValueOutput | ParamType::ValueInout
+    )
+}
+
+fn is_memref(param: &Parameter) -> bool {
+    matches!(
+        param.param_type,
+        ParamType::MemrefInput | ParamType::MemrefOutput | ParamType::MemrefInout
+    )
+}
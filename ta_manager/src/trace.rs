@@ -0,0 +1,270 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write as _,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    process,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use crate::protocol::TeeRequest;
+
+/// Timing spans of one request, collected on the thread of its connection.
+pub(crate) struct RequestTrace {
+    name: &'static str,
+    session_id: Option<u32>,
+    cmd_id: Option<u32>,
+    operation_id: Option<u32>,
+    trace_id: Option<u64>,
+    spans: Vec<(&'static str, Instant, Instant)>,
+}
+
+/// Writes the traces of the requests to a file in the JSON format of
+/// chrome://tracing, which Perfetto also opens. Each connection gets a track
+/// of its own, on which every request is an event holding those of its
+/// phases.
+pub(crate) struct Tracer {
+    // `None` once closed.
+    out: Mutex<Option<BufWriter<File>>>,
+    epoch: Instant,
+}
+
+impl Tracer {
+    pub(crate) fn create(path: &Path, uuid: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // Viewers accept an array lacking its closing bracket, so that the
+        // file stays usable if the manager does not stop cleanly.
+        write!(
+            out,
+            "[\n{{\"name\":\"process_name\",\"ph\":\"M\",\"pid\":{},\"args\":{{\"name\":\"{}\"}}}}",
+            process::id(),
+            escape(&format!("ta {}", uuid))
+        )?;
+        out.flush()?;
+        Ok(Self {
+            out: Mutex::new(Some(out)),
+            epoch: Instant::now(),
+        })
+    }
+
+    // Writes the events of `trace` and flushes them.
+    pub(crate) fn record(&self, trace: RequestTrace) -> io::Result<()> {
+        let (Some(start), Some(end)) = (
+            trace.spans.iter().map(|span| span.1).min(),
+            trace.spans.iter().map(|span| span.2).max(),
+        ) else {
+            return Ok(());
+        };
+        let mut args = String::new();
+        for (key, value) in [
+            ("session_id", trace.session_id),
+            ("cmd_id", trace.cmd_id),
+            ("operation_id", trace.operation_id),
+        ] {
+            if let Some(value) = value {
+                let sep = if args.is_empty() { "" } else { "," };
+                let _ = write!(args, "{}\"{}\":{}", sep, key, value);
+            }
+        }
+
+        if let Some(trace_id) = trace.trace_id {
+            let sep = if args.is_empty() { "" } else { "," };
+            let _ = write!(args, "{}\"trace_id\":\"{:016x}\"", sep, trace_id);
+        }
+
+        let tid = connection_id();
+        let mut events = self.event(trace.name, tid, start, end, &args);
+        for (name, start, end) in &trace.spans {
+            events.push_str(&self.event(name, tid, *start, *end, &args));
+        }
+        match self.out.lock().unwrap().as_mut() {
+            Some(out) => {
+                out.write_all(events.as_bytes())?;
+                out.flush()
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Ends the array of events, dropping those recorded later.
+    pub(crate) fn close(&self) {
+        if let Some(mut out) = self.out.lock().unwrap().take() {
+            let _ = out.write_all(b"\n]\n");
+            let _ = out.flush();
+        }
+    }
+
+    fn event(&self, name: &str, tid: u32, start: Instant, end: Instant, args: &str) -> String {
+        format!(
+            ",\n{{\"name\":\"{}\",\"cat\":\"ta_manager\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":{},\"tid\":{},\"args\":{{{}}}}}",
+            name,
+            micros(start.saturating_duration_since(self.epoch)),
+            micros(end.saturating_duration_since(start)),
+            process::id(),
+            tid,
+            args
+        )
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<RequestTrace>> = const { RefCell::new(None) };
+    static CONNECTION_ID: Cell<u32> = const { Cell::new(0) };
+}
+
+static NEXT_CONNECTION_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Starts the trace of `req`, whose decoding started at `decoding`, on the
+/// thread of its connection.
+pub(crate) fn start(req: &TeeRequest, decoding: Instant) {
+    let (name, session_id, cmd_id, operation_id) = match req {
+        TeeRequest::Traced { request, .. } => return start(request, decoding),
+        TeeRequest::Hello { .. } => ("Hello", None, None, None),
+        TeeRequest::KeyExchange { .. } => ("KeyExchange", None, None, None),
+        TeeRequest::OpenSession { .. } => ("OpenSession", None, None, None),
+        TeeRequest::InvokeCommand {
+            session_id,
+            cmd_id,
+            operation_id,
+            ..
+        }
+        | TeeRequest::InvokeStreamBegin {
+            session_id,
+            cmd_id,
+            operation_id,
+            ..
+        }
+        | TeeRequest::InvokeTemplate {
+            session_id,
+            cmd_id,
+            operation_id,
+            ..
+        } => (
+            "InvokeCommand",
+            Some(*session_id),
+            Some(*cmd_id),
+            Some(*operation_id),
+        ),
+        TeeRequest::CloseSession { session_id } => ("CloseSession", Some(*session_id), None, None),
+        TeeRequest::RegisterTemplate { session_id, .. } => {
+            ("RegisterTemplate", Some(*session_id), None, None)
+        }
+        TeeRequest::Subscribe { session_id } => ("Subscribe", Some(*session_id), None, None),
+        TeeRequest::CancelCommand {
+            session_id,
+            operation_id,
+        } => (
+            "CancelCommand",
+            Some(*session_id),
+            None,
+            Some(*operation_id),
+        ),
+        TeeRequest::InvokeStreamChunk {
+            session_id,
+            operation_id,
+            ..
+        } => (
+            "InvokeStreamChunk",
+            Some(*session_id),
+            None,
+            Some(*operation_id),
+        ),
+        TeeRequest::InvokeBatch {
+            session_id,
+            operation_id,
+            ..
+        } => ("InvokeBatch", Some(*session_id), None, Some(*operation_id)),
+        TeeRequest::InvokeStreamEnd {
+            session_id,
+            operation_id,
+        } => (
+            "InvokeStreamEnd",
+            Some(*session_id),
+            None,
+            Some(*operation_id),
+        ),
+    };
+    let trace = RequestTrace {
+        name,
+        session_id,
+        cmd_id,
+        operation_id,
+        trace_id: None,
+        spans: vec![("decode", decoding, Instant::now())],
+    };
+    CURRENT.with(|current| *current.borrow_mut() = Some(trace));
+}
+
+/// Sets the correlation id of the request of the current trace, if any.
+pub(crate) fn set_trace_id(trace_id: u64) {
+    CURRENT.with(|current| {
+        if let Some(trace) = current.borrow_mut().as_mut() {
+            trace.trace_id = Some(trace_id);
+        }
+    });
+}
+
+/// Returns a random correlation id for a request sent without one.
+pub(crate) fn new_trace_id() -> u64 {
+    let mut id = [0u8; 8];
+    if getrandom::getrandom(&mut id).is_ok() {
+        return u64::from_ne_bytes(id);
+    }
+    // Unique within the process, which is enough to correlate its logs.
+    static NEXT_TRACE_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_TRACE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Adds a phase that ran from `start` to `end` to the current trace, if any.
+pub(crate) fn span(name: &'static str, start: Instant, end: Instant) {
+    CURRENT.with(|current| {
+        if let Some(trace) = current.borrow_mut().as_mut() {
+            trace.spans.push((name, start, end));
+        }
+    });
+}
+
+/// Ends the current trace and returns it.
+pub(crate) fn finish() -> Option<RequestTrace> {
+    CURRENT.with(|current| current.borrow_mut().take())
+}
+
+// Returns the id of the connection served by the current thread.
+fn connection_id() -> u32 {
+    CONNECTION_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+fn micros(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1e6
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
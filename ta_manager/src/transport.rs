@@ -0,0 +1,345 @@
+//! Frames exchanged on a CA connection, sealed once both ends agreed on keys
+//! with a `KeyExchange`.
+//!
+//! With the `encrypted_transport` feature, a manager configured with a
+//! [`TransportKey`] offers [`CAP_ENCRYPTION`](crate::protocol::CAP_ENCRYPTION)
+//! in its `Hello` and refuses connections that do not take it. The CA then
+//! sends a `KeyExchange` with a random nonce, answered with a nonce of the
+//! manager, and both ends derive a key per direction with HKDF-SHA256 from
+//! the shared key and a transcript of the nonces and of the version and
+//! capabilities they agreed on. Each end refuses nonces of another size than
+//! [`KEY_EXCHANGE_NONCE_LEN`]. Every later
+//! frame body is sealed with AES-256-GCM under the key of its direction and
+//! a nonce counting the frames sent in that direction, so that frames cannot
+//! be read, altered, replayed or reordered without the shared key. The
+//! 4-byte length prefix of frames stays in clear.
+
+use std::io;
+
+#[cfg(feature = "encrypted_transport")]
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, OsRng, rand_core::RngCore},
+};
+#[cfg(feature = "encrypted_transport")]
+use hkdf::Hkdf;
+#[cfg(feature = "encrypted_transport")]
+use sha2::Sha256;
+
+use crate::codec::{read_frame, write_frame};
+use crate::ipc::IpcStream;
+
+/// Size of the nonces exchanged in `KeyExchange`.
+pub(crate) const KEY_EXCHANGE_NONCE_LEN: usize = 32;
+
+// A CA connection, from either end.
+pub(crate) struct Connection {
+    pub(crate) stream: IpcStream,
+    #[cfg(feature = "encrypted_transport")]
+    channel: Option<SecureChannel>,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: IpcStream) -> Self {
+        Self {
+            stream,
+            #[cfg(feature = "encrypted_transport")]
+            channel: None,
+        }
+    }
+
+    // Reads the body of the next frame, opened if the connection is sealed.
+    pub(crate) fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let frame = read_frame(&mut self.stream)?;
+        #[cfg(feature = "encrypted_transport")]
+        if let (Some(channel), Some(frame)) = (&mut self.channel, &frame) {
+            return channel.open(frame).map(Some);
+        }
+        Ok(frame)
+    }
+
+    // Writes `body` as a frame, sealed if the connection is.
+    pub(crate) fn write_frame(&mut self, body: &[u8]) -> io::Result<()> {
+        #[cfg(feature = "encrypted_transport")]
+        if let Some(channel) = &mut self.channel {
+            let sealed = channel.seal(body)?;
+            return write_frame(&mut self.stream, &sealed);
+        }
+        write_frame(&mut self.stream, body)
+    }
+
+    // Whether the frames are sealed.
+    pub(crate) fn is_sealed(&self) -> bool {
+        #[cfg(feature = "encrypted_transport")]
+        return self.channel.is_some();
+        #[cfg(not(feature = "encrypted_transport"))]
+        false
+    }
+
+    // Seals the frames exchanged from now on.
+    #[cfg(feature = "encrypted_transport")]
+    pub(crate) fn seal(&mut self, channel: SecureChannel) {
+        self.channel = Some(channel);
+    }
+}
+
+/// Key shared by a [`TAManager`](crate::TAManager) and its CAs, from which
+/// the keys sealing their connections are derived, see
+/// [`TAManagerConfig::with_transport_key`](crate::TAManagerConfig::with_transport_key)
+/// and [`ClientPool::with_transport_key`](crate::ClientPool::with_transport_key).
+#[cfg(feature = "encrypted_transport")]
+#[derive(Clone)]
+pub struct TransportKey([u8; 32]);
+
+#[cfg(feature = "encrypted_transport")]
+impl TransportKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+}
+
+#[cfg(feature = "encrypted_transport")]
+impl std::fmt::Debug for TransportKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportKey").finish_non_exhaustive()
+    }
+}
+
+// End of the connection a `SecureChannel` serves.
+#[cfg(feature = "encrypted_transport")]
+#[derive(Clone, Copy)]
+pub(crate) enum Side {
+    Client,
+    Manager,
+}
+
+// Returns a random nonce for a `KeyExchange`.
+#[cfg(feature = "encrypted_transport")]
+pub(crate) fn key_exchange_nonce() -> Vec<u8> {
+    let mut nonce = vec![0u8; KEY_EXCHANGE_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+// Label opening the transcript the keys are derived from.
+#[cfg(feature = "encrypted_transport")]
+const TRANSCRIPT_LABEL: &[u8] = b"ta_manager transport v1";
+
+// Keys and frame counters of a sealed connection.
+#[cfg(feature = "encrypted_transport")]
+pub(crate) struct SecureChannel {
+    sealer: Aes256Gcm,
+    opener: Aes256Gcm,
+    sent: u64,
+    received: u64,
+}
+
+#[cfg(feature = "encrypted_transport")]
+impl SecureChannel {
+    // Derives the keys of both directions from the nonces of the CA and of
+    // the manager and from the version and capabilities agreed in `Hello`,
+    // so that a handshake altered on the way gives different keys. Fails on
+    // nonces of another size than `KEY_EXCHANGE_NONCE_LEN`.
+    pub(crate) fn new(
+        key: &TransportKey,
+        side: Side,
+        client_nonce: &[u8],
+        manager_nonce: &[u8],
+        version: u32,
+        capabilities: u32,
+    ) -> io::Result<Self> {
+        if client_nonce.len() != KEY_EXCHANGE_NONCE_LEN
+            || manager_nonce.len() != KEY_EXCHANGE_NONCE_LEN
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "key exchange nonce of the wrong size",
+            ));
+        }
+        let transcript = transcript(&[
+            TRANSCRIPT_LABEL,
+            client_nonce,
+            manager_nonce,
+            &version.to_le_bytes(),
+            &capabilities.to_le_bytes(),
+        ]);
+        let hkdf = Hkdf::<Sha256>::new(Some(&transcript), &key.0);
+        let derive = |direction: &[u8]| {
+            let mut key = Key::<Aes256Gcm>::default();
+            hkdf.expand(direction, &mut key)
+                .expect("32 bytes is a valid HKDF-SHA256 output size");
+            Aes256Gcm::new(&key)
+        };
+        let (to_manager, to_client) = (derive(b"client to manager"), derive(b"manager to client"));
+        let (sealer, opener) = match side {
+            Side::Client => (to_manager, to_client),
+            Side::Manager => (to_client, to_manager),
+        };
+        Ok(Self {
+            sealer,
+            opener,
+            sent: 0,
+            received: 0,
+        })
+    }
+
+    fn seal(&mut self, body: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = next_nonce(&mut self.sent)?;
+        self.sealer
+            .encrypt(Nonce::from_slice(&nonce), body)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to seal a frame"))
+    }
+
+    fn open(&mut self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = next_nonce(&mut self.received)?;
+        self.opener
+            .decrypt(Nonce::from_slice(&nonce), frame)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "frame failed authentication"))
+    }
+}
+
+// Concatenates `parts`, each prefixed with its length, so that no two
+// handshakes give the same transcript.
+#[cfg(feature = "encrypted_transport")]
+fn transcript(parts: &[&[u8]]) -> Vec<u8> {
+    let mut transcript = Vec::new();
+    for part in parts {
+        transcript.extend_from_slice(&(part.len() as u32).to_le_bytes());
+        transcript.extend_from_slice(part);
+    }
+    transcript
+}
+
+// Returns the nonce of the frame numbered `counter`, and counts the frame.
+#[cfg(feature = "encrypted_transport")]
+fn next_nonce(counter: &mut u64) -> io::Result<[u8; 12]> {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *counter = counter
+        .checked_add(1)
+        .ok_or_else(|| io::Error::other("too many frames on the connection"))?;
+    Ok(nonce)
+}
+
+#[cfg(all(test, target_os = "linux", feature = "encrypted_transport"))]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn channel(
+        side: Side,
+        client_nonce: &[u8],
+        manager_nonce: &[u8],
+        capabilities: u32,
+    ) -> SecureChannel {
+        SecureChannel::new(
+            &TransportKey::new(KEY),
+            side,
+            client_nonce,
+            manager_nonce,
+            11,
+            capabilities,
+        )
+        .unwrap()
+    }
+
+    // The ends of a connection sealed after a handshake with the given
+    // capabilities on each side.
+    fn sealed_pair(
+        client_capabilities: u32,
+        manager_capabilities: u32,
+    ) -> (Connection, Connection) {
+        let (client, manager) = IpcStream::pair().unwrap();
+        let (client_nonce, manager_nonce) = (key_exchange_nonce(), key_exchange_nonce());
+        let mut client = Connection::new(client);
+        client.seal(channel(
+            Side::Client,
+            &client_nonce,
+            &manager_nonce,
+            client_capabilities,
+        ));
+        let mut manager = Connection::new(manager);
+        manager.seal(channel(
+            Side::Manager,
+            &client_nonce,
+            &manager_nonce,
+            manager_capabilities,
+        ));
+        (client, manager)
+    }
+
+    #[test]
+    fn handshake_seals_both_directions() {
+        let (mut client, mut manager) = sealed_pair(3, 3);
+        client.write_frame(b"request").unwrap();
+        manager.write_frame(b"response").unwrap();
+        assert_eq!(manager.read_frame().unwrap().unwrap(), b"request");
+        assert_eq!(client.read_frame().unwrap().unwrap(), b"response");
+
+        // Frames do not carry the bodies in clear.
+        client.write_frame(b"request").unwrap();
+        let frame = read_frame(&mut manager.stream).unwrap().unwrap();
+        assert!(!frame.windows(7).any(|window| window == b"request"));
+    }
+
+    #[test]
+    fn altered_handshakes_give_other_keys() {
+        let (mut client, mut manager) = sealed_pair(3, 1);
+        client.write_frame(b"request").unwrap();
+        let e = manager.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn nonces_of_the_wrong_size_are_refused() {
+        let key = TransportKey::new(KEY);
+        let nonce = key_exchange_nonce();
+        for short in [&nonce[..16], &[][..], &[0; 33][..]] {
+            for (client_nonce, manager_nonce) in [(short, &nonce[..]), (&nonce[..], short)] {
+                let e = SecureChannel::new(&key, Side::Client, client_nonce, manager_nonce, 11, 3)
+                    .err()
+                    .unwrap();
+                assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_frames_fail() {
+        let nonce = key_exchange_nonce();
+        let mut sealer = channel(Side::Client, &nonce, &nonce, 3);
+        let mut frame = sealer.seal(b"request").unwrap();
+        frame[0] ^= 1;
+        assert!(
+            channel(Side::Manager, &nonce, &nonce, 3)
+                .open(&frame)
+                .is_err()
+        );
+
+        // Nor does a frame go back to its sender.
+        let frame = channel(Side::Client, &nonce, &nonce, 3)
+            .seal(b"request")
+            .unwrap();
+        assert!(
+            channel(Side::Client, &nonce, &nonce, 3)
+                .open(&frame)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn replayed_and_reordered_frames_fail() {
+        let nonce = key_exchange_nonce();
+        let mut sealer = channel(Side::Client, &nonce, &nonce, 3);
+        let first = sealer.seal(b"first").unwrap();
+        let second = sealer.seal(b"second").unwrap();
+
+        let mut opener = channel(Side::Manager, &nonce, &nonce, 3);
+        assert_eq!(opener.open(&first).unwrap(), b"first");
+        assert!(opener.open(&first).is_err());
+
+        let mut opener = channel(Side::Manager, &nonce, &nonce, 3);
+        assert!(opener.open(&second).is_err());
+    }
+}
@@ -0,0 +1,654 @@
+//! Experimental backend running TAs compiled to WebAssembly.
+//!
+//! A [`WasmTa`] is a [`TrustedApplication`] whose logic lives in a
+//! WebAssembly module run by wasmtime, so that it only reaches the host
+//! through the functions it imports and can be shipped as a single portable
+//! file.
+
+use std::{
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use optee_utee::{Error, ErrorKind, Result};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error};
+use wasmtime::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+    TypedFunc,
+};
+
+use crate::TrustedApplication;
+use crate::context::OpenSessionContext;
+use crate::protocol::{ParamType, Parameter, Parameters};
+
+// Memory a guest may grow to by default.
+const DEFAULT_MEMORY_LIMIT: usize = 64 << 20;
+
+// Time a call into the guest may run by default.
+const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Interval at which the epoch of the engine of a TA advances, the precision
+// of the call deadlines.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+type TeeResult<T> = std::result::Result<T, ErrorKind>;
+
+/// [`TrustedApplication`] running a WebAssembly module.
+///
+/// Each TA instance, made by [`TrustedApplication::create`], is a fresh
+/// instance of the module, and its calls are serialized: the commands of
+/// different sessions do not run concurrently.
+///
+/// # Guest interface
+///
+/// The module exports its `memory` and the entry points, which return a TEE
+/// result code, 0 for success:
+///
+/// * `ta_create() -> i32` and `ta_destroy() -> i32`, optional.
+/// * `ta_open_session(session: i32, login: i32) -> i32`, with a handle of
+///   the session, unique in the instance, and the login type of the client.
+/// * `ta_invoke_command(session: i32, cmd_id: i32) -> i32`.
+/// * `ta_close_session(session: i32)`, optional.
+///
+/// It may import from the `optee` module, pointers and lengths designating
+/// guest memory and functions returning `i32` returning a TEE result code:
+///
+/// * `param_type(index: i32) -> i32`: the [`ParamType`] of a parameter of
+///   the call being served, `BadParameters` for an index past 3.
+/// * `param_value(index: i32, b: i32) -> i32`: field `a`, or `b` if `b` is
+///   not 0, of a value parameter.
+/// * `param_set_value(index: i32, a: i32, b: i32) -> i32`.
+/// * `param_memref_size(index: i32) -> i32`: size of a memref parameter.
+/// * `param_memref_read(index: i32, ptr: i32, len: i32) -> i32`: copies a
+///   memref to the guest, `ShortBuffer` if `len` is below its size.
+/// * `param_memref_write(index: i32, ptr: i32, len: i32) -> i32`: sets the
+///   contents of an output memref.
+/// * `random(ptr: i32, len: i32) -> i32`: fills the buffer with random
+///   bytes.
+/// * `sha256(ptr: i32, len: i32, out: i32) -> i32`: writes the 32-byte
+///   SHA-256 digest of the buffer at `out`.
+/// * `storage_read(id: i32, id_len: i32, ptr: i32, len: i32, size: i32) ->
+///   i32`, `storage_write(id: i32, id_len: i32, ptr: i32, len: i32) -> i32`
+///   and `storage_delete(id: i32, id_len: i32) -> i32`: read, create or
+///   replace, and delete the private persistent object `id`. Reading writes
+///   the size of the object as a little-endian `u32` at `size` and fails
+///   with `ShortBuffer` if `len` is below it. Only with the
+///   `secure_storage` feature.
+/// * `log(ptr: i32, len: i32)`: logs the UTF-8 text at debug level.
+///
+/// A trap in the guest, including one for running a call past its
+/// [timeout](Self::with_call_timeout), fails the call with `TargetDead` and
+/// drops the instance, so that later calls fail the same way until the TA is
+/// created again.
+pub struct WasmTa {
+    module: Module,
+    linker: Linker<Host>,
+    memory_limit: usize,
+    call_timeout: Duration,
+    instance: Mutex<Option<Guest>>,
+    next_session: AtomicU32,
+    // Stops the thread advancing the epoch of the engine once dropped.
+    _ticker: Sender<()>,
+}
+
+// State of an instance the host functions reach.
+struct Host {
+    // Parameters of the call being served.
+    params: Parameters,
+    limits: StoreLimits,
+}
+
+// Instance of the module, with its entry points.
+struct Guest {
+    store: Store<Host>,
+    destroy: Option<TypedFunc<(), i32>>,
+    open_session: TypedFunc<(i32, i32), i32>,
+    invoke_command: TypedFunc<(i32, i32), i32>,
+    close_session: Option<TypedFunc<i32, ()>>,
+}
+
+impl WasmTa {
+    /// Compiles `wasm`, a module in the binary or the text format.
+    ///
+    /// # Errors
+    ///
+    /// 1) If the module does not compile, or does not export `memory`,
+    ///    `ta_open_session` and `ta_invoke_command`.
+    pub fn new(wasm: impl AsRef<[u8]>) -> anyhow::Result<Self> {
+        let engine = Engine::new(Config::new().epoch_interruption(true))?;
+        let module = Module::new(&engine, wasm)?;
+        for name in ["memory", "ta_open_session", "ta_invoke_command"] {
+            if module.get_export(name).is_none() {
+                bail!("the module does not export `{}`", name);
+            }
+        }
+        let mut linker = Linker::new(&engine);
+        define_host_functions(&mut linker)?;
+        Ok(Self {
+            module,
+            linker,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            call_timeout: DEFAULT_CALL_TIMEOUT,
+            instance: Mutex::new(None),
+            next_session: AtomicU32::new(1),
+            _ticker: spawn_ticker(engine)?,
+        })
+    }
+
+    /// Compiles the module in the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`new`](Self::new), and errors from reading the file.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let wasm = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        Self::new(wasm)
+    }
+
+    /// Limits the linear memory of an instance to `bytes`, 64 MiB by
+    /// default. Growing past it fails in the guest.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    /// Limits every call into the guest, including `ta_create`, to
+    /// `timeout`, 30 seconds by default, to within 10 ms. The time spent in
+    /// host functions counts, but the call only stops in guest code.
+    pub fn with_call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    // Number of epoch ticks a call may run for.
+    fn deadline(&self) -> u64 {
+        let ticks = self.call_timeout.as_nanos().div_ceil(EPOCH_TICK.as_nanos());
+        u64::try_from(ticks).unwrap_or(u64::MAX).max(1)
+    }
+
+    fn instantiate(&self) -> anyhow::Result<(Guest, Option<TypedFunc<(), i32>>)> {
+        let host = Host {
+            params: Parameters::default(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.memory_limit)
+                .build(),
+        };
+        let mut store = Store::new(self.module.engine(), host);
+        store.limiter(|host| &mut host.limits);
+        // Also bounds the start function of the module.
+        store.set_epoch_deadline(self.deadline());
+        let instance = self.linker.instantiate(&mut store, &self.module)?;
+        let create = instance.get_typed_func(&mut store, "ta_create").ok();
+        let destroy = instance.get_typed_func(&mut store, "ta_destroy").ok();
+        let close_session = instance.get_typed_func(&mut store, "ta_close_session").ok();
+        let open_session = instance.get_typed_func(&mut store, "ta_open_session")?;
+        let invoke_command = instance.get_typed_func(&mut store, "ta_invoke_command")?;
+        let guest = Guest {
+            store,
+            destroy,
+            open_session,
+            invoke_command,
+            close_session,
+        };
+        Ok((guest, create))
+    }
+
+    // Runs `call` on the instance with `params` as the parameters of the
+    // call, and turns the result code of the guest into a result.
+    fn call(
+        &self,
+        params: &mut Parameters,
+        call: impl FnOnce(&mut Guest) -> anyhow::Result<i32>,
+    ) -> Result<()> {
+        let mut instance = self.instance.lock().unwrap();
+        let Some(guest) = instance.as_mut() else {
+            return Err(Error::new(ErrorKind::TargetDead));
+        };
+        guest.store.data_mut().params = std::mem::take(params);
+        guest.store.set_epoch_deadline(self.deadline());
+        let result = call(guest);
+        *params = std::mem::take(&mut guest.store.data_mut().params);
+        match result {
+            Ok(code) => result_of(code),
+            Err(e) => {
+                if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+                    error!(timeout = ?self.call_timeout, "WASM TA call timed out, dropping its instance");
+                } else {
+                    error!(error = ?e, "WASM TA trapped, dropping its instance");
+                }
+                *instance = None;
+                Err(Error::new(ErrorKind::TargetDead))
+            }
+        }
+    }
+}
+
+// Advances the epoch of `engine` every `EPOCH_TICK`, so that calls past
+// their deadline trap, until the returned sender is dropped.
+fn spawn_ticker(engine: Engine) -> std::io::Result<Sender<()>> {
+    let (stop, stopped) = crossbeam_channel::bounded::<()>(0);
+    thread::Builder::new()
+        .name("wasm-epoch".into())
+        .spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(EPOCH_TICK) {
+                engine.increment_epoch();
+            }
+        })?;
+    Ok(stop)
+}
+
+fn result_of(code: i32) -> Result<()> {
+    match code {
+        0 => Ok(()),
+        code => Err(Error::from_raw_error(code as u32)),
+    }
+}
+
+impl TrustedApplication for WasmTa {
+    // Handle of the session in the instance.
+    type SessionContext = i32;
+
+    fn create(&self) -> Result<()> {
+        let (mut guest, create) = self.instantiate().map_err(|e| {
+            error!(error = ?e, "Failed to instantiate the WASM TA");
+            Error::new(ErrorKind::Generic)
+        })?;
+        if let Some(create) = create {
+            let code = create.call(&mut guest.store, ()).map_err(|e| {
+                error!(error = ?e, "WASM TA trapped in ta_create");
+                Error::new(ErrorKind::TargetDead)
+            })?;
+            result_of(code)?;
+        }
+        *self.instance.lock().unwrap() = Some(guest);
+        Ok(())
+    }
+
+    fn open_session(&self, ctx: &OpenSessionContext) -> Result<i32> {
+        let session = self.next_session.fetch_add(1, Ordering::Relaxed) as i32;
+        let login = ctx.identity().login_type() as u32 as i32;
+        // The parameters the guest writes are not sent back to the CA.
+        let mut params = ctx.params().clone();
+        self.call(&mut params, |guest| {
+            guest.open_session.call(&mut guest.store, (session, login))
+        })?;
+        Ok(session)
+    }
+
+    fn close_session(&self, ctx: &mut i32) -> Result<()> {
+        let session = *ctx;
+        self.call(&mut Parameters::default(), |guest| {
+            match &guest.close_session {
+                Some(close) => close.call(&mut guest.store, session).map(|()| 0),
+                None => Ok(0),
+            }
+        })
+    }
+
+    fn destroy(&self) -> Result<()> {
+        let Some(mut guest) = self.instance.lock().unwrap().take() else {
+            return Ok(());
+        };
+        guest.store.set_epoch_deadline(self.deadline());
+        match guest.destroy {
+            Some(destroy) => match destroy.call(&mut guest.store, ()) {
+                Ok(code) => result_of(code),
+                Err(e) => {
+                    error!(error = ?e, "WASM TA trapped in ta_destroy");
+                    Err(Error::new(ErrorKind::TargetDead))
+                }
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn invoke_command(&self, cmd_id: u32, params: &mut Parameters, ctx: &mut i32) -> Result<()> {
+        let session = *ctx;
+        self.call(params, |guest| {
+            guest
+                .invoke_command
+                .call(&mut guest.store, (session, cmd_id as i32))
+        })
+    }
+}
+
+// Returns the result code of a host function.
+fn code(result: TeeResult<()>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(kind) => u32::from(kind) as i32,
+    }
+}
+
+fn param(params: &mut Parameters, index: i32) -> TeeResult<&mut Parameter> {
+    match index {
+        0 => Ok(&mut params.0),
+        1 => Ok(&mut params.1),
+        2 => Ok(&mut params.2),
+        3 => Ok(&mut params.3),
+        _ => Err(ErrorKind::BadParameters),
+    }
+}
+
+fn is_value(param: &Parameter) -> bool {
+    matches!(
+        param.param_type,
+        ParamType::ValueInput | ParamType::ValueOutput | ParamType::ValueInout
+    )
+}
+
+fn is_memref(param: &Parameter) -> bool {
+    matches!(
+        param.param_type,
+        ParamType::MemrefInput | ParamType::MemrefOutput | ParamType::MemrefInout
+    )
+}
+
+// Copies `len` bytes of guest memory at `ptr`.
+fn read_guest(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> TeeResult<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let mut data = vec![0; usize::try_from(len).map_err(|_| ErrorKind::BadParameters)?];
+    memory
+        .read(&*caller, ptr as u32 as usize, &mut data)
+        .map_err(|_| ErrorKind::AccessDenied)?;
+    Ok(data)
+}
+
+fn write_guest(caller: &mut Caller<'_, Host>, ptr: i32, data: &[u8]) -> TeeResult<()> {
+    guest_memory(caller)?
+        .write(caller, ptr as u32 as usize, data)
+        .map_err(|_| ErrorKind::AccessDenied)
+}
+
+fn guest_memory(caller: &mut Caller<'_, Host>) -> TeeResult<wasmtime::Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(ErrorKind::BadState),
+    }
+}
+
+fn define_host_functions(linker: &mut Linker<Host>) -> anyhow::Result<()> {
+    linker.func_wrap(
+        "optee",
+        "param_type",
+        |mut caller: Caller<'_, Host>, index: i32| match param(&mut caller.data_mut().params, index)
+        {
+            Ok(param) => param.param_type as i32,
+            Err(kind) => u32::from(kind) as i32,
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "param_value",
+        |mut caller: Caller<'_, Host>, index: i32, b: i32| {
+            let params = &mut caller.data_mut().params;
+            match param(params, index) {
+                Ok(param) if is_value(param) && b != 0 => param.param.values.b as i32,
+                Ok(param) if is_value(param) => param.param.values.a as i32,
+                Ok(_) => u32::from(ErrorKind::BadParameters) as i32,
+                Err(kind) => u32::from(kind) as i32,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "param_set_value",
+        |mut caller: Caller<'_, Host>, index: i32, a: i32, b: i32| {
+            code(
+                param(&mut caller.data_mut().params, index).and_then(|param| {
+                    if !matches!(
+                        param.param_type,
+                        ParamType::ValueOutput | ParamType::ValueInout
+                    ) {
+                        return Err(ErrorKind::BadParameters);
+                    }
+                    param.param.values.a = a as u32;
+                    param.param.values.b = b as u32;
+                    Ok(())
+                }),
+            )
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "param_memref_size",
+        |mut caller: Caller<'_, Host>, index: i32| match param(&mut caller.data_mut().params, index)
+        {
+            Ok(param) if is_memref(param) => param.param.data.len() as i32,
+            Ok(_) => u32::from(ErrorKind::BadParameters) as i32,
+            Err(kind) => u32::from(kind) as i32,
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "param_memref_read",
+        |mut caller: Caller<'_, Host>, index: i32, ptr: i32, len: i32| {
+            let data = match param(&mut caller.data_mut().params, index) {
+                Ok(param) if is_memref(param) => param.param.data.clone(),
+                Ok(_) => return code(Err(ErrorKind::BadParameters)),
+                Err(kind) => return code(Err(kind)),
+            };
+            if (len as u32 as usize) < data.len() {
+                return code(Err(ErrorKind::ShortBuffer));
+            }
+            code(write_guest(&mut caller, ptr, &data))
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "param_memref_write",
+        |mut caller: Caller<'_, Host>, index: i32, ptr: i32, len: i32| {
+            let data = match read_guest(&mut caller, ptr, len) {
+                Ok(data) => data,
+                Err(kind) => return code(Err(kind)),
+            };
+            code(
+                param(&mut caller.data_mut().params, index).and_then(|param| {
+                    if !matches!(
+                        param.param_type,
+                        ParamType::MemrefOutput | ParamType::MemrefInout
+                    ) {
+                        return Err(ErrorKind::BadParameters);
+                    }
+                    param.param.data = data;
+                    Ok(())
+                }),
+            )
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "random",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let mut data = vec![0; len.max(0) as usize];
+            if let Err(kind) = fill_random(&mut data) {
+                return code(Err(kind));
+            }
+            code(write_guest(&mut caller, ptr, &data))
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "sha256",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32, out: i32| {
+            code(read_guest(&mut caller, ptr, len).and_then(|data| {
+                let digest = Sha256::digest(&data);
+                write_guest(&mut caller, out, &digest)
+            }))
+        },
+    )?;
+    linker.func_wrap(
+        "optee",
+        "log",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            if let Ok(text) = read_guest(&mut caller, ptr, len) {
+                debug!(message = %String::from_utf8_lossy(&text), "WASM TA log");
+            }
+        },
+    )?;
+    #[cfg(feature = "secure_storage")]
+    storage::define(linker)?;
+    Ok(())
+}
+
+// Fills `data` from the random number generator of the system.
+fn fill_random(data: &mut [u8]) -> TeeResult<()> {
+    getrandom::getrandom(data).map_err(|_| ErrorKind::Generic)
+}
+
+// Host functions reaching the secure storage of the TA through the
+// persistent objects of optee-utee, served by `crate::storage`.
+#[cfg(feature = "secure_storage")]
+mod storage {
+    use optee_utee::{
+        DataFlag, GenericObject, MiscellaneousConstants, ObjectStorageConstants, PersistentObject,
+    };
+    use wasmtime::{Caller, Linker};
+
+    use super::{Host, TeeResult, code, read_guest, write_guest};
+    use optee_utee::ErrorKind;
+
+    pub(super) fn define(linker: &mut Linker<Host>) -> anyhow::Result<()> {
+        linker.func_wrap(
+            "optee",
+            "storage_read",
+            |mut caller: Caller<'_, Host>, id: i32, id_len: i32, ptr: i32, len: i32, size: i32| {
+                code(object_id(&mut caller, id, id_len).and_then(|id| {
+                    let data = read(&id)?;
+                    write_guest(&mut caller, size, &(data.len() as u32).to_le_bytes())?;
+                    if (len as u32 as usize) < data.len() {
+                        return Err(ErrorKind::ShortBuffer);
+                    }
+                    write_guest(&mut caller, ptr, &data)
+                }))
+            },
+        )?;
+        linker.func_wrap(
+            "optee",
+            "storage_write",
+            |mut caller: Caller<'_, Host>, id: i32, id_len: i32, ptr: i32, len: i32| {
+                code(object_id(&mut caller, id, id_len).and_then(|id| {
+                    let data = read_guest(&mut caller, ptr, len)?;
+                    PersistentObject::create(
+                        ObjectStorageConstants::Private,
+                        &id,
+                        DataFlag::ACCESS_WRITE | DataFlag::OVERWRITE,
+                        None,
+                        &data,
+                    )
+                    .map(drop)
+                    .map_err(|e| e.kind())
+                }))
+            },
+        )?;
+        linker.func_wrap(
+            "optee",
+            "storage_delete",
+            |mut caller: Caller<'_, Host>, id: i32, id_len: i32| {
+                code(object_id(&mut caller, id, id_len).and_then(|id| {
+                    PersistentObject::open(
+                        ObjectStorageConstants::Private,
+                        &id,
+                        DataFlag::ACCESS_WRITE_META,
+                    )
+                    .and_then(PersistentObject::close_and_delete)
+                    .map_err(|e| e.kind())
+                }))
+            },
+        )?;
+        Ok(())
+    }
+
+    // Reads an object id from guest memory, refusing those too long for
+    // optee-utee.
+    fn object_id(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> TeeResult<Vec<u8>> {
+        if len as u32 > MiscellaneousConstants::TeeObjectIdMaxLen as u32 {
+            return Err(ErrorKind::BadParameters);
+        }
+        read_guest(caller, ptr, len)
+    }
+
+    fn read(id: &[u8]) -> TeeResult<Vec<u8>> {
+        let object =
+            PersistentObject::open(ObjectStorageConstants::Private, id, DataFlag::ACCESS_READ)
+                .map_err(|e| e.kind())?;
+        let size = object.info().map_err(|e| e.kind())?.data_size();
+        let mut data = vec![0; size];
+        let read = object.read(&mut data).map_err(|e| e.kind())?;
+        data.truncate(read as usize);
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use optee_utee::{Identity, LoginType, Uuid};
+
+    use super::*;
+    use crate::notify::NotificationSender;
+    use crate::peer::PeerCredentials;
+
+    // Opens sessions, and runs command 0 at once and command 1 forever.
+    const SPINNING_TA: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "ta_open_session") (param i32 i32) (result i32)
+            i32.const 0)
+          (func (export "ta_invoke_command") (param i32 i32) (result i32)
+            (if (i32.eqz (local.get 1)) (then (return (i32.const 0))))
+            (loop $spin (br $spin))
+            i32.const 0))
+    "#;
+
+    // A session opened with the public login.
+    fn public_session() -> OpenSessionContext {
+        let peer = PeerCredentials {
+            uid: 0,
+            gid: 0,
+            pid: 0,
+            groups: Vec::new(),
+            label: None,
+        };
+        let identity = Identity::new(LoginType::Public, Uuid::from_bytes([0; 16]));
+        OpenSessionContext::new(
+            Parameters::default(),
+            identity,
+            peer,
+            NotificationSender::default(),
+        )
+    }
+
+    #[test]
+    fn calls_past_their_deadline_kill_the_instance() {
+        let ta = WasmTa::new(SPINNING_TA)
+            .unwrap()
+            .with_call_timeout(Duration::from_millis(50));
+        ta.create().unwrap();
+        let mut params = Parameters::default();
+        let mut session = ta.open_session(&public_session()).unwrap();
+        ta.invoke_command(0, &mut params, &mut session).unwrap();
+
+        let start = Instant::now();
+        let e = ta.invoke_command(1, &mut params, &mut session).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TargetDead);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let e = ta.invoke_command(0, &mut params, &mut session).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::TargetDead);
+
+        // A new instance serves again.
+        ta.create().unwrap();
+        let mut session = ta.open_session(&public_session()).unwrap();
+        ta.invoke_command(0, &mut params, &mut session).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Pool of threads serving the sessions of a TA, see
+//! [`ExecutionModel::WorkerPool`](crate::ExecutionModel::WorkerPool).
+//!
+//! The workers share a queue of the sessions with messages to handle. A
+//! worker takes the session at the front of the queue, handles as many of
+//! its messages as the weight of the session allows, and queues it again at
+//! the back if more are waiting, so that sessions take turns whatever the
+//! size of their commands. A session is taken by one worker at a time, so
+//! its messages are still handled in the order they were queued. The queue
+//! of a session counts a message whenever one is sent to it, and once more
+//! when it is dropped, for a worker to drop the session.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use tracing::Span;
+
+use crate::TrustedApplication;
+use crate::session::{SessionQueue, SessionRunner};
+
+// Session a worker can serve one message at a time.
+pub(crate) trait Poll: Send + 'static {
+    // Handles the next message, if there is one. Returns false once the
+    // session ended.
+    fn poll(&mut self) -> bool;
+}
+
+impl<T: TrustedApplication> Poll for SessionRunner<T> {
+    fn poll(&mut self) -> bool {
+        SessionRunner::poll(self)
+    }
+}
+
+pub(crate) struct WorkerPool<R: Poll> {
+    shared: Arc<Shared<R>>,
+}
+
+struct Shared<R> {
+    scheduler: Mutex<Scheduler<R>>,
+    // Signalled when a session is queued, or the pool stops.
+    ready: Condvar,
+}
+
+struct Scheduler<R> {
+    sessions: HashMap<u32, Slot<R>>,
+    // Sessions with messages waiting, in the order they take turns. A
+    // session is queued if and only if it has messages waiting and no
+    // worker is serving it.
+    ready: VecDeque<u32>,
+    // Set once the pool is dropped. The workers then exit once the last
+    // session is dropped.
+    stopped: bool,
+}
+
+struct Slot<R> {
+    // `None` while a worker serves the session.
+    runner: Option<Box<R>>,
+    // Messages sent to the session and not handled yet.
+    pending: usize,
+    // Messages handled in a turn.
+    weight: usize,
+}
+
+impl<R: Poll> WorkerPool<R> {
+    // Starts `workers` threads, at least one, in the current span.
+    pub(crate) fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared {
+            scheduler: Mutex::new(Scheduler {
+                sessions: HashMap::new(),
+                ready: VecDeque::new(),
+                stopped: false,
+            }),
+            ready: Condvar::new(),
+        });
+        for _ in 0..workers.max(1) {
+            let shared = Arc::clone(&shared);
+            let span = Span::current();
+            thread::spawn(move || {
+                let _entered = span.enter();
+                shared.work();
+            });
+        }
+        Self { shared }
+    }
+
+    // Serves the session `session_id` of `queue` with `runner`, handling up
+    // to `weight` of its messages, at least one, in a turn. Returns the
+    // queue telling the workers about its messages.
+    pub(crate) fn add(
+        &self,
+        session_id: u32,
+        weight: usize,
+        queue: SessionQueue,
+        runner: R,
+    ) -> SessionQueue {
+        let slot = Slot {
+            runner: Some(Box::new(runner)),
+            pending: 0,
+            weight: weight.max(1),
+        };
+        self.shared
+            .scheduler
+            .lock()
+            .unwrap()
+            .sessions
+            .insert(session_id, slot);
+        let shared = Arc::clone(&self.shared);
+        queue.on_send(move || shared.notify(session_id))
+    }
+}
+
+impl<R: Poll> Drop for WorkerPool<R> {
+    fn drop(&mut self) {
+        self.shared.scheduler.lock().unwrap().stopped = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl<R> Scheduler<R> {
+    // Counts a message sent to the session `session_id`. Returns whether
+    // the session was queued.
+    fn count(&mut self, session_id: u32) -> bool {
+        let Some(slot) = self.sessions.get_mut(&session_id) else {
+            return false;
+        };
+        slot.pending += 1;
+        let queued = slot.pending == 1 && slot.runner.is_some();
+        if queued {
+            self.ready.push_back(session_id);
+        }
+        queued
+    }
+}
+
+impl<R: Poll> Shared<R> {
+    fn notify(&self, session_id: u32) {
+        if self.scheduler.lock().unwrap().count(session_id) {
+            self.ready.notify_one();
+        }
+    }
+
+    // Serves the sessions in turn until the pool stops and the last session
+    // is dropped.
+    fn work(&self) {
+        let mut scheduler = self.scheduler.lock().unwrap();
+        loop {
+            let Some(session_id) = scheduler.ready.pop_front() else {
+                if scheduler.stopped && scheduler.sessions.is_empty() {
+                    return;
+                }
+                scheduler = self.ready.wait(scheduler).unwrap();
+                continue;
+            };
+            let slot = scheduler.sessions.get_mut(&session_id).unwrap();
+            let mut runner = slot.runner.take().unwrap();
+            let turn = slot.pending.min(slot.weight);
+            slot.pending -= turn;
+            drop(scheduler);
+
+            let running = (0..turn).all(|_| runner.poll());
+            if !running {
+                drop(runner);
+                scheduler = self.scheduler.lock().unwrap();
+                scheduler.sessions.remove(&session_id);
+                if scheduler.stopped && scheduler.sessions.is_empty() {
+                    self.ready.notify_all();
+                }
+                continue;
+            }
+            scheduler = self.scheduler.lock().unwrap();
+            let slot = scheduler.sessions.get_mut(&session_id).unwrap();
+            slot.runner = Some(runner);
+            if slot.pending > 0 {
+                scheduler.ready.push_back(session_id);
+                self.ready.notify_one();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::QueuePolicy;
+    use crate::session::session_queue;
+    use std::sync::mpsc::{self, Sender};
+
+    // Session reporting every message it handles, ending after `messages`.
+    struct Recorder {
+        session_id: u32,
+        messages: usize,
+        log: Sender<u32>,
+    }
+
+    impl Poll for Recorder {
+        fn poll(&mut self) -> bool {
+            let _ = self.log.send(self.session_id);
+            self.messages -= 1;
+            self.messages > 0
+        }
+    }
+
+    // Serves `sessions`, with their weights, by a single worker, counting
+    // `messages` messages on each at once, and returns the order in which
+    // the worker handled them.
+    fn schedule(sessions: &[(u32, usize)], messages: usize) -> Vec<u32> {
+        let pool = WorkerPool::new(1);
+        let (log, handled) = mpsc::channel();
+        // Kept to the end, dropping a queue counting a message.
+        let mut queues = Vec::new();
+        for &(session_id, weight) in sessions {
+            let (queue, rx) = session_queue(1, QueuePolicy::Block);
+            let recorder = Recorder {
+                session_id,
+                messages,
+                log: log.clone(),
+            };
+            queues.push((pool.add(session_id, weight, queue, recorder), rx));
+        }
+        // Counts all messages at once, for the worker not to take a session
+        // before the others are queued.
+        let mut scheduler = pool.shared.scheduler.lock().unwrap();
+        for _ in 0..messages {
+            for &(session_id, _) in sessions {
+                scheduler.count(session_id);
+            }
+        }
+        drop(scheduler);
+        pool.shared.ready.notify_all();
+        handled.iter().take(sessions.len() * messages).collect()
+    }
+
+    #[test]
+    fn sessions_take_turns() {
+        let order = schedule(&[(1, 1), (2, 1), (3, 1)], 3);
+        assert_eq!(order, [1, 2, 3, 1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn weights_set_the_share_of_a_turn() {
+        let order = schedule(&[(1, 2), (2, 1)], 4);
+        assert_eq!(order, [1, 1, 2, 1, 1, 2, 2, 2]);
+    }
+}
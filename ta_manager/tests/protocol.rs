@@ -0,0 +1,288 @@
+//! Round trips of the protocol types through the codecs, on generated
+//! parameters and requests, and strict decoding of malformed frames.
+
+use proptest::prelude::*;
+use ta_manager::protocol::{
+    ParamDelta, ParamType, Parameter, Parameters, TeeParam, TeeRequest, TeeResponse, Value,
+};
+use ta_manager::{BincodeCodec, CanonicalCodec, Codec};
+
+fn param_type() -> impl Strategy<Value = ParamType> {
+    prop_oneof![
+        Just(ParamType::None),
+        Just(ParamType::ValueInput),
+        Just(ParamType::ValueOutput),
+        Just(ParamType::ValueInout),
+        Just(ParamType::MemrefInput),
+        Just(ParamType::MemrefOutput),
+        Just(ParamType::MemrefInout),
+    ]
+}
+
+fn value() -> impl Strategy<Value = Value> {
+    (any::<u32>(), any::<u32>()).prop_map(|(a, b)| Value { a, b })
+}
+
+// Any parameter, well-formed or not.
+fn parameter() -> impl Strategy<Value = Parameter> {
+    (
+        param_type(),
+        prop::collection::vec(any::<u8>(), 0..64),
+        value(),
+    )
+        .prop_map(|(param_type, data, values)| Parameter {
+            param: TeeParam { data, values },
+            param_type,
+        })
+}
+
+// A parameter whose payload matches its type.
+fn well_formed_parameter() -> impl Strategy<Value = Parameter> {
+    (
+        param_type(),
+        prop::collection::vec(any::<u8>(), 1..64),
+        value(),
+    )
+        .prop_map(|(param_type, data, values)| {
+            let param = match param_type {
+                ParamType::None => TeeParam::default(),
+                ParamType::ValueInput | ParamType::ValueOutput | ParamType::ValueInout => {
+                    TeeParam {
+                        data: Vec::new(),
+                        values,
+                    }
+                }
+                ParamType::MemrefInput | ParamType::MemrefOutput | ParamType::MemrefInout => {
+                    TeeParam {
+                        data,
+                        values: Value::default(),
+                    }
+                }
+            };
+            Parameter { param, param_type }
+        })
+}
+
+fn parameters<S: Strategy<Value = Parameter>>(
+    param: impl Fn() -> S,
+) -> impl Strategy<Value = Parameters> {
+    (param(), param(), param(), param()).prop_map(|(p0, p1, p2, p3)| Parameters(p0, p1, p2, p3))
+}
+
+fn delta() -> impl Strategy<Value = ParamDelta> {
+    prop_oneof![
+        (0u8..4, value()).prop_map(|(index, values)| ParamDelta::Values { index, values }),
+        (0u8..4, prop::collection::vec(any::<u8>(), 0..16))
+            .prop_map(|(index, data)| ParamDelta::Data { index, data }),
+        (
+            0u8..4,
+            any::<u32>(),
+            prop::collection::vec(any::<u8>(), 0..16)
+        )
+            .prop_map(|(index, offset, data)| ParamDelta::Patch {
+                index,
+                offset,
+                data
+            }),
+    ]
+}
+
+fn codecs() -> Vec<Box<dyn Codec>> {
+    vec![
+        Box::new(BincodeCodec),
+        Box::new(CanonicalCodec),
+        #[cfg(feature = "cbor")]
+        Box::new(ta_manager::CborCodec),
+        #[cfg(feature = "json")]
+        Box::new(ta_manager::JsonCodec),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn param_type_round_trip(value in 0u32..16) {
+        let param_type = ParamType::from(value);
+        let core = optee_utee::ParamType::from(value);
+        prop_assert_eq!(param_type, ParamType::from(core));
+        prop_assert_eq!(optee_utee::ParamType::from(param_type), core);
+        if optee_utee::ParamType::from_u32(value).is_ok() {
+            prop_assert_eq!(u32::from(param_type), value);
+        } else {
+            prop_assert_eq!(param_type, ParamType::None);
+        }
+    }
+
+    #[test]
+    fn well_formed_parameters_validate(params in parameters(well_formed_parameter)) {
+        prop_assert!(params.validate().is_ok());
+    }
+
+    #[test]
+    fn invoke_round_trip(
+        params in parameters(parameter),
+        session_id in any::<u32>(),
+        cmd_id in any::<u32>(),
+        timeout_ms in any::<Option<u32>>(),
+        trace_id in any::<u64>(),
+    ) {
+        for codec in codecs() {
+            let req = TeeRequest::Traced {
+                trace_id,
+                request: Box::new(TeeRequest::InvokeCommand {
+                    session_id,
+                    cmd_id,
+                    operation_id: cmd_id ^ session_id,
+                    params: params.clone(),
+                    timeout_ms,
+                }),
+            };
+            let decoded = codec.decode_request(&codec.encode_request(&req).unwrap()).unwrap();
+            let TeeRequest::Traced { trace_id: id, request } = decoded else {
+                return Err(TestCaseError::fail(format!("{:?} changed the request", codec)));
+            };
+            prop_assert_eq!(id, trace_id);
+            let TeeRequest::InvokeCommand {
+                session_id: s,
+                cmd_id: c,
+                params: p,
+                timeout_ms: t,
+                ..
+            } = *request else {
+                return Err(TestCaseError::fail(format!("{:?} changed the request", codec)));
+            };
+            prop_assert_eq!((s, c, t), (session_id, cmd_id, timeout_ms));
+            prop_assert_eq!(p.validate().is_ok(), params.validate().is_ok());
+            prop_assert_eq!(&p, &params);
+        }
+    }
+
+    #[test]
+    fn template_round_trip(
+        deltas in prop::collection::vec(delta(), 0..4),
+        template_id in any::<u32>(),
+    ) {
+        for codec in codecs() {
+            let req = TeeRequest::InvokeTemplate {
+                session_id: 1,
+                cmd_id: 2,
+                operation_id: 3,
+                template_id,
+                deltas: deltas.clone(),
+                timeout_ms: None,
+            };
+            let decoded = codec.decode_request(&codec.encode_request(&req).unwrap()).unwrap();
+            let TeeRequest::InvokeTemplate { template_id: id, deltas: d, .. } = decoded else {
+                return Err(TestCaseError::fail(format!("{:?} changed the request", codec)));
+            };
+            prop_assert_eq!(id, template_id);
+            prop_assert_eq!(&d, &deltas);
+        }
+    }
+
+    #[test]
+    fn response_round_trip(params in parameters(parameter), result in any::<u32>(), retry in any::<bool>()) {
+        for codec in codecs() {
+            let resp = TeeResponse::InvokeCommand {
+                params: params.clone(),
+                result,
+                origin: Default::default(),
+                retry,
+            };
+            let decoded = codec.decode_response(&codec.encode_response(&resp).unwrap()).unwrap();
+            let TeeResponse::InvokeCommand { params: p, result: r, retry: t, .. } = decoded else {
+                return Err(TestCaseError::fail(format!("{:?} changed the response", codec)));
+            };
+            prop_assert_eq!((r, t), (result, retry));
+            prop_assert_eq!(&p, &params);
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_decode_cleanly(buf in prop::collection::vec(any::<u8>(), 0..512)) {
+        for codec in codecs() {
+            let _ = codec.decode_request(&buf);
+            let _ = codec.decode_response(&buf);
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_refused(session_id in any::<u32>(), trailing in prop::collection::vec(any::<u8>(), 1..8)) {
+        for codec in [&BincodeCodec as &dyn Codec, &CanonicalCodec] {
+            let mut buf = codec.encode_request(&TeeRequest::CloseSession { session_id }).unwrap();
+            buf.extend_from_slice(&trailing);
+            prop_assert!(codec.decode_request(&buf).is_err());
+        }
+    }
+}
+
+#[test]
+fn deeply_nested_traced_refused() {
+    let traced = |depth| {
+        (0..depth).fold(
+            TeeRequest::CloseSession { session_id: 1 },
+            |req, trace_id| TeeRequest::Traced {
+                trace_id,
+                request: Box::new(req),
+            },
+        )
+    };
+    for codec in [&BincodeCodec as &dyn Codec, &CanonicalCodec] {
+        let shallow = codec.encode_request(&traced(2)).unwrap();
+        assert!(codec.decode_request(&shallow).is_ok());
+        let deep = codec.encode_request(&traced(100)).unwrap();
+        assert!(codec.decode_request(&deep).is_err());
+    }
+}
+
+#[test]
+fn oversized_length_refused() {
+    // An `OpenSession`, variant 0, whose uuid announces 2^52 bytes.
+    let buf = [0, 253, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0x00];
+    assert!(BincodeCodec.decode_request(&buf).is_err());
+}
+
+// The schema published for CAs written in other languages.
+#[cfg(feature = "schema")]
+const PUBLISHED_SCHEMA: &str = include_str!("../schema/protocol.json");
+
+#[cfg(feature = "schema")]
+#[test]
+fn schema_compatible_with_published() {
+    use ta_manager::protocol::schema::Schema;
+
+    let published = Schema::from_json(PUBLISHED_SCHEMA).unwrap();
+    let schema = Schema::new().unwrap();
+    schema.check_compatible(&published).unwrap();
+    assert!(
+        schema == published,
+        "schema/protocol.json is out of date, regenerate it with `ta_managerctl schema`"
+    );
+}
+
+#[cfg(feature = "schema")]
+#[test]
+fn schema_indices_match_bincode() {
+    use serde_reflection::ContainerFormat;
+    use ta_manager::protocol::schema::Schema;
+
+    fn encode(value: impl bincode::Encode) -> Vec<u8> {
+        bincode::encode_to_vec(value, bincode::config::standard()).unwrap()
+    }
+
+    let schema = Schema::new().unwrap();
+    let index = |name: &str, variant: &str| {
+        let ContainerFormat::Enum(variants) = &schema.types[name] else {
+            panic!("{} is not an enum", name);
+        };
+        let (index, _) = variants.iter().find(|(_, v)| v.name == variant).unwrap();
+        *index as u8
+    };
+    assert_eq!(
+        encode(TeeRequest::Subscribe { session_id: 0 }),
+        [index("TeeRequest", "Subscribe"), 0]
+    );
+    assert_eq!(
+        encode(ParamType::MemrefInout),
+        [index("ParamType", "MemrefInout")]
+    );
+}